@@ -0,0 +1,82 @@
+//! Compares the time it takes to simulate a frame of a level full of enemies with and without the
+//! activity bubble that puts far away enemies to sleep
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use specs::{World, Builder, RunNow, System};
+
+use caves::components::{Position, BoundingBox, Movement, Enemy, EnemyBehaviour, Player};
+use caves::map::{FloorMap, GridSize, TilePos, Tile};
+use caves::map_sprites::WallSprite;
+use caves::resources::{FramesElapsed, ActivityBubble};
+use caves::systems::{Dormancy, AI, Physics};
+
+const ENEMIES: usize = 300;
+const TILE_SIZE: u32 = 16;
+const SIZE: GridSize = GridSize {rows: 60, cols: 200};
+
+/// Creates a world with a single large level that has enemies scattered randomly across it and the
+/// player near one end
+fn setup_world(bubble: ActivityBubble) -> World {
+    // Enemies without a home room may wander anywhere, so the walls just keep them on the map
+    let mut map = FloorMap::new(SIZE, TILE_SIZE);
+    let edges: Vec<_> = map.grid().tile_positions_on_edges(TilePos {row: 0, col: 0}, SIZE).collect();
+    for pos in edges {
+        map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+    }
+
+    let mut world = World::new();
+    System::setup(&mut Dormancy, &mut world.res);
+    System::setup(&mut AI, &mut world.res);
+    System::setup(&mut Physics, &mut world.res);
+    world.add_resource(FramesElapsed(1));
+    world.add_resource(bubble);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let tile_center = |row, col| TilePos {row, col}.center(TILE_SIZE as i32);
+    for _ in 0..ENEMIES {
+        let pos = tile_center(rng.gen_range(1, SIZE.rows - 1), rng.gen_range(1, SIZE.cols - 1));
+        world.create_entity()
+            .with(Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE})
+            .with(Movement::default())
+            .build();
+    }
+    world.create_entity()
+        .with(Player)
+        .with(Position(tile_center(SIZE.rows / 2, 10)))
+        .with(BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE})
+        .with(Movement::default())
+        .build();
+    world.add_resource(map);
+
+    world
+}
+
+/// Runs a single frame of the systems that simulate the enemies
+fn run_frame(world: &mut World) {
+    Dormancy.run_now(&world.res);
+    AI.run_now(&world.res);
+    Physics.run_now(&world.res);
+    world.maintain();
+}
+
+fn bench_activity_bubble(c: &mut Criterion) {
+    let mut group = c.benchmark_group("activity bubble 300 enemies");
+    for &(name, bubble) in &[
+        ("without bubble", ActivityBubble::unlimited()),
+        ("with bubble", ActivityBubble::default()),
+    ] {
+        let mut world = setup_world(bubble);
+        // Lets the enemies spread out and the dormant ones fall asleep
+        for _ in 0..10 {
+            run_frame(&mut world);
+        }
+        group.bench_function(name, |b| b.iter(|| run_frame(&mut world)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_activity_bubble);
+criterion_main!(benches);
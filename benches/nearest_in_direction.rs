@@ -0,0 +1,79 @@
+//! Compares finding the entities near another entity with and without the spatial grid
+
+use criterion::{criterion_group, criterion_main, Criterion, black_box};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use sdl2::rect::Point;
+use specs::{World, Builder, Entity, Entities, Join, Read, ReadStorage, RunNow, System};
+
+use caves::components::{Position, BoundingBox, MovementDirection};
+use caves::map::{FloorMap, GridSize};
+use caves::resources::{FramesElapsed, SpatialGrid};
+use caves::systems::{Physics, DirectionQuery, nearest_in_direction};
+
+const ENTITIES: usize = 500;
+const TILE_SIZE: u32 = 16;
+
+/// Creates a world with entities scattered randomly across a level and returns the entities
+fn setup_world() -> (World, Vec<Entity>) {
+    let size = GridSize {rows: 40, cols: 50};
+    let mut world = World::new();
+    System::setup(&mut Physics, &mut world.res);
+    world.add_resource(FramesElapsed(1));
+    world.add_resource(FloorMap::new(size, TILE_SIZE));
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let width = (size.cols as u32 * TILE_SIZE) as i32;
+    let height = (size.rows as u32 * TILE_SIZE) as i32;
+    let entities = (0..ENTITIES).map(|_| {
+        world.create_entity()
+            .with(Position(Point::new(rng.gen_range(0, width), rng.gen_range(0, height))))
+            .with(BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE})
+            .build()
+    }).collect();
+
+    // Physics builds the spatial grid
+    Physics.run_now(&world.res);
+    world.maintain();
+
+    (world, entities)
+}
+
+fn bench_nearest_in_direction(c: &mut Criterion) {
+    let (world, entities) = setup_world();
+    let (world_entities, positions, bounding_boxes, grid) = world.system_data::<(
+        Entities<'_>,
+        ReadStorage<'_, Position>,
+        ReadStorage<'_, BoundingBox>,
+        Read<'_, SpatialGrid>,
+    )>();
+    let bounds = BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE};
+    let range = TILE_SIZE as i32 / 2;
+    let query = |pos| DirectionQuery {pos, direction: MovementDirection::East, bounds, range, margin: 0};
+
+    // Every entity searches once, just like if every entity attacked during the same frame
+    let mut group = c.benchmark_group("nearest_in_direction");
+    group.bench_function("full search", |b| b.iter(|| {
+        for &entity in &entities {
+            let Position(pos) = *positions.get(entity).unwrap();
+            black_box(nearest_in_direction(|_| (&world_entities).join(), &positions,
+                &bounding_boxes, entity, query(pos)));
+        }
+    }));
+    group.bench_function("spatial grid", |b| b.iter(|| {
+        for &entity in &entities {
+            let Position(pos) = *positions.get(entity).unwrap();
+            black_box(nearest_in_direction(|region| grid.query(region), &positions,
+                &bounding_boxes, entity, query(pos)));
+        }
+    }));
+    group.finish();
+}
+
+fn bench_rebuild(c: &mut Criterion) {
+    let (world, _) = setup_world();
+    // The grid is rebuilt on every frame, so that cost has to be paid even if nothing is searched
+    c.bench_function("physics with spatial grid", |b| b.iter(|| Physics.run_now(&world.res)));
+}
+
+criterion_group!(benches, bench_nearest_in_direction, bench_rebuild);
+criterion_main!(benches);
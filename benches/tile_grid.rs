@@ -0,0 +1,69 @@
+//! Compares updating every tile of a large grid one row at a time and in parallel
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use caves::map::{TileGrid, GridSize, Tile};
+use caves::map_sprites::WallSprite;
+
+const SIZE: GridSize = GridSize {rows: 200, cols: 200};
+
+/// Creates a grid with walls scattered randomly across it
+fn setup_grid() -> TileGrid {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut grid = TileGrid::new(SIZE);
+    for pos in grid.tile_positions().collect::<Vec<_>>() {
+        if rng.gen_bool(0.4) {
+            grid.place_tile(pos, Tile::new_wall(WallSprite::default()));
+        }
+    }
+    grid
+}
+
+/// Returns which of the tiles adjacent to each wall are also walls, row by row. This is the same
+/// read-only pass that the generator does before laying out the wall sprites.
+fn wall_adjacency(grid: &TileGrid) -> Vec<Vec<Option<WallSprite>>> {
+    let mut sprites = vec![vec![None; grid.cols_len()]; grid.rows_len()];
+    for (pos, tile) in grid.tiles() {
+        if !tile.is_wall() {
+            continue;
+        }
+        let is_wall = |adj: Option<_>| adj.map(|adj| grid.get(adj).is_wall()).unwrap_or(false);
+        sprites[pos.row][pos.col] = Some(WallSprite {
+            wall_north: is_wall(pos.adjacent_north()),
+            wall_east: is_wall(pos.adjacent_east(grid.cols_len())),
+            wall_south: is_wall(pos.adjacent_south(grid.rows_len())),
+            wall_west: is_wall(pos.adjacent_west()),
+            ..WallSprite::default()
+        });
+    }
+    sprites
+}
+
+fn bench_wall_sprites(c: &mut Criterion) {
+    let grid = setup_grid();
+
+    let mut group = c.benchmark_group("wall sprites 200x200");
+    group.bench_function("adjacency", |b| b.iter(|| wall_adjacency(&grid)));
+
+    let sprites = wall_adjacency(&grid);
+    group.bench_function("apply serial", |b| b.iter_batched_ref(|| grid.clone(), |grid| {
+        for (pos, tile) in grid.tiles_mut() {
+            if let Some(sprite) = sprites[pos.row][pos.col] {
+                tile.set_wall_sprite(sprite);
+            }
+        }
+    }, criterion::BatchSize::LargeInput));
+    group.bench_function("apply parallel", |b| b.iter_batched_ref(|| grid.clone(), |grid| {
+        use rayon::prelude::*;
+        grid.par_tiles_mut().for_each(|(pos, tile)| {
+            if let Some(sprite) = sprites[pos.row][pos.col] {
+                tile.set_wall_sprite(sprite);
+            }
+        });
+    }, criterion::BatchSize::LargeInput));
+    group.finish();
+}
+
+criterion_group!(benches, bench_wall_sprites);
+criterion_main!(benches);
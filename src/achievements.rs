@@ -0,0 +1,595 @@
+//! Achievements unlocked by the player across every run of the game
+
+use std::fs;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::collections::{BTreeSet, BTreeMap};
+
+use crate::resources::{FramesElapsed, GameEvent};
+use crate::upgrades::{Upgrade, Upgrades, RunOutcome, banked_coins};
+
+/// The floor that must be reached for the DeepDiver achievement
+const DEEP_DIVER_FLOOR: usize = 5;
+/// The number of frames that a floor must be cleared within for the Speedrunner achievement
+/// (~60 seconds)
+const SPEEDRUNNER_FRAMES: usize = 30 * 60;
+/// The number of chests that must be opened (across every run) for the TreasureHunter achievement
+const TREASURE_HUNTER_CHESTS: u64 = 25;
+/// The number of frames that an entire run must be won within for the Lightning achievement
+/// (~15 minutes)
+const LIGHTNING_FRAMES: usize = 30 * 60 * 15;
+
+/// Something the player can accomplish once and keep forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Achievement {
+    /// Defeat an enemy
+    FirstKill,
+    /// Go down the stairs of a floor without taking any damage on it
+    Untouchable,
+    /// Go down the stairs of a floor within SPEEDRUNNER_FRAMES of entering it
+    Speedrunner,
+    /// Reach floor DEEP_DIVER_FLOOR
+    DeepDiver,
+    /// Open TREASURE_HUNTER_CHESTS chests
+    TreasureHunter,
+    /// Drink a potion
+    BottomsUp,
+    /// Make it to the end of a run
+    Victorious,
+    /// Make it to the end of a run within LIGHTNING_FRAMES of starting it
+    Lightning,
+}
+
+impl Achievement {
+    /// All of the achievements that can be unlocked
+    pub const ALL: [Achievement; 8] = [
+        Achievement::FirstKill,
+        Achievement::Untouchable,
+        Achievement::Speedrunner,
+        Achievement::DeepDiver,
+        Achievement::TreasureHunter,
+        Achievement::BottomsUp,
+        Achievement::Victorious,
+        Achievement::Lightning,
+    ];
+
+    /// The name of this achievement in the profile file. Must never change once released.
+    pub fn id(self) -> &'static str {
+        use self::Achievement::*;
+        match self {
+            FirstKill => "first_kill",
+            Untouchable => "untouchable",
+            Speedrunner => "speedrunner",
+            DeepDiver => "deep_diver",
+            TreasureHunter => "treasure_hunter",
+            BottomsUp => "bottoms_up",
+            Victorious => "victorious",
+            Lightning => "lightning",
+        }
+    }
+
+    /// Returns the achievement with the given ID, if any
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|achievement| achievement.id() == id)
+    }
+
+    /// The name of this achievement shown to the player
+    pub fn title(self) -> &'static str {
+        use self::Achievement::*;
+        match self {
+            FirstKill => "First Blood",
+            Untouchable => "Untouchable",
+            Speedrunner => "Speedrunner",
+            DeepDiver => "Deep Diver",
+            TreasureHunter => "Treasure Hunter",
+            BottomsUp => "Bottoms Up",
+            Victorious => "Victorious",
+            Lightning => "Lightning",
+        }
+    }
+
+    /// Creates a matcher that has not made any progress towards this achievement
+    pub fn matcher(self) -> Box<dyn AchievementMatcher> {
+        use self::Achievement::*;
+        match self {
+            FirstKill => Box::new(CountMatcher::new(1, |event| *event == GameEvent::EnemyKilled)),
+            Untouchable => Box::new(NoDamageFloor::default()),
+            Speedrunner => Box::new(QuickFloor::new(SPEEDRUNNER_FRAMES)),
+            DeepDiver => Box::new(ReachFloor::new(DEEP_DIVER_FLOOR)),
+            TreasureHunter => Box::new(CountMatcher::new(TREASURE_HUNTER_CHESTS, |event| *event == GameEvent::ChestOpened)),
+            BottomsUp => Box::new(CountMatcher::new(1, |event| *event == GameEvent::PotionDrunk)),
+            Victorious => Box::new(CountMatcher::new(1, |event| matches!(event, GameEvent::RunWon {..}))),
+            Lightning => Box::new(CountMatcher::new(1, |event| match *event {
+                GameEvent::RunWon {frames} => frames <= LIGHTNING_FRAMES,
+                _ => false,
+            })),
+        }
+    }
+}
+
+/// Decides when an achievement is unlocked by watching the events of the game as they happen
+pub trait AchievementMatcher {
+    /// Called for every game event in the order that the events occurred
+    fn on_event(&mut self, event: &GameEvent);
+
+    /// Called once for every update of the game while the player is on a floor, before any of
+    /// the events of that update
+    fn on_frame(&mut self, _frames: FramesElapsed) {}
+
+    /// Returns true once the achievement has been unlocked
+    fn is_unlocked(&self) -> bool;
+
+    /// Returns the progress towards the achievement that should be kept between runs. Progress
+    /// that only matters within a single run is not saved.
+    fn progress(&self) -> u64 {
+        0
+    }
+
+    /// Restores progress previously returned by `progress`
+    fn restore(&mut self, _progress: u64) {}
+}
+
+/// Unlocked once a certain number of matching events have occurred. The count is kept between
+/// runs.
+pub struct CountMatcher {
+    target: u64,
+    count: u64,
+    matches: fn(&GameEvent) -> bool,
+}
+
+impl CountMatcher {
+    pub fn new(target: u64, matches: fn(&GameEvent) -> bool) -> Self {
+        Self {target, count: 0, matches}
+    }
+}
+
+impl AchievementMatcher for CountMatcher {
+    fn on_event(&mut self, event: &GameEvent) {
+        if (self.matches)(event) {
+            self.count = (self.count + 1).min(self.target);
+        }
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.count >= self.target
+    }
+
+    fn progress(&self) -> u64 {
+        self.count
+    }
+
+    fn restore(&mut self, progress: u64) {
+        self.count = progress.min(self.target);
+    }
+}
+
+/// Unlocked when a floor is cleared without taking any damage since entering it
+#[derive(Debug, Default)]
+pub struct NoDamageFloor {
+    damaged: bool,
+    unlocked: bool,
+}
+
+impl AchievementMatcher for NoDamageFloor {
+    fn on_event(&mut self, event: &GameEvent) {
+        match *event {
+            GameEvent::FloorEntered {..} => self.damaged = false,
+            GameEvent::DamageTaken {..} => self.damaged = true,
+            GameEvent::FloorCleared {..} if !self.damaged => self.unlocked = true,
+            _ => {},
+        }
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+}
+
+/// Unlocked when a floor is cleared within a certain number of frames of entering it
+#[derive(Debug)]
+pub struct QuickFloor {
+    max_frames: usize,
+    /// The frames spent on the current floor or None if the player is not on a floor yet
+    frames: Option<usize>,
+    unlocked: bool,
+}
+
+impl QuickFloor {
+    pub fn new(max_frames: usize) -> Self {
+        Self {max_frames, frames: None, unlocked: false}
+    }
+}
+
+impl AchievementMatcher for QuickFloor {
+    fn on_event(&mut self, event: &GameEvent) {
+        match *event {
+            GameEvent::FloorEntered {..} => self.frames = Some(0),
+            GameEvent::FloorCleared {..} => {
+                if let Some(frames) = self.frames.take() {
+                    self.unlocked |= frames <= self.max_frames;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn on_frame(&mut self, FramesElapsed(frames_elapsed): FramesElapsed) {
+        if let Some(frames) = &mut self.frames {
+            *frames += frames_elapsed;
+        }
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+}
+
+/// Unlocked when a certain floor (or any floor below it) is entered
+#[derive(Debug)]
+pub struct ReachFloor {
+    floor: usize,
+    unlocked: bool,
+}
+
+impl ReachFloor {
+    pub fn new(floor: usize) -> Self {
+        Self {floor, unlocked: false}
+    }
+}
+
+impl AchievementMatcher for ReachFloor {
+    fn on_event(&mut self, event: &GameEvent) {
+        if let GameEvent::FloorEntered {floor} = *event {
+            self.unlocked |= floor >= self.floor;
+        }
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+}
+
+/// The achievements, coins, and upgrades of the player that are kept between runs of the game
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub unlocked: BTreeSet<Achievement>,
+    /// The saved progress towards achievements that have not been unlocked yet
+    pub progress: BTreeMap<Achievement, u64>,
+    /// The coins banked from previous runs that have not been spent yet
+    pub coins: u64,
+    /// The permanent upgrades that have been bought
+    pub upgrades: Upgrades,
+}
+
+impl Profile {
+    /// Loads the profile from the given file. An empty profile is returned if the file does not
+    /// exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves the profile to the given file, replacing anything that was there before
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Banks the coins collected during a run that ended with the given outcome
+    pub fn bank_coins(&mut self, collected: u64, outcome: RunOutcome) {
+        self.coins += banked_coins(collected, outcome);
+    }
+
+    /// Parses a profile from the format written by `save`: one achievement per line followed by
+    /// either "unlocked" or the progress made towards it. The banked coins are on a line starting
+    /// with "coins" and each upgrade is on a line starting with "upgrade" followed by its tier.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData,
+            format!("invalid line in profile: `{}`", line));
+
+        let mut profile = Self::default();
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            let first = parts.next();
+            if first == Some("coins") {
+                profile.coins = parts.next().and_then(|coins| coins.parse().ok())
+                    .ok_or_else(|| invalid(line))?;
+            } else if first == Some("upgrade") {
+                let upgrade = parts.next().and_then(Upgrade::from_id)
+                    .ok_or_else(|| invalid(line))?;
+                let tier = parts.next().and_then(|tier| tier.parse().ok())
+                    .filter(|&tier| tier <= upgrade.max_tier())
+                    .ok_or_else(|| invalid(line))?;
+                profile.upgrades.set_tier(upgrade, tier);
+            } else {
+                let achievement = first.and_then(Achievement::from_id)
+                    .ok_or_else(|| invalid(line))?;
+                match parts.next() {
+                    Some("unlocked") => {profile.unlocked.insert(achievement);},
+                    Some(progress) => {
+                        let progress = progress.parse().map_err(|_| invalid(line))?;
+                        profile.progress.insert(achievement, progress);
+                    },
+                    None => return Err(invalid(line)),
+                }
+            }
+            if parts.next().is_some() {
+                return Err(invalid(line));
+            }
+        }
+        Ok(profile)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for achievement in &self.unlocked {
+            writeln!(f, "{} unlocked", achievement.id())?;
+        }
+        for (achievement, progress) in &self.progress {
+            if !self.unlocked.contains(achievement) {
+                writeln!(f, "{} {}", achievement.id(), progress)?;
+            }
+        }
+        if self.coins > 0 {
+            writeln!(f, "coins {}", self.coins)?;
+        }
+        for (upgrade, tier) in self.upgrades.iter() {
+            writeln!(f, "upgrade {} {}", upgrade.id(), tier)?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds the events of the game to the matchers of every achievement that has not been unlocked
+/// yet
+pub struct Achievements {
+    unlocked: BTreeSet<Achievement>,
+    matchers: Vec<(Achievement, Box<dyn AchievementMatcher>)>,
+}
+
+impl Achievements {
+    /// Continues from the achievements and progress saved in the given profile
+    pub fn new(profile: &Profile) -> Self {
+        let matchers = Achievement::ALL.iter()
+            .filter(|achievement| !profile.unlocked.contains(achievement))
+            .map(|&achievement| {
+                let mut matcher = achievement.matcher();
+                if let Some(&progress) = profile.progress.get(&achievement) {
+                    matcher.restore(progress);
+                }
+                (achievement, matcher)
+            })
+            .collect();
+
+        Self {
+            unlocked: profile.unlocked.clone(),
+            matchers,
+        }
+    }
+
+    /// Updates every matcher with the frames that have elapsed (if the player was on a floor) and
+    /// the events that occurred. Returns the achievements that were unlocked as a result.
+    pub fn dispatch(&mut self, frames_elapsed: Option<FramesElapsed>, events: &[GameEvent]) -> Vec<Achievement> {
+        let mut unlocked = Vec::new();
+        for (achievement, matcher) in &mut self.matchers {
+            if let Some(frames_elapsed) = frames_elapsed {
+                matcher.on_frame(frames_elapsed);
+            }
+            for event in events {
+                matcher.on_event(event);
+            }
+            if matcher.is_unlocked() {
+                unlocked.push(*achievement);
+            }
+        }
+
+        self.matchers.retain(|(achievement, _)| !unlocked.contains(achievement));
+        self.unlocked.extend(unlocked.iter().cloned());
+        unlocked
+    }
+
+    /// Returns the profile that should be saved to keep the current achievements and progress.
+    /// The coins and upgrades of the returned profile are left empty.
+    pub fn profile(&self) -> Profile {
+        Profile {
+            unlocked: self.unlocked.clone(),
+            progress: self.matchers.iter()
+                .map(|(achievement, matcher)| (*achievement, matcher.progress()))
+                .filter(|&(_, progress)| progress > 0)
+                .collect(),
+            ..Profile::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::resources::FloorStats;
+
+    use self::GameEvent::*;
+
+    fn cleared(floor: usize) -> GameEvent {
+        FloorCleared {floor, stats: FloorStats::default()}
+    }
+
+    fn feed(matcher: &mut dyn AchievementMatcher, events: &[GameEvent]) {
+        for event in events {
+            matcher.on_event(event);
+        }
+    }
+
+    #[test]
+    fn first_kill() {
+        let mut matcher = Achievement::FirstKill.matcher();
+        feed(&mut *matcher, &[FloorEntered {floor: 1}, DamageTaken {amount: 3}, ItemFound, cleared(1)]);
+        assert!(!matcher.is_unlocked());
+        assert_eq!(matcher.progress(), 0);
+
+        feed(&mut *matcher, &[EnemyKilled]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn count_matcher_progress() {
+        let mut matcher = CountMatcher::new(3, |event| *event == ItemFound);
+        feed(&mut matcher, &[ItemFound, EnemyKilled, ItemFound]);
+        assert!(!matcher.is_unlocked());
+        assert_eq!(matcher.progress(), 2);
+
+        // Progress carries over into a new matcher
+        let mut restored = CountMatcher::new(3, |event| *event == ItemFound);
+        restored.restore(matcher.progress());
+        feed(&mut restored, &[ItemFound, ItemFound]);
+        assert!(restored.is_unlocked());
+        assert_eq!(restored.progress(), 3);
+    }
+
+    #[test]
+    fn untouchable() {
+        let mut matcher = Achievement::Untouchable.matcher();
+        feed(&mut *matcher, &[FloorEntered {floor: 1}, EnemyKilled, DamageTaken {amount: 5}, cleared(1)]);
+        assert!(!matcher.is_unlocked());
+
+        // Damage taken on one floor does not count against the next one
+        feed(&mut *matcher, &[FloorEntered {floor: 2}, DamageTaken {amount: 1}]);
+        assert!(!matcher.is_unlocked());
+        feed(&mut *matcher, &[FloorEntered {floor: 1}, ItemFound, cleared(1)]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn speedrunner() {
+        let mut matcher = Achievement::Speedrunner.matcher();
+        // Frames before entering a floor do not count
+        matcher.on_frame(FramesElapsed(SPEEDRUNNER_FRAMES * 2));
+        feed(&mut *matcher, &[FloorEntered {floor: 1}]);
+        matcher.on_frame(FramesElapsed(SPEEDRUNNER_FRAMES + 1));
+        feed(&mut *matcher, &[cleared(1)]);
+        assert!(!matcher.is_unlocked());
+
+        // Going back up does not clear the floor
+        feed(&mut *matcher, &[FloorEntered {floor: 2}]);
+        matcher.on_frame(FramesElapsed(10));
+        feed(&mut *matcher, &[FloorEntered {floor: 1}]);
+        matcher.on_frame(FramesElapsed(SPEEDRUNNER_FRAMES));
+        feed(&mut *matcher, &[cleared(1)]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn deep_diver() {
+        let mut matcher = Achievement::DeepDiver.matcher();
+        for floor in 1..DEEP_DIVER_FLOOR {
+            feed(&mut *matcher, &[FloorEntered {floor}, cleared(floor)]);
+            assert!(!matcher.is_unlocked());
+        }
+        feed(&mut *matcher, &[FloorEntered {floor: DEEP_DIVER_FLOOR}]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn treasure_hunter() {
+        let mut matcher = Achievement::TreasureHunter.matcher();
+        for _ in 1..TREASURE_HUNTER_CHESTS {
+            feed(&mut *matcher, &[ChestOpened, ItemFound]);
+        }
+        assert!(!matcher.is_unlocked());
+        // Chests opened in previous runs count
+        assert_eq!(matcher.progress(), TREASURE_HUNTER_CHESTS - 1);
+
+        let mut restored = Achievement::TreasureHunter.matcher();
+        restored.restore(matcher.progress());
+        feed(&mut *restored, &[ChestOpened]);
+        assert!(restored.is_unlocked());
+    }
+
+    #[test]
+    fn bottoms_up() {
+        let mut matcher = Achievement::BottomsUp.matcher();
+        feed(&mut *matcher, &[FloorEntered {floor: 1}, ChestOpened, ItemFound]);
+        assert!(!matcher.is_unlocked());
+
+        feed(&mut *matcher, &[PotionDrunk]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn victorious() {
+        let mut matcher = Achievement::Victorious.matcher();
+        feed(&mut *matcher, &[FloorEntered {floor: DEEP_DIVER_FLOOR}, cleared(DEEP_DIVER_FLOOR)]);
+        assert!(!matcher.is_unlocked());
+
+        // Any victory counts, no matter how long it took
+        feed(&mut *matcher, &[RunWon {frames: LIGHTNING_FRAMES * 10}]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn lightning() {
+        let mut matcher = Achievement::Lightning.matcher();
+        // Clearing a single floor quickly is not enough
+        feed(&mut *matcher, &[FloorEntered {floor: 1}, cleared(1), RunWon {frames: LIGHTNING_FRAMES + 1}]);
+        assert!(!matcher.is_unlocked());
+        assert_eq!(matcher.progress(), 0);
+
+        feed(&mut *matcher, &[RunWon {frames: LIGHTNING_FRAMES}]);
+        assert!(matcher.is_unlocked());
+    }
+
+    #[test]
+    fn achievements_unlocked_once() {
+        let mut achievements = Achievements::new(&Profile::default());
+        assert_eq!(achievements.dispatch(Some(FramesElapsed(1)), &[FloorEntered {floor: 1}]), &[]);
+        assert_eq!(achievements.dispatch(Some(FramesElapsed(1)), &[EnemyKilled, cleared(1)]),
+            &[Achievement::FirstKill, Achievement::Untouchable, Achievement::Speedrunner]);
+        assert_eq!(achievements.dispatch(None, &[FloorEntered {floor: 1}, EnemyKilled, cleared(1)]), &[]);
+
+        let profile = achievements.profile();
+        assert_eq!(profile.unlocked.len(), 3);
+        // Already unlocked achievements are not unlocked again in the next run
+        let mut achievements = Achievements::new(&profile);
+        assert_eq!(achievements.dispatch(None, &[FloorEntered {floor: DEEP_DIVER_FLOOR}, EnemyKilled]),
+            &[Achievement::DeepDiver]);
+    }
+
+    #[test]
+    fn profile_round_trip() {
+        let mut profile = Profile::default();
+        profile.unlocked.insert(Achievement::DeepDiver);
+        profile.unlocked.insert(Achievement::FirstKill);
+        profile.progress.insert(Achievement::Untouchable, 7);
+
+        let contents = profile.to_string();
+        assert_eq!(contents, "first_kill unlocked\ndeep_diver unlocked\nuntouchable 7\n");
+        assert_eq!(Profile::parse(&contents).unwrap(), profile);
+
+        for invalid in &["first_kill", "unknown unlocked", "first_kill twice", "first_kill 1 2"] {
+            assert_eq!(Profile::parse(invalid).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn profile_keeps_coins_and_upgrades() {
+        let mut profile = Profile::default();
+        profile.unlocked.insert(Achievement::FirstKill);
+        profile.bank_coins(40, RunOutcome::Victory);
+        profile.bank_coins(161, RunOutcome::Death);
+        assert_eq!(profile.coins, 120);
+        profile.upgrades.set_tier(Upgrade::MaxHealth, 2);
+
+        let contents = profile.to_string();
+        assert_eq!(contents, "first_kill unlocked\ncoins 120\nupgrade max_health 2\n");
+        assert_eq!(Profile::parse(&contents).unwrap(), profile);
+
+        for invalid in &["coins", "coins many", "coins 1 2", "upgrade max_health", "upgrade wings 1",
+            "upgrade max_health 99", "upgrade max_health 1 2"] {
+            assert_eq!(Profile::parse(invalid).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,53 +1,235 @@
 mod texture_manager;
 mod sprite_manager;
 mod sprite;
+mod lazy_animations;
+mod palette;
+mod asset_paths;
+mod asset_watcher;
+mod load_report;
 
 pub use self::texture_manager::*;
 pub use self::sprite_manager::*;
 pub use self::sprite::*;
+pub use self::lazy_animations::*;
+pub use self::palette::*;
+pub use self::asset_paths::*;
+pub use self::asset_watcher::*;
+pub use self::load_report::*;
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use sdl2::render::TextureCreator;
 
 use crate::components::AnimationManager;
 use crate::map_sprites::MapSprites;
+use crate::audio::AudioManager;
+use crate::generator::EnemyType;
 use crate::ui::SDLError;
 
+/// The spritesheet of the player
+const HERO_PATH: &str = "hero.png";
+/// The spritesheet of the map
+const DUNGEON_PATH: &str = "dungeon.png";
+/// The spritesheet of the rat enemy
+const RAT_PATH: &str = "enemies/rat.png";
+
+/// How opaque the ghost of a previous run is drawn, from 0 (invisible) to 255 (fully opaque)
+const GHOST_ALPHA: u8 = 120;
+
+/// A single magenta tile drawn in place of any character spritesheet that could not be found so
+/// that the game can still run during development
+const PLACEHOLDER_PNG: &[u8] = include_bytes!("../assets/placeholder.png");
+
+/// The size (in px) of a tile in every spritesheet. Sprites are scaled from this size to the
+/// tile size of the map when they are rendered.
+pub const NATIVE_TILE_SIZE: u32 = 16;
+/// The smallest tile size (in px) that sprites can be scaled to
+pub const MIN_TILE_SIZE: u32 = 8;
+/// The largest tile size (in px) that sprites can be scaled to
+pub const MAX_TILE_SIZE: u32 = 64;
+
+/// Returns true if sprites can be scaled to the given tile size. Only multiples of 8 are
+/// supported so that every 16px tile of a spritesheet scales to a whole number of pixels.
+pub fn is_supported_tile_size(tile_size: u32) -> bool {
+    (MIN_TILE_SIZE..=MAX_TILE_SIZE).contains(&tile_size) && tile_size.is_multiple_of(8)
+}
+
+/// Converts a length (in px) measured at NATIVE_TILE_SIZE to the same length relative to the
+/// given tile size, rounded to the nearest pixel. Used for sprite sizes, bounding boxes, and
+/// knockback so that everything keeps the same proportions at any tile size.
+pub fn scale_to_tile_size(length: i32, tile_size: u32) -> i32 {
+    let native = NATIVE_TILE_SIZE as i32;
+    let scaled = length * tile_size as i32;
+    // Round half away from zero so that negative offsets scale the same way as positive ones
+    if scaled >= 0 {
+        (scaled + native / 2) / native
+    } else {
+        (scaled - native / 2) / native
+    }
+}
+
+/// Converts a movement speed (in px/frame) measured at NATIVE_TILE_SIZE to the same speed relative
+/// to the given tile size. Unlike lengths, speeds are not rounded since entities can move by
+/// fractions of a pixel.
+pub fn scale_speed_to_tile_size(speed: f32, tile_size: u32) -> f32 {
+    speed * tile_size as f32 / NATIVE_TILE_SIZE as f32
+}
+
+/// Enemy spritesheets are only loaded once a level that can generate that enemy needs them
 pub struct EnemyAnimations {
-    pub rat: AnimationManager,
+    pub rat: LazyAnimations,
+}
+
+impl EnemyAnimations {
+    /// Returns the animations for the given type of enemy
+    pub fn get_mut(&mut self, enemy: EnemyType) -> &mut LazyAnimations {
+        use crate::generator::EnemyType::*;
+        match enemy {
+            Rat => &mut self.rat,
+        }
+    }
+
+    /// Returns the animations for every type of enemy
+    pub fn all_mut(&mut self) -> Vec<&mut LazyAnimations> {
+        vec![&mut self.rat]
+    }
 }
 
 pub struct AssetManager<'a, T> {
     pub textures: TextureManager<'a, T>,
     pub map_sprites: MapSprites,
     pub player_animations: AnimationManager,
+    /// A translucent copy of the player spritesheet used to draw the ghost of a previous run
+    pub ghost_texture: TextureId,
     pub enemy_animations: EnemyAnimations,
     pub sprites: SpriteManager,
+    pub audio: AudioManager,
 }
 
 impl<'a, T> AssetManager<'a, T> {
-    pub fn load(texture_creator: &'a TextureCreator<T>, fps: usize, tile_size: u32) -> Result<Self, SDLError> {
-        let mut textures = TextureManager::new(&texture_creator);
-        let mut sprites = SpriteManager::default();
+    /// Loads every asset that is always needed. The player is drawn with the given palette.
+    ///
+    /// Assets are searched for in each of the directories of `AssetPaths::from_env`. The dungeon
+    /// spritesheet is required, but a placeholder is used for any missing character spritesheet.
+    pub fn load(
+        texture_creator: &'a TextureCreator<T>,
+        fps: usize,
+        hero_palette: HeroPalette,
+    ) -> Result<Self, SDLError> {
+        Self::load_with_report(texture_creator, fps, hero_palette).map(|(assets, _)| assets)
+    }
+
+    /// Same as `load`, but also returns how long each asset took to load
+    ///
+    /// Textures can only be created on the thread that created the window, so they are created
+    /// first. The sprites of each spritesheet are then laid out in parallel, each in a separate
+    /// sprite manager, and combined once they are all done.
+    pub fn load_with_report(
+        texture_creator: &'a TextureCreator<T>,
+        fps: usize,
+        hero_palette: HeroPalette,
+    ) -> Result<(Self, LoadReport), SDLError> {
+        let start = Instant::now();
+        let mut report = LoadReport::default();
+        let mut textures = TextureManager::new(texture_creator);
 
-        let map_texture = textures.create_png_texture("assets/dungeon.png")?;
-        let map_sprites = MapSprites::from_dungeon_spritesheet(map_texture, &mut sprites, tile_size);
+        let asset_paths = AssetPaths::from_env();
 
-        let mut character_animations = |path| {
-            let texture = textures.create_png_texture(path)?;
-            Ok(AnimationManager::standard_character_animations(fps, texture, &mut sprites))
+        let map_texture = report.time(DUNGEON_PATH, || -> Result<_, SDLError> {
+            textures.create_png_texture(asset_paths.find(DUNGEON_PATH)?)
+        })?;
+
+        // The placeholder is a single tile, so its animations are laid out differently
+        let (hero_texture, ghost_texture, hero_placeholder) = match find_character_spritesheet(&asset_paths, HERO_PATH) {
+            Some(hero_path) => {
+                let texture = report.time(HERO_PATH, || create_hero_texture(&mut textures, &hero_path, hero_palette))?;
+                // The ghost keeps the original colors so that it stands out from the player
+                let ghost_texture = textures.create_translucent_png_texture(&hero_path, GHOST_ALPHA)?;
+                (texture, ghost_texture, false)
+            },
+            None => {
+                let texture = report.time("placeholder.png", || textures.create_png_texture_from_bytes(PLACEHOLDER_PNG))?;
+                (texture, texture, true)
+            },
+        };
+
+        let ((mut map_sprites, map_sheet, map_time), (mut player_animations, hero_sheet, hero_time)) = rayon::join(
+            || {
+                let mut sheet = SpriteManager::default();
+                let (map_sprites, time) = timed(|| MapSprites::from_dungeon_spritesheet(map_texture, &mut sheet));
+                (map_sprites, sheet, time)
+            },
+            || {
+                let mut sheet = SpriteManager::default();
+                let (animations, time) = timed(|| if hero_placeholder {
+                    AnimationManager::placeholder_character_animations(fps, hero_texture, &mut sheet)
+                } else {
+                    AnimationManager::standard_character_animations(fps, hero_texture, &mut sheet)
+                });
+                (animations, sheet, time)
+            },
+        );
+        report.record("map sprites", map_time);
+        report.record("player animations", hero_time);
+
+        let mut sprites = SpriteManager::default();
+        map_sprites.offset_sprites(sprites.append(map_sheet));
+        player_animations.offset_sprites(sprites.append(hero_sheet));
+
+        let rat = match find_character_spritesheet(&asset_paths, RAT_PATH) {
+            Some(rat_path) => LazyAnimations::new(rat_path, fps),
+            None => LazyAnimations::placeholder(fps),
         };
 
-        let player_animations = character_animations("assets/hero.png")?;
-        let rat = character_animations("assets/enemies/rat.png")?;
+        // Audio is optional, so this never fails
+        let audio = report.time("audio", AudioManager::load);
 
-        Ok(Self {
+        report.set_elapsed(start.elapsed());
+        let assets = Self {
             textures,
             map_sprites,
             player_animations,
+            ghost_texture,
             enemy_animations: EnemyAnimations {
                 rat,
             },
             sprites,
-        })
+            audio,
+        };
+        Ok((assets, report))
+    }
+}
+
+/// Creates the texture of the hero spritesheet at the given path with its colors replaced by the
+/// given palette
+fn create_hero_texture<T>(
+    textures: &mut TextureManager<T>,
+    hero_path: &Path,
+    hero_palette: HeroPalette,
+) -> Result<TextureId, SDLError> {
+    let remap = hero_palette.remap();
+    if remap.is_empty() {
+        return textures.create_png_texture(hero_path);
+    }
+
+    let (texture, remapped) = textures.create_remapped_png_texture(hero_path, &remap)?;
+    if remapped == 0 {
+        warn!("None of the colors replaced by the `{}` palette were found in {}",
+            hero_palette.name(), hero_path.display());
+    }
+    Ok(texture)
+}
+
+/// Returns the path to the given character spritesheet, or None (with a warning) if it could not
+/// be found and a placeholder should be used instead
+fn find_character_spritesheet(asset_paths: &AssetPaths, asset: &str) -> Option<PathBuf> {
+    match asset_paths.find(asset) {
+        Ok(path) => Some(path),
+        Err(err) => {
+            warn!("!!! {} !!!", err);
+            warn!("!!! Drawing `{}` as a magenta placeholder. The game will not look right. !!!", asset);
+            None
+        },
     }
 }
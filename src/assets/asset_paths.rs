@@ -0,0 +1,107 @@
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::ui::SDLError;
+
+/// The environment variable that can be set to another directory to search for assets in
+pub const ASSETS_ENV_VAR: &str = "CAVES_ASSETS";
+
+/// The directories that assets are searched for in, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetPaths {
+    dirs: Vec<PathBuf>,
+}
+
+impl AssetPaths {
+    /// Searches `./assets`, then the assets directory next to the source code of the game, then
+    /// the directory in the CAVES_ASSETS environment variable (if it is set)
+    pub fn from_env() -> Self {
+        Self::new(option_env!("CARGO_MANIFEST_DIR"), env::var_os(ASSETS_ENV_VAR))
+    }
+
+    /// Creates the search path from the directory containing the source code of the game and the
+    /// value of the CAVES_ASSETS environment variable. Either may be missing. Each directory is
+    /// only searched once.
+    pub fn new(manifest_dir: Option<&str>, env_dir: Option<OsString>) -> Self {
+        let mut dirs = vec![PathBuf::from("assets")];
+        let manifest_assets = manifest_dir.map(|dir| Path::new(dir).join("assets"));
+        // An empty variable is the same as not setting it at all
+        let env_dir = env_dir.filter(|dir| !dir.is_empty()).map(PathBuf::from);
+        for dir in manifest_assets.into_iter().chain(env_dir) {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        Self {dirs}
+    }
+
+    /// Returns the directories that are searched, in order
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Returns the path to the given asset (relative to an assets directory) in the first
+    /// directory that contains it
+    pub fn find<P: AsRef<Path>>(&self, asset: P) -> Result<PathBuf, SDLError> {
+        self.find_with(asset, Path::is_file)
+    }
+
+    /// Same as `find` but uses the given function to check whether each path exists
+    pub fn find_with<P: AsRef<Path>>(&self, asset: P, exists: impl Fn(&Path) -> bool) -> Result<PathBuf, SDLError> {
+        let asset = asset.as_ref();
+        self.dirs.iter()
+            .map(|dir| dir.join(asset))
+            .find(|path| exists(path))
+            .ok_or_else(|| SDLError::MissingAsset {
+                path: asset.to_path_buf(),
+                searched: self.dirs.clone(),
+                working_dir: env::current_dir().ok(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_path_order() {
+        let paths = AssetPaths::new(Some("/src/caves"), Some(OsString::from("/opt/caves")));
+        assert_eq!(paths.dirs(), &[
+            PathBuf::from("assets"),
+            PathBuf::from("/src/caves/assets"),
+            PathBuf::from("/opt/caves"),
+        ]);
+
+        // Missing and empty values are skipped and no directory is searched twice
+        assert_eq!(AssetPaths::new(None, None).dirs(), &[PathBuf::from("assets")]);
+        assert_eq!(AssetPaths::new(None, Some(OsString::new())).dirs(), &[PathBuf::from("assets")]);
+        assert_eq!(AssetPaths::new(Some(""), Some(OsString::from("assets"))).dirs(), &[PathBuf::from("assets")]);
+    }
+
+    #[test]
+    fn finds_first_directory_with_asset() {
+        let paths = AssetPaths::new(Some("/src/caves"), Some(OsString::from("/opt/caves")));
+        let exists = |available: &'static [&'static str]| move |path: &Path| {
+            available.iter().any(|&available| path == Path::new(available))
+        };
+
+        let found = paths.find_with("hero.png", exists(&["/opt/caves/hero.png", "/src/caves/assets/hero.png"]));
+        assert_eq!(found.unwrap(), PathBuf::from("/src/caves/assets/hero.png"));
+        let found = paths.find_with("enemies/rat.png", exists(&["assets/enemies/rat.png", "/opt/caves/enemies/rat.png"]));
+        assert_eq!(found.unwrap(), PathBuf::from("assets/enemies/rat.png"));
+        let found = paths.find_with("dungeon.png", exists(&["/opt/caves/dungeon.png"]));
+        assert_eq!(found.unwrap(), PathBuf::from("/opt/caves/dungeon.png"));
+
+        // Every directory searched is named in the error
+        match paths.find_with("hero.png", exists(&["assets/dungeon.png"])).unwrap_err() {
+            SDLError::MissingAsset {path, searched, ..} => {
+                assert_eq!(path, PathBuf::from("hero.png"));
+                assert_eq!(searched, paths.dirs());
+            },
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The time between each check for changed files
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The time that each file was last modified, keyed by its path
+type ModifiedTimes = HashMap<PathBuf, SystemTime>;
+
+/// Watches the spritesheets in the assets directories for changes so that they can be reloaded
+/// while the game is running. Only used during development.
+///
+/// The files are checked on a background thread. The game picks up the changes by polling
+/// `changed_paths` once per frame.
+pub struct AssetWatcher {
+    changes: Receiver<PathBuf>,
+}
+
+impl AssetWatcher {
+    /// Starts watching every PNG file in the given directories and their subdirectories.
+    /// Directories that do not exist are skipped.
+    pub fn watch(dirs: &[PathBuf]) -> Self {
+        // Canonicalized so that the paths match the ones that textures were loaded from
+        let dirs: Vec<_> = dirs.iter().filter_map(|dir| dir.canonicalize().ok()).collect();
+        let (sender, changes) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut modified = png_modified_times(&dirs);
+            loop {
+                thread::sleep(POLL_INTERVAL);
+
+                let current = png_modified_times(&dirs);
+                for path in changed_paths(&modified, &current) {
+                    // The game has stopped listening, so there is no reason to keep watching
+                    if sender.send(path).is_err() {
+                        return;
+                    }
+                }
+                modified = current;
+            }
+        });
+
+        Self {changes}
+    }
+
+    /// Returns every file that has changed since the last time this was called, each only once
+    pub fn changed_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = self.changes.try_iter().collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+/// Returns the files that were added or modified between the two snapshots, sorted by path
+fn changed_paths(before: &ModifiedTimes, after: &ModifiedTimes) -> Vec<PathBuf> {
+    let mut changed: Vec<_> = after.iter()
+        .filter(|&(path, time)| before.get(path) != Some(time))
+        .map(|(path, _)| path.clone())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Returns the time that each PNG file in the given directories (and their subdirectories) was
+/// last modified. Any file or directory that cannot be read is skipped.
+fn png_modified_times(dirs: &[PathBuf]) -> ModifiedTimes {
+    let mut times = HashMap::new();
+    for dir in dirs {
+        add_png_modified_times(dir, &mut times);
+    }
+    times
+}
+
+fn add_png_modified_times(dir: &Path, times: &mut ModifiedTimes) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        if metadata.is_dir() {
+            add_png_modified_times(&path, times);
+        } else if path.extension().map(|ext| ext == "png").unwrap_or(false) {
+            if let Ok(modified) = metadata.modified() {
+                times.insert(path, modified);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, process, slice};
+
+    #[test]
+    fn only_added_or_modified_files_changed() {
+        let start = SystemTime::UNIX_EPOCH;
+        let later = start + Duration::from_secs(1);
+        let before: ModifiedTimes = vec![
+            (PathBuf::from("dungeon.png"), start),
+            (PathBuf::from("hero.png"), start),
+            (PathBuf::from("enemies/rat.png"), start),
+        ].into_iter().collect();
+        let after: ModifiedTimes = vec![
+            (PathBuf::from("dungeon.png"), later),
+            (PathBuf::from("hero.png"), start),
+            (PathBuf::from("enemies/snake.png"), start),
+        ].into_iter().collect();
+
+        // Removed files are not reported since there is nothing to reload
+        assert_eq!(changed_paths(&before, &after), &[PathBuf::from("dungeon.png"), PathBuf::from("enemies/snake.png")]);
+        assert!(changed_paths(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn finds_pngs_in_subdirectories() {
+        let dir = env::temp_dir().join(format!("caves-asset-watcher-{}", process::id()));
+        fs::create_dir_all(dir.join("enemies")).unwrap();
+        fs::write(dir.join("dungeon.png"), b"").unwrap();
+        fs::write(dir.join("enemies").join("rat.png"), b"").unwrap();
+        fs::write(dir.join("generator.toml"), b"").unwrap();
+
+        let mut paths: Vec<_> = png_modified_times(slice::from_ref(&dir)).keys().cloned().collect();
+        paths.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(paths, &[dir.join("dungeon.png"), dir.join("enemies").join("rat.png")]);
+    }
+}
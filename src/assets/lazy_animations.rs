@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use specs::{World, Join};
+
+use crate::components::{AnimationManager, Animation, Sprite, EnemySpawn};
+use crate::ui::SDLError;
+
+use super::{TextureManager, TextureId, SpriteManager, SpriteId};
+
+/// A character spritesheet that is only loaded the first time that it is actually needed
+///
+/// Used for sheets that may never be needed during a run (e.g. an enemy type that no level can
+/// generate). The player and dungeon spritesheets are always needed, so they are loaded eagerly.
+pub struct LazyAnimations {
+    /// None if the spritesheet is missing and the placeholder should be used instead
+    path: Option<PathBuf>,
+    fps: usize,
+    loaded: Option<(TextureId, AnimationManager)>,
+}
+
+impl LazyAnimations {
+    /// Creates a handle for the spritesheet at the given path without loading it
+    pub fn new<P: Into<PathBuf>>(path: P, fps: usize) -> Self {
+        Self {
+            path: Some(path.into()),
+            fps,
+            loaded: None,
+        }
+    }
+
+    /// Creates a handle that loads the embedded placeholder texture instead of a spritesheet
+    pub fn placeholder(fps: usize) -> Self {
+        Self {
+            path: None,
+            fps,
+            loaded: None,
+        }
+    }
+
+    /// Returns the texture of the spritesheet if it has been loaded
+    pub fn texture(&self) -> Option<TextureId> {
+        self.loaded.as_ref().map(|&(texture, _)| texture)
+    }
+
+    /// Returns the animations if the spritesheet has been loaded
+    pub fn animations(&self) -> Option<&AnimationManager> {
+        self.loaded.as_ref().map(|(_, animations)| animations)
+    }
+
+    /// Loads the spritesheet if it has not been loaded already
+    pub fn load<T>(
+        &mut self,
+        textures: &mut TextureManager<T>,
+        sprites: &mut SpriteManager,
+    ) -> Result<&AnimationManager, SDLError> {
+        let path = self.path.clone();
+        let fps = self.fps;
+        self.load_with(|| {
+            match path {
+                Some(path) => {
+                    let texture = textures.create_png_texture(path)?;
+                    Ok((texture, AnimationManager::standard_character_animations(fps, texture, sprites)))
+                },
+                None => {
+                    let texture = textures.create_png_texture_from_bytes(super::PLACEHOLDER_PNG)?;
+                    Ok((texture, AnimationManager::placeholder_character_animations(fps, texture, sprites)))
+                },
+            }
+        })
+    }
+
+    /// Calls the given function to load the spritesheet only if it has not been loaded already
+    fn load_with<E, F>(&mut self, load: F) -> Result<&AnimationManager, E>
+        where F: FnOnce() -> Result<(TextureId, AnimationManager), E> {
+        if self.loaded.is_none() {
+            self.loaded = Some(load()?);
+        }
+
+        Ok(self.animations().expect("bug: spritesheet should have been loaded"))
+    }
+
+    /// Unloads the texture of the spritesheet. It will be loaded again the next time it is needed.
+    ///
+    /// Any sprites created from the previous texture must no longer be rendered.
+    pub fn unload<T>(&mut self, textures: &mut TextureManager<T>) {
+        if let Some((texture, _)) = self.loaded.take() {
+            textures.unload(texture);
+        }
+    }
+}
+
+/// Returns true if any entity in the world could render a sprite from the given texture, either
+/// now or in the future (e.g. from an animation or an enemy that hasn't spawned yet)
+pub fn world_uses_texture(world: &World, sprites: &SpriteManager, texture: TextureId) -> bool {
+    let uses_texture = |sprite: SpriteId| sprites.get(sprite).texture_id == texture;
+    let animation_uses_texture = |animation: &Animation| {
+        animation.steps.iter().any(|frame| uses_texture(frame.sprite))
+    };
+    let manager_uses_texture = |manager: &AnimationManager| manager.sprites().any(uses_texture);
+
+    world.read_storage::<Sprite>().join().any(|&Sprite(sprite)| uses_texture(sprite))
+        || world.read_storage::<Animation>().join().any(animation_uses_texture)
+        || world.read_storage::<AnimationManager>().join().any(manager_uses_texture)
+        || world.read_storage::<EnemySpawn>().join().any(|spawn| manager_uses_texture(&spawn.enemy.animations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Builder;
+    use sdl2::rect::Rect;
+
+    use crate::assets::SpriteImage;
+    use crate::components::{EnemyBehaviour, BoundingBox};
+    use crate::generator::EnemyValues;
+
+    #[test]
+    fn loads_only_once() {
+        let mut sprites = SpriteManager::default();
+        let texture = TextureId::placeholder(1);
+        let animations = AnimationManager::standard_character_animations(30, texture, &mut sprites);
+
+        let mut lazy = LazyAnimations::new("assets/enemies/rat.png", 30);
+        assert_eq!(lazy.texture(), None);
+        assert!(lazy.animations().is_none());
+
+        let mut loads = 0;
+        for _ in 0..3 {
+            lazy.load_with(|| -> Result<_, ()> {
+                loads += 1;
+                Ok((texture, animations.clone()))
+            }).unwrap();
+        }
+        assert_eq!(loads, 1);
+        assert_eq!(lazy.texture(), Some(texture));
+
+        // A failed load leaves the spritesheet unloaded so that it can be tried again
+        let mut lazy = LazyAnimations::new("assets/enemies/rat.png", 30);
+        assert!(lazy.load_with(|| Err(())).is_err());
+        assert_eq!(lazy.texture(), None);
+    }
+
+    #[test]
+    fn detects_texture_in_use() {
+        let mut sprites = SpriteManager::default();
+        let used = TextureId::placeholder(1);
+        let unused = TextureId::placeholder(2);
+        let sprite = sprites.add(SpriteImage::new_unflipped(used, Rect::new(0, 0, 16, 16)));
+        let animations = AnimationManager::standard_character_animations(30, used, &mut sprites);
+
+        let mut world = World::new();
+        world.register::<Sprite>();
+        world.register::<Animation>();
+        world.register::<AnimationManager>();
+        world.register::<EnemySpawn>();
+        assert!(!world_uses_texture(&world, &sprites, used));
+
+        let entity = world.create_entity().with(Sprite(sprite)).build();
+        assert!(world_uses_texture(&world, &sprites, used));
+        assert!(!world_uses_texture(&world, &sprites, unused));
+        world.delete_entity(entity).unwrap();
+        world.maintain();
+        assert!(!world_uses_texture(&world, &sprites, used));
+
+        // Enemies that have not spawned yet still count
+        world.create_entity().with(EnemySpawn {
+            probability: 0.5,
+            enemy: EnemyValues {
+                behaviour: EnemyBehaviour::Random,
+                animations,
+                attack: 5,
+                speed: 3.0,
+                health_points: 15,
+                hit_wait: 12,
+                bounding_box: BoundingBox::Full {width: 16, height: 16},
+            },
+        }).build();
+        assert!(world_uses_texture(&world, &sprites, used));
+        assert!(!world_uses_texture(&world, &sprites, unused));
+    }
+}
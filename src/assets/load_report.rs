@@ -0,0 +1,86 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The time that it took to load each asset, in the order that they finished loading
+#[derive(Debug, Default, Clone)]
+pub struct LoadReport {
+    assets: Vec<(String, Duration)>,
+    /// The time it took to load everything. Less than the sum of the time of each asset when some
+    /// of them were loaded in parallel.
+    elapsed: Duration,
+}
+
+impl LoadReport {
+    /// Records that the given asset took the given amount of time to load
+    pub fn record<S: Into<String>>(&mut self, asset: S, duration: Duration) {
+        self.assets.push((asset.into(), duration));
+    }
+
+    /// Calls the given function and records the time it took as the time to load the given asset
+    pub fn time<S: Into<String>, R>(&mut self, asset: S, load: impl FnOnce() -> R) -> R {
+        let (value, duration) = timed(load);
+        self.record(asset, duration);
+        value
+    }
+
+    /// Sets the time it took to load everything
+    pub fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+    }
+
+    /// Returns each asset along with the time it took to load
+    pub fn assets(&self) -> &[(String, Duration)] {
+        &self.assets
+    }
+
+    /// Returns the time it took to load everything
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name_width = self.assets.iter().map(|(asset, _)| asset.len()).max().unwrap_or(0);
+        writeln!(f, "Loaded assets in {:.1}ms", millis(self.elapsed))?;
+        for (asset, duration) in &self.assets {
+            writeln!(f, "  {:width$}  {:>7.1}ms", asset, millis(*duration), width = name_width)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls the given function and returns its value along with the time it took
+pub fn timed<R>(load: impl FnOnce() -> R) -> (R, Duration) {
+    let start = Instant::now();
+    let value = load();
+    (value, start.elapsed())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_assets_in_order() {
+        let mut report = LoadReport::default();
+        report.record("dungeon.png", Duration::from_micros(2500));
+        let value = report.time("hero animations", || 7);
+        assert_eq!(value, 7);
+        report.set_elapsed(Duration::from_millis(4));
+
+        let names: Vec<_> = report.assets().iter().map(|(asset, _)| asset.as_str()).collect();
+        assert_eq!(names, &["dungeon.png", "hero animations"]);
+
+        let text = report.to_string();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("Loaded assets in 4.0ms"));
+        assert_eq!(lines.next(), Some("  dungeon.png          2.5ms"));
+        assert!(lines.next().unwrap().starts_with("  hero animations  "));
+        assert_eq!(lines.next(), None);
+    }
+}
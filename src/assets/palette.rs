@@ -0,0 +1,182 @@
+/// A color without any transparency
+pub type Rgb = (u8, u8, u8);
+
+/// The colors of the hero's hair in assets/hero.png from lightest to darkest
+const HERO_HAIR: [Rgb; 3] = [(243, 206, 64), (207, 140, 78), (195, 100, 65)];
+/// The colors of the hero's tunic in assets/hero.png from lightest to darkest
+const HERO_TUNIC: [Rgb; 2] = [(143, 86, 59), (102, 57, 49)];
+
+/// The pixels of an image with 4 bytes per pixel in the order red, green, blue, alpha
+pub struct RgbaPixels<'a> {
+    pub pixels: &'a mut [u8],
+    pub width: usize,
+    pub height: usize,
+    /// The number of bytes in each row, including any padding at the end of the row
+    pub pitch: usize,
+}
+
+impl<'a> RgbaPixels<'a> {
+    /// Replaces every pixel whose color is one of the source colors with the matching target
+    /// color. Transparency is left unchanged. Returns the number of pixels that were replaced.
+    pub fn remap_colors(&mut self, remap: &[(Rgb, Rgb)]) -> usize {
+        let mut remapped = 0;
+        for row in 0..self.height {
+            let start = row * self.pitch;
+            let row_pixels = &mut self.pixels[start..start + self.width * 4];
+            for pixel in row_pixels.chunks_mut(4) {
+                let color = (pixel[0], pixel[1], pixel[2]);
+                if let Some(&(_, (r, g, b))) = remap.iter().find(|&&(source, _)| source == color) {
+                    pixel[0] = r;
+                    pixel[1] = g;
+                    pixel[2] = b;
+                    remapped += 1;
+                }
+            }
+        }
+        remapped
+    }
+}
+
+/// The colors that the hero can be drawn with. Only the hair and tunic change, the sprites
+/// themselves stay the same.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeroPalette {
+    /// The colors of the original spritesheet (blond hair, brown tunic)
+    #[default]
+    Default,
+    /// Silver hair and a blue tunic
+    Ash,
+    /// Black hair and a green tunic
+    Raven,
+    /// Red hair and a grey tunic
+    Crimson,
+}
+
+impl HeroPalette {
+    /// All of the palettes that can be chosen
+    pub const ALL: [HeroPalette; 4] = [
+        HeroPalette::Default,
+        HeroPalette::Ash,
+        HeroPalette::Raven,
+        HeroPalette::Crimson,
+    ];
+
+    /// The name of this palette in the settings file
+    pub fn name(self) -> &'static str {
+        use self::HeroPalette::*;
+        match self {
+            Default => "default",
+            Ash => "ash",
+            Raven => "raven",
+            Crimson => "crimson",
+        }
+    }
+
+    /// Returns the palette with the given name, if any
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|palette| palette.name() == name)
+    }
+
+    /// Returns the hair and tunic colors of this palette in the same order as the colors of the
+    /// original spritesheet
+    fn colors(self) -> ([Rgb; 3], [Rgb; 2]) {
+        use self::HeroPalette::*;
+        match self {
+            Default => (HERO_HAIR, HERO_TUNIC),
+            Ash => ([(226, 226, 232), (176, 176, 190), (132, 132, 150)], [(64, 96, 160), (42, 62, 112)]),
+            Raven => ([(78, 72, 92), (52, 46, 64), (36, 30, 46)], [(78, 128, 62), (52, 88, 44)]),
+            Crimson => ([(226, 92, 64), (184, 58, 48), (142, 38, 38)], [(128, 128, 136), (92, 92, 102)]),
+        }
+    }
+
+    /// Returns each color of the original spritesheet paired with the color that replaces it.
+    /// The default palette replaces nothing.
+    pub fn remap(self) -> Vec<(Rgb, Rgb)> {
+        if self == HeroPalette::Default {
+            return Vec::new();
+        }
+
+        let (hair, tunic) = self.colors();
+        HERO_HAIR.iter().cloned().zip(hair.iter().cloned())
+            .chain(HERO_TUNIC.iter().cloned().zip(tunic.iter().cloned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 image with 4 bytes of padding at the end of each row
+    fn test_image() -> Vec<u8> {
+        let hair = HERO_HAIR[0];
+        let tunic = HERO_TUNIC[1];
+        let other = (1, 2, 3);
+        let pixels = [
+            [hair, other, tunic],
+            [other, hair, (0, 0, 0)],
+        ];
+        let mut data = Vec::new();
+        for row in &pixels {
+            for (i, &(r, g, b)) in row.iter().enumerate() {
+                data.extend(&[r, g, b, 100 + i as u8]);
+            }
+            data.extend(&[9; 4]);
+        }
+        data
+    }
+
+    #[test]
+    fn remaps_exact_colors() {
+        let mut data = test_image();
+        let mut image = RgbaPixels {pixels: &mut data, width: 3, height: 2, pitch: 16};
+        let remap = HeroPalette::Ash.remap();
+        assert_eq!(image.remap_colors(&remap), 3);
+
+        let (hair, tunic) = HeroPalette::Ash.colors();
+        let mut expected = Vec::new();
+        let pixels = [
+            [hair[0], (1, 2, 3), tunic[1]],
+            [(1, 2, 3), hair[0], (0, 0, 0)],
+        ];
+        for row in &pixels {
+            for (i, &(r, g, b)) in row.iter().enumerate() {
+                expected.extend(&[r, g, b, 100 + i as u8]);
+            }
+            // Padding is never touched
+            expected.extend(&[9; 4]);
+        }
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn unmatched_colors_unchanged() {
+        let mut data = test_image();
+        // Close to the source colors, but not exactly the same
+        for pixel in data.chunks_mut(4) {
+            pixel[0] = pixel[0].wrapping_add(1);
+        }
+        let original = data.clone();
+        let mut image = RgbaPixels {pixels: &mut data, width: 3, height: 2, pitch: 16};
+        assert_eq!(image.remap_colors(&HeroPalette::Raven.remap()), 0);
+        assert_eq!(data, original);
+
+        // The default palette never changes anything
+        let mut data = test_image();
+        let mut image = RgbaPixels {pixels: &mut data, width: 3, height: 2, pitch: 16};
+        assert_eq!(image.remap_colors(&HeroPalette::Default.remap()), 0);
+        assert_eq!(data, test_image());
+    }
+
+    #[test]
+    fn palette_names() {
+        for &palette in &HeroPalette::ALL {
+            assert_eq!(HeroPalette::from_name(palette.name()), Some(palette));
+            // Every palette replaces every source color exactly once
+            if palette != HeroPalette::Default {
+                assert_eq!(palette.remap().len(), HERO_HAIR.len() + HERO_TUNIC.len());
+            }
+        }
+        assert_eq!(HeroPalette::from_name("rainbow"), None);
+    }
+}
@@ -1,6 +1,6 @@
 use sdl2::rect::{Point, Rect};
 
-use crate::assets::TextureId;
+use crate::assets::{TextureId, scale_to_tile_size};
 
 /// Defines how a sprite is aligned (or "anchored") relative to its destination rectangle
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -45,6 +45,10 @@ pub struct SpriteImage {
     /// Whether to flip the sprite along the vertical axis
     pub flip_vertical: bool,
     /// The position within the region at which this sprite is anchored
+    ///
+    /// The anchor and the destination offset are always given as if the sprite were not flipped.
+    /// Flipping a sprite mirrors where it is placed across the center of its destination, so a
+    /// sprite and its flipped twin are placed symmetrically on either side of the same line.
     pub anchor: Anchor,
     /// An additional amount to offset the destination rectangle
     pub dest_offset: Point,
@@ -106,8 +110,37 @@ impl SpriteImage {
     /// Given the top left coordinates of where this sprite may be placed, returns the region where
     /// the sprite should really be placed based on its anchor setting
     pub fn apply_anchor(&self, dest: Rect) -> Rect {
-        let width = self.region.width() as i32;
-        let height = self.region.height() as i32;
+        let anchored = self.anchor_size(dest, self.region.width() as i32, self.region.height() as i32);
+        self.mirror_flipped(anchored, dest)
+    }
+
+    /// Returns the rectangle (in the same coordinates as `center`) that this sprite should be
+    /// drawn into when it is rendered on a tile of the given size centered at the given point.
+    ///
+    /// The sprite, its anchor, and its destination offset are all scaled from NATIVE_TILE_SIZE to
+    /// the given tile size. That way a sprite that lines up with the tiles of its spritesheet
+    /// (e.g. a character whose feet touch the bottom of the tile) lines up the same way at any
+    /// tile size.
+    pub fn dest_rect(&self, center: Point, tile_size: u32) -> Rect {
+        let scale = |length| scale_to_tile_size(length, tile_size);
+        let dest = Rect::from_center(center, tile_size, tile_size);
+        let mut dest_rect = self.anchor_size(dest, scale(self.region.width() as i32), scale(self.region.height() as i32));
+        dest_rect.offset(scale(self.dest_offset.x()), scale(self.dest_offset.y()));
+        self.mirror_flipped(dest_rect, dest)
+    }
+
+    /// Mirrors a rectangle that was placed within dest as if this sprite were not flipped across
+    /// the center of dest along each axis that this sprite is flipped on
+    fn mirror_flipped(&self, rect: Rect, dest: Rect) -> Rect {
+        // Mirroring keeps the distance to the opposite edge of dest, which also works when dest
+        // has no center pixel (odd sizes)
+        let x = if self.flip_horizontal { dest.left() + dest.right() - rect.right() } else { rect.x() };
+        let y = if self.flip_vertical { dest.top() + dest.bottom() - rect.bottom() } else { rect.y() };
+        Rect::new(x, y, rect.width(), rect.height())
+    }
+
+    /// Positions a rectangle of the given size within dest based on the anchor setting
+    fn anchor_size(&self, dest: Rect, width: i32, height: i32) -> Rect {
         let center = dest.center();
 
         // Each of these calculations is calculating the anchor point on dest and then offsetting
@@ -132,3 +165,107 @@ impl SpriteImage {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assets::{NATIVE_TILE_SIZE, SpriteManager};
+    use crate::map_sprites::MapSprites;
+
+    fn sprite(width: u32, height: u32) -> SpriteImage {
+        SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, width, height))
+    }
+
+    #[test]
+    fn dest_rect_scales_with_tile_size() {
+        let center = Point::new(100, 60);
+
+        // A single map tile always exactly covers its tile
+        let tile = sprite(16, 16);
+        for &tile_size in &[8, 16, 32] {
+            assert_eq!(tile.dest_rect(center, tile_size), Rect::from_center(center, tile_size, tile_size));
+        }
+
+        // Double height sprites (e.g. staircases) stand on the bottom of their tile
+        let tall = sprite(16, 32).anchor_south();
+        assert_eq!(tall.dest_rect(center, 8), Rect::new(96, 48, 8, 16));
+        assert_eq!(tall.dest_rect(center, 16), Rect::new(92, 36, 16, 32));
+        assert_eq!(tall.dest_rect(center, 32), Rect::new(84, 12, 32, 64));
+
+        // Character frames are larger than a tile and their offsets scale with them
+        let character = sprite(48, 48).dest_offset(2, -4);
+        assert_eq!(character.dest_rect(center, 8), Rect::new(89, 46, 24, 24));
+        assert_eq!(character.dest_rect(center, 16), Rect::new(78, 32, 48, 48));
+        assert_eq!(character.dest_rect(center, 32), Rect::new(56, 4, 96, 96));
+    }
+
+    #[test]
+    fn flipped_twin_placed_symmetrically() {
+        let center = Point::new(100, 60);
+        let anchors = [Anchor::N, Anchor::NE, Anchor::E, Anchor::SE, Anchor::S, Anchor::SW, Anchor::W, Anchor::NW, Anchor::Center];
+        // Odd sizes have no center pixel, so they are the most likely to end up a pixel off
+        let sizes = [(16, 32), (15, 33), (48, 48), (47, 21)];
+        for &anchor in &anchors {
+            for &(width, height) in &sizes {
+                for &(x, y) in &[(0, 0), (3, -2)] {
+                    let original = SpriteImage {anchor, ..sprite(width, height).dest_offset(x, y)};
+                    for &tile_size in &[8, 16, 32] {
+                        let dest = Rect::from_center(center, tile_size, tile_size);
+                        let placed = original.dest_rect(center, tile_size);
+
+                        let flipped = original.clone().flip_horizontally().dest_rect(center, tile_size);
+                        assert_eq!(flipped.size(), placed.size());
+                        assert_eq!((flipped.left() - dest.left(), flipped.top()), (dest.right() - placed.right(), placed.top()),
+                            "{:?} {}x{} at {}px flipped horizontally", anchor, width, height, tile_size);
+
+                        let flipped = original.clone().flip_vertically().dest_rect(center, tile_size);
+                        assert_eq!(flipped.size(), placed.size());
+                        assert_eq!((flipped.left(), flipped.top() - dest.top()), (placed.left(), dest.bottom() - placed.bottom()),
+                            "{:?} {}x{} at {}px flipped vertically", anchor, width, height, tile_size);
+
+                        // Flipping twice puts the sprite right back where it started
+                        let twice = original.clone().flip_horizontally().flip_horizontally();
+                        assert_eq!(twice.dest_rect(center, tile_size), placed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn staircase_pairs_line_up() {
+        // Each pair of staircases is the same art facing either way. Their anchors are symmetric,
+        // so neither one needed to be shifted to make up for the other being flipped.
+        let mut sprites = SpriteManager::default();
+        let map_sprites = MapSprites::from_dungeon_spritesheet(TextureId::placeholder(0), &mut sprites);
+        let center = Point::new(100, 60);
+        for &(right, left) in &[
+            (map_sprites.staircase_up_right(), map_sprites.staircase_up_left()),
+            (map_sprites.staircase_down_right(), map_sprites.staircase_down_left()),
+        ] {
+            let (right, left) = (sprites.get(right), sprites.get(left));
+            assert_ne!(right.flip_horizontal, left.flip_horizontal);
+            for &tile_size in &[8, 16, 32] {
+                assert_eq!(right.dest_rect(center, tile_size), left.dest_rect(center, tile_size));
+            }
+        }
+    }
+
+    #[test]
+    fn dest_rect_keeps_proportions() {
+        // Around the origin, every edge of the sprite is exactly the scaled edge at the native size
+        let center = Point::new(0, 0);
+        let character = sprite(48, 48).anchor_south().dest_offset(0, -4);
+        let native = character.dest_rect(center, NATIVE_TILE_SIZE);
+        for &tile_size in &[8, 24, 32] {
+            let scale = |length: i32| scale_to_tile_size(length, tile_size);
+            let scaled = character.dest_rect(center, tile_size);
+            assert_eq!(
+                (scaled.left(), scaled.top(), scaled.right(), scaled.bottom()),
+                (scale(native.left()), scale(native.top()), scale(native.right()), scale(native.bottom())),
+                "at {}px", tile_size,
+            );
+        }
+    }
+}
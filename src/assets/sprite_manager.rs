@@ -3,6 +3,14 @@ use super::SpriteImage;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SpriteId(usize);
 
+impl SpriteId {
+    /// Returns the ID that this sprite has after the sprite manager it came from is appended to
+    /// another sprite manager. The offset is the value returned from `SpriteManager::append`.
+    pub fn offset(self, offset: usize) -> Self {
+        SpriteId(self.0 + offset)
+    }
+}
+
 #[derive(Default)]
 pub struct SpriteManager {
     sprites: Vec<SpriteImage>,
@@ -17,4 +25,15 @@ impl SpriteManager {
         self.sprites.push(image);
         SpriteId(self.sprites.len() - 1)
     }
+
+    /// Moves every sprite of the given manager to the end of this one. Returns the offset that
+    /// each ID from the given manager must be moved by to refer to the same sprite in this one.
+    ///
+    /// Lets sprites be created in separate managers (e.g. on different threads) and then
+    /// combined.
+    pub fn append(&mut self, mut other: SpriteManager) -> usize {
+        let offset = self.sprites.len();
+        self.sprites.append(&mut other.sprites);
+        offset
+    }
 }
@@ -3,22 +3,50 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use sdl2::{image::LoadTexture, render::{TextureCreator, Texture}};
+use sdl2::{
+    image::{LoadTexture, LoadSurface},
+    render::{TextureCreator, Texture, BlendMode},
+    surface::Surface,
+    pixels::PixelFormatEnum,
+};
 
 use crate::ui::SDLError;
 
+use super::{Rgb, RgbaPixels};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TextureId(usize);
 
+#[cfg(test)]
+impl TextureId {
+    /// An ID that does not refer to any texture. Only useful in tests that never render anything.
+    pub fn placeholder(id: usize) -> Self {
+        TextureId(id)
+    }
+}
+
+/// How a texture was created from its file, so that it can be created again the same way when
+/// the file changes
+#[derive(Debug, Clone)]
+enum TextureSource {
+    Png,
+    Translucent {alpha: u8},
+    Remapped {remap: Vec<(Rgb, Rgb)>},
+}
+
 // NOTE: Ideally, this would just be managed in the Window, but we can't do that because
 // we can't have a field in a struct that refers to another field. Textures are dependent
 // on the TextureCreator and they need to be stored separately in order for this to work.
 pub struct TextureManager<'a, T> {
     texture_creator: &'a TextureCreator<T>,
-    textures: Vec<Texture<'a>>,
+    /// Textures that have been unloaded are None. Their IDs are never reused.
+    textures: Vec<Option<Texture<'a>>>,
     /// Memoized textures for each path so we don't end up loading a path twice for no reason.
     /// Path is canonicalized so that slight differences in the path get normalized.
     path_textures: HashMap<PathBuf, TextureId>,
+    /// The (canonicalized) file that each texture was created from and how it was created. Used
+    /// to reload textures when their files change. Textures created from bytes are not included.
+    sources: HashMap<TextureId, (PathBuf, TextureSource)>,
 }
 
 impl<'a, T> TextureManager<'a, T> {
@@ -27,12 +55,73 @@ impl<'a, T> TextureManager<'a, T> {
             texture_creator,
             textures: Default::default(),
             path_textures: Default::default(),
+            sources: Default::default(),
         }
     }
 
     /// Retrieves the texture for the given ID
     pub fn get(&self, TextureId(index): TextureId) -> &Texture<'a> {
-        &self.textures[index]
+        self.textures[index].as_ref()
+            .expect("bug: attempt to use a texture that was unloaded")
+    }
+
+    /// Frees the texture for the given ID. The texture must not be used after this.
+    ///
+    /// Loading the same path again creates a new texture with a different ID.
+    pub fn unload(&mut self, id: TextureId) {
+        let TextureId(index) = id;
+        self.textures[index] = None;
+        self.path_textures.retain(|_, &mut path_id| path_id != id);
+        self.sources.remove(&id);
+    }
+
+    /// Replaces the texture for the given ID with the given texture. Every sprite that uses the
+    /// ID is drawn from the new texture from then on.
+    pub fn replace(&mut self, TextureId(index): TextureId, texture: Texture<'a>) {
+        let slot = &mut self.textures[index];
+        assert!(slot.is_some(), "bug: attempt to replace a texture that was unloaded");
+        *slot = Some(texture);
+    }
+
+    /// Creates every texture that was created from the given file again, the same way it was
+    /// created originally, and replaces each one in place so that its ID keeps working. Returns
+    /// the IDs of the textures that were replaced.
+    ///
+    /// If any of the textures cannot be created (e.g. the file is only partly written), none of
+    /// them are replaced and the old textures are kept.
+    ///
+    /// Only the pixels of the textures change. Sprites still refer to the same regions of each
+    /// texture, so a spritesheet that moves tiles around or changes size needs a restart.
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<TextureId>, SDLError> {
+        let path = canonical_path(path.as_ref());
+        let mut reloaded = Vec::new();
+        for (&id, (source_path, source)) in &self.sources {
+            if *source_path == path {
+                reloaded.push((id, self.load(&path, source)?));
+            }
+        }
+
+        Ok(reloaded.into_iter().map(|(id, texture)| {
+            self.replace(id, texture);
+            id
+        }).collect())
+    }
+
+    /// Creates a texture from the given path in the way described by the given source
+    fn load(&self, path: &Path, source: &TextureSource) -> Result<Texture<'a>, SDLError> {
+        match source {
+            TextureSource::Png => self.load_png(path),
+            &TextureSource::Translucent {alpha} => self.load_translucent_png(path, alpha),
+            TextureSource::Remapped {remap} => self.load_remapped_png(path, remap).map(|(texture, _)| texture),
+        }
+    }
+
+    /// Adds the given texture and remembers how it was created from the given path
+    fn add(&mut self, texture: Texture<'a>, path: &Path, source: TextureSource) -> TextureId {
+        self.textures.push(Some(texture));
+        let id = TextureId(self.textures.len() - 1);
+        self.sources.insert(id, (canonical_path(path), source));
+        id
     }
 
     /// Creates a texture from the given path
@@ -42,13 +131,79 @@ impl<'a, T> TextureManager<'a, T> {
             return Ok(self.path_textures[path])
         }
 
-        let texture = self.texture_creator.load_texture(path).map_err(SDLError)?;
-        self.textures.push(texture);
-        let id = TextureId(self.textures.len() - 1);
+        let texture = self.load_png(path)?;
+        let id = self.add(texture, path, TextureSource::Png);
         let path = path.canonicalize()
             .expect("Failed to canonicalize path for loaded texture");
         self.path_textures.insert(path, id);
 
         Ok(id)
     }
+
+    /// Creates a texture from the contents of a PNG file (e.g. one embedded with `include_bytes!`)
+    pub fn create_png_texture_from_bytes(&mut self, bytes: &[u8]) -> Result<TextureId, SDLError> {
+        let texture = self.texture_creator.load_texture_bytes(bytes).map_err(SDLError::Sdl)?;
+        self.textures.push(Some(texture));
+        Ok(TextureId(self.textures.len() - 1))
+    }
+
+    /// Creates a texture from the given path that is drawn with the given alpha, from 0
+    /// (invisible) to 255 (fully opaque). The texture is never reused for other loads of the same
+    /// path since it is drawn differently from the original image.
+    pub fn create_translucent_png_texture<P: AsRef<Path>>(&mut self, path: P, alpha: u8) -> Result<TextureId, SDLError> {
+        let path = path.as_ref();
+        let texture = self.load_translucent_png(path, alpha)?;
+        Ok(self.add(texture, path, TextureSource::Translucent {alpha}))
+    }
+
+    /// Creates a texture from the given path with every source color in the image replaced by
+    /// its target color. The texture is never reused for other loads of the same path since its
+    /// pixels are different from the original image.
+    ///
+    /// Returns the texture and the number of pixels that were replaced.
+    pub fn create_remapped_png_texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        remap: &[(Rgb, Rgb)],
+    ) -> Result<(TextureId, usize), SDLError> {
+        let path = path.as_ref();
+        let (texture, remapped) = self.load_remapped_png(path, remap)?;
+        Ok((self.add(texture, path, TextureSource::Remapped {remap: remap.to_vec()}), remapped))
+    }
+
+    fn load_png(&self, path: &Path) -> Result<Texture<'a>, SDLError> {
+        self.texture_creator.load_texture(path)
+            .map_err(|source| SDLError::TextureLoad {path: path.to_path_buf(), source})
+    }
+
+    fn load_translucent_png(&self, path: &Path, alpha: u8) -> Result<Texture<'a>, SDLError> {
+        let mut texture = self.load_png(path)?;
+        texture.set_blend_mode(BlendMode::Blend);
+        texture.set_alpha_mod(alpha);
+        Ok(texture)
+    }
+
+    /// Returns the texture and the number of pixels that were replaced
+    fn load_remapped_png(&self, path: &Path, remap: &[(Rgb, Rgb)]) -> Result<(Texture<'a>, usize), SDLError> {
+        let surface = Surface::from_file(path)
+            .map_err(|source| SDLError::TextureLoad {path: path.to_path_buf(), source})?;
+        // Converting guarantees that every pixel is 4 bytes in RGBA order
+        let mut surface = surface.convert_format(PixelFormatEnum::RGBA32).map_err(SDLError::Sdl)?;
+        let width = surface.width() as usize;
+        let height = surface.height() as usize;
+        let pitch = surface.pitch() as usize;
+        let remapped = surface.with_lock_mut(|pixels| {
+            RgbaPixels {pixels, width, height, pitch}.remap_colors(remap)
+        });
+
+        let texture = self.texture_creator.create_texture_from_surface(&surface)
+            .map_err(|err| SDLError::Sdl(err.to_string()))?;
+        Ok((texture, remapped))
+    }
+}
+
+/// Returns the canonicalized form of the given path, or the path itself if it cannot be
+/// canonicalized (e.g. because the file was deleted)
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
 }
@@ -0,0 +1,129 @@
+//! Sound effects and background music
+
+use std::collections::HashMap;
+
+use sdl2::mixer::{Chunk, Music, Channel, MAX_VOLUME};
+
+use crate::resources::SoundQueue;
+
+/// The background music that loops for the entire run
+const MUSIC_PATH: &str = "assets/sounds/music.ogg";
+
+/// A sound that can be played in response to something happening in the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    Attack,
+    Hit,
+    DoorOpen,
+    Stairs,
+    ChestOpen,
+    PlayerDeath,
+    Tremor,
+}
+
+impl SoundEffect {
+    /// All of the sound effects that can be played
+    pub const ALL: [SoundEffect; 7] = [
+        SoundEffect::Attack,
+        SoundEffect::Hit,
+        SoundEffect::DoorOpen,
+        SoundEffect::Stairs,
+        SoundEffect::ChestOpen,
+        SoundEffect::PlayerDeath,
+        SoundEffect::Tremor,
+    ];
+
+    /// The path to the file containing the samples of this sound effect
+    pub fn path(self) -> &'static str {
+        use self::SoundEffect::*;
+        match self {
+            Attack => "assets/sounds/attack.ogg",
+            Hit => "assets/sounds/hit.ogg",
+            DoorOpen => "assets/sounds/door_open.ogg",
+            Stairs => "assets/sounds/stairs.ogg",
+            ChestOpen => "assets/sounds/chest_open.ogg",
+            PlayerDeath => "assets/sounds/player_death.ogg",
+            Tremor => "assets/sounds/tremor.ogg",
+        }
+    }
+}
+
+/// Plays sound effects and background music
+///
+/// Audio is optional. Any sounds that could not be loaded are silently skipped when played so
+/// that the game can still run without them.
+pub struct AudioManager {
+    effects: HashMap<SoundEffect, Chunk>,
+    music: Option<Music<'static>>,
+    muted: bool,
+}
+
+impl AudioManager {
+    /// Loads all of the sound effects and the background music. Any files that fail to load are
+    /// logged and then ignored.
+    pub fn load() -> Self {
+        let mut failed = Vec::new();
+
+        let mut effects = HashMap::new();
+        for &effect in &SoundEffect::ALL {
+            match Chunk::from_file(effect.path()) {
+                Ok(chunk) => {effects.insert(effect, chunk);},
+                Err(err) => failed.push(format!("{} ({})", effect.path(), err)),
+            }
+        }
+
+        let music = Music::from_file(MUSIC_PATH)
+            .map_err(|err| failed.push(format!("{} ({})", MUSIC_PATH, err)))
+            .ok();
+
+        if !failed.is_empty() {
+            warn!("Unable to load audio, continuing without: {}", failed.join(", "));
+        }
+
+        Self {effects, music, muted: false}
+    }
+
+    /// Starts looping the background music from the beginning
+    pub fn play_music(&self) {
+        if let Some(music) = &self.music {
+            if let Err(err) = music.play(-1) {
+                warn!("Unable to play music: {}", err);
+            }
+        }
+    }
+
+    /// Pauses the background music
+    pub fn pause_music(&self) {
+        Music::pause();
+    }
+
+    /// Resumes the background music where it was paused
+    pub fn resume_music(&self) {
+        Music::resume();
+    }
+
+    /// Toggles whether any audio can be heard
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        Music::set_volume(if self.muted { 0 } else { MAX_VOLUME });
+    }
+
+    /// Plays the given sound effect
+    pub fn play(&self, effect: SoundEffect) {
+        if self.muted {
+            return;
+        }
+
+        if let Some(chunk) = self.effects.get(&effect) {
+            // Any free channel will do. If no channels are free, the sound is skipped.
+            let _ = Channel::all().play(chunk, 0);
+        }
+    }
+
+    /// Plays every sound effect in the given queue
+    pub fn play_all(&self, SoundQueue(effects): SoundQueue) {
+        for effect in effects {
+            self.play(effect);
+        }
+    }
+}
@@ -4,6 +4,9 @@ mod graphics;
 mod stairs;
 mod item;
 mod entrance;
+mod projectile;
+mod status;
+mod weapon;
 
 pub use self::physics::*;
 pub use self::character::*;
@@ -11,3 +14,6 @@ pub use self::graphics::*;
 pub use self::stairs::*;
 pub use self::item::*;
 pub use self::entrance::*;
+pub use self::projectile::*;
+pub use self::status::*;
+pub use self::weapon::*;
@@ -3,6 +3,12 @@
 use component_group::ComponentGroup;
 
 use specs::{Component, VecStorage, HashMapStorage, NullStorage};
+use sdl2::rect::Point;
+
+use crate::generator::{EnemyValues, BossConfig};
+use crate::map::RoomId;
+
+use super::MovementDirection;
 
 /// All the components of a player. Grouped together so they can be easily copied to and from
 /// worlds. The reason this struct exists is because specs doesn't provide a way to copy all the
@@ -14,12 +20,17 @@ pub struct PlayerComponents {
     pub camera_focus: CameraFocus,
     pub player: Player,
     pub health_points: HealthPoints,
+    pub max_health_points: MaxHealthPoints,
+    pub attack: Attack,
+    pub hit_invulnerability: HitInvulnerability,
     pub position: super::Position,
     pub bounding_box: super::BoundingBox,
     pub movement: super::Movement,
     pub sprite: super::Sprite,
     pub animation: super::Animation,
     pub animation_manager: super::AnimationManager,
+    pub inventory: super::Inventory,
+    pub equipment: super::Equipment,
 }
 
 /// Represents the amount of health left for a given entity
@@ -27,21 +38,197 @@ pub struct PlayerComponents {
 #[storage(VecStorage)]
 pub struct HealthPoints(pub usize); // unit: HP
 
+/// The most health that an entity can be healed up to
+#[derive(Debug, Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct MaxHealthPoints(pub usize); // unit: HP
+
 /// Represents the strength of this entity's attack
 #[derive(Debug, Clone, Component)]
 #[storage(VecStorage)]
 pub struct Attack(pub usize); // unit: HP
 
-/// Represents the amount of time (if at all) that the entity waits when hit before being able to
-/// move/attack again
+/// Represents the amount of time (if at all) that the entity waits after hitting another entity
+/// before being able to move/attack again
 #[derive(Debug, Clone, Component)]
 #[storage(VecStorage)]
 pub struct HitWait(pub usize); // unit: frames
 
+/// Represents the amount of time that the entity cannot take any more damage after being hit
+#[derive(Debug, Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct HitInvulnerability(pub usize); // unit: frames
+
+/// Keeps an entity from attacking again until its last attack has finished. Attacking again
+/// shortly after the cooldown ends lands the second hit of a combo.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct AttackCooldown {
+    /// The number of frames until the entity can attack again
+    pub frames_remaining: usize,
+    /// The number of frames after the cooldown ends during which the next attack is a combo hit.
+    /// Zero if the attack that started this cooldown was already a combo hit.
+    pub combo_frames_remaining: usize,
+}
+
+impl AttackCooldown {
+    /// Counts down the cooldown, followed by the combo window once the cooldown is over
+    pub fn step(&mut self, frames_elapsed: usize) {
+        let combo_frames_elapsed = frames_elapsed.saturating_sub(self.frames_remaining);
+        self.frames_remaining = self.frames_remaining.saturating_sub(frames_elapsed);
+        self.combo_frames_remaining = self.combo_frames_remaining.saturating_sub(combo_frames_elapsed);
+    }
+
+    /// Returns true if the entity is allowed to attack
+    pub fn is_ready(&self) -> bool {
+        self.frames_remaining == 0
+    }
+
+    /// Returns true if the entity will be allowed to attack once the cooldown has counted down the
+    /// given number of frames
+    pub fn is_ready_after(&self, frames_elapsed: usize) -> bool {
+        self.frames_remaining <= frames_elapsed
+    }
+
+    /// Returns true if an attack right now would be a combo hit
+    pub fn is_combo(&self) -> bool {
+        self.is_ready() && self.combo_frames_remaining > 0
+    }
+
+    /// Returns true if both the cooldown and the combo window are over
+    pub fn is_complete(&self) -> bool {
+        self.is_ready() && self.combo_frames_remaining == 0
+    }
+}
+
+/// An entity that ignores all damage until the given number of frames have elapsed. Invulnerable
+/// entities blink while they are rendered.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Invulnerable {
+    pub frames_remaining: usize,
+}
+
+impl Invulnerable {
+    /// The number of frames that the entity is shown (or hidden) for at a time while blinking
+    const BLINK_FRAMES: usize = 3;
+
+    /// Returns true if the entity should be rendered during the current frame
+    pub fn is_visible(&self) -> bool {
+        (self.frames_remaining / Self::BLINK_FRAMES).is_multiple_of(2)
+    }
+}
+
+/// Counts the frames that an entity has been standing on a hazard. Removed as soon as the entity
+/// steps off of the hazard.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct HazardExposure {
+    /// The frames spent on the hazard since the entity was last damaged by it
+    pub frames: usize,
+}
+
+/// A health bar shown above an entity for a short time after it takes damage
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct HealthBar {
+    /// The health points of the entity just before it first took damage. Used as the full length
+    /// of the bar.
+    pub max_health: usize,
+    /// The number of frames until the bar is completely hidden again
+    pub frames_remaining: usize,
+}
+
+impl HealthBar {
+    /// The number of frames that the bar stays visible for after the entity takes damage
+    pub const VISIBLE_FRAMES: usize = 120;
+    /// The number of frames at the end of VISIBLE_FRAMES during which the bar fades out
+    const FADE_FRAMES: usize = 30;
+
+    /// Creates a bar that has just become visible
+    pub fn new(max_health: usize) -> Self {
+        Self {max_health, frames_remaining: Self::VISIBLE_FRAMES}
+    }
+
+    /// Returns the fraction [0.0, 1.0] of the bar that should be filled for the given health
+    pub fn fraction(&self, health: usize) -> f64 {
+        if self.max_health == 0 {
+            return 0.0;
+        }
+        (health as f64 / self.max_health as f64).min(1.0)
+    }
+
+    /// Returns the opacity of the bar during the current frame. Zero once the bar is hidden.
+    pub fn alpha(&self) -> u8 {
+        let visible = self.frames_remaining.min(Self::FADE_FRAMES);
+        (visible * 255 / Self::FADE_FRAMES) as u8
+    }
+}
+
 /// The keyboard controlled player. Only one entity should hold this at a given time.
-#[derive(Debug, Clone, Copy, Default, Component)]
-#[storage(NullStorage)]
-pub struct KeyboardControlled;
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct KeyboardControlled {
+    /// Scales the speed that the player walks at (e.g. 1.05 walks 5% faster)
+    pub speed_multiplier: f32,
+}
+
+impl Default for KeyboardControlled {
+    fn default() -> Self {
+        Self {speed_multiplier: 1.0}
+    }
+}
+
+/// A discrete action requested by pressing a key or clicking. Unlike movement, these are buffered
+/// if they are requested slightly before they are allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    Interact,
+    Attack,
+    Dodge,
+}
+
+/// An action requested before it was allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferedAction {
+    pub action: InputAction,
+    /// The position on the map that was clicked to request the action (if any)
+    pub target: Option<Point>,
+    /// The number of frames left before the action is dropped
+    pub frames_remaining: usize,
+}
+
+/// The actions that the keyboard controlled entity requested but has not been able to perform yet.
+/// Each action is performed on the first frame that it is allowed, as long as that is within
+/// BUFFER_FRAMES of when it was requested. At most one of each action is buffered at a time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct BufferedActions(pub Vec<BufferedAction>);
+
+impl BufferedActions {
+    /// About 0.2 seconds
+    pub const BUFFER_FRAMES: usize = 6;
+
+    /// Buffers the given action, replacing any earlier request for the same action
+    pub fn push(&mut self, action: InputAction, target: Option<Point>) {
+        self.0.retain(|buffered| buffered.action != action);
+        self.0.push(BufferedAction {action, target, frames_remaining: Self::BUFFER_FRAMES});
+    }
+
+    /// Counts down every buffered action and drops the ones that were requested too long ago
+    pub fn step(&mut self, frames_elapsed: usize) {
+        self.0.retain(|buffered| buffered.frames_remaining >= frames_elapsed);
+        for buffered in &mut self.0 {
+            buffered.frames_remaining -= frames_elapsed;
+        }
+    }
+
+    /// Removes the given action from the buffer, returning it if it was buffered
+    pub fn take(&mut self, action: InputAction) -> Option<BufferedAction> {
+        let index = self.0.iter().position(|buffered| buffered.action == action)?;
+        Some(self.0.remove(index))
+    }
+}
 
 /// The entity with this component and a Position component will be centered in the camera
 /// when the scene is rendered.
@@ -55,6 +242,13 @@ pub struct CameraFocus;
 #[storage(NullStorage)]
 pub struct Player;
 
+/// Enemies with this component are too far from the player to be simulated. They keep their
+/// position and animation until they become active again. Maintained by the dormancy system based
+/// on the `ActivityBubble` resource.
+#[derive(Debug, Clone, Copy, Default, Component)]
+#[storage(NullStorage)]
+pub struct Dormant;
+
 /// Behavioural pattern of the enemy AI
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EnemyBehaviour {
@@ -65,6 +259,205 @@ pub enum EnemyBehaviour {
 #[derive(Debug, Component)]
 #[storage(HashMapStorage)]
 pub struct Enemy {
-    pub speed: i32, // movements per second
+    pub speed: f32, // px/frame
     pub behaviour: EnemyBehaviour,
+    /// The room that the enemy wanders around in. None if the enemy may wander anywhere.
+    pub home_room: Option<RoomId>,
+}
+
+/// What an enemy is currently doing based on whether it knows where the player is
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub enum AiState {
+    /// Has not seen the player, so wanders around its home room
+    #[default]
+    Idle,
+    /// Lost sight of the player and is searching near where the player was last seen
+    Alert {
+        /// The position where the player was last seen
+        last_seen: Point,
+        /// The number of frames left before the enemy gives up searching
+        frames: usize,
+    },
+    /// Can see the player and is heading straight for it
+    Chasing,
+    /// Gave up searching and is heading back to its home room
+    Returning,
+}
+
+impl AiState {
+    /// The number of frames that an enemy searches for the player after losing sight of it
+    pub const ALERT_FRAMES: usize = 90;
+
+    /// Returns true if the enemy does not know about the player at all
+    pub fn is_unaware(self) -> bool {
+        match self {
+            AiState::Idle | AiState::Returning => true,
+            AiState::Alert {..} | AiState::Chasing => false,
+        }
+    }
+
+    /// Returns the state after the given number of frames. `player` is the position of the
+    /// player (if any) and `sees_player` is true if the enemy can see it. Losing sight of the
+    /// player happens on the first frame that it is hidden, so its position on that frame is used
+    /// as the position where it was last seen. `is_home` is true if the enemy is in its home room.
+    pub fn next(self, player: Option<Point>, sees_player: bool, is_home: bool, frames_elapsed: usize) -> Self {
+        use self::AiState::*;
+        if sees_player {
+            return Chasing;
+        }
+
+        match self {
+            Idle => Idle,
+            Chasing => match player {
+                Some(last_seen) => Alert {last_seen, frames: Self::ALERT_FRAMES},
+                None => Returning,
+            },
+            Alert {last_seen, frames} => match frames.saturating_sub(frames_elapsed) {
+                0 => Returning,
+                frames => Alert {last_seen, frames},
+            },
+            Returning if is_home => Idle,
+            Returning => Returning,
+        }
+    }
+}
+
+/// Shows that an enemy just became aware of the player
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct AlertIndicator {
+    /// The number of frames until the indicator is hidden
+    pub frames_remaining: usize,
+}
+
+impl AlertIndicator {
+    /// The number of frames that the indicator is shown for
+    pub const VISIBLE_FRAMES: usize = 45;
+}
+
+impl Default for AlertIndicator {
+    fn default() -> Self {
+        Self {frames_remaining: Self::VISIBLE_FRAMES}
+    }
+}
+
+/// A place where an enemy may appear when the player first enters the level. The enemy only
+/// spawns if a roll with the given probability succeeds. Either way, the spawn point is removed so
+/// that revisiting the level does not spawn the enemy again.
+#[derive(Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct EnemySpawn {
+    /// The probability [0.0, 1.0] that the enemy is spawned
+    pub probability: f64,
+    /// The enemy that will be spawned
+    pub enemy: EnemyValues,
 }
+
+/// A place where the boss of a level appears when the player first enters the level. Unlike other
+/// enemies, the boss always spawns.
+#[derive(Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct BossSpawn {
+    pub boss: BossConfig,
+}
+
+/// The boss of a level. Its health bar is shown for as long as the player is in its room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Boss {
+    /// The health points that the boss spawned with. Used as the full length of its health bar.
+    pub max_health: usize,
+}
+
+/// An attack where an enemy stops to wind up and then rushes in a straight line toward where the
+/// player was when the wind-up began
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct ChargeAttack {
+    /// The number of frames between the end of one charge and the start of the next wind-up
+    pub period: usize,
+    /// The number of frames that the enemy stands still before charging
+    pub windup: usize,
+    /// The speed of the charge in px/frame
+    pub speed: f32,
+    /// The furthest (in px) that a single charge can go
+    pub distance: u32,
+    /// The damage done on top of the attack of the enemy when it hits the player while charging
+    pub bonus_damage: usize,
+    pub state: ChargeState,
+}
+
+/// The part of a charge attack that an enemy is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargeState {
+    /// Moving normally until the charge can be used again
+    Cooldown {frames_remaining: usize},
+    /// Standing still while facing the direction of the charge
+    WindingUp {direction: MovementDirection, frames_remaining: usize},
+    /// Rushing in the given direction until reaching the target or being stopped
+    Charging {
+        direction: MovementDirection,
+        /// Where the charge ends if nothing gets in the way
+        target: Point,
+        /// The position of the enemy on the previous frame of the charge, if any. A charge that
+        /// makes no progress has run into something.
+        previous: Option<Point>,
+    },
+}
+
+impl ChargeAttack {
+    /// Creates a charge attack that starts out cooling down
+    pub fn new(period: usize, windup: usize, speed: f32, distance: u32, bonus_damage: usize) -> Self {
+        Self {
+            period,
+            windup,
+            speed,
+            distance,
+            bonus_damage,
+            state: ChargeState::Cooldown {frames_remaining: period},
+        }
+    }
+
+    /// Returns the direction of the charge if the enemy is winding up for one
+    pub fn winding_up(&self) -> Option<MovementDirection> {
+        match self.state {
+            ChargeState::WindingUp {direction, ..} => Some(direction),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the enemy is currently charging
+    pub fn is_charging(&self) -> bool {
+        match self.state {
+            ChargeState::Charging {..} => true,
+            ChargeState::Cooldown {..} | ChargeState::WindingUp {..} => false,
+        }
+    }
+
+    /// Returns the extra damage done by a hit during the current frame
+    pub fn hit_bonus(&self) -> usize {
+        if self.is_charging() { self.bonus_damage } else { 0 }
+    }
+
+    /// Ends the current charge or wind-up, if any, and starts cooling down
+    pub fn stop(&mut self) {
+        self.state = ChargeState::Cooldown {frames_remaining: self.period};
+    }
+}
+
+/// A prisoner locked in a cage. The cage breaks open after it has been attacked enough times,
+/// freeing the prisoner so that they can follow the player.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Cage {
+    /// The number of attacks left before the cage breaks open
+    pub hits_remaining: usize,
+}
+
+/// Entities with this component follow the entity with the Player component around the map. A
+/// follower is never blocked by other entities and cannot take or deal damage. Escorting a
+/// follower to a staircase rescues them.
+#[derive(Debug, Clone, Copy, Default, Component)]
+#[storage(NullStorage)]
+pub struct Follower;
@@ -1,9 +1,35 @@
-use specs::{Component, NullStorage};
+use specs::{Component, NullStorage, HashMapStorage};
 
-/// A door between two rooms
-#[derive(Debug, Default, Component)]
-#[storage(NullStorage)]
-pub struct Door;
+/// A door between two rooms. Closed doors block both movement and sight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub enum Door {
+    Closed,
+    Open,
+}
+
+impl Door {
+    pub fn is_open(self) -> bool {
+        self == Door::Open
+    }
+
+    /// Returns the opposite state of this door
+    pub fn toggled(self) -> Self {
+        match self {
+            Door::Closed => Door::Open,
+            Door::Open => Door::Closed,
+        }
+    }
+}
+
+/// The direction of the wall that a door is in. Doors in horizontal walls are opened by walking
+/// north or south through them. Doors in vertical walls are opened by walking east or west.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub enum DoorOrientation {
+    Horizontal,
+    Vertical,
+}
 
 /// A gate between two rooms
 #[derive(Debug, Default, Component)]
@@ -14,3 +40,8 @@ pub struct Gate;
 #[derive(Debug, Default, Component)]
 #[storage(NullStorage)]
 pub struct Locked;
+
+/// A gate that stays shut until the boss of the level is defeated, even for a player with the key
+#[derive(Debug, Default, Component)]
+#[storage(NullStorage)]
+pub struct BossSeal;
@@ -2,10 +2,10 @@
 
 use std::iter::once;
 
-use specs::{Component, VecStorage, HashMapStorage};
+use specs::{Component, VecStorage, HashMapStorage, NullStorage};
 use sdl2::rect::{Point, Rect};
 
-use crate::assets::{TextureId, SpriteId, SpriteImage, SpriteManager, Anchor};
+use crate::assets::{TextureId, SpriteId, SpriteImage, SpriteManager, Anchor, NATIVE_TILE_SIZE, scale_speed_to_tile_size};
 
 /// An entity that is unable to move until the given duration has elapsed
 #[derive(Debug, Default, Component)]
@@ -24,6 +24,17 @@ impl Wait {
     }
 }
 
+/// A notable entity that the player knows about. Discovered entities continue to be rendered in
+/// explored areas of the map even when they are not directly visible.
+#[derive(Debug, Default, Component)]
+#[storage(NullStorage)]
+pub struct Discovered;
+
+/// A purely cosmetic object placed on the floor of a room when the level was generated
+#[derive(Debug, Default, Component)]
+#[storage(NullStorage)]
+pub struct Prop;
+
 /// Renders a sprite from a texture (spritesheet image).
 ///
 /// The sprite is rendered with the region centered on the entity's Position
@@ -31,6 +42,90 @@ impl Wait {
 #[storage(VecStorage)]
 pub struct Sprite(pub SpriteId);
 
+/// Controls the order that sprites are drawn in. Sprites on higher layers are drawn over sprites on
+/// lower layers. Within a layer, sprites further down the screen are drawn in front. The map itself
+/// is always drawn under every layer.
+///
+/// Entities without a layer are drawn with the characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+#[storage(VecStorage)]
+pub struct RenderLayer(pub i8);
+
+impl RenderLayer {
+    /// Stairs, doors and anything else that is part of the walls or floor
+    pub const FIXTURES: Self = RenderLayer(0);
+    /// Things that can be picked up or opened along with other objects that sit on the floor
+    pub const ITEMS: Self = RenderLayer(1);
+    /// The player, enemies and prisoners
+    pub const CHARACTERS: Self = RenderLayer(2);
+    /// Anything flying through the air
+    pub const PROJECTILES: Self = RenderLayer(3);
+    /// Drawn over everything else
+    pub const EFFECTS: Self = RenderLayer(4);
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        RenderLayer::CHARACTERS
+    }
+}
+
+/// Text (e.g. the damage dealt by a hit) that rises up from the entity's Position and fades out.
+/// The entity is deleted once the text has disappeared.
+#[derive(Debug, Clone, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct FloatingText {
+    pub text: String,
+    /// The number of frames until the text disappears
+    pub frames_remaining: usize,
+    /// The distance (px/frame) that the text moves every frame
+    pub velocity: (f32, f32),
+    /// The distance (in px) that the text has moved from the entity's Position so far
+    pub offset: (f32, f32),
+    /// Critical hits are drawn in a different color
+    pub critical: bool,
+}
+
+impl FloatingText {
+    /// The number of frames that the text is visible for
+    pub const LIFETIME_FRAMES: usize = 20;
+    /// The speed (px/frame at NATIVE_TILE_SIZE) that damage numbers rise at
+    const RISE_SPEED: f32 = 0.5;
+
+    /// Creates the number shown above an entity that was just damaged by the given amount
+    pub fn damage(amount: usize, tile_size: u32) -> Self {
+        Self {
+            text: amount.to_string(),
+            frames_remaining: Self::LIFETIME_FRAMES,
+            velocity: (0.0, -scale_speed_to_tile_size(Self::RISE_SPEED, tile_size)),
+            offset: (0.0, 0.0),
+            critical: false,
+        }
+    }
+
+    /// Moves the text and counts down its lifetime by the given number of frames. Returns true if
+    /// the text has disappeared.
+    pub fn advance(&mut self, frames: usize) -> bool {
+        let frames = frames.min(self.frames_remaining);
+        let (vx, vy) = self.velocity;
+        self.offset.0 += vx * frames as f32;
+        self.offset.1 += vy * frames as f32;
+        self.frames_remaining -= frames;
+        self.frames_remaining == 0
+    }
+
+    /// Returns the distance (in whole px) that the text has moved from the entity's Position
+    pub fn offset(&self) -> Point {
+        let (x, y) = self.offset;
+        Point::new(x.round() as i32, y.round() as i32)
+    }
+
+    /// Returns the opacity of the text during the current frame. Fades out over the lifetime.
+    pub fn alpha(&self) -> u8 {
+        (self.frames_remaining.min(Self::LIFETIME_FRAMES) * 255 / Self::LIFETIME_FRAMES) as u8
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Frame {
     /// The sprite that this frame represents
@@ -39,10 +134,99 @@ pub struct Frame {
     pub duration: usize,
 }
 
+/// Identifies one of the animations of an AnimationManager
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnimationId {
+    Idle,
+    Victory,
+    MoveUp,
+    MoveRight,
+    MoveLeft,
+    MoveDown,
+    AttackUp,
+    AttackRight,
+    AttackLeft,
+    AttackDown,
+    HitUp,
+    HitRight,
+    HitLeft,
+    HitDown,
+    StoppedUp,
+    StoppedRight,
+    StoppedLeft,
+    StoppedDown,
+}
+
+impl AnimationId {
+    /// Every animation of an AnimationManager
+    pub const ALL: [AnimationId; 18] = [
+        AnimationId::Idle,
+        AnimationId::Victory,
+        AnimationId::MoveUp,
+        AnimationId::MoveRight,
+        AnimationId::MoveLeft,
+        AnimationId::MoveDown,
+        AnimationId::AttackUp,
+        AnimationId::AttackRight,
+        AnimationId::AttackLeft,
+        AnimationId::AttackDown,
+        AnimationId::HitUp,
+        AnimationId::HitRight,
+        AnimationId::HitLeft,
+        AnimationId::HitDown,
+        AnimationId::StoppedUp,
+        AnimationId::StoppedRight,
+        AnimationId::StoppedLeft,
+        AnimationId::StoppedDown,
+    ];
+
+    /// Returns a stable name for this ID that can be saved and later turned back into an ID
+    pub fn name(self) -> &'static str {
+        use self::AnimationId::*;
+        match self {
+            Idle => "idle",
+            Victory => "victory",
+            MoveUp => "move_up",
+            MoveRight => "move_right",
+            MoveLeft => "move_left",
+            MoveDown => "move_down",
+            AttackUp => "attack_up",
+            AttackRight => "attack_right",
+            AttackLeft => "attack_left",
+            AttackDown => "attack_down",
+            HitUp => "hit_up",
+            HitRight => "hit_right",
+            HitLeft => "hit_left",
+            HitDown => "hit_down",
+            StoppedUp => "stopped_up",
+            StoppedRight => "stopped_right",
+            StoppedLeft => "stopped_left",
+            StoppedDown => "stopped_down",
+        }
+    }
+
+    /// Returns the ID with the given name (as returned by `name`), if any
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|id| id.name() == name)
+    }
+}
+
+/// The progress of an animation from an AnimationManager. Since this does not contain any sprites,
+/// it can be restored using the AnimationManager of the same entity. Nothing saves a run in
+/// progress yet, so this is only the part of an entity's saved state that covers its animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationState {
+    pub id: AnimationId,
+    pub current_step: usize,
+    pub frame_counter: usize,
+}
+
 /// Used to modify the Sprite component every frame
 #[derive(Debug, Clone, Component)]
 #[storage(HashMapStorage)]
 pub struct Animation {
+    /// The animation of the AnimationManager that this animation came from, if any
+    pub id: Option<AnimationId>,
     pub steps: Vec<Frame>,
     /// The current step of the animation
     pub current_step: usize,
@@ -60,6 +244,7 @@ impl Animation {
     /// Create a new animation from the given steps
     pub fn new(steps: Vec<Frame>, can_interrupt: bool, should_loop: bool) -> Self {
         Animation {
+            id: None,
             steps,
             current_step: 0,
             frame_counter: 0,
@@ -71,7 +256,7 @@ impl Animation {
     /// Creates a new animation with a constant frame duration between each sprite
     pub fn with_constant_delay(sprites: &[SpriteId], duration: usize, can_interrupt: bool, should_loop: bool) -> Self {
         Self::new(
-            sprites.into_iter().map(|&sprite| Frame {sprite, duration}).collect(),
+            sprites.iter().map(|&sprite| Frame {sprite, duration}).collect(),
             can_interrupt,
             should_loop
         )
@@ -92,9 +277,62 @@ impl Animation {
         self.steps.iter().map(|f| f.duration).sum()
     }
 
+    /// Returns true if this animation lasts for no frames at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the progress of this animation if it came from an AnimationManager
+    pub fn state(&self) -> Option<AnimationState> {
+        self.id.map(|id| AnimationState {
+            id,
+            current_step: self.current_step,
+            frame_counter: self.frame_counter,
+        })
+    }
+
     /// Returns true if this animation has the same frames as the given animation
     pub fn has_same_steps(&self, other: &Self) -> bool {
-        self.steps == other.steps
+        match (self.id, other.id) {
+            // Each animation of an AnimationManager has different frames, so comparing IDs is
+            // enough (and much faster)
+            (Some(id), Some(other_id)) => id == other_id,
+            _ => self.steps == other.steps,
+        }
+    }
+
+    /// Returns a copy of this animation that plays the given number of times faster. Every step
+    /// still lasts at least one frame. The copy is no longer considered to be the animation from
+    /// the AnimationManager that it came from.
+    pub fn sped_up(&self, factor: usize) -> Self {
+        Self {
+            id: None,
+            steps: self.steps.iter()
+                .map(|frame| Frame {sprite: frame.sprite, duration: (frame.duration / factor).max(1)})
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this animation that plays at the given percentage of its normal speed.
+    /// Every step still lasts at least one frame. Like `sped_up`, the copy is no longer considered
+    /// to be the animation from the AnimationManager that it came from.
+    pub fn at_speed(&self, percent: usize) -> Self {
+        Self {
+            id: None,
+            steps: self.steps.iter()
+                .map(|frame| Frame {sprite: frame.sprite, duration: (frame.duration * 100 / percent).max(1)})
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Moves every sprite of this animation by the given offset. Used when the sprite manager
+    /// that the sprites came from is appended to another one.
+    pub fn offset_sprites(&mut self, offset: usize) {
+        for frame in &mut self.steps {
+            frame.sprite = frame.sprite.offset(offset);
+        }
     }
 
     /// Only updates the animation if the provided animation has different steps
@@ -139,31 +377,64 @@ impl AnimationManager {
     /// Returns the standard character animations based on how most of our character spritesheets
     /// are laid out
     pub fn standard_character_animations(fps: usize, texture_id: TextureId, sprites: &mut SpriteManager) -> Self {
-        /// row_i = the index of the row in the spritesheet
-        /// pattern = the pattern of frame indexes within the row
-        /// durations = the repeating pattern of durations to use for each
-        fn animation(
-            texture_id: TextureId,
-            sprites: &mut SpriteManager,
+        Self::character_animations(fps, texture_id, sprites, Self::standard_frame_region)
+    }
+
+    /// Returns the region of the frame at the given index within the given row of a standard
+    /// character spritesheet
+    pub fn standard_frame_region(row_i: i32, j: i32) -> Rect {
+        // The size of each frame box in the spritesheet
+        const FRAME_SIZE: i32 = 48;
+        Rect::new(
+            j * FRAME_SIZE,
+            FRAME_SIZE * row_i,
+            FRAME_SIZE as u32,
+            FRAME_SIZE as u32,
+        )
+    }
+
+    /// Returns the same animations as `standard_character_animations` except that every frame is
+    /// the entire texture. Used with a single tile placeholder texture when the spritesheet of a
+    /// character is missing.
+    pub fn placeholder_character_animations(fps: usize, texture_id: TextureId, sprites: &mut SpriteManager) -> Self {
+        Self::character_animations(fps, texture_id, sprites, |_, _| {
+            Rect::new(0, 0, NATIVE_TILE_SIZE, NATIVE_TILE_SIZE)
+        })
+    }
+
+    /// Returns the character animations with each frame taken from the region returned by
+    /// `frame_region` for the row and index of that frame in a standard spritesheet
+    fn character_animations(
+        fps: usize,
+        texture_id: TextureId,
+        sprites: &mut SpriteManager,
+        frame_region: fn(i32, i32) -> Rect,
+    ) -> Self {
+        /// One animation taken from a row of a standard spritesheet
+        struct AnimationRow<'a, I> {
+            id: AnimationId,
+            /// The index of the row in the spritesheet
             row_i: i32,
-            pattern: impl Iterator<Item=i32>,
+            /// The pattern of frame indexes within the row
+            pattern: I,
             flip_horizontal: bool,
-            durations: &[usize],
+            /// The repeating pattern of durations to use for each frame
+            durations: &'a [usize],
             can_interrupt: bool,
             should_loop: bool,
-        ) -> Animation {
-            // The size of each frame box in the spritesheet
-            let frame_size = 48;
+        }
 
+        fn animation(
+            texture_id: TextureId,
+            sprites: &mut SpriteManager,
+            frame_region: fn(i32, i32) -> Rect,
+            row: AnimationRow<'_, impl Iterator<Item=i32>>,
+        ) -> Animation {
+            let AnimationRow {id, row_i, pattern, flip_horizontal, durations, can_interrupt, should_loop} = row;
             let steps = pattern.zip(durations.iter().cycle()).map(|(j, &duration)| Frame {
                 sprite: sprites.add(SpriteImage {
                     texture_id,
-                    region: Rect::new(
-                        j * frame_size,
-                        frame_size * row_i,
-                        frame_size as u32,
-                        frame_size as u32,
-                    ),
+                    region: frame_region(row_i, j),
                     flip_horizontal,
                     flip_vertical: false,
                     anchor: Anchor::Center,
@@ -172,7 +443,10 @@ impl AnimationManager {
                 duration,
             }).collect();
 
-            Animation::new(steps, can_interrupt, should_loop)
+            Animation {
+                id: Some(id),
+                ..Animation::new(steps, can_interrupt, should_loop)
+            }
         }
 
         let ms_to_frames = |ms| ms / (1000 / fps);
@@ -181,45 +455,104 @@ impl AnimationManager {
             // Animations are configured based on the character animation guide provided with the
             // asset pack
 
-            idle: animation(texture_id, sprites, 0, 0..3, false, &[ms_to_frames(640), ms_to_frames(80)], true, true),
-            victory: animation(texture_id, sprites, 1, 0..3, false, &[ms_to_frames(640), ms_to_frames(80)], true, true),
-            move_down: animation(texture_id, sprites, 2, 0..4, false, &[ms_to_frames(100)], true, true),
-            move_right: animation(texture_id, sprites, 3, 0..4, false, &[ms_to_frames(100)], true, true),
-            move_left: animation(texture_id, sprites, 3, 0..4, true, &[ms_to_frames(100)], true, true),
-            move_up: animation(texture_id, sprites, 4, 0..4, false, &[ms_to_frames(100)], true, true),
-            attack_down: animation(texture_id, sprites, 5, 0..4, false,
-                &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
-                false, false),
-            attack_right: animation(texture_id, sprites, 6, 0..4, false,
-                &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
-                false, false),
-            attack_left: animation(texture_id, sprites, 6, 0..4, true,
-                &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
-                false, false),
-            attack_up: animation(texture_id, sprites, 7, 0..4, false,
-                &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
-                false, false),
-            hit_down: animation(texture_id, sprites, 8, (0..3).chain(once(0)), false, &[ms_to_frames(100)],
-                false, false),
-            hit_right: animation(texture_id, sprites, 9, (0..3).chain(once(0)), false, &[ms_to_frames(100)],
-                false, false),
-            hit_left: animation(texture_id, sprites, 9, (0..3).chain(once(0)), true, &[ms_to_frames(100)],
-                false, false),
-            hit_up: animation(texture_id, sprites, 10, (0..3).chain(once(0)), false, &[ms_to_frames(100)],
-                false, false),
-            stopped_down: animation(texture_id, sprites, 8, 3..4, false, &[ms_to_frames(1)],
-                true, false),
-            stopped_right: animation(texture_id, sprites, 9, 3..4, false, &[ms_to_frames(1)],
-                true, false),
-            stopped_left: animation(texture_id, sprites, 9, 3..4, true, &[ms_to_frames(1)],
-                true, false),
-            stopped_up: animation(texture_id, sprites, 10, 3..4, false, &[ms_to_frames(1)],
-                true, false),
+            idle: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::Idle, row_i: 0, pattern: 0..3, flip_horizontal: false,
+                durations: &[ms_to_frames(640), ms_to_frames(80)],
+                can_interrupt: true, should_loop: true,
+            }),
+            victory: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::Victory, row_i: 1, pattern: 0..3, flip_horizontal: false,
+                durations: &[ms_to_frames(640), ms_to_frames(80)],
+                can_interrupt: true, should_loop: true,
+            }),
+            move_down: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::MoveDown, row_i: 2, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: true, should_loop: true,
+            }),
+            move_right: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::MoveRight, row_i: 3, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: true, should_loop: true,
+            }),
+            move_left: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::MoveLeft, row_i: 3, pattern: 0..4, flip_horizontal: true,
+                durations: &[ms_to_frames(100)], can_interrupt: true, should_loop: true,
+            }),
+            move_up: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::MoveUp, row_i: 4, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: true, should_loop: true,
+            }),
+            attack_down: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::AttackDown, row_i: 5, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
+                can_interrupt: false, should_loop: false,
+            }),
+            attack_right: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::AttackRight, row_i: 6, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
+                can_interrupt: false, should_loop: false,
+            }),
+            attack_left: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::AttackLeft, row_i: 6, pattern: 0..4, flip_horizontal: true,
+                durations: &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
+                can_interrupt: false, should_loop: false,
+            }),
+            attack_up: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::AttackUp, row_i: 7, pattern: 0..4, flip_horizontal: false,
+                durations: &[ms_to_frames(50), ms_to_frames(100), ms_to_frames(100), ms_to_frames(200)],
+                can_interrupt: false, should_loop: false,
+            }),
+            hit_down: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::HitDown, row_i: 8, pattern: (0..3).chain(once(0)), flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: false, should_loop: false,
+            }),
+            hit_right: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::HitRight, row_i: 9, pattern: (0..3).chain(once(0)), flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: false, should_loop: false,
+            }),
+            hit_left: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::HitLeft, row_i: 9, pattern: (0..3).chain(once(0)), flip_horizontal: true,
+                durations: &[ms_to_frames(100)], can_interrupt: false, should_loop: false,
+            }),
+            hit_up: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::HitUp, row_i: 10, pattern: (0..3).chain(once(0)), flip_horizontal: false,
+                durations: &[ms_to_frames(100)], can_interrupt: false, should_loop: false,
+            }),
+            stopped_down: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::StoppedDown, row_i: 8, pattern: 3..4, flip_horizontal: false,
+                durations: &[ms_to_frames(1)], can_interrupt: true, should_loop: false,
+            }),
+            stopped_right: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::StoppedRight, row_i: 9, pattern: 3..4, flip_horizontal: false,
+                durations: &[ms_to_frames(1)], can_interrupt: true, should_loop: false,
+            }),
+            stopped_left: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::StoppedLeft, row_i: 9, pattern: 3..4, flip_horizontal: true,
+                durations: &[ms_to_frames(1)], can_interrupt: true, should_loop: false,
+            }),
+            stopped_up: animation(texture_id, sprites, frame_region, AnimationRow {
+                id: AnimationId::StoppedUp, row_i: 10, pattern: 3..4, flip_horizontal: false,
+                durations: &[ms_to_frames(1)], can_interrupt: true, should_loop: false,
+            }),
 
             idle_counter: 0,
         }
     }
 
+    /// Moves every sprite of every animation by the given offset. Used when the sprite manager
+    /// that the sprites came from is appended to another one.
+    pub fn offset_sprites(&mut self, offset: usize) {
+        let mut animations = [
+            &mut self.idle, &mut self.victory,
+            &mut self.move_up, &mut self.move_right, &mut self.move_left, &mut self.move_down,
+            &mut self.attack_up, &mut self.attack_right, &mut self.attack_left, &mut self.attack_down,
+            &mut self.hit_up, &mut self.hit_right, &mut self.hit_left, &mut self.hit_down,
+            &mut self.stopped_up, &mut self.stopped_right, &mut self.stopped_left, &mut self.stopped_down,
+        ];
+        for animation in animations.iter_mut() {
+            animation.offset_sprites(offset);
+        }
+    }
+
     /// Returns the default sprite that should be used at the start
     pub fn default_sprite(&self) -> SpriteId {
         let stopped = &self.stopped_down.steps[0];
@@ -230,4 +563,148 @@ impl AnimationManager {
     pub fn default_animation(&self) -> Animation {
         self.stopped_down.clone()
     }
+
+    /// Returns the animation with the given ID
+    pub fn get(&self, id: AnimationId) -> &Animation {
+        use self::AnimationId::*;
+        match id {
+            Idle => &self.idle,
+            Victory => &self.victory,
+            MoveUp => &self.move_up,
+            MoveRight => &self.move_right,
+            MoveLeft => &self.move_left,
+            MoveDown => &self.move_down,
+            AttackUp => &self.attack_up,
+            AttackRight => &self.attack_right,
+            AttackLeft => &self.attack_left,
+            AttackDown => &self.attack_down,
+            HitUp => &self.hit_up,
+            HitRight => &self.hit_right,
+            HitLeft => &self.hit_left,
+            HitDown => &self.hit_down,
+            StoppedUp => &self.stopped_up,
+            StoppedRight => &self.stopped_right,
+            StoppedLeft => &self.stopped_left,
+            StoppedDown => &self.stopped_down,
+        }
+    }
+
+    /// Recreates an animation from its saved progress. Falls back to the default animation if
+    /// the progress no longer fits the animation (e.g. the spritesheet layout changed).
+    pub fn restore(&self, state: AnimationState) -> Animation {
+        let AnimationState {id, current_step, frame_counter} = state;
+        let mut animation = self.get(id).clone();
+        match animation.steps.get(current_step) {
+            Some(step) if frame_counter < step.duration => {
+                animation.current_step = current_step;
+                animation.frame_counter = frame_counter;
+                animation
+            },
+            _ => self.default_animation(),
+        }
+    }
+
+    /// Returns every sprite used in any of the animations
+    pub fn sprites(&self) -> impl Iterator<Item=SpriteId> + '_ {
+        AnimationId::ALL.iter()
+            .flat_map(move |&id| self.get(id).steps.iter().map(|frame| frame.sprite))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_animations() -> AnimationManager {
+        AnimationManager::standard_character_animations(30, TextureId::placeholder(0), &mut SpriteManager::default())
+    }
+
+    #[test]
+    fn animation_ids_round_trip() {
+        let manager = test_animations();
+        for &id in &AnimationId::ALL {
+            assert_eq!(AnimationId::from_name(id.name()), Some(id));
+            assert_eq!(manager.get(id).id, Some(id));
+        }
+        assert_eq!(AnimationId::from_name("dance"), None);
+
+        let mut animation = manager.move_left.clone();
+        animation.current_step = 2;
+        animation.frame_counter = 1;
+        let state = animation.state().unwrap();
+        assert_eq!(state, AnimationState {id: AnimationId::MoveLeft, current_step: 2, frame_counter: 1});
+
+        let restored = manager.restore(state);
+        assert_eq!(restored.id, Some(AnimationId::MoveLeft));
+        assert_eq!(restored.steps, animation.steps);
+        assert_eq!((restored.current_step, restored.frame_counter), (2, 1));
+
+        // Animations that do not come from a manager cannot be restored
+        assert_eq!(Animation::new(animation.steps.clone(), true, true).state(), None);
+    }
+
+    #[test]
+    fn standard_frame_regions() {
+        assert_eq!(AnimationManager::standard_frame_region(0, 0), Rect::new(0, 0, 48, 48));
+        assert_eq!(AnimationManager::standard_frame_region(0, 2), Rect::new(96, 0, 48, 48));
+        assert_eq!(AnimationManager::standard_frame_region(3, 1), Rect::new(48, 144, 48, 48));
+    }
+
+    #[test]
+    fn appended_animations_keep_their_sprites() {
+        let texture = TextureId::placeholder(1);
+        let other_sprite = SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, 16, 16));
+        let mut expected_sprites = SpriteManager::default();
+        expected_sprites.add(other_sprite.clone());
+        let expected = AnimationManager::standard_character_animations(30, texture, &mut expected_sprites);
+
+        let mut sprites = SpriteManager::default();
+        sprites.add(other_sprite);
+
+        // Created on their own, as if on another thread, and then combined
+        let mut sheet = SpriteManager::default();
+        let mut animations = AnimationManager::standard_character_animations(30, texture, &mut sheet);
+        animations.offset_sprites(sprites.append(sheet));
+
+        assert!(animations.sprites().eq(expected.sprites()));
+        for sprite in animations.sprites() {
+            assert_eq!(sprites.get(sprite), expected_sprites.get(sprite));
+        }
+    }
+
+    #[test]
+    fn restore_falls_back_to_default() {
+        let manager = test_animations();
+        let default = manager.default_animation();
+
+        let past_last_step = AnimationState {id: AnimationId::Idle, current_step: 10, frame_counter: 0};
+        let restored = manager.restore(past_last_step);
+        assert_eq!((restored.id, restored.current_step), (default.id, 0));
+
+        let past_step_duration = AnimationState {id: AnimationId::Idle, current_step: 0, frame_counter: 1000};
+        let restored = manager.restore(past_step_duration);
+        assert_eq!((restored.id, restored.current_step), (default.id, 0));
+    }
+
+    #[test]
+    fn id_comparison_matches_step_comparison() {
+        let manager = test_animations();
+        for &id in &AnimationId::ALL {
+            for &other_id in &AnimationId::ALL {
+                let (animation, other) = (manager.get(id), manager.get(other_id));
+                assert_eq!(animation.has_same_steps(other), animation.steps == other.steps,
+                    "{:?} and {:?} compared differently", id, other_id);
+
+                // Only updates when the animations are different
+                let mut updated = animation.clone();
+                updated.current_step = 1;
+                updated.update_if_different(other);
+                let expected_step = if animation.steps == other.steps { 1 } else { 0 };
+                assert_eq!((updated.id, updated.current_step), (
+                    if animation.steps == other.steps { Some(id) } else { Some(other_id) },
+                    expected_step,
+                ));
+            }
+        }
+    }
 }
@@ -1,10 +1,31 @@
 use specs::{Component, HashMapStorage};
 
+use super::{Animation, WeaponKind};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Item {
     TreasureKey,
     RoomKey,
     Potion {stength: u32},
+    /// Coins that can be spent at a merchant. Gold picked up is added to any gold already carried.
+    Gold(u32),
+    /// Equipped as soon as it is found, replacing the weapon that was held before
+    Weapon(WeaponKind),
+}
+
+impl Item {
+    /// Returns a short description of the item for when it is found
+    pub fn name(&self) -> &'static str {
+        match self {
+            Item::TreasureKey => "the treasure key",
+            Item::RoomKey => "a key",
+            Item::Potion {..} => "a potion",
+            Item::Gold(_) => "some gold",
+            Item::Weapon(WeaponKind::Dagger) => "a dagger",
+            Item::Weapon(WeaponKind::Sword) => "a sword",
+            Item::Weapon(WeaponKind::Spear) => "a spear",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Component)]
@@ -13,3 +34,205 @@ pub enum Chest {
     Item(Item),
     Opened,
 }
+
+/// A crate or pot that breaks when it runs out of health points. What it drops is decided when the
+/// level is generated so that the same map key always has the same loot.
+#[derive(Debug, Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct Breakable {
+    /// The item left on the floor once this is broken, if any
+    pub drops: Option<Item>,
+    /// The animation of the item while it is on the floor
+    pub drop_animation: Animation,
+}
+
+/// The item that an enemy leaves on the floor when it is defeated. Like the drops of a breakable,
+/// this is decided when the level is generated.
+#[derive(Debug, Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct Loot {
+    pub item: Item,
+    /// The animation of the item while it is on the floor
+    pub animation: Animation,
+}
+
+/// An item lying on the floor. Walking over it picks it up.
+#[derive(Debug, Clone, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct Pickup(pub Item);
+
+/// A fragment of the map mounted on a wall. Collecting it reveals the given number of nearby
+/// rooms that have not been explored yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct MapFragment {
+    pub rooms: usize,
+}
+
+/// The items carried by an entity
+#[derive(Debug, Clone, Default, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct Inventory {
+    pub items: Vec<Item>,
+}
+
+impl Inventory {
+    /// Adds the given item to the inventory. Gold is added to the gold already carried instead of
+    /// being kept separately.
+    pub fn add(&mut self, item: Item) {
+        if let Item::Gold(amount) = item {
+            for other in &mut self.items {
+                if let Item::Gold(gold) = other {
+                    *gold += amount;
+                    return;
+                }
+            }
+        }
+        self.items.push(item);
+    }
+
+    /// Returns the amount of gold carried
+    pub fn gold(&self) -> u32 {
+        self.items.iter().map(|item| match item {
+            &Item::Gold(amount) => amount,
+            _ => 0,
+        }).sum()
+    }
+
+    /// Removes the given amount of gold from the inventory. Returns false and leaves the inventory
+    /// unchanged if there is not enough gold.
+    pub fn spend_gold(&mut self, amount: u32) -> bool {
+        let gold = self.gold();
+        if gold < amount {
+            return false;
+        }
+
+        self.items.retain(|item| match item {
+            Item::Gold(_) => false,
+            Item::TreasureKey | Item::RoomKey | Item::Potion {..} | Item::Weapon(_) => true,
+        });
+        if gold > amount {
+            self.items.push(Item::Gold(gold - amount));
+        }
+        true
+    }
+
+    /// Returns true if the inventory contains the given item
+    pub fn contains(&self, item: &Item) -> bool {
+        self.items.contains(item)
+    }
+
+    /// Removes one of the given item from the inventory. Returns false if there was none to remove.
+    pub fn take(&mut self, item: &Item) -> bool {
+        match self.items.iter().position(|other| other == item) {
+            Some(index) => {
+                self.items.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes the first potion carried and returns its strength, if any potion is carried
+    pub fn take_potion(&mut self) -> Option<u32> {
+        let index = self.items.iter().position(|item| matches!(item, Item::Potion {..}))?;
+        match self.items.remove(index) {
+            Item::Potion {stength} => Some(stength),
+            _ => unreachable!("bug: only potions should be taken"),
+        }
+    }
+}
+
+/// The reasons that buying from a merchant can fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurchaseError {
+    /// There is nothing for sale at the chosen position in the stock
+    SoldOut,
+    /// The buyer does not carry enough gold to pay the price
+    NotEnoughGold {price: u32, gold: u32},
+}
+
+/// A friendly character that sells items for gold. Merchants never move, fight, or take damage,
+/// but they still block the way like a wall.
+#[derive(Debug, Clone, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct Merchant {
+    /// The items for sale and their prices (in gold). Each item can only be bought once.
+    pub stock: Vec<(Item, u32)>,
+}
+
+impl Merchant {
+    /// Sells the item at the given position in the stock, taking its price from the given
+    /// inventory and adding the item to it. Nothing changes if the purchase fails.
+    pub fn sell(&mut self, index: usize, buyer: &mut Inventory) -> Result<Item, PurchaseError> {
+        let price = match self.stock.get(index) {
+            Some(&(_, price)) => price,
+            None => return Err(PurchaseError::SoldOut),
+        };
+        if !buyer.spend_gold(price) {
+            return Err(PurchaseError::NotEnoughGold {price, gold: buyer.gold()});
+        }
+
+        let (item, _) = self.stock.remove(index);
+        buyer.add(item.clone());
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_potion_removes_first_potion() {
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.take_potion(), None);
+
+        inventory.add(Item::RoomKey);
+        inventory.add(Item::Potion {stength: 3});
+        inventory.add(Item::Potion {stength: 8});
+        assert_eq!(inventory.take_potion(), Some(3));
+        assert_eq!(inventory.items, &[Item::RoomKey, Item::Potion {stength: 8}]);
+    }
+
+    #[test]
+    fn gold_stacks_in_inventory() {
+        let mut inventory = Inventory::default();
+        inventory.add(Item::Gold(5));
+        inventory.add(Item::RoomKey);
+        inventory.add(Item::Gold(7));
+        assert_eq!(inventory.items, &[Item::Gold(12), Item::RoomKey]);
+        assert_eq!(inventory.gold(), 12);
+
+        assert!(!inventory.spend_gold(13));
+        assert_eq!(inventory.gold(), 12);
+        assert!(inventory.spend_gold(4));
+        assert_eq!(inventory.gold(), 8);
+        // Spending everything leaves no empty pile of gold behind
+        assert!(inventory.spend_gold(8));
+        assert_eq!(inventory.items, &[Item::RoomKey]);
+        assert!(inventory.spend_gold(0));
+    }
+
+    #[test]
+    fn buying_from_merchant() {
+        let mut merchant = Merchant {stock: vec![(Item::Potion {stength: 5}, 10), (Item::RoomKey, 25)]};
+        let mut inventory = Inventory {items: vec![Item::Gold(20)]};
+
+        // Too expensive
+        assert_eq!(merchant.sell(1, &mut inventory), Err(PurchaseError::NotEnoughGold {price: 25, gold: 20}));
+        assert_eq!(inventory.items, &[Item::Gold(20)]);
+        assert_eq!(merchant.stock.len(), 2);
+
+        assert_eq!(merchant.sell(0, &mut inventory), Ok(Item::Potion {stength: 5}));
+        assert_eq!(inventory.items, &[Item::Gold(10), Item::Potion {stength: 5}]);
+        // Each item can only be bought once
+        assert_eq!(merchant.stock, &[(Item::RoomKey, 25)]);
+
+        assert_eq!(merchant.sell(1, &mut inventory), Err(PurchaseError::SoldOut));
+        inventory.add(Item::Gold(15));
+        assert_eq!(merchant.sell(0, &mut inventory), Ok(Item::RoomKey));
+        assert_eq!(inventory.items, &[Item::Potion {stength: 5}, Item::RoomKey]);
+        assert_eq!(merchant.sell(0, &mut inventory), Err(PurchaseError::SoldOut));
+    }
+}
@@ -4,7 +4,10 @@ use specs::{Component, VecStorage, HashMapStorage, NullStorage};
 use sdl2::rect::{Point, Rect};
 use rand::{Rng, distributions::{Distribution, Standard}};
 
-/// An entity with this component does not count in collisions and can thus be rendered over
+use crate::assets::scale_to_tile_size;
+
+/// An entity with this component does not count in collisions. It does not change the order that
+/// entities are drawn in (see RenderLayer).
 #[derive(Debug, Default, Component)]
 #[storage(NullStorage)]
 pub struct Ghost;
@@ -24,27 +27,183 @@ pub struct Position(pub Point);
 #[derive(Debug, Clone, Component)]
 #[storage(HashMapStorage)]
 pub struct Movement {
-    /// The most recent direction that the entity was moving in
+    /// The most recent direction that the entity was moving in. The entity always faces this
+    /// direction, even while it is also moving sideways.
     pub direction: MovementDirection,
-    /// The speed of the entity in px/frame
-    pub speed: i32,
+    /// A direction perpendicular to `direction` that the entity is moving in at the same time
+    /// (e.g. East while moving North). None when the entity is moving in a straight line.
+    pub sideways: Option<MovementDirection>,
+    /// The speed of the entity in px/frame. Does not have to be a whole number of pixels.
+    pub speed: f32,
+    /// The fractions of a pixel along each axis that the entity has moved but that have not been
+    /// applied to its position yet
+    pub remainder: (f32, f32),
 }
 
 impl Default for Movement {
     fn default() -> Self {
         Self {
             direction: MovementDirection::East,
-            speed: 0,
+            sideways: None,
+            speed: 0.0,
+            remainder: (0.0, 0.0),
         }
     }
 }
 
 impl Movement {
     pub fn is_moving(&self) -> bool {
-        self.speed != 0
+        self.speed > 0.0
+    }
+
+    /// Returns the velocity of the entity along each axis in px/frame.
+    ///
+    /// Moving diagonally covers the same distance as moving in a straight line, so the speed along
+    /// each axis is divided by sqrt(2).
+    pub fn velocity(&self) -> (f32, f32) {
+        let (x, y, speed) = match self.sideways {
+            Some(sideways) if self.direction.is_perpendicular(sideways) => {
+                let vector = self.direction.to_vector() + sideways.to_vector();
+                (vector.x(), vector.y(), self.speed / 2f32.sqrt())
+            },
+            _ => {
+                let vector = self.direction.to_vector();
+                (vector.x(), vector.y(), self.speed)
+            },
+        };
+        (x as f32 * speed, y as f32 * speed)
+    }
+
+    /// Advances the movement by a single frame and returns the displacement for that frame in
+    /// whole pixels. Whatever fraction of a pixel is left over is carried into the next frame, so
+    /// an entity with a speed of 1.5 moves 1px and then 2px.
+    pub fn step(&mut self) -> Point {
+        self.step_at(1.0)
+    }
+
+    /// Same as step(), but with the speed of the entity multiplied by the given amount for this
+    /// frame (e.g. while the entity is slowed down)
+    pub fn step_at(&mut self, speed_multiplier: f32) -> Point {
+        if !self.is_moving() {
+            self.remainder = (0.0, 0.0);
+            return Point::new(0, 0);
+        }
+
+        let (vel_x, vel_y) = self.velocity();
+        let x = self.remainder.0 + vel_x * speed_multiplier;
+        let y = self.remainder.1 + vel_y * speed_multiplier;
+        self.remainder = (x.fract(), y.fract());
+        Point::new(x.trunc() as i32, y.trunc() as i32)
+    }
+}
+
+/// A short impulse that pushes an entity regardless of its Movement (e.g. after being hit). The
+/// velocity decays every frame until the knockback is complete.
+///
+/// Used in the physics system on top of the normal movement of the entity. Inserting a new
+/// knockback replaces any existing one so that repeated hits refresh the knockback rather than
+/// accumulating it.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Knockback {
+    /// The velocity of the knockback in px/frame
+    pub velocity: Point,
+    /// The number of frames left before the knockback is complete
+    pub frames_remaining: usize,
+}
+
+impl Knockback {
+    /// Creates a knockback in the given direction starting at the given speed (px/frame)
+    pub fn new(direction: MovementDirection, speed: i32, frames: usize) -> Self {
+        Self {
+            velocity: direction.to_vector() * speed,
+            frames_remaining: frames,
+        }
+    }
+
+    /// Returns true if the knockback no longer has any effect
+    pub fn is_complete(&self) -> bool {
+        self.frames_remaining == 0 || self.velocity == Point::new(0, 0)
+    }
+
+    /// Advances the knockback by a single frame and returns the displacement for that frame.
+    /// The velocity is halved after every frame.
+    pub fn step(&mut self) -> Point {
+        if self.is_complete() {
+            return Point::new(0, 0);
+        }
+
+        let displacement = self.velocity;
+        self.velocity /= 2;
+        self.frames_remaining -= 1;
+        displacement
     }
 }
 
+/// A quick roll in a single direction. The entity moves at the roll speed regardless of its
+/// Movement until the roll is over, and then moves at a reduced speed while it recovers.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Dodge {
+    /// The direction of the roll
+    pub direction: MovementDirection,
+    /// The speed of the roll in px/frame
+    pub speed: i32,
+    /// The number of frames left before the roll is over
+    pub roll_frames_remaining: usize,
+    /// The number of frames left after the roll before the entity can move normally again
+    pub recovery_frames_remaining: usize,
+}
+
+impl Dodge {
+    /// The number of frames that a roll lasts for
+    pub const ROLL_FRAMES: usize = 8;
+    /// The number of frames after a roll that the entity moves slower for
+    pub const RECOVERY_FRAMES: usize = 12;
+    /// The entity's speed is divided by this much while it recovers
+    const RECOVERY_SLOWDOWN: i32 = 2;
+
+    /// Creates a roll in the given direction at the given speed (px/frame)
+    pub fn new(direction: MovementDirection, speed: i32) -> Self {
+        Self {
+            direction,
+            speed,
+            roll_frames_remaining: Self::ROLL_FRAMES,
+            recovery_frames_remaining: Self::RECOVERY_FRAMES,
+        }
+    }
+
+    /// Returns true if the entity is still rolling
+    pub fn is_rolling(&self) -> bool {
+        self.roll_frames_remaining > 0
+    }
+
+    /// Returns true if both the roll and the recovery after it are over
+    pub fn is_complete(&self) -> bool {
+        !self.is_rolling() && self.recovery_frames_remaining == 0
+    }
+
+    /// Advances the dodge by a single frame and returns the displacement for that frame given the
+    /// displacement (px) that the entity would otherwise move by during that frame
+    pub fn step(&mut self, velocity: Point) -> Point {
+        if self.is_rolling() {
+            self.roll_frames_remaining -= 1;
+            self.direction.to_vector() * self.speed
+        } else if self.recovery_frames_remaining > 0 {
+            self.recovery_frames_remaining -= 1;
+            velocity / Self::RECOVERY_SLOWDOWN
+        } else {
+            velocity
+        }
+    }
+}
+
+/// An entity that will be moved directly to the given position by the physics system during the
+/// next frame, regardless of its Movement
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct Teleport(pub Point);
+
 /// Represents the direction that an entity would like to move in
 ///
 /// This may not always be possible if there is no way to move further in a given direction (e.g.
@@ -71,6 +230,38 @@ impl Distribution<MovementDirection> for Standard {
 }
 
 impl MovementDirection {
+    /// Returns the direction that most closely points from one position to another
+    pub fn between(from: Point, to: Point) -> Self {
+        use self::MovementDirection::*;
+        let diff = to - from;
+        if diff.x().abs() >= diff.y().abs() {
+            if diff.x() >= 0 { East } else { West }
+        } else if diff.y() >= 0 {
+            South
+        } else {
+            North
+        }
+    }
+
+    /// Returns true if the given direction is at a right angle to this direction
+    pub fn is_perpendicular(self, other: Self) -> bool {
+        use self::MovementDirection::*;
+        matches!((self, other),
+            (North, East) | (North, West) | (South, East) | (South, West) |
+            (East, North) | (East, South) | (West, North) | (West, South))
+    }
+
+    /// Returns the direction that points the opposite way of this direction
+    pub fn opposite(self) -> Self {
+        use self::MovementDirection::*;
+        match self {
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+        }
+    }
+
     /// Returns a Point that represents the unit vector for a given direction
     pub fn to_vector(self) -> Point {
         use self::MovementDirection::*;
@@ -87,7 +278,7 @@ impl MovementDirection {
 /// mean much without a Position also attached to the entity.
 ///
 /// Modifying this after it is initially set is currently NOT supported.
-#[derive(Debug, Clone, Copy, Component)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
 #[storage(VecStorage)]
 pub enum BoundingBox {
     /// A full bounding box centered around the entity's position
@@ -101,13 +292,21 @@ pub enum BoundingBox {
         width: u32,
         height: u32,
     },
+    /// A bounding box with the given size whose center is the given offset away from the
+    /// entity's position
+    Custom {
+        offset: Point,
+        width: u32,
+        height: u32,
+    },
 }
 
 impl BoundingBox {
     /// Shrink the horizontal and vertical size of this bounding box by the given amount centering
     /// the transformation around the reference position. That means that for full bounding boxes
     /// this will shift all four sides inward. For bottom half bounding boxes this will only shift
-    /// the left, right, and bottom sides since the top side is at the position already.
+    /// the left, right, and bottom sides since the top side is at the position already. Custom
+    /// bounding boxes shift all four sides inward without moving their center.
     pub fn shrink(self, value: u32) -> Self {
         use self::BoundingBox::*;
         match self {
@@ -119,6 +318,51 @@ impl BoundingBox {
                 width: width - value * 2,
                 height: height - value,
             },
+            Custom {offset, width, height} => Custom {
+                offset,
+                width: width - value * 2,
+                height: height - value * 2,
+            },
+        }
+    }
+
+    /// Converts a bounding box measured at NATIVE_TILE_SIZE to the same proportions at the given
+    /// tile size
+    pub fn scale_to_tile_size(self, tile_size: u32) -> Self {
+        use self::BoundingBox::*;
+        let scale = |length: u32| scale_to_tile_size(length as i32, tile_size) as u32;
+        match self {
+            Full {width, height} => Full {width: scale(width), height: scale(height)},
+            BottomHalf {width, height} => BottomHalf {width: scale(width), height: scale(height)},
+            Custom {offset, width, height} => Custom {
+                offset: Point::new(
+                    scale_to_tile_size(offset.x(), tile_size),
+                    scale_to_tile_size(offset.y(), tile_size),
+                ),
+                width: scale(width),
+                height: scale(height),
+            },
+        }
+    }
+
+    /// Returns the offset from the entity's position to the center of the bounding box
+    pub fn center_offset(self) -> Point {
+        use self::BoundingBox::*;
+        match self {
+            Full {..} => Point::new(0, 0),
+            // The position is at the top middle of the bounding box
+            BottomHalf {height, ..} => Point::new(0, height as i32/2),
+            Custom {offset, ..} => offset,
+        }
+    }
+
+    /// Returns the width and height of the bounding box
+    pub fn size(self) -> (u32, u32) {
+        use self::BoundingBox::*;
+        match self {
+            Full {width, height} |
+            BottomHalf {width, height} |
+            Custom {width, height, ..} => (width, height),
         }
     }
 
@@ -126,25 +370,117 @@ impl BoundingBox {
     /// boundary of the bounding box. The position is interpreted differently depending on the type
     /// of the bounding box.
     pub fn to_rect(self, pos: Point) -> Rect {
-        use self::BoundingBox::*;
-        match self {
-            Full {width, height} => Rect::from_center(pos, width, height),
-            BottomHalf {width, height} => Rect::from_center(
-                // Make pos be at the top middle of the bounding box
-                pos.offset(0, height as i32/2),
-                width,
-                height
-            ),
-        }
+        let (width, height) = self.size();
+        Rect::from_center(pos + self.center_offset(), width, height)
     }
 
     /// Treat this bounding box as a full bounding box and return its boundary rectangle as if that
-    /// was the case.
+    /// was the case. The returned rectangle is centered around the position and covers the entire
+    /// bounding box.
     pub fn to_full_rect(self, pos: Point) -> Rect {
-        use self::BoundingBox::*;
-        match self {
-            Full {width, height} => Rect::from_center(pos, width, height),
-            BottomHalf {width, height} => Rect::from_center(pos, width, height * 2),
+        let (width, height) = self.size();
+        let offset = self.center_offset();
+        Rect::from_center(pos, width + 2 * offset.x().unsigned_abs(), height + 2 * offset.y().unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knockback_decays() {
+        let mut knockback = Knockback::new(MovementDirection::East, 8, 3);
+        assert_eq!(knockback.step(), Point::new(8, 0));
+        assert_eq!(knockback.step(), Point::new(4, 0));
+        assert_eq!(knockback.step(), Point::new(2, 0));
+        // Ran out of frames
+        assert!(knockback.is_complete());
+        assert_eq!(knockback.step(), Point::new(0, 0));
+
+        // Velocity reaching zero completes the knockback even if there are frames remaining
+        let mut knockback = Knockback::new(MovementDirection::North, 2, 10);
+        assert_eq!(knockback.step(), Point::new(0, -2));
+        assert_eq!(knockback.step(), Point::new(0, -1));
+        assert!(knockback.is_complete());
+        assert_eq!(knockback.step(), Point::new(0, 0));
+    }
+    #[test]
+    fn diagonal_velocity_is_normalized() {
+        use self::MovementDirection::*;
+        let movement = |direction, sideways, speed| Movement {direction, sideways, speed, ..Movement::default()};
+        assert_eq!(movement(North, None, 3.0).velocity(), (0.0, -3.0));
+
+        for &speed in &[1.0, 1.5, 3.0, 8.0] {
+            let (x, y) = movement(West, Some(South), speed).velocity();
+            assert!(x < 0.0 && y > 0.0);
+            assert!((x.abs() - y.abs()).abs() < 1e-6);
+            // Same distance as moving in a straight line
+            assert!(((x * x + y * y).sqrt() - speed).abs() < 1e-4);
         }
+
+        // Only perpendicular directions combine
+        assert_eq!(movement(East, Some(West), 3.0).velocity(), (3.0, 0.0));
+        assert_eq!(movement(East, Some(North), 0.0).velocity(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn fractional_speed_accumulates() {
+        use self::MovementDirection::*;
+        let mut movement = Movement {direction: East, speed: 1.5, ..Movement::default()};
+        let steps: Vec<_> = (0..4).map(|_| movement.step()).collect();
+        assert_eq!(steps, &[Point::new(1, 0), Point::new(2, 0), Point::new(1, 0), Point::new(2, 0)]);
+
+        // Diagonal movement never goes further than moving in a straight line
+        let mut movement = Movement {direction: North, sideways: Some(East), speed: 3.0, ..Movement::default()};
+        let total = (0..100).fold(Point::new(0, 0), |total, _| total + movement.step());
+        assert_eq!(total.x(), -total.y());
+        let distance = ((total.x() * total.x() + total.y() * total.y()) as f64).sqrt();
+        assert!(distance <= 300.0 && distance > 297.0, "moved {}px", distance);
+
+        // Stopping forgets any fraction of a pixel that was left over
+        movement.speed = 0.0;
+        assert_eq!(movement.step(), Point::new(0, 0));
+        assert_eq!(movement.remainder, (0.0, 0.0));
+    }
+
+    #[test]
+    fn bounding_box_rects() {
+        let pos = Point::new(40, 40);
+
+        let full = BoundingBox::Full {width: 16, height: 12};
+        assert_eq!(full.center_offset(), Point::new(0, 0));
+        assert_eq!(full.to_rect(pos), Rect::new(32, 34, 16, 12));
+        assert_eq!(full.to_full_rect(pos), full.to_rect(pos));
+
+        // The position is at the top middle of the box
+        let bottom_half = BoundingBox::BottomHalf {width: 16, height: 8};
+        assert_eq!(bottom_half.center_offset(), Point::new(0, 4));
+        assert_eq!(bottom_half.to_rect(pos), Rect::new(32, 40, 16, 8));
+        assert_eq!(bottom_half.to_full_rect(pos), Rect::new(32, 32, 16, 16));
+
+        let custom = BoundingBox::Custom {offset: Point::new(-3, 5), width: 6, height: 4};
+        assert_eq!(custom.center_offset(), Point::new(-3, 5));
+        assert_eq!(custom.to_rect(pos), Rect::new(34, 43, 6, 4));
+        // Covers the whole box while staying centered around the position
+        let full_rect = custom.to_full_rect(pos);
+        assert_eq!(full_rect, Rect::new(34, 33, 12, 14));
+        assert_eq!(full_rect.center(), pos);
+        assert!(full_rect.contains_rect(custom.to_rect(pos)));
+
+        // A custom box with no offset is the same as a full box
+        let centered = BoundingBox::Custom {offset: Point::new(0, 0), width: 16, height: 12};
+        assert_eq!(centered.to_rect(pos), full.to_rect(pos));
+        assert_eq!(centered.to_full_rect(pos), full.to_full_rect(pos));
+    }
+
+    #[test]
+    fn custom_bounding_box_shrinks_and_scales_around_center() {
+        let custom = BoundingBox::Custom {offset: Point::new(2, -4), width: 10, height: 8};
+        assert_eq!(custom.shrink(2), BoundingBox::Custom {offset: Point::new(2, -4), width: 6, height: 4});
+        assert_eq!(custom.shrink(2).to_rect(Point::new(0, 0)).center(), custom.to_rect(Point::new(0, 0)).center());
+
+        // The offset scales along with the size
+        assert_eq!(custom.scale_to_tile_size(32), BoundingBox::Custom {offset: Point::new(4, -8), width: 20, height: 16});
     }
 }
@@ -0,0 +1,56 @@
+//! Components for things that are fired across the level
+
+use specs::{Component, Entity, HashMapStorage};
+use sdl2::rect::Point;
+
+use crate::assets::SpriteId;
+use crate::map::RoomId;
+
+use super::MovementDirection;
+
+/// Something that flies in a straight line until it hits a wall or an entity with health points
+#[derive(Debug, Clone, Component)]
+#[storage(HashMapStorage)]
+pub struct Projectile {
+    /// The distance (in px) that the projectile moves every frame
+    pub velocity: Point,
+    /// The damage done to the first entity that the projectile hits
+    pub damage: usize, // unit: HP
+    /// The entity that fired the projectile, if any. The projectile passes right through it.
+    pub owner: Option<Entity>,
+}
+
+/// A trap mounted on a wall that fires arrows into the room in front of it while the player is in
+/// that room
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct ArrowShooter {
+    /// The direction that arrows are fired in
+    pub direction: MovementDirection,
+    /// The room that the shooter faces
+    pub room: RoomId,
+    /// The number of frames between each arrow
+    pub period_frames: usize,
+    /// The number of frames left before the next arrow is fired
+    pub frames_remaining: usize,
+    /// The sprite of each arrow that is fired
+    pub arrow: SpriteId,
+}
+
+impl ArrowShooter {
+    pub fn new(direction: MovementDirection, room: RoomId, period_frames: usize, arrow: SpriteId) -> Self {
+        Self {direction, room, period_frames, frames_remaining: period_frames, arrow}
+    }
+
+    /// Counts down to the next arrow. Returns true if an arrow should be fired, in which case the
+    /// count down starts over.
+    pub fn step(&mut self, frames_elapsed: usize) -> bool {
+        if frames_elapsed >= self.frames_remaining {
+            self.frames_remaining = self.period_frames;
+            true
+        } else {
+            self.frames_remaining -= frames_elapsed;
+            false
+        }
+    }
+}
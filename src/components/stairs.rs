@@ -2,7 +2,9 @@
 
 use std::fmt;
 
-use specs::{Component, HashMapStorage};
+use specs::{Component, HashMapStorage, NullStorage};
+
+use crate::map::{TilePos, StairsUid};
 
 /// A staircase to the next level or to the previous level
 #[derive(Debug, Component)]
@@ -20,6 +22,23 @@ pub enum Stairs {
     },
 }
 
+/// The way out of the caves, on the same floor that the player enters from. Interacting with it
+/// while escaping with the treasure wins the run.
+#[derive(Debug, Default, Component)]
+#[storage(NullStorage)]
+pub struct DungeonMouth;
+
+impl Stairs {
+    /// Returns the uid of these stairs when they are placed at the given tile of the given level
+    pub fn uid(&self, level: usize, pos: TilePos) -> StairsUid {
+        let to_next_level = match *self {
+            Stairs::ToNextLevel {..} => true,
+            Stairs::ToPrevLevel {..} => false,
+        };
+        StairsUid::new(level, pos, to_next_level)
+    }
+}
+
 impl fmt::Display for Stairs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::Stairs::*;
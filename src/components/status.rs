@@ -0,0 +1,201 @@
+//! Components for lasting effects (e.g. poison) applied to characters
+
+use std::mem;
+
+use specs::{Component, HashMapStorage};
+
+/// The kinds of status effects. Ordered by how important they are to show (e.g. when choosing
+/// which effect tints a sprite).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StatusKind {
+    /// Damages the entity once every POISON_DAMAGE_FRAMES. The magnitude is the damage (in HP)
+    /// done each time.
+    Poison,
+    /// Slows down the movement of the entity. The magnitude is the percentage of its speed that
+    /// is lost.
+    Slow,
+}
+
+impl StatusKind {
+    /// The number of frames between each time that poison damages the entity
+    pub const POISON_DAMAGE_FRAMES: usize = 30;
+}
+
+/// A single effect that lasts for a limited number of frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// The number of frames left before the effect expires
+    pub frames_remaining: usize,
+    /// How strong the effect is. What this means depends on the kind of effect.
+    pub magnitude: usize,
+    /// The frames elapsed since the effect last did damage. Kept when the effect is refreshed so
+    /// that applying it over and over again does not delay the damage.
+    frames_since_tick: usize,
+}
+
+impl StatusEffect {
+    pub fn new(kind: StatusKind, frames_remaining: usize, magnitude: usize) -> Self {
+        Self {kind, frames_remaining, magnitude, frames_since_tick: 0}
+    }
+
+    /// Poison that does the given damage every POISON_DAMAGE_FRAMES for the given number of frames
+    pub fn poison(frames_remaining: usize, damage: usize) -> Self {
+        Self::new(StatusKind::Poison, frames_remaining, damage)
+    }
+
+    /// Removes the given percentage of the speed of the entity for the given number of frames
+    pub fn slow(frames_remaining: usize, percent: usize) -> Self {
+        Self::new(StatusKind::Slow, frames_remaining, percent)
+    }
+
+    /// Counts down the effect by the given number of frames and returns the damage that it did
+    /// during those frames. Only frames before the effect expired are counted.
+    fn step(&mut self, frames_elapsed: usize) -> usize {
+        let frames_elapsed = frames_elapsed.min(self.frames_remaining);
+        self.frames_remaining -= frames_elapsed;
+
+        match self.kind {
+            StatusKind::Poison => {
+                self.frames_since_tick += frames_elapsed;
+                let ticks = self.frames_since_tick / StatusKind::POISON_DAMAGE_FRAMES;
+                self.frames_since_tick %= StatusKind::POISON_DAMAGE_FRAMES;
+                ticks * self.magnitude
+            },
+            StatusKind::Slow => 0,
+        }
+    }
+}
+
+/// The status effects currently applied to an entity. There is at most one effect of each kind.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct StatusEffects {
+    effects: Vec<StatusEffect>,
+    /// The damage done by the effects that has not been applied to the entity yet
+    pending_damage: usize,
+}
+
+impl StatusEffects {
+    /// Applies the given effect to the entity. If an effect of the same kind is already applied,
+    /// its duration is refreshed instead of adding another effect. The stronger of the two
+    /// magnitudes is kept, so applying an effect over and over again never makes it stack.
+    pub fn apply(&mut self, effect: StatusEffect) {
+        match self.effects.iter_mut().find(|current| current.kind == effect.kind) {
+            Some(current) => {
+                current.frames_remaining = current.frames_remaining.max(effect.frames_remaining);
+                current.magnitude = current.magnitude.max(effect.magnitude);
+            },
+            None => self.effects.push(effect),
+        }
+    }
+
+    /// Counts down every effect, removing any that have expired. Any damage done is added to the
+    /// pending damage.
+    pub fn step(&mut self, frames_elapsed: usize) {
+        for effect in &mut self.effects {
+            self.pending_damage += effect.step(frames_elapsed);
+        }
+        self.effects.retain(|effect| effect.frames_remaining > 0);
+    }
+
+    /// Returns the damage done by the effects since this was last called
+    pub fn take_damage(&mut self) -> usize {
+        mem::take(&mut self.pending_damage)
+    }
+
+    /// Returns true if there are no effects left and no damage still waiting to be applied
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty() && self.pending_damage == 0
+    }
+
+    /// Returns the effect of the given kind, if any
+    pub fn get(&self, kind: StatusKind) -> Option<&StatusEffect> {
+        self.effects.iter().find(|effect| effect.kind == kind)
+    }
+
+    /// Returns the kinds of all the effects currently applied, most important first
+    pub fn kinds(&self) -> Vec<StatusKind> {
+        let mut kinds: Vec<_> = self.effects.iter().map(|effect| effect.kind).collect();
+        kinds.sort_unstable();
+        kinds
+    }
+
+    /// Returns the number that the speed of the entity should be multiplied by
+    pub fn speed_multiplier(&self) -> f32 {
+        match self.get(StatusKind::Slow) {
+            Some(slow) => 1.0 - slow.magnitude.min(100) as f32 / 100.0,
+            None => 1.0,
+        }
+    }
+}
+
+/// An entity that applies the given status effect to anything that it damages (e.g. an enemy with
+/// a poisonous bite, or a projectile)
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+#[storage(HashMapStorage)]
+pub struct InflictsStatus(pub StatusEffect);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poison_damages_on_cadence() {
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusEffect::poison(100, 2));
+
+        effects.step(StatusKind::POISON_DAMAGE_FRAMES - 1);
+        assert_eq!(effects.take_damage(), 0);
+        effects.step(1);
+        assert_eq!(effects.take_damage(), 2);
+        // Damage is only taken once
+        assert_eq!(effects.take_damage(), 0);
+
+        // Several ticks can happen during a single step
+        effects.step(StatusKind::POISON_DAMAGE_FRAMES * 2);
+        assert_eq!(effects.take_damage(), 4);
+    }
+
+    #[test]
+    fn effects_expire() {
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusEffect::slow(10, 50));
+        effects.apply(StatusEffect::poison(StatusKind::POISON_DAMAGE_FRAMES + 5, 1));
+        assert_eq!(effects.kinds(), &[StatusKind::Poison, StatusKind::Slow]);
+        assert_eq!(effects.speed_multiplier(), 0.5);
+
+        effects.step(10);
+        assert_eq!(effects.kinds(), &[StatusKind::Poison]);
+        assert_eq!(effects.speed_multiplier(), 1.0);
+
+        // Frames after the poison expired do not count towards its next tick
+        effects.step(StatusKind::POISON_DAMAGE_FRAMES * 3);
+        assert_eq!(effects.kinds(), &[]);
+        assert!(!effects.is_empty());
+        assert_eq!(effects.take_damage(), 1);
+        assert!(effects.is_empty());
+    }
+
+    #[test]
+    fn same_kind_refreshes_without_stacking() {
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusEffect::poison(60, 2));
+        effects.step(StatusKind::POISON_DAMAGE_FRAMES - 5);
+
+        effects.apply(StatusEffect::poison(60, 1));
+        effects.apply(StatusEffect::poison(60, 1));
+        let poison = effects.get(StatusKind::Poison).unwrap();
+        assert_eq!(poison.frames_remaining, 60);
+        assert_eq!(poison.magnitude, 2);
+        assert_eq!(effects.kinds(), &[StatusKind::Poison]);
+
+        // Refreshing does not delay the next tick
+        effects.step(5);
+        assert_eq!(effects.take_damage(), 2);
+
+        // A shorter effect never cuts the current one short
+        effects.apply(StatusEffect::poison(1, 2));
+        assert_eq!(effects.get(StatusKind::Poison).unwrap().frames_remaining, 55);
+    }
+}
@@ -0,0 +1,130 @@
+use specs::{Component, HashMapStorage};
+
+use super::Animation;
+
+/// The kinds of weapons that can be held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WeaponKind {
+    Dagger,
+    Sword,
+    Spear,
+}
+
+impl Default for WeaponKind {
+    /// The weapon that the player starts with
+    fn default() -> Self {
+        WeaponKind::Sword
+    }
+}
+
+impl WeaponKind {
+    /// Every kind of weapon
+    pub const ALL: [WeaponKind; 3] = [WeaponKind::Dagger, WeaponKind::Sword, WeaponKind::Spear];
+
+    /// Returns the stats of this kind of weapon
+    pub fn weapon(self) -> Weapon {
+        WEAPONS.iter().find(|weapon| weapon.kind == self).cloned()
+            .expect("bug: every kind of weapon should have stats")
+    }
+
+    /// Returns the name of this kind of weapon
+    pub fn name(self) -> &'static str {
+        match self {
+            WeaponKind::Dagger => "dagger",
+            WeaponKind::Sword => "sword",
+            WeaponKind::Spear => "spear",
+        }
+    }
+}
+
+/// The stats of every kind of weapon. The sword matches an attack made without any weapon.
+const WEAPONS: [Weapon; 3] = [
+    // Quick jabs that barely reach past the end of the player's arm
+    Weapon {kind: WeaponKind::Dagger, damage: 70, range: 0.75, sweep: 0.0, attack_speed: 200},
+    // Slashes wide enough to catch enemies that are slightly off to the side
+    Weapon {kind: WeaponKind::Sword, damage: 100, range: 1.0, sweep: 0.25, attack_speed: 100},
+    // Hits hard from two tiles away, but leaves the player open for a long time
+    Weapon {kind: WeaponKind::Spear, damage: 130, range: 2.0, sweep: 0.0, attack_speed: 60},
+];
+
+/// Changes how an entity attacks while it is holding the weapon
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    /// The damage done, as a percentage of the attack of the entity holding the weapon
+    pub damage: usize,
+    /// How far an attack reaches, as a multiple of the tile size
+    pub range: f64,
+    /// How far an attack reaches past either side of the entity holding the weapon, as a multiple
+    /// of the tile size
+    pub sweep: f64,
+    /// How fast the attack animation plays, as a percentage of its normal speed. The entity can
+    /// only attack again once the animation has finished, so this also decides the cooldown.
+    pub attack_speed: usize,
+}
+
+impl Weapon {
+    /// Returns the damage done by an attack of the given strength with this weapon
+    pub fn scale_damage(&self, attack: usize) -> usize {
+        attack * self.damage / 100
+    }
+
+    /// Returns the distance (in px) that an attack with this weapon reaches
+    pub fn range(&self, tile_size: u32) -> i32 {
+        (tile_size as f64 * self.range) as i32
+    }
+
+    /// Returns the distance (in px) that an attack with this weapon reaches past either side of
+    /// the entity holding it
+    pub fn sweep(&self, tile_size: u32) -> i32 {
+        (tile_size as f64 * self.sweep) as i32
+    }
+
+    /// Returns the given attack animation played at the speed of this weapon
+    pub fn attack_animation(&self, animation: &Animation) -> Animation {
+        animation.at_speed(self.attack_speed)
+    }
+
+    /// Returns the number of frames before an entity can attack again after attacking with the
+    /// given animation
+    pub fn cooldown(&self, animation: &Animation) -> usize {
+        self.attack_animation(animation).len()
+    }
+}
+
+/// The weapon held by an entity. Entities without equipment attack as if they held a sword.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+#[storage(HashMapStorage)]
+pub struct Equipment {
+    pub weapon: Weapon,
+}
+
+impl Default for Equipment {
+    fn default() -> Self {
+        Self {weapon: WeaponKind::default().weapon()}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_helpers::test_animations;
+
+    #[test]
+    fn weapons_trade_speed_for_reach() {
+        let animation = test_animations().attack_right;
+        let [dagger, sword, spear] = WeaponKind::ALL;
+        let (dagger, sword, spear) = (dagger.weapon(), sword.weapon(), spear.weapon());
+
+        // Holding a sword is the same as holding nothing at all
+        assert_eq!(sword.cooldown(&animation), animation.len());
+        assert_eq!(sword.scale_damage(10), 10);
+        assert_eq!(sword.range(16), 16);
+
+        assert!(dagger.cooldown(&animation) < sword.cooldown(&animation));
+        assert!(sword.cooldown(&animation) < spear.cooldown(&animation));
+        assert!(dagger.range(16) < sword.range(16) && sword.range(16) < spear.range(16));
+        assert!(dagger.scale_damage(10) < sword.scale_damage(10) && sword.scale_damage(10) < spear.scale_damage(10));
+    }
+}
@@ -7,29 +7,105 @@ mod rooms;
 mod sprite_patterns;
 mod place_items;
 mod doorways;
+mod corridors;
+mod decorations;
+mod hazards;
+mod layout;
 mod enemies;
+mod boss;
+mod validate;
+mod invariants;
+mod room_report;
 
 mod map_key;
 mod bounds;
+mod connection_style;
 mod enemy_config;
+mod config;
+mod errors;
 
 mod world_helpers;
 
 pub use self::map_key::*;
 pub use self::bounds::*;
+pub use self::connection_style::*;
 pub use self::enemy_config::*;
+pub use self::config::*;
+pub use self::errors::*;
+pub use self::invariants::*;
+pub use self::room_report::*;
+pub use self::enemies::spawn_enemies;
+
+use std::time::{Duration, Instant};
 
 use rand::{random, rngs::StdRng, Rng, SeedableRng};
-use specs::{World, Dispatcher};
+use specs::{World, Join, ReadExpect, ReadStorage};
 use sdl2::rect::Point;
 use rayon::prelude::*;
 
 use crate::map::*;
 use crate::map_sprites::MapSprites;
+use crate::components::{AnimationManager, Position, Stairs, EnemySpawn, Enemy, Chest, RenderLayer};
+use crate::resources::{ExploredTiles, RoomTracker, LevelUids, Tremor, LevelRng};
+use crate::systems::LevelDispatcher;
+
+use self::world_helpers::world_stairs_uids;
 
 pub struct GenLevel<'a, 'b> {
     pub world: World,
-    pub dispatcher: Dispatcher<'a, 'b>,
+    pub dispatcher: LevelDispatcher<'a, 'b>,
+    /// Used to decide which enemies spawn when the player first enters the level
+    pub spawn_rng: StdRng,
+}
+
+impl<'a, 'b> GenLevel<'a, 'b> {
+    /// Returns the tile where the player enters this level: the center of the player start room
+    /// on the first level or the tile beside the first staircase to the previous level on every
+    /// other level.
+    pub fn entrance(&self) -> TilePos {
+        let (map, positions, stairs) = self.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Stairs>,
+        )>();
+
+        let player_start = map.rooms().find(|(_, room)| room.room_type() == RoomType::PlayerStart)
+            .map(|(_, room)| room.boundary().center_tile());
+        player_start.or_else(|| {
+            let stairs = (&positions, &stairs).join()
+                .find(|(_, stairs)| match stairs {
+                    Stairs::ToNextLevel {..} => false,
+                    Stairs::ToPrevLevel {..} => true,
+                })
+                .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))?;
+            Some(map.grid().adjacent_positions(stairs).find(|&adj| map.grid().get(adj).is_floor())
+                .unwrap_or(stairs))
+        }).expect("bug: level should have either a player start room or a staircase to the previous level")
+    }
+
+    /// Computes statistics about this level. Paths are measured from where the player enters the
+    /// level (see `entrance`).
+    pub fn stats(&self) -> MapStats {
+        let start = self.entrance();
+        let (map, positions, stairs, spawns, enemies) = self.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Stairs>,
+            ReadStorage<'_, EnemySpawn>,
+            ReadStorage<'_, Enemy>,
+        )>();
+
+        let to_next_level: Vec<_> = (&positions, &stairs).join()
+            .filter(|(_, stairs)| match stairs {
+                Stairs::ToNextLevel {..} => true,
+                Stairs::ToPrevLevel {..} => false,
+            })
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .collect();
+
+        let nenemies = spawns.join().count() + enemies.join().count();
+        map.compute_stats(start, &to_next_level, nenemies)
+    }
 }
 
 pub struct GenGame<'a, 'b> {
@@ -38,6 +114,29 @@ pub struct GenGame<'a, 'b> {
     /// The point that the player spawns at when the game begins. This point is only valid on the
     /// first level and the player should only be spawned at this point on the first level.
     pub player_start: Point,
+    /// The time it took to generate each level (during the try that succeeded). Levels are
+    /// generated in parallel, so the total time is closer to the maximum than the sum.
+    pub level_times: Vec<Duration>,
+}
+
+/// Reported as each phase of generating a level completes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelProgress {
+    /// The level being generated (starting at 1)
+    pub level: usize,
+    /// The name of the phase that just completed
+    pub phase: &'static str,
+    /// The time since generation of the level started
+    pub elapsed: Duration,
+}
+
+/// Logs the completion of a phase of generating a level along with how long that phase took and
+/// how many rooms and entities the level has so far
+pub fn log_phase(key: MapKey, progress: LevelProgress, phase_time: Duration, rooms: usize, entities: usize) {
+    debug!(target: "caves::generator",
+        "key={} level={} phase={:?} phase_ms={:.3} elapsed_ms={:.3} rooms={} entities={}",
+        key, progress.level, progress.phase, phase_time.as_secs_f64() * 1000.0,
+        progress.elapsed.as_secs_f64() * 1000.0, rooms, entities);
 }
 
 fn find_player_start<'a, 'b>(levels: &[GenLevel<'a, 'b>]) -> Point {
@@ -58,17 +157,17 @@ fn find_player_start<'a, 'b>(levels: &[GenLevel<'a, 'b>]) -> Point {
 }
 
 impl<'a, 'b> GenGame<'a, 'b> {
-    fn new(key: MapKey, levels: Vec<GenLevel<'a, 'b>>) -> Self {
+    fn new(key: MapKey, levels: Vec<GenLevel<'a, 'b>>, level_times: Vec<Duration>) -> Self {
         // Calculate the player start position
         let player_start = find_player_start(&levels);
-        GenGame {key, levels, player_start}
+        GenGame {key, levels, player_start, level_times}
     }
 }
 
-/// Represents when we have run out of attempts to generate the map from a given key
-/// This can happen if a loop trying to generate something runs too many times
-#[derive(Debug, Clone, Copy)]
-struct RanOutOfAttempts;
+/// The number of times the entire map is regenerated from the same key before giving up. If it
+/// takes more than this many tries, we can conclude that it was essentially impossible to
+/// generate the map.
+const MAX_TRIES: usize = 10;
 
 #[derive(Clone)]
 pub struct GameGenerator<'a> {
@@ -95,20 +194,74 @@ pub struct GameGenerator<'a> {
     pub room_rows: Bounds<usize>,
     /// The minimum and maximum width (in tiles) of a room
     pub room_cols: Bounds<usize>,
+    /// How rooms are joined together so that every room can be reached
+    pub connection_style: ConnectionStyle,
     /// The maximum % that a room can overlap another room
-    /// Value should be between 0.0 and 1.0
+    /// Value should be between 0.0 and 1.0. Only used when rooms are joined by overlapping.
     pub max_overlap: f64,
     /// The min/max number of doors to give every room. Min must be at least 1 or some rooms will
     /// not be reachable.
     pub doors: Bounds<usize>,
+    /// The minimum and maximum number of challenge rooms on each level from
+    /// `challenge_rooms_start_level` onwards. The challenge room holding the key to the treasure
+    /// chamber counts towards this.
+    pub challenge_rooms: Bounds<usize>,
+    /// The first level (starting at 1) that is guaranteed to have challenge rooms
+    pub challenge_rooms_start_level: usize,
     /// The number of tiles that take you to the next level/prev level
     /// This will create `next_prev_tiles` number of ToNextLevel tiles and
     /// `next_prev_tiles` number of ToPrevLevel tiles
     pub next_prev_tiles: usize,
-    /// The minimum and maximum number of enemies to generate in a room
+    /// The minimum and maximum number of map fragments to place on each level
+    pub map_fragments: Bounds<usize>,
+    /// The number of unexplored rooms revealed when a map fragment is collected
+    pub map_fragment_rooms: usize,
+    /// The probability [0.0, 1.0] that a level contains a caged prisoner that can be rescued
+    pub prisoner_chance: f64,
+    /// The probability [0.0, 1.0] that the layout of a level is mirrored or rotated after its
+    /// rooms are connected
+    pub layout_transform_chance: f64,
+    /// The number of attacks it takes to break open the cage of a prisoner
+    pub cage_hits: usize,
+    /// The animations of a prisoner once they have been freed
+    pub prisoner_animations: AnimationManager,
+    /// The probability [0.0, 1.0] that one of the normal rooms of a level becomes a merchant room
+    pub merchant_chance: f64,
+    /// The probability [0.0, 1.0] that a level has a chest with a weapon in one of its normal rooms
+    pub weapon_chest_chance: f64,
+    /// The probability [0.0, 1.0] that a large room has pillars
+    pub pillar_chance: f64,
+    /// The minimum and maximum number of decorative props to place in each room
+    pub props_per_room: Bounds<usize>,
+    /// The minimum and maximum number of breakable crates and pots to place in each room
+    pub breakables_per_room: Bounds<usize>,
+    /// The probability [0.0, 1.0] that breaking a crate or pot drops an item
+    pub breakable_drop_chance: f64,
+    /// The probability [0.0, 1.0] that each long, straight wall of a room that can have enemies
+    /// gets an arrow shooter
+    pub arrow_shooter_chance: f64,
+    /// The number of frames between each arrow fired by an arrow shooter
+    pub arrow_shooter_period: usize,
+    /// The probability [0.0, 1.0] that a room that can have enemies gets a patch of hazards (water
+    /// or pits) on its floor
+    pub hazard_chance: f64,
+    /// The minimum and maximum number of enemy spawn points to generate in a room
     pub room_enemies: Bounds<usize>,
     /// The maximum proportion (0.0, 1.0] of the area of a room that enemies can take
     pub max_room_enemy_area: f64,
+    /// The most enemy spawn points that can already be within ENEMY_DENSITY_RADIUS tiles of a new
+    /// spawn point. Limits how many enemies end up together where rooms overlap.
+    pub max_nearby_enemies: usize,
+    /// The probability [0.0, 1.0] that each enemy spawn point spawns an enemy when the player
+    /// first enters the level
+    pub enemy_spawn_probability: f64,
+    /// The probability [0.0, 1.0] that each enemy drops gold when it is defeated
+    pub enemy_gold_chance: f64,
+    /// The minimum and maximum amount of gold dropped by an enemy
+    pub enemy_gold: Bounds<u32>,
+    /// The number of frames that the player can spend on a level before a tremor collapses one of
+    /// its doors. There are no tremors on the first level. None to disable tremors entirely.
+    pub tremor_frames: Option<usize>,
     /// Sprites from the spritesheet
     pub sprites: &'a MapSprites,
     /// Configurations for each enemy for each different type of enemy
@@ -116,41 +269,91 @@ pub struct GameGenerator<'a> {
 }
 
 impl<'a> GameGenerator<'a> {
-    pub fn generate<'b, 'c>(self, setup_world: impl Fn() -> (Dispatcher<'b, 'c>, World)) -> GenGame<'b, 'c> {
+    pub fn generate<'b, 'c>(self, setup_world: impl Fn() -> (LevelDispatcher<'b, 'c>, World)) -> Result<GenGame<'b, 'c>, GenerateError> {
         self.generate_with_key(random(), setup_world)
     }
 
-    pub fn generate_with_key<'b, 'c>(self, key: MapKey, setup_world: impl Fn() -> (Dispatcher<'b, 'c>, World)) -> GenGame<'b, 'c> {
+    pub fn generate_with_key<'b, 'c>(self, key: MapKey, setup_world: impl Fn() -> (LevelDispatcher<'b, 'c>, World)) -> Result<GenGame<'b, 'c>, GenerateError> {
+        self.generate_with_progress(key, setup_world, |_| {})
+    }
+
+    /// Generates the game from the given key, calling `on_progress` as each phase of each level
+    /// completes. Levels are generated in parallel, so `on_progress` may be called from several
+    /// threads at once.
+    pub fn generate_with_progress<'b, 'c>(
+        self,
+        key: MapKey,
+        setup_world: impl Fn() -> (LevelDispatcher<'b, 'c>, World),
+        on_progress: impl Fn(LevelProgress) + Sync,
+    ) -> Result<GenGame<'b, 'c>, GenerateError> {
+        self.validate_config()?;
+
         let mut rng = key.to_rng();
 
-        // If this takes more than 10 attempts, we can conclude that it was essentially impossible
-        // to generate the map.
-        for _ in 0..10 {
+        let mut last_failure = None;
+        for _ in 0..MAX_TRIES {
             let (rngs_worlds, dispatchers): (Vec<_>, Vec<_>) = (1..=self.levels).map(|level| {
                 let (dispatcher, world) = setup_world();
                 ((self.clone(), level, StdRng::from_seed(rng.gen()), world), dispatcher)
             }).unzip();
             let levels: Result<Vec<_>, _> = rngs_worlds.into_par_iter()
-                .map(|(generator, level, mut rng, world)| generator.populate_level(&mut rng, level, world))
+                .map(|(generator, level, mut rng, world)| {
+                    let start = Instant::now();
+                    generator.populate_level(key, &mut rng, level, world, &on_progress)
+                        .map(|world| (world, start.elapsed()))
+                })
                 .collect();
             let levels = levels.map(|levels| levels.into_iter()
-                .zip(dispatchers.into_iter())
-                .map(|(world, dispatcher)| GenLevel {world, dispatcher})
-                .collect());
+                .zip(dispatchers)
+                .enumerate()
+                .map(|(i, ((mut world, time), dispatcher))| {
+                    let mut spawn_rng = key.level_rng(i + 1);
+                    // Systems get their own generator so that spawning enemies never changes what
+                    // the systems do afterwards
+                    world.add_resource(LevelRng(StdRng::from_seed(spawn_rng.gen())));
+                    (GenLevel {world, dispatcher, spawn_rng}, time)
+                })
+                .unzip());
 
             match levels {
-                Ok(levels) => return GenGame::new(key, levels),
+                Ok((levels, level_times)) => return Ok(GenGame::new(key, levels, level_times)),
                 // Reseed the rng using itself
-                Err(RanOutOfAttempts) => {
+                Err(failure) => {
+                    last_failure = Some(failure);
                     rng = StdRng::from_seed(rng.gen());
                 },
             }
         }
 
-        panic!("Never succeeded in generating a map with key `{}`!", key);
+        let last_failure: RanOutOfAttempts = last_failure.expect("bug: should have tried at least once");
+        Err(GenerateError::RanOutOfAttempts {
+            key,
+            tries: MAX_TRIES,
+            last_failure,
+            config: self.phase_config(last_failure.phase),
+        })
     }
 
-    fn populate_level(&self, rng: &mut StdRng, level: usize, mut world: World) -> Result<World, RanOutOfAttempts> {
+    fn populate_level(
+        &self,
+        key: MapKey,
+        rng: &mut StdRng,
+        level: usize,
+        mut world: World,
+        on_progress: &(impl Fn(LevelProgress) + Sync),
+    ) -> Result<World, RanOutOfAttempts> {
+        let start = Instant::now();
+        let mut last_elapsed = Duration::default();
+        let mut progress = |phase, map: &FloorMap, world: &World| {
+            let update = LevelProgress {level, phase, elapsed: start.elapsed()};
+            log_phase(key, update, update.elapsed - last_elapsed, map.rooms().count(), world.entities().join().count());
+            last_elapsed = update.elapsed;
+            on_progress(update);
+        };
+
+        // Only the renderer reads render layers, so their storage may not have been registered yet
+        world.register::<RenderLayer>();
+
         // Levels are generated in "phases". The following calls runs each of those in succession.
         let mut map = FloorMap::new(
             GridSize {rows: self.rows, cols: self.cols},
@@ -158,22 +361,76 @@ impl<'a> GameGenerator<'a> {
         );
 
         self.generate_rooms(rng, &mut map, level)?;
+        progress("rooms", &map, &world);
 
         self.connect_rooms(rng, &mut map, &mut world);
+        progress("doorways", &map, &world);
+
+        self.transform_layout(rng, &mut map, &mut world);
+        progress("layout", &map, &world);
 
         if level < self.levels {
             self.place_to_next_level_tiles(rng, &mut map, &mut world)?;
         }
         if level > 1 {
             self.place_to_prev_level_tiles(rng, &mut map, &mut world)?;
+        } else {
+            self.place_dungeon_mouth(rng, &mut map, &mut world)?;
+        }
+        progress("staircases", &map, &world);
+        self.place_map_fragments(rng, &mut map, &mut world)?;
+        progress("map fragments", &map, &world);
+        self.place_prisoner(rng, &mut map, &mut world)?;
+        progress("prisoners", &map, &world);
+        self.place_merchant(rng, &mut map, &mut world)?;
+        progress("merchant", &map, &world);
+        self.place_weapon_chest(rng, &mut map, &mut world)?;
+        progress("weapon chest", &map, &world);
+        if level == self.levels {
+            self.place_treasure_key(rng, &mut map, &mut world)?;
+            self.lock_treasure_chamber(&map, &mut world);
         }
+        progress("treasure chamber", &map, &world);
 
         self.layout_floor_wall_sprites(rng, &mut map);
         self.layout_wall_torch_sprites(&mut map, &mut world);
+        progress("sprites", &map, &world);
+
+        self.decorate_rooms(rng, &mut map, &mut world);
+        progress("decorations", &map, &world);
+
+        self.place_hazards(rng, &mut map, &mut world);
+        progress("hazards", &map, &world);
 
         self.add_enemies(rng, &map, &mut world, level)?;
+        self.relocate_safe_zone_spawns(rng, &map, &mut world)?;
+        if level == self.levels {
+            self.place_boss(rng, &map, &mut world)?;
+        }
+        progress("enemies", &map, &world);
+
+        // Later phases can wall off a staircase that was reachable when it was placed (e.g. with
+        // a pillar or the walls around another staircase), so the finished level is checked
+        world.register::<Stairs>();
+        if let Err(unreachable) = map.validate_reachability(&world) {
+            debug!("key={} level={} staircases cannot be reached: {:?}", key, level, unreachable);
+            return Err(RanOutOfAttempts {phase: GenPhase::Reachability, attempts: 1});
+        }
+        progress("reachability", &map, &world);
+        log_map(&map, log::Level::Trace, &format!("key={} level={}", key, level));
 
+        let uids = LevelUids::new(level, &map, world_stairs_uids(&world, &map, level));
+        world.add_resource(uids);
+        world.add_resource(match self.tremor_frames {
+            Some(frames_remaining) if level > 1 => Tremor::Pending {frames_remaining},
+            _ => Tremor::Inactive,
+        });
+        // Nothing has been explored on a new level
+        world.add_resource(ExploredTiles::new(map.grid().dimensions()));
+        world.add_resource(RoomTracker::default());
         world.add_resource(map);
+        // Chests are only generated on the last level, but reports about every level look for them
+        world.register::<Chest>();
         Ok(world)
     }
 
@@ -183,3 +440,774 @@ impl<'a> GameGenerator<'a> {
     // corresponds to a single phase of level generation. The submodule methods do not typically
     // interact with methods from other submodules. This is a loose guideline, not a hard rule.
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assets::{TextureId, SpriteManager};
+    use crate::components::{Animation, AnimationManager, BoundingBox, Breakable, Item, EnemyBehaviour, EnemySpawn, Merchant, Position, Ghost, Sprite, Stairs, WeaponKind};
+    use crate::systems::{SequentialDispatcher, Keyboard, build_dispatcher};
+    use crate::map_sprites::WallSpriteAlternate;
+    use crate::ui;
+
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    use specs::{Join, ReadExpect, ReadStorage};
+
+    pub(in super) fn test_generator(sprites: &MapSprites) -> GameGenerator<'_> {
+        let texture = TextureId::placeholder(0);
+        let animations = AnimationManager::standard_character_animations(30, texture, &mut SpriteManager::default());
+        GameGenerator {
+            attempts: 2000,
+            levels: 2,
+            rows: 40,
+            cols: 50,
+            tile_size: 16,
+            rooms: (6, 9).into(),
+            room_rows: (7, 14).into(),
+            room_cols: (8, 16).into(),
+            connection_style: ConnectionStyle::Overlap,
+            max_overlap: 0.35,
+            doors: (1, 3).into(),
+            challenge_rooms: (1, 2).into(),
+            challenge_rooms_start_level: 2,
+            next_prev_tiles: 2,
+            map_fragments: (1, 2).into(),
+            map_fragment_rooms: 2,
+            prisoner_chance: 0.15,
+            layout_transform_chance: 0.5,
+            cage_hits: 3,
+            prisoner_animations: animations.clone(),
+            merchant_chance: 0.5,
+            weapon_chest_chance: 0.5,
+            pillar_chance: 0.3,
+            props_per_room: (0, 3).into(),
+            breakables_per_room: (0, 2).into(),
+            breakable_drop_chance: 0.25,
+            arrow_shooter_chance: 0.1,
+            arrow_shooter_period: 60,
+            hazard_chance: 0.2,
+            room_enemies: (0, 5).into(),
+            max_room_enemy_area: 0.4,
+            max_nearby_enemies: 3,
+            enemy_spawn_probability: 0.8,
+            enemy_gold_chance: 0.5,
+            enemy_gold: (1, 5).into(),
+            tremor_frames: Some(900),
+            sprites,
+            enemy_config: EnemyConfig {
+                boss: Some(BossConfig {
+                    values: EnemyValues {
+                        behaviour: EnemyBehaviour::Random,
+                        animations: animations.clone(),
+                        attack: 8,
+                        speed: 2.0,
+                        health_points: 150,
+                        hit_wait: 20,
+                        bounding_box: BoundingBox::Full {width: 32, height: 32},
+                    },
+                    charge: ChargeValues {period: 120, windup: 30, speed: 8.0, distance: 8, bonus_damage: 6},
+                }),
+                rat: Some(EnemyValues {
+                    behaviour: EnemyBehaviour::Random,
+                    animations,
+                    attack: 5,
+                    speed: 3.0,
+                    health_points: 15,
+                    hit_wait: 12,
+                    bounding_box: BoundingBox::Full {width: 16, height: 16},
+                }),
+                scaling: EnemyScaling::default(),
+                levels: &[&[EnemyType::Rat], &[EnemyType::Rat]],
+            },
+        }
+    }
+
+    pub(in super) fn test_sprites() -> MapSprites {
+        MapSprites::from_dungeon_spritesheet(TextureId::placeholder(0), &mut SpriteManager::default())
+    }
+
+    fn setup_world<'b, 'c>() -> (LevelDispatcher<'b, 'c>, World) {
+        (LevelDispatcher::Watchdog(SequentialDispatcher::default()), World::new())
+    }
+
+    /// Same setup as the game so that every storage used in generation is registered
+    pub(in super) fn setup_game_world<'b, 'c>() -> (LevelDispatcher<'b, 'c>, World) {
+        let mut world = World::new();
+        let mut dispatcher = LevelDispatcher::Watchdog(
+            build_dispatcher(SequentialDispatcher::default(), Keyboard::default()));
+        dispatcher.setup(&mut world.res);
+        ui::setup(&mut world.res);
+        (dispatcher, world)
+    }
+
+    #[test]
+    fn generates_map() {
+        let sprites = test_sprites();
+        let game = test_generator(&sprites).generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        assert_eq!(game.levels.len(), 2);
+        assert_eq!(game.level_times.len(), 2);
+    }
+
+    #[test]
+    fn generates_transformed_map() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.layout_transform_chance = 1.0;
+        // Every later phase must still succeed on a mirrored or rotated layout
+        for _ in 0..5 {
+            generator.clone().generate(setup_game_world)
+                .expect("bug: should be able to generate a transformed map with a valid config");
+        }
+    }
+
+    #[test]
+    fn generates_map_with_corridors() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.connection_style = ConnectionStyle::Corridors;
+        for _ in 0..5 {
+            let game = generator.clone().generate(setup_game_world)
+                .expect("bug: should be able to generate a map with corridors with a valid config");
+
+            for level in &game.levels {
+                let map = level.world.read_resource::<FloorMap>();
+                // Rooms joined by corridors never overlap
+                for (id1, room1) in map.rooms() {
+                    for (id2, room2) in map.rooms() {
+                        assert!(id1 == id2 || !room1.boundary().has_intersection(*room2.boundary()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the tiles of every prop in the world. Props are the only ghosts without a
+    /// bounding box.
+    fn prop_tiles(world: &World) -> Vec<TilePos> {
+        let map = world.read_resource::<FloorMap>();
+        let (positions, ghosts, sprites, bounding_boxes) = world.system_data::<(
+            ReadStorage<'_, Position>, ReadStorage<'_, Ghost>, ReadStorage<'_, Sprite>, ReadStorage<'_, BoundingBox>,
+        )>();
+        (&positions, &ghosts, &sprites, !&bounding_boxes).join()
+            .map(|(&Position(pos), _, _, _)| map.world_to_tile_pos(pos))
+            .collect()
+    }
+
+    /// Returns the position of every entity in the world in a consistent order
+    fn entity_positions(world: &World) -> Vec<(i32, i32)> {
+        let mut positions: Vec<_> = world.read_storage::<Position>().join()
+            .map(|&Position(pos)| (pos.x(), pos.y()))
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    #[test]
+    fn decorations_stay_out_of_the_way() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.pillar_chance = 1.0;
+        generator.props_per_room = (2, 4).into();
+
+        let mut pillars = 0;
+        for _ in 0..3 {
+            let game = generator.clone().generate(setup_game_world)
+                .expect("bug: should be able to generate a decorated map with a valid config");
+
+            for level in &game.levels {
+                let map = level.world.read_resource::<FloorMap>();
+                let grid = map.grid();
+                let level_pillars: Vec<_> = grid.tile_positions()
+                    .filter(|&pos| grid.get(pos).is_wall()
+                        && grid.get(pos).wall_sprite().alt == WallSpriteAlternate::BrickPillar)
+                    .collect();
+                pillars += level_pillars.len();
+
+                // The tiles around each pillar can still reach one another, so no pillar ever
+                // splits up the level
+                for &pillar in &level_pillars {
+                    let adjacents: Vec<_> = grid.adjacent_positions(pillar).collect();
+                    assert!(adjacents.iter().all(|&adj| grid.get(adj).is_floor()));
+                    let reachable = grid.depth_first_search(adjacents[0], |_, adj| grid.get(adj).is_floor());
+                    assert!(adjacents.iter().all(|adj| reachable.contains(adj)));
+                }
+
+                // Props are never on top of anything placed before them (enemies come later) and
+                // never in the player start room
+                let props = prop_tiles(&level.world);
+                assert!(!props.is_empty());
+                let others: Vec<_> = {
+                    let (positions, spawns) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, EnemySpawn>)>();
+                    (&positions, !&spawns).join()
+                        .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+                        .collect()
+                };
+                for &pos in &props {
+                    assert_eq!(others.iter().filter(|&&other| other == pos).count(), 1,
+                        "bug: prop placed on top of another entity");
+                    let room_id = grid.get(pos).floor_room_id().expect("bug: prop not on a floor tile");
+                    assert!(map.room(room_id).can_contain_decorations());
+                }
+            }
+        }
+        assert!(pillars > 0);
+    }
+
+    #[test]
+    fn arrow_shooters_face_into_rooms() {
+        use crate::components::ArrowShooter;
+
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.arrow_shooter_chance = 1.0;
+
+        let game = generator.generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+        let mut shooters = 0;
+        for level in &game.levels {
+            let map = level.world.read_resource::<FloorMap>();
+            let (positions, arrow_shooters) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, ArrowShooter>)>();
+            for (&Position(pos), shooter) in (&positions, &arrow_shooters).join() {
+                shooters += 1;
+                let pos = map.world_to_tile_pos(pos);
+                assert!(map.grid().get(pos).is_wall());
+                assert!(map.room(shooter.room).can_generate_enemies());
+                let front = map.world_to_tile_pos(pos.center(map.tile_size() as i32) + shooter.direction.to_vector() * map.tile_size() as i32);
+                assert!(map.grid().get(front).is_room_floor(shooter.room), "shooter at {:?} does not face into its room", pos);
+            }
+        }
+        assert!(shooters > 0);
+    }
+
+    #[test]
+    fn treasure_key_placed_outside_locked_chamber() {
+        use crate::components::{Chest, Item, Door, Gate, Locked};
+
+        let sprites = test_sprites();
+        for &style in &[ConnectionStyle::Overlap, ConnectionStyle::Corridors] {
+            let mut generator = test_generator(&sprites);
+            generator.connection_style = style;
+
+            for _ in 0..4 {
+                let game = generator.clone().generate(setup_game_world)
+                    .expect("bug: should be able to generate a map with a valid config");
+                let level = game.levels.last().unwrap();
+                let map = level.world.read_resource::<FloorMap>();
+                let grid = map.grid();
+                let (positions, chests, doors, gates, locked) = level.world.system_data::<(
+                    ReadStorage<'_, Position>,
+                    ReadStorage<'_, Chest>,
+                    ReadStorage<'_, Door>,
+                    ReadStorage<'_, Gate>,
+                    ReadStorage<'_, Locked>,
+                )>();
+
+                let keys: Vec<_> = (&positions, &chests).join()
+                    .filter(|(_, chest)| **chest == Chest::Item(Item::TreasureKey))
+                    .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+                    .collect();
+                assert_eq!(keys.len(), 1, "the treasure key should be placed exactly once");
+                let key = keys[0];
+                let key_room = grid.get(key).floor_room_id().expect("bug: key not on a floor tile");
+                assert_eq!(map.room(key_room).room_type(), RoomType::Challenge);
+                let (chamber, _) = map.rooms().find(|(_, room)| room.is_treasure_chamber())
+                    .expect("bug: last level should have a treasure chamber");
+                assert_ne!(key_room, chamber, "the key is locked inside the chamber it unlocks");
+
+                // Every entrance of the chamber has a locked gate
+                let gate_tiles: Vec<_> = (&positions, &doors, &gates, &locked).join()
+                    .map(|(&Position(pos), _, _, _)| map.world_to_tile_pos(pos))
+                    .collect();
+                let entrances: Vec<_> = map.room(chamber).boundary().edge_positions()
+                    .filter(|&pos| grid.get(pos).is_floor())
+                    .collect();
+                assert!(!entrances.is_empty());
+                for entrance in &entrances {
+                    assert!(gate_tiles.contains(entrance), "entrance {:?} is not gated", entrance);
+                }
+
+                // The key can be reached without going through the chamber
+                let reachable = grid.depth_first_search(level.entrance(), |_, adj| grid.get(adj).is_floor()
+                    && !gate_tiles.contains(&adj) && !grid.get(adj).is_room_floor(chamber));
+                assert!(reachable.contains(&key), "key at {:?} cannot be reached", key);
+            }
+        }
+    }
+
+    #[test]
+    fn challenge_rooms_within_bounds() {
+        use rand::SeedableRng;
+
+        let sprites = test_sprites();
+        for &style in &[ConnectionStyle::Overlap, ConnectionStyle::Corridors] {
+            let mut generator = test_generator(&sprites);
+            generator.connection_style = style;
+            generator.levels = 3;
+            generator.enemy_config.levels = &[&[EnemyType::Rat], &[EnemyType::Rat], &[EnemyType::Rat]];
+            generator.challenge_rooms = (2, 3).into();
+            generator.challenge_rooms_start_level = 2;
+
+            for seed in 0..6 {
+                let key: MapKey = StdRng::seed_from_u64(seed).gen();
+                let game = generator.clone().generate_with_key(key, setup_game_world)
+                    .expect("bug: should be able to generate a map with a valid config");
+                for (i, level) in game.levels.iter().enumerate() {
+                    let map = level.world.read_resource::<FloorMap>();
+                    let challenge_rooms: Vec<_> = map.rooms()
+                        .filter(|(_, room)| room.room_type() == RoomType::Challenge)
+                        .map(|(id, _)| id)
+                        .collect();
+                    if i + 1 < generator.challenge_rooms_start_level {
+                        assert!(challenge_rooms.is_empty(), "key {} level {}", key, i + 1);
+                        continue;
+                    }
+                    assert!(challenge_rooms.len() >= generator.challenge_rooms.min
+                        && challenge_rooms.len() <= generator.challenge_rooms.max,
+                        "key {} level {}: {} challenge rooms", key, i + 1, challenge_rooms.len());
+
+                    // None of the challenge rooms contain a staircase
+                    let (positions, stairs) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
+                    for (&Position(pos), _) in (&positions, &stairs).join() {
+                        let room = map.grid().get(map.world_to_tile_pos(pos)).floor_room_id();
+                        assert!(!challenge_rooms.iter().any(|&id| Some(id) == room),
+                            "key {} level {}: staircase in a challenge room", key, i + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merchant_rooms_are_safe() {
+        use rand::SeedableRng;
+
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.merchant_chance = 1.0;
+
+        let mut merchant_rooms = 0;
+        for seed in 0..4 {
+            let key: MapKey = StdRng::seed_from_u64(seed).gen();
+            let game = generator.clone().generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            for (i, level) in game.levels.iter().enumerate() {
+                assert_eq!(check_level(level), &[], "key {} level {}", key, i + 1);
+
+                let map = level.world.read_resource::<FloorMap>();
+                let rooms: Vec<_> = map.rooms()
+                    .filter(|(_, room)| room.room_type() == RoomType::Merchant)
+                    .map(|(id, _)| id)
+                    .collect();
+                assert!(rooms.len() <= 1, "key {} level {}: {} merchant rooms", key, i + 1, rooms.len());
+                merchant_rooms += rooms.len();
+
+                let (positions, stairs, spawns, merchants) = level.world.system_data::<(
+                    ReadStorage<'_, Position>,
+                    ReadStorage<'_, Stairs>,
+                    ReadStorage<'_, EnemySpawn>,
+                    ReadStorage<'_, Merchant>,
+                )>();
+                let room_of = |pos| map.grid().get(map.world_to_tile_pos(pos)).floor_room_id();
+                let in_merchant_room = |pos| rooms.iter().any(|&id| Some(id) == room_of(pos));
+                // Neither staircases nor enemies end up in the merchant room
+                assert!(!(&positions, &stairs).join().any(|(&Position(pos), _)| in_merchant_room(pos)));
+                assert!(!(&positions, &spawns).join().any(|(&Position(pos), _)| in_merchant_room(pos)));
+                // Every merchant room has exactly one merchant and there are no merchants elsewhere
+                let merchant_positions: Vec<_> = (&positions, &merchants).join().map(|(&Position(pos), _)| pos).collect();
+                assert_eq!(merchant_positions.len(), rooms.len());
+                assert!(merchant_positions.into_iter().all(in_merchant_room));
+            }
+        }
+        assert!(merchant_rooms > 0);
+    }
+
+    #[test]
+    fn weapon_chests_hold_new_weapons() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.weapon_chest_chance = 1.0;
+
+        for seed in 0..4 {
+            let key: MapKey = StdRng::seed_from_u64(seed).gen();
+            let game = generator.clone().generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            for (i, level) in game.levels.iter().enumerate() {
+                let map = level.world.read_resource::<FloorMap>();
+                let (positions, chests) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Chest>)>();
+                let weapons: Vec<_> = (&positions, &chests).join()
+                    .filter_map(|(&Position(pos), chest)| match chest {
+                        &Chest::Item(Item::Weapon(kind)) => Some((pos, kind)),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(weapons.len(), 1, "key {} level {}", key, i + 1);
+
+                // Finding the weapon that the player starts with would be pointless
+                let (pos, kind) = weapons[0];
+                assert_ne!(kind, WeaponKind::default());
+                let room_id = map.grid().get(map.world_to_tile_pos(pos)).floor_room_id()
+                    .expect("bug: weapon chest not on a floor tile");
+                assert_eq!(map.room(room_id).room_type(), RoomType::Normal);
+            }
+        }
+    }
+
+    #[test]
+    fn same_key_generates_same_map() {
+        let sprites = test_sprites();
+        for &style in &[ConnectionStyle::Overlap, ConnectionStyle::Corridors] {
+            let mut generator = test_generator(&sprites);
+            generator.connection_style = style;
+
+            let key = random();
+            let game1 = generator.clone().generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            let game2 = generator.generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+
+            assert_eq!(game1.player_start, game2.player_start);
+            for (level1, level2) in game1.levels.iter().zip(&game2.levels) {
+                let map1 = level1.world.read_resource::<FloorMap>();
+                let map2 = level2.world.read_resource::<FloorMap>();
+                assert!(*map1 == *map2, "same key generated different {:?} maps:\n{}", style, map1.diff_ascii(&map2));
+                assert_eq!(entity_positions(&level1.world), entity_positions(&level2.world));
+            }
+        }
+    }
+
+    #[test]
+    fn enemies_scaled_to_their_level() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.room_enemies = (2, 5).into();
+        generator.enemy_config.scaling = EnemyScaling {attack_per_level: 0.2, health_per_level: 1.0, speed_per_level: 0.5};
+        let game = generator.generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        // The stats are decided when the spawn points are placed, so spawning the enemies later
+        // (e.g. when the level is entered again) cannot change them
+        let expected = [(5, 15, 3.0), (6, 30, 3.5)];
+        for (level, &expected) in game.levels.iter().zip(&expected) {
+            let spawns = level.world.read_storage::<EnemySpawn>();
+            assert!(spawns.join().count() > 0);
+            for EnemySpawn {enemy, ..} in spawns.join() {
+                assert_eq!((enemy.attack, enemy.health_points, enemy.speed), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn enemies_never_crowd_together() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        // As many enemies as possible so that overlapping rooms would crowd them together
+        generator.room_enemies = (5, 10).into();
+        generator.max_room_enemy_area = 1.0;
+        generator.max_nearby_enemies = 2;
+
+        // A window this size can be split into 4 parts where every tile is within
+        // ENEMY_DENSITY_RADIUS of every other tile in the same part
+        let window = 5;
+        let max_window_spawns = 4 * generator.max_nearby_enemies;
+        assert!(window - 1 <= 2 * enemies::ENEMY_DENSITY_RADIUS);
+
+        let mut total_spawns = 0;
+        for seed in 0..50 {
+            let key: MapKey = StdRng::seed_from_u64(seed).gen();
+            let game = generator.clone().generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            for (i, level) in game.levels.iter().enumerate() {
+                let map = level.world.read_resource::<FloorMap>();
+                let (positions, spawns) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, EnemySpawn>)>();
+                let spawn_tiles: HashSet<_> = (&positions, &spawns).join()
+                    .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+                    .collect();
+                total_spawns += spawn_tiles.len();
+
+                let grid = map.grid();
+                for row in 0..=grid.rows_len() - window {
+                    for col in 0..=grid.cols_len() - window {
+                        let count = (row..row + window)
+                            .flat_map(|row| (col..col + window).map(move |col| TilePos {row, col}))
+                            .filter(|pos| spawn_tiles.contains(pos))
+                            .count();
+                        assert!(count <= max_window_spawns,
+                            "key {} level {}: {} spawns in the window at ({}, {})", key, i + 1, count, row, col);
+                    }
+                }
+            }
+        }
+        assert!(total_spawns > 0);
+    }
+
+    /// Returns the position and drop of every breakable in the world in a consistent order
+    fn breakable_drops(world: &World) -> Vec<((i32, i32), Option<Item>)> {
+        let (positions, breakables) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Breakable>)>();
+        let mut drops: Vec<_> = (&positions, &breakables).join()
+            .map(|(&Position(pos), breakable)| ((pos.x(), pos.y()), breakable.drops.clone()))
+            .collect();
+        drops.sort_by_key(|&(pos, _)| pos);
+        drops
+    }
+
+    #[test]
+    fn same_key_generates_same_drops() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.breakables_per_room = (2, 4).into();
+        generator.breakable_drop_chance = 0.5;
+
+        let key = random();
+        let game1 = generator.clone().generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+        let game2 = generator.generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        for (level1, level2) in game1.levels.iter().zip(&game2.levels) {
+            let drops = breakable_drops(&level1.world);
+            assert!(!drops.is_empty());
+            assert_eq!(drops, breakable_drops(&level2.world));
+        }
+    }
+
+    #[test]
+    fn uids_resolve_after_regenerating() {
+        let sprites = test_sprites();
+        let generator = test_generator(&sprites);
+        let key = random();
+        let game1 = generator.clone().generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+        let game2 = generator.generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        for (i, (level1, level2)) in game1.levels.iter().zip(&game2.levels).enumerate() {
+            let (map1, uids1, positions1) = level1.world.system_data::<(ReadExpect<'_, FloorMap>, ReadExpect<'_, LevelUids>, ReadStorage<'_, Position>)>();
+            let (map2, uids2, positions2) = level2.world.system_data::<(ReadExpect<'_, FloorMap>, ReadExpect<'_, LevelUids>, ReadStorage<'_, Position>)>();
+            assert_eq!(uids1.level(), i + 1);
+
+            // Every room has its own uid and that uid refers to the same room after regenerating
+            let room_uids: HashSet<_> = map1.rooms().map(|(room_id, _)| uids1.room_uid(room_id)).collect();
+            assert_eq!(room_uids.len(), map1.nrooms());
+            for (room_id, room) in map1.rooms() {
+                let uid = uids1.room_uid(room_id);
+                assert_eq!(uids1.room(uid), Some(room_id));
+                let other = uids2.room(uid).expect("bug: room uid did not resolve after regenerating");
+                assert_eq!(map2.room(other), room);
+            }
+
+            // Same for every staircase
+            let stairs = level1.world.read_storage::<Stairs>();
+            let mut nstairs = 0;
+            for (entity, _) in (&level1.world.entities(), &stairs).join() {
+                let uid = uids1.stairs_uid(entity).expect("bug: staircase did not have a uid");
+                assert_eq!(uids1.stairs(uid), Some(entity));
+                let other = uids2.stairs(uid).expect("bug: stairs uid did not resolve after regenerating");
+                assert_eq!(positions1.get(entity).map(|pos| pos.0), positions2.get(other).map(|pos| pos.0));
+                nstairs += 1;
+            }
+            assert!(nstairs > 0);
+        }
+    }
+
+    #[test]
+    fn stats_reach_every_staircase() {
+        let sprites = test_sprites();
+        let generator = test_generator(&sprites);
+        let next_prev_tiles = generator.next_prev_tiles;
+        let game = generator.generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        let stats: Vec<_> = game.levels.iter().map(GenLevel::stats).collect();
+        assert_eq!(stats[0].next_level_paths.len(), next_prev_tiles);
+        // There is no next level after the last level
+        assert!(stats[stats.len() - 1].next_level_paths.is_empty());
+        for level in &stats {
+            assert!(level.next_level_paths.iter().all(|path| path.is_some()), "unreachable staircase: {:?}", level);
+            assert!(level.reachable_floor > 0.5 && level.reachable_floor <= 1.0);
+            assert!(level.room_area.min <= level.room_area.median && level.room_area.median <= level.room_area.max);
+        }
+    }
+
+    #[test]
+    fn reports_progress() {
+        let sprites = test_sprites();
+        let progress = Mutex::new(Vec::new());
+        let game = test_generator(&sprites).generate_with_progress(random(), setup_game_world, |update| {
+            progress.lock().unwrap().push(update);
+        }).expect("bug: should be able to generate a map with a valid config");
+
+        // Only the phases of the levels of the final try matter
+        let progress = progress.into_inner().unwrap();
+        let phases = ["rooms", "doorways", "layout", "staircases", "map fragments", "prisoners", "merchant", "weapon chest", "treasure chamber", "sprites", "decorations", "hazards", "enemies", "reachability"];
+        for level in 1..=2 {
+            let updates: Vec<_> = progress.iter().filter(|update| update.level == level).collect();
+            let last_try = &updates[updates.len() - phases.len()..];
+            assert_eq!(last_try.iter().map(|update| update.phase).collect::<Vec<_>>(), phases);
+            assert!(last_try.windows(2).all(|pair| pair[0].elapsed <= pair[1].elapsed));
+            assert!(last_try.last().unwrap().elapsed <= game.level_times[level - 1]);
+        }
+    }
+
+    #[test]
+    fn logs_each_phase() {
+        // Make sure the logger is installed before anything is generated
+        crate::test_helpers::captured_logs("");
+
+        let sprites = test_sprites();
+        let key: MapKey = random();
+        let game = test_generator(&sprites).generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        // Only the phases of the levels of the final try matter
+        let phases = ["rooms", "doorways", "layout", "staircases", "map fragments", "prisoners", "merchant", "weapon chest", "treasure chamber", "sprites", "decorations", "hazards", "enemies", "reachability"];
+        let logs = crate::test_helpers::captured_logs(&format!("key={} ", key));
+        for level in 1..=game.levels.len() {
+            let entries: Vec<_> = logs.iter().filter(|log| log.contains(&format!(" level={} phase=", level))).collect();
+            let last_try = &entries[entries.len() - phases.len()..];
+            for (entry, phase) in last_try.iter().zip(&phases) {
+                assert!(entry.contains(&format!("phase={:?} ", phase)), "expected phase {} in: {}", phase, entry);
+                assert!(entry.contains(" rooms=") && entry.contains(" entities="), "missing counts in: {}", entry);
+            }
+        }
+
+        // The map is split across several entries
+        let map = game.levels[0].world.read_resource::<FloorMap>();
+        let label = format!("map of {}", key);
+        log_map(&map, log::Level::Debug, &label);
+        let chunks = crate::test_helpers::captured_logs(&label);
+        assert_eq!(chunks.len(), map.grid().rows_len().div_ceil(LOG_MAP_CHUNK_ROWS));
+        assert!(chunks.iter().all(|chunk| chunk.lines().count() <= LOG_MAP_CHUNK_ROWS + 1));
+    }
+
+    #[test]
+    fn valid_config() {
+        let sprites = test_sprites();
+        assert_eq!(test_generator(&sprites).validate_config(), Ok(()));
+    }
+
+    #[test]
+    fn impossible_configs() {
+        let sprites = test_sprites();
+
+        let mut generator = test_generator(&sprites);
+        generator.next_prev_tiles = 10;
+        assert_eq!(generator.validate_config(),
+            Err(ConfigError::NotEnoughRooms {name: "next_prev_tiles", value: 10, max_rooms: 9}));
+
+        let mut generator = test_generator(&sprites);
+        generator.cols = 6;
+        assert_eq!(generator.validate_config(),
+            Err(ConfigError::RoomTooLarge {dimension: "cols", room_min: 8, map_size: 6}));
+
+        let mut generator = test_generator(&sprites);
+        generator.room_rows = (2, 14).into();
+        let err = generator.validate_config().unwrap_err();
+        assert_eq!(err, ConfigError::RoomTooSmall {dimension: "rows", room_min: 2, min_size: 3});
+        assert_eq!(err.to_string(),
+            "the minimum of `room_rows` is 2 but rooms must have at least 3 rows (including walls)");
+
+        let mut generator = test_generator(&sprites);
+        generator.room_cols = (1, 2).into();
+        assert_eq!(generator.validate_config(),
+            Err(ConfigError::RoomTooSmall {dimension: "cols", room_min: 1, min_size: 3}));
+
+        let mut generator = test_generator(&sprites);
+        generator.rooms = Bounds {min: 5, max: 3};
+        assert_eq!(generator.validate_config(), Err(ConfigError::InvertedBounds {name: "rooms"}));
+
+        let mut generator = test_generator(&sprites);
+        generator.doors = (0, 3).into();
+        assert_eq!(generator.validate_config(), Err(ConfigError::NoDoors));
+
+        let mut generator = test_generator(&sprites);
+        generator.levels = 3;
+        assert_eq!(generator.validate_config(),
+            Err(ConfigError::EnemyLevelsMismatch {levels: 3, enemy_levels: 2}));
+
+        let mut generator = test_generator(&sprites);
+        generator.enemy_config.rat = None;
+        assert_eq!(generator.validate_config(),
+            Err(ConfigError::MissingEnemyValues {enemy: EnemyType::Rat}));
+
+        for &tile_size in &[0, 4, 12, 72] {
+            let mut generator = test_generator(&sprites);
+            generator.tile_size = tile_size;
+            assert_eq!(generator.validate_config(), Err(ConfigError::UnsupportedTileSize {tile_size}));
+        }
+        for &tile_size in &[8, 24, 32, 64] {
+            let mut generator = test_generator(&sprites);
+            generator.tile_size = tile_size;
+            assert_eq!(generator.validate_config(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn generate_reports_invalid_config() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.prisoner_chance = 1.5;
+
+        match generator.generate(setup_world) {
+            Err(GenerateError::InvalidConfig(ConfigError::InvalidProbability {name, ..})) => {
+                assert_eq!(name, "prisoner_chance");
+            },
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("should not have generated a map"),
+        }
+    }
+
+    #[test]
+    fn generate_reports_phase_out_of_attempts() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        // Not enough attempts to generate more than a single room
+        generator.attempts = 1;
+
+        match generator.generate(setup_world) {
+            Err(GenerateError::RanOutOfAttempts {tries, last_failure, config, ..}) => {
+                assert_eq!(tries, MAX_TRIES);
+                assert_eq!(last_failure.phase, GenPhase::Rooms);
+                assert_eq!(last_failure.attempts, 2);
+                assert!(config.iter().any(|&(name, _)| name == "room_rows"));
+            },
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("should not have generated a map"),
+        }
+    }
+
+    #[test]
+    fn one_torch_entity_per_torch_wall() {
+        use rand::SeedableRng;
+
+        let sprites = test_sprites();
+        let generator = test_generator(&sprites);
+        let key: MapKey = StdRng::seed_from_u64(3).gen();
+        let game = generator.generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        for level in &game.levels {
+            let map = level.world.read_resource::<FloorMap>();
+            let grid = map.grid();
+            let mut torch_walls: Vec<_> = grid.tile_positions()
+                .filter(|&pos| grid.get(pos).is_wall() && grid.get(pos).wall_sprite().alt == WallSpriteAlternate::TorchLit)
+                .collect();
+            torch_walls.sort();
+            assert!(!torch_walls.is_empty());
+
+            // Torches are animated by the same system as everything else, so the map itself never
+            // needs to change while the level is played
+            let (positions, animations) = level.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Animation>)>();
+            let mut torches: Vec<_> = (&positions, &animations).join()
+                .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+                .filter(|&pos| grid.get(pos).is_wall())
+                .collect();
+            torches.sort();
+            assert_eq!(torches, torch_walls);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use sdl2::rect::Rect;
+use rand::{rngs::StdRng, seq::SliceRandom};
+use specs::{World, Builder, Join, Entities, ReadStorage, WriteStorage};
+
+use super::{GameGenerator, RanOutOfAttempts, GenPhase};
+use crate::components::{Position, BossSpawn, Gate, BossSeal};
+use crate::map::*;
+
+/// Returns the rooms that enemies can be generated in that are just outside of an entrance to the
+/// treasure chamber, in the order that the entrances are found
+fn guard_rooms(map: &FloorMap) -> Vec<RoomId> {
+    let (chamber_id, chamber) = match map.rooms().find(|(_, room)| room.is_treasure_chamber()) {
+        Some((id, room)) => (id, *room.boundary()),
+        None => return Vec::new(),
+    };
+
+    let grid = map.grid();
+    let mut rooms = Vec::new();
+    for edge in chamber.edge_positions().filter(|&edge| grid.get(edge).is_floor()) {
+        // The entrance itself may be part of either room, so the tiles on both sides are checked
+        let sides = Some(edge).into_iter().chain(grid.adjacent_positions(edge));
+        for room_id in sides.filter_map(|pos| grid.get(pos).floor_room_id()) {
+            if room_id != chamber_id && map.room(room_id).can_generate_enemies() && !rooms.contains(&room_id) {
+                rooms.push(room_id);
+            }
+        }
+    }
+    rooms
+}
+
+impl<'a> GameGenerator<'a> {
+    /// Places the boss in a room just outside of the treasure chamber (if there is one on this
+    /// level) and seals every gate of the chamber until the boss is defeated. If no room beside the
+    /// chamber can have enemies, there is no boss and the gates only need the treasure key.
+    pub(in super) fn place_boss(&self,
+        rng: &mut StdRng,
+        map: &FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // No system uses boss spawn points, so their storage may not have been registered yet
+        world.register::<BossSpawn>();
+        world.register::<BossSeal>();
+
+        let boss = match &self.enemy_config.boss {
+            Some(boss) => boss,
+            None => return Ok(()),
+        };
+        let room_id = match guard_rooms(map).choose(rng) {
+            Some(&room_id) => room_id,
+            None => {
+                debug!("No room beside the treasure chamber can have a boss");
+                return Ok(());
+            },
+        };
+
+        let tile_size = self.tile_size as i32;
+        let bounds = boss.values.bounding_box.scale_to_tile_size(self.tile_size);
+        let grid = map.grid();
+        let mut attempts = 0;
+        let pos = loop {
+            if attempts > self.attempts {
+                return Err(RanOutOfAttempts {phase: GenPhase::Boss, attempts});
+            }
+            attempts += 1;
+
+            let pos = map.room(room_id).boundary().random_inner_tile(rng).center(tile_size);
+            // Leaves a tile of open floor all the way around the boss so that it does not start
+            // out stuck against a wall, a pillar, or an entrance
+            let area = bounds.to_full_rect(pos);
+            let area = Rect::new(area.x() - tile_size, area.y() - tile_size,
+                area.width() + 2 * tile_size as u32, area.height() + 2 * tile_size as u32);
+            let is_clear = map.tiles_within(area).all(|(_, pos, tile)| {
+                tile.is_room_floor(room_id) && !tile.is_hazard() && !grid.is_room_entrance(pos)
+            });
+            if is_clear {
+                break pos;
+            }
+        };
+
+        // The boss is only spawned once the player enters the level
+        world.create_entity()
+            .with(Position(pos))
+            .with(BossSpawn {boss: boss.clone()})
+            .build();
+
+        let (entities, gates, mut seals) = world.system_data::<(Entities<'_>, ReadStorage<'_, Gate>, WriteStorage<'_, BossSeal>)>();
+        for (gate, _) in (&entities, &gates).join() {
+            seals.insert(gate, BossSeal)
+                .expect("bug: unable to seal gate");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::random;
+
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+
+    #[test]
+    fn boss_guards_treasure_chamber() {
+        let sprites = test_sprites();
+        for _ in 0..5 {
+            let key = random();
+            let game = test_generator(&sprites).generate_with_key(key, setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            let level = game.levels.last().unwrap();
+            let map = level.world.read_resource::<FloorMap>();
+            let guards = guard_rooms(&map);
+            assert!(!guards.is_empty());
+
+            let (positions, spawns, gates, seals) = level.world.system_data::<(
+                ReadStorage<'_, Position>,
+                ReadStorage<'_, BossSpawn>,
+                ReadStorage<'_, Gate>,
+                ReadStorage<'_, BossSeal>,
+            )>();
+            let boss_rooms: Vec<_> = (&positions, &spawns).join()
+                .map(|(&Position(pos), _)| map.grid().get(map.world_to_tile_pos(pos)).floor_room_id())
+                .collect();
+            assert_eq!(boss_rooms.len(), 1, "key {}: expected exactly one boss", key);
+            assert!(guards.contains(&boss_rooms[0].unwrap()), "key {}: boss is not beside the treasure chamber", key);
+
+            // Every gate is sealed until the boss is defeated
+            assert!((&gates).join().count() > 0);
+            assert_eq!((&gates).join().count(), (&gates, &seals).join().count());
+        }
+    }
+}
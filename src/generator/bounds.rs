@@ -1,27 +1,99 @@
-use std::ops::Add;
+use rand::{Rng, distributions::{Uniform, uniform::SampleUniform}};
 
-use rand::{Rng, distributions::{Distribution, Standard, uniform::SampleUniform}};
+use super::BoundsError;
 
 /// Represents the minimum and maximum boundary for a given type
-/// Both boundaries are inclusive
-#[derive(Debug, Clone)]
+/// Both boundaries are inclusive, so bounds with `min == max` only ever contain that single value.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Bounds<T> {
     pub min: T,
     pub max: T,
 }
 
+impl<T: PartialOrd> Bounds<T> {
+    /// Creates new bounds, failing if the minimum is larger than the maximum
+    pub fn new(min: T, max: T) -> Result<Self, BoundsError> {
+        if min > max {
+            return Err(BoundsError);
+        }
+        Ok(Bounds {min, max})
+    }
+
+    /// Returns true if the given value is between the minimum and maximum (inclusive)
+    pub fn contains(&self, value: T) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+impl<T: PartialOrd + Copy> Bounds<T> {
+    /// Returns the closest value to the given value that is within these bounds
+    pub fn clamp(&self, value: T) -> T {
+        if value < self.min {
+            self.min
+        } else if value > self.max {
+            self.max
+        } else {
+            value
+        }
+    }
+}
+
 impl<T: PartialOrd + SampleUniform + Copy> Bounds<T> {
-    pub fn gen<R: Rng>(&self, rng: &mut R) -> T
-        where Standard: Distribution<T>,
-              T: Add<Output=T> + From<u8> {
-        // Need to add 1 for this to be an inclusive range. These fancy type bounds allow for that.
-        // From<u8> was chosen because a lot of types support From<u8>.
-        rng.gen_range(self.min, self.max + 1.into())
+    /// Generates a random value between the minimum and maximum (inclusive)
+    ///
+    /// Panics if the minimum is larger than the maximum
+    pub fn gen<R: Rng>(&self, rng: &mut R) -> T {
+        // An inclusive distribution is used instead of adding 1 to the maximum so that this
+        // still works when the maximum is the largest value of its type
+        rng.sample(Uniform::new_inclusive(self.min, self.max))
     }
 }
 
-impl<T> From<(T, T)> for Bounds<T> {
+impl<T: PartialOrd> From<(T, T)> for Bounds<T> {
     fn from((min, max): (T, T)) -> Self {
+        debug_assert!(min <= max, "bug: the minimum of bounds should not be larger than the maximum");
         Bounds {min, max}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn generates_values_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(6);
+
+        let single = Bounds::new(4usize, 4).unwrap();
+        assert!((0..20).all(|_| single.gen(&mut rng) == 4));
+
+        // Extreme values must not overflow
+        let largest = Bounds::new(usize::MAX - 1, usize::MAX).unwrap();
+        assert!((0..20).all(|_| largest.gen(&mut rng) >= usize::MAX - 1));
+        let everything = Bounds::new(u8::MIN, u8::MAX).unwrap();
+        for _ in 0..20 {
+            everything.gen(&mut rng);
+        }
+        assert_eq!(Bounds::new(u32::MAX, u32::MAX).unwrap().gen(&mut rng), u32::MAX);
+    }
+
+    #[test]
+    fn inverted_bounds_rejected() {
+        assert_eq!(Bounds::new(5, 3), Err(BoundsError));
+        assert_eq!(Bounds::new(3, 5), Ok(Bounds {min: 3, max: 5}));
+    }
+
+    #[test]
+    fn contains_and_clamp_are_inclusive() {
+        let bounds = Bounds::new(2, 6).unwrap();
+        assert!(bounds.contains(2) && bounds.contains(6));
+        assert!(!bounds.contains(1) && !bounds.contains(7));
+        assert_eq!((bounds.clamp(0), bounds.clamp(4), bounds.clamp(9)), (2, 4, 6));
+
+        let single = Bounds::new(usize::MAX, usize::MAX).unwrap();
+        assert!(single.contains(usize::MAX));
+        assert_eq!(single.clamp(0), usize::MAX);
+    }
+}
@@ -0,0 +1,521 @@
+//! The tuning values of the generator that can be changed without recompiling
+
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::path::Path;
+use std::collections::HashMap;
+
+use crate::components::{AnimationManager, BoundingBox, EnemyBehaviour};
+use crate::map_sprites::MapSprites;
+
+use super::{GameGenerator, Bounds, ConnectionStyle, EnemyConfig, EnemyValues, EnemyType, EnemyScaling, BossConfig, ChargeValues};
+
+/// Allowed enemies on each level. Not configurable from a file.
+pub const ENEMY_LEVELS: &[&[EnemyType]] = {
+    use self::EnemyType::*;
+    &[
+        // Level 1
+        &[Rat],
+        // Level 2
+        &[Rat],
+        // Level 3
+        &[Rat],
+        // Level 4
+        &[Rat],
+        // Level 5
+        &[Rat],
+        // Level 6
+        &[Rat],
+        // Level 7
+        &[Rat],
+        // Level 8
+        &[Rat],
+        // Level 9
+        &[Rat],
+        // Level 10
+        &[Rat],
+    ]
+};
+
+/// The values for one enemy except for its animations
+///
+/// The speed and bounding box are measured at NATIVE_TILE_SIZE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnemyStats {
+    pub behaviour: EnemyBehaviour,
+    pub attack: usize, // HP
+    pub speed: f32, // px/frame
+    pub health_points: usize, // HP
+    pub hit_wait: usize, // frames
+    pub bounding_box: BoundingBox,
+}
+
+impl EnemyStats {
+    fn with_animations(self, animations: AnimationManager) -> EnemyValues {
+        let EnemyStats {behaviour, attack, speed, health_points, hit_wait, bounding_box} = self;
+        EnemyValues {behaviour, animations, attack, speed, health_points, hit_wait, bounding_box}
+    }
+}
+
+/// The animations used by the generator. These come from spritesheets, not from the config.
+#[derive(Clone)]
+pub struct GeneratorAnimations {
+    /// The animations of a prisoner once they have been freed
+    pub prisoner: AnimationManager,
+    /// The animations of a rat, if its spritesheet was loaded
+    pub rat: Option<AnimationManager>,
+}
+
+/// Every value of a `GameGenerator` other than its sprites and animations. See the fields of
+/// `GameGenerator` for what each value means.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub attempts: usize,
+    pub levels: usize,
+    pub rows: usize,
+    pub cols: usize,
+    pub tile_size: u32,
+    pub rooms: Bounds<usize>,
+    pub room_rows: Bounds<usize>,
+    pub room_cols: Bounds<usize>,
+    pub connection_style: ConnectionStyle,
+    pub max_overlap: f64,
+    pub doors: Bounds<usize>,
+    pub challenge_rooms: Bounds<usize>,
+    pub challenge_rooms_start_level: usize,
+    pub next_prev_tiles: usize,
+    pub map_fragments: Bounds<usize>,
+    pub map_fragment_rooms: usize,
+    pub prisoner_chance: f64,
+    pub layout_transform_chance: f64,
+    pub cage_hits: usize,
+    pub merchant_chance: f64,
+    pub weapon_chest_chance: f64,
+    pub pillar_chance: f64,
+    pub props_per_room: Bounds<usize>,
+    pub breakables_per_room: Bounds<usize>,
+    pub breakable_drop_chance: f64,
+    pub arrow_shooter_chance: f64,
+    pub arrow_shooter_period: usize,
+    pub hazard_chance: f64,
+    pub room_enemies: Bounds<usize>,
+    pub max_room_enemy_area: f64,
+    pub max_nearby_enemies: usize,
+    pub enemy_spawn_probability: f64,
+    pub enemy_gold_chance: f64,
+    pub enemy_gold: Bounds<u32>,
+    pub tremor_frames: Option<usize>,
+    pub rat: EnemyStats,
+    pub boss: EnemyStats,
+    pub boss_charge: ChargeValues,
+    pub enemy_scaling: EnemyScaling,
+    /// The choices for enemies to be generated on each level
+    pub enemy_levels: &'static [&'static [EnemyType]],
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 2000,
+            levels: 10,
+            rows: 40,
+            cols: 50,
+            tile_size: 16,
+            rooms: (6, 9).into(),
+            room_rows: (7, 14).into(),
+            room_cols: (8, 16).into(),
+            connection_style: ConnectionStyle::Overlap,
+            max_overlap: 0.35,
+            doors: (1, 3).into(),
+            challenge_rooms: (1, 2).into(),
+            challenge_rooms_start_level: 3,
+            next_prev_tiles: 2,
+            map_fragments: (1, 2).into(),
+            map_fragment_rooms: 2,
+            prisoner_chance: 0.15,
+            layout_transform_chance: 0.5,
+            cage_hits: 3,
+            merchant_chance: 0.15,
+            weapon_chest_chance: 0.25,
+            pillar_chance: 0.3,
+            props_per_room: (0, 3).into(),
+            breakables_per_room: (0, 2).into(),
+            breakable_drop_chance: 0.25,
+            arrow_shooter_chance: 0.1,
+            // About 2 seconds
+            arrow_shooter_period: 60,
+            hazard_chance: 0.2,
+            room_enemies: (0, 5).into(),
+            max_room_enemy_area: 0.4,
+            max_nearby_enemies: 3,
+            enemy_spawn_probability: 0.8,
+            enemy_gold_chance: 0.5,
+            enemy_gold: (1, 5).into(),
+            // About 90 seconds
+            tremor_frames: Some(2700),
+            rat: EnemyStats {
+                behaviour: EnemyBehaviour::Random,
+                attack: 5,
+                speed: 3.0,
+                health_points: 15,
+                hit_wait: 12,
+                bounding_box: BoundingBox::Full {width: 16, height: 16},
+            },
+            boss: EnemyStats {
+                behaviour: EnemyBehaviour::Random,
+                attack: 8,
+                speed: 2.0,
+                health_points: 150,
+                hit_wait: 20,
+                bounding_box: BoundingBox::Full {width: 32, height: 32},
+            },
+            boss_charge: ChargeValues {
+                // About 4 seconds
+                period: 120,
+                // About 1 second
+                windup: 30,
+                speed: 8.0,
+                distance: 8,
+                bonus_damage: 6,
+            },
+            enemy_scaling: EnemyScaling {
+                attack_per_level: 0.1,
+                health_per_level: 0.15,
+                // Slow enough that enemies on the last level are still not much faster than the
+                // player
+                speed_per_level: 0.02,
+            },
+            enemy_levels: ENEMY_LEVELS,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    /// Loads the config from the given file. Unlike the settings, a file that does not exist is an
+    /// error so that the caller can decide what to fall back to.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the config from a small subset of TOML: one `key = value` per line where each value
+    /// is an integer, a float, a string, `false`, or a `[min, max]` pair for bounds. Comments
+    /// start with `#`. The values of an enemy are prefixed with its name (e.g. `rat.attack`).
+    ///
+    /// Every value must be present. Values are only checked for having the right type here. The
+    /// rest of the checks happen in `GameGenerator::validate_config`.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let mut fields = Fields::parse(contents)?;
+        let config = Self {
+            attempts: fields.number("attempts")?,
+            levels: fields.number("levels")?,
+            rows: fields.number("rows")?,
+            cols: fields.number("cols")?,
+            tile_size: fields.number("tile_size")?,
+            rooms: fields.bounds("rooms")?,
+            room_rows: fields.bounds("room_rows")?,
+            room_cols: fields.bounds("room_cols")?,
+            connection_style: match fields.string("connection_style")? {
+                "overlap" => ConnectionStyle::Overlap,
+                "corridors" => ConnectionStyle::Corridors,
+                _ => return Err(invalid_value("connection_style", "\"overlap\" or \"corridors\"")),
+            },
+            max_overlap: fields.number("max_overlap")?,
+            doors: fields.bounds("doors")?,
+            challenge_rooms: fields.bounds("challenge_rooms")?,
+            challenge_rooms_start_level: fields.number("challenge_rooms_start_level")?,
+            next_prev_tiles: fields.number("next_prev_tiles")?,
+            map_fragments: fields.bounds("map_fragments")?,
+            map_fragment_rooms: fields.number("map_fragment_rooms")?,
+            prisoner_chance: fields.number("prisoner_chance")?,
+            layout_transform_chance: fields.number("layout_transform_chance")?,
+            cage_hits: fields.number("cage_hits")?,
+            merchant_chance: fields.number("merchant_chance")?,
+            weapon_chest_chance: fields.number("weapon_chest_chance")?,
+            pillar_chance: fields.number("pillar_chance")?,
+            props_per_room: fields.bounds("props_per_room")?,
+            breakables_per_room: fields.bounds("breakables_per_room")?,
+            breakable_drop_chance: fields.number("breakable_drop_chance")?,
+            arrow_shooter_chance: fields.number("arrow_shooter_chance")?,
+            arrow_shooter_period: fields.number("arrow_shooter_period")?,
+            hazard_chance: fields.number("hazard_chance")?,
+            room_enemies: fields.bounds("room_enemies")?,
+            max_room_enemy_area: fields.number("max_room_enemy_area")?,
+            max_nearby_enemies: fields.number("max_nearby_enemies")?,
+            enemy_spawn_probability: fields.number("enemy_spawn_probability")?,
+            enemy_gold_chance: fields.number("enemy_gold_chance")?,
+            enemy_gold: fields.bounds("enemy_gold")?,
+            tremor_frames: match fields.take("tremor_frames")? {
+                "false" => None,
+                value => Some(value.parse().map_err(|_| invalid_value("tremor_frames", "a number of frames or `false`"))?),
+            },
+            rat: fields.enemy("rat")?,
+            boss: fields.enemy("boss")?,
+            boss_charge: fields.charge("boss")?,
+            enemy_scaling: EnemyScaling {
+                attack_per_level: fields.number("enemy_scaling.attack_per_level")?,
+                health_per_level: fields.number("enemy_scaling.health_per_level")?,
+                speed_per_level: fields.number("enemy_scaling.speed_per_level")?,
+            },
+            enemy_levels: ENEMY_LEVELS,
+        };
+        fields.finish()?;
+        Ok(config)
+    }
+}
+
+impl<'a> GameGenerator<'a> {
+    /// Creates a generator with the values from the given config
+    pub fn from_config(config: GeneratorConfig, sprites: &'a MapSprites, animations: GeneratorAnimations) -> Self {
+        let GeneratorConfig {
+            attempts, levels, rows, cols, tile_size, rooms, room_rows, room_cols, connection_style,
+            max_overlap, doors, challenge_rooms, challenge_rooms_start_level, next_prev_tiles, map_fragments, map_fragment_rooms, prisoner_chance,
+            layout_transform_chance, cage_hits, merchant_chance, weapon_chest_chance, pillar_chance, props_per_room, breakables_per_room,
+            breakable_drop_chance, arrow_shooter_chance, arrow_shooter_period, hazard_chance, room_enemies, max_room_enemy_area, enemy_spawn_probability,
+            max_nearby_enemies, enemy_gold_chance, enemy_gold, tremor_frames, rat, boss, boss_charge, enemy_scaling, enemy_levels,
+        } = config;
+        let GeneratorAnimations {prisoner, rat: rat_animations} = animations;
+
+        GameGenerator {
+            attempts,
+            levels,
+            rows,
+            cols,
+            tile_size,
+            rooms,
+            room_rows,
+            room_cols,
+            connection_style,
+            max_overlap,
+            doors,
+            challenge_rooms,
+            challenge_rooms_start_level,
+            next_prev_tiles,
+            map_fragments,
+            map_fragment_rooms,
+            prisoner_chance,
+            layout_transform_chance,
+            cage_hits,
+            prisoner_animations: prisoner,
+            merchant_chance,
+            weapon_chest_chance,
+            pillar_chance,
+            props_per_room,
+            breakables_per_room,
+            breakable_drop_chance,
+            arrow_shooter_chance,
+            arrow_shooter_period,
+            hazard_chance,
+            room_enemies,
+            max_room_enemy_area,
+            max_nearby_enemies,
+            enemy_spawn_probability,
+            enemy_gold_chance,
+            enemy_gold,
+            tremor_frames,
+            sprites,
+            enemy_config: EnemyConfig {
+                // There is no spritesheet for the boss yet, so it uses the animations of a rat
+                boss: rat_animations.clone().map(|animations| BossConfig {
+                    values: boss.with_animations(animations),
+                    charge: boss_charge,
+                }),
+                rat: rat_animations.map(|animations| rat.with_animations(animations)),
+                scaling: enemy_scaling,
+                levels: enemy_levels,
+            },
+        }
+    }
+}
+
+fn invalid_value(name: &str, expected: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("`{}` must be {}", name, expected))
+}
+
+/// The unparsed values of a config file by key. Each value is removed once it is used so that
+/// any keys left over can be reported.
+struct Fields<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(contents: &'a str) -> io::Result<Self> {
+        let mut values = HashMap::new();
+        for line in contents.lines() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.is_empty() && !value.is_empty() => {
+                    if values.insert(key, value).is_some() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("`{}` is set more than once", key)));
+                    }
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("invalid line in generator config: `{}`", line))),
+            }
+        }
+        Ok(Self {values})
+    }
+
+    /// Returns the unparsed value of the given key and uses it up
+    fn take(&mut self, name: &str) -> io::Result<&'a str> {
+        self.values.remove(name).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("missing field `{}`", name)))
+    }
+
+    fn number<T: FromStr>(&mut self, name: &str) -> io::Result<T> {
+        self.take(name)?.parse().map_err(|_| invalid_value(name, "a number of the right kind (e.g. not negative)"))
+    }
+
+    fn string(&mut self, name: &str) -> io::Result<&'a str> {
+        let value = self.take(name)?;
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            Ok(&value[1..value.len()-1])
+        } else {
+            Err(invalid_value(name, "a quoted string"))
+        }
+    }
+
+    /// Parses a `[first, second]` pair
+    fn pair<T: FromStr>(&mut self, name: &str) -> io::Result<(T, T)> {
+        let value = self.take(name)?;
+        let invalid = || invalid_value(name, "a pair of numbers like `[1, 2]`");
+        if !value.starts_with('[') || !value.ends_with(']') {
+            return Err(invalid());
+        }
+
+        let mut items = value[1..value.len()-1].split(',').map(str::trim);
+        match (items.next(), items.next(), items.next()) {
+            (Some(first), Some(second), None) => Ok((
+                first.parse().map_err(|_| invalid())?,
+                second.parse().map_err(|_| invalid())?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn bounds<T: FromStr + PartialOrd>(&mut self, name: &str) -> io::Result<Bounds<T>> {
+        let (min, max) = self.pair(name)?;
+        Bounds::new(min, max).map_err(|err| io::Error::new(io::ErrorKind::InvalidData,
+            format!("`{}` is invalid: {}", name, err)))
+    }
+
+    fn enemy(&mut self, enemy: &str) -> io::Result<EnemyStats> {
+        let field = |name| format!("{}.{}", enemy, name);
+        let (width, height) = self.pair(&field("bounding_box"))?;
+        Ok(EnemyStats {
+            behaviour: match self.string(&field("behaviour"))? {
+                "random" => EnemyBehaviour::Random,
+                _ => return Err(invalid_value(&field("behaviour"), "\"random\"")),
+            },
+            attack: self.number(&field("attack"))?,
+            speed: self.number(&field("speed"))?,
+            health_points: self.number(&field("health_points"))?,
+            hit_wait: self.number(&field("hit_wait"))?,
+            bounding_box: BoundingBox::Full {width, height},
+        })
+    }
+
+    fn charge(&mut self, enemy: &str) -> io::Result<ChargeValues> {
+        let field = |name| format!("{}.charge_{}", enemy, name);
+        Ok(ChargeValues {
+            period: self.number(&field("period"))?,
+            windup: self.number(&field("windup"))?,
+            speed: self.number(&field("speed"))?,
+            distance: self.number(&field("distance"))?,
+            bonus_damage: self.number(&field("bonus_damage"))?,
+        })
+    }
+
+    /// Fails if any of the values were never used
+    fn finish(self) -> io::Result<()> {
+        let mut unknown: Vec<_> = self.values.keys().cloned().collect();
+        unknown.sort();
+        match unknown.first() {
+            Some(key) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown field `{}`", key))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generator::ConfigError;
+    use crate::generator::tests::{test_generator, test_sprites};
+
+    fn animations() -> GeneratorAnimations {
+        let sprites = test_sprites();
+        let prisoner = test_generator(&sprites).prisoner_animations;
+        GeneratorAnimations {prisoner: prisoner.clone(), rat: Some(prisoner)}
+    }
+
+    #[test]
+    fn sample_config_matches_defaults() {
+        let config = GeneratorConfig::load("assets/generator.toml").unwrap();
+        assert_eq!(config, GeneratorConfig::default());
+
+        let sprites = test_sprites();
+        let generator = GameGenerator::from_config(config, &sprites, animations());
+        assert_eq!(generator.validate_config(), Ok(()));
+        assert_eq!(generator.enemy_config.rat.map(|rat| rat.attack), Some(5));
+    }
+
+    #[test]
+    fn missing_and_unknown_fields() {
+        let contents = fs::read_to_string("assets/generator.toml").unwrap();
+        let without = |key: &str| contents.lines()
+            .filter(|line| !line.starts_with(key))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let err = GeneratorConfig::parse(&without("max_overlap ")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "missing field `max_overlap`");
+        let err = GeneratorConfig::parse(&without("rat.hit_wait ")).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `rat.hit_wait`");
+
+        let err = GeneratorConfig::parse(&format!("{}\nmax_overlap = 0.5", contents)).unwrap_err();
+        assert_eq!(err.to_string(), "`max_overlap` is set more than once");
+        let err = GeneratorConfig::parse(&format!("{}\nbats = 3", contents)).unwrap_err();
+        assert_eq!(err.to_string(), "unknown field `bats`");
+        let err = GeneratorConfig::parse(&format!("{}\nrooms = [6, 9, 12]", without("rooms "))).unwrap_err();
+        assert_eq!(err.to_string(), "`rooms` must be a pair of numbers like `[1, 2]`");
+        let err = GeneratorConfig::parse(&format!("{}\nrooms = [12, 6]", without("rooms "))).unwrap_err();
+        assert_eq!(err.to_string(), "`rooms` is invalid: the minimum is larger than the maximum");
+        let err = GeneratorConfig::parse(&format!("{}\nattempts = -5", without("attempts "))).unwrap_err();
+        assert!(err.to_string().starts_with("`attempts` must be a number"));
+
+        let config = GeneratorConfig::parse(&format!("{}\ntremor_frames = false", without("tremor_frames "))).unwrap();
+        assert_eq!(config.tremor_frames, None);
+    }
+
+    #[test]
+    fn out_of_range_values_name_their_field() {
+        let sprites = test_sprites();
+        let check = |config: GeneratorConfig| {
+            GameGenerator::from_config(config, &sprites, animations()).validate_config()
+        };
+
+        let err = check(GeneratorConfig {max_overlap: -0.2, ..GeneratorConfig::default()}).unwrap_err();
+        assert_eq!(err, ConfigError::InvalidProbability {name: "max_overlap", value: -0.2});
+        assert_eq!(err.to_string(), "`max_overlap` is -0.2 but must be between 0.0 and 1.0");
+
+        let err = check(GeneratorConfig {room_rows: Bounds {min: 14, max: 7}, ..GeneratorConfig::default()}).unwrap_err();
+        assert_eq!(err.to_string(), "the minimum of `room_rows` is larger than its maximum");
+
+        let mut config = GeneratorConfig::default();
+        config.enemy_scaling.health_per_level = -0.5;
+        let err = check(config).unwrap_err();
+        assert_eq!(err.to_string(), "`enemy_scaling.health_per_level` is -0.5 but must not be negative");
+    }
+}
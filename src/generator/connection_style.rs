@@ -0,0 +1,10 @@
+/// How the rooms of a level are joined together so that every room can be reached
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStyle {
+    /// Rooms overlap one another and doorways are placed in the walls where they meet
+    #[default]
+    Overlap,
+    /// Rooms are spread apart and joined by narrow corridors. Doorways are placed where a
+    /// corridor reaches the wall of the room it leads to.
+    Corridors,
+}
@@ -0,0 +1,290 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use super::{RanOutOfAttempts, GenPhase};
+use super::doorways::{doorway_orientation, place_entrance_walls};
+use crate::map_sprites::{FloorSprite, WallSprite};
+use crate::components::DoorOrientation;
+use crate::map::*;
+
+/// The minimum number of empty tiles between the walls of two rooms that are joined by corridors
+pub(in super) const ROOM_SPACING: usize = 2;
+
+/// The extra cost of a corridor changing direction. Keeps corridors mostly straight instead of
+/// zig-zagging towards the room they lead to.
+const TURN_COST: usize = 4;
+
+/// The (row, col) offset of each direction that a corridor can travel in: north, east, south, west
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+
+/// A place on the wall of a room where a corridor can begin or end
+#[derive(Debug, Clone, Copy)]
+struct CorridorEnd {
+    /// The wall tile on the edge of the room
+    wall: TilePos,
+    /// The tile just outside of the wall
+    outside: TilePos,
+    /// The index into DIRECTIONS that points out of the room
+    dir: usize,
+}
+
+/// Returns the tile one step away in the given direction, if that tile is on the grid
+fn step(pos: TilePos, (drow, dcol): (isize, isize), GridSize {rows, cols}: GridSize) -> Option<TilePos> {
+    let row = pos.row as isize + drow;
+    let col = pos.col as isize + dcol;
+    if row < 0 || col < 0 || row as usize >= rows || col as usize >= cols {
+        return None;
+    }
+    Some(TilePos {row: row as usize, col: col as usize})
+}
+
+/// Returns the eight tiles around the given tile. The tile must not be on the edge of the grid.
+fn surrounding(pos: TilePos) -> impl Iterator<Item=TilePos> {
+    (pos.row-1..=pos.row+1)
+        .flat_map(move |row| (pos.col-1..=pos.col+1).map(move |col| TilePos {row, col}))
+        .filter(move |&adj| adj != pos)
+}
+
+/// Returns true if a corridor can pass through the given tile
+///
+/// Corridors only pass through empty tiles and never touch another floor tile, not even
+/// diagonally. That way a wall always separates a corridor from everything else and any wall
+/// between a corridor and a room has the corridor on one side and the room on the other.
+fn is_open(grid: &TileGrid, pos: TilePos) -> bool {
+    let GridSize {rows, cols} = grid.dimensions();
+    // Leave space for the walls of the corridor
+    if pos.row == 0 || pos.col == 0 || pos.row == rows - 1 || pos.col == cols - 1 {
+        return false;
+    }
+
+    grid.get(pos).is_empty() && surrounding(pos).all(|adj| !grid.get(adj).is_floor())
+}
+
+/// Returns every place on the walls of the given room where a corridor can begin or end
+///
+/// Tiles beside a corner are skipped so that a staircase in the corner of the room can never wall
+/// off the corridor. Walls beside an opening are skipped so that they can still become doorways.
+fn corridor_ends(grid: &TileGrid, room: TileRect) -> Vec<CorridorEnd> {
+    let tl = room.top_left();
+    let br = room.bottom_right();
+
+    room.edge_positions().filter_map(|wall| {
+        let dir = if wall.row == tl.row {
+            0
+        } else if wall.col == br.col {
+            1
+        } else if wall.row == br.row {
+            2
+        } else {
+            3
+        };
+
+        // Horizontal walls extend along columns, vertical walls extend along rows
+        let (along, first, last) = if dir % 2 == 0 {
+            (wall.col, tl.col, br.col)
+        } else {
+            (wall.row, tl.row, br.row)
+        };
+        if along < first + 2 || along + 2 > last {
+            return None;
+        }
+
+        let (before, after) = if dir % 2 == 0 {
+            (TilePos {col: wall.col - 1, ..wall}, TilePos {col: wall.col + 1, ..wall})
+        } else {
+            (TilePos {row: wall.row - 1, ..wall}, TilePos {row: wall.row + 1, ..wall})
+        };
+        if ![before, wall, after].iter().all(|&pos| grid.get(pos).is_wall()) {
+            return None;
+        }
+
+        let outside = step(wall, DIRECTIONS[dir], grid.dimensions())?;
+        if !is_open(grid, outside) {
+            return None;
+        }
+
+        Some(CorridorEnd {wall, outside, dir})
+    }).collect()
+}
+
+/// Finds the cheapest path through open tiles from just outside of one room to just outside of
+/// another. Returns where the path leaves the first room and every tile of the path in order.
+fn find_corridor(grid: &TileGrid, from: TileRect, to: TileRect) -> Option<(CorridorEnd, Vec<TilePos>)> {
+    let size = grid.dimensions();
+    let starts = corridor_ends(grid, from);
+    let goals: HashSet<_> = corridor_ends(grid, to).into_iter().map(|end| end.outside).collect();
+
+    // Each state of the search is a tile and the direction the corridor was going when it reached
+    // that tile. The direction is needed to charge for turns.
+    let mut costs = HashMap::new();
+    let mut came_from: HashMap<_, (TilePos, usize)> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    for start in &starts {
+        costs.insert((start.outside, start.dir), 0);
+        open.push(Reverse((0, start.outside, start.dir)));
+    }
+
+    while let Some(Reverse((cost, pos, dir))) = open.pop() {
+        // Already found a cheaper way to get here
+        if cost > costs[&(pos, dir)] {
+            continue;
+        }
+
+        if goals.contains(&pos) {
+            let mut path = vec![pos];
+            let mut state = (pos, dir);
+            while let Some(&prev) = came_from.get(&state) {
+                path.push(prev.0);
+                state = prev;
+            }
+            path.reverse();
+
+            let start = starts.iter().find(|start| start.outside == path[0])
+                .expect("bug: corridor did not begin outside of the room it leaves from");
+            return Some((*start, path));
+        }
+
+        for (next_dir, &offset) in DIRECTIONS.iter().enumerate() {
+            let next = match step(pos, offset, size) {
+                Some(next) if is_open(grid, next) => next,
+                _ => continue,
+            };
+
+            let turn_cost = if next_dir == dir { 0 } else { TURN_COST };
+            let next_cost = cost + 1 + turn_cost;
+            if costs.get(&(next, next_dir)).map(|&prev_cost| next_cost < prev_cost).unwrap_or(true) {
+                costs.insert((next, next_dir), next_cost);
+                came_from.insert((next, next_dir), (pos, dir));
+                open.push(Reverse((next_cost, next, next_dir)));
+            }
+        }
+    }
+
+    None
+}
+
+/// Chooses which pairs of rooms to join so that every room can be reached. Starting from the first
+/// room, the room closest to any room that was already joined is always joined next. This keeps
+/// corridors short so they do not get in the way of one another.
+fn plan_corridors(map: &FloorMap) -> Vec<(RoomId, RoomId)> {
+    let mut remaining: Vec<_> = map.rooms()
+        .map(|(room_id, room)| (room_id, room.boundary().center_tile()))
+        .collect();
+    if remaining.is_empty() {
+        return Vec::new();
+    }
+    let mut joined = vec![remaining.remove(0)];
+
+    let distance = |a: TilePos, b: TilePos| {
+        let (rows, cols) = a.difference(b);
+        rows.abs() + cols.abs()
+    };
+
+    let mut corridors = Vec::new();
+    while !remaining.is_empty() {
+        let (_, index, joined_id) = remaining.iter().enumerate()
+            .flat_map(|(index, &(_, center))| joined.iter()
+                .map(move |&(joined_id, joined_center)| (distance(center, joined_center), index, joined_id)))
+            .min_by_key(|&(dist, _, _)| dist)
+            .expect("bug: should be at least one room left to join");
+
+        let (room_id, center) = remaining.remove(index);
+        joined.push((room_id, center));
+        corridors.push((room_id, joined_id));
+    }
+
+    corridors
+}
+
+/// Turns the given path into floor tiles of the given room, opens up the wall of the room where
+/// the path begins, and surrounds the path with walls
+fn carve_corridor(map: &mut FloorMap, room_id: RoomId, start: CorridorEnd, path: &[TilePos]) {
+    let grid = map.grid_mut();
+    for &pos in path {
+        grid.place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+    }
+    grid.get_mut(start.wall).become_floor(room_id, FloorSprite::default());
+
+    for &pos in path {
+        for adj in surrounding(pos) {
+            if grid.get(adj).is_empty() {
+                grid.place_tile(adj, Tile::new_wall(WallSprite::default()));
+            }
+        }
+    }
+
+    // The opening looks like any other doorway, it just never gets a door
+    let room = *map.room(room_id).boundary();
+    if doorway_orientation(map.grid(), start.wall, room) == DoorOrientation::Horizontal {
+        place_entrance_walls(map, start.wall);
+    }
+}
+
+/// Joins the rooms on the map using corridors. Rooms must not overlap or touch.
+///
+/// Returns each pair of rooms that were joined. The corridor is part of the first room of each
+/// pair and opens directly into it. The other end of the corridor stops at the wall of the second
+/// room so that the doorway phase can place a door there.
+pub(in super) fn connect_with_corridors(
+    map: &mut FloorMap,
+    attempts: usize,
+) -> Result<Vec<(RoomId, RoomId)>, RanOutOfAttempts> {
+    let corridors = plan_corridors(map);
+    for &(room_id, other_id) in &corridors {
+        let from = *map.room(room_id).boundary();
+        let to = *map.room(other_id).boundary();
+        let (start, path) = find_corridor(map.grid(), from, to)
+            .ok_or(RanOutOfAttempts {phase: GenPhase::Rooms, attempts})?;
+        carve_corridor(map, room_id, start, &path);
+    }
+
+    Ok(corridors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_helpers::add_walled_room;
+
+    #[test]
+    fn corridor_joins_rooms() {
+        let mut map = FloorMap::new(GridSize {rows: 24, cols: 30}, 16);
+        let room1 = add_walled_room(&mut map, TileRect::new(TilePos {row: 1, col: 1}, GridSize {rows: 7, cols: 8}));
+        let room2 = add_walled_room(&mut map, TileRect::new(TilePos {row: 14, col: 18}, GridSize {rows: 8, cols: 9}));
+
+        assert_eq!(connect_with_corridors(&mut map, 1), Ok(vec![(room2, room1)]));
+
+        // The corridor opens into room2 and every tile that can be reached from inside of it is
+        // part of room2
+        let grid = map.grid();
+        let center = map.room(room2).boundary().center_tile();
+        let reachable = grid.depth_first_search(center, |_, adj| grid.get(adj).is_floor());
+        assert!(reachable.iter().all(|&pos| grid.get(pos).is_room_floor(room2)));
+        assert!(reachable.len() > map.room_exact_area(room2));
+
+        // The corridor reaches a wall of room1 that can become a doorway
+        let room1_walls: Vec<_> = map.room(room1).boundary().edge_positions().collect();
+        let doorway = reachable.iter()
+            .flat_map(|&pos| grid.adjacent_positions(pos))
+            .filter(|pos| room1_walls.contains(pos))
+            .find(|&wall| grid.adjacents(wall).any(|tile| tile.is_room_floor(room1)));
+        assert!(doorway.is_some());
+
+        // Nothing ever leads off of a floor tile into empty space
+        for pos in reachable {
+            assert!(grid.adjacents(pos).all(|tile| !tile.is_empty()));
+        }
+    }
+
+    #[test]
+    fn no_place_for_corridor() {
+        let mut map = FloorMap::new(GridSize {rows: 20, cols: 30}, 16);
+        add_walled_room(&mut map, TileRect::new(TilePos {row: 2, col: 2}, GridSize {rows: 8, cols: 8}));
+        // Every wall of this room is beside a corner, so no corridor can reach it
+        add_walled_room(&mut map, TileRect::new(TilePos {row: 12, col: 20}, GridSize {rows: 4, cols: 4}));
+
+        assert_eq!(connect_with_corridors(&mut map, 7),
+            Err(RanOutOfAttempts {phase: GenPhase::Rooms, attempts: 7}));
+    }
+}
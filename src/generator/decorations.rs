@@ -0,0 +1,302 @@
+use std::iter::once;
+use std::collections::HashSet;
+
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
+use specs::{World, Builder};
+
+use super::GameGenerator;
+use super::world_helpers::world_contains_any_entity;
+use crate::map_sprites::{WallSprite, WallSpriteAlternate};
+use crate::components::{
+    Position,
+    Ghost,
+    Sprite,
+    Prop,
+    ArrowShooter,
+    MovementDirection,
+    RenderLayer,
+    BoundingBox,
+    HealthPoints,
+    Breakable,
+    Item,
+};
+use crate::map::*;
+
+/// The minimum number of rows and columns (including walls) of a room that can have pillars
+const PILLAR_ROOM_SIZE: usize = 9;
+
+/// The number of tiles between each pillar and the walls nearest to it
+const PILLAR_INSET: usize = 2;
+
+/// The fewest wall tiles in a straight line that can have an arrow shooter in their middle
+const ARROW_SHOOTER_MIN_RUN: usize = 5;
+
+/// The items that a crate or pot can drop along with how likely each one is relative to the others
+const DROP_TABLE: [(Item, u32); 4] = [
+    (Item::Potion {stength: 5}, 3),
+    (Item::Potion {stength: 10}, 1),
+    (Item::Gold(5), 4),
+    (Item::Gold(15), 1),
+];
+
+/// Returns true if nothing should ever be placed at the given position. The center of the
+/// treasure chamber is always kept clear.
+pub(in super) fn is_reserved(map: &FloorMap, room_id: RoomId, pos: TilePos) -> bool {
+    let room = map.room(room_id);
+    room.is_treasure_chamber() && pos == room.boundary().center_tile()
+}
+
+/// Returns true if every floor tile within the given boundary can still be reached from every other
+/// one without passing through any of the blocked tiles
+fn stays_connected(grid: &TileGrid, boundary: TileRect, blocked: &HashSet<TilePos>) -> bool {
+    let open: HashSet<_> = boundary.tile_positions()
+        .filter(|&pos| grid.get(pos).is_floor() && !blocked.contains(&pos))
+        .collect();
+    let start = match open.iter().next() {
+        Some(&start) => start,
+        None => return true,
+    };
+    let reachable = grid.depth_first_search(start, |_, adj| open.contains(&adj));
+    open.iter().all(|pos| reachable.contains(pos))
+}
+
+/// Returns true if every one of the given tiles can be reached from the first one
+fn are_connected(grid: &TileGrid, tiles: &[TilePos]) -> bool {
+    let reachable = grid.depth_first_search(tiles[0], |_, adj| grid.get(adj).is_floor());
+    tiles.iter().all(|pos| reachable.contains(pos))
+}
+
+impl<'a> GameGenerator<'a> {
+    /// Places pillars and props in every room that can be decorated. Decorations are purely
+    /// cosmetic, so a room without enough space simply ends up with fewer of them.
+    pub(in super) fn decorate_rooms(&self, rng: &mut StdRng, map: &mut FloorMap, world: &mut World) {
+        // No system uses props, so their storage may not have been registered yet
+        world.register::<Prop>();
+
+        let rooms: Vec<_> = map.rooms()
+            .filter(|(_, room)| room.can_contain_decorations())
+            .map(|(room_id, room)| (room_id, *room.boundary()))
+            .collect();
+
+        for (room_id, boundary) in rooms {
+            let GridSize {rows, cols} = boundary.dimensions();
+            let is_large = rows >= PILLAR_ROOM_SIZE && cols >= PILLAR_ROOM_SIZE;
+            if is_large && rng.gen_bool(self.pillar_chance) {
+                self.place_pillars(map, world, room_id, boundary);
+            }
+
+            self.place_props(rng, map, world, room_id, boundary);
+            self.place_breakables(rng, map, world, room_id, boundary);
+
+            if map.room(room_id).can_generate_enemies() {
+                self.place_arrow_shooters(rng, map, world, room_id, boundary);
+            }
+        }
+    }
+
+    /// Attempts to place a pillar near each corner of the given room. Any pillar that would get in
+    /// the way of something or cut off part of the level is skipped.
+    fn place_pillars(&self, map: &mut FloorMap, world: &World, room_id: RoomId, boundary: TileRect) {
+        let tl = boundary.top_left();
+        let br = boundary.bottom_right();
+        let positions = [
+            TilePos {row: tl.row + PILLAR_INSET, col: tl.col + PILLAR_INSET},
+            TilePos {row: tl.row + PILLAR_INSET, col: br.col - PILLAR_INSET},
+            TilePos {row: br.row - PILLAR_INSET, col: tl.col + PILLAR_INSET},
+            TilePos {row: br.row - PILLAR_INSET, col: br.col - PILLAR_INSET},
+        ];
+
+        for &pos in &positions {
+            if !self.can_place_pillar(map, world, room_id, pos) {
+                continue;
+            }
+
+            let floor = map.grid().get(pos).clone();
+            let adjacents: Vec<_> = map.grid().adjacent_positions(pos).collect();
+            map.grid_mut().get_mut(pos).become_wall(WallSprite {
+                alt: WallSpriteAlternate::BrickPillar,
+                ..WallSprite::default()
+            });
+
+            // Removing a tile only splits up the level if the tiles around it can no longer reach
+            // one another
+            if !are_connected(map.grid(), &adjacents) {
+                map.grid_mut().place_tile(pos, floor);
+            }
+        }
+    }
+
+    /// Returns true if the floor tile at the given position can become a pillar without getting in
+    /// the way of anything around it
+    fn can_place_pillar(&self, map: &FloorMap, world: &World, room_id: RoomId, pos: TilePos) -> bool {
+        let grid = map.grid();
+        // Rooms overlap, so the tile may not be part of this room anymore
+        if !grid.get(pos).is_room_floor(room_id) || is_reserved(map, room_id, pos) {
+            return false;
+        }
+
+        // Pillars stand on their own, away from walls and entrances
+        if grid.adjacent_positions(pos).any(|adj| !grid.get(adj).is_floor() || grid.is_room_entrance(adj)) {
+            return false;
+        }
+
+        // Never block a staircase, door, or anything else that was placed in the room
+        let tile_size = map.tile_size();
+        !once(pos).chain(grid.adjacent_positions(pos))
+            .any(|pt| world_contains_any_entity(world, pt.tile_rect(tile_size)))
+    }
+
+    /// Places an arrow shooter in the middle of some of the long, straight runs of wall along the
+    /// sides of the given room. Each shooter faces into the room.
+    fn place_arrow_shooters(&self, rng: &mut StdRng, map: &FloorMap, world: &mut World, room_id: RoomId, boundary: TileRect) {
+        use self::MovementDirection::*;
+        let tl = boundary.top_left();
+        let br = boundary.bottom_right();
+        let tile_size = map.tile_size();
+        let grid = map.grid();
+
+        // The wall tiles along each side of the room (without the corners) and the direction from
+        // that side into the room
+        let sides: [(Vec<_>, _); 4] = [
+            ((tl.col+1..br.col).map(|col| TilePos {row: tl.row, col}).collect(), South),
+            ((tl.col+1..br.col).map(|col| TilePos {row: br.row, col}).collect(), North),
+            ((tl.row+1..br.row).map(|row| TilePos {row, col: tl.col}).collect(), East),
+            ((tl.row+1..br.row).map(|row| TilePos {row, col: br.col}).collect(), West),
+        ];
+        let inward = |pos: TilePos, direction| match direction {
+            North => TilePos {row: pos.row - 1, ..pos},
+            South => TilePos {row: pos.row + 1, ..pos},
+            East => TilePos {col: pos.col + 1, ..pos},
+            West => TilePos {col: pos.col - 1, ..pos},
+        };
+        // A shooter needs a bare wall with a clear floor tile of this room in front of it
+        let can_shoot_from = |world: &World, pos: TilePos, direction| {
+            let front = inward(pos, direction);
+            grid.get(pos).is_wall() && grid.get(front).is_room_floor(room_id)
+                && !world_contains_any_entity(world, pos.tile_rect(tile_size))
+                && !world_contains_any_entity(world, front.tile_rect(tile_size))
+        };
+
+        let mut runs = Vec::new();
+        for (positions, direction) in sides.iter() {
+            let mut run = Vec::new();
+            for &pos in positions {
+                if can_shoot_from(world, pos, *direction) {
+                    run.push(pos);
+                    continue;
+                }
+                if run.len() >= ARROW_SHOOTER_MIN_RUN {
+                    runs.push((run.clone(), *direction));
+                }
+                run.clear();
+            }
+            if run.len() >= ARROW_SHOOTER_MIN_RUN {
+                runs.push((run, *direction));
+            }
+        }
+
+        for (run, direction) in runs {
+            if !rng.gen_bool(self.arrow_shooter_chance) {
+                continue;
+            }
+
+            let pos = run[run.len() / 2];
+            world.create_entity()
+                .with(Position(pos.center(tile_size as i32)))
+                .with(Sprite(self.sprites.arrow_shooter()))
+                .with(RenderLayer::FIXTURES)
+                .with(ArrowShooter::new(direction, room_id, self.arrow_shooter_period, self.sprites.arrow()))
+                .build();
+        }
+    }
+
+    /// Places props on random floor tiles of the given room. Props can be walked over, so they
+    /// never get in the way.
+    fn place_props(&self, rng: &mut StdRng, map: &FloorMap, world: &mut World, room_id: RoomId, boundary: TileRect) {
+        let nprops = self.props_per_room.gen(rng);
+        let tile_size = map.tile_size();
+
+        let mut placed = 0;
+        let mut attempts = 0;
+        while placed < nprops && attempts < self.attempts {
+            attempts += 1;
+
+            let pos = boundary.random_inner_tile(rng);
+            if !map.grid().get(pos).is_room_floor(room_id) || is_reserved(map, room_id, pos) {
+                continue;
+            }
+            // Props never go on top of staircases, doors, or other props
+            if world_contains_any_entity(world, pos.tile_rect(tile_size)) {
+                continue;
+            }
+
+            let sprite = *self.sprites.props().choose(rng)
+                .expect("bug: should be at least one prop sprite");
+            world.create_entity()
+                .with(Prop)
+                .with(Ghost)
+                .with(Position(pos.center(tile_size as i32)))
+                .with(Sprite(sprite))
+                .with(RenderLayer::ITEMS)
+                .build();
+            placed += 1;
+        }
+    }
+
+    /// Places crates and pots on random floor tiles of the given room. These get in the way until
+    /// they are broken, so they are never placed beside an entrance or anywhere that would cut off
+    /// part of the room.
+    fn place_breakables(&self, rng: &mut StdRng, map: &FloorMap, world: &mut World, room_id: RoomId, boundary: TileRect) {
+        let nbreakables = self.breakables_per_room.gen(rng);
+        let tile_size = map.tile_size();
+        let grid = map.grid();
+
+        let mut blocked = HashSet::new();
+        // Rooms that overlap other rooms may only be connected through the other room
+        if !stays_connected(grid, boundary, &blocked) {
+            return;
+        }
+        let mut attempts = 0;
+        while blocked.len() < nbreakables && attempts < self.attempts {
+            attempts += 1;
+
+            let pos = boundary.random_inner_tile(rng);
+            if !grid.get(pos).is_room_floor(room_id) || is_reserved(map, room_id, pos) {
+                continue;
+            }
+            // Entrances are the floor tiles in the walls of the room
+            let beside_entrance = grid.adjacent_positions(pos)
+                .any(|adj| boundary.is_edge(adj) && grid.get(adj).is_floor());
+            if beside_entrance || world_contains_any_entity(world, pos.tile_rect(tile_size)) {
+                continue;
+            }
+            blocked.insert(pos);
+            if !stays_connected(grid, boundary, &blocked) {
+                blocked.remove(&pos);
+                continue;
+            }
+
+            // Rolled now so that the loot only depends on the map key
+            let drops = if rng.gen_bool(self.breakable_drop_chance) {
+                DROP_TABLE.choose_weighted(rng, |&(_, weight)| weight).ok().map(|(item, _)| item.clone())
+            } else {
+                None
+            };
+            let sprite = *self.sprites.breakables().choose(rng)
+                .expect("bug: should be at least one breakable sprite");
+            // Breakables that drop nothing never show their drop animation
+            let drop_animation = match &drops {
+                Some(item) => self.sprites.item_animation(item),
+                None => self.sprites.potion_animation(),
+            }.clone();
+            world.create_entity()
+                .with(Breakable {drops, drop_animation})
+                .with(HealthPoints(1))
+                .with(Position(pos.center(tile_size as i32)))
+                .with(BoundingBox::Full {width: tile_size, height: tile_size})
+                .with(Sprite(sprite))
+                .with(RenderLayer::ITEMS)
+                .build();
+        }
+    }
+}
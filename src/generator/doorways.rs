@@ -1,15 +1,75 @@
 use std::collections::HashMap;
 
 use rand::{rngs::StdRng, seq::SliceRandom};
-use specs::{World, Builder};
+use specs::{World, Builder, ReadStorage, Join};
 
 use super::GameGenerator;
+use super::world_helpers::world_door_at;
 use crate::map_sprites::{FloorSprite, WallSpriteAlternate};
-use crate::components::{Position, BoundingBox, Sprite, Door};
+use crate::components::{Position, BoundingBox, Sprite, Door, DoorOrientation, Gate, Locked, RenderLayer};
 use crate::map::*;
 
+/// Returns the orientation of the wall that the doorway at the given position is in. The doorway
+/// must be on the edge of the given room.
+///
+/// A doorway with walls to its east and west is in a horizontal wall and a doorway with walls to
+/// its north and south is in a vertical wall. Next to a corner (or another opening), both or
+/// neither of those pairs may be walls. The door then faces into the room: doorways on the top or
+/// bottom edge of the room are in a horizontal wall and the rest are in a vertical wall.
+pub(in super) fn doorway_orientation(grid: &TileGrid, edge: TilePos, room: TileRect) -> DoorOrientation {
+    let GridSize {rows, cols} = grid.dimensions();
+    let is_wall = |pos: Option<TilePos>| pos.map(|pos| grid.get(pos).is_wall()).unwrap_or(false);
+    let walls_east_west = is_wall(edge.adjacent_west()) && is_wall(edge.adjacent_east(cols));
+    let walls_north_south = is_wall(edge.adjacent_north()) && is_wall(edge.adjacent_south(rows));
+
+    match (walls_east_west, walls_north_south) {
+        (true, false) => DoorOrientation::Horizontal,
+        (false, true) => DoorOrientation::Vertical,
+        _ if edge.row == room.top_left().row || edge.row == room.bottom_right().row => DoorOrientation::Horizontal,
+        _ => DoorOrientation::Vertical,
+    }
+}
+
+/// Returns the bounding box of a door in a doorway with the given orientation
+fn door_bounding_box(orientation: DoorOrientation, tile_size: u32) -> BoundingBox {
+    match orientation {
+        DoorOrientation::Horizontal => BoundingBox::Full {width: tile_size, height: tile_size},
+        DoorOrientation::Vertical => BoundingBox::Full {width: tile_size / 2, height: tile_size},
+    }
+}
+
+/// Returns true if the two positions are the same or touch each other (including diagonally)
+fn is_beside(pos: TilePos, other: TilePos) -> bool {
+    let (drow, dcol) = pos.difference(other);
+    drow.abs() <= 1 && dcol.abs() <= 1
+}
+
+/// Places the entrance walls on either side of a doorway in a horizontal wall
+pub(in super) fn place_entrance_walls(map: &mut FloorMap, edge: TilePos) {
+    for adj in map.grid().adjacent_positions(edge) {
+        // Don't place entrance walls if there is a wall underneath because it looks
+        // awkward. See: https://github.com/sunjay/caves/issues/89
+        let south_adj = adj.adjacent_south(map.grid().rows_len());
+        if south_adj.map(|t| map.grid().get(t).is_wall()).unwrap_or(false) {
+            continue;
+        }
+        let tile = map.grid_mut().get_mut(adj);
+        if !tile.is_wall() {
+            continue;
+        }
+        tile.wall_sprite_mut().alt = if adj.col < edge.col {
+            WallSpriteAlternate::EntranceLeft
+        } else {
+            WallSpriteAlternate::EntranceRight
+        };
+    }
+}
+
 impl<'a> GameGenerator<'a> {
     pub(in super) fn connect_rooms(&self, rng: &mut StdRng, map: &mut FloorMap, world: &mut World) {
+        // No system uses door orientations, so their storage may not have been registered yet
+        world.register::<DoorOrientation>();
+
         // A mapping from the rooms that were connected to the edge tile that connected them
         let mut connected_rooms = HashMap::new();
 
@@ -31,77 +91,103 @@ impl<'a> GameGenerator<'a> {
                     .map(|pair| (edge, pair)))
         }).collect();
 
-        while let Some(&(edge, pair)) = doorways.choose(rng) {
+        // Doorways are kept at least a tile apart from each other and from any door already in the
+        // world. Otherwise, rooms that only barely overlap can end up with doors on either side of
+        // the same thin seam, which looks like a strange double door.
+        let tile_size = map.tile_size();
+        let mut door_tiles: Vec<_> = {
+            let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+            (&positions, &doors).join()
+                .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+                .collect()
+        };
+
+        while !doorways.is_empty() {
+            let spaced: Vec<_> = doorways.iter()
+                .filter(|&&(edge, _)| !door_tiles.iter().any(|&door| is_beside(edge, door)))
+                .collect();
+            // If every remaining doorway would be beside another door, it is better to allow
+            // adjacent doors than to leave those rooms unconnected
+            let &(edge, pair) = match spaced.choose(rng) {
+                Some(&doorway) => doorway,
+                None => doorways.choose(rng).expect("bug: doorways should not be empty"),
+            };
             connected_rooms.insert(pair, edge);
+            door_tiles.push(edge);
 
             // Only retain the doorways that connect rooms we haven't added a doorway for yet
             doorways.retain(|&(_, (r1, r2))| !connected_rooms.contains_key(&(r1, r2)) && !connected_rooms.contains_key(&(r2, r1)));
         }
 
         // Perform all the insertions at once (want to avoid immutable + mutable borrow)
+        // NOTE: Doorways can share an entrance wall, so they must be added in a consistent order
+        // for the same key to always generate the same map.
+        let mut connected_rooms: Vec<_> = connected_rooms.into_iter().collect();
+        connected_rooms.sort_by_key(|&(_, edge)| edge);
         for ((room_id, _), edge) in connected_rooms {
-            // Determine if the door should be horizontally or vertically oriented
-            let mut row_walls = 0;
-            let mut col_walls = 0;
-            for adj in map.grid().adjacent_positions(edge) {
-                if !map.grid().get(adj).is_wall() {
-                    continue;
-                }
-                if adj.row == edge.row {
-                    row_walls += 1;
-                }
-                if adj.col == edge.col {
-                    col_walls += 1;
-                }
-            }
-            // This code assumes that entrances are of width 1. We expect them to have walls either
-            // in the same row or in the same column, never both.
-            let (is_horizontal, sprite) = match (row_walls, col_walls) {
-                (2, 0) => (true, self.sprites.door_horizontal()),
-                (0, 2) => (false, self.sprites.door_vertical()),
-                _ => unreachable!("bug: entrance did not have expected walls"),
-            };
+            let orientation = doorway_orientation(map.grid(), edge, *map.room(room_id).boundary());
 
             // Make the wall into a floor tile
             map.grid_mut().get_mut(edge).become_floor(room_id, FloorSprite::default());
 
             // Place a door on top of the floor tile
-            let tile_size = map.tile_size();
             let pos = edge.center(tile_size as i32);
             world.create_entity()
                 .with(Position(pos))
-                .with(Door)
-                .with(if is_horizontal {
-                    BoundingBox::Full {width: tile_size, height: tile_size}
-                } else {
-                    BoundingBox::Full {width: tile_size / 2, height: tile_size}
-                })
-                .with(Sprite(sprite))
+                .with(Door::Closed)
+                .with(orientation)
+                .with(door_bounding_box(orientation, tile_size))
+                .with(Sprite(self.sprites.door_sprite(orientation)))
+                .with(RenderLayer::FIXTURES)
                 .build();
 
-            if is_horizontal {
-                // Place entrance walls
-                for adj in map.grid().adjacent_positions(edge) {
-                    // Don't place entrance walls if there is a wall underneath because it looks
-                    // awkward. See: https://github.com/sunjay/caves/issues/89
-                    let south_adj = adj.adjacent_south(map.grid().rows_len());
-                    if south_adj.map(|t| map.grid().get(t).is_wall()).unwrap_or(false) {
-                        continue;
-                    }
-                    let tile = map.grid_mut().get_mut(adj);
-                    if !tile.is_wall() {
-                        continue;
-                    }
-                    tile.wall_sprite_mut().alt = if adj.col < edge.col {
-                        WallSpriteAlternate::EntranceLeft
-                    } else {
-                        WallSpriteAlternate::EntranceRight
-                    };
-                }
+            if orientation == DoorOrientation::Horizontal {
+                place_entrance_walls(map, edge);
             }
         }
     }
 
+    /// Puts a locked gate in every entrance of the treasure chamber (if there is one on this level).
+    /// Doors already in an entrance become gates. Corridors that lead out of the treasure chamber
+    /// have no door, so a gate is added where each corridor opens into the room.
+    pub(in super) fn lock_treasure_chamber(&self, map: &FloorMap, world: &mut World) {
+        // No system creates gates, so their storages may not have been registered yet
+        world.register::<Gate>();
+        world.register::<Locked>();
+
+        let chamber = match map.rooms().find(|(_, room)| room.is_treasure_chamber()) {
+            Some((_, room)) => *room.boundary(),
+            None => return,
+        };
+
+        let grid = map.grid();
+        let tile_size = map.tile_size();
+        // All of the walls of the treasure chamber were placed after every other room, so the
+        // only floor tiles on its edges are entrances
+        let entrances: Vec<_> = chamber.edge_positions()
+            .filter(|&edge| grid.get(edge).is_floor())
+            .collect();
+        for edge in entrances {
+            let orientation = doorway_orientation(grid, edge, chamber);
+            let gate = match world_door_at(world, edge.tile_rect(tile_size)) {
+                Some(door) => door,
+                None => world.create_entity()
+                    .with(Position(edge.center(tile_size as i32)))
+                    .with(Door::Closed)
+                    .with(orientation)
+                    .with(door_bounding_box(orientation, tile_size))
+                    .with(RenderLayer::FIXTURES)
+                    .build(),
+            };
+            world.write_storage::<Sprite>().insert(gate, Sprite(self.sprites.gate_sprite(orientation)))
+                .expect("bug: unable to update gate sprite");
+            world.write_storage::<Gate>().insert(gate, Gate)
+                .expect("bug: unable to insert gate");
+            world.write_storage::<Locked>().insert(gate, Locked)
+                .expect("bug: unable to lock gate");
+        }
+    }
+
     /// Returns the two distinct adjacent room IDs to a potential doorway if and only if the wall
     /// that is currently at the returned position is in fact able to become a doorway
     fn doorway_wall_adjacent_rooms(&self, edge: TilePos, room_id: RoomId, grid: &TileGrid) -> Option<(RoomId, RoomId)> {
@@ -118,6 +204,8 @@ impl<'a> GameGenerator<'a> {
         let adj_rooms: Vec<_> = grid.adjacents(edge)
             .filter_map(|adj| adj.floor_room_id())
             .collect();
+        // The two tiles must also be in different rooms. Walls that are left inside a room where
+        // its parts overlap would otherwise become doorways that connect the room to itself.
         let (r1, r2) = match &adj_rooms[..] {
             [r1, r2] if r1 != r2 => (*r1, *r2),
             _ => return None,
@@ -135,3 +223,146 @@ impl<'a> GameGenerator<'a> {
         Some(pair)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+
+    use crate::map_sprites::WallSprite;
+    use crate::generator::{MapKey, ConnectionStyle};
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+
+    #[test]
+    fn doorways_beside_walls_face_into_room() {
+        let mut map = FloorMap::new(GridSize {rows: 7, cols: 8}, 16);
+        let room = TileRect::new(TilePos {row: 1, col: 1}, GridSize {rows: 5, cols: 6});
+        let room_id = map.add_room(room);
+        for pos in room.tile_positions() {
+            map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+        }
+        for pos in room.edge_positions() {
+            map.grid_mut().get_mut(pos).become_wall(WallSprite::default());
+        }
+
+        let top = TilePos {row: 1, col: 3};
+        let left = TilePos {row: 3, col: 1};
+        assert_eq!(doorway_orientation(map.grid(), top, room), DoorOrientation::Horizontal);
+        assert_eq!(doorway_orientation(map.grid(), left, room), DoorOrientation::Vertical);
+
+        // Walls on every side of the doorway (e.g. a pillar just inside of the room)
+        for &pos in &[TilePos {row: 0, col: 3}, TilePos {row: 2, col: 3}, TilePos {row: 3, col: 0}, TilePos {row: 3, col: 2}] {
+            map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+        }
+        assert_eq!(doorway_orientation(map.grid(), top, room), DoorOrientation::Horizontal);
+        assert_eq!(doorway_orientation(map.grid(), left, room), DoorOrientation::Vertical);
+    }
+
+    /// A tall room on the left with two rooms stacked on its right. Each room shares a wall with
+    /// the other two and the seams between them meet at the corner of the left room.
+    fn stacked_rooms(right_size: GridSize) -> FloorMap {
+        let GridSize {rows, cols} = right_size;
+        let mut map = FloorMap::new(GridSize {rows: rows * 2 - 1, cols: cols + 4}, 16);
+        let rects = [
+            TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows: rows * 2 - 1, cols: 5}),
+            TileRect::new(TilePos {row: 0, col: 4}, right_size),
+            TileRect::new(TilePos {row: rows - 1, col: 4}, right_size),
+        ];
+        let room_ids: Vec<_> = rects.iter().map(|&rect| map.add_room(rect)).collect();
+        for (&rect, &room_id) in rects.iter().zip(&room_ids) {
+            for pos in rect.tile_positions() {
+                map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+            }
+        }
+        for &rect in &rects {
+            for pos in rect.edge_positions() {
+                map.grid_mut().get_mut(pos).become_wall(WallSprite::default());
+            }
+        }
+        map
+    }
+
+    /// Connects the rooms of the given map and returns the tile position of every door
+    fn connect_with_seed(map: &mut FloorMap, seed: u64) -> Vec<TilePos> {
+        let sprites = test_sprites();
+        let generator = test_generator(&sprites);
+        let (_, mut world) = setup_game_world();
+        generator.connect_rooms(&mut StdRng::seed_from_u64(seed), map, &mut world);
+
+        let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+        (&positions, &doors).join()
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .collect()
+    }
+
+    #[test]
+    fn doors_are_never_beside_each_other() {
+        for seed in 0..50 {
+            let mut map = stacked_rooms(GridSize {rows: 4, cols: 5});
+            let doors = connect_with_seed(&mut map, seed);
+
+            // Exactly one door between every pair of rooms
+            assert_eq!(doors.len(), 3, "seed {}: {:?}", seed, doors);
+            for (i, &door) in doors.iter().enumerate() {
+                for &other in &doors[i+1..] {
+                    assert!(!is_beside(door, other), "seed {}: doors at {:?} and {:?}", seed, door, other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn adjacent_doors_allowed_when_unavoidable() {
+        // Every wall between two of the rooms is only a single tile long and the one between the
+        // two rooms on the right is beside both of the others
+        for seed in 0..10 {
+            let mut map = stacked_rooms(GridSize {rows: 3, cols: 3});
+            let mut doors = connect_with_seed(&mut map, seed);
+            doors.sort();
+            assert_eq!(doors, vec![TilePos {row: 1, col: 4}, TilePos {row: 2, col: 5}, TilePos {row: 3, col: 4}]);
+        }
+    }
+
+    #[test]
+    fn door_orientation_matches_wall() {
+        let sprites = test_sprites();
+        for &style in &[ConnectionStyle::Overlap, ConnectionStyle::Corridors] {
+            let mut generator = test_generator(&sprites);
+            generator.connection_style = style;
+
+            for seed in 0..8 {
+                let key: MapKey = StdRng::seed_from_u64(seed).gen();
+                let game = generator.clone().generate_with_key(key, setup_game_world)
+                    .expect("bug: should be able to generate a map with a valid config");
+                for level in &game.levels {
+                    let map = level.world.read_resource::<FloorMap>();
+                    let grid = map.grid();
+                    let GridSize {rows, cols} = grid.dimensions();
+                    let is_floor = |pos: Option<TilePos>| pos.map(|pos| grid.get(pos).is_floor()).unwrap_or(false);
+
+                    let (positions, doors, orientations, sprites_storage, gates) = level.world.system_data::<(
+                        ReadStorage<'_, Position>,
+                        ReadStorage<'_, Door>,
+                        ReadStorage<'_, DoorOrientation>,
+                        ReadStorage<'_, Sprite>,
+                        ReadStorage<'_, Gate>,
+                    )>();
+                    assert_eq!((&doors, &orientations).join().count(), doors.join().count());
+                    for (&Position(pos), _, &orientation, &Sprite(sprite), gate) in (&positions, &doors, &orientations, &sprites_storage, gates.maybe()).join() {
+                        let edge = map.world_to_tile_pos(pos);
+                        // The door is walked through along the axis perpendicular to its wall
+                        let is_passable = match orientation {
+                            DoorOrientation::Horizontal => is_floor(edge.adjacent_north()) && is_floor(edge.adjacent_south(rows)),
+                            DoorOrientation::Vertical => is_floor(edge.adjacent_west()) && is_floor(edge.adjacent_east(cols)),
+                        };
+                        assert!(is_passable, "key {}: {:?} door at {:?}", key, orientation, edge);
+
+                        let expected = if gate.is_some() { sprites.gate_sprite(orientation) } else { sprites.door_sprite(orientation) };
+                        assert_eq!(sprite, expected, "key {}: wrong sprite for {:?} door at {:?}", key, orientation, edge);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,147 @@
 use std::collections::HashSet;
 
-use rand::{rngs::StdRng};
-use specs::{World, Builder};
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
+use sdl2::rect::Point;
+use specs::{World, Builder, Entity, Join, Entities, ReadStorage, WriteStorage};
 
-use super::{GameGenerator, RanOutOfAttempts, EnemyValues};
-use crate::components::{Position, Sprite, Enemy, HealthPoints, Attack, HitWait, Movement};
+use super::{GameGenerator, RanOutOfAttempts, GenPhase, EnemyValues, BossConfig};
+use crate::components::{Position, Sprite, Enemy, EnemySpawn, BossSpawn, Boss, ChargeAttack, HealthPoints, Attack, HitWait, Movement, RenderLayer, Item, Loot};
 use crate::map::*;
+use crate::assets::scale_speed_to_tile_size;
+
+/// The distance (in tiles, in every direction) within which enemy spawn points count towards
+/// `max_nearby_enemies`
+pub(in super) const ENEMY_DENSITY_RADIUS: usize = 3;
+
+/// Returns true if an enemy can be spawned on the given tile of the given room
+fn is_spawn_tile(grid: &TileGrid, room_id: RoomId, pos: TilePos) -> bool {
+    // Must be a tile in the right room. Though we may have picked an "inner" tile, it may still be
+    // near a wall or entrance. Enemies avoid hazards, so they are never spawned on one either.
+    grid.get(pos).is_room_floor(room_id) && !grid.get(pos).is_hazard()
+        && !grid.adjacent_positions(pos).any(|pt| grid.get(pt).is_wall() || grid.is_room_entrance(pt))
+}
+
+/// Returns the number of the given spawn tiles that are within ENEMY_DENSITY_RADIUS tiles of the
+/// given tile
+fn nearby_spawns(spawns: &HashSet<TilePos>, pos: TilePos) -> usize {
+    let rows = pos.row.saturating_sub(ENEMY_DENSITY_RADIUS)..=pos.row + ENEMY_DENSITY_RADIUS;
+    rows.flat_map(|row| {
+        let cols = pos.col.saturating_sub(ENEMY_DENSITY_RADIUS)..=pos.col + ENEMY_DENSITY_RADIUS;
+        cols.map(move |col| TilePos {row, col})
+    }).filter(|tile| spawns.contains(tile)).count()
+}
+
+/// Rolls each of the given spawn probabilities (in order) and returns whether each spawn succeeded
+fn roll_spawns<R: Rng>(rng: &mut R, probabilities: &[f64]) -> Vec<bool> {
+    probabilities.iter().map(|&probability| rng.gen_bool(probability)).collect()
+}
+
+/// Spawns the enemies of every enemy spawn point and the boss of every boss spawn point in the
+/// world. Enemies carry the loot of their spawn point, if any. The spawn points are removed so that
+/// calling this again will not spawn any more enemies.
+///
+/// Spawn points are rolled in a consistent order, so the same random number generator will always
+/// spawn the same enemies.
+pub fn spawn_enemies<R: Rng>(world: &mut World, rng: &mut R) {
+    // No system creates loot, so its storage may not have been registered yet
+    world.register::<Loot>();
+    let spawns: Vec<_> = {
+        let (entities, positions, spawns, loots) = world.system_data::<(Entities<'_>, ReadStorage<'_, Position>, ReadStorage<'_, EnemySpawn>, ReadStorage<'_, Loot>)>();
+        (&entities, &positions, &spawns, loots.maybe()).join()
+            .map(|(entity, &Position(pos), spawn, loot)| (entity, pos, spawn.clone(), loot.cloned()))
+            .collect()
+    };
+
+    let probabilities: Vec<_> = spawns.iter().map(|(_, _, spawn, _)| spawn.probability).collect();
+    let rolls = roll_spawns(rng, &probabilities);
+    for ((spawn_point, pos, EnemySpawn {enemy, ..}, loot), spawned) in spawns.into_iter().zip(rolls) {
+        world.delete_entity(spawn_point)
+            .expect("bug: unable to delete enemy spawn point");
+        if !spawned {
+            continue;
+        }
+
+        let enemy = create_enemy(world, pos, enemy);
+        if let Some(loot) = loot {
+            world.write_storage::<Loot>().insert(enemy, loot)
+                .expect("bug: unable to insert loot");
+        }
+    }
+
+    // No system uses boss spawn points, so their storage may not have been registered yet
+    world.register::<BossSpawn>();
+    let boss_spawns: Vec<_> = {
+        let (entities, positions, spawns) = world.system_data::<(Entities<'_>, ReadStorage<'_, Position>, ReadStorage<'_, BossSpawn>)>();
+        (&entities, &positions, &spawns).join()
+            .map(|(entity, &Position(pos), spawn)| (entity, pos, spawn.boss.clone()))
+            .collect()
+    };
+    for (spawn_point, pos, BossConfig {values, charge}) in boss_spawns {
+        world.delete_entity(spawn_point)
+            .expect("bug: unable to delete boss spawn point");
+
+        let max_health = values.health_points;
+        let boss = create_enemy(world, pos, values);
+        let tile_size = world.read_resource::<FloorMap>().tile_size();
+        let distance = charge.distance as u32 * tile_size;
+        let speed = scale_speed_to_tile_size(charge.speed, tile_size);
+        world.write_storage::<Boss>().insert(boss, Boss {max_health})
+            .expect("bug: unable to insert boss");
+        world.write_storage::<ChargeAttack>()
+            .insert(boss, ChargeAttack::new(charge.period, charge.windup, speed, distance, charge.bonus_damage))
+            .expect("bug: unable to insert charge attack");
+    }
+}
+
+/// Adds an enemy with the given values at the given position
+fn create_enemy(world: &mut World, pos: Point, enemy: EnemyValues) -> Entity {
+    let EnemyValues {
+        behaviour,
+        animations,
+        attack,
+        speed,
+        health_points,
+        hit_wait,
+        bounding_box,
+    } = enemy;
+
+    // Enemies stay in the room they spawned in
+    let (home_room, tile_size) = {
+        let map = world.read_resource::<FloorMap>();
+        (map.grid().get(map.world_to_tile_pos(pos)).floor_room_id(), map.tile_size())
+    };
+    world.create_entity()
+        .with(Enemy {behaviour, speed: scale_speed_to_tile_size(speed, tile_size), home_room})
+        .with(HealthPoints(health_points))
+        .with(Attack(attack))
+        .with(HitWait(hit_wait))
+        .with(Position(pos))
+        .with(bounding_box.scale_to_tile_size(tile_size))
+        .with(Movement::default())
+        .with(Sprite(animations.default_sprite()))
+        .with(RenderLayer::CHARACTERS)
+        .with(animations.default_animation())
+        .with(animations)
+        .build()
+}
 
 impl<'a> GameGenerator<'a> {
+    /// Places enemy spawn points in every room that can have enemies. Rooms can overlap, so on top
+    /// of the limits for each room, no spawn point is placed where there are already too many
+    /// nearby. A room that is too crowded ends up with fewer enemies.
     pub(in super) fn add_enemies(&self,
         rng: &mut StdRng,
         map: &FloorMap,
         world: &mut World,
         level: usize,
     ) -> Result<(), RanOutOfAttempts> {
+        // No system uses spawn points, so their storage may not have been registered yet
+        world.register::<EnemySpawn>();
+        world.register::<Loot>();
+
         let grid = map.grid();
+        // Every spawn point on the level, no matter which room it was placed for
+        let mut spawns = HashSet::new();
         for (room_id, room) in map.rooms() {
             if !room.can_generate_enemies() {
                 continue;
@@ -25,59 +152,163 @@ impl<'a> GameGenerator<'a> {
             let nenemies = self.room_enemies.gen(rng).min(max_enemies);
 
             let room_bounds = room.boundary();
-            let mut placed = HashSet::new();
+            let mut placed = 0;
+            // True if a tile was skipped because of the enemies around it
+            let mut crowded = false;
 
             let mut attempts = 0;
-            while placed.len() < nenemies {
+            while placed < nenemies {
                 if attempts > self.attempts {
-                    return Err(RanOutOfAttempts);
+                    if crowded {
+                        debug!("Placed {} of {} enemies in crowded room {}", placed, nenemies, room_id);
+                        break;
+                    }
+                    return Err(RanOutOfAttempts {phase: GenPhase::Enemies, attempts});
                 }
                 attempts += 1;
 
                 // Goal: Don't generate enemies near the walls (so those spaces are free for other things)
                 let pos = room_bounds.random_inner_tile(rng);
                 // Tile where an enemy has already been generated
-                if placed.contains(&pos) {
-                    continue;
-                }
-                // Not a tile in the right room
-                if !grid.get(pos).is_room_floor(room_id) {
+                if spawns.contains(&pos) || !is_spawn_tile(grid, room_id, pos) {
                     continue;
                 }
-                // Though we got an "inner" tile, we may still be near a wall or entrance
-                if grid.adjacent_positions(pos).any(|pt| grid.get(pt).is_wall() || grid.is_room_entrance(pt)) {
+                if nearby_spawns(&spawns, pos) >= self.max_nearby_enemies {
+                    crowded = true;
                     continue;
                 }
 
                 let enemy_pos = pos.center(self.tile_size as i32);
 
-                let EnemyValues {
-                    behaviour,
-                    animations,
-                    attack,
-                    speed,
-                    health_points,
-                    hit_wait,
-                    bounding_box,
-                } = self.enemy_config.random_enemy(rng, level);
-
-                world.create_entity()
-                    .with(Enemy {behaviour, speed})
-                    .with(HealthPoints(health_points))
-                    .with(Attack(attack))
-                    .with(HitWait(hit_wait))
+                // Enemies are only spawned once the player enters the level
+                let spawn_point = world.create_entity()
                     .with(Position(enemy_pos))
-                    .with(bounding_box)
-                    .with(Movement::default())
-                    .with(Sprite(animations.default_sprite()))
-                    .with(animations.default_animation())
-                    .with(animations)
+                    .with(EnemySpawn {
+                        probability: self.enemy_spawn_probability,
+                        enemy: self.enemy_config.random_enemy(rng, level),
+                    })
                     .build();
+                // Rolled now so that the loot only depends on the map key
+                if rng.gen_bool(self.enemy_gold_chance) {
+                    let item = Item::Gold(self.enemy_gold.gen(rng));
+                    let loot = Loot {animation: self.sprites.item_animation(&item).clone(), item};
+                    world.write_storage().insert(spawn_point, loot)
+                        .expect("bug: unable to insert loot");
+                }
 
-                placed.insert(pos);
+                spawns.insert(pos);
+                placed += 1;
             }
         }
 
         Ok(())
     }
+
+    /// Moves every enemy spawn point in a safe zone (the room that the player starts in or a
+    /// merchant room) to a random room that can have enemies. Spawn points are never placed there
+    /// in the first place, so this only guards against other phases of generation moving things
+    /// around.
+    pub(in super) fn relocate_safe_zone_spawns(&self,
+        rng: &mut StdRng,
+        map: &FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        let (entities, mut positions, spawns) = world.system_data::<(Entities<'_>, WriteStorage<'_, Position>, ReadStorage<'_, EnemySpawn>)>();
+        let grid = map.grid();
+        let mut occupied: HashSet<_> = (&positions, &spawns).join()
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .collect();
+        let rooms: Vec<_> = map.rooms().filter(|(_, room)| room.can_generate_enemies()).collect();
+
+        for (spawn_point, Position(pos), _) in (&entities, &mut positions, &spawns).join() {
+            if !map.is_safe_zone(map.world_to_tile_pos(*pos)) {
+                continue;
+            }
+            let (room_id, room) = match rooms.choose(rng) {
+                Some(&room) => room,
+                None => {
+                    warn!("Removed enemy spawn point in a safe zone since no room can have enemies");
+                    entities.delete(spawn_point).expect("bug: unable to delete enemy spawn point");
+                    continue;
+                },
+            };
+
+            let mut attempts = 0;
+            let tile = loop {
+                if attempts > self.attempts {
+                    return Err(RanOutOfAttempts {phase: GenPhase::Enemies, attempts});
+                }
+                attempts += 1;
+
+                let tile = room.boundary().random_inner_tile(rng);
+                if !occupied.contains(&tile) && is_spawn_tile(grid, room_id, tile) {
+                    break tile;
+                }
+            };
+            occupied.insert(tile);
+            *pos = tile.center(self.tile_size as i32);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    use crate::generator::tests::{test_generator, test_sprites};
+    use crate::test_helpers::rooms_side_by_side;
+
+    #[test]
+    fn spawn_rolls_reproducible() {
+        let probabilities = [0.5; 64];
+        let rolls = |seed| roll_spawns(&mut StdRng::from_seed(seed), &probabilities);
+
+        assert_eq!(rolls([1; 32]), rolls([1; 32]));
+        assert_ne!(rolls([1; 32]), rolls([2; 32]));
+        // Some (but not all) of the spawns should succeed
+        let spawned = rolls([1; 32]).into_iter().filter(|&spawned| spawned).count();
+        assert!(spawned > 0 && spawned < probabilities.len());
+    }
+
+    #[test]
+    fn spawn_rolls_respect_probability() {
+        let mut rng = StdRng::from_seed([3; 32]);
+        assert!(roll_spawns(&mut rng, &[1.0; 20]).into_iter().all(|spawned| spawned));
+        assert!(roll_spawns(&mut rng, &[0.0; 20]).into_iter().all(|spawned| !spawned));
+    }
+
+    #[test]
+    fn spawns_moved_out_of_safe_zone() {
+        let sprites = test_sprites();
+        let generator = test_generator(&sprites);
+        let tile_size = generator.tile_size as i32;
+        let mut rng = StdRng::from_seed([5; 32]);
+
+        // Two rooms that share the wall at column 7, with the player starting in the west room
+        let (mut map, rooms) = rooms_side_by_side(9, &[8, 8], true, generator.tile_size);
+        map.rooms_mut().find(|&(room_id, _)| room_id == rooms[0]).unwrap().1.become_player_start();
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<EnemySpawn>();
+        let mut add_spawn = |world: &mut World, pos: TilePos| world.create_entity()
+            .with(Position(pos.center(tile_size)))
+            .with(EnemySpawn {probability: 1.0, enemy: generator.enemy_config.random_enemy(&mut rng, 1)})
+            .build();
+        let in_safe_zone = add_spawn(&mut world, TilePos {row: 4, col: 3});
+        let outside = TilePos {row: 4, col: 10};
+        let already_outside = add_spawn(&mut world, outside);
+
+        generator.relocate_safe_zone_spawns(&mut rng, &map, &mut world).unwrap();
+        let tile = |entity| map.world_to_tile_pos(world.read_storage::<Position>().get(entity).unwrap().0);
+        assert_eq!(tile(already_outside), outside);
+        let moved = tile(in_safe_zone);
+        assert!(!map.is_safe_zone(moved));
+        assert!(map.grid().get(moved).is_room_floor(rooms[1]));
+        assert_ne!(moved, outside);
+    }
 }
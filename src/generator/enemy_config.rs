@@ -3,48 +3,199 @@ use rand::{Rng, seq::SliceRandom};
 use crate::components::{AnimationManager, BoundingBox, EnemyBehaviour};
 
 /// The stats + animations for one enemy
+///
+/// The speed and bounding box are measured at NATIVE_TILE_SIZE. They are scaled to the tile size
+/// of the map when the enemy is spawned.
 #[derive(Clone)]
 pub struct EnemyValues {
     pub behaviour: EnemyBehaviour,
     pub animations: AnimationManager,
     pub attack: usize, // HP
-    pub speed: i32, // movements per second
+    pub speed: f32, // px/frame
     pub health_points: usize, // HP
     pub hit_wait: usize, // frames
     pub bounding_box: BoundingBox,
 }
 
+/// The values of the charge attack of a boss
+///
+/// The speed is measured at NATIVE_TILE_SIZE. It is scaled to the tile size of the map when the
+/// boss is spawned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargeValues {
+    pub period: usize, // frames
+    pub windup: usize, // frames
+    pub speed: f32, // px/frame
+    pub distance: usize, // tiles
+    pub bonus_damage: usize, // HP
+}
+
+/// The stats + animations of the boss that guards the treasure chamber
+#[derive(Clone)]
+pub struct BossConfig {
+    /// The boss attacks on contact just like any other enemy
+    pub values: EnemyValues,
+    pub charge: ChargeValues,
+}
+
 /// Each type of enemy
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EnemyType {
     Rat,
 }
 
+impl EnemyType {
+    /// The name of this type of enemy, as used in the generator config
+    pub fn name(self) -> &'static str {
+        use self::EnemyType::*;
+        match self {
+            Rat => "rat",
+        }
+    }
+}
+
+/// How much stronger enemies get on each level after the first. Enemies on the first level always
+/// have exactly their base values.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EnemyScaling {
+    /// The fraction of the base attack added on each level (e.g. 0.1 adds 10% per level)
+    pub attack_per_level: f32,
+    /// The fraction of the base health points added on each level
+    pub health_per_level: f32,
+    /// The speed (px/frame at NATIVE_TILE_SIZE) added on each level
+    pub speed_per_level: f32,
+}
+
+impl EnemyScaling {
+    /// Returns the given values scaled to the given level (starting at 1). The attack and health
+    /// points are rounded to the nearest whole number.
+    pub fn scale(&self, values: EnemyValues, level: usize) -> EnemyValues {
+        let levels = (level - 1) as f32;
+        let grow = |base: usize, per_level: f32| (base as f32 * (1.0 + per_level * levels)).round() as usize;
+        EnemyValues {
+            attack: grow(values.attack, self.attack_per_level),
+            health_points: grow(values.health_points, self.health_per_level),
+            speed: values.speed + self.speed_per_level * levels,
+            ..values
+        }
+    }
+}
+
+/// Returns each type of enemy that can be generated on any of the given levels
+pub fn level_enemy_types(levels: &[&[EnemyType]]) -> Vec<EnemyType> {
+    let mut types = Vec::new();
+    for &enemy in levels.iter().flat_map(|level| level.iter()) {
+        if !types.contains(&enemy) {
+            types.push(enemy);
+        }
+    }
+    types
+}
+
 /// Configuration for each type of enemy
+///
+/// The values for a type of enemy are only needed (and its spritesheet only loaded) if it can be
+/// generated on at least one level.
 #[derive(Clone)]
 pub struct EnemyConfig {
-    pub rat: EnemyValues,
+    pub rat: Option<EnemyValues>,
+    /// The boss placed outside of the treasure chamber on the last level. No boss is placed if
+    /// this is None.
+    pub boss: Option<BossConfig>,
+    /// How the values of every enemy change from level to level
+    pub scaling: EnemyScaling,
     /// The choices for enemies to be generated on each level
     /// Array must be the same size as the number of levels
     pub levels: &'static [&'static [EnemyType]],
 }
 
 impl EnemyConfig {
-    /// Generates a random enemy for the given level
+    /// Generates a random enemy for the given level. Its values are already scaled to that level.
     pub fn random_enemy<R: Rng>(&self, rng: &mut R, level: usize) -> EnemyValues {
-        // Levels start at 1
-        let types = self.levels.get(level - 1)
-            .expect("bug: enemy config must have as many items as levels");
-        let enemy_type = *types.choose(rng)
+        let enemy_type = *self.level_types(level).choose(rng)
             .expect("bug: every level must have at least one type of enemy that can be generated");
-        self.values(enemy_type)
+        self.level_values(enemy_type, level)
+    }
+
+    /// Returns each type of enemy that can be generated on the given level
+    pub fn level_types(&self, level: usize) -> &'static [EnemyType] {
+        // Levels start at 1
+        self.levels.get(level - 1)
+            .expect("bug: enemy config must have as many items as levels")
+    }
+
+    /// Returns the values for the enemy of the given type scaled to the given level
+    pub fn level_values(&self, enemy: EnemyType, level: usize) -> EnemyValues {
+        self.scaling.scale(self.values(enemy), level)
+    }
+
+    /// Returns each type of enemy that can be generated on any level
+    pub fn enemy_types(&self) -> Vec<EnemyType> {
+        level_enemy_types(self.levels)
+    }
+
+    /// Returns true if there are values for the enemy of the given type
+    pub fn has_values(&self, enemy: EnemyType) -> bool {
+        self.get(enemy).is_some()
     }
 
     /// Returns the values for the enemy of the given type
     pub fn values(&self, enemy: EnemyType) -> EnemyValues {
+        self.get(enemy).cloned()
+            .expect("bug: enemy config must have values for every enemy that can be generated")
+    }
+
+    fn get(&self, enemy: EnemyType) -> Option<&EnemyValues> {
         use self::EnemyType::*;
         match enemy {
-            Rat => self.rat.clone(),
+            Rat => self.rat.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use self::EnemyType::*;
+
+    fn rat() -> EnemyValues {
+        use crate::assets::{TextureId, SpriteManager};
+        EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: AnimationManager::standard_character_animations(30, TextureId::placeholder(0), &mut SpriteManager::default()),
+            attack: 5,
+            speed: 3.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: 16, height: 16},
         }
     }
+
+    #[test]
+    fn scaled_by_level() {
+        let scaling = EnemyScaling {attack_per_level: 0.1, health_per_level: 0.15, speed_per_level: 0.05};
+
+        // The first level always uses the base values
+        let first = scaling.scale(rat(), 1);
+        assert_eq!((first.attack, first.health_points, first.speed), (5, 15, 3.0));
+
+        // 5 * (1 + 0.1 * 9) = 9.5, 15 * (1 + 0.15 * 9) = 35.25, 3 + 0.05 * 9 = 3.45
+        let tenth = scaling.scale(rat(), 10);
+        assert_eq!((tenth.attack, tenth.health_points), (10, 35));
+        assert!((tenth.speed - 3.45).abs() < 1e-5);
+        // Everything else stays the same
+        assert_eq!(tenth.hit_wait, 12);
+        assert_eq!(tenth.bounding_box, rat().bounding_box);
+
+        let unscaled = EnemyScaling::default().scale(rat(), 10);
+        assert_eq!((unscaled.attack, unscaled.health_points, unscaled.speed), (5, 15, 3.0));
+    }
+
+    #[test]
+    fn enemy_types_listed_once() {
+        assert_eq!(level_enemy_types(&[]), Vec::new());
+        assert_eq!(level_enemy_types(&[&[], &[Rat]]), vec![Rat]);
+        assert_eq!(level_enemy_types(&[&[Rat], &[Rat, Rat], &[Rat]]), vec![Rat]);
+    }
 }
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use super::{MapKey, EnemyType};
+use crate::assets::{MIN_TILE_SIZE, MAX_TILE_SIZE};
+
+/// The phases of level generation that can run out of attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenPhase {
+    Rooms,
+    Staircases,
+    MapFragments,
+    Prisoners,
+    Merchant,
+    WeaponChest,
+    TreasureKey,
+    DungeonMouth,
+    Enemies,
+    Boss,
+    /// Checking that every staircase can be reached once everything else has been placed
+    Reachability,
+}
+
+impl fmt::Display for GenPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::GenPhase::*;
+        write!(f, "{}", match self {
+            Rooms => "generating rooms",
+            Staircases => "placing staircases",
+            MapFragments => "placing map fragments",
+            Prisoners => "placing prisoners",
+            Merchant => "placing the merchant",
+            WeaponChest => "placing the weapon chest",
+            TreasureKey => "placing the treasure key",
+            DungeonMouth => "placing the mouth of the dungeon",
+            Enemies => "placing enemies",
+            Boss => "placing the boss",
+            Reachability => "checking that every staircase can be reached",
+        })
+    }
+}
+
+/// Represents when we have run out of attempts to generate the map from a given key
+/// This can happen if a loop trying to generate something runs too many times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RanOutOfAttempts {
+    /// The phase that was running when the attempts ran out
+    pub phase: GenPhase,
+    /// The number of attempts used up by that phase
+    pub attempts: usize,
+}
+
+/// Bounds with a minimum larger than their maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundsError;
+
+impl fmt::Display for BoundsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the minimum is larger than the maximum")
+    }
+}
+
+/// A configuration of the generator that could never produce a map
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The bounds with the given name have a minimum larger than their maximum
+    InvertedBounds {name: &'static str},
+    /// Even the smallest room would not fit in the map
+    RoomTooLarge {
+        /// Either "rows" or "cols"
+        dimension: &'static str,
+        room_min: usize,
+        map_size: usize,
+    },
+    /// The smallest room would not have any tiles that aren't part of its walls
+    RoomTooSmall {
+        /// Either "rows" or "cols"
+        dimension: &'static str,
+        room_min: usize,
+        /// The smallest number of tiles that a room can have in that dimension
+        min_size: usize,
+    },
+    /// More rooms are needed for the given setting than could ever be generated
+    NotEnoughRooms {name: &'static str, value: usize, max_rooms: usize},
+    /// Every room must have at least one door or some rooms will not be reachable
+    NoDoors,
+    /// The probability with the given name is not between 0.0 and 1.0
+    InvalidProbability {name: &'static str, value: f64},
+    /// The enemy configuration must specify the enemies for every level
+    EnemyLevelsMismatch {levels: usize, enemy_levels: usize},
+    /// An enemy that can be generated on some level has no values (e.g. was never loaded)
+    MissingEnemyValues {enemy: EnemyType},
+    /// Sprites cannot be scaled to the given tile size
+    UnsupportedTileSize {tile_size: u32},
+    /// Enemies would get weaker on later levels
+    NegativeEnemyScaling {name: &'static str, value: f32},
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ConfigError::*;
+        match self {
+            InvertedBounds {name} => write!(f, "the minimum of `{}` is larger than its maximum", name),
+            RoomTooLarge {dimension, room_min, map_size} => write!(f,
+                "the smallest room ({} {}) does not fit in the map ({} {})",
+                room_min, dimension, map_size, dimension),
+            RoomTooSmall {dimension, room_min, min_size} => write!(f,
+                "the minimum of `room_{}` is {} but rooms must have at least {} {} (including walls)",
+                dimension, room_min, min_size, dimension),
+            NotEnoughRooms {name, value, max_rooms} => write!(f,
+                "`{}` is {} but at most {} rooms are generated", name, value, max_rooms),
+            NoDoors => write!(f, "the minimum of `doors` must be at least 1"),
+            InvalidProbability {name, value} => write!(f,
+                "`{}` is {} but must be between 0.0 and 1.0", name, value),
+            EnemyLevelsMismatch {levels, enemy_levels} => write!(f,
+                "{} levels are generated but enemies are configured for {} levels", levels, enemy_levels),
+            MissingEnemyValues {enemy} => write!(f,
+                "`{:?}` enemies can be generated but have no values", enemy),
+            UnsupportedTileSize {tile_size} => write!(f,
+                "`tile_size` is {} but must be a multiple of 8 between {} and {}",
+                tile_size, MIN_TILE_SIZE, MAX_TILE_SIZE),
+            NegativeEnemyScaling {name, value} => write!(f,
+                "`enemy_scaling.{}` is {} but must not be negative", name, value),
+        }
+    }
+}
+
+/// The reasons that the generator can fail to produce a map
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenerateError {
+    /// The configuration could never produce a map
+    InvalidConfig(ConfigError),
+    /// Every attempt to generate the map from the given key ran out of attempts
+    RanOutOfAttempts {
+        key: MapKey,
+        /// The number of times the entire map was regenerated before giving up
+        tries: usize,
+        /// The failure from the final try
+        last_failure: RanOutOfAttempts,
+        /// The configuration values that affect the phase that failed
+        config: Vec<(&'static str, String)>,
+    },
+}
+
+impl From<ConfigError> for GenerateError {
+    fn from(err: ConfigError) -> Self {
+        GenerateError::InvalidConfig(err)
+    }
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::GenerateError::*;
+        match self {
+            InvalidConfig(err) => write!(f, "Invalid generator configuration: {}", err),
+            RanOutOfAttempts {key, tries, last_failure, config} => {
+                write!(f, "Never succeeded in generating a map with key `{}` after {} tries. \
+                    Ran out of attempts while {} ({} attempts).",
+                    key, tries, last_failure.phase, last_failure.attempts)?;
+                if !config.is_empty() {
+                    let config: Vec<_> = config.iter()
+                        .map(|(name, value)| format!("{} = {}", name, value))
+                        .collect();
+                    write!(f, " Relevant configuration: {}", config.join(", "))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
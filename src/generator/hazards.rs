@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+
+use rand::{Rng, rngs::StdRng};
+use specs::{World, Builder, Join, ReadStorage};
+
+use super::GameGenerator;
+use super::decorations::is_reserved;
+use super::world_helpers::world_contains_any_entity;
+use crate::components::{Position, Sprite, RenderLayer, Breakable, Cage};
+use crate::map::*;
+
+/// The minimum and maximum number of rows in a patch of hazards
+const PATCH_ROWS: (usize, usize) = (1, 2);
+/// The minimum and maximum number of columns in a patch of hazards
+const PATCH_COLS: (usize, usize) = (2, 3);
+
+/// Returns the tiles that cannot be walked through because something that gets in the way (e.g. a
+/// crate) is standing on them
+fn occupied_tiles(map: &FloorMap, world: &World) -> HashSet<TilePos> {
+    let (positions, breakables, cages) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Breakable>, ReadStorage<'_, Cage>)>();
+    (&positions, breakables.maybe(), cages.maybe()).join()
+        .filter(|&(_, breakable, cage)| breakable.is_some() || cage.is_some())
+        .map(|(&Position(pos), _, _)| map.world_to_tile_pos(pos))
+        .collect()
+}
+
+/// Returns true if the floor tiles around the given patch can all still reach each other without
+/// walking over the patch, any other hazard, or any of the occupied tiles. Any path through the
+/// patch can then go around it instead, so covering it never cuts off any part of the level.
+///
+/// Rooms overlap each other and corridors, so only checking the tiles within the boundary of the
+/// room containing the patch is not enough.
+fn can_walk_around(grid: &TileGrid, patch: TileRect, occupied: &HashSet<TilePos>) -> bool {
+    let is_open = |pos: TilePos| !patch.contains(pos) && grid.get(pos).is_floor()
+        && !grid.get(pos).is_hazard() && !occupied.contains(&pos);
+    let around: HashSet<_> = patch.tile_positions()
+        .flat_map(|pos| grid.adjacent_positions(pos))
+        .filter(|&pos| is_open(pos))
+        .collect();
+    let start = match around.iter().next() {
+        Some(&start) => start,
+        None => return true,
+    };
+    let reachable = grid.depth_first_search(start, |_, adj| is_open(adj));
+    around.iter().all(|pos| reachable.contains(pos))
+}
+
+impl<'a> GameGenerator<'a> {
+    /// Places a small patch of hazards in some of the rooms that the player has to fight through.
+    /// Hazards never cut off any part of a room, so the player can always walk around them.
+    pub(in super) fn place_hazards(&self, rng: &mut StdRng, map: &mut FloorMap, world: &mut World) {
+        let rooms: Vec<_> = map.rooms()
+            .filter(|(_, room)| room.can_contain_hazards())
+            .map(|(room_id, room)| (room_id, *room.boundary()))
+            .collect();
+        let occupied = occupied_tiles(map, world);
+
+        for (room_id, boundary) in rooms {
+            if !rng.gen_bool(self.hazard_chance) {
+                continue;
+            }
+
+            let hazard = if rng.gen() { Hazard::Water } else { Hazard::Pit };
+            let patch = match self.find_hazard_patch(rng, map, world, &occupied, room_id, boundary) {
+                Some(patch) => patch,
+                None => continue,
+            };
+
+            let tile_size = map.tile_size();
+            for pos in patch.tile_positions() {
+                map.grid_mut().get_mut(pos).set_hazard(hazard);
+
+                let mut animation = self.sprites.hazard_animation(hazard).clone();
+                // Able to use the thread rng here because this does NOT need to be deterministic
+                animation.current_step = rand::thread_rng().gen_range(0, animation.steps.len());
+                world.create_entity()
+                    .with(Position(pos.center(tile_size as i32)))
+                    .with(Sprite(animation.current_sprite()))
+                    .with(RenderLayer::FIXTURES)
+                    .with(animation)
+                    .build();
+            }
+        }
+    }
+
+    /// Attempts to find a patch of floor tiles in the given room that can be covered in hazards.
+    /// Every floor tile that could be reached before (including every doorway) must still be
+    /// reachable without walking over the patch.
+    fn find_hazard_patch(
+        &self,
+        rng: &mut StdRng,
+        map: &FloorMap,
+        world: &World,
+        occupied: &HashSet<TilePos>,
+        room_id: RoomId,
+        boundary: TileRect,
+    ) -> Option<TileRect> {
+        let grid = map.grid();
+        let tile_size = map.tile_size();
+        for _ in 0..self.attempts {
+            let size = GridSize {
+                rows: rng.gen_range(PATCH_ROWS.0, PATCH_ROWS.1 + 1),
+                cols: rng.gen_range(PATCH_COLS.0, PATCH_COLS.1 + 1),
+            };
+            let patch = TileRect::new(boundary.random_inner_tile(rng), size);
+            let can_cover = |pos: TilePos| {
+                if !boundary.contains(pos) || boundary.is_edge(pos) || !grid.get(pos).is_room_floor(room_id) {
+                    return false;
+                }
+                // Entrances are the floor tiles in the walls of the room
+                let beside_entrance = grid.adjacent_positions(pos)
+                    .any(|adj| boundary.is_edge(adj) && grid.get(adj).is_floor());
+                !beside_entrance && !is_reserved(map, room_id, pos)
+                    && !world_contains_any_entity(world, pos.tile_rect(tile_size))
+            };
+            if patch.tile_positions().all(can_cover) && can_walk_around(grid, patch, occupied) {
+                return Some(patch);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    use crate::components::EnemySpawn;
+    use crate::map_sprites::WallSprite;
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+    use crate::test_helpers::walled_room;
+
+    /// Returns the tiles that can be reached from the given tile without walking over a hazard or
+    /// through anything in the way, along with the tiles that could be reached if the hazards
+    /// could be walked over safely
+    fn reachable_tiles(map: &FloorMap, world: &World, start: TilePos) -> (HashSet<TilePos>, HashSet<TilePos>) {
+        let grid = map.grid();
+        let occupied = occupied_tiles(map, world);
+        let is_walkable = |pos: TilePos| grid.get(pos).is_floor() && !occupied.contains(&pos);
+        let avoiding_hazards = grid.depth_first_search(start, |_, adj| is_walkable(adj) && !grid.get(adj).is_hazard());
+        let through_hazards = grid.depth_first_search(start, |_, adj| is_walkable(adj))
+            .into_iter()
+            .filter(|&pos| !grid.get(pos).is_hazard())
+            .collect();
+        (avoiding_hazards, through_hazards)
+    }
+
+    #[test]
+    fn patches_never_split_room() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.hazard_chance = 1.0;
+
+        // The east and west halves of the room are only connected through a gap in a wall
+        let gap = TilePos {row: 2, col: 6};
+        let mut template = walled_room(5, 12, generator.tile_size);
+        for &pos in &[TilePos {row: 1, col: 6}, TilePos {row: 3, col: 6}] {
+            template.grid_mut().get_mut(pos).become_wall(WallSprite::default());
+        }
+
+        let mut placed = 0;
+        for seed in 0..50 {
+            let mut map = template.clone();
+            let (_, mut world) = setup_game_world();
+            generator.place_hazards(&mut StdRng::from_seed([seed; 32]), &mut map, &mut world);
+
+            let hazards: Vec<_> = map.grid().tile_positions().filter(|&pos| map.grid().get(pos).is_hazard()).collect();
+            assert!(!hazards.contains(&gap), "hazard placed in the only way through the room");
+            let (avoiding_hazards, through_hazards) = reachable_tiles(&map, &world, gap);
+            assert_eq!(avoiding_hazards, through_hazards);
+            if !hazards.is_empty() {
+                placed += 1;
+            }
+        }
+        // There is plenty of room for hazards on either side of the gap
+        assert!(placed > 0);
+    }
+
+    #[test]
+    fn generated_hazards_never_block_doorways() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        generator.hazard_chance = 1.0;
+        let game = generator.generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        let mut hazards = 0;
+        for level in &game.levels {
+            let map = level.world.read_resource::<FloorMap>();
+            // Every tile that could be reached if there were no hazards can still be reached
+            let (avoiding_hazards, through_hazards) = reachable_tiles(&map, &level.world, level.entrance());
+            assert_eq!(avoiding_hazards, through_hazards, "{:?}", *map);
+            for (room_id, room) in map.rooms() {
+                if !room.can_contain_hazards() {
+                    assert!(!room.boundary().tile_positions()
+                        .any(|pos| map.grid().get(pos).is_room_floor(room_id) && map.grid().get(pos).is_hazard()));
+                }
+            }
+            hazards += map.grid().tiles().filter(|(_, tile)| tile.is_hazard()).count();
+
+            // Enemies avoid hazards, so they never start on one
+            let positions = level.world.read_storage::<Position>();
+            let spawns = level.world.read_storage::<EnemySpawn>();
+            for (&Position(pos), _) in (&positions, &spawns).join() {
+                assert!(!map.grid().get(map.world_to_tile_pos(pos)).is_hazard());
+            }
+        }
+        assert!(hazards > 0);
+    }
+}
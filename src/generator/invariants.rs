@@ -0,0 +1,300 @@
+//! Rules that every generated level must follow. Each rule is checked by a Validator that also
+//! describes the rule, so the checks and the list of invariants can never disagree.
+
+use std::fmt;
+
+use specs::{Join, ReadExpect, ReadStorage};
+
+use crate::map::{FloorMap, TilePos};
+use crate::map_sprites::WallSpriteAlternate;
+use crate::components::{Position, Door, Stairs, EnemySpawn};
+
+use super::GenLevel;
+
+/// How serious it is for a level to break an invariant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The level may be impossible to finish
+    Error,
+    /// The level can still be played but looks or behaves strangely
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Severity::*;
+        match self {
+            Error => write!(f, "error"),
+            Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A place where a level breaks one of the invariants
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the validator that found the violation
+    pub validator: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]: {}", self.severity, self.validator, self.message)
+    }
+}
+
+/// Checks a single invariant of generated levels
+pub trait Validator: Sync {
+    /// A unique name for this validator
+    fn name(&self) -> &'static str;
+
+    /// A statement of the invariant that this validator checks
+    fn invariant(&self) -> &'static str;
+
+    fn severity(&self) -> Severity;
+
+    /// Returns every violation of the invariant in the given level
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation>;
+
+    /// Creates a violation of this validator's invariant with the given message
+    fn violation(&self, message: String) -> Violation {
+        Violation {validator: self.name(), severity: self.severity(), message}
+    }
+}
+
+/// Every validator, in the order they are checked
+pub static VALIDATORS: &[&dyn Validator] = &[
+    &StairsReachable,
+    &SolidBorder,
+    &DoorsInDoorways,
+    &TorchesAboveFloor,
+    &NoEnemiesInSafeZone,
+    &StairsInNormalRooms,
+];
+
+/// Checks the given level against every validator and returns all of the violations
+pub fn check_level(level: &GenLevel<'_, '_>) -> Vec<Violation> {
+    VALIDATORS.iter().flat_map(|validator| validator.check(level)).collect()
+}
+
+/// Returns a markdown table that lists every invariant
+pub fn invariants_markdown() -> String {
+    let mut table = String::from("| Name | Severity | Invariant |\n| --- | --- | --- |\n");
+    for validator in VALIDATORS {
+        table += &format!("| {} | {} | {} |\n", validator.name(), validator.severity(), validator.invariant());
+    }
+    table
+}
+
+/// Checks that the player can walk to every staircase down to the next level
+pub struct StairsReachable;
+
+impl Validator for StairsReachable {
+    fn name(&self) -> &'static str { "stairs_reachable" }
+
+    fn invariant(&self) -> &'static str {
+        "Every staircase to the next level can be reached on foot from where the player enters the level"
+    }
+
+    fn severity(&self) -> Severity { Severity::Error }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        level.stats().next_level_paths.iter().enumerate()
+            .filter(|(_, path)| path.is_none())
+            .map(|(i, _)| self.violation(format!("staircase {} to the next level cannot be reached", i + 1)))
+            .collect()
+    }
+}
+
+/// Checks that nothing can walk off the edge of the map
+pub struct SolidBorder;
+
+impl Validator for SolidBorder {
+    fn name(&self) -> &'static str { "solid_border" }
+
+    fn invariant(&self) -> &'static str {
+        "There are no floor tiles along the outer edge of the map"
+    }
+
+    fn severity(&self) -> Severity { Severity::Error }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        let map = level.world.read_resource::<FloorMap>();
+        let grid = map.grid();
+        grid.tile_positions_on_edges(TilePos {row: 0, col: 0}, grid.dimensions())
+            .filter(|&pos| grid.get(pos).is_floor())
+            .map(|pos| self.violation(format!("floor tile on the edge of the map at {:?}", pos)))
+            .collect()
+    }
+}
+
+/// Checks that every door actually joins two areas
+pub struct DoorsInDoorways;
+
+impl Validator for DoorsInDoorways {
+    fn name(&self) -> &'static str { "doors_in_doorways" }
+
+    fn invariant(&self) -> &'static str {
+        "Every door is on a floor tile that has floor tiles on two opposite sides"
+    }
+
+    fn severity(&self) -> Severity { Severity::Error }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        let (map, positions, doors) = level.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Door>,
+        )>();
+        let grid = map.grid();
+        let is_floor = |pos: Option<TilePos>| pos.map(|pos| grid.get(pos).is_floor()).unwrap_or(false);
+
+        (&positions, &doors).join().filter_map(|(&Position(pos), _)| {
+            let pos = map.world_to_tile_pos(pos);
+            let north_south = is_floor(pos.adjacent_north()) && is_floor(pos.adjacent_south(grid.rows_len()));
+            let east_west = is_floor(pos.adjacent_east(grid.cols_len())) && is_floor(pos.adjacent_west());
+            if grid.get(pos).is_floor() && (north_south || east_west) {
+                None
+            } else {
+                Some(self.violation(format!("door at {:?} does not join two areas", pos)))
+            }
+        }).collect()
+    }
+}
+
+/// Checks that torches are only put on walls that can be seen
+pub struct TorchesAboveFloor;
+
+impl Validator for TorchesAboveFloor {
+    fn name(&self) -> &'static str { "torches_above_floor" }
+
+    fn invariant(&self) -> &'static str {
+        "Every wall with a torch sprite has a floor tile directly south of it"
+    }
+
+    fn severity(&self) -> Severity { Severity::Warning }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        let map = level.world.read_resource::<FloorMap>();
+        let grid = map.grid();
+        grid.tile_positions()
+            .filter(|&pos| grid.get(pos).is_wall() && grid.get(pos).wall_sprite().alt == WallSpriteAlternate::TorchLit)
+            .filter(|&pos| {
+                let south = pos.adjacent_south(grid.rows_len());
+                !south.map(|south| grid.get(south).is_floor()).unwrap_or(false)
+            })
+            .map(|pos| self.violation(format!("torch at {:?} is not above a floor tile", pos)))
+            .collect()
+    }
+}
+
+/// Checks that the player is safe from enemies at the very start of a level and while shopping
+pub struct NoEnemiesInSafeZone;
+
+impl Validator for NoEnemiesInSafeZone {
+    fn name(&self) -> &'static str { "no_enemies_in_safe_zone" }
+
+    fn invariant(&self) -> &'static str {
+        "No enemy spawn point is on the floor of the room that the player starts in or of a merchant room"
+    }
+
+    fn severity(&self) -> Severity { Severity::Warning }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        let (map, positions, spawns) = level.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, EnemySpawn>,
+        )>();
+        (&positions, &spawns).join()
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .filter(|&pos| map.is_safe_zone(pos))
+            .map(|pos| self.violation(format!("enemy spawn point at {:?} is in a safe zone", pos)))
+            .collect()
+    }
+}
+
+/// Checks that special rooms (e.g. the treasure chamber or a merchant room) never lead off the level
+pub struct StairsInNormalRooms;
+
+impl Validator for StairsInNormalRooms {
+    fn name(&self) -> &'static str { "stairs_in_normal_rooms" }
+
+    fn invariant(&self) -> &'static str {
+        "Every staircase is on the floor of a normal room"
+    }
+
+    fn severity(&self) -> Severity { Severity::Warning }
+
+    fn check(&self, level: &GenLevel<'_, '_>) -> Vec<Violation> {
+        let (map, positions, stairs) = level.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Stairs>,
+        )>();
+        (&positions, &stairs).join()
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .filter(|&pos| {
+                let room_id = map.grid().get(pos).floor_room_id();
+                !room_id.map(|room_id| map.room(room_id).can_contain_to_next_level()).unwrap_or(false)
+            })
+            .map(|pos| self.violation(format!("staircase at {:?} is not in a normal room", pos)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::map::Tile;
+    use crate::map_sprites::FloorSprite;
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+
+    #[test]
+    fn every_validator_registered_once() {
+        let names: Vec<_> = VALIDATORS.iter().map(|validator| validator.name()).collect();
+        let unique: HashSet<_> = names.iter().collect();
+        assert_eq!(unique.len(), names.len());
+        for name in &[StairsReachable.name(), SolidBorder.name(), DoorsInDoorways.name(), TorchesAboveFloor.name(), NoEnemiesInSafeZone.name(), StairsInNormalRooms.name()] {
+            assert!(names.contains(name), "{} is not registered", name);
+        }
+
+        // Every invariant gets a row in the table after the header
+        assert_eq!(invariants_markdown().lines().count(), VALIDATORS.len() + 2);
+    }
+
+    #[test]
+    fn generated_levels_have_no_violations() {
+        let sprites = test_sprites();
+        for _ in 0..3 {
+            let game = test_generator(&sprites).generate(setup_game_world)
+                .expect("bug: should be able to generate a map with a valid config");
+            for level in &game.levels {
+                assert_eq!(check_level(level), &[]);
+            }
+        }
+    }
+
+    #[test]
+    fn violations_carry_validator_name() {
+        let sprites = test_sprites();
+        let game = test_generator(&sprites).generate(setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+        let level = &game.levels[0];
+        {
+            let mut map = level.world.write_resource::<FloorMap>();
+            let room_id = map.rooms().next().unwrap().0;
+            map.grid_mut().place_tile(TilePos {row: 0, col: 0}, Tile::new_floor(room_id, FloorSprite::default()));
+        }
+
+        let violations = check_level(level);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].validator, SolidBorder.name());
+        assert_eq!(violations[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,182 @@
+use rand::{Rng, rngs::StdRng};
+use specs::{World, ReadStorage, WriteStorage, Join};
+
+use super::GameGenerator;
+use super::doorways::place_entrance_walls;
+use crate::map_sprites::WallSpriteAlternate;
+use crate::components::{Position, Door, DoorOrientation};
+use crate::map::*;
+
+impl<'a> GameGenerator<'a> {
+    /// Randomly mirrors or rotates the entire layout of the level along with every entity that has
+    /// been placed on it so far. Returns the transform that was applied (if any).
+    ///
+    /// This runs right after the rooms are connected so that everything that depends on which
+    /// side of a wall faces into a room (stairs, wall mounted items, wall sprites, torches) is
+    /// placed on the final layout and never needs to be fixed up afterwards.
+    pub(in super) fn transform_layout(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Option<LayoutTransform> {
+        if !rng.gen_bool(self.layout_transform_chance) {
+            return None;
+        }
+        let transform = rng.gen();
+        apply_layout_transform(transform, map, world);
+        Some(transform)
+    }
+}
+
+/// Applies the given transform to the map and to the positions of all entities in the world
+fn apply_layout_transform(transform: LayoutTransform, map: &mut FloorMap, world: &mut World) {
+    let size = map.grid().dimensions();
+    let tile_size = map.tile_size();
+
+    // Entrance walls only go beside doorways that have floor below them, so they can't just be
+    // moved along with the walls. They are removed and then placed again once the layout has
+    // been transformed.
+    let entrance_walls: Vec<_> = map.grid().tile_positions()
+        .filter(|&pos| map.grid().get(pos).is_wall())
+        .filter(|&pos| matches!(map.grid().get(pos).wall_sprite().alt,
+            WallSpriteAlternate::EntranceLeft | WallSpriteAlternate::EntranceRight))
+        .collect();
+    for pos in entrance_walls {
+        map.grid_mut().get_mut(pos).wall_sprite_mut().alt = WallSpriteAlternate::default();
+    }
+
+    map.transform_layout(transform);
+
+    let (mut positions, doors, orientations) = world.system_data::<(WriteStorage<'_, Position>, ReadStorage<'_, Door>, ReadStorage<'_, DoorOrientation>)>();
+    for Position(pos) in (&mut positions).join() {
+        *pos = transform.point(*pos, size, tile_size);
+    }
+
+    // Mirroring and rotating by 180 degrees never changes the orientation of a wall
+    let doorways: Vec<_> = (&positions, &doors, &orientations).join()
+        .filter(|&(_, _, &orientation)| orientation == DoorOrientation::Horizontal)
+        .map(|(&Position(pos), _, _)| map.world_to_tile_pos(pos))
+        .collect();
+    for edge in doorways {
+        place_entrance_walls(map, edge);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Builder;
+
+    use crate::generator::doorways::doorway_orientation;
+
+    use crate::map_sprites::{FloorSprite, WallSprite};
+
+    /// Two rooms side by side connected by a doorway in the wall between them along with a room
+    /// below them connected to the left room by a doorway in a horizontal wall
+    fn test_map(world: &mut World) -> FloorMap {
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 14, cols: 15}, tile_size);
+        let rects = [
+            TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows: 6, cols: 8}),
+            TileRect::new(TilePos {row: 1, col: 7}, GridSize {rows: 5, cols: 7}),
+            TileRect::new(TilePos {row: 5, col: 1}, GridSize {rows: 8, cols: 6}),
+        ];
+        let mut room_ids = Vec::new();
+        for &rect in &rects {
+            let room_id = map.add_room(rect);
+            room_ids.push(room_id);
+            for pos in rect.tile_positions() {
+                map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+            }
+        }
+        for &rect in &rects {
+            for pos in rect.edge_positions() {
+                map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+            }
+        }
+
+        world.register::<Position>();
+        world.register::<Door>();
+        world.register::<DoorOrientation>();
+        let doorways = [
+            (TilePos {row: 3, col: 7}, room_ids[0]),
+            (TilePos {row: 5, col: 3}, room_ids[0]),
+        ];
+        for &(edge, room_id) in &doorways {
+            let orientation = doorway_orientation(map.grid(), edge, *map.room(room_id).boundary());
+            map.grid_mut().get_mut(edge).become_floor(room_id, FloorSprite::default());
+            world.create_entity()
+                .with(Position(edge.center(tile_size as i32)))
+                .with(Door::Closed)
+                .with(orientation)
+                .build();
+            if orientation == DoorOrientation::Horizontal {
+                place_entrance_walls(&mut map, edge);
+            }
+        }
+        map
+    }
+
+    fn door_positions(world: &World) -> Vec<(i32, i32)> {
+        let mut positions: Vec<_> = world.read_storage::<Position>().join()
+            .map(|&Position(pos)| (pos.x(), pos.y()))
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    #[test]
+    fn transforming_twice_restores_level() {
+        let transforms = [
+            LayoutTransform::MirrorHorizontal,
+            LayoutTransform::MirrorVertical,
+            LayoutTransform::Rotate180,
+        ];
+        for &transform in &transforms {
+            let mut world = World::new();
+            let mut map = test_map(&mut world);
+            let original_map = map.clone();
+            let original_doors = door_positions(&world);
+
+            apply_layout_transform(transform, &mut map, &mut world);
+            assert_ne!(map, original_map);
+            // Doors still sit in doorways and the rooms still contain their floor tiles
+            for &(x, y) in &door_positions(&world) {
+                let edge = map.world_to_tile_pos((x, y).into());
+                assert!(map.grid().get(edge).is_floor());
+                assert!(map.grid().is_room_entrance(edge));
+            }
+            for (room_id, room) in map.rooms() {
+                assert_eq!(map.room_exact_area(room_id), original_map.room_exact_area(room_id));
+                assert!(room.boundary().edge_positions().all(|pos| !map.grid().get(pos).is_empty()));
+            }
+
+            apply_layout_transform(transform, &mut map, &mut world);
+            assert_eq!(map, original_map);
+            assert_eq!(door_positions(&world), original_doors);
+        }
+    }
+
+    #[test]
+    fn entrance_walls_swap_sides() {
+        let mut world = World::new();
+        let mut map = test_map(&mut world);
+        let alt = |map: &FloorMap, row, col| map.grid().get(TilePos {row, col}).wall_sprite().alt;
+        assert_eq!(alt(&map, 5, 2), WallSpriteAlternate::EntranceLeft);
+        assert_eq!(alt(&map, 5, 4), WallSpriteAlternate::EntranceRight);
+
+        apply_layout_transform(LayoutTransform::MirrorHorizontal, &mut map, &mut world);
+        assert_eq!(alt(&map, 5, 10), WallSpriteAlternate::EntranceLeft);
+        assert_eq!(alt(&map, 5, 12), WallSpriteAlternate::EntranceRight);
+
+        // The doorway ends up in the top wall of the room that used to be above it, so there is
+        // still floor below the walls beside it
+        let mut world = World::new();
+        let mut map = test_map(&mut world);
+        apply_layout_transform(LayoutTransform::Rotate180, &mut map, &mut world);
+        assert_eq!(alt(&map, 8, 10), WallSpriteAlternate::EntranceLeft);
+        assert_eq!(alt(&map, 8, 12), WallSpriteAlternate::EntranceRight);
+    }
+}
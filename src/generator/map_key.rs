@@ -1,5 +1,6 @@
 use std::str::FromStr;
 use std::fmt;
+use std::error::Error;
 
 use rand::{
     Rng,
@@ -26,6 +27,26 @@ pub enum InvalidMapKey {
     DecodeError(DecodeError),
 }
 
+impl fmt::Display for InvalidMapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::InvalidMapKey::*;
+        match self {
+            InvalidLength => write!(f, "map key has the wrong length"),
+            DecodeError(err) => write!(f, "map key is not valid base64: {}", err),
+        }
+    }
+}
+
+impl Error for InvalidMapKey {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::InvalidMapKey::*;
+        match self {
+            InvalidLength => None,
+            DecodeError(err) => Some(err),
+        }
+    }
+}
+
 /// The seed of the random number generator
 type Seed = <StdRng as SeedableRng>::Seed;
 
@@ -37,15 +58,15 @@ type Seed = <StdRng as SeedableRng>::Seed;
 ///
 /// ```rust
 /// # use rand::random;
-/// # use map_generator::MapKey;
+/// # use caves::generator::MapKey;
 /// let map_key: MapKey = random();
 /// ```
 ///
 /// MapKeys can be parsed from strings using `.parse()`:
 ///
 /// ```rust,no_run
-/// # use map_generator::MapKey;
-/// let map_key: MapKey = "yourvalidmapkey".parse();
+/// # use caves::generator::MapKey;
+/// let map_key: Result<MapKey, _> = "yourvalidmapkey".parse();
 /// ```
 ///
 /// You can get the string representation of a MapKey either with `.to_string()` or
@@ -53,7 +74,7 @@ type Seed = <StdRng as SeedableRng>::Seed;
 ///
 /// ```rust,no_run
 /// # use rand::random;
-/// # use map_generator::MapKey;
+/// # use caves::generator::MapKey;
 /// let map_key: MapKey = random();
 /// assert_eq!(format!("{}", map_key), map_key.to_string());
 /// ```
@@ -64,6 +85,16 @@ impl MapKey {
     pub(in super) fn to_rng(self) -> StdRng {
         StdRng::from_seed(self.0)
     }
+
+    /// Returns a random number generator for events that happen while the given level is being
+    /// played. Reproducible for the same key and level, regardless of how the map was generated.
+    pub(in super) fn level_rng(self, level: usize) -> StdRng {
+        let mut seed = self.0;
+        for (byte, level_byte) in seed.iter_mut().zip((level as u64).to_le_bytes().iter()) {
+            *byte ^= level_byte;
+        }
+        StdRng::from_seed(seed)
+    }
 }
 
 impl Distribution<MapKey> for Standard {
@@ -80,7 +111,7 @@ impl fmt::Debug for MapKey {
 
 impl fmt::Display for MapKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", base64::encode_config(&self.0, *SEED_ENCODER_CONFIG))
+        write!(f, "{}", base64::encode_config(self.0, *SEED_ENCODER_CONFIG))
     }
 }
 
@@ -90,7 +121,7 @@ impl FromStr for MapKey {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut key: Seed = Default::default();
         let decoded = base64::decode_config(s, *SEED_ENCODER_CONFIG)
-            .map_err(|err| InvalidMapKey::DecodeError(err))?;
+            .map_err(InvalidMapKey::DecodeError)?;
         if decoded.len() != key.len() {
             return Err(InvalidMapKey::InvalidLength);
         }
@@ -129,4 +160,16 @@ mod tests {
             prev_key_encoded = encoded;
         }
     }
+
+    #[test]
+    fn level_rng_reproducible() {
+        let key: MapKey = random();
+        let rolls = |level| key.level_rng(level).gen::<[u64; 4]>();
+
+        assert_eq!(rolls(1), rolls(1));
+        assert_eq!(rolls(5), rolls(5));
+        assert_ne!(rolls(1), rolls(2));
+        // Independent from the generator used to create the map
+        assert_ne!(rolls(1), key.to_rng().gen::<[u64; 4]>());
+    }
 }
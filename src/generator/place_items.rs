@@ -1,13 +1,37 @@
-use rand::{rngs::StdRng, seq::SliceRandom};
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
 use specs::{World, Builder, ReadStorage, Join};
 
-use super::{GameGenerator, RanOutOfAttempts};
+use super::{GameGenerator, RanOutOfAttempts, GenPhase};
 use super::world_helpers::world_contains_any_entity;
 use crate::map::TilePos;
 use crate::map_sprites::WallSprite;
-use crate::components::{Position, Ghost, BoundingBox, Sprite, Stairs};
+use crate::components::{Position, Ghost, BoundingBox, Sprite, Stairs, DungeonMouth, MapFragment, Cage, Chest, Item, Merchant, RenderLayer, WeaponKind};
 use crate::map::*;
 
+/// The items sold by every merchant along with their prices (in gold)
+const MERCHANT_STOCK: [(Item, u32); 3] = [
+    (Item::Potion {stength: 5}, 10),
+    (Item::Potion {stength: 10}, 25),
+    (Item::RoomKey, 30),
+];
+
+/// Describes the objects to place with `place_object_in_rooms` and how to place them
+struct ObjectPlacement<F, N, V, P> {
+    /// The generation phase reported if the objects cannot all be placed
+    phase: GenPhase,
+    /// Returns true for the rooms that an object may be placed in
+    room_filter: F,
+    /// The number of rooms to place an object in
+    nrooms: usize,
+    /// Picks a random wall tile on the edge of the given room boundary
+    next_pos: N,
+    /// Any validation of the chosen room tile beyond what `find_place` already checks
+    extra_validation: V,
+    /// Creates the object given its room tile, the wall tile beside it, and the number of
+    /// objects placed so far
+    place_object: P,
+}
+
 fn validate_chosen_staircase(grid: &TileGrid, world: &World, pos: TilePos, tile_size: u32) -> bool {
     // The staircase cannot be directly beside another staircase. It also cannot be beside
     // a tile that is beside an entrance or else that entrance will get blocked by a wall
@@ -55,8 +79,14 @@ impl<'a> GameGenerator<'a> {
             self.place_stairs(world, map, obj_pos, wall_pos, Stairs::ToNextLevel {id});
             self.surround_stairways(obj_pos, map);
         };
-        self.place_object_in_rooms(rng, map, world, valid_rooms, self.next_prev_tiles,
-            next_pos, validate_chosen_staircase, place_object)?;
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::Staircases,
+            room_filter: valid_rooms,
+            nrooms: self.next_prev_tiles,
+            next_pos,
+            extra_validation: validate_chosen_staircase,
+            place_object,
+        })?;
         Ok(())
     }
 
@@ -74,8 +104,246 @@ impl<'a> GameGenerator<'a> {
             self.place_stairs(world, map, obj_pos, wall_pos, Stairs::ToPrevLevel {id});
             self.surround_stairways(obj_pos, map);
         };
-        self.place_object_in_rooms(rng, map, world, valid_rooms, self.next_prev_tiles,
-            next_pos, validate_chosen_staircase, place_object)?;
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::Staircases,
+            room_filter: valid_rooms,
+            nrooms: self.next_prev_tiles,
+            next_pos,
+            extra_validation: validate_chosen_staircase,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    /// Places the way out of the caves in the room that the player starts in
+    pub(in super) fn place_dungeon_mouth(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // No system creates the mouth of the dungeon, so its storage may not have been registered
+        world.register::<DungeonMouth>();
+
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.is_player_start();
+        // Placed just like a staircase up since that is how the player got down here
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_left_vertical_edge_tile(rng);
+
+        let place_object = |world: &mut World, map: &mut FloorMap, obj_pos: TilePos, _, _| {
+            let pos = obj_pos.center(map.tile_size() as i32);
+            world.create_entity()
+                .with(Ghost)
+                .with(Position(pos))
+                .with(BoundingBox::Full {width: self.tile_size, height: self.tile_size})
+                .with(DungeonMouth)
+                .with(Sprite(self.sprites.staircase_up_right()))
+                .with(RenderLayer::FIXTURES)
+                .build();
+            self.surround_stairways(obj_pos, map);
+        };
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::DungeonMouth,
+            room_filter: valid_rooms,
+            nrooms: 1,
+            next_pos,
+            extra_validation: validate_chosen_staircase,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    pub(in super) fn place_map_fragments(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.can_contain_map_fragment();
+        // Map fragments are mounted on the top wall so that they are visible from inside the room
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_top_horizontal_edge_tile(rng);
+        let no_extra_validation = |_: &TileGrid, _: &World, _: TilePos, _: u32| true;
+
+        let place_object = |world: &mut World, map: &mut FloorMap, _, wall_pos: TilePos, _| {
+            let pos = wall_pos.center(map.tile_size() as i32);
+            world.create_entity()
+                .with(Ghost)
+                .with(Position(pos))
+                .with(BoundingBox::Full {width: self.tile_size, height: self.tile_size})
+                .with(MapFragment {rooms: self.map_fragment_rooms})
+                .with(Sprite(self.sprites.map_fragment()))
+                .with(RenderLayer::ITEMS)
+                .build();
+        };
+        let nfragments = self.map_fragments.gen(rng);
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::MapFragments,
+            room_filter: valid_rooms,
+            nrooms: nfragments,
+            next_pos,
+            extra_validation: no_extra_validation,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    pub(in super) fn place_prisoner(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // Prisoners are rare, so most levels do not have one
+        if !rng.gen_bool(self.prisoner_chance) {
+            return Ok(());
+        }
+
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.can_contain_prisoner();
+        // Cages are placed against the top wall so that they do not block the middle of the room
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_top_horizontal_edge_tile(rng);
+        let no_extra_validation = |_: &TileGrid, _: &World, _: TilePos, _: u32| true;
+
+        let place_object = |world: &mut World, map: &mut FloorMap, obj_pos: TilePos, _, _| {
+            let pos = obj_pos.center(map.tile_size() as i32);
+            world.create_entity()
+                .with(Position(pos))
+                // Same bounding box as the player since the prisoner can walk around once freed
+                .with(BoundingBox::BottomHalf {width: self.tile_size, height: self.tile_size / 2})
+                .with(Cage {hits_remaining: self.cage_hits})
+                .with(Sprite(self.sprites.cage()))
+                .with(RenderLayer::CHARACTERS)
+                .with(self.prisoner_animations.clone())
+                .build();
+        };
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::Prisoners,
+            room_filter: valid_rooms,
+            nrooms: 1,
+            next_pos,
+            extra_validation: no_extra_validation,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    /// Places a merchant in the merchant room, if this level has one
+    pub(in super) fn place_merchant(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // No system creates merchants, so their storage may not have been registered yet
+        world.register::<Merchant>();
+
+        let nmerchants = map.rooms().filter(|(_, room)| room.room_type() == RoomType::Merchant).count();
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.room_type() == RoomType::Merchant;
+        // Merchants stand against the top wall so that they do not block the middle of the room
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_top_horizontal_edge_tile(rng);
+        let no_extra_validation = |_: &TileGrid, _: &World, _: TilePos, _: u32| true;
+
+        let place_object = |world: &mut World, map: &mut FloorMap, obj_pos: TilePos, _, _| {
+            let pos = obj_pos.center(map.tile_size() as i32);
+            // There is no spritesheet for merchants yet, so they look like a freed prisoner
+            let animations = &self.prisoner_animations;
+            world.create_entity()
+                .with(Position(pos))
+                // Solid so that the player cannot walk through it, but it has no health so it can
+                // never be hurt
+                .with(BoundingBox::BottomHalf {width: self.tile_size, height: self.tile_size / 2})
+                .with(Merchant {stock: MERCHANT_STOCK.to_vec()})
+                .with(Sprite(animations.default_sprite()))
+                .with(animations.default_animation())
+                .with(RenderLayer::CHARACTERS)
+                .build();
+        };
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::Merchant,
+            room_filter: valid_rooms,
+            nrooms: nmerchants,
+            next_pos,
+            extra_validation: no_extra_validation,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    /// Places a chest with a weapon other than the one that the player starts with in one of the
+    /// normal rooms, if this level has one
+    pub(in super) fn place_weapon_chest(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // Weapon chests are rare, so most levels do not have one
+        if !rng.gen_bool(self.weapon_chest_chance) {
+            return Ok(());
+        }
+        let weapons: Vec<_> = WeaponKind::ALL.iter().filter(|&&kind| kind != WeaponKind::default()).collect();
+        let weapon = **weapons.choose(rng).expect("bug: should be at least one weapon to find");
+
+        // No system creates chests, so their storage may not have been registered yet
+        world.register::<Chest>();
+
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.can_contain_weapon_chest();
+        // Chests are placed against the top wall so that they do not block the middle of the room
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_top_horizontal_edge_tile(rng);
+        let no_extra_validation = |_: &TileGrid, _: &World, _: TilePos, _: u32| true;
+
+        let place_object = |world: &mut World, map: &mut FloorMap, obj_pos: TilePos, _, _| {
+            let pos = obj_pos.center(map.tile_size() as i32);
+            world.create_entity()
+                .with(Position(pos))
+                .with(BoundingBox::Full {width: self.tile_size, height: self.tile_size})
+                .with(Chest::Item(Item::Weapon(weapon)))
+                .with(Sprite(self.sprites.chest()))
+                .with(RenderLayer::ITEMS)
+                .build();
+        };
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::WeaponChest,
+            room_filter: valid_rooms,
+            nrooms: 1,
+            next_pos,
+            extra_validation: no_extra_validation,
+            place_object,
+        })?;
+        Ok(())
+    }
+
+    /// Places the key to the treasure chamber in a chest in the challenge room
+    pub(in super) fn place_treasure_key(
+        &self,
+        rng: &mut StdRng,
+        map: &mut FloorMap,
+        world: &mut World,
+    ) -> Result<(), RanOutOfAttempts> {
+        // No system creates chests, so their storage may not have been registered yet
+        world.register::<Chest>();
+
+        let valid_rooms = |(_, r): &(RoomId, &Room)| r.room_type() == RoomType::Challenge;
+        // Chests are placed against the top wall so that they do not block the middle of the room
+        let next_pos = |rng: &mut StdRng, rect: TileRect| rect.random_top_horizontal_edge_tile(rng);
+        let no_extra_validation = |_: &TileGrid, _: &World, _: TilePos, _: u32| true;
+
+        let place_object = |world: &mut World, map: &mut FloorMap, obj_pos: TilePos, _, _| {
+            let pos = obj_pos.center(map.tile_size() as i32);
+            world.create_entity()
+                .with(Position(pos))
+                .with(BoundingBox::Full {width: self.tile_size, height: self.tile_size})
+                .with(Chest::Item(Item::TreasureKey))
+                .with(Sprite(self.sprites.chest()))
+                .with(RenderLayer::ITEMS)
+                .build();
+        };
+        self.place_object_in_rooms(rng, map, world, ObjectPlacement {
+            phase: GenPhase::TreasureKey,
+            room_filter: valid_rooms,
+            nrooms: 1,
+            next_pos,
+            extra_validation: no_extra_validation,
+            place_object,
+        })?;
         Ok(())
     }
 
@@ -108,6 +376,7 @@ impl<'a> GameGenerator<'a> {
             .with(BoundingBox::Full {width: stair_size, height: stair_size})
             .with(stairs)
             .with(Sprite(sprite))
+            .with(RenderLayer::FIXTURES)
             .build();
     }
 
@@ -122,25 +391,30 @@ impl<'a> GameGenerator<'a> {
         }
     }
 
-    /// Places `nrooms` copies of a TileObject into `nrooms` randomly choosen rooms from rooms
-    fn place_object_in_rooms(
+    /// Places an object into each of `nrooms` randomly choosen rooms. Objects are entities placed
+    /// on a room tile adjacent to a wall of the room.
+    fn place_object_in_rooms<F, N, V, P>(
         &self,
         rng: &mut StdRng,
         map: &mut FloorMap,
         world: &mut World,
-        room_filter: impl FnMut(&(RoomId, &Room)) -> bool,
-        nrooms: usize,
-        mut next_pos: impl FnMut(&mut StdRng, TileRect) -> TilePos,
-        mut extra_validation: impl FnMut(&TileGrid, &World, TilePos, u32) -> bool,
-        mut place_object: impl FnMut(&mut World, &mut FloorMap, TilePos, TilePos, usize),
-    ) -> Result<(), RanOutOfAttempts> {
+        placement: ObjectPlacement<F, N, V, P>,
+    ) -> Result<(), RanOutOfAttempts>
+        where F: FnMut(&(RoomId, &Room)) -> bool,
+              N: FnMut(&mut StdRng, TileRect) -> TilePos,
+              V: FnMut(&TileGrid, &World, TilePos, u32) -> bool,
+              P: FnMut(&mut World, &mut FloorMap, TilePos, TilePos, usize) {
+        let ObjectPlacement {phase, room_filter, nrooms, mut next_pos, mut extra_validation, mut place_object} = placement;
         // To do this using choose we would need to allocate anyway, so we might as well just use
         // shuffle to do all the random choosing at once
         let mut rooms: Vec<_> = map.rooms()
             .filter(room_filter)
             .map(|(id, r)| (id, *r.boundary()))
             .collect();
-        assert!(rooms.len() >= nrooms, "Not enough rooms to place items");
+        // Not enough rooms to place items
+        if rooms.len() < nrooms {
+            return Err(RanOutOfAttempts {phase, attempts: 0});
+        }
         rooms.shuffle(rng);
 
         let tile_size = map.tile_size();
@@ -152,19 +426,16 @@ impl<'a> GameGenerator<'a> {
         // cycles trying to find a place. This method efficiently moves on to the next room as soon
         // as a single attempt fails, ensuring that the search will either make progress or fail
         // as soon as we reach the attempt limit.
-        let mut attempts = 0;
-
         let mut placed = 0;
-        for (room_id, rect) in rooms.into_iter().cycle() {
+        for (attempts, (room_id, rect)) in rooms.into_iter().cycle().enumerate() {
             // Found enough places
             if placed >= nrooms {
                 break;
             }
 
             if attempts >= self.attempts {
-                return Err(RanOutOfAttempts);
+                return Err(RanOutOfAttempts {phase, attempts});
             }
-            attempts += 1;
 
             // Pick a random point on one of the edges of the room
             let pos = next_pos(rng, rect);
@@ -0,0 +1,241 @@
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+
+use specs::{Component, Join, ReadExpect, ReadStorage};
+
+use crate::components::{Position, Enemy, EnemySpawn, Chest, Prop};
+use crate::map::*;
+use crate::map_sprites::WallSpriteAlternate;
+
+use super::GenLevel;
+
+/// The largest fraction of the floor of a room next to the entrance of a level that should be
+/// taken up by enemies. The player should have some room to breathe when they first arrive.
+pub const NEAR_ENTRANCE_ENEMY_RATIO: f64 = 0.3;
+
+/// A room that goes against one of the guidelines for how a room should feel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomWarning {
+    /// The room is next to the entrance and more than NEAR_ENTRANCE_ENEMY_RATIO of its floor is
+    /// taken up by enemies
+    CrowdedNearEntrance,
+    /// More of the floor of the room is taken up by enemies than `max_room_enemy_area` allows
+    OverMaxEnemyArea,
+    /// The room cannot be reached from the entrance
+    Unreachable,
+}
+
+impl fmt::Display for RoomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::RoomWarning::*;
+        write!(f, "{}", match self {
+            CrowdedNearEntrance => "crowded near entrance",
+            OverMaxEnemyArea => "over max enemy area",
+            Unreachable => "unreachable",
+        })
+    }
+}
+
+/// What was placed in one room of a generated level. Useful for tuning the generator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomReport {
+    pub room_id: RoomId,
+    pub room_type: RoomType,
+    /// The number of floor tiles in the room
+    pub floor_area: usize,
+    /// The number of enemies in the room, including spawn points that have not spawned yet
+    pub enemies: usize,
+    /// The fraction of the floor area of the room taken up by enemies. Each enemy takes up a tile.
+    pub enemy_ratio: f64,
+    pub chests: usize,
+    /// The number of props and pillars in the room
+    pub decorations: usize,
+    /// The number of lit torches on the walls of the room
+    pub torches: usize,
+    /// The length (in tiles) of the shortest path from the entrance of the level to any floor tile
+    /// of the room. None if the room cannot be reached.
+    pub distance: Option<usize>,
+    pub warnings: Vec<RoomWarning>,
+}
+
+/// Counts the entities with the given component by the room that they are in. Entities that are
+/// not on a floor tile are not counted.
+fn count_by_room<T: Component>(
+    map: &FloorMap,
+    positions: &ReadStorage<'_, Position>,
+    storage: &ReadStorage<'_, T>,
+) -> HashMap<RoomId, usize> {
+    let mut counts = HashMap::new();
+    for (&Position(pos), _) in (positions, storage).join() {
+        if let Some(room_id) = map.grid().get(map.world_to_tile_pos(pos)).floor_room_id() {
+            *counts.entry(room_id).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+impl<'a, 'b> GenLevel<'a, 'b> {
+    /// Reports what was placed in each room of this level, flagging any room that goes against
+    /// the given `max_room_enemy_area` or one of the other guidelines for how a room should feel.
+    /// Distances are measured from where the player enters the level (see `entrance`).
+    pub fn room_reports(&self, max_room_enemy_area: f64) -> Vec<RoomReport> {
+        let entrance = self.entrance();
+        let (map, positions, enemies, spawns, chests, props) = self.world.system_data::<(
+            ReadExpect<'_, FloorMap>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Enemy>,
+            ReadStorage<'_, EnemySpawn>,
+            ReadStorage<'_, Chest>,
+            ReadStorage<'_, Prop>,
+        )>();
+        let grid = map.grid();
+
+        let enemies = count_by_room(&map, &positions, &enemies);
+        let spawns = count_by_room(&map, &positions, &spawns);
+        let chests = count_by_room(&map, &positions, &chests);
+        let props = count_by_room(&map, &positions, &props);
+        let distances = map.path_lengths(entrance);
+
+        // Every room with a floor tile beside a floor tile of the room with the entrance
+        let entrance_room = grid.get(entrance).floor_room_id();
+        let near_entrance: HashSet<_> = grid.tile_positions()
+            .filter(|&pos| entrance_room.is_some() && grid.get(pos).floor_room_id() == entrance_room)
+            .flat_map(|pos| grid.adjacent_positions(pos))
+            .filter_map(|adj| grid.get(adj).floor_room_id())
+            .collect();
+
+        map.rooms().map(|(room_id, room)| {
+            let count = |counts: &HashMap<RoomId, usize>| counts.get(&room_id).cloned().unwrap_or(0);
+
+            let floor_area = map.room_exact_area(room_id);
+            let nenemies = count(&enemies) + count(&spawns);
+            let enemy_ratio = if floor_area == 0 { 0.0 } else { nenemies as f64 / floor_area as f64 };
+
+            let walls: Vec<_> = map.room_tiles_with_walls(room_id).into_iter()
+                .filter(|&pos| grid.get(pos).is_wall())
+                .map(|pos| grid.get(pos).wall_sprite().alt)
+                .collect();
+            let walls_with = |alt| walls.iter().filter(|&&wall| wall == alt).count();
+
+            let distance = room.boundary().tile_positions()
+                .filter(|&pos| grid.get(pos).is_room_floor(room_id))
+                .filter_map(|pos| distances.get(&pos).cloned())
+                .min();
+
+            let mut warnings = Vec::new();
+            if near_entrance.contains(&room_id) && enemy_ratio > NEAR_ENTRANCE_ENEMY_RATIO {
+                warnings.push(RoomWarning::CrowdedNearEntrance);
+            }
+            if enemy_ratio > max_room_enemy_area {
+                warnings.push(RoomWarning::OverMaxEnemyArea);
+            }
+            if distance.is_none() {
+                warnings.push(RoomWarning::Unreachable);
+            }
+
+            RoomReport {
+                room_id,
+                room_type: room.room_type(),
+                floor_area,
+                enemies: nenemies,
+                enemy_ratio,
+                chests: count(&chests),
+                decorations: count(&props) + walls_with(WallSpriteAlternate::BrickPillar),
+                torches: walls_with(WallSpriteAlternate::TorchLit),
+                distance,
+                warnings,
+            }
+        }).collect()
+    }
+}
+
+/// Formats the reports of the rooms of one level as a table with a row for each room
+pub fn format_room_reports(level: usize, reports: &[RoomReport], max_room_enemy_area: f64) -> String {
+    let mut table = format!("level {} (max enemy ratio {:.2}, {:.2} near the entrance)\n",
+        level, max_room_enemy_area, NEAR_ENTRANCE_ENEMY_RATIO);
+    table.push_str(&format!("{:>4} {:<15} {:>4} {:>7} {:>5} {:>6} {:>11} {:>7} {:>8}  warnings\n",
+        "room", "type", "area", "enemies", "ratio", "chests", "decorations", "torches", "distance"));
+    for report in reports {
+        let distance = report.distance.map(|distance| distance.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let warnings: Vec<_> = report.warnings.iter().map(|warning| warning.to_string()).collect();
+        let row = format!("{:>4} {:<15} {:>4} {:>7} {:>5.2} {:>6} {:>11} {:>7} {:>8}  {}",
+            report.room_id.to_string(), format!("{:?}", report.room_type), report.floor_area,
+            report.enemies, report.enemy_ratio, report.chests, report.decorations, report.torches,
+            distance, warnings.join(", "));
+        table.push_str(row.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::components::Ghost;
+    use crate::generator::MapKey;
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+
+    #[test]
+    fn reports_agree_with_level() {
+        let sprites = test_sprites();
+        let mut generator = test_generator(&sprites);
+        // Every chest counted below should be the one holding the treasure key
+        generator.weapon_chest_chance = 0.0;
+        let max_room_enemy_area = generator.max_room_enemy_area;
+        let key: MapKey = "ZXZlcnkgY2F2ZSBoYXMgYSByb29tIHRvIGJyZWF0aGU".parse().unwrap();
+        let game = generator.generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        for (i, level) in game.levels.iter().enumerate() {
+            let reports = level.room_reports(max_room_enemy_area);
+            let map = level.world.read_resource::<FloorMap>();
+            let grid = map.grid();
+            assert_eq!(reports.len(), map.nrooms());
+
+            // Recount everything from the tiles and entities directly
+            let (positions, spawns, props, ghosts) = level.world.system_data::<(
+                ReadStorage<'_, Position>,
+                ReadStorage<'_, EnemySpawn>,
+                ReadStorage<'_, Prop>,
+                ReadStorage<'_, Ghost>,
+            )>();
+            let room_of = |pos| grid.get(map.world_to_tile_pos(pos)).floor_room_id();
+            let entrance_distances = map.path_lengths(level.entrance());
+            for report in &reports {
+                let room_id = report.room_id;
+                let floor: Vec<_> = grid.tile_positions().filter(|&pos| grid.get(pos).is_room_floor(room_id)).collect();
+                assert_eq!(report.floor_area, floor.len());
+
+                let enemies = (&positions, &spawns).join().filter(|(&Position(pos), _)| room_of(pos) == Some(room_id)).count();
+                assert_eq!(report.enemies, enemies);
+                assert!(report.enemy_ratio <= max_room_enemy_area, "level {} room {}: {:?}", i + 1, room_id, report);
+                assert!(!report.warnings.contains(&RoomWarning::OverMaxEnemyArea));
+
+                // Every prop is also a ghost
+                let nprops = (&positions, &props, &ghosts).join().filter(|(&Position(pos), _, _)| room_of(pos) == Some(room_id)).count();
+                assert!(report.decorations >= nprops);
+                // The only chest holds the key to the treasure chamber, which is in a challenge room
+                if report.room_type != RoomType::Challenge {
+                    assert_eq!(report.chests, 0);
+                }
+
+                let distance = floor.iter().filter_map(|pos| entrance_distances.get(pos)).min().cloned();
+                assert_eq!(report.distance, distance);
+                assert_eq!(report.warnings.contains(&RoomWarning::Unreachable), distance.is_none());
+            }
+
+            // Only the last level has a treasure chamber to hold the key for
+            let chests: usize = reports.iter().map(|report| report.chests).sum();
+            assert_eq!(chests, if i + 1 == game.levels.len() { 1 } else { 0 });
+
+            // The room with the entrance is right there
+            let entrance_room = grid.get(level.entrance()).floor_room_id();
+            assert!(reports.iter().any(|report| Some(report.room_id) == entrance_room && report.distance == Some(0)));
+
+            let table = format_room_reports(i + 1, &reports, max_room_enemy_area);
+            assert_eq!(table.lines().count(), reports.len() + 2);
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use std::hash::Hash;
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use rand::{rngs::StdRng, Rng};
+use rand::{rngs::StdRng, Rng, seq::SliceRandom};
 
-use super::{GameGenerator, RanOutOfAttempts};
+use super::{GameGenerator, RanOutOfAttempts, GenPhase, ConnectionStyle};
+use super::corridors::{ROOM_SPACING, connect_with_corridors};
 use crate::map_sprites::{FloorSprite, WallSprite};
 use crate::map::*;
 
@@ -20,9 +22,9 @@ impl<'a> GameGenerator<'a> {
         let mut attempts = 0;
         while room_rects.len() < nrooms {
             for _ in 0..nrooms {
-                'gen_room: loop {
+                loop {
                     if attempts > self.attempts {
-                        return Err(RanOutOfAttempts);
+                        return Err(RanOutOfAttempts {phase: GenPhase::Rooms, attempts});
                     }
                     attempts += 1;
 
@@ -33,6 +35,11 @@ impl<'a> GameGenerator<'a> {
                 }
             }
 
+            // Rooms that are joined by corridors never overlap, so all of them are still valid
+            if self.connection_style == ConnectionStyle::Corridors {
+                continue;
+            }
+
             // Remove rooms that aren't a valid size anymore
             self.remove_invalid_rooms(&mut room_rects);
             // Remove rooms that are adjacent to each other since that can end up in cases where
@@ -49,7 +56,14 @@ impl<'a> GameGenerator<'a> {
 
             self.place_rect(map, room_id);
         }
-        self.assign_special_rooms(rng, map, level);
+        let corridors = match self.connection_style {
+            ConnectionStyle::Overlap => Vec::new(),
+            ConnectionStyle::Corridors => connect_with_corridors(map, attempts)?,
+        };
+        let graph = self.room_graph(map, &corridors);
+        self.assign_special_rooms(rng, map, level, &graph);
+        self.assign_challenge_rooms(rng, map, level, &graph);
+        self.assign_merchant_room(rng, map, &graph);
 
         Ok(())
     }
@@ -74,6 +88,15 @@ impl<'a> GameGenerator<'a> {
             return None;
         }
 
+        // Leave enough space between rooms for the corridors that will join them
+        if self.connection_style == ConnectionStyle::Corridors {
+            let spaced = rect.expand(ROOM_SPACING);
+            if room_rects.iter().any(|&rect2| spaced.has_intersection(rect2)) {
+                return None;
+            }
+            return Some(rect);
+        }
+
         for &rect2 in room_rects {
             if let Some(common) = rect.intersection(rect2) {
                 // Room cannot only overlap at a corner.
@@ -227,7 +250,7 @@ impl<'a> GameGenerator<'a> {
         let mut room_tiles = vec![vec![true; cols]; rows];
 
         let top_left = room.top_left();
-        for &other_room in &*room_rects {
+        for &other_room in room_rects {
             if *room == other_room {
                 continue;
             }
@@ -328,7 +351,7 @@ impl<'a> GameGenerator<'a> {
         false // room is valid
     }
 
-    fn rect_graph_breadth_first_search(&self, graph: &HashMap<usize, Vec<usize>>, start: usize) -> HashSet<usize> {
+    fn rect_graph_breadth_first_search<T: Copy + Eq + Hash>(&self, graph: &HashMap<T, Vec<T>>, start: T) -> HashSet<T> {
         let mut open = VecDeque::new();
         open.push_back(start);
 
@@ -351,7 +374,52 @@ impl<'a> GameGenerator<'a> {
         seen
     }
 
-    fn assign_special_rooms(&self, rng: &mut StdRng, map: &mut FloorMap, level: usize) {
+    /// Returns which rooms can be walked to directly from each room. The corridors are the pairs of
+    /// rooms that were joined by corridors (if any).
+    fn room_graph(&self, map: &FloorMap, corridors: &[(RoomId, RoomId)]) -> HashMap<RoomId, Vec<RoomId>> {
+        // Adjacency list representation
+        let mut graph: HashMap<_, Vec<_>> = HashMap::new();
+
+        match self.connection_style {
+            // Create an undirected graph based on intersections
+            // NOTE: Since all rooms are connected at this point, the graph should have as many
+            // keys as there are rooms. All rooms should be accounted for.
+            ConnectionStyle::Overlap => for (id1, r1) in map.rooms() {
+                for (id2, r2) in map.rooms() {
+                    if id1 != id2 && r1.boundary().has_intersection(*r2.boundary()) {
+                        graph.entry(id1).or_default().push(id2);
+                    }
+                }
+            },
+            // Create an undirected graph based on which rooms are joined by corridors
+            ConnectionStyle::Corridors => for &(id1, id2) in corridors {
+                graph.entry(id1).or_default().push(id2);
+                graph.entry(id2).or_default().push(id1);
+            },
+        }
+
+        assert_eq!(graph.len(), map.nrooms(),
+            "bug: not all rooms were added to the graph even though there should no longer be any disconnected rooms");
+        graph
+    }
+
+    /// Returns the rooms that can reach every other room without going through the treasure
+    /// chamber (if any). The key to the treasure chamber can be placed in any of these rooms.
+    fn rooms_reaching_all_but_chamber(&self, map: &FloorMap, graph: &HashMap<RoomId, Vec<RoomId>>) -> HashSet<RoomId> {
+        let treasure_chamber = map.rooms().find(|(_, room)| room.is_treasure_chamber()).map(|(id, _)| id);
+        let graph: HashMap<_, Vec<_>> = graph.iter()
+            .filter(|&(&id, _)| Some(id) != treasure_chamber)
+            .map(|(&id, adjacents)| (id, adjacents.iter().cloned().filter(|&adj| Some(adj) != treasure_chamber).collect()))
+            .collect();
+        graph.keys()
+            .cloned()
+            .filter(|&id| self.rect_graph_breadth_first_search(&graph, id).len() == graph.len())
+            .collect()
+    }
+
+    /// Assigns the player start room, treasure chamber and the challenge room holding the key to
+    /// the treasure chamber
+    fn assign_special_rooms(&self, rng: &mut StdRng, map: &mut FloorMap, level: usize, graph: &HashMap<RoomId, Vec<RoomId>>) {
         // If we're on the first level, pick a random room for the player to start
         if level == 1 {
             let room_id = {
@@ -360,38 +428,23 @@ impl<'a> GameGenerator<'a> {
                 room.become_player_start();
                 room_id
             };
-            // Put this room's tiles on top
-            self.place_rect(map, room_id);
+            self.place_special_rect(map, room_id);
         }
 
         // If we're on the last level, pick the biggest room as the treasure chamber
         if level == self.levels {
-            // Adjacency list representation
-            let mut graph: HashMap<_, Vec<_>> = HashMap::new();
-
-            // Create an undirected graph based on intersections
-            // NOTE: Since all rooms are connected at this point, the graph should have as many
-            // keys as there are rooms. All rooms should be accounted for.
-            for (id1, r1) in map.rooms() {
-                for (id2, r2) in map.rooms() {
-                    if id1 != id2 && r1.boundary().has_intersection(*r2.boundary()) {
-                        graph.entry(id1).or_default().push(id2);
-                    }
-                }
-            }
-
-            assert_eq!(graph.len(), map.nrooms(),
-                "bug: not all rooms were added to the graph even though there should no longer be any disconnected rooms");
-
             // Since the treasure room is the final room of the game, it is possible for it to
             // accidentally make another room unreachable if we aren't careful in choosing it. To
             // avoid this, we first try to find the largest room that has only one other adjacent.
             // This can never make another room unreachable because it is already the end of a
             // path. If that doesn't work, all rooms must have at least 2 adjacents, so we can pick
             // the largest room and every other room will always have at least one way to get to it.
-            let largest_room = graph.into_iter()
-                .filter_map(|(id, adjacents)| if adjacents.len() == 1 { Some(id) } else { None })
-                .max_by_key(|&id| map.room(id).boundary().area());
+            // NOTE: Going through the rooms in order (instead of through the graph) keeps the choice
+            // deterministic when several rooms have the same area.
+            let largest_room = map.rooms()
+                .filter(|(id, _)| graph[id].len() == 1)
+                .max_by_key(|(_, room)| room.boundary().area())
+                .map(|(id, _)| id);
 
             let room_id = match largest_room {
                 Some(room_id) => room_id,
@@ -405,8 +458,113 @@ impl<'a> GameGenerator<'a> {
             };
 
             map.room_mut(room_id).become_treasure_chamber();
+            self.place_special_rect(map, room_id);
+
+            // The key to the treasure chamber is kept in a challenge room. Wherever the player
+            // enters the level, they must be able to get to the key without going through the
+            // (locked) treasure chamber, so the challenge room must be able to reach every other
+            // room with the treasure chamber removed from the graph.
+            let reaching_all = self.rooms_reaching_all_but_chamber(map, graph);
+            let candidates: Vec<_> = map.rooms()
+                .filter(|(_, room)| room.room_type() == RoomType::Normal)
+                .map(|(id, _)| id)
+                .filter(|id| reaching_all.contains(id))
+                .collect();
+            // If there is nowhere to put the key, placing it will run out of attempts
+            if let Some(&room_id) = candidates.choose(rng) {
+                map.room_mut(room_id).become_challenge();
+            }
+        }
+    }
+
+    /// Promotes normal rooms to challenge rooms until the level has the configured number of
+    /// challenge rooms. Only levels from `challenge_rooms_start_level` onwards are guaranteed to
+    /// have any challenge rooms.
+    ///
+    /// Rooms at the end of a path (with only one entrance) and rooms that are larger than most
+    /// make the best challenges, so those are preferred. If there are not enough of those, the
+    /// preferences are relaxed one at a time. The player start room and the treasure chamber are
+    /// never chosen. Staircases are only ever placed in normal rooms, so challenge rooms never
+    /// contain a staircase. Enough normal rooms are always left over for the staircases and map
+    /// fragments, even if that means placing fewer challenge rooms than configured.
+    fn assign_challenge_rooms(&self, rng: &mut StdRng, map: &mut FloorMap, level: usize, graph: &HashMap<RoomId, Vec<RoomId>>) {
+        if level < self.challenge_rooms_start_level {
+            return;
+        }
+
+        let nchallenge_rooms = self.challenge_rooms.gen(rng);
+        // Each of these is placed in a separate normal room
+        let reserved = self.next_prev_tiles.max(self.map_fragments.max).max(1);
+
+        let mut areas: Vec<_> = map.rooms().map(|(_, room)| room.boundary().area()).collect();
+        areas.sort_unstable();
+        let median_area = areas[areas.len() / 2];
+        // Any of the challenge rooms on the last level may end up holding the key to the treasure
+        // chamber, so every one of them needs to be reachable without going through the chamber
+        let reaching_all = self.rooms_reaching_all_but_chamber(map, graph);
+
+        let mut assigned = map.rooms().filter(|(_, room)| room.room_type() == RoomType::Challenge).count();
+        while assigned < nchallenge_rooms {
+            let normal_rooms = map.rooms().filter(|(_, room)| room.room_type() == RoomType::Normal).count();
+            if normal_rooms <= reserved {
+                break;
+            }
+
+            let candidates: Vec<_> = map.rooms()
+                .filter(|(id, room)| room.room_type() == RoomType::Normal && reaching_all.contains(id))
+                .map(|(id, room)| {
+                    let preferences = (graph[&id].len() == 1) as usize + (room.boundary().area() > median_area) as usize;
+                    (id, preferences)
+                })
+                .collect();
+            // Only choose from the rooms that meet as many of the preferences as possible
+            let best = match candidates.iter().map(|&(_, preferences)| preferences).max() {
+                Some(best) => best,
+                None => break,
+            };
+            let best_candidates: Vec<_> = candidates.into_iter()
+                .filter(|&(_, preferences)| preferences == best)
+                .map(|(id, _)| id)
+                .collect();
+            let &room_id = best_candidates.choose(rng)
+                .expect("bug: there should be at least one candidate with the best preferences");
+            map.room_mut(room_id).become_challenge();
+            assigned += 1;
+        }
+    }
+
+    /// Occasionally turns one of the normal rooms into a merchant room. Just like with challenge
+    /// rooms, enough normal rooms are always left over for the staircases and map fragments. The
+    /// rooms beside the treasure chamber are left for the boss that guards it.
+    fn assign_merchant_room(&self, rng: &mut StdRng, map: &mut FloorMap, graph: &HashMap<RoomId, Vec<RoomId>>) {
+        // Merchants are rare, so most levels do not have one
+        if !rng.gen_bool(self.merchant_chance) {
+            return;
+        }
+
+        let reserved = self.next_prev_tiles.max(self.map_fragments.max).max(1);
+        let normal_rooms = map.rooms().filter(|(_, room)| room.room_type() == RoomType::Normal).count();
+        if normal_rooms <= reserved {
+            return;
+        }
+
+        let treasure_chamber = map.rooms().find(|(_, room)| room.is_treasure_chamber()).map(|(id, _)| id);
+        let candidates: Vec<_> = map.rooms()
+            .filter(|(id, room)| room.room_type() == RoomType::Normal
+                && !treasure_chamber.iter().any(|chamber| graph[id].contains(chamber)))
+            .map(|(id, _)| id)
+            .collect();
+        let room_id = match candidates.choose(rng) {
+            Some(&room_id) => room_id,
+            None => return,
+        };
+        map.room_mut(room_id).become_merchant();
+    }
 
-            // Put this room's tiles on top
+    /// Puts the tiles of a special room on top of any rooms that overlap it. Rooms joined by
+    /// corridors never overlap, and placing the room again would wall off its corridors.
+    fn place_special_rect(&self, map: &mut FloorMap, room_id: RoomId) {
+        if self.connection_style == ConnectionStyle::Overlap {
             self.place_rect(map, room_id);
         }
     }
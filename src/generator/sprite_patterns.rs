@@ -1,47 +1,25 @@
+use std::collections::HashMap;
+
 use rand::{rngs::StdRng, Rng, seq::SliceRandom};
+use rayon::prelude::*;
 use specs::{World, Builder};
 
 use super::{GameGenerator, TileRect, TilePos, GridSize};
 use super::world_helpers::world_contains_any_entity;
-use crate::map_sprites::{WallSprite, WallSpriteAlternate, FLOOR_PATTERNS};
-use crate::components::{Position, Sprite};
+use crate::map_sprites::{WallSprite, WallSpriteAlternate, FloorPalette, FLOOR_PATTERNS};
+use crate::components::{Position, Sprite, RenderLayer};
 use crate::map::*;
 
 impl<'a> GameGenerator<'a> {
     pub(in super) fn layout_floor_wall_sprites(&self, rng: &mut StdRng, map: &mut FloorMap) {
         self.layout_wall_sprites(rng, map);
         self.layout_floor_sprites(rng, map);
+        layout_floor_palettes(map);
     }
 
     fn layout_wall_sprites(&self, rng: &mut StdRng, map: &mut FloorMap) {
-        for pos in map.grid().tile_positions() {
-            if !map.grid().get(pos).is_wall() {
-                continue;
-            }
-            // Sprite already has a predetermined alternate
-            if map.grid().get(pos).wall_sprite().alt != Default::default() {
-                continue;
-            }
-
-            let mut wall_sprite = WallSprite::default();
-            wall_sprite.alt = rng.gen();
-
-            for adj in map.grid().adjacent_positions(pos) {
-                if !map.grid().get(adj).is_wall() {
-                    continue;
-                }
-
-                match pos.difference(adj) {
-                    (a, 0) if a > 0 => wall_sprite.wall_north = true,
-                    (0, a) if a < 0 => wall_sprite.wall_east = true,
-                    (a, 0) if a < 0 => wall_sprite.wall_south = true,
-                    (0, a) if a > 0 => wall_sprite.wall_west = true,
-                    _ => unreachable!("bug: position and its adjacent were not in the same row/column"),
-                }
-            }
-
-            map.grid_mut().get_mut(pos).set_wall_sprite(wall_sprite);
-        }
+        let sprites = wall_sprites(rng, map.grid());
+        apply_wall_sprites(map.grid_mut(), &sprites);
     }
 
     fn layout_floor_sprites(&self, rng: &mut StdRng, map: &mut FloorMap) {
@@ -109,6 +87,10 @@ impl<'a> GameGenerator<'a> {
                 if !map.grid().get(pos).is_wall() {
                     continue;
                 }
+                // Something else (e.g. a map fragment) is already mounted on this wall
+                if world_contains_any_entity(world, pos.tile_rect(map.tile_size())) {
+                    continue;
+                }
 
                 let has_south_floor = pos.adjacent_south(map.grid().rows_len())
                     .map(|pt| (pt.tile_rect(map.tile_size()), map.grid().get(pt)))
@@ -129,6 +111,7 @@ impl<'a> GameGenerator<'a> {
                     world.create_entity()
                         .with(Position(pos))
                         .with(Sprite(torch_animation.current_sprite()))
+                        .with(RenderLayer::FIXTURES)
                         .with(torch_animation)
                         .build();
                 }
@@ -136,3 +119,175 @@ impl<'a> GameGenerator<'a> {
         }
     }
 }
+
+/// Returns the palette used for the floor of rooms with the given type
+fn room_palette(rtype: RoomType) -> FloorPalette {
+    use self::RoomType::*;
+    match rtype {
+        Normal | PlayerStart | Merchant => FloorPalette::Stone,
+        Challenge | TreasureChamber => FloorPalette::Clay,
+    }
+}
+
+/// Sets the palette of every floor tile based on the type of the room that owns it. Doorways are
+/// owned by the room they were added for, so they match the floor of that room.
+///
+/// Floor patterns are placed without regard for palettes, so this never changes which sprite a
+/// tile uses, only which palette it is drawn from.
+fn layout_floor_palettes(map: &mut FloorMap) {
+    let palettes: HashMap<_, _> = map.rooms()
+        .map(|(room_id, room)| (room_id, room_palette(room.room_type())))
+        .collect();
+    for (_, tile) in map.grid_mut().tiles_mut() {
+        if let Some(room_id) = tile.floor_room_id() {
+            tile.set_floor_palette(palettes[&room_id]);
+        }
+    }
+}
+
+/// Chooses a sprite for every wall tile that does not already have a predetermined alternate.
+/// Returns the sprite (if any) for each tile, row by row.
+///
+/// The alternates are chosen in the order that `TileGrid::tiles` visits the tiles, so the same
+/// rng always produces the same sprites.
+fn wall_sprites(rng: &mut StdRng, grid: &TileGrid) -> Vec<Vec<Option<WallSprite>>> {
+    let mut sprites = vec![vec![None; grid.cols_len()]; grid.rows_len()];
+    for (pos, tile) in grid.tiles() {
+        if !tile.is_wall() {
+            continue;
+        }
+        // Sprite already has a predetermined alternate
+        if tile.wall_sprite().alt != Default::default() {
+            continue;
+        }
+
+        let mut wall_sprite = wall_adjacency(grid, pos);
+        wall_sprite.alt = rng.gen();
+        sprites[pos.row][pos.col] = Some(wall_sprite);
+    }
+    sprites
+}
+
+/// Returns a wall sprite that records which of the tiles adjacent to the given position are walls
+fn wall_adjacency(grid: &TileGrid, pos: TilePos) -> WallSprite {
+    let mut wall_sprite = WallSprite::default();
+    for adj in grid.adjacent_positions(pos) {
+        if !grid.get(adj).is_wall() {
+            continue;
+        }
+
+        match pos.difference(adj) {
+            (a, 0) if a > 0 => wall_sprite.wall_north = true,
+            (0, a) if a < 0 => wall_sprite.wall_east = true,
+            (a, 0) if a < 0 => wall_sprite.wall_south = true,
+            (0, a) if a > 0 => wall_sprite.wall_west = true,
+            _ => unreachable!("bug: position and its adjacent were not in the same row/column"),
+        }
+    }
+    wall_sprite
+}
+
+/// Sets the sprites chosen by `wall_sprites`. Every sprite was chosen before any of them were set,
+/// so the rows can be updated in parallel without changing the result.
+fn apply_wall_sprites(grid: &mut TileGrid, sprites: &[Vec<Option<WallSprite>>]) {
+    grid.par_tiles_mut().for_each(|(pos, tile)| {
+        if let Some(sprite) = sprites[pos.row][pos.col] {
+            tile.set_wall_sprite(sprite);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    use crate::map_sprites::FloorSprite;
+    use crate::generator::MapKey;
+    use crate::generator::tests::{test_generator, test_sprites, setup_game_world};
+    use crate::test_helpers::rooms_side_by_side;
+
+    /// A large grid with walls scattered randomly around it. Some of the walls already have an
+    /// alternate.
+    fn random_grid(rng: &mut StdRng) -> TileGrid {
+        let mut grid = TileGrid::new(GridSize {rows: 60, cols: 80});
+        for pos in grid.tile_positions().collect::<Vec<_>>() {
+            if rng.gen_bool(0.4) {
+                let mut sprite = WallSprite::default();
+                if rng.gen_bool(0.1) {
+                    sprite.alt = WallSpriteAlternate::BrickPillar;
+                }
+                grid.place_tile(pos, Tile::new_wall(sprite));
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn parallel_wall_sprites_match_serial() {
+        let key = 0x5eed;
+        let mut grid = random_grid(&mut StdRng::seed_from_u64(key));
+
+        // Updating one tile at a time in order, reading the adjacents from the grid as it changes
+        let mut serial = grid.clone();
+        let mut rng = StdRng::seed_from_u64(key + 1);
+        for pos in serial.tile_positions().collect::<Vec<_>>() {
+            let tile = serial.get(pos);
+            if !tile.is_wall() || tile.wall_sprite().alt != Default::default() {
+                continue;
+            }
+            let mut sprite = wall_adjacency(&serial, pos);
+            sprite.alt = rng.gen();
+            serial.get_mut(pos).set_wall_sprite(sprite);
+        }
+
+        let sprites = wall_sprites(&mut StdRng::seed_from_u64(key + 1), &grid);
+        apply_wall_sprites(&mut grid, &sprites);
+        assert_eq!(grid, serial);
+        // Predetermined alternates are kept
+        assert!(grid.tiles().any(|(_, tile)| tile.is_wall() && tile.wall_sprite().alt == WallSpriteAlternate::BrickPillar));
+    }
+
+    #[test]
+    fn doorways_match_their_room_palette() {
+        // Two rooms that share the wall at column 4 with a doorway in the middle of it
+        let (mut map, rooms) = rooms_side_by_side(5, &[5, 5], true, 16);
+        map.room_mut(rooms[1]).become_challenge();
+        let doorway = TilePos {row: 2, col: 4};
+        map.grid_mut().get_mut(doorway).become_floor(rooms[1], FloorSprite::default());
+
+        layout_floor_palettes(&mut map);
+        assert_eq!(map.grid().get(TilePos {row: 2, col: 2}).floor_palette(), FloorPalette::Stone);
+        assert_eq!(map.grid().get(TilePos {row: 2, col: 6}).floor_palette(), FloorPalette::Clay);
+        assert_eq!(map.grid().get(doorway).floor_palette(), FloorPalette::Clay);
+    }
+
+    #[test]
+    fn challenge_rooms_use_alternate_palette() {
+        let sprites = test_sprites();
+        let key: MapKey = StdRng::seed_from_u64(0x5eed).gen();
+        let game = test_generator(&sprites).generate_with_key(key, setup_game_world)
+            .expect("bug: should be able to generate a map with a valid config");
+
+        let mut challenge_tiles = 0;
+        for level in &game.levels {
+            let map = level.world.read_resource::<FloorMap>();
+            for (_, tile) in map.grid().tiles() {
+                let room_id = match tile.floor_room_id() {
+                    Some(room_id) => room_id,
+                    None => continue,
+                };
+
+                let rtype = map.room(room_id).room_type();
+                assert_eq!(tile.floor_palette(), room_palette(rtype), "wrong palette for {:?} room", rtype);
+                if rtype == RoomType::Challenge {
+                    challenge_tiles += 1;
+                }
+            }
+        }
+        // The last level always has a challenge room holding the treasure key
+        assert!(challenge_tiles > 0);
+        assert_ne!(room_palette(RoomType::Challenge), room_palette(RoomType::Normal));
+    }
+}
@@ -0,0 +1,159 @@
+use super::{GameGenerator, ConfigError, GenPhase};
+use crate::assets::is_supported_tile_size;
+
+/// The smallest number of rows or columns that a room can have. Every room needs at least one
+/// tile inside of its walls.
+const MIN_ROOM_SIZE: usize = 3;
+
+impl<'a> GameGenerator<'a> {
+    /// Checks for configurations that could never generate a map. This is cheap and catches
+    /// mistakes up front instead of after running out of attempts.
+    ///
+    /// Passing this check does not guarantee that a map will be generated, only that it is
+    /// possible.
+    pub fn validate_config(&self) -> Result<(), ConfigError> {
+        use self::ConfigError::*;
+
+        if !is_supported_tile_size(self.tile_size) {
+            return Err(UnsupportedTileSize {tile_size: self.tile_size});
+        }
+
+        let bounds = [
+            ("rooms", self.rooms.min, self.rooms.max),
+            ("room_rows", self.room_rows.min, self.room_rows.max),
+            ("room_cols", self.room_cols.min, self.room_cols.max),
+            ("doors", self.doors.min, self.doors.max),
+            ("challenge_rooms", self.challenge_rooms.min, self.challenge_rooms.max),
+            ("map_fragments", self.map_fragments.min, self.map_fragments.max),
+            ("props_per_room", self.props_per_room.min, self.props_per_room.max),
+            ("breakables_per_room", self.breakables_per_room.min, self.breakables_per_room.max),
+            ("room_enemies", self.room_enemies.min, self.room_enemies.max),
+            ("enemy_gold", self.enemy_gold.min as usize, self.enemy_gold.max as usize),
+        ];
+        for &(name, min, max) in &bounds {
+            if min > max {
+                return Err(InvertedBounds {name});
+            }
+        }
+
+        if self.room_rows.min < MIN_ROOM_SIZE {
+            return Err(RoomTooSmall {dimension: "rows", room_min: self.room_rows.min, min_size: MIN_ROOM_SIZE});
+        }
+        if self.room_cols.min < MIN_ROOM_SIZE {
+            return Err(RoomTooSmall {dimension: "cols", room_min: self.room_cols.min, min_size: MIN_ROOM_SIZE});
+        }
+        if self.room_rows.min > self.rows {
+            return Err(RoomTooLarge {dimension: "rows", room_min: self.room_rows.min, map_size: self.rows});
+        }
+        if self.room_cols.min > self.cols {
+            return Err(RoomTooLarge {dimension: "cols", room_min: self.room_cols.min, map_size: self.cols});
+        }
+
+        // Each of these is placed in a separate room
+        let max_rooms = self.rooms.max;
+        let per_room = [
+            ("next_prev_tiles", self.next_prev_tiles),
+            ("map_fragments", self.map_fragments.max),
+            ("challenge_rooms", self.challenge_rooms.max),
+        ];
+        for &(name, value) in &per_room {
+            if value > max_rooms {
+                return Err(NotEnoughRooms {name, value, max_rooms});
+            }
+        }
+
+        if self.doors.min == 0 {
+            return Err(NoDoors);
+        }
+
+        let probabilities = [
+            ("max_overlap", self.max_overlap),
+            ("max_room_enemy_area", self.max_room_enemy_area),
+            ("prisoner_chance", self.prisoner_chance),
+            ("merchant_chance", self.merchant_chance),
+            ("weapon_chest_chance", self.weapon_chest_chance),
+            ("layout_transform_chance", self.layout_transform_chance),
+            ("pillar_chance", self.pillar_chance),
+            ("breakable_drop_chance", self.breakable_drop_chance),
+            ("arrow_shooter_chance", self.arrow_shooter_chance),
+            ("hazard_chance", self.hazard_chance),
+            ("enemy_spawn_probability", self.enemy_spawn_probability),
+            ("enemy_gold_chance", self.enemy_gold_chance),
+        ];
+        for &(name, value) in &probabilities {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(InvalidProbability {name, value});
+            }
+        }
+
+        let enemy_levels = self.enemy_config.levels.len();
+        if enemy_levels != self.levels {
+            return Err(EnemyLevelsMismatch {levels: self.levels, enemy_levels});
+        }
+
+        if let Some(enemy) = self.enemy_config.enemy_types().into_iter()
+            .find(|&enemy| !self.enemy_config.has_values(enemy)) {
+            return Err(MissingEnemyValues {enemy});
+        }
+
+        let scaling = self.enemy_config.scaling;
+        let scaling = [
+            ("attack_per_level", scaling.attack_per_level),
+            ("health_per_level", scaling.health_per_level),
+            ("speed_per_level", scaling.speed_per_level),
+        ];
+        for &(name, value) in &scaling {
+            if !(0.0..).contains(&value) {
+                return Err(NegativeEnemyScaling {name, value});
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the configuration values that affect the given phase of level generation
+    pub(in super) fn phase_config(&self, phase: GenPhase) -> Vec<(&'static str, String)> {
+        use self::GenPhase::*;
+        let mut config = vec![
+            ("attempts", self.attempts.to_string()),
+            ("rows", self.rows.to_string()),
+            ("cols", self.cols.to_string()),
+            ("rooms", format!("{:?}", (self.rooms.min, self.rooms.max))),
+        ];
+        match phase {
+            Rooms => config.extend(vec![
+                ("room_rows", format!("{:?}", (self.room_rows.min, self.room_rows.max))),
+                ("room_cols", format!("{:?}", (self.room_cols.min, self.room_cols.max))),
+                ("connection_style", format!("{:?}", self.connection_style)),
+                ("max_overlap", self.max_overlap.to_string()),
+            ]),
+            Staircases => config.push(("next_prev_tiles", self.next_prev_tiles.to_string())),
+            MapFragments => config.push(("map_fragments", format!("{:?}", (self.map_fragments.min, self.map_fragments.max)))),
+            Prisoners => config.push(("prisoner_chance", self.prisoner_chance.to_string())),
+            Merchant => config.push(("merchant_chance", self.merchant_chance.to_string())),
+            WeaponChest => config.push(("weapon_chest_chance", self.weapon_chest_chance.to_string())),
+            // The key can only be placed in the challenge room, so only the rooms matter
+            TreasureKey => {},
+            // The mouth of the dungeon can only be placed in the room that the player starts in
+            DungeonMouth => {},
+            Enemies => config.extend(vec![
+                ("room_enemies", format!("{:?}", (self.room_enemies.min, self.room_enemies.max))),
+                ("max_room_enemy_area", self.max_room_enemy_area.to_string()),
+                ("max_nearby_enemies", self.max_nearby_enemies.to_string()),
+            ]),
+            // The boss needs a room with enough open space for its bounding box
+            Boss => config.extend(vec![
+                ("room_rows", format!("{:?}", (self.room_rows.min, self.room_rows.max))),
+                ("room_cols", format!("{:?}", (self.room_cols.min, self.room_cols.max))),
+                ("pillar_chance", self.pillar_chance.to_string()),
+                ("hazard_chance", self.hazard_chance.to_string()),
+            ]),
+            // Staircases are most often blocked by the walls around other staircases or by pillars
+            Reachability => config.extend(vec![
+                ("next_prev_tiles", self.next_prev_tiles.to_string()),
+                ("pillar_chance", self.pillar_chance.to_string()),
+            ]),
+        }
+        config
+    }
+}
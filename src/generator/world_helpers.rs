@@ -1,7 +1,8 @@
-use specs::{World, ReadStorage, Join};
+use specs::{World, Entities, Entity, ReadStorage, Join};
 use sdl2::rect::Rect;
 
-use crate::components::Position;
+use crate::components::{Position, Stairs, Door};
+use crate::map::{FloorMap, StairsUid};
 
 //TODO: These functions are just utility methods. Maybe it would be better to wrap World in
 // a struct and provide these methods on it directly.
@@ -11,3 +12,19 @@ pub(in super) fn world_contains_any_entity(world: &World, bounds: Rect) -> bool
     world.system_data::<ReadStorage<'_, Position>>().join()
         .any(|&Position(pos)| bounds.contains_point(pos))
 }
+
+/// Returns the door within the given boundary, if any
+pub(in super) fn world_door_at(world: &World, bounds: Rect) -> Option<Entity> {
+    let (entities, positions, doors) = world.system_data::<(Entities<'_>, ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+    (&entities, &positions, &doors).join()
+        .find(|&(_, &Position(pos), _)| bounds.contains_point(pos))
+        .map(|(entity, _, _)| entity)
+}
+
+/// Returns the uid of every staircase in the world
+pub(in super) fn world_stairs_uids(world: &World, map: &FloorMap, level: usize) -> Vec<(Entity, StairsUid)> {
+    let (entities, positions, stairs) = world.system_data::<(Entities<'_>, ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
+    (&entities, &positions, &stairs).join()
+        .map(|(entity, &Position(pos), stairs)| (entity, stairs.uid(level, map.world_to_tile_pos(pos))))
+        .collect()
+}
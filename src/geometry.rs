@@ -0,0 +1,168 @@
+//! Distances between points and rectangles in world coordinates
+//!
+//! The right and bottom edges of a Rect are exclusive, so a rectangle at x = 0 with a width of 2
+//! covers the pixels at x = 0 and x = 1 and its right() is 2.
+
+use sdl2::rect::{Point, Rect};
+
+use crate::components::MovementDirection;
+
+/// Returns the signed gap (in px) between the given rectangles along the x-axis and the y-axis.
+///
+/// Each gap is the number of pixels between the rectangles along that axis. The gap is zero when
+/// the rectangles touch and negative (the amount of overlap) when their ranges along that axis
+/// overlap. The rectangles intersect if and only if both gaps are negative.
+pub fn rect_gap(a: Rect, b: Rect) -> (i32, i32) {
+    let gap_x = a.left().max(b.left()) - a.right().min(b.right());
+    let gap_y = a.top().max(b.top()) - a.bottom().min(b.bottom());
+    (gap_x, gap_y)
+}
+
+/// Returns the coordinate of the side of the rectangle that faces the given direction. This is a
+/// y coordinate for North and South and an x coordinate for East and West.
+pub fn side(rect: Rect, direction: MovementDirection) -> i32 {
+    use self::MovementDirection::*;
+    match direction {
+        North => rect.top(),
+        South => rect.bottom(),
+        East => rect.right(),
+        West => rect.left(),
+    }
+}
+
+/// Returns the signed gap (in px) between the side of `a` that faces the given direction and the
+/// opposite side of `b`. Positive if `b` is ahead of `a` in that direction, zero if they touch,
+/// and negative if `b` is behind that side of `a`.
+pub fn directional_gap(a: Rect, b: Rect, direction: MovementDirection) -> i32 {
+    use self::MovementDirection::*;
+    match direction {
+        North => a.top() - b.bottom(),
+        South => b.top() - a.bottom(),
+        East => b.left() - a.right(),
+        West => a.left() - b.right(),
+    }
+}
+
+/// Returns the pixel inside the given rectangle that is nearest to the given point. The point
+/// itself is returned if it is already inside the rectangle.
+pub fn clamp_point_to_rect(point: Point, rect: Rect) -> Point {
+    Point::new(
+        point.x().max(rect.left()).min(rect.right() - 1),
+        point.y().max(rect.top()).min(rect.bottom() - 1),
+    )
+}
+
+/// Returns the distance (in px) from the given point to the nearest pixel inside the given
+/// rectangle. Zero if and only if the point is inside the rectangle.
+pub fn point_rect_distance(point: Point, rect: Rect) -> f64 {
+    let diff = point - clamp_point_to_rect(point, rect);
+    ((diff.x() * diff.x() + diff.y() * diff.y()) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    const DIRECTIONS: [MovementDirection; 4] = [
+        MovementDirection::North,
+        MovementDirection::South,
+        MovementDirection::East,
+        MovementDirection::West,
+    ];
+
+    /// A small rectangle somewhere near the origin
+    fn random_rect(rng: &mut StdRng) -> Rect {
+        Rect::new(rng.gen_range(-6, 6), rng.gen_range(-6, 6), rng.gen_range(1, 5), rng.gen_range(1, 5))
+    }
+
+    fn pixels(rect: Rect) -> Vec<Point> {
+        (rect.left()..rect.right())
+            .flat_map(|x| (rect.top()..rect.bottom()).map(move |y| Point::new(x, y)))
+            .collect()
+    }
+
+    /// Computes the gap along a single axis from the pixel coordinates covered by each rectangle
+    fn brute_force_gap(a: Vec<i32>, b: Vec<i32>) -> i32 {
+        let shared = a.iter().filter(|coord| b.contains(coord)).count() as i32;
+        if shared > 0 {
+            return -shared;
+        }
+        a.iter().flat_map(|&p| b.iter().map(move |&q| (p - q).abs() - 1)).min().unwrap()
+    }
+
+    #[test]
+    fn rect_gap_properties() {
+        let mut rng = StdRng::seed_from_u64(1788);
+        for _ in 0..2000 {
+            let a = random_rect(&mut rng);
+            let b = random_rect(&mut rng);
+            let (gap_x, gap_y) = rect_gap(a, b);
+
+            assert_eq!(rect_gap(b, a), (gap_x, gap_y), "gap is not symmetric for {:?} and {:?}", a, b);
+            assert_eq!(gap_x < 0 && gap_y < 0, a.has_intersection(b));
+
+            let columns = |rect: Rect| (rect.left()..rect.right()).collect();
+            let rows = |rect: Rect| (rect.top()..rect.bottom()).collect();
+            assert_eq!(gap_x, brute_force_gap(columns(a), columns(b)), "{:?} {:?}", a, b);
+            assert_eq!(gap_y, brute_force_gap(rows(a), rows(b)), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn directional_gap_properties() {
+        let mut rng = StdRng::seed_from_u64(1788);
+        for _ in 0..2000 {
+            let a = random_rect(&mut rng);
+            let b = random_rect(&mut rng);
+            let (gap_x, gap_y) = rect_gap(a, b);
+
+            for &direction in &DIRECTIONS {
+                let gap = directional_gap(a, b, direction);
+                // Looking the other way from b finds a at the same distance
+                let reverse = match direction {
+                    MovementDirection::North => MovementDirection::South,
+                    MovementDirection::South => MovementDirection::North,
+                    MovementDirection::East => MovementDirection::West,
+                    MovementDirection::West => MovementDirection::East,
+                };
+                assert_eq!(directional_gap(b, a, reverse), gap);
+
+                // Whenever b is ahead of a, the gap is the same as the gap along that axis
+                if gap >= 0 {
+                    let axis_gap = match direction {
+                        MovementDirection::North | MovementDirection::South => gap_y,
+                        MovementDirection::East | MovementDirection::West => gap_x,
+                    };
+                    assert_eq!(gap, axis_gap, "{:?} {:?} {:?}", a, b, direction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn point_rect_distance_properties() {
+        let mut rng = StdRng::seed_from_u64(1788);
+        for _ in 0..2000 {
+            let rect = random_rect(&mut rng);
+            let point = Point::new(rng.gen_range(-10, 10), rng.gen_range(-10, 10));
+
+            let nearest = clamp_point_to_rect(point, rect);
+            assert!(rect.contains_point(nearest));
+            let distance = point_rect_distance(point, rect);
+            assert_eq!(distance == 0.0, rect.contains_point(point));
+            if rect.contains_point(point) {
+                assert_eq!(nearest, point);
+            }
+
+            let brute_force = pixels(rect).into_iter()
+                .map(|pixel| {
+                    let diff = point - pixel;
+                    ((diff.x() * diff.x() + diff.y() * diff.y()) as f64).sqrt()
+                })
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(distance, brute_force, "{:?} {:?}", point, rect);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Problems that come up outside of the game itself (e.g. being unable to save) and need the
+//! attention of the player. The game is paused until the player acknowledges each one.
+
+use std::sync::mpsc::{self, Sender, Receiver};
+
+/// A problem that interrupts the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterruptEvent {
+    /// Something that the player expects to be kept could not be written to disk
+    SaveFailed {
+        /// What was being saved (e.g. "profile")
+        what: &'static str,
+        path: String,
+        error: String,
+    },
+}
+
+impl InterruptEvent {
+    /// Returns the lines of text that describe the problem to the player
+    pub fn lines(&self) -> Vec<String> {
+        use self::InterruptEvent::*;
+        match self {
+            SaveFailed {what, path, error} => vec![
+                format!("Unable to save the {} to {}", what, path),
+                error.clone(),
+            ],
+        }
+    }
+}
+
+/// Delivers interrupt events from anywhere in the game (including other threads) to the main loop,
+/// which checks for them once every iteration
+pub struct Interrupts {
+    sender: Sender<InterruptEvent>,
+    receiver: Receiver<InterruptEvent>,
+}
+
+impl Default for Interrupts {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {sender, receiver}
+    }
+}
+
+impl Interrupts {
+    /// Returns a sender that can report interrupt events from another thread
+    pub fn sender(&self) -> Sender<InterruptEvent> {
+        self.sender.clone()
+    }
+
+    /// Reports an interrupt event
+    pub fn report(&self, event: InterruptEvent) {
+        // The receiver is owned by self, so it cannot have been dropped yet
+        self.sender.send(event).expect("bug: interrupt receiver was dropped");
+    }
+
+    /// Takes every interrupt event reported since the last call, in the order they were reported
+    pub fn take(&self) -> Vec<InterruptEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn events_from_other_threads_arrive_in_order() {
+        let interrupts = Interrupts::default();
+        assert_eq!(interrupts.take(), &[]);
+
+        let failure = |what| InterruptEvent::SaveFailed {what, path: "profile.txt".to_string(), error: "disk full".to_string()};
+        let sender = interrupts.sender();
+        thread::spawn(move || {
+            sender.send(failure("profile")).unwrap();
+            sender.send(failure("run history")).unwrap();
+        }).join().unwrap();
+        interrupts.report(failure("settings"));
+
+        assert_eq!(interrupts.take(), &[failure("profile"), failure("run history"), failure("settings")]);
+        assert_eq!(interrupts.take(), &[]);
+        assert_eq!(failure("profile").lines(), &["Unable to save the profile to profile.txt", "disk full"]);
+    }
+}
@@ -0,0 +1,34 @@
+//! A procedurally generated dungeon crawler. The game itself is in the `caves` binary. The library
+//! exists so that the benchmarks can use the same code as the game.
+
+#![deny(unused_must_use)]
+
+#[macro_use]
+extern crate specs_derive;
+#[macro_use]
+extern crate shred_derive;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+
+pub mod systems;
+pub mod components;
+pub mod generator;
+pub mod resources;
+pub mod map;
+pub mod ui;
+pub mod map_sprites;
+pub mod assets;
+pub mod audio;
+pub mod achievements;
+pub mod upgrades;
+pub mod settings;
+pub mod run_history;
+pub mod replays;
+pub mod interrupts;
+pub mod geometry;
+pub mod timestep;
+
+#[cfg(test)]
+pub mod test_helpers;
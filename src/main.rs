@@ -1,195 +1,540 @@
 #![deny(unused_must_use)]
 
-#[macro_use]
-extern crate specs_derive;
-#[macro_use]
-extern crate shred_derive;
-#[macro_use]
-extern crate lazy_static;
-
-use sdl2;
-use shred;
-
-mod systems;
-mod components;
-mod generator;
-mod resources;
-mod map;
-mod ui;
-mod map_sprites;
-mod assets;
-
-use std::{thread,time::Duration};
-
-use sdl2::{event::Event as SDLEvent, keyboard::{Keycode, Scancode}};
+use std::{env, fs, io, mem, process, thread, error::Error, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use log::{LevelFilter, debug, info, warn};
+use sdl2::{EventPump, TimerSubsystem, clipboard::ClipboardUtil, event::Event as SDLEvent, keyboard::{Keycode, Scancode, Mod}, mouse::MouseButton, rect::Point, render::RenderTarget};
 use specs::{DispatcherBuilder, World};
 
-use crate::components::{
+use caves::{systems, generator, ui, assets, resources};
+use caves::timestep::FixedTimestep;
+use caves::achievements::Profile;
+use caves::settings::Settings;
+use caves::run_history::{RunHistory, RunRecord};
+use caves::replays::Replay;
+use caves::interrupts::{Interrupts, InterruptEvent};
+use caves::components::{
     PlayerComponents,
     Position,
     HealthPoints,
+    MaxHealthPoints,
+    Attack,
+    HitInvulnerability,
     Movement,
     BoundingBox,
     KeyboardControlled,
     CameraFocus,
     Sprite,
     Player,
-    EnemyBehaviour,
+    Inventory,
+    Equipment,
 };
-use crate::assets::{AssetManager, EnemyAnimations};
-use crate::resources::{FramesElapsed, ChangeGameState, ActionQueue, EventQueue, Event, Key};
-use crate::ui::{Window, GameScreen, SDLError, RenderContext};
-use crate::generator::{GameGenerator, GenGame, EnemyConfig, EnemyType, EnemyValues};
-use crate::map_sprites::MapSprites;
-
-const MAX_FRAMES_PER_UPDATE: usize = 2;
-
-fn game_generator<'a>(
-    tile_size: u32,
-    map_sprites: &'a MapSprites,
-    enemy_animations: EnemyAnimations,
-) -> GameGenerator<'a> {
-    use self::EnemyType::*;
-    GameGenerator {
-        attempts: 2000,
-        levels: 10,
-        rows: 40,
-        cols: 50,
-        tile_size,
-        rooms: (6, 9).into(),
-        room_rows: (7, 14).into(),
-        room_cols: (8, 16).into(),
-        max_overlap: 0.35,
-        doors: (1, 3).into(),
-        next_prev_tiles: 2,
-        room_enemies: (0, 5).into(),
-        max_room_enemy_area: 0.4,
-        sprites: map_sprites,
-        enemy_config: EnemyConfig {
-            rat: EnemyValues {
-                behaviour: EnemyBehaviour::Random,
-                animations: enemy_animations.rat,
-                attack: 5,
-                speed: 3,
-                health_points: 15,
-                hit_wait: 12,
-                bounding_box: BoundingBox::Full {width: 16, height: 16},
-            },
-            // Allowed enemies on each level
-            levels: &[
-                // Level 1
-                &[Rat],
-                // Level 2
-                &[Rat],
-                // Level 3
-                &[Rat],
-                // Level 4
-                &[Rat],
-                // Level 5
-                &[Rat],
-                // Level 6
-                &[Rat],
-                // Level 7
-                &[Rat],
-                // Level 8
-                &[Rat],
-                // Level 9
-                &[Rat],
-                // Level 10
-                &[Rat],
-            ],
+use caves::assets::{AssetManager, AssetPaths, AssetWatcher};
+use caves::audio::AudioManager;
+use caves::resources::{FramesElapsed, Event, Key, Notification, GameState};
+use caves::upgrades::RunOutcome;
+use caves::ui::{Window, GameScreen, GameOverChoice, GhostRun, GhostTrack, SDLError, RenderContext, Zoom, Screenshots, screenshot_filename};
+use caves::generator::{GameGenerator, GeneratorConfig, GeneratorAnimations, GenGame, GenLevel, MapKey, Severity, EnemyConfig};
+use caves::systems::{LevelDispatcher, SequentialDispatcher, build_dispatcher};
+
+/// The file that the achievements, coins, and upgrades of the player are saved in
+const PROFILE_PATH: &str = "profile.txt";
+/// The file that the options chosen by the player are read from
+const SETTINGS_PATH: &str = "settings.txt";
+/// The file that a record of every finished run is appended to
+const RUN_HISTORY_PATH: &str = "run_history.txt";
+/// The file that the map key is written to when it cannot be copied to the clipboard. Kept next to
+/// the other files the game saves.
+const MAP_KEY_PATH: &str = "last_map_key.txt";
+/// The directory that the fastest victory on each map is saved in so that it can be played back
+/// as a ghost
+const REPLAYS_DIR: &str = "replays";
+/// The number of runs listed on the game over screen
+const RECENT_RUNS: usize = 10;
+/// The file that `--list-invariants` writes the table of level invariants to
+const INVARIANTS_PATH: &str = "invariants.md";
+/// The file that the generator config is loaded from, if it exists
+const GENERATOR_CONFIG_PATH: &str = "assets/generator.toml";
+
+/// Prints statistics about each of the given levels as a table, followed by the values that each
+/// type of enemy has on each level
+fn print_stats(levels: &[GenLevel<'_, '_>], enemy_config: &EnemyConfig) {
+    println!("{:>5} {:>5} {:>16} {:>8} {:>7} {:>9}  paths to next level",
+        "level", "rooms", "area min/med/max", "doorways", "enemies", "reachable");
+    for (i, level) in levels.iter().enumerate() {
+        let stats = level.stats();
+        let area = format!("{}/{}/{}", stats.room_area.min, stats.room_area.median, stats.room_area.max);
+        let paths: Vec<_> = stats.next_level_paths.iter()
+            .map(|path| path.map(|len| len.to_string()).unwrap_or_else(|| "-".to_string()))
+            .collect();
+        println!("{:>5} {:>5} {:>16} {:>8} {:>7} {:>8.1}%  {}",
+            i + 1, stats.rooms, area, stats.doorways, stats.enemies, stats.reachable_floor * 100.0, paths.join(", "));
+    }
+
+    println!();
+    println!("{:>5} {:>6} {:>6} {:>6} {:>6}", "level", "enemy", "attack", "health", "speed");
+    for level in 1..=levels.len() {
+        for &enemy in enemy_config.level_types(level) {
+            let values = enemy_config.level_values(enemy, level);
+            println!("{:>5} {:>6} {:>6} {:>6} {:>6.2}",
+                level, enemy.name(), values.attack, values.health_points, values.speed);
+        }
+    }
+}
+
+/// Writes every log message that is at or above the maximum level to stderr
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() {
+    // Defaults to info so that the map key is always shown
+    let log_level = match option_value("--log-level").map(|level| level.parse()) {
+        None => LevelFilter::Info,
+        Some(Ok(level)) => level,
+        Some(Err(_)) => {
+            eprintln!("Error: --log-level must be one of: off, error, warn, info, debug, trace");
+            process::exit(1);
         },
+    };
+    log::set_logger(&StderrLogger).expect("bug: logger should only be set once");
+    log::set_max_level(log_level);
+
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        // The full chain of errors is only useful when tracking down a problem
+        let mut source = err.source();
+        if has_flag("--verbose") {
+            while let Some(err) = source {
+                eprintln!("  caused by: {}", err);
+                source = err.source();
+            }
+        } else if source.is_some() {
+            eprintln!("Run with --verbose for more details");
+        }
+        process::exit(1);
     }
 }
 
-fn main() -> Result<(), SDLError> {
+/// Returns true if the given flag was passed on the command line
+fn has_flag(flag: &str) -> bool {
+    env::args().skip(1).any(|arg| arg == flag)
+}
+
+/// Returns the value passed after the given option on the command line, if any
+fn option_value(option: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+    args.find(|arg| arg == option)?;
+    args.next()
+}
+
+/// Checks every generated level against every invariant, printing the invariants and any
+/// violations. Returns false if any violation is an error.
+fn self_check(levels: &[GenLevel<'_, '_>]) -> bool {
+    println!("Invariants:");
+    for validator in generator::VALIDATORS {
+        println!("  {} ({}): {}", validator.name(), validator.severity(), validator.invariant());
+    }
+
+    let mut passed = true;
+    for (i, level) in levels.iter().enumerate() {
+        for violation in generator::check_level(level) {
+            println!("level {}: {}", i + 1, violation);
+            passed &= violation.severity != Severity::Error;
+        }
+    }
+    passed
+}
+
+fn run() -> Result<(), SDLError> {
+    // Generated from the validators so that it never goes out of date
+    if has_flag("--list-invariants") {
+        fs::write(INVARIANTS_PATH, generator::invariants_markdown())?;
+        println!("Wrote invariants to {}", INVARIANTS_PATH);
+        return Ok(());
+    }
+
     let fps = 30.0;
 
-    let mut window = Window::init(320, 240)?;
+    let screen_size = (320, 240);
+    let mut window = Window::init(screen_size.0, screen_size.1)?;
     let texture_creator = window.texture_creator();
     let mut event_pump = window.event_pump()?;
 
-    let tile_size = 16;
-    let AssetManager {
-        textures,
+    let settings = Settings::load(SETTINGS_PATH).unwrap_or_else(|err| {
+        warn!("Unable to load settings from {}: {}", SETTINGS_PATH, err);
+        Settings::default()
+    });
+
+    // Kept between runs so that the game stays zoomed in the way the player chose
+    let mut zoom = Zoom::new(screen_size, settings.pixel_perfect);
+    zoom.apply(window.canvas_mut())?;
+
+    let generator_config = match GeneratorConfig::load(GENERATOR_CONFIG_PATH) {
+        Ok(config) => {
+            info!("Using generator config from {}", GENERATOR_CONFIG_PATH);
+            config
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            info!("Using built-in generator config ({} not found)", GENERATOR_CONFIG_PATH);
+            GeneratorConfig::default()
+        },
+        Err(err) => {
+            eprintln!("Unable to load generator config from {}: {}", GENERATOR_CONFIG_PATH, err);
+            process::exit(1);
+        },
+    };
+
+    let tile_size = generator_config.tile_size;
+    let (AssetManager {
+        mut textures,
         map_sprites,
         player_animations,
-        enemy_animations,
-        sprites,
-    } = AssetManager::load(&texture_creator, fps as usize, tile_size)?;
+        ghost_texture,
+        mut enemy_animations,
+        mut sprites,
+        mut audio,
+    }, load_report) = AssetManager::load_with_report(&texture_creator, fps as usize, settings.hero_palette)?;
+    if has_flag("--verbose") {
+        eprint!("{}", load_report);
+    }
 
+    // Running systems one at a time to time them is slower, so this is only done in debug builds
+    // or when explicitly requested
+    let watch_frame_budget = cfg!(debug_assertions) || env::var_os("CAVES_FRAME_WATCHDOG").is_some();
     let keyboard_system = systems::Keyboard::default();
-    let GenGame {key, levels, player_start} = game_generator(
-        tile_size,
-        &map_sprites,
-        enemy_animations,
-    ).generate(|| {
-        let mut world = World::new();
-
-        world.add_resource(FramesElapsed(1));
-        world.add_resource(ChangeGameState::default());
-        world.add_resource(EventQueue::default());
-        world.add_resource(ActionQueue::default());
-
-        let mut dispatcher = DispatcherBuilder::new()
-            .with(keyboard_system.clone(), "Keyboard", &[])
-            .with(systems::AI, "AI", &[])
-            .with(systems::Physics, "Physics", &["Keyboard", "AI"])
-            .with(systems::Interactions, "Interactions", &["Physics"])
-            .with(systems::Animator, "Animator", &["Interactions"])
-            .build();
-
-        dispatcher.setup(&mut world.res);
-        // Renderer is not called in the dispatcher, so we need to separately set up the component
-        // storages for anything it uses.
-        ui::setup(&mut world.res);
-
-        (dispatcher, world)
+    // Spritesheets are reloaded as soon as they change so that they can be edited without
+    // restarting the game. Only done in debug builds since it is only useful during development.
+    let asset_watcher = if cfg!(debug_assertions) {
+        Some(AssetWatcher::watch(AssetPaths::from_env().dirs()))
+    } else {
+        None
+    };
+
+    let mut profile = Profile::load(PROFILE_PATH).unwrap_or_else(|err| {
+        warn!("Unable to load profile from {}: {}", PROFILE_PATH, err);
+        Profile::default()
     });
 
-    println!("Map Key: {}", key);
-
-    // Add the character
-    let player = PlayerComponents {
-        keyboard_controlled: KeyboardControlled,
-        camera_focus: CameraFocus,
-        player: Player,
-        health_points: HealthPoints(20),
-        position: Position(player_start),
-        bounding_box: BoundingBox::BottomHalf {width: 16, height: 8},
-        movement: Movement::default(),
-        sprite: Sprite(player_animations.default_sprite()),
-        animation: player_animations.default_animation(),
-        animation_manager: player_animations,
-    };
+    let mut history = RunHistory::load(RUN_HISTORY_PATH).unwrap_or_else(|err| {
+        warn!("Unable to load run history from {}: {}", RUN_HISTORY_PATH, err);
+        RunHistory::default()
+    });
 
-    let mut game_screen = GameScreen::new(player, levels);
+    audio.play_music();
 
-    for (i, level) in game_screen.levels().enumerate() {
-        level.render_to_file(format!("level{}.png", i+1))?;
-    }
+    // Only report on each room of the map with the given key. Useful when tuning the generator.
+    let analyze_key = option_value("--analyze-key").map(|key| key.parse().unwrap_or_else(|err| {
+        eprintln!("Error: invalid --analyze-key: {}", err);
+        process::exit(1);
+    }));
+
+    // Problems (e.g. failed saves) that pause the game until the player acknowledges them
+    let interrupts = Interrupts::default();
 
     let mut timer = window.timer()?;
-    let mut ctx = RenderContext::new(window.canvas_mut(), &textures, &sprites, &map_sprites);
+    let clipboard = window.clipboard()?;
+    // The key of the map to play again, or None to generate a new map
+    let mut retry_key = analyze_key;
+    loop {
+        // Only the enemies that can actually be generated need their spritesheets. Spritesheets
+        // that were freed after the previous run are loaded again.
+        for enemy in generator::level_enemy_types(generator_config.enemy_levels) {
+            enemy_animations.get_mut(enemy).load(&mut textures, &mut sprites)?;
+        }
+
+        let game_generator = GameGenerator::from_config(generator_config.clone(), &map_sprites, GeneratorAnimations {
+            // Prisoners are fellow adventurers, so they look just like the player
+            prisoner: player_animations.clone(),
+            rat: enemy_animations.rat.animations().cloned(),
+        });
+        // Every run starts from freshly set up worlds, even when the same map is played again
+        let setup_world = || {
+            let mut world = World::new();
+            resources::reset_run_resources(&mut world);
+
+            let mut dispatcher = if watch_frame_budget {
+                LevelDispatcher::Watchdog(build_dispatcher(SequentialDispatcher::default(), keyboard_system.clone()))
+            } else {
+                LevelDispatcher::Parallel(build_dispatcher(DispatcherBuilder::new(), keyboard_system.clone()).build())
+            };
+
+            dispatcher.setup(&mut world.res);
+            // Renderer is not called in the dispatcher, so we need to separately set up the component
+            // storages for anything it uses.
+            ui::setup(&mut world.res);
+
+            (dispatcher, world)
+        };
+        let enemy_config = game_generator.enemy_config.clone();
+        // The generator is kept so that the same map can be generated again for the ghost
+        let generated = match retry_key {
+            Some(key) => game_generator.clone().generate_with_key(key, setup_world),
+            None => game_generator.clone().generate(setup_world),
+        };
+        let GenGame {key, levels, player_start, level_times} = generated.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+
+        info!("Map Key: {}", key);
+
+        // Only print statistics about the generated levels. Useful when tuning the generator.
+        if has_flag("--stats") {
+            print_stats(&levels, &enemy_config);
+            return Ok(());
+        }
+        if analyze_key.is_some() {
+            for (i, level) in levels.iter().enumerate() {
+                let reports = level.room_reports(generator_config.max_room_enemy_area);
+                println!("{}", generator::format_room_reports(i + 1, &reports, generator_config.max_room_enemy_area));
+            }
+            return Ok(());
+        }
+        // Only check that the generated levels follow every invariant
+        if has_flag("--selfcheck") {
+            if !self_check(&levels) {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        let generation_time = level_times.iter().max().cloned().unwrap_or_default();
+
+        // Free any enemy spritesheets that ended up not being used on any level. This must happen
+        // before the textures are borrowed for rendering.
+        for animations in enemy_animations.all_mut() {
+            if let Some(texture) = animations.texture() {
+                if !levels.iter().any(|level| assets::world_uses_texture(&level.world, &sprites, texture)) {
+                    animations.unload(&mut textures);
+                }
+            }
+        }
+
+        // Sets up a game with the character added to the given levels. Called again with freshly
+        // generated levels to play back the fastest previous run on this map.
+        let new_game_screen = |levels, player_start| {
+            let mut player = PlayerComponents {
+                keyboard_controlled: KeyboardControlled::default(),
+                camera_focus: CameraFocus,
+                player: Player,
+                health_points: HealthPoints(20),
+                max_health_points: MaxHealthPoints(20),
+                attack: Attack(10),
+                hit_invulnerability: HitInvulnerability(30),
+                position: Position(player_start),
+                bounding_box: BoundingBox::BottomHalf {width: 16, height: 8}.scale_to_tile_size(tile_size),
+                movement: Movement::default(),
+                sprite: Sprite(player_animations.default_sprite()),
+                animation: player_animations.default_animation(),
+                animation_manager: player_animations.clone(),
+                inventory: Inventory::default(),
+                equipment: Equipment::default(),
+            };
+            profile.upgrades.apply(&mut player);
+
+            let mut game_screen = GameScreen::new(player, levels, &profile, fps as usize);
+            game_screen.set_map_key(key);
+            game_screen.set_escape_ending(settings.escape_ending);
+            if !settings.skip_tutorial {
+                game_screen.start_tutorial();
+            }
+            game_screen
+        };
+
+        let mut game_screen = new_game_screen(levels, player_start);
+        match Replay::load(REPLAYS_DIR, key) {
+            Ok(Some(replay)) => {
+                // The ghost is extracted from a copy of the game that is never shown
+                let GenGame {levels, player_start, ..} = game_generator.generate_with_key(key, setup_world)
+                    .unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        process::exit(1);
+                    });
+                let track = GhostTrack::extract(new_game_screen(levels, player_start), &replay.inputs, |done, total| {
+                    debug!("Extracting ghost: {}/{} frames", done, total);
+                });
+                info!("Playing back the fastest run on this map ({} frames)", replay.frames);
+                game_screen.set_ghost(GhostRun::new(track, ghost_texture));
+            },
+            Ok(None) => {},
+            Err(err) => warn!("Unable to load replay from {}: {}", Replay::path(REPLAYS_DIR, key).display(), err),
+        }
+
+        for (i, level) in game_screen.levels().enumerate() {
+            level.render_to_file(format!("level{}.png", i+1))?;
+        }
+
+        let mut ctx = RenderContext::new(window.canvas_mut(), &mut textures, &sprites, &map_sprites)?;
+        ctx.screen_shake = !settings.reduce_motion;
+        let services = GameServices {
+            history: &mut history,
+            interrupts: &interrupts,
+            clipboard: &clipboard,
+            zoom: &mut zoom,
+            event_pump: &mut event_pump,
+            timer: &mut timer,
+            audio: &mut audio,
+            asset_watcher: asset_watcher.as_ref(),
+        };
+        let choice = run_game(&mut game_screen, key, &mut ctx, services, fps, generation_time)?;
+
+        save_replay(&game_screen, key, &interrupts);
+
+        // Keeps any progress made towards achievements that were not unlocked
+        profile = game_screen.profile();
+        save_profile(&profile, &interrupts);
+
+        retry_key = match choice {
+            Some(GameOverChoice::Retry) => Some(key),
+            Some(GameOverChoice::NewSeed) => None,
+            // The player quit the game
+            None => break,
+        };
+    }
+
+    Ok(())
+}
+
+/// Reloads every texture whose file has changed. Textures that cannot be loaded (e.g. a PNG that
+/// is only partly saved) are kept as they were, since the file will change again once it is saved.
+///
+/// Only the pixels of each texture change. Sprites still refer to the same regions of their
+/// spritesheet, so changing the layout of a spritesheet (e.g. moving tiles) requires a restart.
+fn reload_changed_textures<T: RenderTarget>(watcher: &AssetWatcher, ctx: &mut RenderContext<T>) {
+    for path in watcher.changed_paths() {
+        match ctx.textures.reload(&path) {
+            Ok(reloaded) => if !reloaded.is_empty() {
+                info!("Reloaded {}", path.display());
+            },
+            Err(err) => warn!("Unable to reload {}, keeping the old texture: {}", path.display(), err),
+        }
+    }
+}
+
+/// Everything used while running the game that outlives a single run
+struct GameServices<'a> {
+    history: &'a mut RunHistory,
+    interrupts: &'a Interrupts,
+    clipboard: &'a ClipboardUtil,
+    zoom: &'a mut Zoom,
+    event_pump: &'a mut EventPump,
+    timer: &'a mut TimerSubsystem,
+    audio: &'a mut AudioManager,
+    asset_watcher: Option<&'a AssetWatcher>,
+}
 
-    // Frames elapsed since the last render
-    let mut last_frames_elapsed = 0;
+/// Runs the game until the player quits or makes a choice after being defeated. Returns the
+/// choice that was made, or None if the player quit.
+fn run_game<T: RenderTarget>(
+    game_screen: &mut GameScreen,
+    key: MapKey,
+    ctx: &mut RenderContext<T>,
+    services: GameServices<'_>,
+    fps: f64,
+    generation_time: Duration,
+) -> Result<Option<GameOverChoice>, SDLError> {
+    let GameServices {history, interrupts, clipboard, zoom, event_pump, timer, audio, asset_watcher} = services;
+    let mut timestep = FixedTimestep::new(fps as usize, timer.ticks());
+    // Waiting for vsync paces the loop, so there is only a need to sleep when vsync is off
+    let vsync = ctx.canvas.info().flags & sdl2::sys::SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
     // Events since the last dispatch
     let mut events = Vec::new();
-    let mut running = true;
+    // Mouse buttons pressed since the last time events were polled
+    let mut clicks = Vec::new();
     let mut debug = false;
-    while running {
+    let started = Instant::now();
+    // True once the run has been added to the run history
+    let mut recorded = false;
+    // True while the music is paused along with the game
+    let mut music_paused = false;
+    let screenshots = Screenshots::default();
+    // True if a screenshot should be taken the next time a frame is drawn
+    let mut screenshot_requested = false;
+    loop {
         let ticks = timer.ticks(); // ms
 
+        if let Some(watcher) = asset_watcher {
+            reload_changed_textures(watcher, ctx);
+        }
+
+        let playing = game_screen.game_state() == GameState::Playing;
         for event in event_pump.poll_iter() {
             match event {
                 SDLEvent::Quit {..} | SDLEvent::KeyDown {keycode: Some(Keycode::Escape), ..} => {
-                    running = false;
+                    return Ok(None);
                 },
                 SDLEvent::KeyDown {scancode: Some(Scancode::D), repeat: false, ..} => {},
                 SDLEvent::KeyUp {scancode: Some(Scancode::D), repeat: false, ..} => {
                     debug = !debug;
                 },
+                SDLEvent::KeyDown {scancode: Some(Scancode::B), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::B), repeat: false, ..} => {
+                    ctx.show_bounding_boxes = !ctx.show_bounding_boxes;
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::G), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::G), repeat: false, ..} => {
+                    ctx.show_tile_overlay = !ctx.show_tile_overlay;
+                },
+                // Plain-text description of the surroundings, only logged while the debug view is
+                // shown
+                SDLEvent::KeyDown {scancode: Some(Scancode::T), repeat: false, ..} if debug => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::T), repeat: false, ..} if debug => {
+                    info!("Surroundings:\n{}", game_screen.describe_surroundings());
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::P), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::P), repeat: false, ..} => {
+                    ctx.show_play_clock = !ctx.show_play_clock;
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::M), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::M), repeat: false, ..} => {
+                    audio.toggle_mute();
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::F12), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::F12), repeat: false, ..} => {
+                    screenshot_requested = true;
+                },
+                // Skipping between floors is only possible while the debug view is shown and the
+                // game is being played
+                SDLEvent::KeyDown {scancode: Some(Scancode::PageDown), repeat: false, ..} if debug && playing => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::PageDown), repeat: false, ..} if debug && playing => {
+                    let next = game_screen.current_level_index() + 1;
+                    game_screen.go_to_level(next);
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::PageUp), repeat: false, ..} if debug && playing => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::PageUp), repeat: false, ..} if debug && playing => {
+                    match game_screen.current_level_index().checked_sub(1) {
+                        Some(prev) => game_screen.go_to_level(prev),
+                        None => game_screen.notify(Notification::new("Already on the first floor")),
+                    }
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::Equals), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::Equals), repeat: false, ..} => {
+                    // The camera is clamped to the new logical size the next time the level is rendered
+                    zoom.zoom_in();
+                    zoom.apply(ctx.canvas)?;
+                },
+                SDLEvent::KeyDown {scancode: Some(Scancode::Minus), repeat: false, ..} => {},
+                SDLEvent::KeyUp {scancode: Some(Scancode::Minus), repeat: false, ..} => {
+                    zoom.zoom_out();
+                    zoom.apply(ctx.canvas)?;
+                },
+                // Only the screens that show the map key can copy it
+                SDLEvent::KeyDown {keycode: Some(Keycode::C), keymod, repeat: false, ..}
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && game_screen.shows_map_key() => {
+                    copy_map_key(key, clipboard, game_screen, interrupts);
+                },
                 SDLEvent::KeyDown {scancode: Some(scancode), repeat: false, ..} => {
                     if let Some(scancode) = Key::from_scancode(scancode) {
                         events.push(Event::KeyDown(scancode));
@@ -200,37 +545,180 @@ fn main() -> Result<(), SDLError> {
                         events.push(Event::KeyUp(scancode));
                     }
                 },
+                SDLEvent::MouseButtonDown {mouse_btn, ..} => clicks.push(mouse_btn),
                 _ => {},
             }
         }
 
-        let frames_elapsed = (ticks as f64 / 1000.0 * fps) as usize;
-        let frames_elapsed_delta = frames_elapsed - last_frames_elapsed;
-        // limit the maximum number of frames we update at a given time
-        let frames_elapsed_delta = frames_elapsed_delta.min(MAX_FRAMES_PER_UPDATE);
+        if !clicks.is_empty() {
+            // SDL already reports the positions of mouse events relative to the logical size of
+            // the canvas, but the mouse state is always in window coordinates
+            let mouse = event_pump.mouse_state();
+            // The window is not created with high DPI support, so its size in px is the same as
+            // the size of the canvas
+            let window_size = ctx.canvas.output_size().map_err(SDLError::Sdl)?;
+            let target = game_screen.current_level().camera_offset().and_then(|camera_offset| {
+                ui::window_to_world(Point::new(mouse.x(), mouse.y()), window_size, ctx.canvas.logical_size(), zoom.pixel_perfect(), camera_offset)
+            });
+            for button in clicks.drain(..) {
+                match (button, target) {
+                    (MouseButton::Left, Some(target)) => events.push(Event::PointerAttack(target)),
+                    (MouseButton::Right, Some(target)) => events.push(Event::PointerInteract(target)),
+                    _ => {},
+                }
+            }
+        }
 
-        // At least one frame must have passed for us to do anything
-        if frames_elapsed_delta >= 1 {
-            game_screen.dispatch(FramesElapsed(frames_elapsed_delta), events.drain(..).collect());
+        for result in screenshots.finished() {
+            match result {
+                Ok(path) => game_screen.notify(Notification::new(format!("Screenshot saved to {}", path))),
+                Err(err) => {
+                    warn!("Unable to save screenshot: {}", err);
+                    game_screen.notify(Notification::new("Unable to save screenshot"));
+                },
+            }
+        }
 
-            ctx.canvas.clear();
-            game_screen.render(&mut ctx)?;
-            if debug {
-                let elapsed = timer.ticks() - ticks; // ms/frame
-                ui::render_debug_view(&mut ctx, ui::DebugInfo {
-                    // (1000 ms / s) / (ms / frame) == (frames / s)
-                    fps: (1000.0 / elapsed as f64) as u32,
-                })?;
+        // Every frame of the game is exactly the same length. Events go to the first frame run.
+        let frames = timestep.update(ticks);
+        for _ in 0..frames {
+            for event in interrupts.take() {
+                game_screen.interrupt(event);
+            }
+            let sounds = game_screen.dispatch(FramesElapsed(1), mem::take(&mut events));
+            audio.play_all(sounds);
+            let pause_music = !music_plays_during(game_screen.game_state());
+            if pause_music != music_paused {
+                if pause_music {
+                    audio.pause_music();
+                } else {
+                    audio.resume_music();
+                }
+                music_paused = pause_music;
+            }
+            if !game_screen.take_unlocked().is_empty() {
+                save_profile(&game_screen.profile(), interrupts);
+            }
+            if !recorded {
+                if let (Some(outcome), Some(game_over)) = (game_screen.run_outcome(), game_screen.game_over()) {
+                    let mut totals = game_over.stats().totals;
+                    totals.add(game_over.stats().floor);
+                    record_run(history, interrupts, RunRecord {
+                        map_key: key,
+                        outcome,
+                        floor: game_over.floor(),
+                        enemies_killed: totals.enemies_killed,
+                        frames: game_screen.play_clock().frames(),
+                        duration: started.elapsed(),
+                        finished_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+                    });
+                    game_screen.set_run_history(history.recent(RECENT_RUNS).cloned().collect());
+                    // The gold carried at the end of the run was just banked
+                    save_profile(&game_screen.profile(), interrupts);
+                    recorded = true;
+                }
             }
-            ctx.canvas.present();
+            if let Some(choice) = game_screen.game_over_choice() {
+                return Ok(Some(choice));
+            }
+        }
+
+        // Nothing has changed if no frames were run
+        if frames == 0 && !vsync {
+            thread::sleep(Duration::from_millis(timestep.ms_until_next_frame() as u64));
+            continue;
+        }
 
-            last_frames_elapsed = frames_elapsed;
-        } else {
-            let ms_per_frame = (1000.0 / fps) as u64;
-            let ms_elapsed = (timer.ticks() - ticks) as u64;
-            thread::sleep(Duration::from_millis(ms_per_frame - ms_elapsed));
+        ctx.canvas.clear();
+        game_screen.render(ctx)?;
+        if debug {
+            let elapsed = timer.ticks() - ticks; // ms/frame
+            ui::render_debug_view(ctx, ui::DebugInfo {
+                // (1000 ms / s) / (ms / frame) == (frames / s)
+                fps: (1000.0 / elapsed as f64) as u32,
+                generation_time,
+                room: game_screen.current_room_uid(),
+            })?;
         }
+        if screenshot_requested {
+            screenshot_requested = false;
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+            if let Err(err) = screenshots.capture(ctx.canvas, zoom.pixel_perfect(), screenshot_filename(timestamp, key)) {
+                warn!("Unable to take screenshot: {}", err);
+                game_screen.notify(Notification::new("Unable to take screenshot"));
+            }
+        }
+        ctx.canvas.present();
     }
+}
 
-    Ok(())
+/// Returns true if the background music keeps playing while the game is in the given state. The
+/// music stops while the level is paused (e.g. for the shop) and picks up where it left off after.
+fn music_plays_during(state: GameState) -> bool {
+    match state {
+        GameState::Playing | GameState::LevelTransition {..} => true,
+        GameState::Victory | GameState::Defeat => true,
+        GameState::Paused | GameState::Shop {..} => false,
+    }
+}
+
+/// Adds the given run to the run history and to the end of the run history file. The player is
+/// told if the run could not be saved.
+fn record_run(history: &mut RunHistory, interrupts: &Interrupts, record: RunRecord) {
+    if let Err(err) = RunHistory::append(RUN_HISTORY_PATH, &record) {
+        warn!("Unable to save run to {}: {}", RUN_HISTORY_PATH, err);
+        interrupts.report(InterruptEvent::SaveFailed {what: "run", path: RUN_HISTORY_PATH.to_string(), error: err.to_string()});
+    }
+    history.push(record);
+}
+
+/// Copies the map key to the clipboard so that the map can be shared or played again. If the
+/// clipboard cannot be used, the key is written to a file instead and the player is told where.
+fn copy_map_key(key: MapKey, clipboard: &ClipboardUtil, game_screen: &mut GameScreen, interrupts: &Interrupts) {
+    match clipboard.set_clipboard_text(&key.to_string()) {
+        Ok(()) => {
+            game_screen.notify(Notification::new("Map key copied"));
+            return;
+        },
+        Err(err) => warn!("Unable to copy map key to the clipboard: {}", err),
+    }
+
+    match fs::write(MAP_KEY_PATH, format!("{}\n", key)) {
+        Ok(()) => game_screen.notify(Notification::new(format!("Map key saved to {}", MAP_KEY_PATH))),
+        Err(err) => {
+            warn!("Unable to save map key to {}: {}", MAP_KEY_PATH, err);
+            interrupts.report(InterruptEvent::SaveFailed {what: "map key", path: MAP_KEY_PATH.to_string(), error: err.to_string()});
+        },
+    }
+}
+
+/// Saves the inputs of the given game if the run ended in victory faster than any previous
+/// victory on the same map. The player is told if the inputs could not be saved.
+fn save_replay(game_screen: &GameScreen, key: MapKey, interrupts: &Interrupts) {
+    if game_screen.run_outcome() != Some(RunOutcome::Victory) {
+        return;
+    }
+
+    let replay = Replay {
+        frames: game_screen.play_clock().frames(),
+        inputs: game_screen.recorded_inputs().to_vec(),
+    };
+    // A replay that cannot be loaded is replaced since it could never be played back anyway
+    let best = Replay::load(REPLAYS_DIR, key).unwrap_or(None);
+    if !replay.is_faster_than(best.as_ref()) {
+        return;
+    }
+    if let Err(err) = replay.save(REPLAYS_DIR, key) {
+        let path = Replay::path(REPLAYS_DIR, key).display().to_string();
+        warn!("Unable to save replay to {}: {}", path, err);
+        interrupts.report(InterruptEvent::SaveFailed {what: "replay", path, error: err.to_string()});
+    }
+}
+
+/// Saves the given profile. The player is told if it could not be saved.
+fn save_profile(profile: &Profile, interrupts: &Interrupts) {
+    if let Err(err) = profile.save(PROFILE_PATH) {
+        warn!("Unable to save profile to {}: {}", PROFILE_PATH, err);
+        interrupts.report(InterruptEvent::SaveFailed {what: "profile", path: PROFILE_PATH.to_string(), error: err.to_string()});
+    }
 }
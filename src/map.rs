@@ -4,6 +4,11 @@ mod room;
 mod tile_pos;
 mod tile_rect;
 mod tile;
+mod layout_transform;
+mod uid;
+mod stats;
+mod reachability;
+mod ascii;
 
 pub use self::grid_size::*;
 pub use self::grid::*;
@@ -11,9 +16,14 @@ pub use self::room::*;
 pub use self::tile_pos::*;
 pub use self::tile_rect::*;
 pub use self::tile::*;
+pub use self::layout_transform::*;
+pub use self::uid::*;
+pub use self::stats::*;
+pub use self::ascii::*;
 
 use std::fmt;
 use std::cmp;
+use std::collections::HashSet;
 
 use sdl2::rect::{Rect, Point};
 
@@ -55,12 +65,14 @@ impl fmt::Debug for FloorMap {
             for tile in row {
                 use self::Tile::*;
                 write!(f, "{}", match tile {
-                    &Floor {room_id, ..} => {
+                    &Floor {room_id, hazard, ..} => {
+                        let symbol = if hazard.is_some() { "~" } else { " " };
                         match self.room(room_id).room_type() {
-                            RoomType::Normal => " ".on_blue(),
-                            RoomType::Challenge => " ".on_red(),
-                            RoomType::PlayerStart => " ".on_bright_blue(),
-                            RoomType::TreasureChamber => " ".on_yellow(),
+                            RoomType::Normal => symbol.on_blue(),
+                            RoomType::Challenge => symbol.on_red(),
+                            RoomType::PlayerStart => symbol.on_bright_blue(),
+                            RoomType::TreasureChamber => symbol.on_yellow(),
+                            RoomType::Merchant => symbol.on_green(),
                         }
                     },
                     Wall {..} => "\u{25a2}".on_black(),
@@ -75,6 +87,26 @@ impl fmt::Debug for FloorMap {
     }
 }
 
+/// The number of rows of a map written in each log record by `log_map`
+pub const LOG_MAP_CHUNK_ROWS: usize = 8;
+
+/// Logs the alternate (`{:#?}`) rendering of the given map a few rows at a time since some
+/// terminals choke on a single write as large as an entire map. The map is only formatted if the
+/// given level is enabled.
+pub fn log_map(map: &FloorMap, level: log::Level, label: &str) {
+    if !log_enabled!(target: "caves::map", level) {
+        return;
+    }
+
+    let rendered = format!("{:#?}", map);
+    let rows: Vec<_> = rendered.lines().collect();
+    for (i, chunk) in rows.chunks(LOG_MAP_CHUNK_ROWS).enumerate() {
+        let first_row = i * LOG_MAP_CHUNK_ROWS;
+        log!(target: "caves::map", level, "{} (rows {}-{} of {}):\n{}",
+            label, first_row, first_row + chunk.len() - 1, rows.len(), chunk.join("\n"));
+    }
+}
+
 impl FloorMap {
     /// Create a new FloorMap with the given number of rows and columns
     pub fn new(size: GridSize, tile_size: u32) -> Self {
@@ -118,6 +150,41 @@ impl FloorMap {
             .count()
     }
 
+    /// Returns true if the given tile is on the floor of the room that the player starts in or of a
+    /// merchant room. Enemies never enter or see into those rooms so that the player is safe at the
+    /// very start of a level and while shopping.
+    pub fn is_safe_zone(&self, pos: TilePos) -> bool {
+        self.grid().get(pos).floor_room_id()
+            .map(|room_id| self.room(room_id).is_safe_zone())
+            .unwrap_or(false)
+    }
+
+    /// Returns the positions of every floor tile in the given room along with all of the wall
+    /// tiles that surround those floor tiles (including the corners of the room)
+    pub fn room_tiles_with_walls(&self, room_id: RoomId) -> HashSet<TilePos> {
+        let grid = self.grid();
+        let mut tiles = HashSet::new();
+        let floor_tiles = self.room(room_id).boundary().tile_positions()
+            .filter(|&pos| grid.get(pos).is_room_floor(room_id));
+        for pos in floor_tiles {
+            tiles.insert(pos);
+
+            // Need to check diagonals too or else the corners of the room will be left out
+            let rows = pos.row.saturating_sub(1)..=cmp::min(pos.row + 1, grid.rows_len() - 1);
+            for row in rows {
+                let cols = pos.col.saturating_sub(1)..=cmp::min(pos.col + 1, grid.cols_len() - 1);
+                for col in cols {
+                    let adj = TilePos {row, col};
+                    if grid.get(adj).is_wall() {
+                        tiles.insert(adj);
+                    }
+                }
+            }
+        }
+
+        tiles
+    }
+
     /// Returns the room with the specified room ID
     /// Not for use after map generation is complete.
     pub(in super) fn room_mut(&mut self, room_id: RoomId) -> &mut Room {
@@ -138,6 +205,18 @@ impl FloorMap {
         RoomId(self.rooms.len() - 1)
     }
 
+    /// Moves every tile and room boundary of the map using the given transform. Anything else
+    /// positioned on the map must be transformed separately.
+    /// Not for use after map generation is complete.
+    pub(in super) fn transform_layout(&mut self, transform: LayoutTransform) {
+        let size = self.grid.dimensions();
+        self.grid = transform.grid(&self.grid);
+        for room in &mut self.rooms {
+            let boundary = transform.tile_rect(*room.boundary(), size);
+            room.set_boundary(boundary);
+        }
+    }
+
     /// Returns a reference to this level's grid of tiles
     pub fn grid(&self) -> &TileGrid {
         &self.grid
@@ -0,0 +1,204 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use specs::{World, Join, ReadStorage};
+
+use crate::components::{Position, Stairs, Door, EnemySpawn};
+
+use super::{FloorMap, Tile, TilePos, RoomType};
+
+impl FloorMap {
+    /// Returns a plain-text rendering of this map with one character per tile and one line per
+    /// row. Unlike the alternate (`{:#?}`) debug output, there are no colors, so the text can be
+    /// read in CI logs and compared line by line.
+    ///
+    /// * `.` - floor
+    /// * `#` - wall
+    /// * ` ` - empty
+    /// * `@` - the center of the player start room
+    pub fn to_ascii(&self) -> String {
+        self.ascii_with_markers(&HashMap::new())
+    }
+
+    /// Same as `to_ascii`, but with the entities in the given world that are part of the layout of
+    /// the level drawn over the tiles they are on
+    ///
+    /// * `>` - staircase to the next level
+    /// * `<` - staircase to the previous level
+    /// * `D` - door
+    /// * `E` - enemy spawn point
+    pub fn to_ascii_with_entities(&self, world: &World) -> String {
+        let (positions, stairs, doors, spawns) = world.system_data::<(
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, Stairs>,
+            ReadStorage<'_, Door>,
+            ReadStorage<'_, EnemySpawn>,
+        )>();
+
+        let mut markers = HashMap::new();
+        for (&Position(pos), _) in (&positions, &spawns).join() {
+            markers.insert(self.world_to_tile_pos(pos), 'E');
+        }
+        for (&Position(pos), _) in (&positions, &doors).join() {
+            markers.insert(self.world_to_tile_pos(pos), 'D');
+        }
+        for (&Position(pos), stairs) in (&positions, &stairs).join() {
+            markers.insert(self.world_to_tile_pos(pos), match stairs {
+                Stairs::ToNextLevel {..} => '>',
+                Stairs::ToPrevLevel {..} => '<',
+            });
+        }
+
+        self.ascii_with_markers(&markers)
+    }
+
+    /// Returns the plain-text rendering of this map and the given map side by side, with a third
+    /// column that marks every tile that is different between them with a `^`. Rows that are the
+    /// same in both maps have no marks.
+    pub fn diff_ascii(&self, other: &FloorMap) -> String {
+        diff_ascii(&self.to_ascii(), &other.to_ascii())
+    }
+
+    /// Renders the map with the given characters in place of the tiles at their positions
+    fn ascii_with_markers(&self, markers: &HashMap<TilePos, char>) -> String {
+        let player_start = self.rooms().find(|(_, room)| room.room_type() == RoomType::PlayerStart)
+            .map(|(_, room)| room.boundary().center_tile());
+
+        let mut ascii = String::new();
+        for (row, tiles) in self.grid().rows().enumerate() {
+            for (col, tile) in tiles.iter().enumerate() {
+                let pos = TilePos {row, col};
+                ascii.push(match (markers.get(&pos), tile) {
+                    (Some(&marker), _) => marker,
+                    (None, _) if Some(pos) == player_start => '@',
+                    (None, Tile::Floor {..}) => '.',
+                    (None, Tile::Wall {..}) => '#',
+                    (None, Tile::Empty) => ' ',
+                });
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+}
+
+/// Returns the two plain-text renderings (e.g. from `FloorMap::to_ascii`) side by side, with a
+/// third column that marks every character that is different between them with a `^`. The
+/// renderings may have different sizes, in which case the missing characters count as different.
+pub fn diff_ascii(left: &str, right: &str) -> String {
+    let left: Vec<Vec<char>> = left.lines().map(|line| line.chars().collect()).collect();
+    let right: Vec<Vec<char>> = right.lines().map(|line| line.chars().collect()).collect();
+    let width = left.iter().chain(&right).map(|line| line.len()).max().unwrap_or(0);
+
+    let mut differences = 0;
+    let mut diff = String::new();
+    for row in 0..cmp::max(left.len(), right.len()) {
+        let line = |lines: &[Vec<char>]| lines.get(row).cloned().unwrap_or_default();
+        let (left_line, right_line) = (line(&left), line(&right));
+
+        let marks: String = (0..width).map(|col| {
+            if left_line.get(col) == right_line.get(col) {
+                ' '
+            } else {
+                differences += 1;
+                '^'
+            }
+        }).collect();
+
+        let pad = |line: Vec<char>| format!("{:width$}", line.into_iter().collect::<String>(), width = width);
+        let line = format!("{} | {} | {}", pad(left_line), pad(right_line), marks);
+        diff += line.trim_end();
+        diff.push('\n');
+    }
+
+    format!("{} tiles differ\n{}", differences, diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Builder;
+
+    use crate::generator::EnemyValues;
+    use crate::components::{BoundingBox, EnemyBehaviour};
+    use crate::map::{GridSize, TileRect};
+    use crate::map_sprites::WallSprite;
+    use crate::test_helpers::{test_animations, add_walled_room};
+
+    /// A 5x7 room in a 5x8 map, leaving the last column empty
+    fn room_map() -> FloorMap {
+        let mut map = FloorMap::new(GridSize {rows: 5, cols: 8}, 16);
+        add_walled_room(&mut map, TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows: 5, cols: 7}));
+        map
+    }
+
+    fn char_at(ascii: &str, TilePos {row, col}: TilePos) -> char {
+        ascii.lines().nth(row).unwrap().chars().nth(col).unwrap()
+    }
+
+    #[test]
+    fn one_char_per_tile() {
+        let mut map = room_map();
+        let ascii = map.to_ascii();
+        assert_eq!(ascii.lines().count(), 5);
+        assert!(ascii.lines().all(|line| line.chars().count() == 8));
+        assert_eq!(char_at(&ascii, TilePos {row: 0, col: 0}), '#');
+        assert_eq!(char_at(&ascii, TilePos {row: 2, col: 3}), '.');
+        assert_eq!(char_at(&ascii, TilePos {row: 2, col: 7}), ' ');
+        // No colors or other escape codes
+        assert!(!ascii.contains('\u{1b}'));
+
+        map.rooms_mut().next().unwrap().1.become_player_start();
+        assert_eq!(char_at(&map.to_ascii(), TilePos {row: 2, col: 3}), '@');
+    }
+
+    #[test]
+    fn entities_drawn_over_tiles() {
+        let map = room_map();
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Stairs>();
+        world.register::<Door>();
+        world.register::<EnemySpawn>();
+
+        let center = |pos: TilePos| Position(pos.center(map.tile_size() as i32));
+        world.create_entity().with(center(TilePos {row: 0, col: 2})).with(Stairs::ToNextLevel {id: 1}).build();
+        world.create_entity().with(center(TilePos {row: 4, col: 4})).with(Stairs::ToPrevLevel {id: 1}).build();
+        world.create_entity().with(center(TilePos {row: 2, col: 6})).with(Door::Closed).build();
+        world.create_entity().with(center(TilePos {row: 1, col: 1})).with(EnemySpawn {
+            probability: 1.0,
+            enemy: EnemyValues {
+                behaviour: EnemyBehaviour::Random,
+                animations: test_animations(),
+                attack: 1,
+                speed: 1.0,
+                health_points: 1,
+                hit_wait: 1,
+                bounding_box: BoundingBox::Full {width: 16, height: 16},
+            },
+        }).build();
+
+        let ascii = map.to_ascii_with_entities(&world);
+        assert_eq!(char_at(&ascii, TilePos {row: 0, col: 2}), '>');
+        assert_eq!(char_at(&ascii, TilePos {row: 4, col: 4}), '<');
+        assert_eq!(char_at(&ascii, TilePos {row: 2, col: 6}), 'D');
+        assert_eq!(char_at(&ascii, TilePos {row: 1, col: 1}), 'E');
+        assert_eq!(char_at(&ascii, TilePos {row: 1, col: 2}), '.');
+    }
+
+    #[test]
+    fn diff_marks_changed_tiles() {
+        let map = room_map();
+        assert!(map.diff_ascii(&map).starts_with("0 tiles differ\n"));
+
+        let mut other = room_map();
+        other.grid_mut().place_tile(TilePos {row: 2, col: 3}, Tile::new_wall(WallSprite::default()));
+        let diff = map.diff_ascii(&other);
+        let lines: Vec<_> = diff.lines().collect();
+        assert_eq!(lines[0], "1 tiles differ");
+        // The last column of the map is empty
+        assert_eq!(lines[1], "#######  | #######  |");
+        assert_eq!(lines[3], "#.....#  | #..#..#  |    ^");
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::{HashSet, VecDeque};
 use std::ops::{Index, IndexMut};
 use std::iter::once;
 
+use rayon::prelude::*;
+
 use super::{Tile, GridSize, TilePos};
 
 /// Represents a 2D grid of tiles
@@ -66,16 +68,13 @@ impl TileGrid {
     /// A room entrance is defined as a floor tile that has at least one other floor tile with a
     /// different ID as one of its adjacents.
     pub fn is_room_entrance(&self, pos: TilePos) -> bool {
-        match self.get(pos) {
-            Tile::Floor {room_id, ..} => {
-                for pos in self.adjacent_positions(pos) {
-                    match self.get(pos) {
-                        Tile::Floor {room_id: room_id2, ..} if room_id != room_id2 => return true,
-                        _ => {},
-                    }
+        if let Tile::Floor {room_id, ..} = self.get(pos) {
+            for pos in self.adjacent_positions(pos) {
+                match self.get(pos) {
+                    Tile::Floor {room_id: room_id2, ..} if room_id != room_id2 => return true,
+                    _ => {},
                 }
-            },
-            _ => {},
+            }
         }
 
         false
@@ -95,6 +94,30 @@ impl TileGrid {
         (0..self.rows_len()).flat_map(move |row| (0..cols).map(move |col| TilePos {row, col}))
     }
 
+    /// Returns an iterator over every tile in this map along with its position. Tiles are visited
+    /// row by row in the same order as `tile_positions`.
+    pub fn tiles(&self) -> impl Iterator<Item=(TilePos, &Tile)> {
+        self.0.iter().enumerate().flat_map(|(row, tiles)| {
+            tiles.iter().enumerate().map(move |(col, tile)| (TilePos {row, col}, tile))
+        })
+    }
+
+    /// Returns an iterator over every tile in this map along with its position, allowing each tile
+    /// to be modified. Tiles are visited in the same order as `tiles`.
+    pub fn tiles_mut(&mut self) -> impl Iterator<Item=(TilePos, &mut Tile)> {
+        self.0.iter_mut().enumerate().flat_map(|(row, tiles)| {
+            tiles.iter_mut().enumerate().map(move |(col, tile)| (TilePos {row, col}, tile))
+        })
+    }
+
+    /// Same as `tiles_mut` but the rows are modified in parallel. Only useful when the update to
+    /// each tile does not depend on any other tile or on the order that tiles are visited in.
+    pub fn par_tiles_mut(&mut self) -> impl ParallelIterator<Item=(TilePos, &mut Tile)> {
+        self.0.par_iter_mut().enumerate().flat_map(|(row, tiles)| {
+            tiles.par_iter_mut().enumerate().map(move |(col, tile)| (TilePos {row, col}, tile))
+        })
+    }
+
     /// Returns the tile positions within the region defined by top_left and size
     pub fn tile_positions_within(&self, top_left: TilePos, size: GridSize) -> impl Iterator<Item=TilePos> {
         let start_row = top_left.row;
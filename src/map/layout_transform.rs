@@ -0,0 +1,173 @@
+use sdl2::rect::Point;
+use rand::{Rng, distributions::{Distribution, Standard}};
+
+use super::{TilePos, TileRect, TileGrid, GridSize};
+
+/// A transformation of the entire layout of a map that maps every tile onto another tile of the
+/// same map. Every transform is its own inverse, so applying the same transform twice always
+/// results in the original layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutTransform {
+    /// Mirrors the map across its vertical center line (left becomes right)
+    MirrorHorizontal,
+    /// Mirrors the map across its horizontal center line (top becomes bottom)
+    MirrorVertical,
+    /// Rotates the map by 180 degrees around its center
+    Rotate180,
+}
+
+impl Distribution<LayoutTransform> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> LayoutTransform {
+        use self::LayoutTransform::*;
+        match rng.gen_range(0, 3) {
+            0 => MirrorHorizontal,
+            1 => MirrorVertical,
+            2 => Rotate180,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl LayoutTransform {
+    /// Returns true if this transform swaps the left and right sides of the map
+    pub fn flips_horizontally(self) -> bool {
+        use self::LayoutTransform::*;
+        match self {
+            MirrorHorizontal | Rotate180 => true,
+            MirrorVertical => false,
+        }
+    }
+
+    /// Returns true if this transform swaps the top and bottom of the map
+    pub fn flips_vertically(self) -> bool {
+        use self::LayoutTransform::*;
+        match self {
+            MirrorVertical | Rotate180 => true,
+            MirrorHorizontal => false,
+        }
+    }
+
+    /// Returns the position that the tile at the given position moves to on a grid of the given
+    /// size
+    pub fn tile_pos(self, TilePos {row, col}: TilePos, GridSize {rows, cols}: GridSize) -> TilePos {
+        debug_assert!(row < rows && col < cols, "bug: position was not on the grid");
+        TilePos {
+            row: if self.flips_vertically() { rows - 1 - row } else { row },
+            col: if self.flips_horizontally() { cols - 1 - col } else { col },
+        }
+    }
+
+    /// Returns the rectangle that covers all of the tiles in the given rectangle once they have
+    /// been moved on a grid of the given size
+    pub fn tile_rect(self, rect: TileRect, size: GridSize) -> TileRect {
+        // The corners of the rectangle swap places, so the opposite corner becomes the top left
+        let top_left = self.tile_pos(rect.top_left(), size);
+        let bottom_right = self.tile_pos(rect.bottom_right(), size);
+        let top_left = TilePos {
+            row: top_left.row.min(bottom_right.row),
+            col: top_left.col.min(bottom_right.col),
+        };
+        TileRect::new(top_left, rect.dimensions())
+    }
+
+    /// Returns the point (in world coordinates) that the given point moves to on a grid of the
+    /// given size. A point at the center of a tile moves to the center of the transformed tile.
+    pub fn point(self, point: Point, size: GridSize, tile_size: u32) -> Point {
+        let level = size.to_rect(tile_size);
+        let x = if self.flips_horizontally() { level.width() as i32 - point.x() } else { point.x() };
+        let y = if self.flips_vertically() { level.height() as i32 - point.y() } else { point.y() };
+        Point::new(x, y)
+    }
+
+    /// Returns a new grid with every tile of the given grid moved to its transformed position
+    pub fn grid(self, grid: &TileGrid) -> TileGrid {
+        let size = grid.dimensions();
+        let mut transformed = grid.clone();
+        for pos in grid.tile_positions() {
+            *transformed.get_mut(self.tile_pos(pos, size)) = grid.get(pos).clone();
+        }
+        transformed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use crate::map::Tile;
+    use crate::map_sprites::WallSprite;
+
+    const ALL: [LayoutTransform; 3] = [
+        LayoutTransform::MirrorHorizontal,
+        LayoutTransform::MirrorVertical,
+        LayoutTransform::Rotate180,
+    ];
+
+    #[test]
+    fn transforming_twice_is_identity() {
+        let mut rng = StdRng::seed_from_u64(1782);
+        let size = GridSize {rows: 13, cols: 20};
+        let tile_size = 16;
+
+        for &transform in &ALL {
+            for _ in 0..100 {
+                let pos = TilePos {row: rng.gen_range(0, size.rows), col: rng.gen_range(0, size.cols)};
+                let moved = transform.tile_pos(pos, size);
+                assert!(moved.row < size.rows && moved.col < size.cols);
+                assert_eq!(transform.tile_pos(moved, size), pos);
+
+                let dim = GridSize {
+                    rows: rng.gen_range(1, size.rows - pos.row + 1),
+                    cols: rng.gen_range(1, size.cols - pos.col + 1),
+                };
+                let rect = TileRect::new(pos, dim);
+                let moved = transform.tile_rect(rect, size);
+                assert_eq!(moved.dimensions(), dim);
+                assert_eq!(transform.tile_rect(moved, size), rect);
+                // Every tile of the rectangle ends up in the transformed rectangle
+                let mut tiles: Vec<_> = rect.tile_positions().map(|pos| transform.tile_pos(pos, size)).collect();
+                let mut moved_tiles: Vec<_> = moved.tile_positions().collect();
+                tiles.sort();
+                moved_tiles.sort();
+                assert_eq!(tiles, moved_tiles);
+
+                let point = Point::new(rng.gen_range(0, 20 * 16), rng.gen_range(0, 13 * 16));
+                assert_eq!(transform.point(transform.point(point, size, tile_size), size, tile_size), point);
+            }
+        }
+    }
+
+    #[test]
+    fn tile_centers_stay_centered() {
+        let size = GridSize {rows: 5, cols: 7};
+        let tile_size = 16;
+        for &transform in &ALL {
+            for pos in TileRect::new(TilePos {row: 0, col: 0}, size).tile_positions() {
+                let center = transform.point(pos.center(tile_size as i32), size, tile_size);
+                assert_eq!(center, transform.tile_pos(pos, size).center(tile_size as i32));
+            }
+        }
+    }
+
+    #[test]
+    fn grid_transform() {
+        let size = GridSize {rows: 4, cols: 5};
+        let mut grid = TileGrid::new(size);
+        let wall = TilePos {row: 0, col: 1};
+        grid.place_tile(wall, Tile::new_wall(WallSprite::default()));
+
+        let expected = [
+            (LayoutTransform::MirrorHorizontal, TilePos {row: 0, col: 3}),
+            (LayoutTransform::MirrorVertical, TilePos {row: 3, col: 1}),
+            (LayoutTransform::Rotate180, TilePos {row: 3, col: 3}),
+        ];
+        for &(transform, moved) in &expected {
+            let transformed = transform.grid(&grid);
+            assert!(transformed.get(moved).is_wall());
+            assert_eq!(transformed.tile_positions().filter(|&pos| transformed.get(pos).is_wall()).count(), 1);
+            assert_eq!(transform.grid(&transformed), grid);
+        }
+    }
+}
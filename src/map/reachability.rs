@@ -0,0 +1,128 @@
+use specs::{World, Join, ReadStorage};
+
+use crate::components::{Position, Stairs};
+
+use super::{FloorMap, TilePos, RoomType};
+
+impl FloorMap {
+    /// Checks that every staircase in the given world can be reached on foot from wherever the
+    /// player can enter this level: the center of the player start room, or beside each staircase
+    /// to the previous level on levels without a player start room.
+    ///
+    /// Staircases are placed in walls, so a staircase counts as reached once a floor tile beside
+    /// it is reached. Returns the tiles of every staircase that cannot be reached from at least
+    /// one of the places the player can enter the level, sorted by position.
+    pub fn validate_reachability(&self, world: &World) -> Result<(), Vec<TilePos>> {
+        let (positions, stairs) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
+        let grid = self.grid();
+
+        let staircases: Vec<_> = (&positions, &stairs).join()
+            .map(|(&Position(pos), stairs)| {
+                let to_prev_level = match stairs {
+                    Stairs::ToNextLevel {..} => false,
+                    Stairs::ToPrevLevel {..} => true,
+                };
+                (self.world_to_tile_pos(pos), to_prev_level)
+            })
+            .collect();
+
+        let mut unreachable = Vec::new();
+        let player_start = self.rooms().find(|(_, room)| room.room_type() == RoomType::PlayerStart)
+            .map(|(_, room)| room.boundary().center_tile());
+        let entrances = match player_start {
+            Some(start) => vec![start],
+            None => staircases.iter()
+                .filter(|&&(_, to_prev_level)| to_prev_level)
+                .filter_map(|&(stairs, _)| {
+                    let entrance = grid.adjacent_positions(stairs).find(|&adj| grid.get(adj).is_floor());
+                    // A staircase with no floor beside it cannot even be used to enter the level
+                    if entrance.is_none() {
+                        unreachable.push(stairs);
+                    }
+                    entrance
+                })
+                .collect(),
+        };
+
+        for entrance in entrances {
+            let distances = self.path_lengths(entrance);
+            let reached = |pos: TilePos| distances.contains_key(&pos)
+                || grid.adjacent_positions(pos).any(|adj| distances.contains_key(&adj));
+
+            for &(stairs, _) in &staircases {
+                if !reached(stairs) && !unreachable.contains(&stairs) {
+                    unreachable.push(stairs);
+                }
+            }
+        }
+
+        if unreachable.is_empty() {
+            Ok(())
+        } else {
+            unreachable.sort();
+            Err(unreachable)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Builder;
+
+    use crate::map::Tile;
+    use crate::map_sprites::WallSprite;
+    use crate::test_helpers::walled_room;
+
+    fn add_stairs(world: &mut World, map: &FloorMap, pos: TilePos, stairs: Stairs) {
+        world.create_entity()
+            .with(Position(pos.center(map.tile_size() as i32)))
+            .with(stairs)
+            .build();
+    }
+
+    fn stairs_world() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Stairs>();
+        world
+    }
+
+    #[test]
+    fn stairs_blocked_by_later_wall() {
+        let mut map = walled_room(7, 9, 16);
+        map.rooms_mut().next().unwrap().1.become_player_start();
+        let mut world = stairs_world();
+        // Staircases in the north wall, each with a single open tile south of them
+        let first = TilePos {row: 0, col: 2};
+        let second = TilePos {row: 0, col: 6};
+        add_stairs(&mut world, &map, first, Stairs::ToNextLevel {id: 1});
+        add_stairs(&mut world, &map, second, Stairs::ToNextLevel {id: 2});
+        assert_eq!(map.validate_reachability(&world), Ok(()));
+
+        // A pillar placed by a later phase covers the only open side of the second staircase
+        map.grid_mut().place_tile(TilePos {row: 1, col: 6}, Tile::new_wall(WallSprite::default()));
+        assert_eq!(map.validate_reachability(&world), Err(vec![second]));
+    }
+
+    #[test]
+    fn stairs_checked_from_every_entrance() {
+        // Two rooms split by a wall at column 6. Neither is a player start room.
+        let mut map = walled_room(7, 13, 16);
+        for row in 1..6 {
+            map.grid_mut().place_tile(TilePos {row, col: 6}, Tile::new_wall(WallSprite::default()));
+        }
+        let mut world = stairs_world();
+        let west_prev = TilePos {row: 0, col: 2};
+        let east_prev = TilePos {row: 0, col: 10};
+        let next = TilePos {row: 6, col: 3};
+        add_stairs(&mut world, &map, west_prev, Stairs::ToPrevLevel {id: 1});
+        add_stairs(&mut world, &map, next, Stairs::ToNextLevel {id: 1});
+        assert_eq!(map.validate_reachability(&world), Ok(()));
+
+        // Entering from the east room, the staircases in the west room cannot be reached
+        add_stairs(&mut world, &map, east_prev, Stairs::ToPrevLevel {id: 2});
+        assert_eq!(map.validate_reachability(&world), Err(vec![west_prev, east_prev, next]));
+    }
+}
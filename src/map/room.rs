@@ -15,6 +15,9 @@ pub enum RoomType {
     /// the very last level. No ToNextLevel or ToPrevLevel tiles should be in this room.
     /// Enemies should also not be placed in this room.
     TreasureChamber,
+    /// A rare room where a merchant sells items for gold. Like the player start room, the player
+    /// is safe here: no enemies, staircases, or hazards are placed in this room.
+    Merchant,
 }
 
 /// Represents a "room" on the map separated from other rooms by walls/entrances. Rooms are allowed
@@ -42,12 +45,14 @@ impl Room {
         &self.boundary
     }
 
+    /// Moves the room to the given boundary
+    pub(in super) fn set_boundary(&mut self, boundary: TileRect) {
+        self.boundary = boundary;
+    }
+
     /// Returns true if a room is allowed to contain ToNextLevel tiles
     pub fn can_contain_to_next_level(&self) -> bool {
-        match self.rtype {
-            RoomType::Normal => true,
-            _ => false,
-        }
+        matches!(self.rtype, RoomType::Normal)
     }
 
     /// Returns true if a room is allowed to contain ToPrevLevel tiles
@@ -56,19 +61,54 @@ impl Room {
         self.can_contain_to_next_level()
     }
 
+    /// Returns true if a room is allowed to contain map fragments
+    pub fn can_contain_map_fragment(&self) -> bool {
+        matches!(self.rtype, RoomType::Normal)
+    }
+
+    /// Returns true if a room is allowed to contain a caged prisoner
+    pub fn can_contain_prisoner(&self) -> bool {
+        matches!(self.rtype, RoomType::Normal)
+    }
+
+    /// Returns true if a room is allowed to contain a chest with a weapon in it
+    pub fn can_contain_weapon_chest(&self) -> bool {
+        // currently the same as the rooms that can contain prisoners
+        self.can_contain_prisoner()
+    }
+
     /// Returns true if a room is allowed to contain generated enemies
     pub fn can_generate_enemies(&self) -> bool {
-        match self.rtype {
-            RoomType::Normal => true,
-            _ => false,
-        }
+        matches!(self.rtype, RoomType::Normal | RoomType::Challenge)
+    }
+
+    /// Returns true if a room is allowed to contain pillars and props. The player start room is
+    /// kept clear so that nothing gets in the way at the very start of the game.
+    pub fn can_contain_decorations(&self) -> bool {
+        !matches!(self.rtype, RoomType::PlayerStart)
+    }
+
+    /// Returns true if a room is allowed to contain hazards (e.g. water or pits). Only rooms that
+    /// the player has to fight through get hazards.
+    pub fn can_contain_hazards(&self) -> bool {
+        matches!(self.rtype, RoomType::Normal | RoomType::Challenge)
+    }
+
+    /// Returns true if this room is the treasure chamber
+    pub fn is_treasure_chamber(&self) -> bool {
+        matches!(self.rtype, RoomType::TreasureChamber)
     }
 
     /// Returns true if this room is the room that the player starts in
     pub fn is_player_start(&self) -> bool {
+        matches!(self.rtype, RoomType::PlayerStart)
+    }
+
+    /// Returns true if enemies should never enter or see into this room
+    pub fn is_safe_zone(&self) -> bool {
         match self.rtype {
-            RoomType::PlayerStart => true,
-            _ => false,
+            RoomType::PlayerStart | RoomType::Merchant => true,
+            RoomType::Normal | RoomType::Challenge | RoomType::TreasureChamber => false,
         }
     }
 
@@ -81,4 +121,14 @@ impl Room {
     pub fn become_treasure_chamber(&mut self) {
         self.rtype = RoomType::TreasureChamber;
     }
+
+    /// Turns this room into a challenge room
+    pub fn become_challenge(&mut self) {
+        self.rtype = RoomType::Challenge;
+    }
+
+    /// Turns this room into a merchant room
+    pub fn become_merchant(&mut self) {
+        self.rtype = RoomType::Merchant;
+    }
 }
@@ -0,0 +1,172 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{FloorMap, TilePos};
+
+/// The smallest, median, and largest of a set of values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Spread {
+    pub min: usize,
+    /// The lower of the two middle values if there are an even number of values
+    pub median: usize,
+    pub max: usize,
+}
+
+impl Spread {
+    /// Computes the spread of the given values. All zeros if there are no values.
+    pub fn of(mut values: Vec<usize>) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        values.sort_unstable();
+        Self {
+            min: values[0],
+            median: values[(values.len() - 1) / 2],
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+/// Statistics about a generated map. Useful for tuning the parameters of the generator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapStats {
+    /// The number of rooms on the map
+    pub rooms: usize,
+    /// The number of floor tiles in each room
+    pub room_area: Spread,
+    /// The number of openings in the walls of rooms (with or without a door)
+    pub doorways: usize,
+    /// The length (in tiles) of the shortest path from the start to each of the given ToNextLevel
+    /// staircases. None if a staircase cannot be reached.
+    pub next_level_paths: Vec<Option<usize>>,
+    /// The number of enemies on the map
+    pub enemies: usize,
+    /// The fraction [0.0, 1.0] of the floor tiles of the map that can be reached from the start
+    pub reachable_floor: f64,
+}
+
+impl FloorMap {
+    /// Computes statistics about this map. Paths are measured from the given start tile to each of
+    /// the given ToNextLevel staircases, only moving through floor tiles.
+    pub fn compute_stats(&self, start: TilePos, to_next_level: &[TilePos], enemies: usize) -> MapStats {
+        let grid = self.grid();
+
+        let room_area = Spread::of(self.rooms().map(|(room_id, _)| self.room_exact_area(room_id)).collect());
+
+        // A doorway is a floor tile in place of one of the walls of a room
+        let doorways = self.rooms()
+            .flat_map(|(room_id, room)| room.boundary().edge_positions()
+                .filter(move |&pos| grid.get(pos).is_room_floor(room_id)))
+            .count();
+
+        let distances = self.path_lengths(start);
+        // Staircases are usually in a wall, so the path ends on the tile beside them
+        let next_level_paths = to_next_level.iter().map(|&stairs| {
+            let to_stairs = distances.get(&stairs).cloned();
+            let to_adjacent = grid.adjacent_positions(stairs)
+                .filter_map(|adj| distances.get(&adj))
+                .map(|distance| distance + 1)
+                .min();
+            to_stairs.into_iter().chain(to_adjacent).min()
+        }).collect();
+
+        let floor_tiles = grid.tile_positions().filter(|&pos| grid.get(pos).is_floor()).count();
+        let reachable_floor = if floor_tiles == 0 {
+            0.0
+        } else {
+            distances.len() as f64 / floor_tiles as f64
+        };
+
+        MapStats {
+            rooms: self.nrooms(),
+            room_area,
+            doorways,
+            next_level_paths,
+            enemies,
+            reachable_floor,
+        }
+    }
+
+    /// Returns the length of the shortest path from the given tile to every floor tile that can be
+    /// reached from it. Uses a breadth-first search over floor tiles.
+    pub fn path_lengths(&self, start: TilePos) -> HashMap<TilePos, usize> {
+        let grid = self.grid();
+        let mut distances = HashMap::new();
+        if !grid.get(start).is_floor() {
+            return distances;
+        }
+
+        let mut open = VecDeque::new();
+        distances.insert(start, 0);
+        open.push_back(start);
+        while let Some(pos) = open.pop_front() {
+            let distance = distances[&pos];
+            for adj in grid.adjacent_positions(pos) {
+                if grid.get(adj).is_floor() && !distances.contains_key(&adj) {
+                    distances.insert(adj, distance + 1);
+                    open.push_back(adj);
+                }
+            }
+        }
+
+        distances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::{GridSize, TileRect, Tile};
+    use crate::map_sprites::FloorSprite;
+    use crate::test_helpers::add_walled_room;
+
+    #[test]
+    fn shortest_paths_to_stairs() {
+        // Two rooms joined by a doorway at (2, 4), with an opening at (4, 3) that leads to a dead
+        // end and a floor tile at (5, 7) that cannot be reached at all
+        //
+        // #########
+        // #...#...#
+        // #.......#
+        // #...#...#
+        // ###.#####
+        //    .   .
+        let mut map = FloorMap::new(GridSize {rows: 6, cols: 9}, 16);
+        let room1 = add_walled_room(&mut map, TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows: 5, cols: 5}));
+        add_walled_room(&mut map, TileRect::new(TilePos {row: 0, col: 4}, GridSize {rows: 5, cols: 5}));
+        map.grid_mut().get_mut(TilePos {row: 2, col: 4}).become_floor(room1, FloorSprite::default());
+        map.grid_mut().get_mut(TilePos {row: 4, col: 3}).become_floor(room1, FloorSprite::default());
+        map.grid_mut().place_tile(TilePos {row: 5, col: 3}, Tile::new_floor(room1, FloorSprite::default()));
+        map.grid_mut().place_tile(TilePos {row: 5, col: 7}, Tile::new_floor(room1, FloorSprite::default()));
+
+        let start = TilePos {row: 1, col: 1};
+        let stairs = [
+            // In the wall of the second room, beside (1, 7)
+            TilePos {row: 0, col: 7},
+            // On the floor
+            TilePos {row: 3, col: 2},
+            // Surrounded by walls
+            TilePos {row: 5, col: 0},
+        ];
+        let stats = map.compute_stats(start, &stairs, 3);
+
+        assert_eq!(stats.rooms, 2);
+        // The doorway and the opening are both on the boundary of the first room
+        assert_eq!(stats.room_area, Spread {min: 9, median: 9, max: 11});
+        assert_eq!(stats.doorways, 2);
+        // (1, 1) -> (2, 1) -> ... -> (2, 7) -> (1, 7) -> (0, 7)
+        assert_eq!(stats.next_level_paths, vec![Some(9), Some(3), None]);
+        assert_eq!(stats.enemies, 3);
+        // Every floor tile except the one at (5, 7)
+        let floor_tiles = 9 + 9 + 1 + 2 + 1;
+        assert_eq!(stats.reachable_floor, (floor_tiles - 1) as f64 / floor_tiles as f64);
+    }
+
+    #[test]
+    fn spread_of_values() {
+        assert_eq!(Spread::of(vec![]), Spread {min: 0, median: 0, max: 0});
+        assert_eq!(Spread::of(vec![7, 1, 4]), Spread {min: 1, median: 4, max: 7});
+        assert_eq!(Spread::of(vec![8, 2, 6, 4]), Spread {min: 2, median: 4, max: 8});
+    }
+}
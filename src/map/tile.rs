@@ -1,6 +1,13 @@
 use super::{RoomId};
 use crate::assets::SpriteId;
-use crate::map_sprites::{MapSprites, FloorSprite, WallSprite};
+use crate::map_sprites::{MapSprites, FloorSprite, FloorPalette, WallSprite};
+
+/// Something on a floor tile that hurts the player while they stand on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hazard {
+    Water,
+    Pit,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Tile {
@@ -9,6 +16,11 @@ pub enum Tile {
         room_id: RoomId,
         /// The floor sprite to use
         sprite: FloorSprite,
+        /// The palette that the floor sprite is drawn from
+        palette: FloorPalette,
+        /// The hazard on this tile (if any). Hazards can still be walked over, but they damage the
+        /// player and enemies avoid them.
+        hazard: Option<Hazard>,
     },
     /// A tile that cannot be traversed
     /// Not associated to a particular room, since rooms can share walls
@@ -23,7 +35,7 @@ pub enum Tile {
 impl Tile {
     /// Creates a new floor tile with the given sprite
     pub fn new_floor(room_id: RoomId, sprite: FloorSprite) -> Self {
-        Tile::Floor {room_id, sprite}
+        Tile::Floor {room_id, sprite, palette: FloorPalette::default(), hazard: None}
     }
 
     /// Creates a new wall tile with the given sprite
@@ -37,10 +49,10 @@ impl Tile {
     }
 
     /// Returns the sprite that should be used as the background of this tile
-    pub fn background_sprite<'a>(&self, map_sprites: &'a MapSprites) -> SpriteId {
+    pub fn background_sprite(&self, map_sprites: &MapSprites) -> SpriteId {
         use self::Tile::*;
         match *self {
-            Floor {sprite, ..} => map_sprites.floor_sprite(sprite),
+            Floor {sprite, palette, ..} => map_sprites.floor_sprite(palette, sprite),
             Wall {sprite, ..} => map_sprites.wall_sprite(sprite),
             Empty => map_sprites.empty_tile_sprite(),
         }
@@ -78,6 +90,43 @@ impl Tile {
         }
     }
 
+    /// Returns the palette of this tile if and only if the tile is a floor tile
+    pub fn floor_palette(&self) -> FloorPalette {
+        match self {
+            &Tile::Floor {palette, ..} => palette,
+            _ => unreachable!("bug: cannot get a floor palette for a non-floor tile"),
+        }
+    }
+
+    /// Sets the palette to the given floor palette only if the tile is a floor tile
+    pub fn set_floor_palette(&mut self, floor_palette: FloorPalette) {
+        match self {
+            Tile::Floor {palette, ..} => *palette = floor_palette,
+            _ => unreachable!("bug: cannot set a floor palette for a non-floor tile"),
+        }
+    }
+
+    /// Returns the hazard on this tile or None if it is not a floor tile with a hazard
+    pub fn hazard(&self) -> Option<Hazard> {
+        match self {
+            &Tile::Floor {hazard, ..} => hazard,
+            _ => None,
+        }
+    }
+
+    /// Returns true if this tile is a floor tile with a hazard on it
+    pub fn is_hazard(&self) -> bool {
+        self.hazard().is_some()
+    }
+
+    /// Places the given hazard on this tile only if the tile is a floor tile
+    pub fn set_hazard(&mut self, floor_hazard: Hazard) {
+        match self {
+            Tile::Floor {hazard, ..} => *hazard = Some(floor_hazard),
+            _ => unreachable!("bug: cannot place a hazard on a non-floor tile"),
+        }
+    }
+
     /// Returns the room ID of the tile if it is a floor tile or None if it is not
     pub fn floor_room_id(&self) -> Option<RoomId> {
         match self {
@@ -88,34 +137,22 @@ impl Tile {
 
     /// Returns true if this tile is any floor tile
     pub fn is_floor(&self) -> bool {
-        match self {
-            Tile::Floor {..} => true,
-            _ => false,
-        }
+        matches!(self, Tile::Floor {..})
     }
 
     /// Returns true if this tile is a floor tile from the given room
     pub fn is_room_floor(&self, id: RoomId) -> bool {
-        match self {
-            Tile::Floor {room_id, ..} if *room_id == id => true,
-            _ => false,
-        }
+        matches!(self, Tile::Floor {room_id, ..} if *room_id == id)
     }
 
     /// Returns true if this tile is a wall
     pub fn is_wall(&self) -> bool {
-        match self {
-            Tile::Wall {..} => true,
-            _ => false
-        }
+        matches!(self, Tile::Wall {..})
     }
 
     /// Returns true if this tile is empty
     pub fn is_empty(&self) -> bool {
-        match self {
-            Tile::Empty => true,
-            _ => false
-        }
+        matches!(self, Tile::Empty)
     }
 
     /// Turns this tile into a Wall tile
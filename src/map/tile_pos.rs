@@ -5,7 +5,7 @@ use sdl2::rect::{Point, Rect};
 use super::GridSize;
 
 /// Represents the location of a single tile in a 2D grid of tiles
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TilePos {
     pub row: usize,
     pub col: usize,
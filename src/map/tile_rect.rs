@@ -66,6 +66,20 @@ impl TileRect {
         pos == self.bottom_right()
     }
 
+    /// Returns true if the given position is within this rectangle (including its edges)
+    pub fn contains(&self, pos: TilePos) -> bool {
+        let tl = self.top_left();
+        let br = self.bottom_right();
+        tl.row <= pos.row && pos.row <= br.row && tl.col <= pos.col && pos.col <= br.col
+    }
+
+    /// Returns true if the given position is on one of the edges of this rectangle
+    pub fn is_edge(&self, pos: TilePos) -> bool {
+        let tl = self.top_left();
+        let br = self.bottom_right();
+        self.contains(pos) && (pos.row == tl.row || pos.row == br.row || pos.col == tl.col || pos.col == br.col)
+    }
+
     /// Returns the tile position that is considered the "center" of this rectangle.
     ///
     /// If the exact center is not a valid tile position (i.e. it is between 4 tiles), then this
@@ -153,7 +167,11 @@ impl TileRect {
     }
 
     /// Returns a random non-edge tile position inside the rect
+    ///
+    /// The rect must have at least 3 rows and 3 columns or else it has no inner tiles.
     pub fn random_inner_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.dim.rows >= 3 && self.dim.cols >= 3,
+            "bug: rect with dimensions {:?} has no inner tiles", self.dim);
         TilePos {
             row: self.top_left.row + rng.gen_range(1, self.dim.rows - 1),
             col: self.top_left.col + rng.gen_range(1, self.dim.cols - 1),
@@ -161,15 +179,35 @@ impl TileRect {
     }
 
     /// Returns a random tile position on one of the horizontal (top or bottom) edges
+    ///
+    /// The rect must have at least 2 rows so that the top and bottom edges are different.
     pub fn random_horizontal_edge_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.dim.rows >= 2 && self.dim.cols >= 1,
+            "bug: rect with dimensions {:?} does not have separate top and bottom edges", self.dim);
         TilePos {
             row: self.top_left.row + *[0, self.dim.rows - 1].choose(rng).unwrap(),
             col: self.top_left.col + rng.gen_range(0, self.dim.cols),
         }
     }
 
+    /// Returns a random tile position on the top horizontal edge, excluding the corners
+    ///
+    /// The rect must have at least 3 columns so that the top edge has a tile between its corners.
+    pub fn random_top_horizontal_edge_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.dim.rows >= 1 && self.dim.cols >= 3,
+            "bug: rect with dimensions {:?} has no top edge tiles between its corners", self.dim);
+        TilePos {
+            row: self.top_left.row,
+            col: self.top_left.col + rng.gen_range(1, self.dim.cols - 1),
+        }
+    }
+
     /// Returns a random tile position on one of the vertical (left or right) edges
+    ///
+    /// The rect must have at least 2 columns so that the left and right edges are different.
     pub fn random_vertical_edge_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.dim.rows >= 1 && self.dim.cols >= 2,
+            "bug: rect with dimensions {:?} does not have separate left and right edges", self.dim);
         if rng.gen() {
             self.random_left_vertical_edge_tile(rng)
         } else {
@@ -179,6 +217,7 @@ impl TileRect {
 
     /// Returns a random tile position on the left vertical edge
     pub fn random_left_vertical_edge_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.area() > 0, "bug: rect with dimensions {:?} has no tiles", self.dim);
         TilePos {
             row: self.top_left.row + rng.gen_range(0, self.dim.rows),
             col: self.top_left.col,
@@ -187,6 +226,7 @@ impl TileRect {
 
     /// Returns a random tile position on the right vertical edge
     pub fn random_right_vertical_edge_tile<R: Rng>(self, rng: &mut R) -> TilePos {
+        debug_assert!(self.area() > 0, "bug: rect with dimensions {:?} has no tiles", self.dim);
         TilePos {
             row: self.top_left.row + rng.gen_range(0, self.dim.rows),
             col: self.top_left.col + self.dim.cols - 1,
@@ -198,6 +238,8 @@ impl TileRect {
 mod tests {
     use super::*;
 
+    use std::collections::HashSet;
+
     #[test]
     fn center() {
         let rect = TileRect::new(TilePos {row: 1, col: 2}, GridSize {rows: 11, cols: 9});
@@ -221,4 +263,62 @@ mod tests {
         let rect = TileRect::new(TilePos {row: 2, col: 3}, GridSize {rows: 10, cols: 12});
         assert_eq!(rect.expand(2), TileRect::new(TilePos {row: 0, col: 1}, GridSize {rows: 14, cols: 16}));
     }
+
+    #[test]
+    fn random_tiles_at_minimum_size() {
+        use rand::{SeedableRng, rngs::StdRng};
+        let mut rng = StdRng::seed_from_u64(1783);
+
+        // The smallest rect with an inner tile only has one inner tile
+        let rect = TileRect::new(TilePos {row: 4, col: 7}, GridSize {rows: 3, cols: 3});
+        for _ in 0..20 {
+            assert_eq!(rect.random_inner_tile(&mut rng), TilePos {row: 5, col: 8});
+        }
+
+        let rect = TileRect::new(TilePos {row: 4, col: 7}, GridSize {rows: 2, cols: 2});
+        for _ in 0..20 {
+            let pos = rect.random_horizontal_edge_tile(&mut rng);
+            assert!(pos.row == 4 || pos.row == 5);
+            assert!(pos.col == 7 || pos.col == 8);
+            let pos = rect.random_vertical_edge_tile(&mut rng);
+            assert!(pos.row == 4 || pos.row == 5);
+            assert!(pos.col == 7 || pos.col == 8);
+        }
+
+        // The corners are not part of the top edge
+        let rect = TileRect::new(TilePos {row: 4, col: 7}, GridSize {rows: 1, cols: 3});
+        for _ in 0..20 {
+            assert_eq!(rect.random_top_horizontal_edge_tile(&mut rng), TilePos {row: 4, col: 8});
+        }
+
+        let rect = TileRect::new(TilePos {row: 4, col: 7}, GridSize {rows: 1, cols: 1});
+        for _ in 0..20 {
+            assert_eq!(rect.random_left_vertical_edge_tile(&mut rng), TilePos {row: 4, col: 7});
+            assert_eq!(rect.random_right_vertical_edge_tile(&mut rng), TilePos {row: 4, col: 7});
+        }
+    }
+
+    #[test]
+    fn top_edge_tile_excludes_corners() {
+        use rand::{SeedableRng, rngs::StdRng};
+        let mut rng = StdRng::seed_from_u64(1774);
+
+        let rect = TileRect::new(TilePos {row: 2, col: 3}, GridSize {rows: 6, cols: 5});
+        let cols: HashSet<_> = (0..100).map(|_| {
+            let pos = rect.random_top_horizontal_edge_tile(&mut rng);
+            assert_eq!(pos.row, 2);
+            pos.col
+        }).collect();
+        assert_eq!(cols, [4, 5, 6].iter().cloned().collect());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "has no inner tiles")]
+    fn no_inner_tiles() {
+        use rand::{SeedableRng, rngs::StdRng};
+        let mut rng = StdRng::seed_from_u64(1783);
+        let rect = TileRect::new(TilePos {row: 4, col: 7}, GridSize {rows: 2, cols: 5});
+        rect.random_inner_tile(&mut rng);
+    }
 }
@@ -0,0 +1,108 @@
+use std::fmt;
+
+use super::{Room, RoomType, TilePos};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hashes the given values using 64-bit FNV-1a. Unlike the hashers in std, the result is
+/// guaranteed to be the same on every platform and with every version of Rust.
+fn stable_hash(values: &[u64]) -> u64 {
+    values.iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn room_type_tag(rtype: RoomType) -> u64 {
+    // Spelled out so that reordering the variants does not change any uids
+    match rtype {
+        RoomType::Normal => 0,
+        RoomType::Challenge => 1,
+        RoomType::PlayerStart => 2,
+        RoomType::TreasureChamber => 3,
+        RoomType::Merchant => 4,
+    }
+}
+
+/// An identifier for a room that stays the same every time a level is generated from the same
+/// map key. Unlike RoomId, it does not depend on the order that rooms were created in, so it is
+/// safe to store outside of the game (e.g. in a save file).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RoomUid(u64);
+
+impl RoomUid {
+    /// Computes the uid of the given room on the given level (starting at 1)
+    pub fn new(level: usize, room: &Room) -> Self {
+        let boundary = room.boundary();
+        let top_left = boundary.top_left();
+        let size = boundary.dimensions();
+        RoomUid(stable_hash(&[
+            level as u64,
+            top_left.row as u64,
+            top_left.col as u64,
+            size.rows as u64,
+            size.cols as u64,
+            room_type_tag(room.room_type()),
+        ]))
+    }
+}
+
+impl fmt::Display for RoomUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "room-{:016x}", self.0)
+    }
+}
+
+/// An identifier for a staircase that stays the same every time a level is generated from the
+/// same map key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StairsUid(u64);
+
+impl StairsUid {
+    /// Computes the uid of the staircase at the given tile of the given level (starting at 1)
+    pub fn new(level: usize, pos: TilePos, to_next_level: bool) -> Self {
+        StairsUid(stable_hash(&[
+            level as u64,
+            pos.row as u64,
+            pos.col as u64,
+            to_next_level as u64,
+        ]))
+    }
+}
+
+impl fmt::Display for StairsUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stairs-{:016x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::{GridSize, TileRect};
+
+    #[test]
+    fn uids_depend_on_every_field() {
+        let room = Room::new(TileRect::new(TilePos {row: 3, col: 4}, GridSize {rows: 8, cols: 9}));
+        let uid = RoomUid::new(2, &room);
+        // The same room always has the same uid
+        assert_eq!(uid, RoomUid::new(2, &room.clone()));
+        assert_eq!(uid.to_string(), format!("room-{:016x}", uid.0));
+
+        let moved = Room::new(TileRect::new(TilePos {row: 4, col: 3}, GridSize {rows: 8, cols: 9}));
+        let resized = Room::new(TileRect::new(TilePos {row: 3, col: 4}, GridSize {rows: 9, cols: 8}));
+        let mut treasure = room.clone();
+        treasure.become_treasure_chamber();
+        for other in &[RoomUid::new(1, &room), RoomUid::new(2, &moved), RoomUid::new(2, &resized), RoomUid::new(2, &treasure)] {
+            assert_ne!(uid, *other);
+        }
+
+        let pos = TilePos {row: 5, col: 6};
+        let stairs = StairsUid::new(2, pos, true);
+        assert_eq!(stairs, StairsUid::new(2, pos, true));
+        assert_ne!(stairs, StairsUid::new(2, pos, false));
+        assert_ne!(stairs, StairsUid::new(3, pos, true));
+        assert_ne!(stairs, StairsUid::new(2, TilePos {row: 6, col: 5}, true));
+    }
+}
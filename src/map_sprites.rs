@@ -6,8 +6,16 @@ pub use self::wall_sprite::*;
 
 use sdl2::rect::Rect;
 
-use crate::components::Animation;
-use crate::assets::{TextureId, SpriteId, SpriteImage, SpriteManager};
+use crate::components::{Animation, DoorOrientation, Item};
+use crate::assets::{TextureId, SpriteId, SpriteImage, SpriteManager, NATIVE_TILE_SIZE};
+use crate::map::Hazard;
+
+/// Returns the region of the dungeon spritesheet that starts at the tile with the given row and
+/// column and has the given size (in px)
+fn tile_region(row: i32, col: i32, width: u32, height: u32) -> Rect {
+    let tile_size = NATIVE_TILE_SIZE as i32;
+    Rect::new(col * tile_size, row * tile_size, width, height)
+}
 
 /// A lookup table for all map sprites
 /// Used to avoid having to manage sprites in each tile
@@ -15,6 +23,8 @@ use crate::assets::{TextureId, SpriteId, SpriteImage, SpriteManager};
 pub struct MapSprites {
     /// Sprites for each type of floor tile. Each of these must map to a FloorSprite variant
     floor_tiles: Vec<SpriteId>,
+    /// Sprites for each type of floor tile in the clay palette, in the same order as floor_tiles
+    clay_floor_tiles: Vec<SpriteId>,
     /// Sprites for each type of wall tile. Each of these must map to a WallSprite variant
     wall_tiles: Vec<SpriteId>,
     /// Sprites for each orientation of staircase
@@ -23,13 +33,41 @@ pub struct MapSprites {
     staircase_down_tiles: Vec<SpriteId>,
     /// Sprites for each orientation of a door
     door_tiles: Vec<SpriteId>,
+    /// Sprites for each orientation of a gate
+    gate_tiles: Vec<SpriteId>,
     /// The torch animation
     torch_animation: Animation,
+    /// A map fragment mounted on a wall
+    map_fragment: SpriteId,
+    /// A cage with a prisoner locked inside
+    cage: SpriteId,
+    /// A closed chest
+    chest: SpriteId,
+    /// Objects that are placed on the floor of rooms purely for decoration
+    props: Vec<SpriteId>,
+    /// Crates and pots that can be broken open
+    breakables: Vec<SpriteId>,
+    /// A potion bobbing up and down on the floor
+    potion_animation: Animation,
+    /// A pile of gold glinting on the floor
+    gold_animation: Animation,
+    /// A trap mounted on a wall that fires arrows
+    arrow_shooter: SpriteId,
+    /// An arrow fired by an arrow shooter
+    arrow: SpriteId,
+    /// The rippling of water on the floor
+    water_animation: Animation,
+    /// The shimmering darkness of a pit in the floor
+    pit_animation: Animation,
 }
 
 impl MapSprites {
     /// Creates a table of sprites from the standard layout of the dungeon spritesheet
-    pub fn from_dungeon_spritesheet(texture_id: TextureId, sprites: &mut SpriteManager, tile_size: u32) -> Self {
+    pub fn from_dungeon_spritesheet(texture_id: TextureId, sprites: &mut SpriteManager) -> Self {
+        // Regions are always measured in the pixels of the spritesheet. Sprites are scaled to the
+        // tile size of the map when they are rendered.
+        let tile_size = NATIVE_TILE_SIZE;
+
         // Adds all of the sprites to the sprite manager and returns a vector of the produced sprite IDs
         macro_rules! add_sprites {
             ($($sp:expr),* $(,)*) => (
@@ -41,27 +79,11 @@ impl MapSprites {
 
         // Returns the (tile_size)x(tile_size) sprite for the given row and column of the spritesheet
         macro_rules! tile_sprite {
-            (x: $x:expr, y: $y:expr, width: $width:expr, height: $height:expr) => (
-                SpriteImage::new_unflipped(
-                    texture_id,
-                    Rect::new(
-                        $x,
-                        $y,
-                        $width,
-                        $height,
-                    ),
-                )
-            );
             (row: $row:expr, col: $col:expr, width: $width:expr, height: $height:expr) => (
-                tile_sprite!(
-                    x: $col as i32 * tile_size as i32,
-                    y: $row as i32 * tile_size as i32,
-                    width: $width,
-                    height: $height
-                )
+                SpriteImage::new_unflipped(texture_id, tile_region($row, $col, $width, $height))
             );
             (row: $row:expr, col: $col:expr) => (
-                tile_sprite!(row: $row, col: $col, width: tile_size, height: tile_size);
+                tile_sprite!(row: $row, col: $col, width: tile_size, height: tile_size)
             )
         }
 
@@ -82,6 +104,22 @@ impl MapSprites {
                 tile_sprite!(row: 2, col: 2), // 11
                 tile_sprite!(row: 2, col: 3), // 12
             ],
+            clay_floor_tiles: add_sprites![
+                tile_sprite!(row: 4, col: 0), // 1
+                tile_sprite!(row: 4, col: 1), // 2
+                tile_sprite!(row: 4, col: 2), // 3
+                tile_sprite!(row: 4, col: 3), // 4
+
+                tile_sprite!(row: 5, col: 0), // 5
+                tile_sprite!(row: 5, col: 1), // 6
+                tile_sprite!(row: 5, col: 2), // 7
+                tile_sprite!(row: 5, col: 3), // 8
+
+                tile_sprite!(row: 6, col: 0), // 9
+                tile_sprite!(row: 6, col: 1), // 10
+                tile_sprite!(row: 6, col: 2), // 11
+                tile_sprite!(row: 6, col: 3), // 12
+            ],
             wall_tiles: add_sprites![
                 tile_sprite!(row: 8, col: 0),
                 tile_sprite!(row: 8, col: 1),
@@ -123,6 +161,9 @@ impl MapSprites {
                 // Entrance walls
                 tile_sprite!(row: 10, col: 12), // Left
                 tile_sprite!(row: 10, col: 13), // Right
+
+                // Rubble (collapsed doorway)
+                tile_sprite!(row: 16, col: 18),
             ],
             staircase_up_tiles: add_sprites![
                 // bottom step faces right
@@ -142,39 +183,149 @@ impl MapSprites {
                 // vertical door (closed)
                 tile_sprite!(row: 10, col: 15, width: tile_size, height: tile_size*2).anchor_south(),
             ],
+            //TODO: Placeholders until there is art for gates
+            gate_tiles: add_sprites![
+                // horizontal gate (closed)
+                tile_sprite!(row: 11, col: 16),
+                // vertical gate (closed)
+                tile_sprite!(row: 10, col: 17, width: tile_size, height: tile_size*2).anchor_south(),
+            ],
             torch_animation: Animation::with_constant_delay(
-                &add_sprites![
-                    tile_sprite!(row: 15, col: 0),
-                    tile_sprite!(row: 15, col: 1),
-                    tile_sprite!(row: 15, col: 2),
-                    tile_sprite!(row: 15, col: 3),
+                &[
+                    sprites.add(tile_sprite!(row: 15, col: 0)),
+                    sprites.add(tile_sprite!(row: 15, col: 1)),
+                    sprites.add(tile_sprite!(row: 15, col: 2)),
+                    sprites.add(tile_sprite!(row: 15, col: 3)),
                 ],
                 3,
                 false,
                 true,
             ),
+            map_fragment: sprites.add(tile_sprite!(row: 13, col: 15)),
+            cage: sprites.add(tile_sprite!(row: 18, col: 17)),
+            //TODO: Placeholder until there is art for chests
+            chest: sprites.add(tile_sprite!(row: 18, col: 15)),
+            props: add_sprites![
+                tile_sprite!(row: 16, col: 16), // Pot
+                tile_sprite!(row: 16, col: 17), // Broken pot
+                tile_sprite!(row: 16, col: 18), // Rubble
+                tile_sprite!(row: 18, col: 13), // Barrel
+            ],
+            breakables: add_sprites![
+                tile_sprite!(row: 16, col: 16), // Pot
+                tile_sprite!(row: 18, col: 13), // Barrel
+            ],
+            //TODO: Placeholder until there is art for potions
+            potion_animation: Animation::with_constant_delay(
+                &[
+                    sprites.add(tile_sprite!(row: 18, col: 16)),
+                    sprites.add(tile_sprite!(row: 18, col: 16).dest_offset(0, -1)),
+                    sprites.add(tile_sprite!(row: 18, col: 16).dest_offset(0, -2)),
+                    sprites.add(tile_sprite!(row: 18, col: 16).dest_offset(0, -1)),
+                ],
+                8,
+                true,
+                true,
+            ),
+            //TODO: Placeholder until there is art for gold
+            gold_animation: Animation::with_constant_delay(
+                &[
+                    sprites.add(tile_sprite!(row: 14, col: 1)),
+                    sprites.add(tile_sprite!(row: 14, col: 1).flip_horizontally()),
+                ],
+                15,
+                true,
+                true,
+            ),
+            //TODO: Both of these are placeholders until there is art for arrow shooters
+            arrow_shooter: sprites.add(tile_sprite!(row: 13, col: 16)),
+            arrow: sprites.add(tile_sprite!(row: 18, col: 14)),
+            //TODO: Both of these are placeholders until there is art for water and pits
+            water_animation: Animation::with_constant_delay(
+                &[
+                    sprites.add(tile_sprite!(row: 5, col: 15)),
+                    sprites.add(tile_sprite!(row: 5, col: 15).flip_horizontally()),
+                ],
+                20,
+                true,
+                true,
+            ),
+            pit_animation: Animation::with_constant_delay(
+                &[
+                    sprites.add(tile_sprite!(row: 1, col: 15)),
+                    sprites.add(tile_sprite!(row: 1, col: 15).flip_horizontally()),
+                ],
+                20,
+                true,
+                true,
+            ),
+        }
+    }
+
+    /// Moves every sprite by the given offset. Used when the sprite manager that the sprites came
+    /// from is appended to another one.
+    pub fn offset_sprites(&mut self, offset: usize) {
+        let Self {
+            floor_tiles,
+            clay_floor_tiles,
+            wall_tiles,
+            staircase_up_tiles,
+            staircase_down_tiles,
+            door_tiles,
+            gate_tiles,
+            torch_animation,
+            map_fragment,
+            cage,
+            chest,
+            props,
+            breakables,
+            potion_animation,
+            gold_animation,
+            arrow_shooter,
+            arrow,
+            water_animation,
+            pit_animation,
+        } = self;
+
+        let mut tile_lists = [floor_tiles, clay_floor_tiles, wall_tiles, staircase_up_tiles,
+            staircase_down_tiles, door_tiles, gate_tiles, props, breakables];
+        for tiles in tile_lists.iter_mut() {
+            for sprite in tiles.iter_mut() {
+                *sprite = sprite.offset(offset);
+            }
+        }
+        for sprite in [map_fragment, cage, chest, arrow_shooter, arrow].iter_mut() {
+            **sprite = sprite.offset(offset);
+        }
+        for animation in [torch_animation, potion_animation, gold_animation, water_animation, pit_animation].iter_mut() {
+            animation.offset_sprites(offset);
         }
     }
 
     pub fn empty_tile_sprite(&self) -> SpriteId {
-        self.floor_sprite(FloorSprite::Floor4)
+        self.floor_sprite(FloorPalette::Stone, FloorSprite::Floor4)
     }
 
-    pub fn floor_sprite(&self, sprite: FloorSprite) -> SpriteId {
+    pub fn floor_sprite(&self, palette: FloorPalette, sprite: FloorSprite) -> SpriteId {
+        let tiles = match palette {
+            FloorPalette::Stone => &self.floor_tiles,
+            FloorPalette::Clay => &self.clay_floor_tiles,
+        };
+
         use self::FloorSprite::*;
         match sprite {
-            Floor1 => self.floor_tiles[0],
-            Floor2 => self.floor_tiles[1],
-            Floor3 => self.floor_tiles[2],
-            Floor4 => self.floor_tiles[3],
-            Floor5 => self.floor_tiles[4],
-            Floor6 => self.floor_tiles[5],
-            Floor7 => self.floor_tiles[6],
-            Floor8 => self.floor_tiles[7],
-            Floor9 => self.floor_tiles[8],
-            Floor10 => self.floor_tiles[9],
-            Floor11 => self.floor_tiles[10],
-            Floor12 => self.floor_tiles[11],
+            Floor1 => tiles[0],
+            Floor2 => tiles[1],
+            Floor3 => tiles[2],
+            Floor4 => tiles[3],
+            Floor5 => tiles[4],
+            Floor6 => tiles[5],
+            Floor7 => tiles[6],
+            Floor8 => tiles[7],
+            Floor9 => tiles[8],
+            Floor10 => tiles[9],
+            Floor11 => tiles[10],
+            Floor12 => tiles[11],
         }
     }
 
@@ -199,6 +350,7 @@ impl MapSprites {
             w!{alt: TorchLit} => s(21),
             w!{alt: EntranceLeft} => s(22),
             w!{alt: EntranceRight} => s(23),
+            w!{alt: Rubble} => s(24),
 
             w!{N: false, E: false, S: false, W: false} => s(0), // no walls adjacent
 
@@ -245,6 +397,22 @@ impl MapSprites {
         self.staircase_down_tiles[1]
     }
 
+    /// Returns the sprite of a door in a wall with the given orientation
+    pub fn door_sprite(&self, orientation: DoorOrientation) -> SpriteId {
+        match orientation {
+            DoorOrientation::Horizontal => self.door_horizontal(),
+            DoorOrientation::Vertical => self.door_vertical(),
+        }
+    }
+
+    /// Returns the sprite of a gate in a wall with the given orientation
+    pub fn gate_sprite(&self, orientation: DoorOrientation) -> SpriteId {
+        match orientation {
+            DoorOrientation::Horizontal => self.gate_horizontal(),
+            DoorOrientation::Vertical => self.gate_vertical(),
+        }
+    }
+
     pub fn door_horizontal(&self) -> SpriteId {
         self.door_tiles[0]
     }
@@ -253,7 +421,98 @@ impl MapSprites {
         self.door_tiles[1]
     }
 
+    pub fn gate_horizontal(&self) -> SpriteId {
+        self.gate_tiles[0]
+    }
+
+    pub fn gate_vertical(&self) -> SpriteId {
+        self.gate_tiles[1]
+    }
+
     pub fn torch_animation(&self) -> &Animation {
         &self.torch_animation
     }
+
+    pub fn map_fragment(&self) -> SpriteId {
+        self.map_fragment
+    }
+
+    pub fn cage(&self) -> SpriteId {
+        self.cage
+    }
+
+    pub fn chest(&self) -> SpriteId {
+        self.chest
+    }
+
+    pub fn props(&self) -> &[SpriteId] {
+        &self.props
+    }
+
+    pub fn breakables(&self) -> &[SpriteId] {
+        &self.breakables
+    }
+
+    pub fn potion_animation(&self) -> &Animation {
+        &self.potion_animation
+    }
+
+    /// Returns the animation of the given item while it is lying on the floor
+    pub fn item_animation(&self, item: &Item) -> &Animation {
+        match item {
+            Item::Gold(_) => &self.gold_animation,
+            //TODO: Keys and weapons never end up on the floor, so they do not have their own
+            // animation yet
+            Item::TreasureKey | Item::RoomKey | Item::Potion {..} | Item::Weapon(_) => &self.potion_animation,
+        }
+    }
+
+    pub fn arrow_shooter(&self) -> SpriteId {
+        self.arrow_shooter
+    }
+
+    pub fn arrow(&self) -> SpriteId {
+        self.arrow
+    }
+
+    pub fn hazard_animation(&self, hazard: Hazard) -> &Animation {
+        match hazard {
+            Hazard::Water => &self.water_animation,
+            Hazard::Pit => &self.pit_animation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_regions_measured_in_native_tiles() {
+        assert_eq!(tile_region(0, 0, 16, 16), Rect::new(0, 0, 16, 16));
+        assert_eq!(tile_region(17, 7, 16, 32), Rect::new(112, 272, 16, 32));
+    }
+
+    #[test]
+    fn appended_map_sprites_keep_their_sprites() {
+        let texture = TextureId::placeholder(1);
+        let other_sprite = SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, 16, 16));
+        let mut expected_sprites = SpriteManager::default();
+        expected_sprites.add(other_sprite.clone());
+        let expected = MapSprites::from_dungeon_spritesheet(texture, &mut expected_sprites);
+
+        // Created on their own, as if on another thread, and then combined
+        let mut sprites = SpriteManager::default();
+        sprites.add(other_sprite);
+        let mut sheet = SpriteManager::default();
+        let mut map_sprites = MapSprites::from_dungeon_spritesheet(texture, &mut sheet);
+        map_sprites.offset_sprites(sprites.append(sheet));
+
+        assert_eq!(map_sprites.empty_tile_sprite(), expected.empty_tile_sprite());
+        assert_eq!(map_sprites.wall_sprite(WallSprite::default()), expected.wall_sprite(WallSprite::default()));
+        assert_eq!(map_sprites.props(), expected.props());
+        assert_eq!(map_sprites.arrow(), expected.arrow());
+        assert_eq!(map_sprites.hazard_animation(Hazard::Pit).steps, expected.hazard_animation(Hazard::Pit).steps);
+        assert_eq!(sprites.get(map_sprites.chest()), expected_sprites.get(expected.chest()));
+    }
 }
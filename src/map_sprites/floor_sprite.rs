@@ -1,6 +1,8 @@
 /// Used to decouple SpriteImage from a specific SpriteTable
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum FloorSprite {
+    // Strategy: Fill with a default floor tile and then come back and place patterns after
+    #[default]
     Floor1,
     Floor2,
     Floor3,
@@ -15,11 +17,15 @@ pub enum FloorSprite {
     Floor12,
 }
 
-impl Default for FloorSprite {
-    fn default() -> Self {
-        // Strategy: Fill with a default floor tile and then come back and place patterns after
-        FloorSprite::Floor1
-    }
+/// The set of floor sprites used to draw a tile. Every palette has a sprite for each FloorSprite
+/// variant, so any pattern can be drawn with any palette.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FloorPalette {
+    /// Grey stone floor used by most rooms
+    #[default]
+    Stone,
+    /// Brown clay floor used to set special rooms apart from the rest of the level
+    Clay,
 }
 
 /// These patterns will be placed in a non-overlapping way throughout the tiles on the map
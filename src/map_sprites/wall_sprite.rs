@@ -22,8 +22,9 @@ pub struct WallSprite {
 }
 
 /// Different alternate wall styles for some of the wall sprites
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum WallSpriteAlternate {
+    #[default]
     Alt0,
     Alt1,
     Alt2,
@@ -31,12 +32,8 @@ pub enum WallSpriteAlternate {
     TorchLit,
     EntranceLeft,
     EntranceRight,
-}
-
-impl Default for WallSpriteAlternate {
-    fn default() -> Self {
-        WallSpriteAlternate::Alt0
-    }
+    /// A passage that collapsed during a tremor
+    Rubble,
 }
 
 impl Distribution<WallSpriteAlternate> for Standard {
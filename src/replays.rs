@@ -0,0 +1,190 @@
+//! The inputs of the fastest victory on each map, kept between runs of the game so that the run can
+//! be played back as a ghost the next time the same map is played
+
+use std::fs;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sdl2::rect::Point;
+
+use crate::generator::MapKey;
+use crate::resources::{Event, Key};
+use crate::ui::RecordedInput;
+
+/// Every input given to the game during a run that ended in victory
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Replay {
+    /// The number of frames of the game that were played before the run was won
+    pub frames: usize,
+    pub inputs: Vec<RecordedInput>,
+}
+
+impl Replay {
+    /// Returns the path of the file that the replay for the map with the given key is kept in
+    pub fn path<P: AsRef<Path>>(dir: P, key: MapKey) -> PathBuf {
+        dir.as_ref().join(format!("{}.txt", key))
+    }
+
+    /// Loads the replay of the map with the given key from the given directory. Returns None if
+    /// no run on that map has been saved yet.
+    pub fn load<P: AsRef<Path>>(dir: P, key: MapKey) -> io::Result<Option<Self>> {
+        match fs::read_to_string(Self::path(dir, key)) {
+            Ok(contents) => Self::parse(&contents).map(Some),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves the replay of the map with the given key to the given directory, replacing any
+    /// replay of that map that was there before. The directory is created if it does not exist.
+    pub fn save<P: AsRef<Path>>(&self, dir: P, key: MapKey) -> io::Result<()> {
+        fs::create_dir_all(&dir)?;
+        fs::write(Self::path(dir, key), self.to_string())
+    }
+
+    /// Returns true if this replay won faster than the given replay (if any)
+    pub fn is_faster_than(&self, other: Option<&Replay>) -> bool {
+        other.map(|other| self.frames < other.frames).unwrap_or(true)
+    }
+
+    /// Parses a replay from the format written by `save`: the frames played on the first line
+    /// followed by one line for each input. Each input is the frames elapsed followed by its
+    /// events, separated by spaces.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData,
+            format!("invalid line in replay: `{}`", line));
+
+        let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+        let first = lines.next().unwrap_or("");
+        let frames = match first.split_whitespace().collect::<Vec<_>>().as_slice() {
+            &["frames", frames] => frames.parse().map_err(|_| invalid(first))?,
+            _ => return Err(invalid(first)),
+        };
+
+        let inputs = lines.map(|line| {
+            let mut parts = line.split_whitespace();
+            let frames_elapsed = parts.next().and_then(|frames| frames.parse().ok())
+                .ok_or_else(|| invalid(line))?;
+            let events = parts.map(|event| parse_event(event).ok_or_else(|| invalid(line)))
+                .collect::<Result<_, _>>()?;
+            Ok(RecordedInput {frames_elapsed, events})
+        }).collect::<io::Result<_>>()?;
+
+        Ok(Self {frames, inputs})
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "frames {}", self.frames)?;
+        for input in &self.inputs {
+            write!(f, "{}", input.frames_elapsed)?;
+            for event in &input.events {
+                // Events that come from the level itself are produced again when the run is played
+                // back, so they are never saved
+                if let Some(event) = format_event(event) {
+                    write!(f, " {}", event)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats an input event as a single word, or returns None if the event is not an input
+fn format_event(event: &Event) -> Option<String> {
+    Some(match event {
+        Event::KeyDown(key) => format!("down:{}", key.id()),
+        Event::KeyUp(key) => format!("up:{}", key.id()),
+        Event::PointerAttack(pos) => format!("attack:{},{}", pos.x(), pos.y()),
+        Event::PointerInteract(pos) => format!("interact:{},{}", pos.x(), pos.y()),
+        Event::RoomChanged {..} => return None,
+    })
+}
+
+/// Parses an event formatted by `format_event`
+fn parse_event(event: &str) -> Option<Event> {
+    let mut parts = event.splitn(2, ':');
+    let (kind, value) = (parts.next()?, parts.next()?);
+    let parse_point = |value: &str| -> Option<Point> {
+        let mut coords = value.splitn(2, ',');
+        Some(Point::new(coords.next()?.parse().ok()?, coords.next()?.parse().ok()?))
+    };
+    Some(match kind {
+        "down" => Event::KeyDown(Key::from_id(value)?),
+        "up" => Event::KeyUp(Key::from_id(value)?),
+        "attack" => Event::PointerAttack(parse_point(value)?),
+        "interact" => Event::PointerInteract(parse_point(value)?),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    use rand::random;
+
+    use crate::test_helpers::walled_room;
+
+    fn replay() -> Replay {
+        Replay {
+            frames: 95,
+            inputs: vec![
+                RecordedInput {frames_elapsed: 1, events: vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::B)]},
+                RecordedInput {frames_elapsed: 2, events: Vec::new()},
+                RecordedInput {frames_elapsed: 1, events: vec![Event::PointerAttack(Point::new(-3, 40)), Event::KeyUp(Key::LightKey5)]},
+                RecordedInput {frames_elapsed: 1, events: vec![Event::PointerInteract(Point::new(12, 7))]},
+            ],
+        }
+    }
+
+    #[test]
+    fn replay_round_trip() {
+        let replay = replay();
+        let contents = replay.to_string();
+        assert_eq!(contents, "frames 95\n1 down:right down:b\n2\n1 attack:-3,40 up:light5\n1 interact:12,7\n");
+        assert_eq!(Replay::parse(&contents).unwrap(), replay);
+
+        for &key in &Key::ALL {
+            assert_eq!(Key::from_id(key.id()), Some(key));
+        }
+
+        for invalid in &["", "frames", "frames 1 2", "frames 1\nmany", "frames 1\n1 down:wings",
+            "frames 1\n1 attack:1", "frames 1\n1 jump:a"] {
+            assert_eq!(Replay::parse(invalid).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn level_events_not_saved() {
+        let (room, _) = walled_room(5, 5, 16).rooms().next().unwrap();
+        let mut replay = replay();
+        replay.inputs[1].events.push(Event::RoomChanged {from: None, to: room});
+        assert_eq!(Replay::parse(&replay.to_string()).unwrap(), self::replay());
+    }
+
+    #[test]
+    fn save_replaces_slower_replay() {
+        let dir = env::temp_dir().join(format!("caves-replays-{}", process::id()));
+        let key = random();
+        assert_eq!(Replay::load(&dir, key).unwrap(), None);
+
+        let slow = replay();
+        slow.save(&dir, key).unwrap();
+        let fast = Replay {frames: 80, ..replay()};
+        assert!(fast.is_faster_than(Replay::load(&dir, key).unwrap().as_ref()));
+        fast.save(&dir, key).unwrap();
+        assert!(!slow.is_faster_than(Some(&fast)));
+        assert!(slow.is_faster_than(None));
+
+        let loaded = Replay::load(&dir, key).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Some(fast));
+    }
+}
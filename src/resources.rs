@@ -1,13 +1,20 @@
 //! ECS Resources for use by various systems
 
-use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::collections::{HashMap, VecDeque};
 
-use sdl2::keyboard::Scancode;
-use specs::Entity;
+use rand::{SeedableRng, rngs::StdRng};
+use sdl2::{keyboard::Scancode, rect::{Point, Rect}};
+use specs::{Entity, World};
+
+use crate::map::{FloorMap, RoomId, RoomUid, StairsUid, TilePos, GridSize};
+use crate::audio::SoundEffect;
 
 /// Resource that represents the number of frames elapsed since the last time all of the systems
 /// were run. Value is guaranteed to be greater than or equal to 1.
-/// Often this will be just 1 but it may be greater if there is lag or if a system takes too long.
+/// The game itself always runs one frame at a time (catching up on lag by running several frames
+/// in a row), but systems should still handle larger values since tests and replays may use them.
 #[derive(Debug, Clone, Copy)]
 pub struct FramesElapsed(pub usize);
 
@@ -22,19 +29,26 @@ impl<'a> IntoIterator for &'a EventQueue {
     type IntoIter = ::std::slice::Iter<'a, Event>;
 
     fn into_iter(self) -> Self::IntoIter {
-        (&self.0).into_iter()
+        self.0.iter()
     }
 }
 
-/// Represents an event from the user of the application
-#[derive(Debug, Clone)]
+/// Represents an event from the user of the application or from the level itself
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     KeyDown(Key),
     KeyUp(Key),
+    /// The player clicked to attack towards the given position on the map
+    PointerAttack(Point),
+    /// The player clicked to interact with whatever is towards the given position on the map
+    PointerInteract(Point),
+    /// The player moved into a different room. Emitted by the RoomTracking system during the
+    /// frame that the change happens.
+    RoomChanged {from: Option<RoomId>, to: RoomId},
 }
 
 /// Represents the key that was pressed/released
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
     UpArrow,
     DownArrow,
@@ -84,39 +98,154 @@ impl Key {
             _ => return None,
         })
     }
-}
+    /// All of the keys
+    pub const ALL: [Key; 17] = [
+        Key::UpArrow, Key::DownArrow, Key::LeftArrow, Key::RightArrow,
+        Key::Menu, Key::Select, Key::Start, Key::VolumeDown, Key::VolumeUp,
+        Key::X, Key::Y, Key::A, Key::B,
+        Key::LightKey1, Key::LightKey2, Key::LightKey4, Key::LightKey5,
+    ];
 
-/// Resource that represents an intention to change the game state
-#[derive(Debug, Default)]
-pub struct ChangeGameState(Option<GameState>);
-
-impl ChangeGameState {
-    pub fn get(&self) -> Option<GameState> {
-        self.0
+    /// The name of this key in saved files. Must never change once released.
+    pub fn id(self) -> &'static str {
+        use self::Key::*;
+        match self {
+            UpArrow => "up",
+            DownArrow => "down",
+            LeftArrow => "left",
+            RightArrow => "right",
+            Menu => "menu",
+            Select => "select",
+            Start => "start",
+            VolumeDown => "volume_down",
+            VolumeUp => "volume_up",
+            X => "x",
+            Y => "y",
+            A => "a",
+            B => "b",
+            LightKey1 => "light1",
+            LightKey2 => "light2",
+            LightKey4 => "light4",
+            LightKey5 => "light5",
+        }
     }
 
-    /// Replaces the
-    pub fn replace(&mut self, state: GameState) -> Option<GameState> {
-        //TODO: Can replace this with a single call to Option::replace once that is stablized.
-        // https://doc.rust-lang.org/std/option/enum.Option.html#method.replace
-        let prev = self.0.take();
-        self.0 = Some(state);
-        prev
+    /// Returns the key with the given ID, if any
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|key| key.id() == id)
     }
 }
 
-/// Changes to the game state
+/// The floor that a level transition goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelDirection {
+    /// The floor below the current one
+    Next,
+    /// The floor above the current one
+    Prev,
+}
+
+/// The states that the game can be in
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GameState {
-    /// Game should change to the next level (and move the player and its components there)
-    GoToNextLevel {id: usize},
-    /// Game should change to the previous level (and move the player and its components there)
-    GoToPrevLevel {id: usize},
-    /// Game should pause, but stay on the same level
-    Pause,
+    /// The current level is running
+    Playing,
+    /// Nothing changes until the game is resumed
+    Paused,
+    /// The level is paused while the player buys from the given merchant
+    Shop {merchant: Entity},
+    /// The level is changing to the floor in the given direction. The id is the id of the
+    /// staircase that the player took.
+    LevelTransition {to: LevelDirection, id: usize},
+    /// The player made it out of the caves. The game does not continue after this.
+    Victory,
+    /// The player ran out of health. The game does not continue after this.
+    Defeat,
     //TODO: PauseToShowMessage or something for when we want to show some info
 }
 
+/// A change of game state that is not allowed from the state that the game is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: GameState,
+    pub to: GameState,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot change the game state from {:?} to {:?}", self.from, self.to)
+    }
+}
+
+/// Resource that holds the state of the game. Every change of state goes through `transition` so
+/// that states are only ever entered from the states that can lead to them.
+///
+/// | From                           | To                                                |
+/// |--------------------------------|---------------------------------------------------|
+/// | Playing                        | Paused, Shop, LevelTransition, Victory, Defeat    |
+/// | Paused                         | The state that was paused (see `resume`)          |
+/// | Shop, LevelTransition          | Playing, Paused                                   |
+/// | Victory, Defeat                | Nothing, the game is over                         |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameStateMachine {
+    state: GameState,
+    /// The state before the last transition, if any
+    previous: Option<GameState>,
+}
+
+impl Default for GameStateMachine {
+    fn default() -> Self {
+        Self {state: GameState::Playing, previous: None}
+    }
+}
+
+impl GameStateMachine {
+    /// Returns the state that the game is currently in
+    pub fn current(&self) -> GameState {
+        self.state
+    }
+
+    /// Returns the state that the game was in before the last transition, if any
+    pub fn previous(&self) -> Option<GameState> {
+        self.previous
+    }
+
+    /// Returns true if the game can change to the given state from its current state
+    pub fn can_transition(&self, to: GameState) -> bool {
+        use self::GameState::*;
+        match (self.state, to) {
+            // Pausing only ever goes back to the state that was paused
+            (Paused, to) => Some(to) == self.previous,
+            (Playing, Playing) => false,
+            (Playing, _) => true,
+            (Shop {..}, Playing) | (Shop {..}, Paused) => true,
+            (LevelTransition {..}, Playing) | (LevelTransition {..}, Paused) => true,
+            (Shop {..}, _) | (LevelTransition {..}, _) => false,
+            (Victory, _) | (Defeat, _) => false,
+        }
+    }
+
+    /// Changes the game to the given state. Returns the state that the game was in before or an
+    /// error if that state cannot change to the given state.
+    pub fn transition(&mut self, to: GameState) -> Result<GameState, IllegalTransition> {
+        if !self.can_transition(to) {
+            return Err(IllegalTransition {from: self.state, to});
+        }
+        let from = mem::replace(&mut self.state, to);
+        self.previous = Some(from);
+        Ok(from)
+    }
+
+    /// Changes a paused game back to the state it was in before it was paused
+    pub fn resume(&mut self) -> Result<GameState, IllegalTransition> {
+        let to = self.previous.unwrap_or(GameState::Playing);
+        if self.state != GameState::Paused {
+            return Err(IllegalTransition {from: self.state, to});
+        }
+        self.transition(to)
+    }
+}
+
 /// Resource that represents any actions that have happened during the current frame.
 ///
 /// This queue resets every frame
@@ -130,6 +259,8 @@ pub enum Action {
     Interact,
     /// The entity performed its attack
     Attack,
+    /// The entity requested to drink one of the potions it is carrying
+    DrinkPotion,
     /// The entity was hit by something and took damage
     Hit,
     /// The entity completed something
@@ -137,3 +268,1161 @@ pub enum Action {
     /// The entity was defeated in battle (0 HP)
     Defeat,
 }
+
+/// Resource that represents the sound effects that should be played after the current frame.
+///
+/// This queue resets every frame
+#[derive(Debug, Default)]
+pub struct SoundQueue(pub Vec<SoundEffect>);
+
+/// A short message announced to the player (e.g. "Floor 3")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub text: String,
+    /// The number of frames left before the notification disappears
+    pub frames_remaining: usize,
+}
+
+impl Notification {
+    /// The number of frames that each notification is shown for (~2 seconds)
+    pub const DURATION: usize = 60;
+
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            frames_remaining: Self::DURATION,
+        }
+    }
+}
+
+/// Resource that represents any notifications that should be announced to the player after the
+/// current frame. Notifications are shown one at a time in the order they were queued.
+///
+/// This queue resets every frame
+#[derive(Debug, Default)]
+pub struct NotificationQueue(pub Vec<Notification>);
+
+impl NotificationQueue {
+    /// Queues a notification with the given text
+    pub fn push(&mut self, text: impl Into<String>) {
+        self.0.push(Notification::new(text));
+    }
+}
+
+/// Something that happened in the game that the player may be rewarded for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameEvent {
+    /// The player entered the given floor (starting at 1)
+    FloorEntered {floor: usize},
+    /// The player went down the stairs of the given floor (starting at 1)
+    FloorCleared {floor: usize, stats: FloorStats},
+    /// An enemy was defeated
+    EnemyKilled,
+    /// The player lost the given number of health points
+    DamageTaken {amount: usize},
+    /// A map fragment was collected or a prisoner was freed
+    ItemFound,
+    /// A follower was escorted to a staircase
+    PrisonerRescued,
+    /// A chest with something inside was opened
+    ChestOpened,
+    /// The player drank a potion
+    PotionDrunk,
+    /// The player made it to the end of the run after playing for the given number of frames
+    RunWon {frames: usize},
+}
+
+/// Resource that represents the game events that occurred during the current frame.
+///
+/// This queue resets every frame
+#[derive(Debug, Default)]
+pub struct GameEvents(pub Vec<GameEvent>);
+
+/// What the player has accomplished during a single visit to a floor
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FloorStats {
+    /// The number of frames spent on the floor
+    pub frames: usize,
+    pub enemies_killed: usize,
+    /// The total health points lost by the player
+    pub damage_taken: usize,
+    /// The total health points taken from enemies by the player
+    pub damage_dealt: usize,
+    /// The number of map fragments collected and prisoners freed
+    pub items_found: usize,
+    /// The number of chests opened, map fragments collected and prisoners freed
+    pub secrets_found: usize,
+}
+
+impl FloorStats {
+    /// Adds the given stats onto these stats
+    pub fn add(&mut self, other: FloorStats) {
+        let FloorStats {frames, enemies_killed, damage_taken, damage_dealt, items_found, secrets_found} = other;
+        self.frames += frames;
+        self.enemies_killed += enemies_killed;
+        self.damage_taken += damage_taken;
+        self.damage_dealt += damage_dealt;
+        self.items_found += items_found;
+        self.secrets_found += secrets_found;
+    }
+}
+
+/// Resource that keeps track of what the player has accomplished over the entire run. Carried
+/// over from level to level along with the player.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunStats {
+    /// The number of prisoners that were escorted to a staircase
+    pub rescues: usize,
+    /// The stats for the current visit to the current floor
+    pub floor: FloorStats,
+    /// The stats of every previous visit to a floor, not including the current one
+    pub totals: FloorStats,
+}
+
+impl RunStats {
+    /// Folds the stats for the current floor into the run totals and starts a new floor. Returns
+    /// the stats of the floor that was finished.
+    pub fn finish_floor(&mut self) -> FloorStats {
+        let floor = mem::take(&mut self.floor);
+        self.totals.add(floor);
+        floor
+    }
+}
+
+/// Returns the time that the given number of frames takes as minutes and seconds (m:ss), rounded
+/// down to the nearest second
+pub fn format_play_time(frames: usize, fps: usize) -> String {
+    let seconds = frames / fps;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Formats the given number of frames as minutes, seconds and milliseconds (m:ss.mmm). Precise
+/// enough to compare runs that finish within a second of each other.
+pub fn format_split_time(frames: usize, fps: usize) -> String {
+    let millis = frames * 1000 / fps;
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+/// Resource that keeps track of how long the player has actually spent playing during the run.
+/// Carried over from level to level along with the player.
+///
+/// The clock only advances while a level is being played. Levels are not dispatched during level
+/// transitions or once the game is over, so that time is never counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PlayClock {
+    frames: usize,
+}
+
+impl PlayClock {
+    /// Advances the clock by the given number of frames of play
+    pub fn advance(&mut self, frames_elapsed: usize) {
+        self.frames += frames_elapsed;
+    }
+
+    /// Returns the number of frames that have been played
+    pub fn frames(self) -> usize {
+        self.frames
+    }
+
+    /// Returns the time played as minutes and seconds (m:ss)
+    pub fn format(self, fps: usize) -> String {
+        format_play_time(self.frames, fps)
+    }
+
+    /// Returns the time played as minutes, seconds and milliseconds (m:ss.mmm)
+    pub fn format_precise(self, fps: usize) -> String {
+        format_split_time(self.frames, fps)
+    }
+}
+
+/// The time during the run at which the player went down the stairs of a floor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split {
+    /// The number of the floor that was cleared (starting at 1)
+    pub floor: usize,
+    /// The play clock at the moment the player went down the stairs
+    pub clock: PlayClock,
+}
+
+/// Resource that keeps track of which way the player is headed during the run. Carried over from
+/// level to level along with the player.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RunPhase {
+    /// The player is making their way down to the treasure
+    #[default]
+    Descending,
+    /// The player has the treasure and is making their way back up to the surface
+    Escaping,
+}
+
+impl RunPhase {
+    /// Starts the escape back to the surface. Returns true if the phase changed as a result.
+    /// Escaping cannot be undone for the rest of the run.
+    pub fn begin_escape(&mut self) -> bool {
+        match self {
+            RunPhase::Descending => {
+                *self = RunPhase::Escaping;
+                true
+            },
+            RunPhase::Escaping => false,
+        }
+    }
+
+    pub fn is_escaping(self) -> bool {
+        match self {
+            RunPhase::Descending => false,
+            RunPhase::Escaping => true,
+        }
+    }
+}
+
+/// Adds a fresh copy of every resource that lasts for an entire run and is shared by every level,
+/// replacing any values left over from a previous run. Resources that depend on the map of a level
+/// (e.g. ExploredTiles) are added by the generator instead.
+///
+/// Any new resource that carries state from one frame to the next must be added here so that
+/// retrying a run does not start with the state of the last attempt.
+pub fn reset_run_resources(world: &mut World) {
+    world.add_resource(FramesElapsed(1));
+    world.add_resource(GameStateMachine::default());
+    world.add_resource(EventQueue::default());
+    world.add_resource(ActionQueue::default());
+    world.add_resource(SoundQueue::default());
+    world.add_resource(NotificationQueue::default());
+    world.add_resource(GameEvents::default());
+    world.add_resource(RunStats::default());
+    world.add_resource(RunPhase::default());
+    world.add_resource(PlayClock::default());
+    world.add_resource(ScreenShake::default());
+    world.add_resource(DecalBuffer::default());
+    world.add_resource(TutorialState::default());
+}
+
+/// Resource that counts down to the tremor of a level. When the tremor happens, one of the doors
+/// of the level collapses and can never be passed through again. There is at most one tremor per
+/// level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tremor {
+    /// The tremor happens once the player has spent the given number of frames on the level
+    Pending {frames_remaining: usize},
+    /// The tremor has already happened or will never happen on this level
+    #[default]
+    Inactive,
+}
+
+/// Resource that holds the random number generator used by systems while a level is played. Seeded
+/// from the map key by the generator so that the same inputs on the same map always lead to the
+/// same outcome.
+#[derive(Debug, Clone)]
+pub struct LevelRng(pub StdRng);
+
+impl Default for LevelRng {
+    fn default() -> Self {
+        LevelRng(StdRng::seed_from_u64(0))
+    }
+}
+
+/// Resource that keeps track of the room that the player is currently in. Depends on the map of
+/// the level, so it is added by the generator.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RoomTracker {
+    /// The room that the player is considered to be in
+    current: Option<RoomId>,
+    /// A different room that the player is in along with the number of frames they have been
+    /// there for. The player only changes rooms once they stay in the new room long enough.
+    pending: Option<(RoomId, usize)>,
+}
+
+impl RoomTracker {
+    /// The number of frames that the player must stay in a new room before they are considered to
+    /// be in it. Keeps the room from flipping back and forth every frame while the player stands
+    /// in a doorway between two rooms.
+    pub const STABLE_FRAMES: usize = 6;
+
+    /// Returns the room that the player is considered to be in, if any
+    pub fn current(&self) -> Option<RoomId> {
+        self.current
+    }
+
+    /// Updates the tracker with the room that the player is in during the current frame. Returns
+    /// an event if the player is now considered to be in a different room.
+    ///
+    /// The first room is entered immediately. Tiles outside of every room (None) never change the
+    /// current room.
+    pub fn update(&mut self, room: Option<RoomId>, frames_elapsed: usize) -> Option<Event> {
+        let room = match room {
+            Some(room) if Some(room) != self.current => room,
+            _ => {
+                self.pending = None;
+                return None;
+            },
+        };
+
+        let frames = match self.pending {
+            Some((pending, frames)) if pending == room => frames + frames_elapsed,
+            _ => frames_elapsed,
+        };
+        if self.current.is_some() && frames < Self::STABLE_FRAMES {
+            self.pending = Some((room, frames));
+            return None;
+        }
+
+        let from = self.current.replace(room);
+        self.pending = None;
+        Some(Event::RoomChanged {from, to: room})
+    }
+}
+
+/// The steps of the tutorial, each teaching one of the controls of the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TutorialStep {
+    Move,
+    Attack,
+    Interact,
+}
+
+impl TutorialStep {
+    /// Every step in the order that they are shown
+    pub const ALL: [TutorialStep; 3] = [TutorialStep::Move, TutorialStep::Attack, TutorialStep::Interact];
+
+    /// The text shown to the player until they perform the action of this step
+    pub fn prompt(self) -> &'static str {
+        match self {
+            TutorialStep::Move => "Arrow keys to move",
+            TutorialStep::Attack => "J to attack",
+            TutorialStep::Interact => "K to interact",
+        }
+    }
+}
+
+/// Resource that represents the progress of the player through the tutorial shown in the start
+/// room of the first level. The tutorial is finished by default, so it only appears on a level
+/// where it has been started.
+///
+/// Once the tutorial is finished (or skipped), it never starts again during the same run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TutorialState {
+    /// The steps that have not been completed yet, in the order they are shown
+    remaining: Vec<TutorialStep>,
+    /// True while the player is somewhere that the prompt of the current step should be shown
+    visible: bool,
+}
+
+impl TutorialState {
+    /// Starts the tutorial from its first step
+    pub fn start() -> Self {
+        Self {remaining: TutorialStep::ALL.to_vec(), visible: false}
+    }
+
+    /// Returns the step that the player has to complete next, if any
+    pub fn current(&self) -> Option<TutorialStep> {
+        self.remaining.first().cloned()
+    }
+
+    /// Returns the step whose prompt should currently be shown, if any
+    pub fn visible_step(&self) -> Option<TutorialStep> {
+        self.current().filter(|_| self.visible)
+    }
+
+    /// Returns true if every step has been completed or the tutorial was skipped
+    pub fn is_finished(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
+    /// Sets whether the prompt of the current step should be shown
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Moves on to the next step if the given step is the current one. Steps can only be
+    /// completed in order.
+    pub fn complete(&mut self, step: TutorialStep) {
+        if self.current() == Some(step) {
+            self.remaining.remove(0);
+        }
+    }
+
+    /// Ends the tutorial without completing any of the remaining steps
+    pub fn skip(&mut self) {
+        self.remaining.clear();
+    }
+}
+
+/// The directions that the screen cycles through while it is shaking. Each is scaled by the
+/// current amplitude of the shake.
+const SCREEN_SHAKE_DIRECTIONS: [(i32, i32); 4] = [(3, 0), (-3, 1), (1, -2), (-1, 2)];
+/// The largest component of any of the directions above
+const SCREEN_SHAKE_DIRECTION_MAX: i32 = 3;
+
+/// Resource that represents the screen shaking for a number of frames (e.g. during a tremor or
+/// when the player is hit). The shake gradually dies down until it stops completely.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenShake {
+    /// The largest distance (in px) that the screen moves along either axis when the shake starts
+    amplitude: u32,
+    /// The number of frames that the shake was started with
+    frames: usize,
+    frames_remaining: usize,
+}
+
+impl ScreenShake {
+    /// Shakes the screen for the given number of frames, moving it by at most `amplitude` px along
+    /// either axis. A shake that is currently stronger than the new one is left as it is.
+    pub fn start(&mut self, amplitude: u32, frames: usize) {
+        if self.current_amplitude() > amplitude {
+            return;
+        }
+        self.amplitude = amplitude;
+        self.frames = frames;
+        self.frames_remaining = frames;
+    }
+
+    /// Advances the shaking by the given number of frames
+    pub fn step(&mut self, frames: usize) {
+        self.frames_remaining = self.frames_remaining.saturating_sub(frames);
+    }
+
+    /// Stops the shaking immediately
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the largest distance (in px) that the screen can currently be moved along either
+    /// axis. Decreases linearly from the starting amplitude down to zero.
+    fn current_amplitude(&self) -> u32 {
+        if self.frames_remaining == 0 {
+            return 0;
+        }
+        // Rounds up so the shake does not stop before its last frame
+        let amplitude = self.amplitude as usize * self.frames_remaining;
+        amplitude.div_ceil(self.frames) as u32
+    }
+
+    /// Returns the offset (in px) that the screen should currently be moved by. The offset is
+    /// always the same for the same shake and number of frames remaining.
+    pub fn offset(&self) -> Point {
+        let amplitude = self.current_amplitude() as i32;
+        let (x, y) = SCREEN_SHAKE_DIRECTIONS[self.frames_remaining % SCREEN_SHAKE_DIRECTIONS.len()];
+        Point::new(x * amplitude / SCREEN_SHAKE_DIRECTION_MAX, y * amplitude / SCREEN_SHAKE_DIRECTION_MAX)
+    }
+}
+
+/// Resource that represents the position on the map of the top left corner of the screen the last
+/// time that the level was rendered. None if the level has not been rendered yet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CameraOffset(pub Option<Point>);
+
+/// The most decals that can be on a level at once. The oldest decal is removed to make room for
+/// each new decal past this limit.
+pub const DECAL_CAPACITY: usize = 64;
+
+/// The kinds of marks that can be left on the floor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecalKind {
+    /// Left where an entity took a heavy hit
+    Blood,
+}
+
+impl DecalKind {
+    /// The number of frames that a decal of this kind lasts for before it disappears
+    pub fn frames(self) -> usize {
+        use self::DecalKind::*;
+        match self {
+            Blood => 300,
+        }
+    }
+}
+
+/// A temporary mark on the floor. Decals are only drawn and never interact with anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decal {
+    /// The position (in world coordinates) of the center of the decal
+    pub pos: Point,
+    pub kind: DecalKind,
+    pub frames_remaining: usize,
+}
+
+impl Decal {
+    pub fn new(pos: Point, kind: DecalKind) -> Self {
+        Self {pos, kind, frames_remaining: kind.frames()}
+    }
+
+    /// Returns how visible this decal is from 0 (not at all) to 255 (completely opaque). Decals
+    /// gradually fade out over their lifetime.
+    pub fn alpha(&self) -> u8 {
+        (self.frames_remaining * 255 / self.kind.frames()) as u8
+    }
+}
+
+/// Resource that holds the decals on the floor of the current level, oldest first
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DecalBuffer {
+    decals: VecDeque<Decal>,
+}
+
+impl DecalBuffer {
+    /// Adds a decal, removing the oldest decal if the buffer is already full
+    pub fn push(&mut self, decal: Decal) {
+        if self.decals.len() >= DECAL_CAPACITY {
+            self.decals.pop_front();
+        }
+        self.decals.push_back(decal);
+    }
+
+    /// Advances every decal by the given number of frames and removes any that have faded away
+    pub fn step(&mut self, frames: usize) {
+        for decal in &mut self.decals {
+            decal.frames_remaining = decal.frames_remaining.saturating_sub(frames);
+        }
+        self.decals.retain(|decal| decal.frames_remaining > 0);
+    }
+
+    /// Returns the decals in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item=&Decal> {
+        self.decals.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+}
+
+/// The number of tiles stored in each word of ExploredTiles
+const EXPLORED_WORD_BITS: usize = 64;
+
+/// Resource that represents the tiles of the current level that the player has explored. Explored
+/// tiles remain on the map (dimmed) even after they are no longer visible.
+///
+/// Each level has its own world, so the explored tiles of a level are kept while the player is on
+/// other levels. Stored as a bitset with one bit per tile of the grid (row-major).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExploredTiles {
+    size: GridSize,
+    bits: Vec<u64>,
+}
+
+impl ExploredTiles {
+    /// Creates a set with no explored tiles for a grid of the given size
+    pub fn new(size: GridSize) -> Self {
+        let tiles = size.rows * size.cols;
+        Self {
+            size,
+            bits: vec![0; tiles.div_ceil(EXPLORED_WORD_BITS)],
+        }
+    }
+
+    /// Returns the index of the bit for the given tile or None if the tile is not on the grid
+    fn index(&self, TilePos {row, col}: TilePos) -> Option<usize> {
+        if row < self.size.rows && col < self.size.cols {
+            Some(row * self.size.cols + col)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the given tile has been explored
+    pub fn contains(&self, pos: TilePos) -> bool {
+        match self.index(pos) {
+            Some(index) => self.bits[index / EXPLORED_WORD_BITS] & (1 << (index % EXPLORED_WORD_BITS)) != 0,
+            None => false,
+        }
+    }
+
+    /// Marks all of the given tiles as explored
+    pub fn explore(&mut self, tiles: impl IntoIterator<Item=TilePos>) {
+        for pos in tiles {
+            let index = self.index(pos).expect("bug: explored a tile that was not on the grid");
+            self.bits[index / EXPLORED_WORD_BITS] |= 1 << (index % EXPLORED_WORD_BITS);
+        }
+    }
+
+    /// Returns true if any of the floor tiles of the given room have been explored
+    pub fn is_room_explored(&self, map: &FloorMap, room_id: RoomId) -> bool {
+        map.room(room_id).boundary().tile_positions()
+            .any(|pos| map.grid().get(pos).is_room_floor(room_id) && self.contains(pos))
+    }
+
+    /// Returns up to `nrooms` rooms that have not been explored yet, sorted from nearest to
+    /// farthest from the given position. Distance is measured to the center tile of each room.
+    /// Rooms at the same distance are ordered by their room ID so the result is deterministic.
+    pub fn nearest_unexplored_rooms(&self, map: &FloorMap, pos: TilePos, nrooms: usize) -> Vec<RoomId> {
+        let mut rooms: Vec<_> = map.rooms()
+            .filter(|&(id, _)| !self.is_room_explored(map, id))
+            .map(|(id, room)| {
+                let (drow, dcol) = room.boundary().center_tile().difference(pos);
+                (id, drow * drow + dcol * dcol)
+            })
+            .collect();
+        // Stable sort to preserve the room ID order for ties
+        rooms.sort_by_key(|&(_, distance)| distance);
+
+        rooms.into_iter().take(nrooms).map(|(id, _)| id).collect()
+    }
+
+    /// Marks every tile of the given rooms (including their walls) as explored
+    pub fn reveal_rooms(&mut self, map: &FloorMap, rooms: &[RoomId]) {
+        for &room_id in rooms {
+            self.explore(map.room_tiles_with_walls(room_id));
+        }
+    }
+}
+
+/// Resource that maps the stable uids of the rooms and staircases of a level to the IDs and
+/// entities that they correspond to in the current game. The uids are the same every time a level
+/// is generated from the same map key, so they can be used to refer to rooms and staircases from
+/// outside of the game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelUids {
+    /// The level these uids belong to (starting at 1)
+    level: usize,
+    room_uids: HashMap<RoomId, RoomUid>,
+    rooms: HashMap<RoomUid, RoomId>,
+    stairs_uids: HashMap<Entity, StairsUid>,
+    stairs: HashMap<StairsUid, Entity>,
+}
+
+impl LevelUids {
+    /// Computes the uid of every room on the map and of each of the given staircases
+    pub fn new(level: usize, map: &FloorMap, stairs: impl IntoIterator<Item=(Entity, StairsUid)>) -> Self {
+        let room_uids: HashMap<_, _> = map.rooms()
+            .map(|(room_id, room)| (room_id, RoomUid::new(level, room)))
+            .collect();
+        let rooms: HashMap<_, _> = room_uids.iter().map(|(&id, &uid)| (uid, id)).collect();
+        assert_eq!(rooms.len(), room_uids.len(), "bug: two rooms on level {} have the same uid", level);
+
+        let stairs_uids: HashMap<_, _> = stairs.into_iter().collect();
+        let stairs: HashMap<_, _> = stairs_uids.iter().map(|(&entity, &uid)| (uid, entity)).collect();
+        assert_eq!(stairs.len(), stairs_uids.len(), "bug: two staircases on level {} have the same uid", level);
+
+        Self {level, room_uids, rooms, stairs_uids, stairs}
+    }
+
+    /// The level that these uids belong to (starting at 1)
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Returns the uid of the given room
+    pub fn room_uid(&self, room_id: RoomId) -> RoomUid {
+        *self.room_uids.get(&room_id).expect("bug: room was not on the map of this level")
+    }
+
+    /// Returns the room with the given uid or None if that room is not on this level
+    pub fn room(&self, uid: RoomUid) -> Option<RoomId> {
+        self.rooms.get(&uid).cloned()
+    }
+
+    /// Returns the uid of the given staircase or None if the entity is not a staircase
+    pub fn stairs_uid(&self, entity: Entity) -> Option<StairsUid> {
+        self.stairs_uids.get(&entity).cloned()
+    }
+
+    /// Returns the staircase with the given uid or None if it is not on this level
+    pub fn stairs(&self, uid: StairsUid) -> Option<Entity> {
+        self.stairs.get(&uid).cloned()
+    }
+}
+
+/// Resource that decides which enemies are close enough to the player to be simulated. Enemies
+/// outside of the bubble are dormant: they do not think, move, or animate until the player comes
+/// back within range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActivityBubble {
+    /// The distance (in tiles) from the player within which enemies are active
+    pub radius: u32,
+    /// The distance (in tiles) from the player within which enemies that are chasing the player
+    /// stay active. Larger than `radius` so that an enemy does not freeze in the middle of a chase
+    /// just because the player is outrunning it.
+    pub chase_radius: u32,
+}
+
+impl Default for ActivityBubble {
+    fn default() -> Self {
+        Self {
+            radius: 24,
+            chase_radius: 36,
+        }
+    }
+}
+
+impl ActivityBubble {
+    /// A bubble that covers the entire level, so no enemy is ever dormant
+    pub fn unlimited() -> Self {
+        Self {
+            radius: u32::MAX,
+            chase_radius: u32::MAX,
+        }
+    }
+
+    /// Returns true if an enemy at the given position should be active while the player is at the
+    /// given position
+    pub fn is_active(self, enemy: Point, player: Point, tile_size: u32, chasing: bool) -> bool {
+        let radius = if chasing { self.chase_radius } else { self.radius };
+        let radius = radius as i64 * tile_size as i64;
+        let diff = enemy - player;
+        let (x, y) = (diff.x() as i64, diff.y() as i64);
+        // Saturating so that an unlimited radius does not overflow
+        x * x + y * y <= radius.saturating_mul(radius)
+    }
+}
+
+/// Resource that groups entities by the tiles that their bounding boxes overlap. Used to find
+/// the entities in a region without looking at every entity in the world.
+///
+/// Rebuilt by the physics system at the end of every frame, so it is only up to date for the
+/// systems that run after physics.
+#[derive(Debug, Default)]
+pub struct SpatialGrid {
+    /// The width and height (in px) of each bucket
+    bucket_size: u32,
+    buckets: HashMap<TilePos, Vec<Entity>>,
+}
+
+impl SpatialGrid {
+    /// Removes every entity from the grid and changes the size (in px) of each bucket
+    pub fn clear(&mut self, bucket_size: u32) {
+        assert!(bucket_size > 0, "bug: buckets must have a non-zero size");
+        self.bucket_size = bucket_size;
+        // Keeping the buckets allocated means that rebuilding every frame rarely allocates
+        for bucket in self.buckets.values_mut() {
+            bucket.clear();
+        }
+    }
+
+    /// Adds the entity with the given boundary to every bucket that the boundary overlaps
+    pub fn insert(&mut self, entity: Entity, bounds: Rect) {
+        for pos in self.buckets_within(bounds) {
+            self.buckets.entry(pos).or_default().push(entity);
+        }
+    }
+
+    /// Returns every entity that was inserted with a boundary that shares a bucket with the given
+    /// region. This may include entities that do not actually intersect with the region.
+    ///
+    /// Entities are returned in the same order as they would be by a join over the world.
+    pub fn query(&self, region: Rect) -> Vec<Entity> {
+        if self.buckets.is_empty() {
+            return Vec::new();
+        }
+
+        let mut entities: Vec<_> = self.buckets_within(region)
+            .filter_map(|pos| self.buckets.get(&pos))
+            .flatten()
+            .cloned()
+            .collect();
+        // Entities that span several buckets will show up more than once
+        entities.sort_unstable_by_key(|entity| entity.id());
+        entities.dedup();
+        entities
+    }
+
+    /// Returns the positions of the buckets that overlap the given region. Anything at negative
+    /// coordinates goes in the buckets along the top and left edges.
+    fn buckets_within(&self, region: Rect) -> impl Iterator<Item=TilePos> {
+        let bucket_size = self.bucket_size as i32;
+        let bucket = move |coord: i32| (coord.max(0) / bucket_size) as usize;
+        // The right and bottom edges of a rectangle are exclusive
+        let (start_row, end_row) = (bucket(region.top()), bucket(region.bottom() - 1));
+        let (start_col, end_col) = (bucket(region.left()), bucket(region.right() - 1));
+
+        (start_row..=end_row).flat_map(move |row| (start_col..=end_col).map(move |col| TilePos {row, col}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder};
+
+    use crate::map::{GridSize, TileRect};
+    use crate::test_helpers::rooms_side_by_side;
+
+    /// Creates a map with a row of rooms, each with the given number of columns
+    fn row_of_rooms(room_cols: &[usize]) -> FloorMap {
+        rooms_side_by_side(5, room_cols, false, 16).0
+    }
+
+    #[test]
+    fn floor_stats_folded_into_totals() {
+        let mut stats = RunStats::default();
+        stats.floor.frames = 100;
+        stats.floor.enemies_killed = 3;
+        stats.floor.damage_taken = 12;
+
+        let first = stats.finish_floor();
+        assert_eq!(first, FloorStats {frames: 100, enemies_killed: 3, damage_taken: 12, damage_dealt: 0, items_found: 0, secrets_found: 0});
+        // The next floor starts from nothing
+        assert_eq!(stats.floor, FloorStats::default());
+        assert_eq!(stats.totals, first);
+
+        stats.floor.frames = 50;
+        stats.floor.items_found = 2;
+        stats.floor.secrets_found = 1;
+        let second = stats.finish_floor();
+        assert_eq!(second, FloorStats {frames: 50, enemies_killed: 0, damage_taken: 0, damage_dealt: 0, items_found: 2, secrets_found: 1});
+        assert_eq!(stats.totals, FloorStats {frames: 150, enemies_killed: 3, damage_taken: 12, damage_dealt: 0, items_found: 2, secrets_found: 1});
+    }
+
+    #[test]
+    fn play_time_formatted_as_minutes_and_seconds() {
+        assert_eq!(format_play_time(0, 30), "0:00");
+        // Partial seconds are rounded down
+        assert_eq!(format_play_time(29, 30), "0:00");
+        assert_eq!(format_play_time(30 * 65 + 15, 30), "1:05");
+        assert_eq!(format_play_time(60 * 61, 60), "1:01");
+        assert_eq!(format_play_time(30 * 60 * 75, 30), "75:00");
+
+        let mut clock = PlayClock::default();
+        clock.advance(30 * 59);
+        clock.advance(30);
+        assert_eq!((clock.frames(), clock.format(30)), (30 * 60, "1:00".to_string()));
+    }
+
+    #[test]
+    fn split_time_formatted_with_milliseconds() {
+        assert_eq!(format_split_time(0, 30), "0:00.000");
+        assert_eq!(format_split_time(1, 30), "0:00.033");
+        assert_eq!(format_split_time(30 * 65 + 15, 30), "1:05.500");
+        assert_eq!(format_split_time(30 * 60 * 75 + 29, 30), "75:00.966");
+
+        let mut clock = PlayClock::default();
+        clock.advance(45);
+        assert_eq!(clock.format_precise(30), "0:01.500");
+    }
+
+    #[test]
+    fn escape_begins_only_once() {
+        let mut phase = RunPhase::default();
+        assert!(!phase.is_escaping());
+
+        assert!(phase.begin_escape());
+        assert!(phase.is_escaping());
+        // There is no going back to descending once the escape has started
+        assert!(!phase.begin_escape());
+        assert_eq!(phase, RunPhase::Escaping);
+    }
+
+    #[test]
+    fn room_changes_once_new_room_is_stable() {
+        let map = row_of_rooms(&[4, 4]);
+        let rooms: Vec<_> = map.rooms().map(|(id, _)| id).collect();
+        let (room1, room2) = (rooms[0], rooms[1]);
+        let mut tracker = RoomTracker::default();
+
+        // The first room is entered right away
+        match tracker.update(Some(room1), 1) {
+            Some(Event::RoomChanged {from: None, to}) => assert_eq!(to, room1),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(tracker.update(Some(room1), 1).is_none());
+
+        // Standing in a doorway and stepping back and forth between the rooms changes nothing
+        for _ in 0..20 {
+            assert!(tracker.update(Some(room2), 2).is_none());
+            assert!(tracker.update(None, 1).is_none());
+            assert!(tracker.update(Some(room2), RoomTracker::STABLE_FRAMES - 1).is_none());
+            assert!(tracker.update(Some(room1), 1).is_none());
+        }
+        assert_eq!(tracker.current(), Some(room1));
+
+        for _ in 1..RoomTracker::STABLE_FRAMES {
+            assert!(tracker.update(Some(room2), 1).is_none());
+        }
+        match tracker.update(Some(room2), 1) {
+            Some(Event::RoomChanged {from, to}) => assert_eq!((from, to), (Some(room1), room2)),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert_eq!(tracker.current(), Some(room2));
+    }
+
+    #[test]
+    fn game_state_transition_table() {
+        use self::GameState::*;
+        let merchant = World::new().create_entity().build();
+        let shop = Shop {merchant};
+        let level_transition = LevelTransition {to: LevelDirection::Next, id: 0};
+        let states = [Playing, Paused, shop, level_transition, Victory, Defeat];
+
+        let legal = [
+            (Playing, vec![Paused, shop, level_transition, Victory, Defeat]),
+            // Paused from Playing, so only Playing can be resumed
+            (Paused, vec![Playing]),
+            (shop, vec![Playing, Paused]),
+            (level_transition, vec![Playing, Paused]),
+            (Victory, vec![]),
+            (Defeat, vec![]),
+        ];
+        for (from, allowed) in &legal {
+            for &to in &states {
+                let mut machine = GameStateMachine::default();
+                if *from != Playing {
+                    machine.transition(*from).unwrap();
+                }
+                let expected = allowed.contains(&to);
+                assert_eq!(machine.can_transition(to), expected, "{:?} to {:?}", from, to);
+                match machine.transition(to) {
+                    Ok(prev) => {
+                        assert!(expected, "{:?} to {:?} should be illegal", from, to);
+                        assert_eq!(prev, *from);
+                        assert_eq!((machine.current(), machine.previous()), (to, Some(*from)));
+                    },
+                    Err(err) => {
+                        assert!(!expected, "{:?} to {:?} should be legal", from, to);
+                        assert_eq!(err, IllegalTransition {from: *from, to});
+                        assert_eq!(machine.current(), *from);
+                    },
+                }
+            }
+        }
+
+        let err = IllegalTransition {from: Victory, to: Paused};
+        assert_eq!(err.to_string(), "cannot change the game state from Victory to Paused");
+    }
+
+    #[test]
+    fn resume_returns_to_paused_state() {
+        let merchant = World::new().create_entity().build();
+        let mut machine = GameStateMachine::default();
+        assert!(machine.resume().is_err());
+
+        machine.transition(GameState::Shop {merchant}).unwrap();
+        machine.transition(GameState::Paused).unwrap();
+        // Only the state that was paused can be resumed
+        assert!(machine.transition(GameState::Playing).is_err());
+        assert_eq!(machine.resume(), Ok(GameState::Paused));
+        assert_eq!(machine.current(), GameState::Shop {merchant});
+        assert!(machine.resume().is_err());
+    }
+
+    #[test]
+    fn reset_clears_every_run_resource() {
+        let mut world = World::new();
+        reset_run_resources(&mut world);
+        let entity = world.create_entity().build();
+
+        // Leave something behind in every resource, as if a run had just ended
+        world.add_resource(FramesElapsed(2));
+        world.write_resource::<GameStateMachine>().transition(GameState::Paused).unwrap();
+        world.write_resource::<EventQueue>().0.push(Event::KeyDown(Key::A));
+        world.write_resource::<ActionQueue>().0.insert(entity, Vec::new());
+        world.write_resource::<SoundQueue>().0.push(SoundEffect::PlayerDeath);
+        world.write_resource::<NotificationQueue>().push("The ground trembles");
+        world.write_resource::<GameEvents>().0.push(GameEvent::EnemyKilled);
+        world.write_resource::<RunStats>().rescues = 2;
+        world.write_resource::<RunStats>().totals.damage_dealt = 40;
+        world.write_resource::<RunPhase>().begin_escape();
+        world.write_resource::<PlayClock>().advance(90);
+        world.write_resource::<ScreenShake>().start(3, 10);
+        world.write_resource::<DecalBuffer>().push(Decal::new(Point::new(8, 8), DecalKind::Blood));
+        *world.write_resource::<TutorialState>() = TutorialState::start();
+
+        reset_run_resources(&mut world);
+        assert_eq!(world.read_resource::<FramesElapsed>().0, 1);
+        assert_eq!(*world.read_resource::<GameStateMachine>(), GameStateMachine::default());
+        assert!(world.read_resource::<EventQueue>().0.is_empty());
+        assert!(world.read_resource::<ActionQueue>().0.is_empty());
+        assert!(world.read_resource::<SoundQueue>().0.is_empty());
+        assert!(world.read_resource::<NotificationQueue>().0.is_empty());
+        assert!(world.read_resource::<GameEvents>().0.is_empty());
+        assert_eq!(*world.read_resource::<RunStats>(), RunStats::default());
+        assert_eq!(*world.read_resource::<RunPhase>(), RunPhase::Descending);
+        assert_eq!(world.read_resource::<PlayClock>().frames(), 0);
+        assert_eq!(*world.read_resource::<ScreenShake>(), ScreenShake::default());
+        assert!(world.read_resource::<DecalBuffer>().is_empty());
+        assert!(world.read_resource::<TutorialState>().is_finished());
+    }
+
+    #[test]
+    fn tutorial_steps_completed_in_order() {
+        let mut tutorial = TutorialState::start();
+        assert_eq!(tutorial.current(), Some(TutorialStep::Move));
+        // Only shown once the player is somewhere the tutorial applies
+        assert_eq!(tutorial.visible_step(), None);
+        tutorial.set_visible(true);
+        assert_eq!(tutorial.visible_step(), Some(TutorialStep::Move));
+
+        // Performing a later action early does not skip ahead
+        tutorial.complete(TutorialStep::Attack);
+        assert_eq!(tutorial.current(), Some(TutorialStep::Move));
+
+        for &step in &TutorialStep::ALL {
+            tutorial.complete(step);
+        }
+        assert!(tutorial.is_finished());
+        assert_eq!(tutorial.visible_step(), None);
+
+        let mut tutorial = TutorialState::start();
+        tutorial.skip();
+        assert!(tutorial.is_finished());
+        assert!(TutorialState::default().is_finished());
+    }
+
+    #[test]
+    fn screen_shake_decays_to_zero() {
+        let mut shake = ScreenShake::default();
+        assert_eq!(shake.offset(), Point::new(0, 0));
+
+        shake.start(6, 10);
+        let mut amplitudes = Vec::new();
+        for _ in 0..10 {
+            let offset = shake.offset();
+            let amplitude = shake.current_amplitude();
+            assert!(offset.x().abs() <= amplitude as i32 && offset.y().abs() <= amplitude as i32);
+            amplitudes.push(amplitude);
+            shake.step(1);
+        }
+        assert_eq!(amplitudes, &[6, 6, 5, 5, 4, 3, 3, 2, 2, 1]);
+        assert_eq!(shake.offset(), Point::new(0, 0));
+        // Stepping past the end never starts shaking again
+        shake.step(5);
+        assert_eq!(shake.offset(), Point::new(0, 0));
+
+        // A weaker shake does not interrupt a stronger one
+        shake.start(6, 10);
+        shake.start(2, 30);
+        assert_eq!(shake.current_amplitude(), 6);
+        shake.step(8);
+        shake.start(2, 30);
+        assert_eq!(shake.current_amplitude(), 2);
+        shake.stop();
+        assert_eq!(shake, ScreenShake::default());
+    }
+
+    #[test]
+    fn decals_fade_and_expire() {
+        let mut decals = DecalBuffer::default();
+        let first = Decal::new(Point::new(0, 0), DecalKind::Blood);
+        assert_eq!(first.alpha(), 255);
+        decals.push(first);
+        decals.step(100);
+        decals.push(Decal::new(Point::new(16, 0), DecalKind::Blood));
+        let alphas: Vec<_> = decals.iter().map(Decal::alpha).collect();
+        assert!(alphas[0] < alphas[1]);
+
+        // The first decal expires exactly at the end of its lifetime
+        decals.step(DecalKind::Blood.frames() - 101);
+        assert_eq!(decals.len(), 2);
+        decals.step(1);
+        assert_eq!(decals.iter().map(|decal| decal.pos).collect::<Vec<_>>(), &[Point::new(16, 0)]);
+        decals.step(100);
+        assert!(decals.is_empty());
+    }
+
+    #[test]
+    fn oldest_decal_evicted_when_full() {
+        let mut decals = DecalBuffer::default();
+        for i in 0..DECAL_CAPACITY + 2 {
+            decals.push(Decal::new(Point::new(i as i32, 0), DecalKind::Blood));
+        }
+        assert_eq!(decals.len(), DECAL_CAPACITY);
+        let xs: Vec<_> = decals.iter().map(|decal| decal.pos.x() as usize).collect();
+        assert_eq!(xs, (2..DECAL_CAPACITY + 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn nearest_unexplored_rooms() {
+        let map = row_of_rooms(&[5, 5, 5, 5, 5]);
+        let rooms: Vec<_> = map.rooms().map(|(id, _)| id).collect();
+        // Standing in the middle room
+        let pos = map.room(rooms[2]).boundary().center_tile();
+
+        let mut explored = ExploredTiles::new(map.grid().dimensions());
+        explored.explore(map.room_tiles_with_walls(rooms[2]));
+
+        // Rooms on either side are the same distance away, so the room ID decides
+        assert_eq!(explored.nearest_unexplored_rooms(&map, pos, 2), vec![rooms[1], rooms[3]]);
+        assert_eq!(explored.nearest_unexplored_rooms(&map, pos, 3), vec![rooms[1], rooms[3], rooms[0]]);
+
+        // Revealed rooms are no longer unexplored
+        explored.reveal_rooms(&map, &[rooms[1], rooms[3]]);
+        assert_eq!(explored.nearest_unexplored_rooms(&map, pos, 10), vec![rooms[0], rooms[4]]);
+
+        explored.reveal_rooms(&map, &[rooms[0], rooms[4]]);
+        assert!(explored.nearest_unexplored_rooms(&map, pos, 10).is_empty());
+    }
+
+    #[test]
+    fn explored_tiles_cover_grid() {
+        // Not a multiple of the word size so the last word is only partially used
+        let size = GridSize {rows: 7, cols: 11};
+        let mut explored = ExploredTiles::new(size);
+        let corners = [
+            TilePos {row: 0, col: 0},
+            TilePos {row: 0, col: 10},
+            TilePos {row: 6, col: 0},
+            TilePos {row: 6, col: 10},
+        ];
+        explored.explore(corners.iter().cloned());
+
+        let grid = TileRect::new(TilePos {row: 0, col: 0}, size);
+        for pos in grid.tile_positions() {
+            assert_eq!(explored.contains(pos), corners.contains(&pos));
+        }
+        // Tiles off of the grid are never explored (and do not wrap onto the next row)
+        assert!(!explored.contains(TilePos {row: 1, col: 11}));
+        assert!(!explored.contains(TilePos {row: 7, col: 0}));
+    }
+
+    #[test]
+    fn spatial_grid_finds_overlapping_entities() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..4).map(|_| world.create_entity().build()).collect();
+
+        let mut grid = SpatialGrid::default();
+        // Nothing has been inserted yet
+        assert!(grid.query(Rect::new(0, 0, 100, 100)).is_empty());
+
+        grid.clear(16);
+        // Spans four buckets
+        grid.insert(entities[2], Rect::new(8, 8, 16, 16));
+        grid.insert(entities[0], Rect::new(40, 8, 4, 4));
+        // Partially off of the top left of the map
+        grid.insert(entities[1], Rect::new(-4, -4, 8, 8));
+        grid.insert(entities[3], Rect::new(16, 16, 16, 16));
+
+        // Sorted the same way as a join and never repeated
+        assert_eq!(grid.query(Rect::new(0, 0, 48, 48)), entities);
+        assert_eq!(grid.query(Rect::new(20, 20, 2, 2)), vec![entities[2], entities[3]]);
+        assert_eq!(grid.query(Rect::new(-10, -10, 4, 4)), vec![entities[1], entities[2]]);
+        // Edges are exclusive, so this region only covers the first bucket
+        assert_eq!(grid.query(Rect::new(0, 0, 16, 16)), vec![entities[1], entities[2]]);
+        assert!(grid.query(Rect::new(100, 100, 10, 10)).is_empty());
+
+        grid.clear(16);
+        assert!(grid.query(Rect::new(0, 0, 48, 48)).is_empty());
+    }
+
+    #[test]
+    fn activity_bubble_extended_while_chasing() {
+        let bubble = ActivityBubble {radius: 3, chase_radius: 5};
+        let player = Point::new(100, 100);
+
+        assert!(bubble.is_active(Point::new(100 + 3 * 16, 100), player, 16, false));
+        assert!(!bubble.is_active(Point::new(100 + 4 * 16, 100), player, 16, false));
+        // Chasing enemies stay active farther away
+        assert!(bubble.is_active(Point::new(100 + 4 * 16, 100), player, 16, true));
+        assert!(!bubble.is_active(Point::new(100 + 4 * 16, 100 + 4 * 16), player, 16, true));
+
+        assert!(ActivityBubble::unlimited().is_active(Point::new(-50000, 90000), player, 64, false));
+    }
+
+    #[test]
+    fn reveal_rooms_includes_walls() {
+        let map = row_of_rooms(&[5, 6]);
+        let rooms: Vec<_> = map.rooms().map(|(id, _)| id).collect();
+
+        let mut explored = ExploredTiles::new(map.grid().dimensions());
+        explored.reveal_rooms(&map, &[rooms[1]]);
+
+        let boundary = *map.room(rooms[1]).boundary();
+        assert!(boundary.tile_positions().all(|pos| explored.contains(pos)));
+        // The other room is left alone
+        assert!(!explored.is_room_explored(&map, rooms[0]));
+        assert!(!explored.contains(map.room(rooms[0]).boundary().top_left()));
+    }
+}
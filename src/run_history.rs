@@ -0,0 +1,201 @@
+//! A record of every run of the game that ended in victory or defeat, kept between runs of the game
+
+use std::fs::{self, OpenOptions};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::generator::MapKey;
+use crate::upgrades::RunOutcome;
+
+/// The number of seconds in a day
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The results of a single run of the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRecord {
+    /// The key of the map that was played
+    pub map_key: MapKey,
+    pub outcome: RunOutcome,
+    /// The deepest floor reached during the run (starting at 1)
+    pub floor: usize,
+    pub enemies_killed: usize,
+    /// The number of frames of the game that were played
+    pub frames: usize,
+    /// The amount of real time that the run took, including any lag or time spent in menus
+    pub duration: Duration,
+    /// When the run ended, in seconds since the Unix epoch (UTC)
+    pub finished_at: u64,
+}
+
+impl RunRecord {
+    /// Parses a record from a single line in the format written by `Display`: the map key, the
+    /// outcome, the floor, the enemies killed, the frames, the duration in milliseconds, and the
+    /// time the run finished, separated by spaces.
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+            format!("invalid line in run history: `{}`", line));
+
+        let parts: Vec<_> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            &[map_key, outcome, floor, enemies_killed, frames, duration, finished_at] => Ok(Self {
+                map_key: map_key.parse().map_err(|_| invalid())?,
+                outcome: RunOutcome::from_id(outcome).ok_or_else(invalid)?,
+                floor: floor.parse().map_err(|_| invalid())?,
+                enemies_killed: enemies_killed.parse().map_err(|_| invalid())?,
+                frames: frames.parse().map_err(|_| invalid())?,
+                duration: Duration::from_millis(duration.parse().map_err(|_| invalid())?),
+                finished_at: finished_at.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Returns the date (YYYY-MM-DD, in UTC) that the run ended on
+    pub fn date(&self) -> String {
+        // Converts days since the epoch to a date in the proleptic Gregorian calendar. Based on
+        // the algorithm from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = self.finished_at / SECONDS_PER_DAY + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl fmt::Display for RunRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {} {} {} {}", self.map_key, self.outcome.id(), self.floor,
+            self.enemies_killed, self.frames, self.duration.as_millis(), self.finished_at)
+    }
+}
+
+/// Every run recorded so far, oldest first
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunHistory {
+    runs: Vec<RunRecord>,
+}
+
+impl RunHistory {
+    /// Loads the history from the given file. An empty history is returned if the file does not
+    /// exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Parses a history with one record per line. Unlike the other files of the game, a line that
+    /// cannot be parsed is skipped with a warning so that a single bad line (e.g. from a crash
+    /// while writing) does not lose the entire history.
+    pub fn parse(contents: &str) -> Self {
+        let runs = contents.lines().map(str::trim).filter(|line| !line.is_empty())
+            .filter_map(|line| match RunRecord::parse(line) {
+                Ok(record) => Some(record),
+                Err(err) => {
+                    warn!("Skipping run: {}", err);
+                    None
+                },
+            })
+            .collect();
+        Self {runs}
+    }
+
+    /// Adds the given record to the end of the given file, creating the file if it does not exist.
+    /// The file is only ever appended to, so games running at the same time do not overwrite each
+    /// other's records.
+    pub fn append<P: AsRef<Path>>(path: P, record: &RunRecord) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Written all at once so that the line is not interleaved with a line from another game
+        file.write_all(format!("{}\n", record).as_bytes())
+    }
+
+    /// Adds a record to the end of this history
+    pub fn push(&mut self, record: RunRecord) {
+        self.runs.push(record);
+    }
+
+    /// Returns up to the given number of the most recent runs, most recent first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item=&RunRecord> {
+        self.runs.iter().rev().take(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    use rand::random;
+
+    fn record(floor: usize) -> RunRecord {
+        RunRecord {
+            map_key: random(),
+            outcome: RunOutcome::Death,
+            floor,
+            enemies_killed: 7,
+            frames: 30 * 95,
+            duration: Duration::from_millis(97_250),
+            finished_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn record_round_trip() {
+        for &outcome in &RunOutcome::ALL {
+            let record = RunRecord {outcome, ..record(3)};
+            assert_eq!(RunRecord::parse(&record.to_string()).unwrap(), record);
+        }
+        assert_eq!(record(1).date(), "2023-11-14");
+        assert_eq!(RunRecord {finished_at: 0, ..record(1)}.date(), "1970-01-01");
+        assert_eq!(RunRecord {finished_at: 951_782_400, ..record(1)}.date(), "2000-02-29");
+
+        // Missing and extra fields are both invalid
+        let line = record(2).to_string();
+        assert!(RunRecord::parse(line.rsplit_once(' ').unwrap().0).is_err());
+        assert!(RunRecord::parse(&format!("{} 4", line)).is_err());
+    }
+
+    #[test]
+    fn corrupt_line_skipped() {
+        let (first, last) = (record(1), record(5));
+        let contents = format!("{}\n{} 12 ???\n\n{}\n", first, record(2).map_key, last);
+        let history = RunHistory::parse(&contents);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.recent(10).collect::<Vec<_>>(), &[&last, &first]);
+        assert_eq!(history.recent(1).collect::<Vec<_>>(), &[&last]);
+    }
+
+    #[test]
+    fn append_creates_file() {
+        let path = env::temp_dir().join(format!("caves-run-history-{}.txt", process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(RunHistory::load(&path).unwrap().is_empty());
+
+        let records = [record(1), record(2), record(3)];
+        for record in &records {
+            RunHistory::append(&path, record).unwrap();
+        }
+        let history = RunHistory::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(history.recent(10).cloned().collect::<Vec<_>>(), records.iter().rev().cloned().collect::<Vec<_>>());
+    }
+}
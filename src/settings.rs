@@ -0,0 +1,106 @@
+//! Options chosen by the player that are kept between runs of the game
+
+use std::fs;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::assets::HeroPalette;
+
+/// The options chosen by the player
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// The colors that the player is drawn with
+    pub hero_palette: HeroPalette,
+    /// If true, effects that move the whole screen (e.g. screen shake) are turned off
+    pub reduce_motion: bool,
+    /// If true, the game is only ever scaled up by a whole number so that every pixel of a sprite
+    /// is drawn the same size
+    pub pixel_perfect: bool,
+    /// If true, the prompts that teach the controls at the start of the first level are not shown
+    pub skip_tutorial: bool,
+    /// If true, reaching the treasure does not end the run. The player must instead make it back
+    /// up to the room where they entered the caves.
+    pub escape_ending: bool,
+}
+
+impl Settings {
+    /// Loads the settings from the given file. The default settings are returned if the file
+    /// does not exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves the settings to the given file, replacing anything that was there before
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Parses settings from the format written by `save`: one setting per line followed by its
+    /// value. Settings that are missing keep their default value.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData,
+            format!("invalid line in settings: `{}`", line));
+
+        let mut settings = Self::default();
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("hero_palette"), Some(name), None) => {
+                    settings.hero_palette = HeroPalette::from_name(name).ok_or_else(|| invalid(line))?;
+                },
+                (Some("reduce_motion"), Some(value), None) => {
+                    settings.reduce_motion = value.parse().map_err(|_| invalid(line))?;
+                },
+                (Some("pixel_perfect"), Some(value), None) => {
+                    settings.pixel_perfect = value.parse().map_err(|_| invalid(line))?;
+                },
+                (Some("skip_tutorial"), Some(value), None) => {
+                    settings.skip_tutorial = value.parse().map_err(|_| invalid(line))?;
+                },
+                (Some("escape_ending"), Some(value), None) => {
+                    settings.escape_ending = value.parse().map_err(|_| invalid(line))?;
+                },
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hero_palette {}", self.hero_palette.name())?;
+        writeln!(f, "reduce_motion {}", self.reduce_motion)?;
+        writeln!(f, "pixel_perfect {}", self.pixel_perfect)?;
+        writeln!(f, "skip_tutorial {}", self.skip_tutorial)?;
+        writeln!(f, "escape_ending {}", self.escape_ending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = Settings {hero_palette: HeroPalette::Raven, reduce_motion: true, pixel_perfect: true, skip_tutorial: true, escape_ending: true};
+        let contents = settings.to_string();
+        assert_eq!(contents, "hero_palette raven\nreduce_motion true\npixel_perfect true\nskip_tutorial true\nescape_ending true\n");
+        assert_eq!(Settings::parse(&contents).unwrap(), settings);
+
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        // Files written before a setting existed still load
+        assert!(!Settings::parse("hero_palette raven").unwrap().reduce_motion);
+        assert!(!Settings::parse("hero_palette raven\nreduce_motion true").unwrap().pixel_perfect);
+        assert!(!Settings::parse("pixel_perfect true").unwrap().skip_tutorial);
+        assert!(!Settings::parse("skip_tutorial true").unwrap().escape_ending);
+        for invalid in &["hero_palette", "hero_palette rainbow", "hero_palette ash raven", "volume 3", "reduce_motion yes", "pixel_perfect 1", "skip_tutorial on", "escape_ending maybe"] {
+            assert_eq!(Settings::parse(invalid).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -3,12 +3,52 @@ mod animator;
 mod physics;
 mod interactions;
 mod ai;
+mod fog_of_war;
+mod nearest;
+mod sequential;
+mod watchdog;
+mod tremors;
+mod room_tracking;
+mod projectiles;
+mod floating_texts;
+mod status_effects;
+mod tutorial;
+mod dormancy;
 
 pub use self::shared::*;
 pub use self::animator::*;
 pub use self::physics::*;
 pub use self::interactions::*;
 pub use self::ai::*;
+pub use self::fog_of_war::*;
+pub use self::nearest::*;
+pub use self::sequential::*;
+pub use self::watchdog::*;
+pub use self::tremors::*;
+pub use self::room_tracking::*;
+pub use self::projectiles::*;
+pub use self::floating_texts::*;
+pub use self::status_effects::*;
+pub use self::tutorial::*;
+pub use self::dormancy::*;
 
 mod keyboard;
 pub type Keyboard = SharedSystem<keyboard::Keyboard>;
+
+/// Adds every system that runs on a level to the given builder in the order they should run
+pub fn build_dispatcher<'a, B: DispatchBuilder<'a>>(builder: B, keyboard: Keyboard) -> B {
+    builder
+        .with(keyboard, "Keyboard", &[])
+        .with(Dormancy, "Dormancy", &[])
+        .with(AI, "AI", &["Dormancy"])
+        .with(StatusEffects, "StatusEffects", &[])
+        .with(Physics, "Physics", &["Keyboard", "AI", "StatusEffects"])
+        .with(Projectiles, "Projectiles", &["Physics"])
+        .with(Interactions, "Interactions", &["Physics", "Projectiles"])
+        .with(Tremors, "Tremors", &["Interactions"])
+        .with(RoomTracking, "RoomTracking", &["Physics"])
+        .with(FogOfWar, "FogOfWar", &["Tremors"])
+        .with(Animator, "Animator", &["Interactions"])
+        .with(FloatingTexts, "FloatingTexts", &["Interactions"])
+        .with(Tutorial, "Tutorial", &["Interactions", "RoomTracking"])
+}
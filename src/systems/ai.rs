@@ -1,19 +1,298 @@
-use rand::{Rng, thread_rng};
-use specs::{System, Join, ReadExpect, ReadStorage, WriteStorage, Entities};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
 
-use crate::components::{Movement, BoundingBox, Position, Player, Enemy, EnemyBehaviour, Wait};
-use crate::map::FloorMap;
+use rand::{Rng, seq::SliceRandom};
+use sdl2::rect::{Point, Rect};
+use specs::{System, Join, Write, ReadExpect, ReadStorage, WriteStorage, Entities, Entity};
+
+use crate::components::{
+    Movement,
+    MovementDirection,
+    BoundingBox,
+    Position,
+    Player,
+    Enemy,
+    EnemyBehaviour,
+    Follower,
+    Teleport,
+    Wait,
+    Door,
+    AiState,
+    AlertIndicator,
+    Dormant,
+    ChargeAttack,
+    ChargeState,
+};
+use crate::resources::{FramesElapsed, LevelRng};
+use crate::map::{FloorMap, RoomId, TilePos};
+use crate::assets::scale_speed_to_tile_size;
+use super::JoinedEntities;
+use super::has_line_of_sight;
+use super::physics::COLLISION_THRESHOLD;
+
+/// Followers try to stay within this many tiles of the player
+const FOLLOW_DISTANCE: i32 = 2;
+/// Followers that fall more than this many tiles behind (about the width of the screen) catch up
+/// by teleporting directly to the player
+const CATCH_UP_DISTANCE: i32 = 20;
+/// The speed of a follower in px/frame at NATIVE_TILE_SIZE. Same as the player so followers can
+/// keep up.
+const FOLLOWER_SPEED: f32 = 3.0;
+/// Enemies can see the player from up to this many tiles away
+const SIGHT_RANGE: i32 = 6;
+
+/// What a follower should do in order to keep up with the entity it is following
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowStep {
+    /// Close enough, no need to move
+    Stay,
+    /// Move in the given direction to get closer
+    Move(MovementDirection),
+    /// Too far behind, go directly to the position of the leader
+    CatchUp,
+}
+
+impl FollowStep {
+    /// Decides how a follower at the given position should move to keep up with a leader
+    pub fn toward(follower: Point, leader: Point, tile_size: i32) -> Self {
+        let diff = leader - follower;
+        let distance_sq = diff.x() * diff.x() + diff.y() * diff.y();
+        let tiles_sq = |tiles: i32| (tiles * tile_size) * (tiles * tile_size);
+
+        if distance_sq > tiles_sq(CATCH_UP_DISTANCE) {
+            FollowStep::CatchUp
+        } else if distance_sq > tiles_sq(FOLLOW_DISTANCE) {
+            FollowStep::Move(MovementDirection::between(follower, leader))
+        } else {
+            FollowStep::Stay
+        }
+    }
+}
+
+/// Returns true if an enemy that lives in the given room may wander onto the given tile. Enemies
+/// may walk on the floor of their room and in its doorways, but never in the safe zone or over a
+/// hazard.
+pub fn is_home_tile(map: &FloorMap, home_room: RoomId, pos: TilePos) -> bool {
+    let tile = map.grid().get(pos);
+    let in_room = tile.is_room_floor(home_room) || (tile.is_floor() && map.room(home_room).boundary().is_edge(pos));
+    in_room && !map.is_safe_zone(pos) && !tile.is_hazard()
+}
+
+/// Returns true if an enemy at the given position that moves for the given number of frames would
+/// step into the safe zone. Enemies that are already in it (e.g. knocked in by an attack) may
+/// still move so that they can leave.
+pub fn enters_safe_zone(map: &FloorMap, pos: Point, bounds: BoundingBox, movement: &Movement, frames: usize) -> bool {
+    if !movement.is_moving() {
+        return false;
+    }
+
+    // The furthest that the enemy could move, including any fraction of a pixel left over from
+    // previous frames
+    let (vx, vy) = movement.velocity();
+    let furthest = |v: f32| (v.abs() * frames as f32).ceil().copysign(v) as i32;
+    let next = pos.offset(furthest(vx), furthest(vy));
+
+    !touches_safe_zone(map, bounds.to_rect(pos)) && touches_safe_zone(map, bounds.to_rect(next))
+}
+
+/// Returns true if an enemy at the given position that moves for the given number of frames would
+/// step onto a hazard. Like the player, an enemy is on whichever tile its position is on. Enemies
+/// that are already on a hazard may still move so that they can get off of it.
+pub fn enters_hazard(map: &FloorMap, pos: Point, movement: &Movement, frames: usize) -> bool {
+    if !movement.is_moving() {
+        return false;
+    }
+
+    let (vx, vy) = movement.velocity();
+    let furthest = |v: f32| (v.abs() * frames as f32).ceil().copysign(v) as i32;
+    let next = pos.offset(furthest(vx), furthest(vy));
+    // Moving off of the map is stopped by the walls, not by this
+    let is_hazard = |pos: Point| map.level_boundary().contains_point(pos)
+        && map.grid().get(map.world_to_tile_pos(pos)).is_hazard();
+
+    !is_hazard(pos) && is_hazard(next)
+}
+
+/// Returns true if any part of the given area is over a tile in the safe zone
+fn touches_safe_zone(map: &FloorMap, area: Rect) -> bool {
+    let tile_size = map.tile_size() as i32;
+    let grid = map.grid();
+    // Only the tiles that the area actually overlaps, not the ones that it just touches the edge of
+    let tile_range = |start: i32, end: i32, len: usize| {
+        let first = cmp::max(start, 0) / tile_size;
+        let last = cmp::min((end - 1) / tile_size, len as i32 - 1);
+        first..=last
+    };
+    tile_range(area.top(), area.bottom(), grid.rows_len()).any(|row| {
+        tile_range(area.left(), area.right(), grid.cols_len())
+            .any(|col| map.is_safe_zone(TilePos {row: row as usize, col: col as usize}))
+    })
+}
+
+/// Returns the directions that an enemy on the given tile can wander in without leaving its room
+pub fn home_directions(map: &FloorMap, home_room: RoomId, pos: TilePos) -> Vec<MovementDirection> {
+    let tile_size = map.tile_size() as i32;
+    map.grid().adjacent_positions(pos)
+        .filter(|&adj| is_home_tile(map, home_room, adj))
+        .map(|adj| MovementDirection::between(pos.center(tile_size), adj.center(tile_size)))
+        .collect()
+}
+
+/// Returns the direction of the first step along the shortest path over floor tiles from the
+/// given tile back into the given room. The path only goes through the safe zone or over hazards
+/// if it starts there. None if there is no way back or the tile is already in the room.
+pub fn step_toward_home(map: &FloorMap, home_room: RoomId, start: TilePos) -> Option<MovementDirection> {
+    let grid = map.grid();
+    if is_home_tile(map, home_room, start) {
+        return None;
+    }
+    let starts_in_safe_zone = map.is_safe_zone(start);
+    let starts_on_hazard = grid.get(start).is_hazard();
+    let is_walkable = |pos| {
+        let tile = grid.get(pos);
+        tile.is_floor() && (starts_in_safe_zone || !map.is_safe_zone(pos))
+            && (starts_on_hazard || !tile.is_hazard())
+    };
+
+    first_step(map, start, is_walkable, |pos| is_home_tile(map, home_room, pos))
+}
+
+/// Returns the direction that an enemy at the given position should move in to reach the target.
+/// Enemies head straight for the target unless there are hazards in the way, in which case they
+/// take the shortest path around the hazards instead. None if the target can only be reached by
+/// walking over a hazard.
+pub fn direction_toward(map: &FloorMap, pos: Point, target: Point) -> Option<MovementDirection> {
+    let grid = map.grid();
+    let start = map.world_to_tile_pos(pos);
+    let goal = map.world_to_tile_pos(target);
+    // Any hazard in the area between the enemy and the target could end up in the way. Checking
+    // the whole area (instead of just the next tile) keeps the enemy from going back and forth
+    // between heading straight for the target and going around the hazards.
+    let rows = cmp::min(start.row, goal.row)..=cmp::max(start.row, goal.row);
+    let cols = cmp::min(start.col, goal.col)..=cmp::max(start.col, goal.col);
+    let is_clear = rows.clone().all(|row| cols.clone().all(|col| !grid.get(TilePos {row, col}).is_hazard()));
+    if is_clear {
+        return Some(MovementDirection::between(pos, target));
+    }
+
+    // A target standing on a hazard is only approached as far as the edge of the hazard
+    let is_walkable = |pos| grid.get(pos).is_floor() && !grid.get(pos).is_hazard();
+    first_step(map, start, is_walkable, |pos| pos == goal || grid.adjacent_positions(pos).any(|adj| adj == goal))
+}
+
+/// Returns the direction of the first step along the shortest path over walkable tiles from the
+/// given tile to any goal tile. None if no goal tile can be reached.
+fn first_step(
+    map: &FloorMap,
+    start: TilePos,
+    is_walkable: impl Fn(TilePos) -> bool,
+    is_goal: impl Fn(TilePos) -> bool,
+) -> Option<MovementDirection> {
+    let grid = map.grid();
+    let tile_size = map.tile_size() as i32;
+
+    // The direction of the first step taken to reach each tile
+    let mut first_steps = HashMap::new();
+    let mut open = VecDeque::new();
+    for adj in grid.adjacent_positions(start).filter(|&adj| is_walkable(adj)) {
+        first_steps.insert(adj, MovementDirection::between(start.center(tile_size), adj.center(tile_size)));
+        open.push_back(adj);
+    }
+    while let Some(pos) = open.pop_front() {
+        let step = first_steps[&pos];
+        if is_goal(pos) {
+            return Some(step);
+        }
+        for adj in grid.adjacent_positions(pos) {
+            if adj != start && is_walkable(adj) && !first_steps.contains_key(&adj) {
+                first_steps.insert(adj, step);
+                open.push_back(adj);
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns true if an enemy at the given position can see the player at the given position. The
+/// player can never be seen in the safe zone.
+pub fn enemy_sees(
+    map: &FloorMap,
+    enemy: Point,
+    player: Point,
+    positions: &ReadStorage<'_, Position>,
+    doors: &ReadStorage<'_, Door>,
+) -> bool {
+    let tile_size = map.tile_size() as i32;
+    let diff = player - enemy;
+    let range = SIGHT_RANGE * tile_size;
+    !map.is_safe_zone(map.world_to_tile_pos(player))
+        && diff.x() * diff.x() + diff.y() * diff.y() <= range * range
+        && has_line_of_sight(map.grid(), enemy, player, tile_size, positions, doors)
+}
+
+/// Moves an enemy randomly around its home room
+fn wander<R: Rng>(
+    rng: &mut R,
+    map: &FloorMap,
+    enemy: &Enemy,
+    pos: Option<Point>,
+    movement: &mut Movement,
+) {
+    let home = enemy.home_room.and_then(|home_room| pos.map(|pos| (home_room, pos)));
+    let (home_room, tile) = match home {
+        Some((home_room, pos)) => (home_room, map.world_to_tile_pos(pos)),
+        None => {
+            // favor keeping the movement direction the same
+            if rng.gen_range(0, 10) == 0 {
+                movement.direction = rng.gen();
+            }
+            return;
+        },
+    };
+
+    // Enemies that were knocked out of their room head straight back
+    if !is_home_tile(map, home_room, tile) {
+        match step_toward_home(map, home_room, tile) {
+            Some(direction) => movement.direction = direction,
+            None => movement.speed = 0.0,
+        }
+        return;
+    }
+
+    // favor keeping the movement direction the same
+    if rng.gen_range(0, 10) == 0 {
+        movement.direction = rng.gen();
+    }
+    // Never wander out of the room
+    let directions = home_directions(map, home_room, tile);
+    if !directions.contains(&movement.direction) {
+        match directions.choose(rng) {
+            Some(&direction) => movement.direction = direction,
+            None => movement.speed = 0.0,
+        }
+    }
+}
 
 #[derive(SystemData)]
 pub struct AIData<'a> {
     entities: Entities<'a>,
+    frames: ReadExpect<'a, FramesElapsed>,
     map: ReadExpect<'a, FloorMap>,
+    rng: Write<'a, LevelRng>,
     movements: WriteStorage<'a, Movement>,
     bounding_boxes: ReadStorage<'a, BoundingBox>,
     positions: ReadStorage<'a, Position>,
     players: ReadStorage<'a, Player>,
     enemies: ReadStorage<'a, Enemy>,
+    followers: ReadStorage<'a, Follower>,
+    teleports: WriteStorage<'a, Teleport>,
     waits: ReadStorage<'a, Wait>,
+    dormants: ReadStorage<'a, Dormant>,
+    doors: ReadStorage<'a, Door>,
+    ai_states: WriteStorage<'a, AiState>,
+    alert_indicators: WriteStorage<'a, AlertIndicator>,
+    charges: WriteStorage<'a, ChargeAttack>,
 }
 
 pub struct AI;
@@ -24,27 +303,593 @@ impl<'a> System<'a> for AI {
     fn run(&mut self, data: Self::SystemData) {
         let AIData {
             entities,
+            frames,
             map,
+            mut rng,
             mut movements,
             bounding_boxes,
             positions,
             players,
             enemies,
+            followers,
+            mut teleports,
             waits,
+            dormants,
+            doors,
+            mut ai_states,
+            mut alert_indicators,
+            mut charges,
         } = data;
 
-        let mut rng = thread_rng();
+        let FramesElapsed(frames_elapsed) = *frames;
+        let LevelRng(rng) = &mut *rng;
+
+        let mut hidden_indicators = Vec::new();
+        for (entity, indicator) in (&entities, &mut alert_indicators).join() {
+            indicator.frames_remaining = indicator.frames_remaining.saturating_sub(frames_elapsed);
+            if indicator.frames_remaining == 0 {
+                hidden_indicators.push(entity);
+            }
+        }
+        for entity in hidden_indicators {
+            alert_indicators.remove(entity);
+        }
+
+        let player = (&positions, &players).join().next().map(|(&Position(pos), _)| pos);
+
+        // Dormant enemies keep doing whatever they were doing once they wake up
+        let mut active: Vec<_> = (&entities, &enemies, &movements, !&waits, !&dormants).join()
+            .map(|(entity, ..)| (entity, positions.get(entity).map(|&Position(pos)| pos)))
+            .collect();
+        // Enemies share the random number generator, so they are updated top to bottom and then
+        // left to right to keep the numbers each enemy gets from depending on the order that the
+        // enemies were created in
+        active.sort_unstable_by_key(|&(entity, pos)| (pos.map(|pos| (pos.y(), pos.x())), entity.id()));
+        for (entity, pos) in active {
+            let enemy = enemies.get(entity).expect("bug: enemy was just joined over");
+            let movement = movements.get_mut(entity).expect("bug: enemy was just joined over");
+            movement.speed = enemy.speed;
+            let state = match pos {
+                Some(pos) => {
+                    let sees_player = player.map(|player| enemy_sees(&map, pos, player, &positions, &doors))
+                        .unwrap_or(false);
+                    let is_home = enemy.home_room
+                        .map(|home_room| is_home_tile(&map, home_room, map.world_to_tile_pos(pos)))
+                        .unwrap_or(true);
+                    update_state(&mut ai_states, &mut alert_indicators, entity,
+                        player, sees_player, is_home, frames_elapsed)
+                },
+                None => AiState::Idle,
+            };
 
-        for (entity, enemy, movement, ()) in (&entities, &enemies, &mut movements, !&waits).join() {
-            match enemy.behaviour {
-                EnemyBehaviour::Random => {
-                    // favor keeping the movement direction the same
-                    if rng.gen_range(0, 10) == 0 {
-                        movement.direction = rng.gen();
+            match (state, pos, player) {
+                (AiState::Chasing, Some(pos), Some(player)) => {
+                    match direction_toward(&map, pos, player) {
+                        Some(direction) => movement.direction = direction,
+                        None => movement.speed = 0.0,
+                    }
+                },
+                (AiState::Alert {last_seen, ..}, Some(pos), _) => {
+                    // Searches from where the player was last seen
+                    let diff = last_seen - pos;
+                    if diff.x().abs() + diff.y().abs() <= map.tile_size() as i32 / 2 {
+                        movement.speed = 0.0;
+                    } else {
+                        match direction_toward(&map, pos, last_seen) {
+                            Some(direction) => movement.direction = direction,
+                            None => movement.speed = 0.0,
+                        }
+                    }
+                },
+                (AiState::Returning, Some(pos), _) => {
+                    let tile = map.world_to_tile_pos(pos);
+                    match enemy.home_room.and_then(|home_room| step_toward_home(&map, home_room, tile)) {
+                        Some(direction) => movement.direction = direction,
+                        None => movement.speed = 0.0,
                     }
-                    movement.speed = enemy.speed;
+                },
+                _ => match enemy.behaviour {
+                    EnemyBehaviour::Random => wander(rng, &map, enemy, pos, movement),
+                },
+            }
+
+            // Winding up and charging override everything else that the enemy would be doing
+            if let (Some(charge), Some(pos), Some(&bounds)) = (charges.get_mut(entity), pos, bounding_boxes.get(entity)) {
+                let target = match state {
+                    AiState::Chasing => player,
+                    _ => None,
+                };
+                update_charge(&map, charge, pos, bounds, target, movement, frames_elapsed);
+            }
+
+            // No matter what the enemy is doing, it stops at the edge of the safe zone
+            if let (Some(pos), Some(&bounds)) = (pos, bounding_boxes.get(entity)) {
+                if enters_safe_zone(&map, pos, bounds, movement, frames_elapsed) {
+                    movement.speed = 0.0;
+                }
+            }
+            // It also never walks onto a hazard
+            if let Some(pos) = pos {
+                if enters_hazard(&map, pos, movement, frames_elapsed) {
+                    movement.speed = 0.0;
+                }
+            }
+        }
+
+        let leader = player;
+        if let Some(leader) = leader {
+            let tile_size = map.tile_size() as i32;
+            for (entity, &Position(pos), movement, _) in (&entities, &positions, &mut movements, &followers).join() {
+                match FollowStep::toward(pos, leader, tile_size) {
+                    FollowStep::Stay => movement.speed = 0.0,
+                    FollowStep::Move(direction) => {
+                        movement.direction = direction;
+                        movement.speed = scale_speed_to_tile_size(FOLLOWER_SPEED, map.tile_size());
+                    },
+                    FollowStep::CatchUp => {
+                        movement.speed = 0.0;
+                        teleports.insert(entity, Teleport(leader))
+                            .expect("bug: unable to insert teleport");
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for AI {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.enemies, &data.movements, !&data.waits, !&data.dormants).join().count()
+            + (&data.positions, &data.movements, &data.followers).join().count()
+    }
+}
+
+/// Returns the furthest position (up to the given distance in px) that an entity with the given
+/// bounds can charge to from the given position in a straight line before it runs into a wall
+pub fn charge_target(map: &FloorMap, pos: Point, bounds: BoundingBox, direction: MovementDirection, distance: u32) -> Point {
+    // Same size as the box that physics uses for collisions with walls
+    let start = bounds.shrink(COLLISION_THRESHOLD).to_rect(pos);
+    let mut end = start;
+    end.offset(direction.to_vector().x() * distance as i32, direction.to_vector().y() * distance as i32);
+
+    let mut travel = distance as i32;
+    for (wall, _, _) in map.tiles_within(start.union(end)).filter(|(_, _, tile)| tile.is_wall()) {
+        let wall = Rect::new(wall.x(), wall.y(), map.tile_size(), map.tile_size());
+        let overlaps_x = wall.left() < start.right() && wall.right() > start.left();
+        let overlaps_y = wall.top() < start.bottom() && wall.bottom() > start.top();
+        // The gap between the entity and the wall, if the wall is in its path
+        let gap = match direction {
+            MovementDirection::North if overlaps_x && wall.bottom() <= start.top() => start.top() - wall.bottom(),
+            MovementDirection::South if overlaps_x && wall.top() >= start.bottom() => wall.top() - start.bottom(),
+            MovementDirection::East if overlaps_y && wall.left() >= start.right() => wall.left() - start.right(),
+            MovementDirection::West if overlaps_y && wall.right() <= start.left() => start.left() - wall.right(),
+            _ => continue,
+        };
+        travel = cmp::min(travel, gap);
+    }
+
+    pos + direction.to_vector() * travel
+}
+
+/// Returns the distance (in px) left to go in the given direction to reach the given target
+fn distance_left(direction: MovementDirection, pos: Point, target: Point) -> i32 {
+    let diff = target - pos;
+    match direction {
+        MovementDirection::North => -diff.y(),
+        MovementDirection::South => diff.y(),
+        MovementDirection::East => diff.x(),
+        MovementDirection::West => -diff.x(),
+    }
+}
+
+/// Advances the charge attack of an enemy at the given position by the given number of frames.
+/// While the enemy is winding up or charging, its movement is replaced by the charge. A new
+/// charge is only started toward a player that the enemy is chasing.
+fn update_charge(
+    map: &FloorMap,
+    charge: &mut ChargeAttack,
+    pos: Point,
+    bounds: BoundingBox,
+    player: Option<Point>,
+    movement: &mut Movement,
+    frames_elapsed: usize,
+) {
+    charge.state = match charge.state {
+        ChargeState::Cooldown {frames_remaining} => {
+            let frames_remaining = frames_remaining.saturating_sub(frames_elapsed);
+            match player {
+                Some(player) if frames_remaining == 0 => {
+                    let direction = MovementDirection::between(pos, player);
+                    movement.direction = direction;
+                    movement.speed = 0.0;
+                    ChargeState::WindingUp {direction, frames_remaining: charge.windup}
+                },
+                _ => ChargeState::Cooldown {frames_remaining},
+            }
+        },
+
+        ChargeState::WindingUp {direction, frames_remaining} => {
+            movement.direction = direction;
+            movement.speed = 0.0;
+            match frames_remaining.saturating_sub(frames_elapsed) {
+                0 => ChargeState::Charging {
+                    direction,
+                    target: charge_target(map, pos, bounds, direction, charge.distance),
+                    previous: None,
+                },
+                frames_remaining => ChargeState::WindingUp {direction, frames_remaining},
+            }
+        },
+
+        ChargeState::Charging {direction, target, previous} => {
+            let remaining = distance_left(direction, pos, target);
+            // Reached the end of the charge or ran into something that the target did not account
+            // for (e.g. a closed door or another entity)
+            if remaining <= 0 || previous == Some(pos) {
+                movement.speed = 0.0;
+                ChargeState::Cooldown {frames_remaining: charge.period}
+            } else {
+                movement.direction = direction;
+                movement.sideways = None;
+                // Slows down at the very end so that it stops exactly at the target
+                movement.speed = charge.speed.min(remaining as f32 / frames_elapsed.max(1) as f32);
+                ChargeState::Charging {direction, target, previous: Some(pos)}
+            }
+        },
+    };
+}
+
+/// Moves the enemy to its next state and shows the alert indicator if it just became aware of the
+/// player. Returns the new state.
+fn update_state(
+    ai_states: &mut WriteStorage<'_, AiState>,
+    alert_indicators: &mut WriteStorage<'_, AlertIndicator>,
+    entity: Entity,
+    player: Option<Point>,
+    sees_player: bool,
+    is_home: bool,
+    frames_elapsed: usize,
+) -> AiState {
+    // Enemies start out idle
+    let state = ai_states.entry(entity)
+        .expect("bug: unable to get AI state")
+        .or_insert_with(AiState::default);
+    let next = state.next(player, sees_player, is_home, frames_elapsed);
+    if state.is_unaware() && !next.is_unaware() {
+        alert_indicators.insert(entity, AlertIndicator::default())
+            .expect("bug: unable to insert alert indicator");
+    }
+    *state = next;
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generator::EnemyValues;
+    use crate::map::Tile;
+    use crate::map_sprites::{FloorSprite, WallSprite};
+    use crate::resources::{Event, Key};
+    use crate::test_helpers::{TestWorld, test_animations, walled_room, rooms_side_by_side};
+
+    const TILE_SIZE: u32 = 16;
+    /// The doorway in the wall between the rooms returned by `two_rooms`
+    const DOORWAY: TilePos = TilePos {row: 4, col: 7};
+
+    /// Two rooms that share the wall at column 7, joined by a doorway. Returns the map and the
+    /// IDs of the west and east rooms.
+    fn two_rooms() -> (FloorMap, RoomId, RoomId) {
+        let (mut map, rooms) = rooms_side_by_side(9, &[8, 8], true, TILE_SIZE);
+        map.grid_mut().get_mut(DOORWAY).become_floor(rooms[0], FloorSprite::default());
+        (map, rooms[0], rooms[1])
+    }
+
+    #[test]
+    fn wandering_enemy_stays_in_home_room() {
+        let (map, west, east) = two_rooms();
+        // The doorway belongs to both rooms, but nothing past it does
+        assert!(is_home_tile(&map, west, DOORWAY) && is_home_tile(&map, east, DOORWAY));
+        assert_eq!(home_directions(&map, west, DOORWAY), vec![MovementDirection::West]);
+        assert_eq!(home_directions(&map, east, DOORWAY), vec![MovementDirection::East]);
+
+        let mut test = TestWorld::with_map(map);
+        // Far enough away that the enemy never sees the player, even from the doorway
+        test.spawn_player_at(TilePos {row: 1, col: 13});
+        let enemy = test.spawn_enemy_at(TilePos {row: 4, col: 5}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 3.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+        });
+
+        for _ in 0..600 {
+            test.step(1);
+            let map = test.world.read_resource::<FloorMap>();
+            let tile = map.world_to_tile_pos(test.position(enemy));
+            assert!(is_home_tile(&map, west, tile), "enemy wandered out of its room to {:?}", tile);
+        }
+    }
+
+    #[test]
+    fn enemy_outside_home_room_heads_back() {
+        let (map, west, east) = two_rooms();
+        assert_eq!(step_toward_home(&map, west, TilePos {row: 4, col: 5}), None);
+        // Straight back through the doorway
+        assert_eq!(step_toward_home(&map, west, TilePos {row: 4, col: 9}), Some(MovementDirection::West));
+        // Has to line up with the doorway first
+        assert_eq!(step_toward_home(&map, west, TilePos {row: 1, col: 8}), Some(MovementDirection::South));
+        assert_eq!(step_toward_home(&map, east, TilePos {row: 7, col: 6}), Some(MovementDirection::North));
+    }
+
+    #[test]
+    fn state_transitions() {
+        let player = Point::new(40, 20);
+        let last_seen = Point::new(10, 10);
+        let alert = AiState::Alert {last_seen, frames: AiState::ALERT_FRAMES};
+
+        // Seeing the player always starts a chase
+        for &state in &[AiState::Idle, alert, AiState::Chasing, AiState::Returning] {
+            assert_eq!(state.next(Some(player), true, true, 1), AiState::Chasing);
+        }
+        assert_eq!(AiState::Idle.next(Some(player), false, true, 1), AiState::Idle);
+        // Searches from where the player was when it went out of sight
+        assert_eq!(AiState::Chasing.next(Some(player), false, true, 1),
+            AiState::Alert {last_seen: player, frames: AiState::ALERT_FRAMES});
+        assert_eq!(AiState::Chasing.next(None, false, true, 1), AiState::Returning);
+        // Gives up once it runs out of time
+        assert_eq!(alert.next(Some(player), false, false, 10),
+            AiState::Alert {last_seen, frames: AiState::ALERT_FRAMES - 10});
+        assert_eq!(alert.next(Some(player), false, false, AiState::ALERT_FRAMES), AiState::Returning);
+        // Idle again once back home
+        assert_eq!(AiState::Returning.next(Some(player), false, false, 1), AiState::Returning);
+        assert_eq!(AiState::Returning.next(Some(player), false, true, 1), AiState::Idle);
+    }
+
+    #[test]
+    fn player_hiding_around_corner() {
+        // A wall sticks out of the top of the room between the enemy and the player
+        //
+        // ###############
+        // #......#......#
+        // #......#......#
+        // #......#......#
+        // #....E.#.P....#
+        // #.............#
+        // ...
+        let mut map = walled_room(10, 15, TILE_SIZE);
+        for row in 1..=4 {
+            map.grid_mut().place_tile(TilePos {row, col: 7}, Tile::new_wall(WallSprite::default()));
+        }
+        let mut test = TestWorld::with_map(map);
+        let player = test.spawn_player_at(TilePos {row: 4, col: 9});
+        // An enemy that never moves so that only the player decides what it can see
+        let enemy = test.spawn_enemy_at(TilePos {row: 4, col: 5}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 0.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+        });
+
+        let state = |test: &TestWorld| *test.world.read_storage::<AiState>().get(enemy).unwrap();
+        let has_indicator = |test: &TestWorld| test.world.read_storage::<AlertIndicator>().get(enemy).is_some();
+        let mut states = Vec::new();
+        let mut record = |state: AiState| {
+            let name = match state {
+                AiState::Idle => "idle",
+                AiState::Alert {..} => "alert",
+                AiState::Chasing => "chasing",
+                AiState::Returning => "returning",
+            };
+            if states.last() != Some(&name) {
+                states.push(name);
+            }
+        };
+
+        // Hidden by the wall
+        test.step(1);
+        record(state(&test));
+        assert!(!has_indicator(&test));
+
+        // Walks south until it comes out from behind the wall
+        test.step_with_events(vec![Event::KeyDown(Key::DownArrow)]);
+        record(state(&test));
+        let mut frames_chasing = 0;
+        for _ in 0..20 {
+            test.step(1);
+            record(state(&test));
+            if state(&test) == AiState::Chasing {
+                frames_chasing += 1;
+                if frames_chasing == 1 {
+                    assert!(has_indicator(&test));
                 }
             }
         }
+        assert!(frames_chasing > 0);
+
+        // Goes back behind the wall and stays there
+        let hidden_at = test.position(player).offset(0, -3 * TILE_SIZE as i32);
+        test.step_with_events(vec![Event::KeyUp(Key::DownArrow), Event::KeyDown(Key::UpArrow)]);
+        record(state(&test));
+        while test.position(player).y() > hidden_at.y() {
+            test.step(1);
+            record(state(&test));
+        }
+        test.step_with_events(vec![Event::KeyUp(Key::UpArrow)]);
+        record(state(&test));
+        match state(&test) {
+            // Searching where the player disappeared
+            AiState::Alert {last_seen, ..} => assert!(last_seen.y() > hidden_at.y()),
+            state => panic!("expected the enemy to be alert, found {:?}", state),
+        }
+        for _ in 0..AiState::ALERT_FRAMES + 5 {
+            test.step(1);
+            record(state(&test));
+        }
+        assert!(!has_indicator(&test));
+
+        assert_eq!(states, &["idle", "chasing", "alert", "returning", "idle"]);
+    }
+
+    #[test]
+    fn enemies_never_enter_safe_zone() {
+        let (mut map, west, east) = two_rooms();
+        map.rooms_mut().find(|&(room_id, _)| room_id == west).unwrap().1.become_player_start();
+        // The doorway is on the floor of the start room, so it is part of the safe zone
+        assert!(map.is_safe_zone(DOORWAY));
+        assert!(!is_home_tile(&map, east, DOORWAY));
+        assert_eq!(home_directions(&map, east, TilePos {row: 4, col: 8}), vec![MovementDirection::North,
+            MovementDirection::East, MovementDirection::South]);
+        // An enemy knocked into the safe zone can still find its way out
+        assert_eq!(step_toward_home(&map, east, TilePos {row: 4, col: 5}), Some(MovementDirection::East));
+
+        let mut test = TestWorld::with_map(map);
+        let player = test.spawn_player_at(TilePos {row: 4, col: 5});
+        // Right next to the doorway, close enough to see the player if it weren't in the safe zone
+        let enemy = test.spawn_enemy_at(DOORWAY.adjacent_east(15).unwrap(), EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 3.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+        });
+        let move_player = |test: &mut TestWorld, pos: TilePos| {
+            let pos = test.tile_center(pos);
+            test.world.write_storage::<Position>().insert(player, Position(pos)).unwrap();
+        };
+
+        let mut chased = false;
+        for frame in 0..1000 {
+            match frame {
+                // Comes out just long enough to be seen and then runs back into the safe zone,
+                // leaving the enemy to search right outside the doorway
+                300 => move_player(&mut test, TilePos {row: 4, col: 9}),
+                330 => move_player(&mut test, TilePos {row: 4, col: 6}),
+                _ => {},
+            }
+            test.step(1);
+
+            let state = *test.world.read_storage::<AiState>().get(enemy).unwrap();
+            if frame < 300 {
+                assert_ne!(state, AiState::Chasing, "enemy saw into the safe zone");
+            }
+            chased |= state == AiState::Chasing;
+
+            let map = test.world.read_resource::<FloorMap>();
+            let bounds = *test.world.read_storage::<BoundingBox>().get(enemy).unwrap();
+            let area = bounds.to_rect(test.position(enemy));
+            assert!(!touches_safe_zone(&map, area), "enemy entered the safe zone at {:?} on frame {}", area, frame);
+        }
+        assert!(chased);
+    }
+
+    #[test]
+    fn follower_keeps_distance() {
+        let tile_size = 16;
+        let leader = Point::new(100, 100);
+
+        // Close enough in every direction
+        assert_eq!(FollowStep::toward(leader, leader, tile_size), FollowStep::Stay);
+        assert_eq!(FollowStep::toward(leader.offset(2 * tile_size, 0), leader, tile_size), FollowStep::Stay);
+        assert_eq!(FollowStep::toward(leader.offset(tile_size, -tile_size), leader, tile_size), FollowStep::Stay);
+
+        // Just outside of the follow distance moves toward the leader along the longest axis
+        assert_eq!(FollowStep::toward(leader.offset(2 * tile_size + 1, 0), leader, tile_size),
+            FollowStep::Move(MovementDirection::West));
+        assert_eq!(FollowStep::toward(leader.offset(-tile_size, -4 * tile_size), leader, tile_size),
+            FollowStep::Move(MovementDirection::South));
+        assert_eq!(FollowStep::toward(leader.offset(-5 * tile_size, 2 * tile_size), leader, tile_size),
+            FollowStep::Move(MovementDirection::East));
+    }
+
+    #[test]
+    fn follower_catches_up() {
+        let tile_size = 16;
+        let leader = Point::new(100, 100);
+
+        // Right at the catch up distance is still within walking distance
+        let edge = leader.offset(0, CATCH_UP_DISTANCE * tile_size);
+        assert_eq!(FollowStep::toward(edge, leader, tile_size), FollowStep::Move(MovementDirection::North));
+        // Any further and the follower is left too far behind
+        assert_eq!(FollowStep::toward(edge.offset(0, 1), leader, tile_size), FollowStep::CatchUp);
+        assert_eq!(FollowStep::toward(leader.offset(-15 * tile_size, 15 * tile_size), leader, tile_size),
+            FollowStep::CatchUp);
+    }
+
+    #[test]
+    fn charge_stops_at_wall() {
+        let map = walled_room(5, 10, TILE_SIZE);
+        let bounds = BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE};
+        let pos = TilePos {row: 2, col: 2}.center(TILE_SIZE as i32);
+
+        // Nothing in the way
+        assert_eq!(charge_target(&map, pos, bounds, MovementDirection::East, 32), pos.offset(32, 0));
+        // Ends up flush against the wall (within the collision threshold)
+        assert_eq!(charge_target(&map, pos, bounds, MovementDirection::East, 128), pos.offset(97, 0));
+        assert_eq!(charge_target(&map, pos, bounds, MovementDirection::West, 128), pos.offset(-17, 0));
+        assert_eq!(charge_target(&map, pos, bounds, MovementDirection::North, 128), pos.offset(0, -17));
+    }
+
+    #[test]
+    fn charge_winds_up_then_charges() {
+        let map = walled_room(5, 20, TILE_SIZE);
+        let bounds = BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE};
+        let pos = TilePos {row: 2, col: 2}.center(TILE_SIZE as i32);
+        let player = TilePos {row: 2, col: 15}.center(TILE_SIZE as i32);
+        let mut charge = ChargeAttack::new(10, 5, 8.0, 40, 3);
+        let mut movement = Movement::default();
+
+        // Only starts winding up toward a player that it is chasing
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 10);
+        assert_eq!(charge.state, ChargeState::Cooldown {frames_remaining: 0});
+        update_charge(&map, &mut charge, pos, bounds, Some(player), &mut movement, 1);
+        assert_eq!(charge.winding_up(), Some(MovementDirection::East));
+        assert_eq!(movement.speed, 0.0);
+        assert_eq!(charge.hit_bonus(), 0);
+
+        // Stands still until the wind-up is over, even after the player is out of sight
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 4);
+        assert_eq!(charge.winding_up(), Some(MovementDirection::East));
+        assert_eq!(movement.speed, 0.0);
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 1);
+        assert!(charge.is_charging());
+        assert_eq!(charge.hit_bonus(), 3);
+
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 1);
+        assert_eq!((movement.direction, movement.speed), (MovementDirection::East, 8.0));
+        // Slows down to land exactly on the target
+        update_charge(&map, &mut charge, pos.offset(36, 0), bounds, None, &mut movement, 1);
+        assert_eq!(movement.speed, 4.0);
+        update_charge(&map, &mut charge, pos.offset(40, 0), bounds, None, &mut movement, 1);
+        assert_eq!(charge.state, ChargeState::Cooldown {frames_remaining: 10});
+        assert_eq!(movement.speed, 0.0);
+    }
+
+    #[test]
+    fn blocked_charge_ends_early() {
+        let map = walled_room(5, 20, TILE_SIZE);
+        let bounds = BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE};
+        let pos = TilePos {row: 2, col: 2}.center(TILE_SIZE as i32);
+        let mut charge = ChargeAttack::new(10, 5, 8.0, 40, 3);
+        charge.state = ChargeState::Charging {
+            direction: MovementDirection::East,
+            target: pos.offset(40, 0),
+            previous: None,
+        };
+        let mut movement = Movement::default();
+
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 1);
+        assert!(charge.is_charging());
+        // Something (e.g. a closed door) stopped the enemy from moving at all
+        update_charge(&map, &mut charge, pos, bounds, None, &mut movement, 1);
+        assert_eq!(charge.state, ChargeState::Cooldown {frames_remaining: 10});
     }
 }
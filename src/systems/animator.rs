@@ -2,11 +2,16 @@ use std::borrow::Cow;
 
 use specs::{System, Join, ReadExpect, ReadStorage, WriteStorage, Entities};
 
-use crate::components::{Movement, MovementDirection::*, Sprite, Animation, AnimationManager, Wait};
+use crate::components::{Movement, MovementDirection::*, Sprite, Animation, AnimationManager, Wait, Dodge, Dormant, ChargeAttack, Equipment};
 use crate::resources::{ActionQueue, Action::*, FramesElapsed};
 
+use super::JoinedEntities;
+
 /// The number of frames that an entity can be idle before the idle animation starts
 const IDLE_LENGTH: usize = 300;
+/// How many times faster the movement animation plays while rolling. There is no roll animation,
+/// so this is used instead.
+const ROLL_ANIMATION_SPEEDUP: usize = 3;
 
 #[derive(SystemData)]
 pub struct AnimatorData<'a> {
@@ -14,6 +19,10 @@ pub struct AnimatorData<'a> {
     action_queue: ReadExpect<'a, ActionQueue>,
     frames: ReadExpect<'a, FramesElapsed>,
     movements: ReadStorage<'a, Movement>,
+    dodges: ReadStorage<'a, Dodge>,
+    dormants: ReadStorage<'a, Dormant>,
+    charges: ReadStorage<'a, ChargeAttack>,
+    equipments: ReadStorage<'a, Equipment>,
     sprites: WriteStorage<'a, Sprite>,
     animations: WriteStorage<'a, Animation>,
     animation_managers: WriteStorage<'a, AnimationManager>,
@@ -31,6 +40,10 @@ impl<'a> System<'a> for Animator {
             action_queue,
             frames,
             movements,
+            dodges,
+            dormants,
+            charges,
+            equipments,
             mut sprites,
             mut animations,
             mut animation_managers,
@@ -40,13 +53,9 @@ impl<'a> System<'a> for Animator {
         let FramesElapsed(frames_elapsed) = *frames;
         let ActionQueue(ref action_queue) = *action_queue;
 
-        //TODO: This code often needs to compare the frames in the animation for equality. If we
-        // could either name each animation or store a specialized frame list that keeps around a
-        // hash of its contents, we could make that comparision much faster.
-
         // Set the current animation based on an entity's movements or based on actions that have
         // occurred during this frame
-        for (entity, movement, animation, manager) in (&entities, &movements, &mut animations, &mut animation_managers).join() {
+        for (entity, movement, animation, manager, ()) in (&entities, &movements, &mut animations, &mut animation_managers, !&dormants).join() {
             // No point in continuing if we can't interrupt the animation that is currently running
             // This also prevents the idle counter from being incremented during an animation
             if !animation.can_interrupt && !animation.is_complete() {
@@ -55,9 +64,21 @@ impl<'a> System<'a> for Animator {
 
             let direction = movement.direction;
 
+            // Winding up for a charge is telegraphed by holding the attack animation
+            if let Some(direction) = charges.get(entity).and_then(|charge| charge.winding_up()) {
+                manager.idle_counter = 0;
+                match direction {
+                    North => animation.update_if_different(&manager.attack_up),
+                    East => animation.update_if_different(&manager.attack_right),
+                    South => animation.update_if_different(&manager.attack_down),
+                    West => animation.update_if_different(&manager.attack_left),
+                }
+                continue;
+            }
+
             // Don't want to copy the events that occurred but also don't want to deal with the
             // option type
-            let actions: Cow<'_, Vec<_>> = action_queue.get(&entity).map(|q| Cow::Borrowed(q)).unwrap_or_default();
+            let actions: Cow<'_, [_]> = action_queue.get(&entity).map(|q| Cow::Borrowed(&q[..])).unwrap_or_default();
 
             // Update the idle counter so we can decide whether to play the idle animation
             match (movement.is_moving(), &actions[..]) {
@@ -92,38 +113,51 @@ impl<'a> System<'a> for Animator {
             // The order of this code is important: movement animations are overridden by actions
 
             if movement.is_moving() {
-                match direction {
-                    North => animation.update_if_different(&manager.move_up),
-                    East => animation.update_if_different(&manager.move_right),
-                    South => animation.update_if_different(&manager.move_down),
-                    West => animation.update_if_different(&manager.move_left),
+                let move_animation = match direction {
+                    North => &manager.move_up,
+                    East => &manager.move_right,
+                    South => &manager.move_down,
+                    West => &manager.move_left,
+                };
+                match dodges.get(entity) {
+                    Some(dodge) if dodge.is_rolling() => {
+                        animation.update_if_different(&move_animation.sped_up(ROLL_ANIMATION_SPEEDUP));
+                    },
+                    _ => animation.update_if_different(move_animation),
                 }
             }
 
             for action in actions.iter() {
                 let action_animation = match action {
-                    Interact => None,
-                    Attack => Some(match direction {
-                        North => &manager.attack_up,
-                        East => &manager.attack_right,
-                        South => &manager.attack_down,
-                        West => &manager.attack_left,
-                    }),
-                    Hit => Some(match direction {
+                    Interact | DrinkPotion => None,
+                    Attack => {
+                        let attack = match direction {
+                            North => &manager.attack_up,
+                            East => &manager.attack_right,
+                            South => &manager.attack_down,
+                            West => &manager.attack_left,
+                        };
+                        // The attack plays at the speed of the weapon being held
+                        Some(match equipments.get(entity) {
+                            Some(equipment) => Cow::Owned(equipment.weapon.attack_animation(attack)),
+                            None => Cow::Borrowed(attack),
+                        })
+                    },
+                    Hit => Some(Cow::Borrowed(match direction {
                         North => &manager.hit_up,
                         East => &manager.hit_right,
                         South => &manager.hit_down,
                         West => &manager.hit_left,
-                    }),
-                    Victory => Some(&manager.victory),
+                    })),
+                    Victory => Some(Cow::Borrowed(&manager.victory)),
                     Defeat => unimplemented!(), //TODO
                 };
                 if let Some(action_animation) = action_animation {
-                    if animation.has_same_steps(action_animation) {
+                    if animation.has_same_steps(&action_animation) {
                         continue;
                     }
 
-                    *animation = action_animation.clone();
+                    *animation = action_animation.into_owned();
                     if !animation.can_interrupt && !animation.should_loop {
                         // If another wait was already there, this will overwrite it
                         waits.insert(entity, Wait::new(animation.len()))
@@ -133,8 +167,9 @@ impl<'a> System<'a> for Animator {
             }
         }
 
-        // Update the sprites based on the current animation frame
-        for (sprite, animation) in (&mut sprites, &mut animations).join() {
+        // Update the sprites based on the current animation frame. Dormant entities stay on the
+        // frame they were on.
+        for (sprite, animation, ()) in (&mut sprites, &mut animations, !&dormants).join() {
             animation.frame_counter += frames_elapsed;
 
             // This code should work regardless of how many frames have elapsed
@@ -154,3 +189,10 @@ impl<'a> System<'a> for Animator {
         }
     }
 }
+
+impl<'a> JoinedEntities<'a> for Animator {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.movements, &data.animations, &data.animation_managers, !&data.dormants).join().count()
+            + (&data.sprites, &data.animations, !&data.dormants).join().count()
+    }
+}
@@ -0,0 +1,153 @@
+//! Puts enemies that are far away from the player to sleep so that they do not cost anything to
+//! simulate, and wakes them back up when the player gets close
+
+use specs::{System, Join, Read, ReadExpect, ReadStorage, WriteStorage, Entities};
+
+use crate::components::{Position, Player, Enemy, AiState, Dormant};
+use crate::resources::ActivityBubble;
+
+use crate::map::FloorMap;
+
+use super::JoinedEntities;
+
+#[derive(SystemData)]
+pub struct DormancyData<'a> {
+    entities: Entities<'a>,
+    map: ReadExpect<'a, FloorMap>,
+    bubble: Read<'a, ActivityBubble>,
+    positions: ReadStorage<'a, Position>,
+    players: ReadStorage<'a, Player>,
+    enemies: ReadStorage<'a, Enemy>,
+    ai_states: ReadStorage<'a, AiState>,
+    dormants: WriteStorage<'a, Dormant>,
+}
+
+/// Dormant enemies are left exactly where they are, so waking up never moves them
+pub struct Dormancy;
+
+impl<'a> System<'a> for Dormancy {
+    type SystemData = DormancyData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let DormancyData {entities, map, bubble, positions, players, enemies, ai_states, mut dormants} = data;
+
+        let player = (&positions, &players).join().next().map(|(&Position(pos), _)| pos);
+        for (entity, &Position(pos), _) in (&entities, &positions, &enemies).join() {
+            let is_active = match player {
+                Some(player) => {
+                    let chasing = ai_states.get(entity) == Some(&AiState::Chasing);
+                    bubble.is_active(pos, player, map.tile_size(), chasing)
+                },
+                // Nothing to be far away from
+                None => true,
+            };
+
+            if is_active {
+                dormants.remove(entity);
+            } else if dormants.get(entity).is_none() {
+                dormants.insert(entity, Dormant)
+                    .expect("bug: unable to mark enemy as dormant");
+            }
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for Dormancy {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.enemies).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Entity;
+
+    use crate::generator::EnemyValues;
+    use crate::components::{Animation, BoundingBox, EnemyBehaviour};
+    use crate::map::TilePos;
+    use crate::test_helpers::{TestWorld, test_animations};
+
+    const TILE_SIZE: u32 = 16;
+
+    fn enemy_values() -> EnemyValues {
+        EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 0.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+        }
+    }
+
+    /// A long room with a bubble that covers only part of it
+    fn long_room<'a>() -> TestWorld<'a> {
+        let test = TestWorld::new(5, 30, TILE_SIZE);
+        *test.world.write_resource() = ActivityBubble {radius: 6, chase_radius: 10};
+        test
+    }
+
+    fn is_dormant(test: &TestWorld<'_>, entity: Entity) -> bool {
+        test.world.read_storage::<Dormant>().get(entity).is_some()
+    }
+
+    fn animation_progress(test: &TestWorld<'_>, entity: Entity) -> (usize, usize) {
+        let animations = test.world.read_storage::<Animation>();
+        let animation = animations.get(entity).unwrap();
+        (animation.current_step, animation.frame_counter)
+    }
+
+    #[test]
+    fn dormant_animation_does_not_advance() {
+        let mut test = long_room();
+        test.spawn_player_at(TilePos {row: 2, col: 1});
+        let near = test.spawn_enemy_at(TilePos {row: 2, col: 4}, enemy_values());
+        let far = test.spawn_enemy_at(TilePos {row: 2, col: 25}, enemy_values());
+
+        test.step(1);
+        assert!(!is_dormant(&test, near));
+        assert!(is_dormant(&test, far));
+
+        let near_start = animation_progress(&test, near);
+        let far_start = animation_progress(&test, far);
+        test.step(5);
+        assert_ne!(animation_progress(&test, near), near_start);
+        assert_eq!(animation_progress(&test, far), far_start);
+    }
+
+    #[test]
+    fn wakes_up_in_place() {
+        let mut test = long_room();
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+        let enemy = test.spawn_enemy_at(TilePos {row: 2, col: 25}, enemy_values());
+        let start = test.position(enemy);
+
+        test.step(10);
+        assert!(is_dormant(&test, enemy));
+        assert_eq!(test.position(enemy), start);
+
+        // Player walks up to the enemy
+        let near = test.tile_center(TilePos {row: 2, col: 20});
+        test.world.write_storage::<Position>().insert(player, Position(near)).unwrap();
+        test.step(1);
+        assert!(!is_dormant(&test, enemy));
+        assert_eq!(test.position(enemy), start);
+    }
+
+    #[test]
+    fn chasing_extends_bubble() {
+        let mut test = long_room();
+        test.spawn_player_at(TilePos {row: 2, col: 1});
+        let enemy = test.spawn_enemy_at(TilePos {row: 2, col: 9}, enemy_values());
+
+        test.step(1);
+        assert!(is_dormant(&test, enemy));
+
+        test.world.write_storage::<AiState>().insert(enemy, AiState::Chasing).unwrap();
+        test.step(1);
+        assert!(!is_dormant(&test, enemy));
+    }
+}
@@ -0,0 +1,73 @@
+//! Moves floating text (e.g. damage numbers) and removes it once it has faded out
+
+use specs::{System, Join, ReadExpect, WriteStorage, Entities};
+
+use crate::components::FloatingText;
+
+use crate::resources::FramesElapsed;
+
+use super::JoinedEntities;
+
+#[derive(SystemData)]
+pub struct FloatingTextsData<'a> {
+    entities: Entities<'a>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    floating_texts: WriteStorage<'a, FloatingText>,
+}
+
+pub struct FloatingTexts;
+
+impl<'a> System<'a> for FloatingTexts {
+    type SystemData = FloatingTextsData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let FloatingTextsData {entities, frames, mut floating_texts} = data;
+        let FramesElapsed(frames_elapsed) = *frames;
+
+        for (entity, text) in (&entities, &mut floating_texts).join() {
+            if text.advance(frames_elapsed) {
+                entities.delete(entity)
+                    .expect("bug: unable to delete floating text");
+            }
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for FloatingTexts {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        data.floating_texts.join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::Point;
+    use specs::Builder;
+
+    use crate::components::Position;
+    use crate::test_helpers::TestWorld;
+
+    #[test]
+    fn deleted_after_lifetime() {
+        let mut test = TestWorld::new(5, 5, 16);
+        let text = test.world.create_entity()
+            .with(Position(Point::new(40, 40)))
+            .with(FloatingText::damage(12, 16))
+            .build();
+        let floating_text = |test: &TestWorld| test.world.read_storage::<FloatingText>().get(text).cloned();
+
+        test.step(FloatingText::LIFETIME_FRAMES / 2);
+        let rising = floating_text(&test).unwrap();
+        assert_eq!(rising.offset(), Point::new(0, -5));
+        assert!(rising.alpha() > 0 && rising.alpha() < 255);
+        // Only the text moves, not the entity
+        assert_eq!(test.position(text), Point::new(40, 40));
+
+        test.step(FloatingText::LIFETIME_FRAMES / 2 - 1);
+        assert_eq!(floating_text(&test).unwrap().offset(), Point::new(0, -10));
+        test.step(1);
+        assert!(!test.world.is_alive(text));
+    }
+}
@@ -0,0 +1,338 @@
+//! Keeps track of the parts of the map that the player has explored
+
+use std::cmp;
+use std::collections::HashSet;
+
+use specs::{System, Join, ReadExpect, WriteExpect, ReadStorage, WriteStorage, Entities};
+
+use sdl2::rect::Point;
+
+use crate::components::{Position, BoundingBox, CameraFocus, Door, Stairs, MapFragment, Discovered};
+use crate::resources::ExploredTiles;
+use crate::map::{FloorMap, TileGrid, TilePos};
+
+use super::JoinedEntities;
+
+/// Returns the tile that the search for visible tiles should start from for an entity at the given
+/// position with the given bounding box.
+///
+/// This is usually the tile that the position is on. A bounding box whose position is not at its
+/// center (e.g. a bottom half bounding box) can have its position inside a closed door while most
+/// of the box is still in the area beside the door. Starting at the door would only find the door
+/// itself, so the search starts from the tile beside the door that overlaps the bounding box the
+/// most instead.
+pub fn visibility_start(
+    grid: &TileGrid,
+    pos: Point,
+    bounds: Option<BoundingBox>,
+    tile_size: i32,
+    positions: &ReadStorage<'_, Position>,
+    doors: &ReadStorage<'_, Door>,
+) -> TilePos {
+    let tile = TilePos {
+        row: pos.y() as usize / tile_size as usize,
+        col: pos.x() as usize / tile_size as usize,
+    };
+    if !is_closed_door(tile, tile_size, positions, doors) {
+        return tile;
+    }
+
+    let rect = match bounds {
+        Some(bounds) => bounds.to_rect(pos),
+        None => return tile,
+    };
+    let center = rect.center();
+    grid.adjacent_positions(tile)
+        .filter(|&adj| !grid.get(adj).is_wall() && !is_closed_door(adj, tile_size, positions, doors))
+        .max_by_key(|&adj| {
+            let tile_rect = adj.tile_rect(tile_size as u32);
+            let overlap = rect.intersection(tile_rect).map(|r| r.width() * r.height()).unwrap_or(0);
+            // Break ties with the tile whose center is nearest to the center of the box
+            let diff = adj.center(tile_size) - center;
+            (overlap, -(diff.x().abs() + diff.y().abs()))
+        })
+        .unwrap_or(tile)
+}
+
+/// Returns true if there is a closed door on the given tile
+fn is_closed_door(
+    target: TilePos,
+    tile_size: i32,
+    positions: &ReadStorage<'_, Position>,
+    doors: &ReadStorage<'_, Door>,
+) -> bool {
+    let target_center = target.center(tile_size);
+    // Open doors can be seen through
+    (positions, doors).join()
+        .any(|(&Position(pos), door)| pos == target_center && !door.is_open())
+}
+
+/// Returns true if nothing blocks the straight line between the given positions. Walls and closed
+/// doors block the line. Only the tiles that the line passes through are checked, so this is finer
+/// grained than find_visible_tiles.
+pub fn has_line_of_sight(
+    grid: &TileGrid,
+    from: Point,
+    to: Point,
+    tile_size: i32,
+    positions: &ReadStorage<'_, Position>,
+    doors: &ReadStorage<'_, Door>,
+) -> bool {
+    // Checking a few points per tile is enough to catch every tile that the line passes through
+    // apart from the ones that it barely clips the corner of
+    let step = (tile_size / 4).max(1);
+    let diff = to - from;
+    let steps = cmp::max(diff.x().abs(), diff.y().abs()) / step + 1;
+    (0..=steps).all(|i| {
+        let pt = from + diff * i / steps;
+        if pt.x() < 0 || pt.y() < 0 {
+            return false;
+        }
+        let tile = TilePos {
+            row: pt.y() as usize / tile_size as usize,
+            col: pt.x() as usize / tile_size as usize,
+        };
+        if tile.row >= grid.rows_len() || tile.col >= grid.cols_len() {
+            return false;
+        }
+        !grid.get(tile).is_wall() && !is_closed_door(tile, tile_size, positions, doors)
+    })
+}
+
+/// Returns all of the tiles that are directly visible from the given tile without passing through
+/// doors that are closed. Wall corners adjacent to visible tiles are included even though they are
+/// not *directly* visible. Use visibility_start to find the tile to search from for an entity.
+pub fn find_visible_tiles(
+    grid: &TileGrid,
+    pos: TilePos,
+    tile_size: i32,
+    positions: &ReadStorage<'_, Position>,
+    doors: &ReadStorage<'_, Door>,
+) -> HashSet<TilePos> {
+    let mut visible = grid.depth_first_search(pos, |node, _| {
+        // Stop searching at walls or closed entrances (but still include them in the result)
+        !grid.get(node).is_wall() && !is_closed_door(node, tile_size, positions, doors)
+    });
+
+    // Need to specially handle wall corners because they are not *directly* visible.
+    // A corner is a wall tile with at least two visible walls
+    let corners: Vec<_> = visible.iter()
+        .flat_map(|&pt| grid.adjacent_positions(pt))
+        .filter(|&pt| !visible.contains(&pt) && grid.get(pt).is_wall())
+        .filter(|&pt| grid.adjacent_positions(pt).filter(|adj| visible.contains(adj)).count() >= 2)
+        .collect();
+    visible.extend(corners);
+
+    visible
+}
+
+#[derive(SystemData)]
+pub struct FogOfWarData<'a> {
+    entities: Entities<'a>,
+    map: ReadExpect<'a, FloorMap>,
+    explored: WriteExpect<'a, ExploredTiles>,
+    camera_focuses: ReadStorage<'a, CameraFocus>,
+    positions: ReadStorage<'a, Position>,
+    bounding_boxes: ReadStorage<'a, BoundingBox>,
+    doors: ReadStorage<'a, Door>,
+    stairs: ReadStorage<'a, Stairs>,
+    map_fragments: ReadStorage<'a, MapFragment>,
+    discovered: WriteStorage<'a, Discovered>,
+}
+
+#[derive(Default)]
+pub struct FogOfWar;
+
+impl<'a> System<'a> for FogOfWar {
+    type SystemData = FogOfWarData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let FogOfWarData {
+            entities,
+            map,
+            mut explored,
+            camera_focuses,
+            positions,
+            bounding_boxes,
+            doors,
+            stairs,
+            map_fragments,
+            mut discovered,
+        } = data;
+
+        // Everything visible to the camera focus gets explored
+        let tile_size = map.tile_size() as i32;
+        for (entity, &Position(pos), _) in (&entities, &positions, &camera_focuses).join() {
+            let bounds = bounding_boxes.get(entity).cloned();
+            let start = visibility_start(map.grid(), pos, bounds, tile_size, &positions, &doors);
+            let visible = find_visible_tiles(map.grid(), start, tile_size, &positions, &doors);
+            explored.explore(visible);
+        }
+
+        // Notable entities become discovered once the tile they are on has been explored. This
+        // includes tiles that were explored without being seen (e.g. from a map fragment).
+        let notable = (&entities, &positions, !&discovered).join()
+            .filter(|&(entity, _, _)| stairs.get(entity).is_some() || map_fragments.get(entity).is_some())
+            .filter(|&(_, &Position(pos), _)| explored.contains(map.world_to_tile_pos(pos)))
+            .map(|(entity, _, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in notable {
+            discovered.insert(entity, Discovered)
+                .expect("bug: unable to mark entity as discovered");
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for FogOfWar {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.camera_focuses).join().count()
+            + (&data.positions, !&data.discovered).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder};
+
+    use crate::map::{GridSize, TileRect, Tile};
+    use crate::map_sprites::{FloorSprite, WallSprite};
+    use crate::test_helpers::walled_room;
+
+    #[test]
+    fn open_doors_are_see_through() {
+        // Two rooms side by side with an entrance between them
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 5, cols: 10}, tile_size as u32);
+        let rooms: Vec<_> = (0..2).map(|i| {
+            let boundary = TileRect::new(TilePos {row: 0, col: i * 5}, GridSize {rows: 5, cols: 5});
+            let room_id = map.add_room(boundary);
+            for pos in boundary.tile_positions() {
+                map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+            }
+            for pos in boundary.edge_positions() {
+                map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+            }
+            room_id
+        }).collect();
+        let entrance = TilePos {row: 2, col: 4};
+        map.grid_mut().place_tile(entrance, Tile::new_floor(rooms[0], FloorSprite::default()));
+        map.grid_mut().place_tile(TilePos {row: 2, col: 5}, Tile::new_floor(rooms[1], FloorSprite::default()));
+        let player_pos = map.room(rooms[0]).boundary().center_tile();
+        let other_room = map.room(rooms[1]).boundary().center_tile();
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Door>();
+        let door = world.create_entity()
+            .with(Position(entrance.center(tile_size)))
+            .with(Door::Closed)
+            .build();
+
+        let sees_other_room = |world: &World| {
+            let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+            find_visible_tiles(map.grid(), player_pos, tile_size, &positions, &doors).contains(&other_room)
+        };
+        let toggle = |world: &World| {
+            let mut doors = world.write_storage::<Door>();
+            let door = doors.get_mut(door).unwrap();
+            *door = door.toggled();
+        };
+
+        assert!(!sees_other_room(&world));
+        toggle(&world);
+        assert!(sees_other_room(&world));
+        toggle(&world);
+        assert!(!sees_other_room(&world));
+    }
+
+    #[test]
+    fn line_of_sight_blocked_by_walls_and_closed_doors() {
+        let tile_size = 16;
+        let mut map = walled_room(7, 9, tile_size as u32);
+        // A pillar in the middle of the room
+        map.grid_mut().place_tile(TilePos {row: 3, col: 4}, Tile::new_wall(WallSprite::default()));
+        let center = |row, col| TilePos {row, col}.center(tile_size);
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Door>();
+        let door = world.create_entity()
+            .with(Position(center(5, 4)))
+            .with(Door::Closed)
+            .build();
+        let can_see = |world: &World, from, to| {
+            let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+            has_line_of_sight(map.grid(), from, to, tile_size, &positions, &doors)
+        };
+
+        assert!(can_see(&world, center(1, 1), center(1, 7)));
+        assert!(can_see(&world, center(1, 1), center(5, 1)));
+        // Blocked by the pillar, but only while it is directly in the way
+        assert!(!can_see(&world, center(3, 1), center(3, 7)));
+        assert!(!can_see(&world, center(2, 2), center(4, 6)));
+        assert!(can_see(&world, center(2, 1), center(2, 7)));
+        // Blocked by the door until it is opened
+        assert!(!can_see(&world, center(5, 1), center(5, 7)));
+        *world.write_storage::<Door>().get_mut(door).unwrap() = Door::Open;
+        assert!(can_see(&world, center(5, 1), center(5, 7)));
+        // Never sees outside of the map
+        assert!(!can_see(&world, center(1, 1), Point::new(-8, 24)));
+    }
+
+    #[test]
+    fn see_past_door_to_the_north() {
+        // A room with a closed door in its north wall leading to a corridor
+        //
+        //   .
+        // ##+##
+        // #...#
+        // #...#
+        // #####
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 6, cols: 5}, tile_size as u32);
+        let boundary = TileRect::new(TilePos {row: 1, col: 0}, GridSize {rows: 5, cols: 5});
+        let room_id = map.add_room(boundary);
+        for pos in boundary.tile_positions() {
+            map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+        }
+        for pos in boundary.edge_positions() {
+            map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+        }
+        let entrance = TilePos {row: 1, col: 2};
+        map.grid_mut().place_tile(entrance, Tile::new_floor(room_id, FloorSprite::default()));
+        map.grid_mut().place_tile(TilePos {row: 0, col: 2}, Tile::new_floor(room_id, FloorSprite::default()));
+        let below_door = TilePos {row: 2, col: 2};
+        let room_corner = TilePos {row: 3, col: 3};
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Door>();
+        world.create_entity()
+            .with(Position(entrance.center(tile_size)))
+            .with(Door::Closed)
+            .build();
+        let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+
+        // Each box is pushed up against the door so that its position is just inside the door
+        let door_bottom = entrance.tile_rect(tile_size as u32).bottom();
+        let boxes = [
+            (BoundingBox::Full {width: 16, height: 16}, Point::new(entrance.center(tile_size).x(), door_bottom - 2)),
+            (BoundingBox::BottomHalf {width: 16, height: 8}, Point::new(entrance.center(tile_size).x(), door_bottom - 2)),
+        ];
+        for &(bounds, pos) in &boxes {
+            let start = visibility_start(map.grid(), pos, Some(bounds), tile_size, &positions, &doors);
+            assert_eq!(start, below_door, "{:?}", bounds);
+            let visible = find_visible_tiles(map.grid(), start, tile_size, &positions, &doors);
+            assert!(visible.contains(&room_corner), "{:?}", bounds);
+            assert!(visible.contains(&entrance), "{:?}", bounds);
+            // Cannot see through the closed door
+            assert!(!visible.contains(&TilePos {row: 0, col: 2}), "{:?}", bounds);
+        }
+
+        // Without a bounding box, there is nothing to go on but the position itself
+        let pos = Point::new(entrance.center(tile_size).x(), door_bottom - 2);
+        assert_eq!(visibility_start(map.grid(), pos, None, tile_size, &positions, &doors), entrance);
+    }
+}
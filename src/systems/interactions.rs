@@ -1,7 +1,9 @@
 //! Manages interactions between entities and adjacent tiles
 
+use std::cmp::Reverse;
+
 use sdl2::rect::{Point, Rect};
-use specs::{Entity, System, Join, ReadExpect, WriteExpect, ReadStorage, WriteStorage, Entities};
+use specs::{Builder, Entity, System, Join, Read, Write, ReadExpect, WriteExpect, ReadStorage, WriteStorage, Entities, LazyUpdate};
 
 use crate::components::{
     Position,
@@ -9,30 +11,139 @@ use crate::components::{
     Movement,
     MovementDirection,
     Player,
+    Enemy,
     Stairs,
+    DungeonMouth,
     Door,
+    Gate,
+    Locked,
+    BossSeal,
+    Boss,
+    ChargeAttack,
+    Chest,
+    Item,
+    Inventory,
+    Equipment,
+    Breakable,
+    Loot,
+    Merchant,
+    Pickup,
+    Sprite,
+    RenderLayer,
     HealthPoints,
+    MaxHealthPoints,
     Attack,
+    AttackCooldown,
     HitWait,
+    HitInvulnerability,
+    Invulnerable,
+    HazardExposure,
+    HealthBar,
+    Wait,
+    Knockback,
+    MapFragment,
+    Cage,
+    Follower,
+    Ghost,
+    AnimationManager,
+    Projectile,
+    FloatingText,
+    StatusEffects,
+    StatusEffect,
+    InflictsStatus,
 };
-use crate::resources::{ActionQueue, Action, ChangeGameState, GameState};
-use crate::map::FloorMap;
+use crate::resources::{ActionQueue, Action, GameStateMachine, GameState, LevelDirection, RunPhase, ExploredTiles, FramesElapsed, SoundQueue, NotificationQueue, RunStats, GameEvents, GameEvent, SpatialGrid, ScreenShake, DecalBuffer, Decal, DecalKind};
+use crate::audio::SoundEffect;
+use crate::assets::scale_to_tile_size;
+use crate::map::{FloorMap, Hazard};
+
+use super::JoinedEntities;
+use super::physics::COLLISION_THRESHOLD;
+use super::nearest::{nearest_in_direction, DirectionQuery};
+
+/// The initial speed (px/frame at NATIVE_TILE_SIZE) of the knockback applied to an entity that
+/// gets hit. Must stay below half the size of a bounding box so that physics never pushes an
+/// entity past a wall.
+const KNOCKBACK_SPEED: i32 = 6;
+/// The number of frames that a knockback lasts
+const KNOCKBACK_FRAMES: usize = 4;
+/// Followers within this many tiles of the player when they take a staircase are rescued.
+/// Slightly further than the distance that followers try to stay within.
+const ESCORT_DISTANCE: i32 = 3;
+/// The health points restored to the player for each rescued follower
+const RESCUE_REWARD: usize = 10;
+/// Hits that do at least this much damage leave blood on the floor
+const HEAVY_DAMAGE: usize = 10;
+/// How far (in px) and for how many frames the screen shakes when the player is hit
+const PLAYER_HIT_SHAKE_AMPLITUDE: u32 = 2;
+const PLAYER_HIT_SHAKE_FRAMES: usize = 8;
+/// The number of frames after an attack finishes during which attacking again lands a combo hit
+const COMBO_WINDOW_FRAMES: usize = 10;
+/// The damage done to the player for every HAZARD_DAMAGE_FRAMES spent standing on a hazard
+const HAZARD_DAMAGE: usize = 1;
+/// About 1.5 seconds. Longer than the invulnerability after a hit so that no damage is skipped.
+const HAZARD_DAMAGE_FRAMES: usize = 45;
+/// The percentage of their speed that players lose while wading through water. Applied again on
+/// every frame spent in the water, so it wears off shortly after the player leaves it.
+const WATER_SLOW_PERCENT: usize = 40;
+const WATER_SLOW_FRAMES: usize = 10;
 
 #[derive(SystemData)]
 pub struct InteractionsData<'a> {
     entities: Entities<'a>,
-    change_game_state: WriteExpect<'a, ChangeGameState>,
-    actions: ReadExpect<'a, ActionQueue>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    game_state: WriteExpect<'a, GameStateMachine>,
+    run_phase: ReadExpect<'a, RunPhase>,
+    actions: WriteExpect<'a, ActionQueue>,
+    sounds: WriteExpect<'a, SoundQueue>,
+    notifications: WriteExpect<'a, NotificationQueue>,
     map: ReadExpect<'a, FloorMap>,
+    explored: WriteExpect<'a, ExploredTiles>,
+    run_stats: WriteExpect<'a, RunStats>,
+    game_events: Write<'a, GameEvents>,
+    screen_shake: Write<'a, ScreenShake>,
+    decals: Write<'a, DecalBuffer>,
+    spatial_grid: Read<'a, SpatialGrid>,
     positions: ReadStorage<'a, Position>,
     bounding_boxes: ReadStorage<'a, BoundingBox>,
     movements: ReadStorage<'a, Movement>,
     players: ReadStorage<'a, Player>,
+    enemies: ReadStorage<'a, Enemy>,
     stairs: ReadStorage<'a, Stairs>,
     doors: WriteStorage<'a, Door>,
+    gates: ReadStorage<'a, Gate>,
+    locked: WriteStorage<'a, Locked>,
+    boss_seals: WriteStorage<'a, BossSeal>,
+    bosses: ReadStorage<'a, Boss>,
+    charges: WriteStorage<'a, ChargeAttack>,
+    chests: WriteStorage<'a, Chest>,
+    inventories: WriteStorage<'a, Inventory>,
+    equipments: WriteStorage<'a, Equipment>,
+    breakables: ReadStorage<'a, Breakable>,
+    loots: ReadStorage<'a, Loot>,
+    merchants: ReadStorage<'a, Merchant>,
+    dungeon_mouths: ReadStorage<'a, DungeonMouth>,
+    pickups: ReadStorage<'a, Pickup>,
     healths: WriteStorage<'a, HealthPoints>,
+    max_healths: ReadStorage<'a, MaxHealthPoints>,
     attacks: ReadStorage<'a, Attack>,
+    attack_cooldowns: WriteStorage<'a, AttackCooldown>,
     hit_waits: ReadStorage<'a, HitWait>,
+    hit_invulnerabilities: ReadStorage<'a, HitInvulnerability>,
+    invulnerables: WriteStorage<'a, Invulnerable>,
+    hazard_exposures: WriteStorage<'a, HazardExposure>,
+    health_bars: WriteStorage<'a, HealthBar>,
+    waits: WriteStorage<'a, Wait>,
+    knockbacks: WriteStorage<'a, Knockback>,
+    map_fragments: ReadStorage<'a, MapFragment>,
+    ghosts: ReadStorage<'a, Ghost>,
+    cages: WriteStorage<'a, Cage>,
+    followers: ReadStorage<'a, Follower>,
+    animation_managers: ReadStorage<'a, AnimationManager>,
+    projectiles: ReadStorage<'a, Projectile>,
+    status_effects: WriteStorage<'a, StatusEffects>,
+    inflicts_status: ReadStorage<'a, InflictsStatus>,
+    updater: ReadExpect<'a, LazyUpdate>,
 }
 
 impl<'a> InteractionsData<'a> {
@@ -41,129 +152,723 @@ impl<'a> InteractionsData<'a> {
         let (pos, direction, bounds) = self.position_movement_bounds(entity);
         // Want to be very close when interacting
         let range = self.map.tile_size() as i32 / 4;
-        for (other_entity, _) in self.nearest_in_direction(entity, pos, direction, bounds, range) {
+        let query = DirectionQuery {pos, direction, bounds, range, margin: 0};
+        for (other_entity, _) in self.nearest_in_direction(entity, query) {
             if self.doors.get(other_entity).is_some() {
-                self.entities.delete(other_entity)
-                    .expect("bug: unable to delete door");
+                self.toggle_door(entity, other_entity);
+                break; // stop at the first interaction
+            }
+
+            if self.chests.get(other_entity).is_some() {
+                self.open_chest(entity, other_entity);
+                break; // stop at the first interaction
+            }
+
+            if let Some(&MapFragment {rooms}) = self.map_fragments.get(other_entity) {
+                self.collect_map_fragment(other_entity, pos, rooms);
+                break; // stop at the first interaction
+            }
+
+            if self.dungeon_mouths.get(other_entity).is_some() {
+                self.leave_dungeon();
                 break; // stop at the first interaction
             }
+
+            if self.merchants.get(other_entity).is_some() {
+                // Nothing happens if the game cannot open the shop right now
+                let _ = self.game_state.transition(GameState::Shop {merchant: other_entity});
+                break; // stop at the first interaction
+            }
+        }
+    }
+
+    /// Wins the run if the player is escaping with the treasure
+    fn leave_dungeon(&mut self) {
+        if !self.run_phase.is_escaping() {
+            self.notifications.push("The way back to the surface");
+            return;
         }
+        // Nothing happens if the game cannot end right now
+        let _ = self.game_state.transition(GameState::Victory);
+    }
+
+    /// Opens the given door if it is closed or closes it if it is open. Locked doors cannot be
+    /// opened without a key, sealed gates cannot be opened at all until the boss is defeated, and
+    /// doors cannot be closed while something is in the doorway.
+    fn toggle_door(&mut self, entity: Entity, door_entity: Entity) {
+        let door = *self.doors.get(door_entity).expect("bug: can only toggle doors");
+        // Checked before unlocking so that the key is not used up
+        if !door.is_open() && self.boss_seals.get(door_entity).is_some() {
+            self.notifications.push("Defeat the boss to open this gate");
+            return;
+        }
+        let can_toggle = match door {
+            Door::Closed => self.locked.get(door_entity).is_none() || self.unlock(entity, door_entity),
+            Door::Open => !self.is_doorway_occupied(door_entity),
+        };
+        if !can_toggle {
+            return;
+        }
+
+        self.doors.insert(door_entity, door.toggled())
+            .expect("bug: unable to update door");
+        self.sounds.0.push(SoundEffect::DoorOpen);
     }
 
-    /// Attempts to attack an entity adjacent to this entity in the given direction
+    /// Unlocks the given locked door or gate using up the key that opens it. Gates are opened by
+    /// the treasure key and every other door is opened by a room key. Returns false if the entity
+    /// does not have the right key.
+    fn unlock(&mut self, entity: Entity, door: Entity) -> bool {
+        let key = if self.gates.get(door).is_some() { Item::TreasureKey } else { Item::RoomKey };
+        let has_key = self.inventories.get_mut(entity)
+            .map(|inventory| inventory.take(&key))
+            .unwrap_or(false);
+        if !has_key {
+            return false;
+        }
+
+        self.locked.remove(door);
+        true
+    }
+
+    /// Opens the given chest, giving whatever is inside to the given entity
+    fn open_chest(&mut self, entity: Entity, chest: Entity) {
+        let item = match self.chests.insert(chest, Chest::Opened).expect("bug: unable to open chest") {
+            Some(Chest::Item(item)) => item,
+            // Nothing left in the chest
+            _ => return,
+        };
+
+        self.notifications.push(format!("Found {}", item.name()));
+        self.give_item(entity, item);
+        self.sounds.0.push(SoundEffect::ChestOpen);
+        self.run_stats.floor.items_found += 1;
+        self.run_stats.floor.secrets_found += 1;
+        self.game_events.0.push(GameEvent::ItemFound);
+        self.game_events.0.push(GameEvent::ChestOpened);
+    }
+
+    /// Gives the given item to the given entity. Weapons are equipped right away, replacing the
+    /// weapon that the entity was holding. Every other item goes into the inventory.
+    fn give_item(&mut self, entity: Entity, item: Item) {
+        match item {
+            Item::Weapon(kind) => {
+                self.equipments.insert(entity, Equipment {weapon: kind.weapon()})
+                    .expect("bug: unable to equip weapon");
+            },
+            item => self.inventories.entry(entity)
+                .expect("bug: unable to get inventory of entity that was given an item")
+                .or_insert_with(Inventory::default)
+                .add(item),
+        }
+    }
+
+    /// Shows the given amount of damage floating up from the top of the given entity
+    fn show_damage(&self, entity: Entity, damage: usize) {
+        let pos = match self.positions.get(entity) {
+            Some(&Position(pos)) => pos,
+            None => return,
+        };
+        let top = self.bounding_boxes.get(entity).map(|bounds| bounds.to_rect(pos).top()).unwrap_or(pos.y());
+
+        // A separate entity so that the number keeps floating even if the damaged entity is deleted
+        self.updater.create_entity(&self.entities)
+            .with(Position(Point::new(pos.x(), top)))
+            .with(FloatingText::damage(damage, self.map.tile_size()))
+            .build();
+    }
+
+    /// Leaves whatever the given breakable or enemy drops on the floor where it was
+    fn drop_item(&mut self, entity: Entity) {
+        let pos = match self.positions.get(entity) {
+            Some(&Position(pos)) => pos,
+            None => return,
+        };
+        let (item, drop_animation) = match (self.breakables.get(entity), self.loots.get(entity)) {
+            (Some(Breakable {drops: Some(item), drop_animation}), _) => (item.clone(), drop_animation),
+            (_, Some(Loot {item, animation})) => (item.clone(), animation),
+            _ => return,
+        };
+
+        let size = self.map.tile_size() / 2;
+        self.updater.create_entity(&self.entities)
+            .with(Pickup(item))
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: size, height: size})
+            .with(Ghost)
+            .with(Sprite(drop_animation.current_sprite()))
+            .with(drop_animation.clone())
+            .with(RenderLayer::ITEMS)
+            .build();
+    }
+
+    /// Picks up every item on the floor that a player is touching
+    pub fn collect_pickups(&mut self) {
+        let mut collected = Vec::new();
+        for (player, _, &Position(player_pos), player_bounds) in (&self.entities, &self.players, &self.positions, &self.bounding_boxes).join() {
+            let player_box = player_bounds.to_rect(player_pos);
+            for pickup in self.spatial_grid.query(player_box) {
+                let (Pickup(item), &Position(pos), bounds) = match (self.pickups.get(pickup), self.positions.get(pickup), self.bounding_boxes.get(pickup)) {
+                    (Some(item), Some(pos), Some(bounds)) => (item, pos, bounds),
+                    _ => continue,
+                };
+                if bounds.to_rect(pos).has_intersection(player_box) {
+                    collected.push((player, pickup, item.clone()));
+                }
+            }
+        }
+
+        for (player, pickup, item) in collected {
+            // Already picked up by someone else during this frame
+            if !self.entities.is_alive(pickup) {
+                continue;
+            }
+            self.entities.delete(pickup)
+                .expect("bug: unable to delete pickup");
+
+            self.notifications.push(format!("Found {}", item.name()));
+            self.give_item(player, item);
+            self.run_stats.floor.items_found += 1;
+            self.game_events.0.push(GameEvent::ItemFound);
+        }
+    }
+
+    /// Returns true if anything that collides with doors is in the doorway of the given door
+    fn is_doorway_occupied(&self, door: Entity) -> bool {
+        let (&Position(door_pos), door_bounds) = match (self.positions.get(door), self.bounding_boxes.get(door)) {
+            (Some(pos), Some(bounds)) => (pos, bounds),
+            _ => unreachable!("bug: doors should have a position and a bounding box"),
+        };
+        // Entities pressed up against the doorway are not in it
+        let doorway = door_bounds.shrink(COLLISION_THRESHOLD).to_rect(door_pos);
+
+        (&self.entities, &self.positions, &self.bounding_boxes, !&self.ghosts).join()
+            .filter(|&(entity, _, _, ())| entity != door)
+            .any(|(_, &Position(pos), bounds, ())| bounds.to_rect(pos).has_intersection(doorway))
+    }
+
+    /// Collects the given map fragment, revealing the nearest rooms to the given position that
+    /// have not been explored yet
+    fn collect_map_fragment(&mut self, fragment: Entity, pos: Point, rooms: usize) {
+        let tile_pos = self.map.world_to_tile_pos(pos);
+        let revealed = self.explored.nearest_unexplored_rooms(&self.map, tile_pos, rooms);
+        self.explored.reveal_rooms(&self.map, &revealed);
+
+        self.entities.delete(fragment)
+            .expect("bug: unable to delete map fragment");
+        self.run_stats.floor.items_found += 1;
+        self.run_stats.floor.secrets_found += 1;
+        self.game_events.0.push(GameEvent::ItemFound);
+    }
+
+    /// Attempts to attack an entity adjacent to this entity in the given direction. Attacks made
+    /// before the entity's last attack has finished are dropped. An attack made within a short
+    /// window after that does 50% more damage as the second hit of a combo. The weapon held by the
+    /// entity decides how far the attack reaches, how much damage it does, and how long it takes.
     pub fn attack_adjacent(&mut self, entity: Entity) {
+        let combo = match self.attack_cooldowns.get(entity) {
+            Some(cooldown) if !cooldown.is_ready() => return,
+            Some(cooldown) => cooldown.is_combo(),
+            None => false,
+        };
+
+        // Entities without any equipment attack as if they held the default weapon
+        let weapon = self.equipments.get(entity).cloned().unwrap_or_default().weapon;
         let (pos, direction, bounds) = self.position_movement_bounds(entity);
-        // Most attacks take up an entire tile length in a given direction
-        let range = self.map.tile_size() as i32;
-        for (other_entity, other_pos) in self.nearest_in_direction(entity, pos, direction, bounds, range) {
-            if self.doors.get(other_entity).is_some() {
-                self.entities.delete(other_entity)
-                    .expect("bug: unable to delete door");
+        // The entity can attack again once its attack animation has finished playing
+        if let Some(manager) = self.animation_managers.get(entity) {
+            let animation = match direction {
+                MovementDirection::North => &manager.attack_up,
+                MovementDirection::East => &manager.attack_right,
+                MovementDirection::South => &manager.attack_down,
+                MovementDirection::West => &manager.attack_left,
+            };
+            let cooldown = AttackCooldown {
+                frames_remaining: weapon.cooldown(animation),
+                // A combo only has two hits
+                combo_frames_remaining: if combo { 0 } else { COMBO_WINDOW_FRAMES },
+            };
+            self.attack_cooldowns.insert(entity, cooldown)
+                .expect("bug: unable to insert attack cooldown");
+        }
+
+        let tile_size = self.map.tile_size();
+        let range = weapon.range(tile_size);
+        let margin = weapon.sweep(tile_size);
+        // Entities without an Attack component can still hit things, they just do no damage
+        let damage = self.attacks.get(entity).map(|&Attack(attack)| attack).unwrap_or(0);
+        let damage = weapon.scale_damage(damage);
+        let damage = if combo { damage * 3 / 2 } else { damage };
+        self.sounds.0.push(SoundEffect::Attack);
+        let query = DirectionQuery {pos, direction, bounds, range, margin};
+        for (other_entity, _) in self.nearest_in_direction(entity, query) {
+            // Attacks open closed doors and pass right through open ones
+            if let Some(&door) = self.doors.get(other_entity) {
+                if !door.is_open() {
+                    self.toggle_door(entity, other_entity);
+                }
                 continue;
             }
 
-            //TODO: Attack any nearby entities in the given direction. Lower the HealthPoints
-            // component of anything that gets hit. Anyone nearby in the direction of the method
-            // should be hit.
-            if self.healths.get_mut(other_entity).is_some() {
-                //TODO: Replace this with the more advanced behaviour based on health
-                self.entities.delete(other_entity)
-                    .expect("bug: unable to delete entity");
+            if let Some(cage) = self.cages.get_mut(other_entity) {
+                cage.hits_remaining = cage.hits_remaining.saturating_sub(1);
+                if cage.hits_remaining == 0 {
+                    self.free_prisoner(other_entity);
+                }
+                continue;
+            }
+
+            // Anyone nearby in the direction of the attack should be hit
+            if self.healths.get(other_entity).is_some() {
+                // Knock the entity away from the attacker
+                if self.apply_damage(other_entity, damage, direction) {
+                    self.inflict_status(entity, other_entity);
+                }
                 continue;
             }
         }
     }
 
-    fn position_movement_bounds(&self, entity: Entity) -> (Point, MovementDirection, BoundingBox) {
-        match (self.positions.get(entity), self.movements.get(entity), self.bounding_boxes.get(entity)) {
-            (Some(&Position(pos)), Some(movement), Some(&bounds)) => (pos, movement.direction, bounds),
-            _ => unreachable!("bug: only entities with positions, movement directions, and a bounding box can interact"),
-        }
+    /// Breaks open the cage around the given prisoner so that they start following the player
+    fn free_prisoner(&mut self, prisoner: Entity) {
+        let animation = self.animation_managers.get(prisoner)
+            .expect("bug: prisoners should have animations")
+            .default_animation();
+        self.cages.remove(prisoner);
+        self.updater.insert(prisoner, animation);
+        self.updater.insert(prisoner, Movement::default());
+        // Followers should never get in the way of the player
+        self.updater.insert(prisoner, Ghost);
+        self.updater.insert(prisoner, Follower);
+        self.sounds.0.push(SoundEffect::DoorOpen);
+        self.notifications.push("Prisoner freed!");
+        self.run_stats.floor.items_found += 1;
+        self.run_stats.floor.secrets_found += 1;
+        self.game_events.0.push(GameEvent::ItemFound);
     }
 
-    /// Returns the nearest entities in the given direction. Only entities that are up to `range`
-    /// away are returned. Result is sorted nearest to farthest.
-    fn nearest_in_direction(
-        &self,
-        entity: Entity,
-        pos: Point,
-        direction: MovementDirection,
-        bounds: BoundingBox,
-        range: i32,
-    ) -> impl Iterator<Item=(Entity, Point)> {
-        //TODO: Maybe instead of a (tile_size)x(tile_size) box we should consider a custom radius.
-        // This might be useful because we know that attacks don't necessary take up the entire
-        // adjacent tile. We also don't want to interact with things that are too far away.
-        //TODO: Filter by entity != other_entity so the entity being searched for isn't returned.
-        //TODO: If entity has a bounding box, start from the `direction` side of that box and
-        // construct a Rect of dimensions (tile_size)x(tile_size) in the given direction
-        //TODO: If both entity and other_entity have bounding boxes, we need to use those to find
-        // the distance instead of just the point itself. The algorithm will find the distance
-        // between two rectangles instead of just two points
-        let bounds = bounds.to_rect(pos);
-
-        // Generate the rectangle that the other bounding box must intersect with
-        // Assumption: bounding boxes do not intersect (due to the physics engine)
-        use self::MovementDirection::*;
-        let direction_box = match direction {
-            North => Rect::from_center(
-                Point::new(pos.x(), bounds.top() - range / 2),
-                range as u32,
-                range as u32,
-            ),
-            South => Rect::from_center(
-                Point::new(pos.x(), bounds.bottom() + range / 2),
-                range as u32,
-                range as u32,
-            ),
-            East => Rect::from_center(
-                Point::new(bounds.right() + range / 2, pos.y()),
-                range as u32,
-                range as u32,
-            ),
-            West => Rect::from_center(
-                Point::new(bounds.left() - range / 2, pos.y()),
-                range as u32,
-                range as u32,
-            ),
-        };
-
-        let mut near = Vec::new();
-        for (other, &Position(other_pos)) in (&self.entities, &self.positions).join() {
-            if entity == other {
+    /// Has every enemy that is touching a player attack that player. Enemies wait for their
+    /// HitWait duration after a successful hit so that they cannot hit on every frame.
+    pub fn enemies_attack_on_contact(&mut self) {
+        let mut contacts = Vec::new();
+        for (enemy, _, &Position(enemy_pos), enemy_bounds, &Attack(attack), ()) in (&self.entities, &self.enemies, &self.positions, &self.bounding_boxes, &self.attacks, !&self.waits).join() {
+            // Running into the player in the middle of a charge hits harder
+            let attack = attack + self.charges.get(enemy).map(|charge| charge.hit_bonus()).unwrap_or(0);
+            let enemy_box = enemy_bounds.to_rect(enemy_pos);
+            for player in self.spatial_grid.query(enemy_box) {
+                let (&Position(player_pos), player_bounds) = match (self.players.get(player), self.positions.get(player), self.bounding_boxes.get(player)) {
+                    (Some(_), Some(pos), Some(bounds)) => (pos, bounds),
+                    _ => continue,
+                };
+                if enemy_box.has_intersection(player_bounds.to_rect(player_pos)) {
+                    contacts.push((enemy, enemy_pos, player, player_pos, attack));
+                }
+            }
+        }
+
+        // Only the first hit lands if the player becomes invulnerable, so contacts are resolved in
+        // an order that does not depend on the order that the entities were created in: the
+        // strongest attack first, then top to bottom and left to right
+        contacts.sort_unstable_by_key(|&(enemy, enemy_pos, player, _, attack)| {
+            (Reverse(attack), enemy_pos.y(), enemy_pos.x(), player.id(), enemy.id())
+        });
+
+        for (enemy, enemy_pos, player, player_pos, attack) in contacts {
+            // Knock the player away from the enemy
+            let direction = MovementDirection::between(enemy_pos, player_pos);
+            if !self.apply_damage(player, attack, direction) {
                 continue;
             }
+            self.inflict_status(enemy, player);
+            // A charge ends as soon as it lands
+            if let Some(charge) = self.charges.get_mut(enemy) {
+                charge.stop();
+            }
+
+            match self.hit_waits.get(enemy) {
+                Some(&HitWait(hit_wait)) if hit_wait > 0 => {
+                    self.waits.insert(enemy, Wait::new(hit_wait))
+                        .expect("bug: unable to insert wait after hitting");
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Has every projectile hit an entity with health points that it is touching, other than the
+    /// entity that fired it. Each projectile is removed as soon as it hits something, so it never
+    /// does damage more than once.
+    pub fn projectiles_hit(&mut self) {
+        let mut hits = Vec::new();
+        for (projectile, &Position(pos), proj, bounds) in (&self.entities, &self.positions, &self.projectiles, self.bounding_boxes.maybe()).join() {
+            let proj_box = bounds.map(|bounds| bounds.to_rect(pos)).unwrap_or_else(|| Rect::new(pos.x(), pos.y(), 1, 1));
+            // Ties go to the target nearest the top left so that the outcome never depends on the
+            // order that the entities were created in
+            let target = (&self.entities, &self.positions, &self.bounding_boxes, &self.healths).join()
+                .filter(|&(target, _, _, &HealthPoints(health))| Some(target) != proj.owner && health > 0)
+                .filter(|&(_, &Position(target_pos), target_bounds, _)| target_bounds.to_rect(target_pos).has_intersection(proj_box))
+                .map(|(target, &Position(target_pos), _, _)| (target_pos.y(), target_pos.x(), target.id(), target))
+                .min();
+            if let Some((_, _, _, target)) = target {
+                let direction = MovementDirection::between(Point::new(0, 0), proj.velocity);
+                let effect = self.inflicts_status.get(projectile).map(|InflictsStatus(effect)| effect.clone());
+                hits.push((projectile, target, proj.damage, direction, effect));
+            }
+        }
+
+        for (projectile, target, damage, direction, effect) in hits {
+            self.entities.delete(projectile)
+                .expect("bug: unable to delete projectile");
+            // Another projectile may have already defeated the target during this frame
+            let is_alive = self.healths.get(target).map(|&HealthPoints(health)| health > 0).unwrap_or(false);
+            if is_alive && self.apply_damage(target, damage, direction) {
+                if let Some(effect) = effect {
+                    self.apply_status(target, effect);
+                }
+            }
+        }
+    }
+
+    /// Damages every player that is standing on a hazard once for every HAZARD_DAMAGE_FRAMES that
+    /// they stay on it. Stepping off of the hazard starts the count over again.
+    pub fn hazards_damage_players(&mut self) {
+        let FramesElapsed(frames_elapsed) = *self.frames;
+        let level_boundary = self.map.level_boundary();
+
+        let mut damaged = Vec::new();
+        let mut left_hazard = Vec::new();
+        let mut slowed = Vec::new();
+        for (player, _, &Position(pos)) in (&self.entities, &self.players, &self.positions).join() {
+            let hazard = if level_boundary.contains_point(pos) {
+                self.map.grid().get(self.map.world_to_tile_pos(pos)).hazard()
+            } else {
+                None
+            };
+            let hazard = match hazard {
+                Some(hazard) => hazard,
+                None => {
+                    left_hazard.push(player);
+                    continue;
+                },
+            };
+            if hazard == Hazard::Water {
+                slowed.push(player);
+            }
+
+            let exposure = self.hazard_exposures.entry(player)
+                .expect("bug: unable to get hazard exposure of player")
+                .or_insert_with(HazardExposure::default);
+            exposure.frames += frames_elapsed;
+            while exposure.frames >= HAZARD_DAMAGE_FRAMES {
+                exposure.frames -= HAZARD_DAMAGE_FRAMES;
+                damaged.push(player);
+            }
+        }
+
+        for player in left_hazard {
+            self.hazard_exposures.remove(player);
+        }
+        for player in slowed {
+            self.apply_status(player, StatusEffect::slow(WATER_SLOW_FRAMES, WATER_SLOW_PERCENT));
+        }
+        for player in damaged {
+            // Knocked back the way that the player came from
+            let direction = self.movements.get(player)
+                .map(|movement| movement.direction.opposite())
+                .unwrap_or(MovementDirection::South);
+            self.apply_damage(player, HAZARD_DAMAGE, direction);
+        }
+    }
+
+    /// Applies the status effect inflicted by the given attacker (if any) to the entity it hit
+    fn inflict_status(&mut self, attacker: Entity, target: Entity) {
+        if let Some(InflictsStatus(effect)) = self.inflicts_status.get(attacker) {
+            let effect = effect.clone();
+            self.apply_status(target, effect);
+        }
+    }
 
-            // Using the full boundary (regardless of the bounding box type) because we want
-            // entities to be found regardless of whether their full height is used in collision
-            // detection
-            let other_bounds = self.bounding_boxes.get(other)
-                .map(|b| b.to_full_rect(other_pos))
-                .unwrap_or_else(|| Rect::from_center(other_pos, 0, 0));
+    /// Applies the given status effect to the given entity
+    fn apply_status(&mut self, entity: Entity, effect: StatusEffect) {
+        self.status_effects.entry(entity)
+            .expect("bug: unable to get status effects of entity")
+            .or_insert_with(StatusEffects::default)
+            .apply(effect);
+    }
 
-            if direction_box.has_intersection(other_bounds) {
-                near.push((other, other_pos, other_bounds));
+    /// Damages every entity by the damage its status effects (e.g. poison) have done since the
+    /// last frame. This damage ignores invulnerability and never knocks the entity back.
+    pub fn status_effects_damage(&mut self) {
+        let mut damaged = Vec::new();
+        for (entity, effects, &HealthPoints(health)) in (&self.entities, &mut self.status_effects, &self.healths).join() {
+            let damage = effects.take_damage();
+            if damage > 0 && health > 0 {
+                damaged.push((entity, damage));
             }
         }
 
-        // Return result sorted by the distance *between* the boundary rectangles in the given
-        // direction
-        match direction {
-            North => near.sort_unstable_by_key(|(_, _, other_bounds)| {
-                (bounds.top() - other_bounds.bottom()).abs()
-            }),
-            South => near.sort_unstable_by_key(|(_, _, other_bounds)| {
-                (other_bounds.top() - bounds.bottom()).abs()
-            }),
-            East => near.sort_unstable_by_key(|(_, _, other_bounds)| {
-                (other_bounds.left() - bounds.right()).abs()
-            }),
-            West => near.sort_unstable_by_key(|(_, _, other_bounds)| {
-                (bounds.left() - other_bounds.right()).abs()
-            }),
+        for (entity, damage) in damaged {
+            self.lower_health(entity, damage);
+        }
+    }
+
+    /// Lowers the HealthPoints of the given entity by the given amount of damage and knocks it
+    /// back in the given direction. Entities that run out of health are removed.
+    ///
+    /// Returns false if the damage was ignored because the entity is invulnerable.
+    fn apply_damage(&mut self, entity: Entity, damage: usize, knockback_direction: MovementDirection) -> bool {
+        if self.invulnerables.get(entity).is_some() {
+            return false;
+        }
+
+        if !self.lower_health(entity, damage) {
+            return true;
+        }
+
+        self.actions.0.entry(entity).or_default().push(Action::Hit);
+        self.sounds.0.push(SoundEffect::Hit);
+        // Inserting replaces any existing knockback so repeated hits refresh it instead of
+        // accumulating it
+        let knockback_speed = scale_to_tile_size(KNOCKBACK_SPEED, self.map.tile_size());
+        self.knockbacks.insert(entity, Knockback::new(knockback_direction, knockback_speed, KNOCKBACK_FRAMES))
+            .expect("bug: unable to insert knockback");
+
+        if let Some(&HitInvulnerability(frames_remaining)) = self.hit_invulnerabilities.get(entity) {
+            self.invulnerables.insert(entity, Invulnerable {frames_remaining})
+                .expect("bug: unable to insert invulnerability");
+        }
+
+        true
+    }
+
+    /// Lowers the HealthPoints of the given entity by the given amount of damage, showing the
+    /// damage and recording it in the run statistics. Entities that run out of health are removed.
+    ///
+    /// Returns true if the entity still has health left.
+    fn lower_health(&mut self, entity: Entity, damage: usize) -> bool {
+        let HealthPoints(health) = self.healths.get_mut(entity)
+            .expect("bug: only entities with health points can take damage");
+        let damage = damage.min(*health);
+        if damage > 0 {
+            // The bar keeps the length it had the first time the entity was damaged
+            let max_health = self.health_bars.get(entity).map(|bar| bar.max_health).unwrap_or(*health);
+            self.health_bars.insert(entity, HealthBar::new(max_health))
+                .expect("bug: unable to insert health bar");
+        }
+        *health -= damage;
+        let health = *health;
+        if damage > 0 {
+            self.show_damage(entity, damage);
+        }
+
+        let is_breakable = self.breakables.get(entity).is_some();
+        if damage >= HEAVY_DAMAGE && !is_breakable {
+            if let Some(&Position(pos)) = self.positions.get(entity) {
+                self.decals.push(Decal::new(pos, DecalKind::Blood));
+            }
+        }
+
+        let is_player = self.players.get(entity).is_some();
+        if is_player && damage > 0 {
+            self.run_stats.floor.damage_taken += damage;
+            self.game_events.0.push(GameEvent::DamageTaken {amount: damage});
+            self.screen_shake.start(PLAYER_HIT_SHAKE_AMPLITUDE, PLAYER_HIT_SHAKE_FRAMES);
+        } else if !is_player {
+            self.run_stats.floor.damage_dealt += damage;
+        }
+
+        if health == 0 {
+            //TODO: Play the defeat animation instead of removing the entity right away. The player
+            // is not removed since the game cannot continue without it.
+            if !is_player {
+                self.entities.delete(entity)
+                    .expect("bug: unable to delete entity");
+                self.drop_item(entity);
+                if self.enemies.get(entity).is_some() {
+                    self.run_stats.floor.enemies_killed += 1;
+                    self.game_events.0.push(GameEvent::EnemyKilled);
+                }
+                if self.bosses.get(entity).is_some() {
+                    self.unseal_gates();
+                }
+            } else {
+                self.sounds.0.push(SoundEffect::PlayerDeath);
+            }
+            return false;
+        }
+
+        true
+    }
+
+    /// Removes the seal from every gate now that the boss has been defeated. The gates still need
+    /// the treasure key to be opened.
+    fn unseal_gates(&mut self) {
+        let sealed: Vec<_> = (&self.entities, &self.boss_seals).join().map(|(gate, _)| gate).collect();
+        for gate in sealed {
+            self.boss_seals.remove(gate);
+        }
+        self.notifications.push("Boss defeated!");
+    }
+
+    /// Counts down the invulnerability of every entity and removes it once it is complete
+    fn update_invulnerables(&mut self) {
+        let FramesElapsed(frames_elapsed) = *self.frames;
+        let mut vulnerable = Vec::new();
+        for (entity, invulnerable) in (&self.entities, &mut self.invulnerables).join() {
+            invulnerable.frames_remaining = invulnerable.frames_remaining.saturating_sub(frames_elapsed);
+            if invulnerable.frames_remaining == 0 {
+                vulnerable.push(entity);
+            }
+        }
+
+        for entity in vulnerable {
+            self.invulnerables.remove(entity);
+        }
+    }
+
+    /// Counts down the attack cooldown of every entity and removes it once its combo window is
+    /// also over
+    fn update_attack_cooldowns(&mut self) {
+        let FramesElapsed(frames_elapsed) = *self.frames;
+        let mut complete = Vec::new();
+        for (entity, cooldown) in (&self.entities, &mut self.attack_cooldowns).join() {
+            cooldown.step(frames_elapsed);
+            if cooldown.is_complete() {
+                complete.push(entity);
+            }
+        }
+
+        for entity in complete {
+            self.attack_cooldowns.remove(entity);
+        }
+    }
+
+    /// Counts down the time left before each health bar is hidden. The bars are kept once they
+    /// are hidden so that they remember the full health of the entity.
+    fn update_health_bars(&mut self) {
+        let FramesElapsed(frames_elapsed) = *self.frames;
+        for bar in (&mut self.health_bars).join() {
+            bar.frames_remaining = bar.frames_remaining.saturating_sub(frames_elapsed);
+        }
+    }
+
+    /// Fades the decals on the floor and removes any that have disappeared
+    fn update_decals(&mut self) {
+        let FramesElapsed(frames_elapsed) = *self.frames;
+        self.decals.step(frames_elapsed);
+    }
+
+    /// If the player is intersecting with a staircase, requests a change to the next/prev level.
+    /// Any followers that were escorted to the staircase are rescued.
+    ///
+    /// If the player is touching several staircases, the one that overlaps the player the most is
+    /// taken. Ties go to the staircase nearest the top left.
+    pub fn enter_stairs(&mut self) {
+        let mut escorts = Vec::new();
+        for (player, &Position(pos), bounds, _) in (&self.entities, &self.positions, &self.bounding_boxes, &self.players).join() {
+            let player_box = bounds.to_rect(pos);
+            let entered = self.spatial_grid.query(player_box).into_iter().filter_map(|other| {
+                let (&Position(other_pos), other_bounds) = match (self.positions.get(other), self.bounding_boxes.get(other), self.stairs.get(other)) {
+                    (Some(pos), Some(bounds), Some(_)) => (pos, bounds),
+                    _ => return None,
+                };
+                let overlap = player_box.intersection(other_bounds.to_rect(other_pos))?;
+                let area = overlap.width() * overlap.height();
+                Some(((Reverse(area), other_pos.y(), other_pos.x(), other.id()), other))
+            }).min_by_key(|&(key, _)| key);
+            let staircase = match entered {
+                Some((_, staircase)) => staircase,
+                None => continue,
+            };
+
+            let change = match self.stairs.get(staircase) {
+                Some(&Stairs::ToNextLevel {id}) => GameState::LevelTransition {to: LevelDirection::Next, id},
+                Some(&Stairs::ToPrevLevel {id}) => GameState::LevelTransition {to: LevelDirection::Prev, id},
+                None => unreachable!("bug: only staircases can be entered"),
+            };
+            // Only the first staircase entered changes the level
+            if self.game_state.transition(change).is_ok() {
+                self.sounds.0.push(SoundEffect::Stairs);
+                escorts.push((player, pos));
+            }
+        }
+
+        for (player, pos) in escorts {
+            self.rescue_escorted(player, pos);
+        }
+    }
+
+    /// Rescues every follower close enough to the given player position, rewarding the player
+    fn rescue_escorted(&mut self, player: Entity, pos: Point) {
+        let max_distance = ESCORT_DISTANCE * self.map.tile_size() as i32;
+        let rescued: Vec<_> = (&self.entities, &self.positions, &self.followers).join()
+            .filter(|&(_, &Position(follower_pos), _)| {
+                let diff = follower_pos - pos;
+                diff.x() * diff.x() + diff.y() * diff.y() <= max_distance * max_distance
+            })
+            .map(|(follower, _, _)| follower)
+            .collect();
+
+        for follower in rescued {
+            self.entities.delete(follower)
+                .expect("bug: unable to delete rescued follower");
+            self.run_stats.rescues += 1;
+            self.game_events.0.push(GameEvent::PrisonerRescued);
+            self.notifications.push("Prisoner rescued!");
+            self.heal(player, RESCUE_REWARD);
+        }
+    }
+
+    /// Drinks the first potion carried by the given entity, restoring as much health as the
+    /// strength of the potion. The potion is kept if the entity is already at full health.
+    fn drink_potion(&mut self, entity: Entity) {
+        let at_full_health = match (self.healths.get(entity), self.max_healths.get(entity)) {
+            (Some(&HealthPoints(health)), Some(&MaxHealthPoints(max_health))) => health >= max_health,
+            _ => false,
+        };
+        let inventory = match self.inventories.get_mut(entity) {
+            Some(inventory) => inventory,
+            None => return,
+        };
+        if !inventory.items.iter().any(|item| matches!(item, Item::Potion {..})) {
+            self.notifications.push("No potions");
+            return;
+        }
+        if at_full_health {
+            self.notifications.push("Already at full health");
+            return;
+        }
+
+        let strength = inventory.take_potion().expect("bug: potion should be carried");
+        self.heal(entity, strength as usize);
+        self.game_events.0.push(GameEvent::PotionDrunk);
+        self.notifications.push(format!("Drank a potion (+{} HP)", strength));
+    }
+
+    /// Restores the given amount of health to the given entity, up to its maximum health (if any)
+    fn heal(&mut self, entity: Entity, amount: usize) {
+        let max_health = self.max_healths.get(entity).map(|&MaxHealthPoints(max_health)| max_health);
+        if let Some(HealthPoints(health)) = self.healths.get_mut(entity) {
+            *health += amount;
+            if let Some(max_health) = max_health {
+                *health = (*health).min(max_health);
+            }
+        }
+    }
+
+    fn position_movement_bounds(&self, entity: Entity) -> (Point, MovementDirection, BoundingBox) {
+        match (self.positions.get(entity), self.movements.get(entity), self.bounding_boxes.get(entity)) {
+            (Some(&Position(pos)), Some(movement), Some(&bounds)) => (pos, movement.direction, bounds),
+            _ => unreachable!("bug: only entities with positions, movement directions, and a bounding box can interact"),
         }
+    }
 
-        near.into_iter().map(|(other, other_pos, _)| (other, other_pos))
+    /// Returns the nearest entities that match the given query. Only entities that are up to
+    /// `range` away and at most `margin` past either side of the entity are returned. Result is
+    /// sorted nearest to farthest.
+    fn nearest_in_direction(&self, entity: Entity, query: DirectionQuery) -> Vec<(Entity, Point)> {
+        nearest_in_direction(|region| self.spatial_grid.query(region), &self.positions,
+            &self.bounding_boxes, entity, query)
     }
 }
 
@@ -176,6 +881,11 @@ impl<'a> System<'a> for Interactions {
     fn run(&mut self, mut data: Self::SystemData) {
         // Cloning this isn't great, but it's the only way to get around borrowing issues since
         // Rust doesn't do per-field mutability
+        data.update_invulnerables();
+        data.update_attack_cooldowns();
+        data.update_health_bars();
+        data.update_decals();
+
         let actions = data.actions.0.clone();
         for (entity, actions) in actions.into_iter() {
             for action in actions {
@@ -183,38 +893,1152 @@ impl<'a> System<'a> for Interactions {
                 match action {
                     Interact => data.interact_with_adjacent(entity),
                     Attack => data.attack_adjacent(entity),
+                    DrinkPotion => data.drink_potion(entity),
                     // None of these require interaction with an adjacent tile
                     Hit | Victory | Defeat => {},
                 }
             }
         }
 
-        let InteractionsData {
-            entities,
-            mut change_game_state,
-            positions,
-            bounding_boxes,
-            players,
-            stairs,
-            ..
-        } = data;
+        data.enemies_attack_on_contact();
+        data.projectiles_hit();
+        data.hazards_damage_players();
+        data.status_effects_damage();
+        data.collect_pickups();
+        data.enter_stairs();
+    }
+}
 
-        // If the player is intersecting with anything interesting, we may be need to do something
-        for (&Position(pos), bounds, _) in (&positions, &bounding_boxes, &players).join() {
-            let player_box = bounds.to_rect(pos);
-            for (other_entity, &Position(other_pos), other_bounds, ()) in (&*entities, &positions, &bounding_boxes, !&players).join() {
-                let other_box = other_bounds.to_rect(other_pos);
-                if player_box.has_intersection(other_box) {
-                    // If player entered a staircase, we need to move to the next/prev level
-                    if let Some(staircase) = stairs.get(other_entity) {
-                        let change = match staircase {
-                            &Stairs::ToNextLevel {id} => GameState::GoToNextLevel {id},
-                            &Stairs::ToPrevLevel {id} => GameState::GoToPrevLevel {id},
-                        };
-                        change_game_state.replace(change);
-                    }
+impl<'a> JoinedEntities<'a> for Interactions {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        // Collisions, attacks and pickups are all checked between entities with a position and a
+        // bounding box
+        (&data.positions, &data.bounding_boxes).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder, RunNow};
+
+    use crate::components::{EnemyBehaviour, StatusKind, WeaponKind};
+    use crate::systems::{Physics, FloatingTexts};
+    use crate::resources::{Event, Key};
+    use crate::generator::EnemyValues;
+    use crate::test_helpers::{TestWorld, level_world, walled_room, test_animations};
+    use crate::map::{GridSize, TilePos, Tile, Hazard};
+    use crate::map_sprites::WallSprite;
+
+    fn setup_world(map: FloorMap) -> World {
+        let mut world = level_world(map);
+        System::setup(&mut Physics, &mut world.res);
+        System::setup(&mut Interactions, &mut world.res);
+        // Damage creates floating text
+        System::setup(&mut FloatingTexts, &mut world.res);
+        world
+    }
+
+    fn health(world: &World, entity: Entity) -> usize {
+        world.read_storage::<HealthPoints>().get(entity).unwrap().0
+    }
+
+    #[test]
+    fn doors_toggle_open_and_closed() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size));
+        let door = world.create_entity()
+            .with(Door::Closed)
+            .with(Position(TilePos {row: 1, col: 2}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        let start = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(Position(start))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        // Walks east from the start and returns true if the door stopped the player
+        let is_blocked = |world: &mut World| {
+            world.write_storage::<Position>().insert(player, Position(start)).unwrap();
+            world.write_storage::<Movement>().get_mut(player).unwrap().speed = 4.0;
+            for _ in 0..10 {
+                Physics.run_now(&world.res);
+                world.maintain();
+            }
+            world.write_storage::<Movement>().get_mut(player).unwrap().speed = 0.0;
+            let Position(pos) = *world.read_storage::<Position>().get(player).unwrap();
+            pos.x() < TilePos {row: 1, col: 2}.center(tile_size as i32).x()
+        };
+        let interact = |world: &mut World| {
+            world.write_storage::<Position>().insert(player, Position(start)).unwrap();
+            *world.write_resource() = ActionQueue::default();
+            world.write_resource::<ActionQueue>().0.insert(player, vec![Action::Interact]);
+            // Interactions only find entities that were added to the spatial grid by physics
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+            *world.read_storage::<Door>().get(door).unwrap()
+        };
+
+        assert!(is_blocked(&mut world));
+        assert_eq!(interact(&mut world), Door::Open);
+        assert!(!is_blocked(&mut world));
+        assert_eq!(interact(&mut world), Door::Closed);
+        assert!(is_blocked(&mut world));
+
+        // Cannot close a door on something in the doorway
+        assert_eq!(interact(&mut world), Door::Open);
+        world.create_entity()
+            .with(Position(TilePos {row: 1, col: 2}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        assert_eq!(interact(&mut world), Door::Open);
+    }
+
+    #[test]
+    fn damage_ignored_while_invulnerable() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(100))
+            .with(HitInvulnerability(30))
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        // Damage the player on every single frame
+        let mut hit_frames = Vec::new();
+        for frame in 0..=60 {
+            *world.write_resource() = ActionQueue::default();
+            Interactions.run_now(&world.res);
+            world.maintain();
+
+            let before = health(&world, player);
+            {
+                let mut data: InteractionsData = world.system_data();
+                data.apply_damage(player, 5, MovementDirection::East);
+            }
+            world.maintain();
+            if health(&world, player) < before {
+                hit_frames.push(frame);
+            }
+        }
+
+        // HP only drops once per invulnerability window
+        assert_eq!(hit_frames, vec![0, 30, 60]);
+        assert_eq!(health(&world, player), 85);
+        // The last hit shakes the screen
+        assert_ne!(world.read_resource::<ScreenShake>().offset(), Point::new(0, 0));
+    }
+
+    #[test]
+    fn health_bar_shown_after_damage() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+        let enemy = world.create_entity()
+            .with(Enemy {speed: 0.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(HealthPoints(40))
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+        let hit = |world: &mut World, damage| {
+            let mut data: InteractionsData = world.system_data();
+            data.apply_damage(enemy, damage, MovementDirection::East);
+        };
+        let bar = |world: &World| world.read_storage::<HealthBar>().get(enemy).cloned();
+        let run_frames = |world: &mut World, frames| {
+            for _ in 0..frames {
+                *world.write_resource() = ActionQueue::default();
+                Interactions.run_now(&world.res);
+                world.maintain();
+            }
+        };
+
+        // No bar until the enemy is actually damaged
+        hit(&mut world, 0);
+        assert_eq!(bar(&world), None);
+        hit(&mut world, 10);
+        let shown = bar(&world).unwrap();
+        assert_eq!(shown, HealthBar::new(40));
+        assert_eq!(shown.fraction(health(&world, enemy)), 0.75);
+        assert_eq!(shown.alpha(), 255);
+
+        // The bar fades and then disappears
+        run_frames(&mut world, HealthBar::VISIBLE_FRAMES - 15);
+        let fading = bar(&world).unwrap();
+        assert!(fading.alpha() > 0 && fading.alpha() < 255);
+        run_frames(&mut world, 15);
+        assert_eq!(bar(&world).unwrap().alpha(), 0);
+
+        // Taking damage again shows the bar with the same full length
+        hit(&mut world, 10);
+        let shown = bar(&world).unwrap();
+        assert_eq!(shown, HealthBar::new(40));
+        assert_eq!(shown.fraction(health(&world, enemy)), 0.5);
+    }
+
+    #[test]
+    fn damage_shown_as_floating_text() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+        let pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let enemy = world.create_entity()
+            .with(Enemy {speed: 0.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(HealthPoints(15))
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+        let hit = |world: &mut World, damage| {
+            {
+                let mut data: InteractionsData = world.system_data();
+                data.apply_damage(enemy, damage, MovementDirection::East);
+            }
+            world.maintain();
+        };
+        let texts = |world: &World| (&world.read_storage::<Position>(), &world.read_storage::<FloatingText>()).join()
+            .map(|(&Position(pos), text)| (pos, text.text.clone()))
+            .collect::<Vec<_>>();
+
+        hit(&mut world, 0);
+        assert_eq!(texts(&world), &[]);
+        hit(&mut world, 10);
+        let top = Point::new(pos.x(), pos.y() - tile_size as i32 / 2);
+        assert_eq!(texts(&world), &[(top, "10".to_string())]);
+
+        // The killing blow is shown even though the enemy is removed right away
+        hit(&mut world, 10);
+        assert!(!world.is_alive(enemy));
+        assert_eq!(texts(&world), &[(top, "10".to_string()), (top, "5".to_string())]);
+    }
+
+    #[test]
+    fn heavy_hits_leave_blood() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+        let pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let enemy = world.create_entity()
+            .with(Enemy {speed: 0.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(HealthPoints(40))
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+        let hit = |world: &mut World, damage| {
+            let mut data: InteractionsData = world.system_data();
+            data.apply_damage(enemy, damage, MovementDirection::East);
+        };
+        let decals = |world: &World| world.read_resource::<DecalBuffer>().iter().cloned().collect::<Vec<_>>();
+
+        hit(&mut world, HEAVY_DAMAGE - 1);
+        assert_eq!(decals(&world), &[]);
+        hit(&mut world, HEAVY_DAMAGE);
+        assert_eq!(decals(&world), &[Decal::new(pos, DecalKind::Blood)]);
+
+        // Decals fade away as the level is updated
+        for _ in 0..DecalKind::Blood.frames() {
+            *world.write_resource() = ActionQueue::default();
+            Interactions.run_now(&world.res);
+            world.maintain();
+        }
+        assert_eq!(decals(&world), &[]);
+    }
+
+    #[test]
+    fn enemies_wait_between_hits() {
+        let tile_size = 16;
+        let hit_wait = 10;
+        let mut map = FloorMap::new(GridSize {rows: 3, cols: 4}, tile_size);
+        // Wall to the east of the player so that knockback cannot separate them
+        for row in 0..3 {
+            map.grid_mut().place_tile(TilePos {row, col: 2}, Tile::new_wall(WallSprite::default()));
+        }
+        let mut world = setup_world(map);
+
+        let player_pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(100))
+            .with(Position(player_pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+        // Touching the player from the west (bounding boxes overlap within the collision
+        // threshold, just like entities pushed together by physics)
+        world.create_entity()
+            .with(Enemy {speed: 0.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(Attack(1))
+            .with(HitWait(hit_wait))
+            .with(Position(player_pos.offset(-(tile_size as i32) + 2, 0)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        let mut hit_frames = Vec::new();
+        for frame in 0..40 {
+            *world.write_resource() = ActionQueue::default();
+            let before = health(&world, player);
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+            if health(&world, player) < before {
+                hit_frames.push(frame);
+            }
+        }
+
+        assert!(hit_frames.len() > 1, "bug: enemy should have hit more than once");
+        for hits in hit_frames.windows(2) {
+            assert!(hits[1] - hits[0] >= hit_wait, "enemy hit again after only {} frames", hits[1] - hits[0]);
+        }
+
+        // Every hit counts towards the damage taken on this floor
+        assert_eq!(world.read_resource::<RunStats>().floor.damage_taken, 100 - health(&world, player));
+    }
+
+    #[test]
+    fn escorted_followers_rescued_at_stairs() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 30}, tile_size));
+
+        let stairs_pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        world.create_entity()
+            .with(Ghost)
+            .with(Position(stairs_pos))
+            .with(BoundingBox::Full {width: tile_size / 2, height: tile_size / 2})
+            .with(Stairs::ToNextLevel {id: 0})
+            .build();
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(5))
+            .with(Position(stairs_pos))
+            .with(BoundingBox::BottomHalf {width: tile_size, height: tile_size / 2})
+            .with(Movement::default())
+            .build();
+        let escorted = world.create_entity()
+            .with(Follower)
+            .with(Position(TilePos {row: 1, col: 3}.center(tile_size as i32)))
+            .build();
+        let left_behind = world.create_entity()
+            .with(Follower)
+            .with(Position(TilePos {row: 1, col: 20}.center(tile_size as i32)))
+            .build();
+
+        Physics.run_now(&world.res);
+        Interactions.run_now(&world.res);
+        world.maintain();
+
+        assert_eq!(world.read_resource::<GameStateMachine>().current(), GameState::LevelTransition {to: LevelDirection::Next, id: 0});
+        assert!(!world.is_alive(escorted));
+        assert!(world.is_alive(left_behind));
+        assert_eq!(world.read_resource::<RunStats>().rescues, 1);
+        assert_eq!(health(&world, player), 5 + RESCUE_REWARD);
+    }
+
+    #[test]
+    fn rescue_reward_capped_at_max_health() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 30}, tile_size));
+
+        let stairs_pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        world.create_entity()
+            .with(Ghost)
+            .with(Position(stairs_pos))
+            .with(BoundingBox::Full {width: tile_size / 2, height: tile_size / 2})
+            .with(Stairs::ToNextLevel {id: 0})
+            .build();
+        // Already at full health
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(20))
+            .with(MaxHealthPoints(20))
+            .with(Position(stairs_pos))
+            .with(BoundingBox::BottomHalf {width: tile_size, height: tile_size / 2})
+            .with(Movement::default())
+            .build();
+        for &col in &[2, 3] {
+            world.create_entity()
+                .with(Follower)
+                .with(Position(TilePos {row: 1, col}.center(tile_size as i32)))
+                .build();
+        }
+
+        Physics.run_now(&world.res);
+        Interactions.run_now(&world.res);
+        world.maintain();
+
+        assert_eq!(world.read_resource::<RunStats>().rescues, 2);
+        assert_eq!(health(&world, player), 20);
+    }
+
+    #[test]
+    fn drinking_potion_heals_up_to_max_health() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+
+        let mut inventory = Inventory::default();
+        inventory.add(Item::Potion {stength: 5});
+        inventory.add(Item::Potion {stength: 5});
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(12))
+            .with(MaxHealthPoints(20))
+            .with(inventory)
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .with(BoundingBox::BottomHalf {width: tile_size, height: tile_size / 2})
+            .with(Movement::default())
+            .build();
+
+        let drink = |world: &mut World| {
+            world.write_resource::<ActionQueue>().0.insert(player, vec![Action::DrinkPotion]);
+            Interactions.run_now(&world.res);
+            world.maintain();
+        };
+        let potions = |world: &World| world.read_storage::<Inventory>().get(player).unwrap().items.len();
+
+        drink(&mut world);
+        assert_eq!(health(&world, player), 17);
+        assert_eq!(potions(&world), 1);
+        assert_eq!(world.read_resource::<GameEvents>().0, &[GameEvent::PotionDrunk]);
+
+        // The second potion only heals up to the maximum health
+        drink(&mut world);
+        assert_eq!(health(&world, player), 20);
+        assert_eq!(potions(&world), 0);
+
+        // Nothing happens without any potions left
+        world.write_storage::<HealthPoints>().insert(player, HealthPoints(3)).unwrap();
+        drink(&mut world);
+        assert_eq!(health(&world, player), 3);
+        assert_eq!(world.read_resource::<GameEvents>().0.len(), 2);
+    }
+
+    #[test]
+    fn potion_kept_at_full_health() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 3}, tile_size));
+
+        let mut inventory = Inventory::default();
+        inventory.add(Item::Potion {stength: 5});
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(20))
+            .with(MaxHealthPoints(20))
+            .with(inventory)
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .build();
+
+        world.write_resource::<ActionQueue>().0.insert(player, vec![Action::DrinkPotion]);
+        Interactions.run_now(&world.res);
+        world.maintain();
+
+        assert_eq!(health(&world, player), 20);
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::Potion {stength: 5}]);
+    }
+
+    #[test]
+    fn followers_not_rescued_without_stairs() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size));
+
+        world.create_entity()
+            .with(Player)
+            .with(HealthPoints(5))
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .with(BoundingBox::BottomHalf {width: tile_size, height: tile_size / 2})
+            .with(Movement::default())
+            .build();
+        let follower = world.create_entity()
+            .with(Follower)
+            .with(Position(TilePos {row: 1, col: 2}.center(tile_size as i32)))
+            .build();
+
+        Interactions.run_now(&world.res);
+        world.maintain();
+
+        assert!(world.is_alive(follower));
+        assert_eq!(world.read_resource::<RunStats>().rescues, 0);
+    }
+
+    #[test]
+    fn player_takes_stairs() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let stairs_pos = test.tile_center(TilePos {row: 2, col: 5});
+        test.world.create_entity()
+            .with(Ghost)
+            .with(Position(stairs_pos))
+            .with(BoundingBox::Full {width: tile_size / 2, height: tile_size / 2})
+            .with(Stairs::ToNextLevel {id: 3})
+            .build();
+        test.spawn_player_at(TilePos {row: 2, col: 2});
+
+        assert_eq!(test.step(10), None);
+        assert_eq!(test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]), None);
+        assert_eq!(test.step(60), Some(GameState::LevelTransition {to: LevelDirection::Next, id: 3}));
+    }
+
+    #[test]
+    fn treasure_key_unlocks_gate() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size));
+        let beside_player = TilePos {row: 1, col: 2}.center(tile_size as i32);
+        let start = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(Position(start))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        let interact = |world: &mut World| {
+            world.write_storage::<Position>().insert(player, Position(start)).unwrap();
+            *world.write_resource() = ActionQueue::default();
+            world.write_resource::<ActionQueue>().0.insert(player, vec![Action::Interact]);
+            // Interactions only find entities that were added to the spatial grid by physics
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+        };
+        let add_gate = |world: &mut World| world.create_entity()
+            .with(Door::Closed)
+            .with(Gate)
+            .with(Locked)
+            .with(Position(beside_player))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+
+        // Cannot open the gate without the key
+        let gate = add_gate(&mut world);
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Closed);
+        world.delete_entity(gate).unwrap();
+
+        let chest = world.create_entity()
+            .with(Chest::Item(Item::TreasureKey))
+            .with(Position(beside_player))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Chest>().get(chest).unwrap(), Chest::Opened);
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::TreasureKey]);
+        // An opened chest is empty
+        interact(&mut world);
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::TreasureKey]);
+        let chests_opened = world.read_resource::<GameEvents>().0.iter()
+            .filter(|&&event| event == GameEvent::ChestOpened).count();
+        assert_eq!(chests_opened, 1);
+        assert_eq!(world.read_resource::<RunStats>().floor.secrets_found, 1);
+        world.delete_entity(chest).unwrap();
+
+        // The key is used up by opening the gate, which then stays unlocked
+        let gate = add_gate(&mut world);
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Open);
+        assert!(world.read_storage::<Locked>().get(gate).is_none());
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[]);
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Closed);
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Open);
+    }
+
+    #[test]
+    fn boss_death_unseals_gate() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size));
+        let start = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(Position(start))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .with(Inventory {items: vec![Item::TreasureKey]})
+            .build();
+        let gate = world.create_entity()
+            .with(Door::Closed)
+            .with(Gate)
+            .with(Locked)
+            .with(BossSeal)
+            .with(Position(TilePos {row: 1, col: 2}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        let boss = world.create_entity()
+            .with(Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None})
+            .with(Boss {max_health: 5})
+            .with(HealthPoints(5))
+            .with(Position(TilePos {row: 1, col: 6}.center(tile_size as i32)))
+            .build();
+
+        let interact = |world: &mut World| {
+            *world.write_resource() = ActionQueue::default();
+            world.write_resource::<ActionQueue>().0.insert(player, vec![Action::Interact]);
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+        };
+
+        // Even the key cannot open the gate while the boss is alive, and the key is not used up
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Closed);
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::TreasureKey]);
+
+        world.system_data::<InteractionsData<'_>>().lower_health(boss, 5);
+        world.maintain();
+        assert!(!world.is_alive(boss));
+        assert!(world.read_storage::<BossSeal>().get(gate).is_none());
+        // Still needs the key
+        assert!(world.read_storage::<Locked>().get(gate).is_some());
+
+        interact(&mut world);
+        assert_eq!(*world.read_storage::<Door>().get(gate).unwrap(), Door::Open);
+        assert_eq!(world.read_storage::<Inventory>().get(player).unwrap().items, &[]);
+    }
+
+    #[test]
+    fn attack_opens_adjacent_door() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let door_pos = test.tile_center(TilePos {row: 2, col: 3});
+        let door = test.world.create_entity()
+            .with(Door::Closed)
+            .with(Position(door_pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+
+        // Walk up to the door so the player is facing it
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(5);
+        test.step_with_events(vec![Event::KeyUp(Key::RightArrow)]);
+        assert!(test.position(player).x() < door_pos.x());
+        assert_eq!(*test.world.read_storage::<Door>().get(door).unwrap(), Door::Closed);
+
+        test.step_with_events(vec![Event::KeyUp(Key::B)]);
+        // The door is opened rather than destroyed
+        assert!(test.world.is_alive(door));
+        assert_eq!(*test.world.read_storage::<Door>().get(door).unwrap(), Door::Open);
+    }
+
+    #[test]
+    fn pointer_interact_faces_clicked_door() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let door_pos = test.tile_center(TilePos {row: 1, col: 2});
+        let door = test.world.create_entity()
+            .with(Door::Closed)
+            .with(Position(door_pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        // The player starts out right below the door, facing away from it
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        test.world.write_storage::<Position>().insert(player, Position(door_pos + Point::new(0, 10))).unwrap();
+        test.world.write_storage::<Movement>().get_mut(player).unwrap().direction = MovementDirection::South;
+
+        // Clicking anywhere above the player is enough to turn towards the door
+        test.step_with_events(vec![Event::PointerInteract(door_pos + Point::new(3, -20))]);
+        assert_eq!(test.world.read_storage::<Movement>().get(player).unwrap().direction, MovementDirection::North);
+        assert_eq!(*test.world.read_storage::<Door>().get(door).unwrap(), Door::Open);
+    }
+
+    #[test]
+    fn enemies_hit_player_on_contact() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        // An enemy that never moves, so the player has to walk into it
+        test.spawn_enemy_at(TilePos {row: 2, col: 4}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 3,
+            speed: 0.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: tile_size, height: tile_size},
+        });
+
+        test.step(5);
+        assert_eq!(health(&test.world, player), 20);
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(20);
+        assert!(health(&test.world, player) < 20);
+        assert_eq!(test.world.read_resource::<RunStats>().floor.damage_taken, 20 - health(&test.world, player));
+    }
+
+    #[test]
+    fn enemy_attacks_and_water_inflict_status() {
+        let tile_size = 16;
+        let water = TilePos {row: 2, col: 2};
+        let mut map = walled_room(5, 5, tile_size);
+        map.grid_mut().get_mut(water).set_hazard(Hazard::Water);
+        let mut world = setup_world(map);
+        let pos = water.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(20))
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+        world.create_entity()
+            .with(Enemy {behaviour: EnemyBehaviour::Random, speed: 0.0, home_room: None})
+            .with(HealthPoints(10))
+            .with(Attack(3))
+            .with(InflictsStatus(StatusEffect::poison(90, 1)))
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        // Physics finds the enemies touching the player
+        Physics.run_now(&world.res);
+        Interactions.run_now(&world.res);
+        world.maintain();
+
+        assert_eq!(health(&world, player), 17);
+        let effects = world.read_storage::<StatusEffects>();
+        let effects = effects.get(player).unwrap();
+        assert_eq!(effects.get(StatusKind::Poison).unwrap().frames_remaining, 90);
+        assert_eq!(effects.get(StatusKind::Slow).unwrap().magnitude, WATER_SLOW_PERCENT);
+    }
+
+    #[test]
+    fn projectile_damages_once_and_never_its_owner() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 10, tile_size);
+        let mut target_at = |pos: TilePos| test.world.create_entity()
+            .with(HealthPoints(30))
+            .with(Position(pos.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        let owner = target_at(TilePos {row: 2, col: 2});
+        let target = target_at(TilePos {row: 2, col: 6});
+        // Fired from inside of its owner straight at the target
+        let arrow = test.world.create_entity()
+            .with(Position(TilePos {row: 2, col: 2}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: 6, height: 6})
+            .with(Ghost)
+            .with(Projectile {velocity: Point::new(4, 0), damage: 4, owner: Some(owner)})
+            .build();
+
+        test.step(30);
+        assert!(!test.world.is_alive(arrow));
+        assert_eq!(health(&test.world, owner), 30);
+        assert_eq!(health(&test.world, target), 26);
+    }
+
+    #[test]
+    fn hazards_damage_player_on_cadence() {
+        let tile_size = 16;
+        let hazard = TilePos {row: 2, col: 2};
+        let mut map = walled_room(5, 5, tile_size);
+        map.grid_mut().get_mut(hazard).set_hazard(Hazard::Water);
+        let mut world = setup_world(map);
+        let player = world.create_entity()
+            .with(Player)
+            .with(HealthPoints(20))
+            .with(HitInvulnerability(30))
+            .with(Position(hazard.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        // Returns the frames (counting from 1) on which the player took damage
+        let run = |world: &mut World, frames: usize| -> Vec<usize> {
+            (1..=frames).filter(|_| {
+                let before = health(world, player);
+                *world.write_resource() = ActionQueue::default();
+                Interactions.run_now(&world.res);
+                world.maintain();
+                health(world, player) < before
+            }).collect()
+        };
+
+        assert_eq!(run(&mut world, HAZARD_DAMAGE_FRAMES * 2 + 10), &[HAZARD_DAMAGE_FRAMES, HAZARD_DAMAGE_FRAMES * 2]);
+        assert_eq!(health(&world, player), 20 - HAZARD_DAMAGE * 2);
+
+        // Stepping off of the hazard starts the count over, so the time already spent on it does
+        // not count towards the next tick
+        let safe = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        world.write_storage().insert(player, Position(safe)).unwrap();
+        assert!(run(&mut world, 5).is_empty());
+        world.write_storage().insert(player, Position(hazard.center(tile_size as i32))).unwrap();
+        assert_eq!(run(&mut world, HAZARD_DAMAGE_FRAMES), &[HAZARD_DAMAGE_FRAMES]);
+
+        // Several ticks can pass in a single (slow) frame
+        *world.write_resource() = FramesElapsed(HAZARD_DAMAGE_FRAMES * 2);
+        Interactions.run_now(&world.res);
+        world.maintain();
+        // Only the first lands since the player is invulnerable right after being hit
+        assert_eq!(health(&world, player), 20 - HAZARD_DAMAGE * 4);
+    }
+
+    #[test]
+    fn attacks_dropped_during_cooldown_and_combo_lands() {
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 3, cols: 6}, tile_size);
+        // Wall to the east of the target so that knockback cannot push it out of range
+        for row in 0..3 {
+            map.grid_mut().place_tile(TilePos {row, col: 3}, Tile::new_wall(WallSprite::default()));
+        }
+        let mut world = setup_world(map);
+
+        let player_pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let animations = test_animations();
+        let cooldown = animations.attack_right.len();
+        let player = world.create_entity()
+            .with(Player)
+            .with(Attack(10))
+            .with(Position(player_pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement {direction: MovementDirection::East, sideways: None, speed: 0.0, ..Movement::default()})
+            .with(animations)
+            .build();
+        let target = world.create_entity()
+            .with(HealthPoints(1000))
+            .with(Position(player_pos.offset(tile_size as i32, 0)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        // Runs a single frame, attacking if requested, and returns the damage done to the target
+        let frame = |world: &mut World, attack: bool| {
+            let mut actions = ActionQueue::default();
+            if attack {
+                actions.0.insert(player, vec![Action::Attack]);
+            }
+            *world.write_resource() = actions;
+            let before = health(world, target);
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+            before - health(world, target)
+        };
+
+        // Mashing the attack key only lands one attack while the animation plays
+        let mut damage = Vec::new();
+        for _ in 0..cooldown {
+            damage.push(frame(&mut world, true));
+        }
+        assert_eq!(damage[0], 10);
+        assert!(damage[1..].iter().all(|&d| d == 0), "attacks landed during cooldown: {:?}", damage);
+
+        // Attacking right after the cooldown ends is a combo hit
+        assert_eq!(frame(&mut world, true), 15);
+        // A combo only has two hits, so waiting out the cooldown again leads to a normal hit
+        for _ in 1..cooldown {
+            assert_eq!(frame(&mut world, false), 0);
+        }
+        assert_eq!(frame(&mut world, true), 10);
+
+        // Missing the combo window leads to another normal hit
+        for _ in 0..cooldown + COMBO_WINDOW_FRAMES {
+            assert_eq!(frame(&mut world, false), 0);
+        }
+        assert!(world.read_storage::<AttackCooldown>().get(player).is_none());
+        assert_eq!(frame(&mut world, true), 10);
+    }
+
+    #[test]
+    fn spear_reaches_enemies_the_dagger_misses() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size));
+
+        let player_pos = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let player = world.create_entity()
+            .with(Player)
+            .with(Attack(10))
+            .with(Equipment {weapon: WeaponKind::Dagger.weapon()})
+            .with(Position(player_pos))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement {direction: MovementDirection::East, sideways: None, speed: 0.0, ..Movement::default()})
+            .with(test_animations())
+            .build();
+        // One and a half tiles between the player and the target
+        let target = world.create_entity()
+            .with(HealthPoints(1000))
+            .with(Position(player_pos.offset(tile_size as i32 * 5 / 2, 0)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement::default())
+            .build();
+
+        // Runs a single frame with the given action and returns the damage done to the target
+        let frame = |world: &mut World, action: Action| {
+            let mut actions = ActionQueue::default();
+            actions.0.insert(player, vec![action]);
+            *world.write_resource() = actions;
+            // Every attack starts without any cooldown left from the last one
+            world.write_storage::<AttackCooldown>().remove(player);
+            let before = health(world, target);
+            Physics.run_now(&world.res);
+            Interactions.run_now(&world.res);
+            world.maintain();
+            before - health(world, target)
+        };
+
+        assert_eq!(frame(&mut world, Action::Attack), 0);
+
+        // Weapons found in chests are equipped right away instead of going into the inventory
+        let chest = world.create_entity()
+            .with(Chest::Item(Item::Weapon(WeaponKind::Spear)))
+            .with(Position(player_pos.offset(tile_size as i32, 0)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .build();
+        frame(&mut world, Action::Interact);
+        assert_eq!(world.read_storage::<Equipment>().get(player).unwrap().weapon.kind, WeaponKind::Spear);
+        assert!(world.read_storage::<Inventory>().get(player).iter().all(|inventory| inventory.items.is_empty()));
+        world.delete_entity(chest).unwrap();
+
+        assert_eq!(frame(&mut world, Action::Attack), WeaponKind::Spear.weapon().scale_damage(10));
+    }
+
+    #[test]
+    fn attack_cooldown_depends_on_weapon() {
+        let tile_size = 16;
+        let mut world = setup_world(FloorMap::new(GridSize {rows: 3, cols: 6}, tile_size));
+        let animations = test_animations();
+        let attack_frames = animations.attack_right.len();
+        let player = world.create_entity()
+            .with(Player)
+            .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement {direction: MovementDirection::East, sideways: None, speed: 0.0, ..Movement::default()})
+            .with(animations)
+            .build();
+
+        let cooldowns: Vec<_> = WeaponKind::ALL.iter().map(|&kind| {
+            world.write_storage().insert(player, Equipment {weapon: kind.weapon()}).unwrap();
+            world.write_storage::<AttackCooldown>().remove(player);
+            *world.write_resource() = ActionQueue::default();
+            world.write_resource::<ActionQueue>().0.insert(player, vec![Action::Attack]);
+            Interactions.run_now(&world.res);
+            world.maintain();
+            world.read_storage::<AttackCooldown>().get(player).unwrap().frames_remaining
+        }).collect();
+
+        // Dagger, sword and spear, from fastest to slowest. The sword takes as long as the attack
+        // animation, just like attacking without a weapon.
+        assert!(cooldowns[0] < cooldowns[1] && cooldowns[1] < cooldowns[2], "{:?}", cooldowns);
+        assert_eq!(cooldowns[1], attack_frames);
+    }
+
+    #[test]
+    fn outcome_independent_of_creation_order() {
+        use std::collections::HashSet;
+
+        use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+        use specs::EntityBuilder;
+
+        use crate::systems::AI;
+
+        let tile_size = 16;
+        let center = TilePos {row: 3, col: 4}.center(tile_size as i32);
+        // A player surrounded by enemies that all touch it at once, standing on two staircases.
+        // Two more enemies wander around too far away to see the player.
+        let create = |world: &mut World, index: usize| {
+            let builder = world.create_entity();
+            fn enemy(builder: EntityBuilder<'_>, pos: Point, attack: usize, hit_wait: usize) -> EntityBuilder<'_> {
+                builder
+                    .with(Enemy {speed: 0.0, behaviour: EnemyBehaviour::Random, home_room: None})
+                    .with(HealthPoints(50))
+                    .with(Attack(attack))
+                    .with(HitWait(hit_wait))
+                    .with(Position(pos))
+                    .with(BoundingBox::Full {width: 16, height: 16})
+                    .with(Movement::default())
+            }
+            fn stairs(builder: EntityBuilder<'_>, pos: Point, id: usize) -> EntityBuilder<'_> {
+                builder
+                    .with(Ghost)
+                    .with(Stairs::ToNextLevel {id})
+                    .with(Position(pos))
+                    .with(BoundingBox::Full {width: 8, height: 8})
+            }
+            match index {
+                0 => builder
+                    .with(Player)
+                    .with(HealthPoints(200))
+                    .with(Attack(5))
+                    .with(HitInvulnerability(3))
+                    .with(Position(center))
+                    .with(BoundingBox::Full {width: tile_size, height: tile_size})
+                    .with(Movement {direction: MovementDirection::East, sideways: None, speed: 0.0, ..Movement::default()}),
+                1 => enemy(builder, center.offset(-14, 0), 2, 5),
+                2 => enemy(builder, center.offset(14, 0), 2, 7),
+                3 => enemy(builder, center.offset(0, -14), 3, 4),
+                4 => enemy(builder, center.offset(0, 14), 3, 6),
+                5 => stairs(builder, center.offset(-4, 4), 0),
+                6 => stairs(builder, center.offset(4, 4), 1),
+                7 | 8 => {
+                    let tile = if index == 7 { TilePos {row: 1, col: 20} } else { TilePos {row: 5, col: 24} };
+                    enemy(builder, tile.center(tile_size as i32), 1, 5)
+                        .with(Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None})
+                },
+                _ => unreachable!(),
+            }.build()
+        };
+
+        // Runs the scenario with the entities created in the given order and returns the state of
+        // every entity and the requested level change after each frame
+        let run = |order: &[usize]| {
+            let mut world = setup_world(FloorMap::new(GridSize {rows: 7, cols: 30}, tile_size));
+            System::setup(&mut AI, &mut world.res);
+            let mut entities = vec![None; order.len()];
+            for &index in order {
+                entities[index] = Some(create(&mut world, index));
+            }
+            let entities: Vec<_> = entities.into_iter().map(Option::unwrap).collect();
+            let player = entities[0];
+
+            let mut frames = Vec::new();
+            for frame in 0..100 {
+                let mut actions = ActionQueue::default();
+                if frame % 7 == 0 {
+                    actions.0.insert(player, vec![Action::Attack]);
                 }
+                *world.write_resource() = actions;
+                *world.write_resource() = GameStateMachine::default();
+                AI.run_now(&world.res);
+                Physics.run_now(&world.res);
+                Interactions.run_now(&world.res);
+                world.maintain();
+
+                let states: Vec<_> = entities.iter().map(|&entity| {
+                    let pos = world.read_storage::<Position>().get(entity).map(|&Position(pos)| pos);
+                    let health = world.read_storage::<HealthPoints>().get(entity).map(|&HealthPoints(health)| health);
+                    (pos, health)
+                }).collect();
+                frames.push((states, world.read_resource::<GameStateMachine>().current()));
             }
+            frames
+        };
+
+        let order: Vec<_> = (0..9).collect();
+        let expected = run(&order);
+        // Something should have happened for the comparison to mean anything
+        assert!(expected.last().unwrap().0[0].1 < Some(200));
+        let wandered = |index: usize| expected.iter().map(|(states, _)| states[index].0).collect::<HashSet<_>>().len();
+        assert!(wandered(7) > 1 && wandered(8) > 1);
+        assert_eq!(expected[0].1, GameState::LevelTransition {to: LevelDirection::Next, id: 0});
+
+        let mut rng = StdRng::seed_from_u64(1795);
+        let mut shuffled = order.clone();
+        for _ in 0..10 {
+            shuffled.shuffle(&mut rng);
+            assert!(run(&shuffled) == expected, "outcome changed when entities were created in the order {:?}", shuffled);
         }
+        let reversed: Vec<_> = order.into_iter().rev().collect();
+        assert!(run(&reversed) == expected, "outcome changed when entities were created in reverse");
+    }
+
+    #[test]
+    fn broken_breakables_drop_pickups() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let breakable = |test: &mut TestWorld, tile, drops| {
+            let pos = test.tile_center(tile);
+            test.world.create_entity()
+                .with(Breakable {drops, drop_animation: test_animations().idle})
+                .with(HealthPoints(1))
+                .with(Position(pos))
+                .with(BoundingBox::Full {width: tile_size, height: tile_size})
+                .build()
+        };
+        let pot = breakable(&mut test, TilePos {row: 1, col: 3}, None);
+        let barrel = breakable(&mut test, TilePos {row: 2, col: 3}, Some(Item::Potion {stength: 5}));
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+
+        let hit = |test: &mut TestWorld, entity| {
+            let mut data: InteractionsData = test.world.system_data();
+            data.apply_damage(entity, 1, MovementDirection::East);
+        };
+        let pickups = |test: &TestWorld| test.world.read_storage::<Pickup>().join().count();
+
+        // Breaking something that drops nothing leaves nothing behind, not even blood
+        hit(&mut test, pot);
+        test.step(1);
+        assert!(!test.world.is_alive(pot));
+        assert_eq!(pickups(&test), 0);
+        assert_eq!(test.world.read_resource::<DecalBuffer>().iter().count(), 0);
+
+        hit(&mut test, barrel);
+        test.step(1);
+        assert!(!test.world.is_alive(barrel));
+        assert_eq!(pickups(&test), 1);
+        assert!(test.world.read_storage::<Inventory>().get(player).is_none_or(|inv| inv.items.is_empty()));
+
+        // Walking over the drop picks it up
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(20);
+        assert_eq!(pickups(&test), 0);
+        assert_eq!(test.world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::Potion {stength: 5}]);
+        assert_eq!(test.world.read_resource::<RunStats>().floor.items_found, 1);
+        // Loot dropped in plain sight is not a secret
+        assert_eq!(test.world.read_resource::<RunStats>().floor.secrets_found, 0);
+    }
+
+    #[test]
+    fn killed_enemies_drop_their_loot() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let enemy = test.spawn_enemy_at(TilePos {row: 2, col: 3}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 0.0,
+            health_points: 5,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: tile_size, height: tile_size},
+        });
+        test.world.write_storage().insert(enemy, Loot {item: Item::Gold(7), animation: test_animations().idle}).unwrap();
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+        test.world.write_storage().insert(player, Inventory {items: vec![Item::Gold(3)]}).unwrap();
+
+        {
+            let mut data: InteractionsData = test.world.system_data();
+            data.apply_damage(enemy, 5, MovementDirection::East);
+        }
+        test.step(1);
+        assert!(!test.world.is_alive(enemy));
+        assert_eq!(test.world.read_storage::<Pickup>().join().count(), 1);
+
+        // The gold is added to the gold that the player already had
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(20);
+        assert_eq!(test.world.read_storage::<Pickup>().join().count(), 0);
+        assert_eq!(test.world.read_storage::<Inventory>().get(player).unwrap().items, &[Item::Gold(10)]);
+    }
+
+    #[test]
+    fn merchants_block_the_way_and_open_their_shop() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 8, tile_size);
+        let merchant_pos = test.tile_center(TilePos {row: 2, col: 4});
+        let merchant = test.world.create_entity()
+            .with(Merchant {stock: vec![(Item::RoomKey, 30)]})
+            .with(Position(merchant_pos))
+            .with(BoundingBox::BottomHalf {width: tile_size, height: tile_size / 2})
+            .build();
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+
+        // Walking into the merchant stops the player in front of it
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(30);
+        test.step_with_events(vec![Event::KeyUp(Key::RightArrow)]);
+        assert!(test.position(player).x() < merchant_pos.x() - tile_size as i32 / 2);
+
+        // Attacking the merchant does nothing to it
+        test.step_with_events(vec![Event::KeyDown(Key::B)]);
+        test.step_with_events(vec![Event::KeyUp(Key::B)]);
+        test.step(20);
+        assert!(test.world.is_alive(merchant));
+        assert_eq!(test.position(merchant), merchant_pos);
+
+        test.step_with_events(vec![Event::KeyDown(Key::A)]);
+        assert_eq!(test.step_with_events(vec![Event::KeyUp(Key::A)]), Some(GameState::Shop {merchant}));
     }
 }
@@ -1,18 +1,44 @@
+use sdl2::rect::Point;
 use specs::{System, Join, ReadExpect, WriteExpect, ReadStorage, WriteStorage, Entities};
 
-use crate::components::{Movement, MovementDirection, KeyboardControlled, Wait};
-use crate::resources::{EventQueue, Event, ActionQueue, Action, Key};
+use crate::components::{
+    Position,
+    Movement,
+    MovementDirection,
+    KeyboardControlled,
+    Wait,
+    Dodge,
+    Invulnerable,
+    AttackCooldown,
+    InputAction,
+    BufferedActions,
+};
+use crate::resources::{EventQueue, Event, ActionQueue, Action, Key, FramesElapsed};
+use crate::assets::{scale_to_tile_size, scale_speed_to_tile_size};
+use crate::map::FloorMap;
 
-const MOVEMENT_SPEED: i32 = 3;
+use super::JoinedEntities;
+
+/// The speed of the player in px/frame at NATIVE_TILE_SIZE
+const MOVEMENT_SPEED: f32 = 3.0;
+/// The speed of the player in px/frame at NATIVE_TILE_SIZE while dodging
+const ROLL_SPEED: i32 = 6;
 
 #[derive(SystemData)]
 pub struct KeyboardData<'a> {
     entities: Entities<'a>,
     events: ReadExpect<'a, EventQueue>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    map: ReadExpect<'a, FloorMap>,
     actions: WriteExpect<'a, ActionQueue>,
     keyboard_controlled: ReadStorage<'a, KeyboardControlled>,
+    positions: ReadStorage<'a, Position>,
     movements: WriteStorage<'a, Movement>,
     waits: ReadStorage<'a, Wait>,
+    dodges: WriteStorage<'a, Dodge>,
+    invulnerables: WriteStorage<'a, Invulnerable>,
+    attack_cooldowns: ReadStorage<'a, AttackCooldown>,
+    buffered_actions: WriteStorage<'a, BufferedActions>,
 }
 
 #[derive(Default)]
@@ -32,6 +58,13 @@ impl Keyboard {
         self.direction_stack.last().cloned()
     }
 
+    /// Returns the most recent direction that is still pressed and perpendicular to the current
+    /// direction (if any). Moving in both directions at once moves diagonally.
+    fn sideways_direction(&self) -> Option<MovementDirection> {
+        let current = self.current_direction()?;
+        self.direction_stack.iter().rev().cloned().find(|&dir| current.is_perpendicular(dir))
+    }
+
     /// Adds a direction to the stack. Can be overridden by later directions.
     /// Will be kept in case the later keys are released while this one is still held.
     fn push_direction(&mut self, direction: MovementDirection) {
@@ -60,24 +93,40 @@ impl<'a> System<'a> for Keyboard {
         let KeyboardData {
             entities,
             events,
+            frames,
+            map,
             mut actions,
             keyboard_controlled,
+            positions,
             mut movements,
             waits,
+            mut dodges,
+            mut invulnerables,
+            attack_cooldowns,
+            mut buffered_actions,
         } = data;
 
-        // Set to true if the user has requested to interact with the tile it is facing
-        let mut interact = false;
-        // Set to true if the user has initiated an attack
-        let mut attack = false;
+        let FramesElapsed(frames_elapsed) = *frames;
+
+        // The actions requested during this frame along with the position on the map that was
+        // clicked to request each one (if any). The entity turns to face that position before it
+        // interacts or attacks.
+        let mut requested = Vec::new();
+        // Drinking a potion is never buffered since it is always allowed
+        let mut drink_potion = false;
 
         for event in &*events {
             match event {
-                KeyUp(A) => interact = true,
-                KeyUp(B) => attack = true,
+                KeyUp(A) => requested.push((InputAction::Interact, None)),
+                KeyUp(Y) => drink_potion = true,
+                KeyUp(B) => requested.push((InputAction::Attack, None)),
+                KeyDown(X) => requested.push((InputAction::Dodge, None)),
+                &PointerInteract(target) => requested.push((InputAction::Interact, Some(target))),
+                &PointerAttack(target) => requested.push((InputAction::Attack, Some(target))),
 
-                // We only want the user to be able to move in one of the cardinal directions at
-                // once. We override each movement based on the order in which the events arrive.
+                // The most recent direction is the one the user faces. Holding a perpendicular
+                // direction at the same time moves diagonally. Opposite directions override each
+                // other based on the order in which the events arrive.
                 KeyDown(UpArrow) => self.push_direction(North),
                 KeyDown(RightArrow) => self.push_direction(East),
                 KeyDown(DownArrow) => self.push_direction(South),
@@ -92,21 +141,335 @@ impl<'a> System<'a> for Keyboard {
             }
         }
 
-        for (entity, movement, _, ()) in (&entities, &mut movements, &keyboard_controlled, !&waits).join() {
-            if interact {
-                actions.0.entry(entity).or_default().push(Action::Interact);
+        for (entity, &Position(pos), movement, controlled) in (&entities, &positions, &mut movements, &keyboard_controlled).join() {
+            // Actions requested while they are not allowed are buffered for a few frames in case
+            // they become allowed shortly after
+            let buffered = buffered_actions.entry(entity)
+                .expect("bug: unable to buffer actions")
+                .or_insert_with(Default::default);
+            buffered.step(frames_elapsed);
+            for &(action, target) in &requested {
+                buffered.push(action, target);
             }
-            if attack {
-                actions.0.entry(entity).or_default().push(Action::Attack);
+
+            // Nothing is allowed while waiting
+            if waits.get(entity).is_some() {
+                continue;
+            }
+
+            // The position on the map that the user clicked on to request an action performed
+            // during this frame (if any)
+            let mut pointer_target: Option<Point> = None;
+
+            if let Some(interact) = buffered.take(InputAction::Interact) {
+                actions.0.entry(entity).or_default().push(Action::Interact);
+                pointer_target = interact.target.or(pointer_target);
             }
 
+            // Can only dodge while moving and only once the last dodge is completely over
             if let Some(direction) = self.current_direction() {
+                if dodges.get(entity).is_none() && buffered.take(InputAction::Dodge).is_some() {
+                    let speed = scale_to_tile_size(ROLL_SPEED, map.tile_size());
+                    dodges.insert(entity, Dodge::new(direction, speed))
+                        .expect("bug: unable to insert dodge");
+
+                    // The countdown in Interactions happens during this frame too, so one extra
+                    // frame is needed to stay invulnerable for the entire roll. A longer
+                    // invulnerability (e.g. from being hit) is left as is.
+                    let frames_remaining = Dodge::ROLL_FRAMES + 1;
+                    if invulnerables.get(entity).map(|i| i.frames_remaining < frames_remaining).unwrap_or(true) {
+                        invulnerables.insert(entity, Invulnerable {frames_remaining})
+                            .expect("bug: unable to insert invulnerability");
+                    }
+                }
+            }
+
+            let rolling = dodges.get(entity).filter(|dodge| dodge.is_rolling());
+            // Cannot attack in the middle of a roll or before the last attack has finished. The
+            // cooldown counts down in Interactions before the attack is made.
+            let can_attack = rolling.is_none() && attack_cooldowns.get(entity)
+                .map(|cooldown| cooldown.is_ready_after(frames_elapsed))
+                .unwrap_or(true);
+            if can_attack {
+                if let Some(attack) = buffered.take(InputAction::Attack) {
+                    actions.0.entry(entity).or_default().push(Action::Attack);
+                    pointer_target = attack.target.or(pointer_target);
+                }
+            }
+
+            if drink_potion {
+                actions.0.entry(entity).or_default().push(Action::DrinkPotion);
+            }
+
+            let speed = scale_speed_to_tile_size(MOVEMENT_SPEED, map.tile_size()) * controlled.speed_multiplier;
+            if let Some(dodge) = rolling {
+                // The roll only goes in a straight line, but the entity still needs to look like
+                // it is moving
+                movement.direction = dodge.direction;
+                movement.sideways = None;
+                movement.speed = speed;
+            } else if let Some(direction) = self.current_direction() {
                 movement.direction = direction;
-                movement.speed = MOVEMENT_SPEED;
+                movement.sideways = self.sideways_direction();
+                movement.speed = speed;
             } else {
                 // Since the key events do not indicate that we need to move anywhere, stop moving
-                movement.speed = 0;
+                movement.sideways = None;
+                movement.speed = 0.0;
+            }
+
+            // Clicking only changes the direction faced for this frame. Any direction still held
+            // takes over again on the next frame. Nothing changes the direction of a roll.
+            if let (Some(target), None) = (pointer_target, rolling) {
+                movement.direction = MovementDirection::between(pos, target);
             }
         }
     }
 }
+
+impl<'a> JoinedEntities<'a> for Keyboard {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.movements, &data.keyboard_controlled).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder};
+
+    use crate::components::{BoundingBox, Door, HealthPoints, EnemyBehaviour};
+    use crate::generator::EnemyValues;
+    use crate::map::TilePos;
+    use crate::systems::COLLISION_THRESHOLD;
+    use crate::test_helpers::{TestWorld, test_animations};
+
+    fn health(world: &World, entity: specs::Entity) -> usize {
+        world.read_storage::<HealthPoints>().get(entity).unwrap().0
+    }
+
+    fn is_rolling(world: &World, entity: specs::Entity) -> bool {
+        world.read_storage::<Dodge>().get(entity).map(|dodge| dodge.is_rolling()).unwrap_or(false)
+    }
+
+    fn is_invulnerable(world: &World, entity: specs::Entity) -> bool {
+        world.read_storage::<Invulnerable>().get(entity).is_some()
+    }
+
+    #[test]
+    fn invulnerable_for_entire_roll() {
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+        let mut last_pos = test.position(player);
+
+        let mut rolling_frames = Vec::new();
+        let mut invulnerable_frames = Vec::new();
+        let mut displacements = Vec::new();
+        for frame in 0..24 {
+            if frame == 0 {
+                test.step_with_events(vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::X)]);
+            } else {
+                test.step(1);
+            }
+
+            // Checked after each frame, so the roll is still in progress during its last frame
+            if frame < Dodge::ROLL_FRAMES {
+                rolling_frames.push(frame);
+            } else {
+                assert!(!is_rolling(&test.world, player));
+            }
+            if is_invulnerable(&test.world, player) {
+                invulnerable_frames.push(frame);
+            }
+            let pos = test.position(player);
+            displacements.push((pos - last_pos).x());
+            last_pos = pos;
+        }
+
+        assert_eq!(invulnerable_frames, rolling_frames);
+        // Fast while rolling, slow while recovering, and then back to normal
+        let mut expected = vec![6; Dodge::ROLL_FRAMES];
+        expected.extend(vec![1; Dodge::RECOVERY_FRAMES]);
+        expected.extend(vec![3; 24 - Dodge::ROLL_FRAMES - Dodge::RECOVERY_FRAMES]);
+        assert_eq!(displacements, expected);
+        assert!(test.world.read_storage::<Dodge>().get(player).is_none());
+    }
+
+    #[test]
+    fn enemy_contact_ignored_until_roll_is_over() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 10, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        // An enemy that never moves, close enough to touch during the roll
+        let enemy = test.spawn_enemy_at(TilePos {row: 2, col: 4}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 3,
+            speed: 0.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: tile_size, height: tile_size},
+        });
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::X)]);
+        for _ in 1..Dodge::ROLL_FRAMES {
+            test.step(1);
+            assert_eq!(health(&test.world, player), 20);
+            // Rolling does not go through the enemy
+            assert!(test.position(player).x() < test.position(enemy).x() - tile_size as i32 / 2);
+        }
+        // Still touching the enemy on the first frame after the roll
+        test.step(1);
+        assert_eq!(health(&test.world, player), 17);
+    }
+
+    #[test]
+    fn cannot_attack_while_rolling() {
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+        let attacked = |test: &TestWorld| test.world.read_resource::<ActionQueue>().0.get(&player)
+            .map(|actions| actions.contains(&Action::Attack))
+            .unwrap_or(false);
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::X)]);
+        for _ in 1..Dodge::ROLL_FRAMES {
+            test.step_with_events(vec![Event::KeyUp(Key::B)]);
+            assert!(!attacked(&test));
+        }
+        test.step_with_events(vec![Event::KeyUp(Key::B)]);
+        assert!(attacked(&test));
+    }
+
+    /// Presses attack on each of the given frames, with the player starting out waiting for the
+    /// given number of frames (if any). Returns the frames on which an attack was made.
+    fn attack_frames(wait: Option<usize>, presses: &[usize], frames: usize) -> Vec<usize> {
+        let mut test = TestWorld::new(5, 10, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        if let Some(wait) = wait {
+            test.world.write_storage().insert(player, Wait::new(wait)).unwrap();
+        }
+        (0..frames).filter(|frame| {
+            let events = if presses.contains(frame) { vec![Event::KeyUp(Key::B)] } else { Vec::new() };
+            test.step_with_events(events);
+            test.world.read_resource::<ActionQueue>().0.get(&player)
+                .map(|actions| actions.contains(&Action::Attack))
+                .unwrap_or(false)
+        }).collect()
+    }
+
+    #[test]
+    fn early_attack_buffered_until_wait_is_over() {
+        // e.g. stunned after being hit
+        let wait = Some(20);
+        // Mashing the attack key attacks as soon as it is allowed
+        let mashing: Vec<_> = (0..40).collect();
+        let first_allowed = attack_frames(wait, &mashing, 40)[0];
+        assert!(first_allowed > 10);
+
+        // Pressed a few frames early, the attack still lands on the first frame it can
+        assert_eq!(attack_frames(wait, &[first_allowed - 3], 40), vec![first_allowed]);
+        // Right at the edge of the buffer
+        let edge = first_allowed - BufferedActions::BUFFER_FRAMES;
+        assert_eq!(attack_frames(wait, &[edge], 40), vec![first_allowed]);
+        // Pressed too early, the attack is dropped
+        assert_eq!(attack_frames(wait, &[first_allowed - 10], 40), vec![]);
+        assert_eq!(attack_frames(wait, &[edge - 1], 40), vec![]);
+    }
+
+    #[test]
+    fn early_attack_buffered_until_last_attack_is_over() {
+        let mashing: Vec<_> = (0..40).collect();
+        let second = attack_frames(None, &mashing, 40)[1];
+        assert!(second > 3);
+
+        assert_eq!(attack_frames(None, &[0, second - 3], 40), vec![0, second]);
+    }
+
+    #[test]
+    fn early_dodge_buffered_until_moving() {
+        // Pressed a few frames before starting to move
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        test.step_with_events(vec![Event::KeyDown(Key::X)]);
+        test.step(2);
+        assert!(!is_rolling(&test.world, player));
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        assert!(is_rolling(&test.world, player));
+
+        // Pressed long before starting to move
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        test.step_with_events(vec![Event::KeyDown(Key::X)]);
+        test.step(9);
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        assert!(!is_rolling(&test.world, player));
+        // Movement is never buffered, so it starts right away
+        assert_eq!(test.world.read_storage::<Movement>().get(player).unwrap().direction, MovementDirection::East);
+        assert!(test.world.read_storage::<Movement>().get(player).unwrap().speed > 0.0);
+    }
+
+    #[test]
+    fn speed_multiplier_scales_walking_speed() {
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        let speed = |test: &TestWorld| test.world.read_storage::<Movement>().get(player).unwrap().speed;
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        let base_speed = speed(&test);
+        assert!(base_speed > 0.0);
+
+        test.world.write_storage::<KeyboardControlled>().get_mut(player).unwrap().speed_multiplier = 1.1;
+        test.step(1);
+        assert_eq!(speed(&test), base_speed * 1.1);
+    }
+
+    #[test]
+    fn cannot_dodge_while_standing_still_or_recovering() {
+        let mut test = TestWorld::new(5, 30, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+
+        test.step_with_events(vec![Event::KeyDown(Key::X)]);
+        assert!(test.world.read_storage::<Dodge>().get(player).is_none());
+
+        test.step_with_events(vec![Event::KeyUp(Key::X), Event::KeyDown(Key::RightArrow)]);
+        test.step_with_events(vec![Event::KeyDown(Key::X)]);
+        assert!(is_rolling(&test.world, player));
+        test.step(Dodge::ROLL_FRAMES);
+        // Pressing again during the recovery does not start another roll
+        test.step_with_events(vec![Event::KeyUp(Key::X)]);
+        test.step_with_events(vec![Event::KeyDown(Key::X)]);
+        assert!(!is_rolling(&test.world, player));
+        assert!(!is_invulnerable(&test.world, player));
+    }
+
+    #[test]
+    fn roll_stops_at_walls_and_doors() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 6, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 3});
+        let bounds = *test.world.read_storage::<BoundingBox>().get(player).unwrap();
+        let wall_left = TilePos {row: 2, col: 5}.top_left(tile_size as i32).x();
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::X)]);
+        test.step(Dodge::ROLL_FRAMES);
+        let pos = test.position(player);
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), wall_left);
+
+        // A closed door as thin as the ones in vertical walls
+        let mut test = TestWorld::new(5, 10, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        let door_pos = test.tile_center(TilePos {row: 2, col: 4});
+        let door_bounds = BoundingBox::Full {width: tile_size / 2, height: tile_size};
+        test.world.create_entity()
+            .with(Door::Closed)
+            .with(Position(door_pos))
+            .with(door_bounds)
+            .build();
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow), Event::KeyDown(Key::X)]);
+        test.step(Dodge::ROLL_FRAMES);
+        let pos = test.position(player);
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(),
+            door_bounds.shrink(COLLISION_THRESHOLD).to_rect(door_pos).left());
+    }
+}
@@ -0,0 +1,217 @@
+//! Queries for the entities near another entity
+
+use sdl2::rect::{Point, Rect};
+use specs::{Entity, ReadStorage};
+
+use crate::components::{Position, BoundingBox, MovementDirection};
+use crate::geometry::{side, directional_gap};
+
+/// Returns the region that an entity with the given boundary must intersect with to be up to
+/// `range` away from the given side of `bounds`. The region is as wide as `bounds` and is widened
+/// by `margin` on either side.
+pub fn direction_box(direction: MovementDirection, bounds: Rect, range: i32, margin: i32) -> Rect {
+    use self::MovementDirection::*;
+    // The box is flush against the side of the boundary facing the direction so that nothing
+    // behind that side is ever inside of it
+    let width = bounds.width() + margin as u32 * 2;
+    let height = bounds.height() + margin as u32 * 2;
+    let edge = side(bounds, direction);
+    match direction {
+        North => Rect::new(bounds.left() - margin, edge - range, width, range as u32),
+        South => Rect::new(bounds.left() - margin, edge, width, range as u32),
+        East => Rect::new(edge, bounds.top() - margin, range as u32, height),
+        West => Rect::new(edge - range, bounds.top() - margin, range as u32, height),
+    }
+}
+
+/// A search for the entities in front of one side of an entity's boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirectionQuery {
+    /// The position of the entity doing the search
+    pub pos: Point,
+    /// The direction to search in
+    pub direction: MovementDirection,
+    /// The bounding box of the entity doing the search
+    pub bounds: BoundingBox,
+    /// How far past the side of the boundary facing `direction` to search
+    pub range: i32,
+    /// How far past either end of that side to search
+    pub margin: i32,
+}
+
+/// Returns the nearest entities in the direction of the query. Only entities that are up to
+/// `range` away from the side of the entity's boundary facing that direction are returned. The
+/// search covers the width of that side and `margin` past either end of it. Result is sorted
+/// nearest to farthest, measured from that side of the boundary. Entities at the same distance
+/// are sorted top to bottom and then left to right so that the order never depends on the order
+/// that the entities were created in.
+///
+/// Only the entities returned by `candidates` for the region returned by `direction_box` are
+/// considered.
+pub fn nearest_in_direction<I>(
+    candidates: impl FnOnce(Rect) -> I,
+    positions: &ReadStorage<'_, Position>,
+    bounding_boxes: &ReadStorage<'_, BoundingBox>,
+    entity: Entity,
+    query: DirectionQuery,
+) -> Vec<(Entity, Point)>
+    where I: IntoIterator<Item=Entity> {
+    let DirectionQuery {pos, direction, bounds, range, margin} = query;
+    let bounds = bounds.to_rect(pos);
+
+    // Generate the rectangle that the other bounding box must intersect with
+    // Assumption: bounding boxes do not intersect (due to the physics engine)
+    let direction_box = direction_box(direction, bounds, range, margin);
+
+    let mut near = Vec::new();
+    for other in candidates(direction_box) {
+        if entity == other {
+            continue;
+        }
+        let other_pos = match positions.get(other) {
+            Some(&Position(other_pos)) => other_pos,
+            None => continue,
+        };
+
+        // Using the full boundary (regardless of the bounding box type) because we want
+        // entities to be found regardless of whether their full height is used in collision
+        // detection
+        let other_bounds = bounding_boxes.get(other)
+            .map(|b| b.to_full_rect(other_pos))
+            .unwrap_or_else(|| Rect::from_center(other_pos, 0, 0));
+
+        if direction_box.has_intersection(other_bounds) {
+            near.push((other, other_pos, other_bounds));
+        }
+    }
+
+    // Return result sorted by the distance *between* the boundary rectangles in the given
+    // direction
+    near.sort_unstable_by_key(|&(other, other_pos, other_bounds)| {
+        let gap = directional_gap(bounds, other_bounds, direction).abs();
+        (gap, other_pos.y(), other_pos.x(), other.id())
+    });
+
+    near.into_iter().map(|(other, other_pos, _)| (other, other_pos)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use specs::{Builder, Join, RunNow, System, World};
+
+    use crate::systems::Physics;
+    use crate::resources::SpatialGrid;
+    use crate::map::{FloorMap, GridSize};
+    use crate::test_helpers::level_world;
+
+    #[test]
+    fn direction_box_starts_at_facing_side() {
+        use self::MovementDirection::*;
+        let pos = Point::new(100, 100);
+
+        // Spans x = 92 to 108 and y = 92 to 108
+        let full = BoundingBox::Full {width: 16, height: 16}.to_rect(pos);
+        assert_eq!(direction_box(North, full, 16, 4), Rect::new(88, 76, 24, 16));
+        assert_eq!(direction_box(South, full, 16, 4), Rect::new(88, 108, 24, 16));
+        assert_eq!(direction_box(East, full, 16, 4), Rect::new(108, 88, 16, 24));
+        assert_eq!(direction_box(West, full, 16, 4), Rect::new(76, 88, 16, 24));
+
+        // Spans x = 92 to 108 and y = 100 to 108
+        let bottom_half = BoundingBox::BottomHalf {width: 16, height: 8}.to_rect(pos);
+        assert_eq!(direction_box(North, bottom_half, 16, 4), Rect::new(88, 84, 24, 16));
+        assert_eq!(direction_box(South, bottom_half, 16, 4), Rect::new(88, 108, 24, 16));
+        assert_eq!(direction_box(East, bottom_half, 16, 4), Rect::new(108, 96, 16, 16));
+        assert_eq!(direction_box(West, bottom_half, 16, 4), Rect::new(76, 96, 16, 16));
+
+        // Without a margin, the box is exactly as wide as the side it starts from
+        assert_eq!(direction_box(East, full, 32, 0), Rect::new(108, 92, 32, 16));
+    }
+
+    #[test]
+    fn only_finds_entities_in_front() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<BoundingBox>();
+
+        let bounds = BoundingBox::Full {width: 16, height: 16};
+        let pos = Point::new(100, 100);
+        let attacker = world.create_entity().with(Position(pos)).with(bounds).build();
+        let ahead = world.create_entity().with(Position(pos.offset(20, 0))).with(bounds).build();
+        // Diagonally ahead, touching the corner of the attacker
+        let diagonal = world.create_entity().with(Position(pos.offset(16, 16))).with(bounds).build();
+        // Mostly beside the attacker, only slightly past the side it is facing
+        let beside = world.create_entity().with(Position(pos.offset(4, -20))).with(bounds).build();
+
+        let (entities, positions, bounding_boxes) = world.system_data::<(
+            specs::Entities<'_>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, BoundingBox>,
+        )>();
+        let search = |direction, range, margin| -> Vec<_> {
+            let query = DirectionQuery {pos, direction, bounds, range, margin};
+            nearest_in_direction(|_| (&entities).join(), &positions, &bounding_boxes, attacker, query)
+                .into_iter().map(|(entity, _)| entity).collect()
+        };
+
+        // Nearest is measured from the side of the attacker, not its center
+        assert_eq!(search(MovementDirection::East, 16, 0), vec![ahead]);
+        assert_eq!(search(MovementDirection::East, 32, 4), vec![diagonal, ahead]);
+        assert_eq!(search(MovementDirection::North, 32, 0), vec![beside]);
+        assert!(search(MovementDirection::West, 32, 4).is_empty());
+        assert!(search(MovementDirection::South, 8, 4).contains(&diagonal));
+    }
+
+    #[test]
+    fn spatial_grid_matches_full_search() {
+        let tile_size = 16;
+        let size = GridSize {rows: 40, cols: 50};
+        let mut world = level_world(FloorMap::new(size, tile_size));
+        System::setup(&mut Physics, &mut world.res);
+
+        // Lots of entities packed closely together so that there are many ties in distance
+        let mut rng = StdRng::seed_from_u64(1783);
+        let width = (size.cols as u32 * tile_size) as i32;
+        let height = (size.rows as u32 * tile_size) as i32;
+        let entities: Vec<_> = (0..500).map(|i| {
+            let pos = Point::new(rng.gen_range(0, width), rng.gen_range(0, height));
+            let builder = world.create_entity().with(Position(pos));
+            // Some entities do not have a bounding box at all
+            match i % 3 {
+                0 => builder.with(BoundingBox::Full {width: 16, height: 16}),
+                1 => builder.with(BoundingBox::BottomHalf {width: 16, height: 8}),
+                _ => builder,
+            }.build()
+        }).collect();
+        Physics.run_now(&world.res);
+        world.maintain();
+
+        let (world_entities, positions, bounding_boxes, grid) = world.system_data::<(
+            specs::Entities<'_>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, BoundingBox>,
+            specs::Read<'_, SpatialGrid>,
+        )>();
+        let directions = [MovementDirection::North, MovementDirection::South, MovementDirection::East, MovementDirection::West];
+        let mut found = 0;
+        for &entity in &entities {
+            let Position(pos) = *positions.get(entity).unwrap();
+            let bounds = BoundingBox::BottomHalf {width: 16, height: 8};
+            for &direction in &directions {
+                for &(range, margin) in &[(4, 0), (16, 0), (16, 4), (64, 8)] {
+                    let query = DirectionQuery {pos, direction, bounds, range, margin};
+                    let full_search = nearest_in_direction(|_| (&world_entities).join(),
+                        &positions, &bounding_boxes, entity, query);
+                    let grid_search = nearest_in_direction(|region| grid.query(region),
+                        &positions, &bounding_boxes, entity, query);
+                    assert_eq!(grid_search, full_search);
+                    found += full_search.len();
+                }
+            }
+        }
+        // Make sure that the searches actually found something
+        assert!(found > 1000);
+    }
+}
@@ -1,22 +1,36 @@
-use sdl2::rect::Rect;
-use specs::{System, Join, ReadExpect, ReadStorage, WriteStorage, Entities, LazyUpdate};
+use std::cmp::Ordering;
 
-use crate::components::{Movement, Position, Wait, BoundingBox, Ghost};
-use crate::resources::FramesElapsed;
+use sdl2::rect::{Point, Rect};
+use specs::{System, Join, ReadExpect, Write, ReadStorage, WriteStorage, Entities, Entity, LazyUpdate};
+
+use crate::components::{Movement, Position, Wait, BoundingBox, Ghost, Knockback, Dodge, Teleport, Follower, Door, StatusEffects, Dormant};
+use crate::resources::{FramesElapsed, SpatialGrid};
 use crate::map::FloorMap;
 
+use super::JoinedEntities;
+
 // Collisions within this threshold will be *ignored*
-const COLLISION_THRESHOLD: u32 = 1;
+pub(in super) const COLLISION_THRESHOLD: u32 = 1;
+/// The most (in px) that an entity can be pushed away from each entity it overlaps with per frame
+const SEPARATION_SPEED: i32 = 2;
 
 #[derive(SystemData)]
 pub struct PhysicsData<'a> {
     entities: Entities<'a>,
     frames: ReadExpect<'a, FramesElapsed>,
     map: ReadExpect<'a, FloorMap>,
-    movements: ReadStorage<'a, Movement>,
+    spatial_grid: Write<'a, SpatialGrid>,
+    movements: WriteStorage<'a, Movement>,
     bounding_boxes: ReadStorage<'a, BoundingBox>,
     ghosts: ReadStorage<'a, Ghost>,
+    doors: ReadStorage<'a, Door>,
+    followers: ReadStorage<'a, Follower>,
+    status_effects: ReadStorage<'a, StatusEffects>,
+    dormants: ReadStorage<'a, Dormant>,
+    teleports: WriteStorage<'a, Teleport>,
     waits: WriteStorage<'a, Wait>,
+    knockbacks: WriteStorage<'a, Knockback>,
+    dodges: WriteStorage<'a, Dodge>,
     positions: WriteStorage<'a, Position>,
     updater: ReadExpect<'a, LazyUpdate>,
 }
@@ -27,86 +41,131 @@ impl<'a> System<'a> for Physics {
     type SystemData = PhysicsData<'a>;
 
     fn run(&mut self, data: Self::SystemData) {
-        let PhysicsData {entities, frames, map, movements, bounding_boxes, ghosts, mut positions, mut waits, updater} = data;
+        let PhysicsData {entities, frames, map, mut spatial_grid, mut movements, bounding_boxes, ghosts, doors, followers, status_effects, dormants, mut teleports, mut positions, mut waits, mut knockbacks, mut dodges, updater} = data;
         let FramesElapsed(frames_elapsed) = *frames;
         let tile_size = map.tile_size();
 
+        // Entities may have been added or moved since the grid was last rebuilt
+        rebuild_spatial_grid(&mut spatial_grid, tile_size, &entities, &positions, &bounding_boxes);
+
         // Need to do updating in a separate phase so we can read all the positions in a nested loop
         let mut updates = Vec::new();
-        for (entity, Position(pos), &Movement {direction, speed}) in (&entities, &positions, &movements).join() {
+        for (entity, Position(pos), movement) in (&entities, &positions, &mut movements).join() {
+            // Teleporting skips everything else, including collisions
+            if let Some(&Teleport(target)) = teleports.get(entity) {
+                updates.push((entity, target));
+                continue;
+            }
+
             // Entity is waiting for a given amount of frames to elapse
-            if let Some(wait) = waits.get_mut(entity) {
-                wait.frames_elapsed += frames_elapsed;
-                if wait.frames_elapsed >= wait.duration {
-                    updater.remove::<Wait>(entity); // stop waiting at the next frame
-                }
-                continue; // do not continue updating since we are still waiting
+            let is_waiting = match waits.get_mut(entity) {
+                Some(wait) => {
+                    wait.frames_elapsed += frames_elapsed;
+                    if wait.frames_elapsed >= wait.duration {
+                        updater.remove::<Wait>(entity); // stop waiting at the next frame
+                    }
+                    true
+                },
+                None => false,
+            };
+
+            // Dormant entities are frozen in place, but can still be knocked back
+            let is_frozen = is_waiting || dormants.get(entity).is_some();
+            let knockback = knockbacks.get_mut(entity);
+            // Do not continue updating if we are frozen and nothing is pushing us around
+            if is_frozen && knockback.is_none() {
+                continue;
             }
 
-            let frames_elapsed = frames_elapsed as i32;
+            let mut displacement = Point::new(0, 0);
+            // Entities that are waiting or dormant cannot move on their own
+            if !is_frozen {
+                let speed_multiplier = status_effects.get(entity)
+                    .map(|effects| effects.speed_multiplier())
+                    .unwrap_or(1.0);
+                match dodges.get_mut(entity) {
+                    // Rolling overrides the normal movement
+                    Some(dodge) => {
+                        for _ in 0..frames_elapsed {
+                            displacement += dodge.step(movement.step_at(speed_multiplier));
+                        }
+                        if dodge.is_complete() {
+                            updater.remove::<Dodge>(entity);
+                        }
+                    },
+                    None => for _ in 0..frames_elapsed {
+                        displacement += movement.step_at(speed_multiplier);
+                    },
+                }
+            }
 
-            let mut next_pos = *pos + direction.to_vector() * speed * frames_elapsed;
+            // Knockback is applied on top of the normal movement, even while waiting
+            if let Some(knockback) = knockback {
+                for _ in 0..frames_elapsed {
+                    displacement += knockback.step();
+                }
+                if knockback.is_complete() {
+                    updater.remove::<Knockback>(entity);
+                }
+            }
 
             if let Some(&bounds_box) = bounding_boxes.get(entity) {
                 // Shrink by the threshold so we don't detect collisions too eagerly
                 let bounds_box = bounds_box.shrink(COLLISION_THRESHOLD);
-                let bounds = bounds_box.to_rect(next_pos);
+                // Anything the entity could hit on its way to its next position
+                let bounds = bounds_box.to_rect(*pos).union(bounds_box.to_rect(*pos + displacement));
 
                 // Check if any of the tiles that this new position intersects with is a wall
-                let potential_collisions = map.tiles_within(bounds)
+                let mut walls: Vec<_> = map.tiles_within(bounds)
                     .filter(|(_, _, tile)| tile.is_wall())
                     .map(|(pos, _, _)| Rect::new(
                         pos.x(),
                         pos.y(),
                         tile_size,
                         tile_size,
-                    ));
-                let potential_collisions = potential_collisions
-                    .chain((&entities, &positions, &bounding_boxes, !&ghosts).join()
-                    .filter_map(|(other, &Position(other_pos), &bounds_box, ())| {
-                        // Do not collide with self
-                        if entity == other { return None; }
-
-                        // Shrink by the threshold so we don't detect collisions too eagerly
-                        let bounds_box = bounds_box.shrink(COLLISION_THRESHOLD);
-
-                        Some(bounds_box.to_rect(other_pos))
-                    }));
-
-                for other in potential_collisions {
-                    // Recalculate bounds based on latest next_pos
-                    let bounds = bounds_box.to_rect(next_pos);
-
-                    // Need to recalculate the intersection since we are changing next_pos in each
-                    // iteration. Would not make sense to precalculate the intersections when
-                    // collecting potential collision objects
-                    if let Some(rect) = bounds.intersection(other) {
-                        // Do the minimal amount of movement in one direction to avoid the collision
-                        if rect.width() <= rect.height() {
-                            let adjustment = rect.width() as i32;
-                            if rect.x() > next_pos.x() {
-                                // Collision was on the right so we'll move left
-                                next_pos = next_pos.offset(-adjustment, 0);
-                            } else {
-                                // Collision was on the left so we'll move right
-                                next_pos = next_pos.offset(adjustment, 0);
-                            }
-                        } else {
-                            let adjustment = rect.height() as i32;
-                            // Need to make sure to use > instead of >= here or else we will fly
-                            // through walls when moving up into them. Do not want to move up by
-                            // the given adjustment when the colliding object is already above us.
-                            if rect.y() > next_pos.y() {
-                                // Collision was below so we'll move up
-                                next_pos = next_pos.offset(0, -adjustment);
-                            } else {
-                                // Collision was above so we'll move down
-                                next_pos = next_pos.offset(0, adjustment);
-                            }
-                        }
+                    ))
+                    .collect();
+                // Only the entities near the path of this entity need to be checked
+                let mut bodies = Vec::new();
+                // Followers only collide with walls so that they can never get stuck on anything
+                let is_follower = followers.get(entity).is_some();
+                for other in spatial_grid.query(bounds) {
+                    // Do not collide with self
+                    if entity == other || is_follower || ghosts.get(other).is_some() { continue; }
+                    let (&Position(other_pos), &bounds_box) = match (positions.get(other), bounding_boxes.get(other)) {
+                        (Some(pos), Some(bounds_box)) => (pos, bounds_box),
+                        _ => continue,
+                    };
+                    // Shrink by the threshold so we don't detect collisions too eagerly
+                    let rect = bounds_box.shrink(COLLISION_THRESHOLD).to_rect(other_pos);
+                    match doors.get(other) {
+                        // Open doors can be walked through
+                        Some(door) if door.is_open() => {},
+                        // Closed doors are as solid as the walls around them
+                        Some(_) => walls.push(rect),
+                        None => bodies.push((other, rect)),
                     }
                 }
 
+                let mut potential_collisions: Vec<_> = walls.iter().cloned()
+                    .chain(bodies.iter().map(|&(_, rect)| rect))
+                    .collect();
+                // Each collision can change the position that the next one is resolved from, so
+                // they are resolved top to bottom and left to right rather than in the order that
+                // the entities were created in
+                potential_collisions.sort_unstable_by_key(|rect| (rect.y(), rect.x(), rect.width(), rect.height()));
+
+                // Moving along each axis separately means that being blocked along one axis does
+                // not stop movement along the other. That way entities slide along walls instead
+                // of getting stuck on them.
+                let mut next_pos = *pos;
+                next_pos = move_along_axis(bounds_box, next_pos, Point::new(displacement.x(), 0), &potential_collisions);
+                next_pos = move_along_axis(bounds_box, next_pos, Point::new(0, displacement.y()), &potential_collisions);
+                // Anything that is still overlapping was already overlapping before moving
+                next_pos = push_apart(entity, bounds_box, next_pos, &bodies, frames_elapsed);
+                // Walls always win so that being pushed apart never leaves anything inside a wall
+                next_pos = separate(bounds_box, next_pos, &walls);
+
                 updates.push((entity, next_pos));
             }
         }
@@ -116,5 +175,431 @@ impl<'a> System<'a> for Physics {
                 *pos = next_pos;
             }
         }
+        teleports.clear();
+
+        // Now that everything has moved, the spatial grid can be rebuilt for the systems after
+        rebuild_spatial_grid(&mut spatial_grid, tile_size, &entities, &positions, &bounding_boxes);
+    }
+}
+
+impl<'a> JoinedEntities<'a> for Physics {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        // Every entity with a position is added to the spatial grid
+        (&data.positions, &data.movements).join().count() + data.positions.join().count()
+    }
+}
+
+/// Puts every entity with a position into the spatial grid based on where it is right now
+fn rebuild_spatial_grid(
+    spatial_grid: &mut SpatialGrid,
+    tile_size: u32,
+    entities: &Entities<'_>,
+    positions: &WriteStorage<'_, Position>,
+    bounding_boxes: &ReadStorage<'_, BoundingBox>,
+) {
+    spatial_grid.clear(tile_size);
+    for (entity, &Position(pos), bounds) in (entities, positions, bounding_boxes.maybe()).join() {
+        // Using the full boundary so that the grid can be used for any kind of query
+        let bounds = bounds.map(|b| b.to_full_rect(pos))
+            .unwrap_or_else(|| Rect::from_center(pos, 0, 0));
+        spatial_grid.insert(entity, bounds);
+    }
+}
+
+/// Moves the given position by a displacement along a single axis. Stops the entity flush with the
+/// first thing it runs into along the way.
+fn move_along_axis(bounds_box: BoundingBox, pos: Point, displacement: Point, others: &[Rect]) -> Point {
+    let mut next_pos = pos + displacement;
+    for &other in others {
+        // Recalculate bounds based on latest next_pos
+        let bounds = bounds_box.to_rect(next_pos);
+        if !bounds.has_intersection(other) {
+            continue;
+        }
+
+        // Only push back against things that are ahead of where the entity started. Anything else
+        // was already overlapping before the entity moved. Comparing against the start rather than
+        // the end means that fast entities cannot skip past the leading edge of a thin wall.
+        if displacement.x() > 0 && other.left() > pos.x() {
+            next_pos = next_pos.offset(other.left() - bounds.right(), 0);
+        } else if displacement.x() < 0 && other.left() <= pos.x() {
+            next_pos = next_pos.offset(other.right() - bounds.left(), 0);
+        } else if displacement.y() > 0 && other.top() > pos.y() {
+            next_pos = next_pos.offset(0, other.top() - bounds.bottom());
+        } else if displacement.y() < 0 && other.top() <= pos.y() {
+            next_pos = next_pos.offset(0, other.bottom() - bounds.top());
+        }
+    }
+
+    next_pos
+}
+
+/// Moves the entity a little bit away from each of the given entities that it overlaps with.
+/// Both entities are pushed apart when they are both moving, so entities that end up on top of
+/// each other spread back out over a few frames rather than staying stacked or jumping apart.
+fn push_apart(entity: Entity, bounds_box: BoundingBox, mut next_pos: Point, others: &[(Entity, Rect)], frames_elapsed: usize) -> Point {
+    let max_push = SEPARATION_SPEED * frames_elapsed as i32;
+    for &(other, other_rect) in others {
+        // Recalculate bounds based on latest next_pos
+        let bounds = bounds_box.to_rect(next_pos);
+        let rect = match bounds.intersection(other_rect) {
+            Some(rect) => rect,
+            None => continue,
+        };
+
+        // Entities in exactly the same place would always be pushed the same way, so the one that
+        // was created first goes up or left and the other goes down or right
+        let away = |center: i32, other_center: i32| match center.cmp(&other_center) {
+            Ordering::Less => -1,
+            Ordering::Greater => 1,
+            Ordering::Equal => if entity.id() < other.id() { -1 } else { 1 },
+        };
+        // Each entity only needs to cover half of the overlap since the other one moves too
+        if rect.width() <= rect.height() {
+            let adjustment = ((rect.width() as i32 + 1) / 2).min(max_push);
+            next_pos = next_pos.offset(away(bounds.center().x(), other_rect.center().x()) * adjustment, 0);
+        } else {
+            let adjustment = ((rect.height() as i32 + 1) / 2).min(max_push);
+            next_pos = next_pos.offset(0, away(bounds.center().y(), other_rect.center().y()) * adjustment);
+        }
+    }
+
+    next_pos
+}
+
+/// Does the minimal amount of movement needed to stop overlapping with each of the given
+/// rectangles
+fn separate(bounds_box: BoundingBox, mut next_pos: Point, others: &[Rect]) -> Point {
+    for &other in others {
+        // Recalculate bounds based on latest next_pos
+        let bounds = bounds_box.to_rect(next_pos);
+
+        // Need to recalculate the intersection since we are changing next_pos in each
+        // iteration. Would not make sense to precalculate the intersections when
+        // collecting potential collision objects
+        if let Some(rect) = bounds.intersection(other) {
+            // Do the minimal amount of movement in one direction to avoid the collision
+            if rect.width() <= rect.height() {
+                let adjustment = rect.width() as i32;
+                if rect.x() > next_pos.x() {
+                    // Collision was on the right so we'll move left
+                    next_pos = next_pos.offset(-adjustment, 0);
+                } else {
+                    // Collision was on the left so we'll move right
+                    next_pos = next_pos.offset(adjustment, 0);
+                }
+            } else {
+                let adjustment = rect.height() as i32;
+                // Need to make sure to use > instead of >= here or else we will fly
+                // through walls when moving up into them. Do not want to move up by
+                // the given adjustment when the colliding object is already above us.
+                if rect.y() > next_pos.y() {
+                    // Collision was below so we'll move up
+                    next_pos = next_pos.offset(0, -adjustment);
+                } else {
+                    // Collision was above so we'll move down
+                    next_pos = next_pos.offset(0, adjustment);
+                }
+            }
+        }
+    }
+
+    next_pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{Builder, RunNow};
+
+    use crate::generator::EnemyValues;
+    use crate::components::{MovementDirection, EnemyBehaviour};
+    use crate::map::{GridSize, TilePos, Tile};
+    use crate::map_sprites::WallSprite;
+    use crate::resources::{Event, Key};
+    use crate::test_helpers::{TestWorld, level_world, walled_room, test_animations};
+
+    #[test]
+    fn knockback_stops_at_walls() {
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 3, cols: 5}, tile_size);
+        // Column of walls to the east of the entity
+        for row in 0..3 {
+            map.grid_mut().place_tile(TilePos {row, col: 3}, Tile::new_wall(WallSprite::default()));
+        }
+        let wall_left = TilePos {row: 1, col: 3}.top_left(tile_size as i32).x();
+
+        let mut world = level_world(map);
+        System::setup(&mut Physics, &mut world.res);
+
+        let start = TilePos {row: 1, col: 2}.center(tile_size as i32);
+        let bounds = BoundingBox::Full {width: tile_size, height: tile_size};
+        let entity = world.create_entity()
+            .with(Position(start))
+            .with(bounds)
+            .with(Movement::default())
+            // Strong enough that it would go into the wall if walls were ignored
+            .with(Knockback::new(MovementDirection::East, 6, 4))
+            .build();
+
+        for _ in 0..4 {
+            Physics.run_now(&world.res);
+            world.maintain();
+        }
+
+        let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+        // Stopped flush with the wall (within the collision threshold)
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), wall_left);
+        assert!(pos.x() > start.x());
+        assert_eq!(pos.y(), start.y());
+        // Knockback is removed once it is complete
+        assert!(world.read_storage::<Knockback>().get(entity).is_none());
+    }
+
+    #[test]
+    fn custom_bounding_box_collides_at_its_offset() {
+        let tile_size = 16;
+        let mut map = FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size);
+        // Column of walls to the east of the entity
+        for row in 0..3 {
+            map.grid_mut().place_tile(TilePos {row, col: 6}, Tile::new_wall(WallSprite::default()));
+        }
+        let wall_left = TilePos {row: 1, col: 6}.top_left(tile_size as i32).x();
+
+        let mut world = level_world(map);
+        System::setup(&mut Physics, &mut world.res);
+
+        // A box that sits entirely east of the position
+        let start = TilePos {row: 1, col: 2}.center(tile_size as i32);
+        let bounds = BoundingBox::Custom {offset: Point::new(8, 0), width: 8, height: 8};
+        let entity = world.create_entity()
+            .with(Position(start))
+            .with(bounds)
+            .with(Movement {direction: MovementDirection::East, sideways: None, speed: 3.0, ..Movement::default()})
+            .build();
+
+        for _ in 0..30 {
+            Physics.run_now(&world.res);
+            world.maintain();
+        }
+
+        // The box is flush with the wall (within the collision threshold) even though the
+        // position is still well away from it
+        let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), wall_left);
+        assert!(wall_left - pos.x() > tile_size as i32 / 2);
+        assert_eq!(pos.y(), start.y());
+    }
+
+    #[test]
+    fn knockback_applies_while_waiting() {
+        let tile_size = 16;
+        let map = FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size);
+
+        let mut world = level_world(map);
+        System::setup(&mut Physics, &mut world.res);
+
+        let start = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let entity = world.create_entity()
+            .with(Position(start))
+            .with(BoundingBox::Full {width: tile_size, height: tile_size})
+            .with(Movement {direction: MovementDirection::West, sideways: None, speed: 3.0, ..Movement::default()})
+            .with(Wait::new(10))
+            .with(Knockback::new(MovementDirection::East, 4, 3))
+            .build();
+
+        Physics.run_now(&world.res);
+        world.maintain();
+
+        // Only the knockback should have moved the entity since it is waiting
+        let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+        assert_eq!(pos, start + Point::new(4, 0));
+    }
+
+    #[test]
+    fn player_stops_at_walls() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 6, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        let start = test.position(player);
+        let wall_left = TilePos {row: 2, col: 5}.top_left(tile_size as i32).x();
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step(30);
+        let pos = test.position(player);
+        // Stopped flush with the wall (within the collision threshold) and kept trying to walk
+        // into it without going any further
+        let bounds = *test.world.read_storage::<BoundingBox>().get(player).unwrap();
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), wall_left);
+        assert_eq!(pos.y(), start.y());
+        test.step(10);
+        assert_eq!(test.position(player), pos);
+    }
+    #[test]
+    fn player_slides_along_walls() {
+        let tile_size = 16;
+        let mut map = walled_room(8, 10, tile_size);
+        // A short wall across the middle of the room, above where the player starts
+        for col in 1..=6 {
+            map.grid_mut().place_tile(TilePos {row: 3, col}, Tile::new_wall(WallSprite::default()));
+        }
+        let wall_bottom = TilePos {row: 3, col: 1}.top_left(tile_size as i32).y() + tile_size as i32;
+
+        let mut test = TestWorld::with_map(map);
+        let player = test.spawn_player_at(TilePos {row: 5, col: 2});
+        let start = test.position(player);
+        let bounds = *test.world.read_storage::<BoundingBox>().get(player).unwrap();
+
+        // Moving diagonally up and to the right
+        test.step_with_events(vec![Event::KeyDown(Key::UpArrow), Event::KeyDown(Key::RightArrow)]);
+        let pos = test.position(player);
+        assert!(pos.x() > start.x() && pos.y() < start.y());
+        assert_eq!(pos.x() - start.x(), start.y() - pos.y());
+
+        // Blocked going up, but keeps moving to the right along the wall
+        test.step(20);
+        let pos = test.position(player);
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).top(), wall_bottom);
+        let blocked = pos;
+        test.step(5);
+        let pos = test.position(player);
+        assert_eq!(pos.y(), blocked.y());
+        assert!(pos.x() > blocked.x());
+
+        // Past the end of the wall, moving up is possible again
+        test.step(40);
+        let pos = test.position(player);
+        assert!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).bottom() <= wall_bottom);
+        // Facing the most recent direction
+        assert_eq!(test.world.read_storage::<Movement>().get(player).unwrap().direction, MovementDirection::East);
+    }
+
+    #[test]
+    fn fractional_speed_travels_exact_distance() {
+        let tile_size = 16;
+        let mut world = level_world(FloorMap::new(GridSize {rows: 3, cols: 14}, tile_size));
+        System::setup(&mut Physics, &mut world.res);
+
+        let start = TilePos {row: 1, col: 1}.center(tile_size as i32);
+        let entity = world.create_entity()
+            .with(Position(start))
+            .with(BoundingBox::Full {width: 8, height: 8})
+            .with(Movement {direction: MovementDirection::East, sideways: None, speed: 1.5, ..Movement::default()})
+            .build();
+
+        let mut steps = Vec::new();
+        let mut last = start;
+        for _ in 0..100 {
+            Physics.run_now(&world.res);
+            world.maintain();
+            let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+            steps.push(pos.x() - last.x());
+            last = pos;
+        }
+
+        // Alternates between 1px and 2px every frame
+        assert!(steps.chunks(2).all(|pair| pair == [1, 2]), "moved {:?}", steps);
+        assert_eq!(last, start + Point::new(150, 0));
+    }
+
+    #[test]
+    fn fractional_speed_never_tunnels_through_walls() {
+        let tile_size = 16;
+        for &speed in &[1.5, 7.5, 15.5, 20.25] {
+            let mut map = FloorMap::new(GridSize {rows: 3, cols: 8}, tile_size);
+            // A wall that is only one tile thick
+            for row in 0..3 {
+                map.grid_mut().place_tile(TilePos {row, col: 4}, Tile::new_wall(WallSprite::default()));
+            }
+            let wall_left = TilePos {row: 1, col: 4}.top_left(tile_size as i32).x();
+
+            let mut world = level_world(map);
+            System::setup(&mut Physics, &mut world.res);
+
+            let bounds = BoundingBox::Full {width: 8, height: 8};
+            let entity = world.create_entity()
+                .with(Position(TilePos {row: 1, col: 1}.center(tile_size as i32)))
+                .with(bounds)
+                .with(Movement {direction: MovementDirection::East, sideways: None, speed, ..Movement::default()})
+                .build();
+
+            for _ in 0..100 {
+                Physics.run_now(&world.res);
+                world.maintain();
+                let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+                assert!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right() <= wall_left,
+                    "speed {} went into the wall", speed);
+            }
+            let Position(pos) = *world.read_storage::<Position>().get(entity).unwrap();
+            assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), wall_left);
+        }
+    }
+
+    /// An enemy that never moves on its own
+    fn stationary_enemy(tile_size: u32) -> EnemyValues {
+        EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 0.0,
+            health_points: 15,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: tile_size, height: tile_size},
+        }
+    }
+
+    #[test]
+    fn player_stops_flush_with_enemies() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 10, tile_size);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 1});
+        let start = test.position(player);
+        test.world.write_storage::<Movement>().insert(player,
+            Movement {direction: MovementDirection::East, sideways: None, speed: 3.0, ..Movement::default()}).unwrap();
+        let enemy = test.spawn_enemy_at(TilePos {row: 2, col: 6}, stationary_enemy(tile_size));
+        let enemy_bounds = *test.world.read_storage::<BoundingBox>().get(enemy).unwrap();
+        let enemy_left = enemy_bounds.shrink(COLLISION_THRESHOLD).to_rect(test.position(enemy)).left();
+
+        // Only running physics so that the enemy does not get to attack the player
+        for _ in 0..30 {
+            Physics.run_now(&test.world.res);
+            test.world.maintain();
+        }
+
+        let pos = test.position(player);
+        let bounds = *test.world.read_storage::<BoundingBox>().get(player).unwrap();
+        assert_eq!(bounds.shrink(COLLISION_THRESHOLD).to_rect(pos).right(), enemy_left);
+        assert_eq!(pos.y(), start.y());
+    }
+
+    #[test]
+    fn stacked_enemies_separate() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(5, 7, tile_size);
+        let start = test.tile_center(TilePos {row: 2, col: 3});
+        let enemies: Vec<_> = (0..2)
+            .map(|_| test.spawn_enemy_at(TilePos {row: 2, col: 3}, stationary_enemy(tile_size)))
+            .collect();
+        let bounds = *test.world.read_storage::<BoundingBox>().get(enemies[0]).unwrap();
+        let bounds = bounds.shrink(COLLISION_THRESHOLD);
+        let rects = |test: &TestWorld| (bounds.to_rect(test.position(enemies[0])), bounds.to_rect(test.position(enemies[1])));
+
+        Physics.run_now(&test.world.res);
+        test.world.maintain();
+        // Pushed apart gently rather than all at once
+        let (first, second) = rects(&test);
+        assert!(first.has_intersection(second));
+        assert!(first.x() < bounds.to_rect(start).x() && second.x() > bounds.to_rect(start).x());
+
+        for _ in 0..5 {
+            Physics.run_now(&test.world.res);
+            test.world.maintain();
+        }
+        let (first, second) = rects(&test);
+        assert!(!first.has_intersection(second), "{:?} and {:?} still overlap", first, second);
+        // Only pushed sideways and never into the walls
+        assert_eq!(first.y(), second.y());
+        assert!(first.left() >= tile_size as i32 && second.right() <= 6 * tile_size as i32);
     }
 }
@@ -0,0 +1,160 @@
+//! Fires arrows from arrow shooters and moves every projectile until it hits a wall. Hitting
+//! entities is handled along with every other kind of damage in the interactions system.
+
+use specs::{System, Join, Read, ReadExpect, ReadStorage, WriteStorage, Entities, LazyUpdate, Builder};
+
+use crate::components::{Position, BoundingBox, Ghost, Sprite, Projectile, ArrowShooter, RenderLayer};
+use crate::resources::{FramesElapsed, RoomTracker};
+use crate::assets::scale_to_tile_size;
+use crate::map::FloorMap;
+
+use super::JoinedEntities;
+
+/// The speed (px/frame at NATIVE_TILE_SIZE) of an arrow
+const ARROW_SPEED: i32 = 4;
+/// The damage done by an arrow
+const ARROW_DAMAGE: usize = 4;
+/// The width and height (in px at NATIVE_TILE_SIZE) of the bounding box of an arrow
+const ARROW_SIZE: i32 = 6;
+
+#[derive(SystemData)]
+pub struct ProjectilesData<'a> {
+    entities: Entities<'a>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    map: ReadExpect<'a, FloorMap>,
+    room_tracker: Read<'a, RoomTracker>,
+    positions: WriteStorage<'a, Position>,
+    projectiles: ReadStorage<'a, Projectile>,
+    shooters: WriteStorage<'a, ArrowShooter>,
+    updater: ReadExpect<'a, LazyUpdate>,
+}
+
+#[derive(Default)]
+pub struct Projectiles;
+
+impl<'a> System<'a> for Projectiles {
+    type SystemData = ProjectilesData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let ProjectilesData {entities, frames, map, room_tracker, mut positions, projectiles, mut shooters, updater} = data;
+        let FramesElapsed(frames_elapsed) = *frames;
+        let tile_size = map.tile_size();
+
+        // Projectiles stop as soon as they fly into anything that is not floor
+        let level_boundary = map.level_boundary();
+        for (projectile, Position(pos), &Projectile {velocity, ..}) in (&entities, &mut positions, &projectiles).join() {
+            *pos += velocity * frames_elapsed as i32;
+            if !level_boundary.contains_point(*pos) || !map.grid().get(map.world_to_tile_pos(*pos)).is_floor() {
+                entities.delete(projectile)
+                    .expect("bug: unable to delete projectile");
+            }
+        }
+
+        // Shooters only fire while the player is in the room that they face
+        let current_room = room_tracker.current();
+        for (shooter, &Position(pos), arrow_shooter) in (&entities, &positions, &mut shooters).join() {
+            if Some(arrow_shooter.room) != current_room || !arrow_shooter.step(frames_elapsed) {
+                continue;
+            }
+
+            // Arrows start in the middle of the tile in front of the shooter
+            let direction = arrow_shooter.direction.to_vector();
+            let size = scale_to_tile_size(ARROW_SIZE, tile_size) as u32;
+            updater.create_entity(&entities)
+                .with(Position(pos + direction * tile_size as i32))
+                .with(BoundingBox::Full {width: size, height: size})
+                .with(Sprite(arrow_shooter.arrow))
+                .with(RenderLayer::PROJECTILES)
+                .with(Ghost)
+                .with(Projectile {
+                    velocity: direction * scale_to_tile_size(ARROW_SPEED, tile_size),
+                    damage: ARROW_DAMAGE,
+                    owner: Some(shooter),
+                })
+                .build();
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for Projectiles {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.projectiles).join().count()
+            + (&data.positions, &data.shooters).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::{Point, Rect};
+    use specs::{World, Entity, RunNow};
+
+    use crate::assets::{SpriteManager, SpriteImage, TextureId};
+    use crate::components::MovementDirection;
+    use crate::map::TilePos;
+    use crate::test_helpers::{level_world, walled_room};
+
+    fn setup_world() -> World {
+        let mut world = level_world(walled_room(5, 8, 16));
+        System::setup(&mut Projectiles, &mut world.res);
+        // Used by the arrows that are fired
+        world.register::<BoundingBox>();
+        world.register::<Sprite>();
+        world.register::<Ghost>();
+        world.register::<RenderLayer>();
+        world
+    }
+
+    fn fire(world: &mut World, pos: Point, velocity: Point) -> Entity {
+        world.create_entity()
+            .with(Position(pos))
+            .with(BoundingBox::Full {width: 6, height: 6})
+            .with(Ghost)
+            .with(Projectile {velocity, damage: 4, owner: None})
+            .build()
+    }
+
+    #[test]
+    fn projectile_stops_at_wall() {
+        let mut world = setup_world();
+        let arrow = fire(&mut world, TilePos {row: 2, col: 1}.center(16), Point::new(4, 0));
+
+        // The wall on the east side of the room is at column 7
+        let mut frames = 0;
+        while world.is_alive(arrow) {
+            Projectiles.run_now(&world.res);
+            world.maintain();
+            frames += 1;
+            assert!(frames < 100, "projectile never stopped");
+        }
+        // Stopped on the first frame that it reached the wall
+        let distance = TilePos {row: 2, col: 7}.top_left(16).x() - TilePos {row: 2, col: 1}.center(16).x();
+        assert_eq!(frames, (distance + 3) / 4);
+    }
+
+    #[test]
+    fn shooter_only_fires_while_player_in_room() {
+        let mut world = setup_world();
+        let room_id = world.read_resource::<FloorMap>().rooms().next().unwrap().0;
+        let sprite = SpriteManager::default().add(SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, 16, 16)));
+        world.create_entity()
+            .with(Position(TilePos {row: 0, col: 3}.center(16)))
+            .with(ArrowShooter::new(MovementDirection::South, room_id, 3, sprite))
+            .build();
+        let arrows = |world: &World| world.read_storage::<Projectile>().join().count();
+
+        for _ in 0..6 {
+            Projectiles.run_now(&world.res);
+            world.maintain();
+        }
+        assert_eq!(arrows(&world), 0);
+
+        world.write_resource::<RoomTracker>().update(Some(room_id), 1);
+        for _ in 0..6 {
+            Projectiles.run_now(&world.res);
+            world.maintain();
+        }
+        assert_eq!(arrows(&world), 2);
+    }
+}
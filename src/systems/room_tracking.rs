@@ -0,0 +1,102 @@
+//! Keeps track of the room that the player is in so that other parts of the game can react when
+//! the player moves into a different room
+
+use specs::{System, Join, ReadExpect, WriteExpect, ReadStorage};
+
+use crate::components::{Position, Player};
+use crate::resources::{FramesElapsed, EventQueue, RoomTracker};
+
+use crate::map::FloorMap;
+
+use super::JoinedEntities;
+
+#[derive(SystemData)]
+pub struct RoomTrackingData<'a> {
+    frames: ReadExpect<'a, FramesElapsed>,
+    map: ReadExpect<'a, FloorMap>,
+    tracker: WriteExpect<'a, RoomTracker>,
+    events: WriteExpect<'a, EventQueue>,
+    positions: ReadStorage<'a, Position>,
+    players: ReadStorage<'a, Player>,
+}
+
+pub struct RoomTracking;
+
+impl<'a> System<'a> for RoomTracking {
+    type SystemData = RoomTrackingData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let RoomTrackingData {frames, map, mut tracker, mut events, positions, players} = data;
+        let FramesElapsed(frames_elapsed) = *frames;
+
+        let room = match (&positions, &players).join().next() {
+            Some((&Position(pos), _)) => map.grid().get(map.world_to_tile_pos(pos)).floor_room_id(),
+            None => return,
+        };
+        if let Some(event) = tracker.update(room, frames_elapsed) {
+            events.0.push(event);
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for RoomTracking {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.players).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::{TilePos, RoomId};
+    use crate::map_sprites::FloorSprite;
+    use crate::resources::Event;
+    use crate::test_helpers::{TestWorld, rooms_side_by_side};
+
+    /// Two rooms side by side that share the wall at column 7, with a doorway at row 4
+    fn two_rooms() -> FloorMap {
+        let (mut map, rooms) = rooms_side_by_side(9, &[8, 8], true, 16);
+        map.grid_mut().get_mut(TilePos {row: 4, col: 7}).become_floor(rooms[1], FloorSprite::default());
+        map
+    }
+
+    /// Runs a single frame and returns every room change reported during it
+    fn room_changes(test: &mut TestWorld<'_>) -> Vec<(Option<RoomId>, RoomId)> {
+        test.step(1);
+        test.world.read_resource::<EventQueue>().0.iter().filter_map(|event| match *event {
+            Event::RoomChanged {from, to} => Some((from, to)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn reports_room_changes_without_flapping() {
+        let mut test = TestWorld::with_map(two_rooms());
+        let rooms: Vec<_> = test.world.read_resource::<FloorMap>().rooms().map(|(id, _)| id).collect();
+        let (west, east) = (rooms[0], rooms[1]);
+        let player = test.spawn_player_at(TilePos {row: 4, col: 3});
+        let doorway = test.tile_center(TilePos {row: 4, col: 7});
+        let inside_west = test.tile_center(TilePos {row: 4, col: 6});
+        let inside_east = test.tile_center(TilePos {row: 4, col: 9});
+        let move_player = |test: &mut TestWorld<'_>, pos| {
+            test.world.write_storage::<Position>().insert(player, Position(pos)).unwrap();
+        };
+
+        assert_eq!(room_changes(&mut test), &[(None, west)]);
+        assert_eq!(test.world.read_resource::<RoomTracker>().current(), Some(west));
+
+        // Pacing back and forth through the doorway never settles in the other room
+        for _ in 0..10 {
+            move_player(&mut test, doorway);
+            assert_eq!(room_changes(&mut test), &[]);
+            move_player(&mut test, inside_west);
+            assert_eq!(room_changes(&mut test), &[]);
+        }
+
+        move_player(&mut test, inside_east);
+        let changes: Vec<_> = (0..RoomTracker::STABLE_FRAMES).flat_map(|_| room_changes(&mut test)).collect();
+        assert_eq!(changes, &[(Some(west), east)]);
+        assert_eq!(test.world.read_resource::<RoomTracker>().current(), Some(east));
+    }
+}
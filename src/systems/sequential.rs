@@ -0,0 +1,185 @@
+//! A dispatcher that runs systems one at a time so that each system can be timed
+
+use std::time::{Duration, Instant};
+
+use specs::{System, RunNow, Resources, DispatcherBuilder};
+use shred::DynamicSystemData;
+
+/// A system that can report how many entities it goes through when it runs
+pub trait JoinedEntities<'a>: System<'a> {
+    /// Returns the number of entities visited by the joins that the system runs on every frame
+    fn joined_entities(data: &Self::SystemData) -> usize;
+}
+
+/// Anything that systems can be added to in the order they should run. Implemented by both the
+/// parallel dispatcher builder and the sequential dispatcher so that the same code can configure
+/// either one.
+pub trait DispatchBuilder<'a>: Sized {
+    /// Adds a system with the given name that runs after all of the given dependencies
+    fn with<S>(self, system: S, name: &'static str, deps: &[&str]) -> Self
+        where S: for<'c> JoinedEntities<'c> + Send + 'a;
+}
+
+impl<'a, 'b> DispatchBuilder<'a> for DispatcherBuilder<'a, 'b> {
+    fn with<S>(self, system: S, name: &'static str, deps: &[&str]) -> Self
+        where S: for<'c> JoinedEntities<'c> + Send + 'a {
+        DispatcherBuilder::with(self, system, name, deps)
+    }
+}
+
+/// The time that a single system took to run
+#[derive(Debug, Clone, Copy)]
+pub struct SystemTime {
+    pub name: &'static str,
+    pub duration: Duration,
+    /// The number of entities that the system joined over
+    pub entities: usize,
+}
+
+/// A system that can be run directly on the resources and can count the entities it joins over
+trait SequentialSystem<'a>: RunNow<'a> {
+    fn count_joined(&self, res: &'a Resources) -> usize;
+}
+
+impl<'a, S: JoinedEntities<'a>> SequentialSystem<'a> for S {
+    fn count_joined(&self, res: &'a Resources) -> usize {
+        S::joined_entities(&S::SystemData::fetch(&self.accessor(), res))
+    }
+}
+
+/// A system that can be run directly on the resources
+type BoxedSystem<'a> = Box<dyn for<'c> SequentialSystem<'c> + 'a>;
+
+/// Runs systems one at a time in the order that they were added.
+///
+/// Much slower than the parallel dispatcher, but the time taken by each system is recorded.
+#[derive(Default)]
+pub struct SequentialDispatcher<'a> {
+    systems: Vec<(&'static str, BoxedSystem<'a>)>,
+}
+
+impl<'a> DispatchBuilder<'a> for SequentialDispatcher<'a> {
+    fn with<S>(mut self, system: S, name: &'static str, deps: &[&str]) -> Self
+        where S: for<'c> JoinedEntities<'c> + Send + 'a {
+        // Running in the order systems were added only respects the dependencies if every
+        // dependency has already been added
+        for dep in deps {
+            assert!(self.systems.iter().any(|(added, _)| added == dep),
+                "bug: system `{}` was added before its dependency `{}`", name, dep);
+        }
+
+        self.systems.push((name, Box::new(system)));
+        self
+    }
+}
+
+impl<'a> SequentialDispatcher<'a> {
+    /// Sets up the resources for all of the systems
+    pub fn setup(&mut self, res: &mut Resources) {
+        for (_, system) in &mut self.systems {
+            system.setup(res);
+        }
+    }
+
+    /// Runs every system in order and returns the time taken by each one along with the number of
+    /// entities it joined over
+    pub fn dispatch(&mut self, res: &Resources) -> Vec<SystemTime> {
+        self.systems.iter_mut().map(|(name, system)| {
+            let start = Instant::now();
+            system.run_now(res);
+            let duration = start.elapsed();
+            // Counted outside of the timed section so that the count never makes a system look slow
+            SystemTime {name, duration, entities: system.count_joined(res)}
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::Point;
+    use specs::{Builder, Entity};
+    use component_group::ComponentGroup;
+
+    use crate::systems::{Keyboard, LevelDispatcher, build_dispatcher};
+    use crate::components::{Position, BoundingBox, HealthPoints};
+    use crate::resources::{GameStateMachine, ActionQueue, EventQueue, SoundQueue, NotificationQueue, ExploredTiles, Event, Key};
+    use crate::map::{FloorMap, TilePos};
+    use crate::test_helpers::{walled_room, level_world, player_components};
+
+    /// Runs a scripted scenario with the given dispatcher and returns the final player position,
+    /// the final target position and health, and the number of explored tiles
+    fn run_scenario(mut dispatcher: LevelDispatcher) -> (Point, Point, usize, usize) {
+        let tile_size = 16;
+        let mut world = level_world(walled_room(5, 12, tile_size));
+        dispatcher.setup(&mut world.res);
+
+        let player = player_components(TilePos {row: 2, col: 2}.center(tile_size as i32)).create(&mut world);
+        let target = world.create_entity()
+            .with(HealthPoints(30))
+            .with(Position(TilePos {row: 2, col: 6}.center(tile_size as i32)))
+            .with(BoundingBox::Full {width: 16, height: 16})
+            .build();
+
+        let mut script = vec![vec![Event::KeyDown(Key::RightArrow)]];
+        script.extend((0..10).map(|_| Vec::new()));
+        script.push(vec![Event::KeyUp(Key::RightArrow)]);
+        script.push(vec![Event::KeyUp(Key::B)]);
+        script.extend((0..5).map(|_| Vec::new()));
+
+        for events in script {
+            *world.write_resource() = GameStateMachine::default();
+            *world.write_resource() = ActionQueue::default();
+            *world.write_resource() = SoundQueue::default();
+            *world.write_resource() = NotificationQueue::default();
+            *world.write_resource() = EventQueue(events);
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+        }
+
+        let position = |entity: Entity| world.read_storage::<Position>().get(entity).unwrap().0;
+        let HealthPoints(target_health) = *world.read_storage::<HealthPoints>().get(target).unwrap();
+        let explored = world.read_resource::<ExploredTiles>();
+        let explored = world.read_resource::<FloorMap>().grid().tile_positions()
+            .filter(|&pos| explored.contains(pos))
+            .count();
+        (position(player), position(target), target_health, explored)
+    }
+
+    #[test]
+    fn sequential_matches_parallel() {
+        let parallel = LevelDispatcher::Parallel(
+            build_dispatcher(DispatcherBuilder::new(), Keyboard::default()).build());
+        let sequential = LevelDispatcher::Watchdog(
+            build_dispatcher(SequentialDispatcher::default(), Keyboard::default()));
+
+        let expected = run_scenario(parallel);
+        // Make sure the scenario actually did something
+        let (_, _, target_health, explored) = expected;
+        assert!(target_health < 30);
+        assert!(explored > 0);
+
+        assert_eq!(run_scenario(sequential), expected);
+    }
+
+    #[test]
+    fn counts_joined_entities() {
+        let tile_size = 16;
+        let mut world = level_world(walled_room(5, 12, tile_size));
+        let mut dispatcher = build_dispatcher(SequentialDispatcher::default(), Keyboard::default());
+        dispatcher.setup(&mut world.res);
+
+        player_components(TilePos {row: 2, col: 2}.center(tile_size as i32)).create(&mut world);
+        for col in 4..8 {
+            world.create_entity().with(Position(TilePos {row: 2, col}.center(tile_size as i32))).build();
+        }
+
+        let times = dispatcher.dispatch(&world.res);
+        let entities = |name| times.iter().find(|time| time.name == name).unwrap().entities;
+        // Only the player moves, but every entity with a position goes into the spatial grid
+        assert_eq!(entities("Physics"), 1 + 5);
+        assert_eq!(entities("Keyboard"), 1);
+        assert_eq!(entities("RoomTracking"), 1);
+    }
+}
@@ -2,6 +2,8 @@ use std::sync::{Arc, Mutex};
 
 use specs::System;
 
+use super::JoinedEntities;
+
 #[derive(Debug, Default)]
 pub struct SharedSystem<S> {
     system: Arc<Mutex<S>>,
@@ -28,3 +30,9 @@ impl<'a, S: System<'a>> System<'a> for SharedSystem<S> {
             .run(data);
     }
 }
+
+impl<'a, S: JoinedEntities<'a>> JoinedEntities<'a> for SharedSystem<S> {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        S::joined_entities(data)
+    }
+}
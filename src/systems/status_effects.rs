@@ -0,0 +1,77 @@
+//! Counts down status effects (e.g. poison) and removes them once they expire
+
+use specs::{System, Join, ReadExpect, WriteStorage, Entities};
+
+use crate::components;
+
+use crate::resources::FramesElapsed;
+
+use super::JoinedEntities;
+
+#[derive(SystemData)]
+pub struct StatusEffectsData<'a> {
+    entities: Entities<'a>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    status_effects: WriteStorage<'a, components::StatusEffects>,
+}
+
+/// Ticks every status effect. Any damage done by the effects is left for the Interactions system
+/// to apply, so it is handled the same way as every other kind of damage.
+pub struct StatusEffects;
+
+impl<'a> System<'a> for StatusEffects {
+    type SystemData = StatusEffectsData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let StatusEffectsData {entities, frames, mut status_effects} = data;
+        let FramesElapsed(frames_elapsed) = *frames;
+
+        let mut expired = Vec::new();
+        for (entity, effects) in (&entities, &mut status_effects).join() {
+            effects.step(frames_elapsed);
+            if effects.is_empty() {
+                expired.push(entity);
+            }
+        }
+
+        for entity in expired {
+            status_effects.remove(entity);
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for StatusEffects {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        data.status_effects.join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::components::{StatusEffect, StatusKind, HealthPoints};
+    use crate::map::TilePos;
+    use crate::test_helpers::TestWorld;
+
+    #[test]
+    fn poison_damages_player_until_expired() {
+        let mut test = TestWorld::new(5, 5, 16);
+        let player = test.spawn_player_at(TilePos {row: 2, col: 2});
+        let health = |test: &TestWorld| test.world.read_storage::<HealthPoints>().get(player).unwrap().0;
+        let start_health = health(&test);
+
+        let mut effects = components::StatusEffects::default();
+        effects.apply(StatusEffect::poison(StatusKind::POISON_DAMAGE_FRAMES * 2, 1));
+        test.world.write_storage().insert(player, effects).unwrap();
+
+        test.step(StatusKind::POISON_DAMAGE_FRAMES - 1);
+        assert_eq!(health(&test), start_health);
+        test.step(1);
+        assert_eq!(health(&test), start_health - 1);
+
+        test.step(StatusKind::POISON_DAMAGE_FRAMES * 3);
+        assert_eq!(health(&test), start_health - 2);
+        assert!(test.world.read_storage::<components::StatusEffects>().get(player).is_none());
+    }
+}
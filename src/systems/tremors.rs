@@ -0,0 +1,244 @@
+//! Tremors that collapse one of the doors of a level after the player has been there for a while
+
+use std::collections::HashSet;
+use std::iter::once;
+
+use rand::seq::SliceRandom;
+use specs::{Entity, System, Join, Write, ReadExpect, WriteExpect, ReadStorage, Entities};
+
+use crate::components::{Position, BoundingBox, Player, Door, Locked, Ghost};
+use crate::resources::{FramesElapsed, Tremor, ScreenShake, SoundQueue, NotificationQueue, LevelRng};
+use crate::audio::SoundEffect;
+use crate::map::{FloorMap, RoomType, TilePos};
+use crate::map_sprites::{WallSprite, WallSpriteAlternate};
+
+use super::JoinedEntities;
+
+/// How far (in px) and for how many frames the screen shakes during a tremor
+const TREMOR_SHAKE_AMPLITUDE: u32 = 3;
+const TREMOR_SHAKE_FRAMES: usize = 20;
+
+/// Returns true if the given tile is a floor tile of a challenge room
+fn is_challenge_room_floor(map: &FloorMap, pos: TilePos) -> bool {
+    match map.grid().get(pos).floor_room_id() {
+        Some(room_id) => map.room(room_id).room_type() == RoomType::Challenge,
+        None => false,
+    }
+}
+
+/// Returns the doors that can collapse without cutting off any part of the level. A door is only
+/// returned if the tiles on either side of it can still reach each other without passing through
+/// it or any of the `blocked` tiles. Doors beside challenge rooms are never returned.
+pub fn collapsible_doors(map: &FloorMap, doors: &[(Entity, TilePos)], blocked: &HashSet<TilePos>) -> Vec<Entity> {
+    let grid = map.grid();
+    doors.iter().filter_map(|&(door, pos)| {
+        let sides: Vec<_> = grid.adjacent_positions(pos)
+            .filter(|&adj| grid.get(adj).is_floor())
+            .collect();
+        if sides.is_empty() || once(pos).chain(sides.iter().cloned()).any(|pt| is_challenge_room_floor(map, pt)) {
+            return None;
+        }
+
+        let reachable = grid.depth_first_search(sides[0], |_, adj| {
+            adj != pos && grid.get(adj).is_floor() && !blocked.contains(&adj)
+        });
+        if sides.iter().all(|side| reachable.contains(side)) {
+            Some(door)
+        } else {
+            None
+        }
+    }).collect()
+}
+
+#[derive(SystemData)]
+pub struct TremorsData<'a> {
+    entities: Entities<'a>,
+    frames: ReadExpect<'a, FramesElapsed>,
+    map: WriteExpect<'a, FloorMap>,
+    tremor: Write<'a, Tremor>,
+    rng: Write<'a, LevelRng>,
+    screen_shake: Write<'a, ScreenShake>,
+    sounds: WriteExpect<'a, SoundQueue>,
+    notifications: WriteExpect<'a, NotificationQueue>,
+    positions: ReadStorage<'a, Position>,
+    bounding_boxes: ReadStorage<'a, BoundingBox>,
+    players: ReadStorage<'a, Player>,
+    doors: ReadStorage<'a, Door>,
+    locked: ReadStorage<'a, Locked>,
+    ghosts: ReadStorage<'a, Ghost>,
+}
+
+pub struct Tremors;
+
+impl<'a> System<'a> for Tremors {
+    type SystemData = TremorsData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let TremorsData {
+            entities,
+            frames,
+            mut map,
+            mut tremor,
+            mut rng,
+            mut screen_shake,
+            mut sounds,
+            mut notifications,
+            positions,
+            bounding_boxes,
+            players,
+            doors,
+            locked,
+            ghosts,
+        } = data;
+        let FramesElapsed(frames_elapsed) = *frames;
+
+        screen_shake.step(frames_elapsed);
+
+        let frames_remaining = match *tremor {
+            Tremor::Pending {frames_remaining} => frames_remaining.saturating_sub(frames_elapsed),
+            Tremor::Inactive => return,
+        };
+        // Challenge rooms are never disturbed, so the tremor waits until the player leaves
+        let in_challenge_room = (&positions, &players).join()
+            .any(|(&Position(pos), _)| is_challenge_room_floor(&map, map.world_to_tile_pos(pos)));
+        if frames_remaining > 0 || in_challenge_room {
+            *tremor = Tremor::Pending {frames_remaining};
+            return;
+        }
+        *tremor = Tremor::Inactive;
+
+        screen_shake.start(TREMOR_SHAKE_AMPLITUDE, TREMOR_SHAKE_FRAMES);
+        sounds.0.push(SoundEffect::Tremor);
+        notifications.push("The ground trembles");
+
+        let tile_size = map.tile_size();
+        let mut door_tiles: Vec<_> = (&entities, &positions, &doors).join()
+            .map(|(door, &Position(pos), _)| (door, map.world_to_tile_pos(pos)))
+            .collect();
+        // The door that collapses should never depend on the order that the doors were created in
+        door_tiles.sort_unstable_by_key(|&(_, pos)| pos);
+        // Locked doors may never open, so they cannot be relied on to get around
+        let blocked: HashSet<_> = door_tiles.iter()
+            .filter(|&&(door, _)| locked.get(door).is_some())
+            .map(|&(_, pos)| pos)
+            .collect();
+        // A door cannot collapse on top of anything that is standing in the doorway
+        let door_tiles: Vec<_> = door_tiles.into_iter().filter(|&(door, pos)| {
+            let tile = pos.tile_rect(tile_size);
+            !(&entities, &positions, &bounding_boxes, !&ghosts).join()
+                .any(|(other, &Position(other_pos), bounds, ())| {
+                    other != door && bounds.to_rect(other_pos).has_intersection(tile)
+                })
+        }).collect();
+
+        let collapsible = collapsible_doors(&map, &door_tiles, &blocked);
+        let door = match collapsible.choose(&mut rng.0) {
+            Some(&door) => door,
+            // Nothing can collapse without cutting off part of the level
+            None => return,
+        };
+        let &(_, pos) = door_tiles.iter().find(|&&(other, _)| other == door)
+            .expect("bug: collapsible door was not one of the doors");
+
+        entities.delete(door).expect("bug: failed to delete collapsed door");
+        map.grid_mut().get_mut(pos).become_wall(WallSprite {
+            alt: WallSpriteAlternate::Rubble,
+            ..WallSprite::default()
+        });
+    }
+}
+
+impl<'a> JoinedEntities<'a> for Tremors {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        (&data.positions, &data.doors).join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::Point;
+    use specs::{Builder, World};
+
+    use crate::map_sprites::FloorSprite;
+    use crate::test_helpers::{TestWorld, rooms_side_by_side};
+
+    /// Two rooms that share the wall at column 7. Each of the given rows of that wall becomes a
+    /// doorway.
+    fn two_rooms(doorway_rows: &[usize]) -> FloorMap {
+        let (mut map, rooms) = rooms_side_by_side(9, &[8, 8], true, 16);
+        for &row in doorway_rows {
+            map.grid_mut().get_mut(TilePos {row, col: 7}).become_floor(rooms[0], FloorSprite::default());
+        }
+        map
+    }
+
+    fn add_door(world: &mut World, pos: TilePos) -> Entity {
+        let tile_size = world.read_resource::<FloorMap>().tile_size();
+        world.create_entity()
+            .with(Position(pos.center(tile_size as i32)))
+            .with(Door::Closed)
+            .with(BoundingBox::Full {width: tile_size / 2, height: tile_size})
+            .build()
+    }
+
+    #[test]
+    fn only_collapses_doors_with_a_way_around() {
+        let mut world = World::new();
+        let door1 = world.create_entity().build();
+        let door2 = world.create_entity().build();
+        let pos1 = TilePos {row: 2, col: 7};
+        let pos2 = TilePos {row: 6, col: 7};
+
+        // The only way between the rooms
+        let map = two_rooms(&[pos1.row]);
+        assert!(collapsible_doors(&map, &[(door1, pos1)], &HashSet::new()).is_empty());
+
+        // Each door can collapse since the other one is still a way around
+        let map = two_rooms(&[pos1.row, pos2.row]);
+        let doors = [(door1, pos1), (door2, pos2)];
+        assert_eq!(collapsible_doors(&map, &doors, &HashSet::new()), vec![door1, door2]);
+        // ...unless the other door is locked
+        let blocked = once(pos2).collect();
+        assert_eq!(collapsible_doors(&map, &doors, &blocked), vec![door2]);
+    }
+
+    #[test]
+    fn tremor_replaces_door_with_rubble_once() {
+        let pos1 = TilePos {row: 2, col: 7};
+        let pos2 = TilePos {row: 6, col: 7};
+        let mut test = TestWorld::with_map(two_rooms(&[pos1.row, pos2.row]));
+        test.spawn_player_at(TilePos {row: 4, col: 3});
+        let door1 = add_door(&mut test.world, pos1);
+        let door2 = add_door(&mut test.world, pos2);
+        *test.world.write_resource() = Tremor::Pending {frames_remaining: 5};
+
+        test.step(4);
+        assert!(test.world.is_alive(door1) && test.world.is_alive(door2));
+        test.step(1);
+        assert_eq!(*test.world.read_resource::<Tremor>(), Tremor::Inactive);
+        assert_ne!(test.world.read_resource::<ScreenShake>().offset(), Point::new(0, 0));
+
+        // Exactly one of the doors collapsed and its tile became a wall
+        let alive: Vec<_> = [(door1, pos1), (door2, pos2)].iter()
+            .filter(|&&(door, _)| test.world.is_alive(door))
+            .cloned()
+            .collect();
+        assert_eq!(alive.len(), 1);
+        let (_, open) = alive[0];
+        let collapsed = if open == pos1 { pos2 } else { pos1 };
+        {
+            let map = test.world.read_resource::<FloorMap>();
+            let tile = map.grid().get(collapsed);
+            assert!(tile.is_wall());
+            assert_eq!(tile.wall_sprite().alt, WallSpriteAlternate::Rubble);
+            assert!(map.grid().get(open).is_floor());
+        }
+
+        // There is only ever one tremor per level
+        test.step(100);
+        assert_eq!(test.world.read_storage::<Door>().join().count(), 1);
+        assert_eq!(test.world.read_resource::<ScreenShake>().offset(), Point::new(0, 0));
+    }
+}
@@ -0,0 +1,154 @@
+//! Advances the tutorial as the player performs the action that each prompt asks for
+
+use specs::{System, Join, Entities, ReadExpect, WriteExpect, ReadStorage};
+
+use crate::components::Player;
+use crate::map::{FloorMap, RoomType};
+
+use crate::resources::{EventQueue, Event, Key, ActionQueue, Action, RoomTracker, TutorialState, TutorialStep};
+
+use super::JoinedEntities;
+
+#[derive(SystemData)]
+pub struct TutorialData<'a> {
+    entities: Entities<'a>,
+    map: ReadExpect<'a, FloorMap>,
+    tracker: ReadExpect<'a, RoomTracker>,
+    events: ReadExpect<'a, EventQueue>,
+    actions: ReadExpect<'a, ActionQueue>,
+    tutorial: WriteExpect<'a, TutorialState>,
+    players: ReadStorage<'a, Player>,
+}
+
+/// The tutorial is only shown while the player is in the start room. Pressing the Select key skips
+/// the rest of the tutorial from anywhere on the level.
+pub struct Tutorial;
+
+impl<'a> System<'a> for Tutorial {
+    type SystemData = TutorialData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let TutorialData {entities, map, tracker, events, actions, mut tutorial, players} = data;
+
+        if tutorial.is_finished() {
+            return;
+        }
+
+        // Set to true if the user has pressed the key that skips the tutorial
+        let mut skipped = false;
+        // Set to true if the user has pressed any of the keys that move the player
+        let mut moved = false;
+        for event in &*events {
+            match event {
+                Event::KeyDown(Key::Select) => skipped = true,
+                Event::KeyDown(Key::UpArrow) | Event::KeyDown(Key::DownArrow) |
+                Event::KeyDown(Key::LeftArrow) | Event::KeyDown(Key::RightArrow) => moved = true,
+                _ => {},
+            }
+        }
+
+        if skipped {
+            tutorial.skip();
+            return;
+        }
+
+        let in_start_room = tracker.current()
+            .map(|room_id| map.room(room_id).room_type() == RoomType::PlayerStart)
+            .unwrap_or(false);
+        tutorial.set_visible(in_start_room);
+        // Only actions taken while the prompt is shown count towards the tutorial
+        let step = match tutorial.visible_step() {
+            Some(step) => step,
+            None => return,
+        };
+
+        let player_action = |action| (&entities, &players).join().any(|(entity, _)| {
+            actions.0.get(&entity).map(|actions| actions.contains(&action)).unwrap_or(false)
+        });
+        let performed = match step {
+            TutorialStep::Move => moved,
+            TutorialStep::Attack => player_action(Action::Attack),
+            TutorialStep::Interact => player_action(Action::Interact),
+        };
+        if performed {
+            tutorial.complete(step);
+        }
+    }
+}
+
+impl<'a> JoinedEntities<'a> for Tutorial {
+    fn joined_entities(data: &Self::SystemData) -> usize {
+        data.players.join().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::TilePos;
+    use crate::test_helpers::{TestWorld, walled_room};
+
+    fn start_room_world<'a>() -> TestWorld<'a> {
+        let mut map = walled_room(7, 7, 16);
+        map.rooms_mut().next().unwrap().1.become_player_start();
+        let mut test = TestWorld::with_map(map);
+        test.spawn_player_at(TilePos {row: 3, col: 3});
+        *test.world.write_resource() = TutorialState::start();
+        test
+    }
+
+    fn current_step(test: &TestWorld) -> Option<TutorialStep> {
+        test.world.read_resource::<TutorialState>().visible_step()
+    }
+
+    #[test]
+    fn prompts_advance_as_actions_performed() {
+        let mut test = start_room_world();
+        test.step(1);
+        assert_eq!(current_step(&test), Some(TutorialStep::Move));
+
+        // Attacking before moving does not complete anything
+        test.step_with_events(vec![Event::KeyUp(Key::B)]);
+        assert_eq!(current_step(&test), Some(TutorialStep::Move));
+        // Nothing else can be done until the attack is over
+        test.step(30);
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        test.step_with_events(vec![Event::KeyUp(Key::RightArrow)]);
+        assert_eq!(current_step(&test), Some(TutorialStep::Attack));
+
+        test.step_with_events(vec![Event::KeyDown(Key::B)]);
+        test.step_with_events(vec![Event::KeyUp(Key::B)]);
+        assert_eq!(current_step(&test), Some(TutorialStep::Interact));
+
+        test.step(30);
+        test.step_with_events(vec![Event::KeyDown(Key::A)]);
+        test.step_with_events(vec![Event::KeyUp(Key::A)]);
+        assert_eq!(current_step(&test), None);
+        assert!(test.world.read_resource::<TutorialState>().is_finished());
+    }
+
+    #[test]
+    fn skipped_with_select() {
+        let mut test = start_room_world();
+        test.step(1);
+        test.step_with_events(vec![Event::KeyDown(Key::Select)]);
+        assert!(test.world.read_resource::<TutorialState>().is_finished());
+
+        // Never comes back, even after the player performs actions in the start room
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        assert_eq!(current_step(&test), None);
+    }
+
+    #[test]
+    fn hidden_outside_start_room() {
+        let mut test = TestWorld::new(7, 7, 16);
+        test.spawn_player_at(TilePos {row: 3, col: 3});
+        *test.world.write_resource() = TutorialState::start();
+
+        test.step_with_events(vec![Event::KeyDown(Key::RightArrow)]);
+        assert_eq!(current_step(&test), None);
+        assert_eq!(test.world.read_resource::<TutorialState>().current(), Some(TutorialStep::Move));
+    }
+}
@@ -0,0 +1,84 @@
+//! Detects frames that take too long to update and reports which systems were responsible
+
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use specs::{Dispatcher, Resources};
+
+use super::{SequentialDispatcher, SystemTime};
+
+/// The maximum amount of time that updating a single frame should take (~30 FPS)
+pub const FRAME_BUDGET: Duration = Duration::from_millis(33);
+
+/// The number of slowest systems to report when a frame goes over budget
+const REPORTED_SYSTEMS: usize = 3;
+
+/// Dispatches the systems of a level
+pub enum LevelDispatcher<'a, 'b> {
+    /// Runs systems in parallel with no additional overhead
+    Parallel(Dispatcher<'a, 'b>),
+    /// Runs systems one at a time and logs the slowest systems whenever a frame goes over the
+    /// frame budget
+    Watchdog(SequentialDispatcher<'a>),
+}
+
+impl<'a, 'b> LevelDispatcher<'a, 'b> {
+    /// Sets up the resources for all of the systems
+    pub fn setup(&mut self, res: &mut Resources) {
+        use self::LevelDispatcher::*;
+        match self {
+            Parallel(dispatcher) => dispatcher.setup(res),
+            Watchdog(dispatcher) => dispatcher.setup(res),
+        }
+    }
+
+    /// Runs all of the systems once
+    pub fn dispatch(&mut self, res: &Resources) {
+        use self::LevelDispatcher::*;
+        match self {
+            Parallel(dispatcher) => dispatcher.dispatch(res),
+            Watchdog(dispatcher) => {
+                let times = dispatcher.dispatch(res);
+                if let Some(report) = over_budget_report(times) {
+                    warn!("{}", report);
+                }
+            },
+        }
+    }
+}
+
+/// Returns a description of the slowest systems if the total time exceeds the frame budget
+fn over_budget_report(mut times: Vec<SystemTime>) -> Option<String> {
+    let total: Duration = times.iter().map(|time| time.duration).sum();
+    if total <= FRAME_BUDGET {
+        return None;
+    }
+
+    times.sort_by_key(|time| Reverse(time.duration));
+    let slowest: Vec<_> = times.iter().take(REPORTED_SYSTEMS)
+        .map(|SystemTime {name, duration, entities}| {
+            format!("{} ({:.2}ms, {} entities)", name, duration.as_secs_f64() * 1000.0, entities)
+        })
+        .collect();
+
+    Some(format!("Frame took {:.2}ms (budget: {}ms). Slowest systems: {}",
+        total.as_secs_f64() * 1000.0, FRAME_BUDGET.as_millis(), slowest.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(name: &'static str, millis: u64, entities: usize) -> SystemTime {
+        SystemTime {name, duration: Duration::from_millis(millis), entities}
+    }
+
+    #[test]
+    fn reports_slowest_systems_with_their_entities() {
+        assert_eq!(over_budget_report(vec![time("AI", 10, 5), time("Physics", 20, 40)]), None);
+
+        let times = vec![time("AI", 10, 5), time("Physics", 20, 40), time("Animator", 1, 80), time("Interactions", 15, 60)];
+        assert_eq!(over_budget_report(times).unwrap(), "Frame took 46.00ms (budget: 33ms). Slowest \
+            systems: Physics (20.00ms, 40 entities), Interactions (15.00ms, 60 entities), AI (10.00ms, 5 entities)");
+    }
+}
@@ -0,0 +1,286 @@
+//! Helpers for testing systems on small levels without opening a window
+
+use std::sync::{Mutex, Once};
+
+use sdl2::rect::Point;
+use specs::{World, Builder, Entity};
+use component_group::ComponentGroup;
+
+use crate::assets::{TextureId, SpriteManager, scale_speed_to_tile_size};
+use crate::components::{
+    PlayerComponents,
+    AnimationManager,
+    Attack,
+    BoundingBox,
+    CameraFocus,
+    Enemy,
+    Equipment,
+    HealthPoints,
+    MaxHealthPoints,
+    HitInvulnerability,
+    HitWait,
+    Inventory,
+    KeyboardControlled,
+    Movement,
+    Player,
+    Position,
+    Sprite,
+};
+use crate::generator::EnemyValues;
+use crate::resources::{
+    FramesElapsed,
+    GameStateMachine,
+    GameState,
+    ActionQueue,
+    EventQueue,
+    Event,
+    SoundQueue,
+    NotificationQueue,
+    GameEvents,
+    ExploredTiles,
+    RoomTracker,
+    RunStats,
+    reset_run_resources,
+};
+use crate::map::{FloorMap, GridSize, TileRect, TilePos, Tile, RoomId};
+use crate::map_sprites::{FloorSprite, WallSprite};
+use crate::systems::{SequentialDispatcher, Keyboard, build_dispatcher};
+use crate::ui;
+
+/// The frames per second used for animations in tests
+pub const TEST_FPS: usize = 30;
+
+lazy_static! {
+    /// Every message logged by any test since the capturing logger was installed
+    static ref CAPTURED_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Keeps every log message so that tests can check what was logged
+struct CapturingLogger;
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::Level::Debug
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            CAPTURED_LOGS.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the capturing logger (if it is not installed already) and returns a copy of every
+/// message logged so far that contains the given text. Tests run in parallel and share the same
+/// logger, so the text should be unique to the calling test (e.g. a randomly generated map key).
+pub fn captured_logs(containing: &str) -> Vec<String> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CapturingLogger).expect("bug: no other logger should be installed in tests");
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+
+    CAPTURED_LOGS.lock().unwrap().iter().filter(|message| message.contains(containing)).cloned().collect()
+}
+
+/// Returns a map with a single room that covers the entire grid. Every tile is part of the
+/// floor of the room, so the room has no walls.
+pub fn open_room(rows: usize, cols: usize, tile_size: u32) -> FloorMap {
+    let size = GridSize {rows, cols};
+    let mut map = FloorMap::new(size, tile_size);
+    let boundary = TileRect::new(TilePos {row: 0, col: 0}, size);
+    let room_id = map.add_room(boundary);
+    for pos in boundary.tile_positions() {
+        map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+    }
+    map
+}
+
+/// Returns a map with a single room that covers the entire grid. The edges of the grid are the
+/// walls of the room.
+pub fn walled_room(rows: usize, cols: usize, tile_size: u32) -> FloorMap {
+    let mut map = FloorMap::new(GridSize {rows, cols}, tile_size);
+    add_walled_room(&mut map, TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows, cols}));
+    map
+}
+
+/// Returns a map with a row of rooms that each span every row of the grid and have the given
+/// number of columns. When `share_walls` is true, each room starts at the east wall of the room
+/// before it. Otherwise, every room has walls of its own. The rooms are not connected.
+///
+/// The IDs of the rooms are returned from west to east.
+pub fn rooms_side_by_side(
+    rows: usize,
+    room_cols: &[usize],
+    share_walls: bool,
+    tile_size: u32,
+) -> (FloorMap, Vec<RoomId>) {
+    let shared = if share_walls { room_cols.len().saturating_sub(1) } else { 0 };
+    let cols = room_cols.iter().sum::<usize>() - shared;
+    let mut map = FloorMap::new(GridSize {rows, cols}, tile_size);
+
+    let mut col = 0;
+    let rooms = room_cols.iter().map(|&cols| {
+        let room_id = add_walled_room(&mut map, TileRect::new(TilePos {row: 0, col}, GridSize {rows, cols}));
+        col += if share_walls { cols - 1 } else { cols };
+        room_id
+    }).collect();
+
+    (map, rooms)
+}
+
+/// Adds a room with the given boundary to the map. The edges of the boundary become the walls of
+/// the room and every other tile within it becomes part of its floor.
+pub fn add_walled_room(map: &mut FloorMap, boundary: TileRect) -> RoomId {
+    let room_id = map.add_room(boundary);
+    for pos in boundary.tile_positions() {
+        map.grid_mut().place_tile(pos, Tile::new_floor(room_id, FloorSprite::default()));
+    }
+    for pos in boundary.edge_positions() {
+        map.grid_mut().place_tile(pos, Tile::new_wall(WallSprite::default()));
+    }
+    room_id
+}
+
+/// Returns a world with every resource that the systems of a level expect to already be added.
+/// Component storages are registered when a dispatcher is set up on the world.
+pub fn level_world(map: FloorMap) -> World {
+    let mut world = World::new();
+    reset_run_resources(&mut world);
+    world.add_resource(ExploredTiles::new(map.grid().dimensions()));
+    world.add_resource(RoomTracker::default());
+    world.add_resource(map);
+    world
+}
+
+/// Returns the animations of a character that never need to be rendered
+pub fn test_animations() -> AnimationManager {
+    AnimationManager::standard_character_animations(TEST_FPS,
+        TextureId::placeholder(0), &mut SpriteManager::default())
+}
+
+/// Returns the components of a player at the given position with the same stats as the player
+/// in the game
+pub fn player_components(pos: Point) -> PlayerComponents {
+    let animations = test_animations();
+    PlayerComponents {
+        keyboard_controlled: KeyboardControlled::default(),
+        camera_focus: CameraFocus,
+        player: Player,
+        health_points: HealthPoints(20),
+        max_health_points: MaxHealthPoints(20),
+        attack: Attack(10),
+        hit_invulnerability: HitInvulnerability(30),
+        position: Position(pos),
+        bounding_box: BoundingBox::BottomHalf {width: 16, height: 8},
+        movement: Movement::default(),
+        sprite: Sprite(animations.default_sprite()),
+        animation: animations.default_animation(),
+        animation_manager: animations,
+        inventory: Inventory::default(),
+        equipment: Equipment::default(),
+    }
+}
+
+/// A level that runs every system of the game, one frame at a time
+pub struct TestWorld<'a> {
+    pub world: World,
+    dispatcher: SequentialDispatcher<'a>,
+}
+
+impl<'a> TestWorld<'a> {
+    /// Creates a level with a single walled room that covers the entire grid
+    pub fn new(rows: usize, cols: usize, tile_size: u32) -> Self {
+        Self::with_map(walled_room(rows, cols, tile_size))
+    }
+
+    /// Creates a level with the given map
+    pub fn with_map(map: FloorMap) -> Self {
+        let mut world = level_world(map);
+        let mut dispatcher = build_dispatcher(SequentialDispatcher::default(), Keyboard::default());
+        dispatcher.setup(&mut world.res);
+        // Renderer storages are not set up by any system
+        ui::setup(&mut world.res);
+
+        Self {world, dispatcher}
+    }
+
+    /// Returns the position (in world coordinates) of the center of the given tile
+    pub fn tile_center(&self, pos: TilePos) -> Point {
+        pos.center(self.world.read_resource::<FloorMap>().tile_size() as i32)
+    }
+
+    /// Adds a player to the center of the given tile
+    pub fn spawn_player_at(&mut self, pos: TilePos) -> Entity {
+        let pos = self.tile_center(pos);
+        player_components(pos).create(&mut self.world)
+    }
+
+    /// Adds an enemy with the given values to the center of the given tile, just like the enemies
+    /// spawned when the player enters a level.
+    ///
+    /// Enemies with a speed of zero never move. Otherwise their movement is random, within the
+    /// room that contains the given tile.
+    pub fn spawn_enemy_at(&mut self, pos: TilePos, enemy: EnemyValues) -> Entity {
+        let (home_room, tile_size) = {
+            let map = self.world.read_resource::<FloorMap>();
+            (map.grid().get(pos).floor_room_id(), map.tile_size())
+        };
+        let pos = self.tile_center(pos);
+        let EnemyValues {behaviour, animations, attack, speed, health_points, hit_wait, bounding_box} = enemy;
+        self.world.create_entity()
+            .with(Enemy {behaviour, speed: scale_speed_to_tile_size(speed, tile_size), home_room})
+            .with(HealthPoints(health_points))
+            .with(Attack(attack))
+            .with(HitWait(hit_wait))
+            .with(Position(pos))
+            .with(bounding_box.scale_to_tile_size(tile_size))
+            .with(Movement::default())
+            .with(Sprite(animations.default_sprite()))
+            .with(animations.default_animation())
+            .with(animations)
+            .build()
+    }
+
+    /// Returns the current position of the given entity
+    pub fn position(&self, entity: Entity) -> Point {
+        self.world.read_storage::<Position>().get(entity)
+            .expect("bug: entity has no position").0
+    }
+
+    /// Runs a single frame with the given input events. Returns the change of game state
+    /// requested during the frame, if any.
+    pub fn step_with_events(&mut self, events: Vec<Event>) -> Option<GameState> {
+        // Same as a level screen: every queue only holds what happened in the current frame
+        *self.world.write_resource() = FramesElapsed(1);
+        *self.world.write_resource() = GameStateMachine::default();
+        *self.world.write_resource() = ActionQueue::default();
+        *self.world.write_resource() = SoundQueue::default();
+        *self.world.write_resource() = NotificationQueue::default();
+        *self.world.write_resource() = GameEvents::default();
+        *self.world.write_resource() = EventQueue(events);
+        self.world.write_resource::<RunStats>().floor.frames += 1;
+
+        self.dispatcher.dispatch(&self.world.res);
+        self.world.maintain();
+
+        // The game only dispatches a level while it is being played
+        match self.world.read_resource::<GameStateMachine>().current() {
+            GameState::Playing => None,
+            state => Some(state),
+        }
+    }
+
+    /// Runs up to the given number of frames without any input. Stops early and returns the
+    /// change of game state as soon as one is requested, just like the game does.
+    pub fn step(&mut self, frames: usize) -> Option<GameState> {
+        for _ in 0..frames {
+            if let Some(state) = self.step_with_events(Vec::new()) {
+                return Some(state);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,101 @@
+//! Runs the game at a fixed number of frames per second, no matter how often the screen is drawn
+
+/// The most frames that are run at once to catch up after the game falls behind (e.g. while the
+/// window is being dragged). Any time past this is dropped so that the game does not freeze while
+/// it runs hundreds of frames in a row.
+pub const MAX_CATCH_UP_FRAMES: usize = 8;
+
+/// Turns the real time that has passed into a whole number of fixed-length frames. Time that does
+/// not add up to a whole frame is carried over to the next update, so no time is lost to rounding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedTimestep {
+    fps: u64,
+    /// The time (in ms) of the last update
+    last_ticks: u32,
+    /// The time that has passed but has not been run as a frame yet, in ms multiplied by the fps.
+    /// Each frame is exactly 1000 of these units, which keeps everything in whole numbers.
+    accumulator: u64,
+}
+
+impl FixedTimestep {
+    /// Starts counting time from the given time (in ms)
+    pub fn new(fps: usize, ticks: u32) -> Self {
+        Self {fps: fps as u64, last_ticks: ticks, accumulator: 0}
+    }
+
+    /// Returns the number of frames that should be run now that it is the given time (in ms)
+    pub fn update(&mut self, ticks: u32) -> usize {
+        // The timer wraps around after ~49 days
+        let elapsed = ticks.wrapping_sub(self.last_ticks) as u64;
+        self.last_ticks = ticks;
+
+        self.accumulator += elapsed * self.fps;
+        let frames = (self.accumulator / 1000) as usize;
+        self.accumulator %= 1000;
+        if frames > MAX_CATCH_UP_FRAMES {
+            // Too far behind to catch up, so the rest of the time is skipped
+            self.accumulator = 0;
+            return MAX_CATCH_UP_FRAMES;
+        }
+        frames
+    }
+
+    /// Returns the time (in ms) left until the next frame should be run
+    pub fn ms_until_next_frame(&self) -> u32 {
+        let remaining = 1000 - self.accumulator;
+        // Rounds up so that waiting this long always reaches the next frame
+        remaining.div_ceil(self.fps) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn erratic_pacing_runs_every_frame() {
+        let fps = 30;
+        let mut rng = StdRng::seed_from_u64(1798);
+        let mut ticks = 5_000;
+        let mut timestep = FixedTimestep::new(fps, ticks);
+        let end = ticks + 10_000;
+
+        let mut frames = 0;
+        while ticks < end {
+            // Anywhere from a very fast render to one that is several frames late
+            ticks = (ticks + rng.gen_range(0, 150)).min(end);
+            let ran = timestep.update(ticks);
+            assert!(ran <= MAX_CATCH_UP_FRAMES);
+            frames += ran;
+        }
+        assert_eq!(frames, 10 * fps);
+    }
+
+    #[test]
+    fn remainder_carried_to_next_update() {
+        let mut timestep = FixedTimestep::new(30, 0);
+        // Each frame is 33.3ms, so 33ms is never quite enough on its own
+        assert_eq!(timestep.update(33), 0);
+        assert_eq!(timestep.ms_until_next_frame(), 1);
+        assert_eq!(timestep.update(66), 1);
+        assert_eq!(timestep.update(99), 1);
+        // Exactly three frames have passed after 100ms
+        assert_eq!(timestep.update(100), 1);
+        assert_eq!(timestep.ms_until_next_frame(), 34);
+
+        // The timer wrapping around does not count as a huge amount of time
+        let mut timestep = FixedTimestep::new(30, u32::MAX - 10);
+        assert_eq!(timestep.update(23), 1);
+    }
+
+    #[test]
+    fn long_pause_skipped() {
+        let mut timestep = FixedTimestep::new(30, 0);
+        assert_eq!(timestep.update(60_000), MAX_CATCH_UP_FRAMES);
+        // Nothing left over from the pause
+        assert_eq!(timestep.update(60_033), 0);
+        assert_eq!(timestep.update(60_034), 1);
+    }
+}
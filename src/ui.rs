@@ -3,6 +3,21 @@ mod renderer;
 mod game_screen;
 mod level_screen;
 mod text;
+mod notifications;
+mod transition;
+mod game_over;
+mod interruption;
+mod ghost_run;
+mod describe;
+mod pointer;
+mod zoom;
+mod map_key_footer;
+mod tutorial;
+mod boss_health;
+mod camera;
+mod shop;
+mod upgrade_shop;
+mod screenshot;
 
 pub mod debug;
 
@@ -11,6 +26,114 @@ pub use self::renderer::*;
 pub use self::game_screen::*;
 pub use self::level_screen::*;
 pub use self::text::*;
+pub use self::notifications::*;
+pub use self::transition::*;
+pub use self::game_over::*;
+pub use self::interruption::*;
+pub use self::ghost_run::*;
+pub use self::pointer::*;
+pub use self::zoom::*;
+pub use self::map_key_footer::*;
+pub use self::tutorial::*;
+pub use self::boss_health::*;
+pub use self::camera::*;
+pub use self::shop::*;
+pub use self::upgrade_shop::*;
+pub use self::screenshot::*;
 
-#[derive(Debug, Clone)]
-pub struct SDLError(pub String);
+use std::io;
+use std::fmt;
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::generator::InvalidMapKey;
+
+/// An error that stops the game from running
+#[derive(Debug)]
+pub enum SDLError {
+    /// An error reported by SDL
+    Sdl(String),
+    /// The image at the given path could not be loaded as a texture
+    TextureLoad {path: PathBuf, source: String},
+    /// The asset at the given path (relative to an assets directory) was not in any of the
+    /// directories that were searched
+    MissingAsset {path: PathBuf, searched: Vec<PathBuf>, working_dir: Option<PathBuf>},
+    /// The font used to draw text could not be loaded
+    FontLoad(String),
+    InvalidMapKey(InvalidMapKey),
+    Io(io::Error),
+}
+
+impl fmt::Display for SDLError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::SDLError::*;
+        match self {
+            Sdl(err) => write!(f, "SDL error: {}", err),
+            TextureLoad {path, source} => write!(f, "unable to load texture from `{}`: {}", path.display(), source),
+            MissingAsset {path, searched, working_dir} => {
+                let searched: Vec<_> = searched.iter().map(|dir| format!("`{}`", dir.display())).collect();
+                write!(f, "unable to find `{}` in any of the asset directories: {}", path.display(), searched.join(", "))?;
+                match working_dir {
+                    Some(dir) => write!(f, " (relative to `{}`)", dir.display()),
+                    None => Ok(()),
+                }
+            },
+            FontLoad(err) => write!(f, "unable to load font: {}", err),
+            InvalidMapKey(_) => write!(f, "invalid map key"),
+            Io(_) => write!(f, "I/O error"),
+        }
+    }
+}
+
+impl Error for SDLError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SDLError::*;
+        match self {
+            InvalidMapKey(err) => Some(err),
+            Io(err) => Some(err),
+            Sdl(_) | TextureLoad {..} | MissingAsset {..} | FontLoad(_) => None,
+        }
+    }
+}
+
+impl From<InvalidMapKey> for SDLError {
+    fn from(err: InvalidMapKey) -> Self {
+        SDLError::InvalidMapKey(err)
+    }
+}
+
+impl From<io::Error> for SDLError {
+    fn from(err: io::Error) -> Self {
+        SDLError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_messages() {
+        let err = SDLError::TextureLoad {
+            path: PathBuf::from("assets/missing.png"),
+            source: "Couldn't open assets/missing.png".to_string(),
+        };
+        assert_eq!(err.to_string(), "unable to load texture from `assets/missing.png`: Couldn't open assets/missing.png");
+        assert!(err.source().is_none());
+
+        let err = SDLError::MissingAsset {
+            path: PathBuf::from("hero.png"),
+            searched: vec![PathBuf::from("assets"), PathBuf::from("/opt/caves")],
+            working_dir: Some(PathBuf::from("/home/player")),
+        };
+        assert_eq!(err.to_string(), "unable to find `hero.png` in any of the asset directories: `assets`, `/opt/caves` (relative to `/home/player`)");
+
+        let err = SDLError::from("abcd".parse::<crate::generator::MapKey>().unwrap_err());
+        assert_eq!(err.to_string(), "invalid map key");
+        assert_eq!(err.source().unwrap().to_string(), "map key has the wrong length");
+
+        let err = SDLError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(err.to_string(), "I/O error");
+        assert_eq!(err.source().unwrap().to_string(), "no such file");
+    }
+}
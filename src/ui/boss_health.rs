@@ -0,0 +1,104 @@
+use sdl2::{
+    rect::Rect,
+    pixels::Color,
+    render::RenderTarget,
+};
+use specs::{World, Join, ReadStorage};
+
+use crate::components::{Enemy, Boss, HealthPoints};
+use crate::resources::RoomTracker;
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// The height of the label above the bar
+const LABEL_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of the screen and the top of the label
+const TOP_MARGIN: u32 = 4;
+/// The distance (in px) between the top of the label and the top of the bar
+const LABEL_SPACING: u32 = 10;
+/// The size (in px) of the bar
+const BAR_WIDTH: u32 = 120;
+const BAR_HEIGHT: u32 = 4;
+
+/// Returns the current and maximum health of the boss if the player is in the boss's room
+pub fn boss_in_current_room(world: &World) -> Option<(usize, usize)> {
+    let current = world.read_resource::<RoomTracker>().current()?;
+    let (enemies, bosses, healths) = world.system_data::<(ReadStorage<'_, Enemy>, ReadStorage<'_, Boss>, ReadStorage<'_, HealthPoints>)>();
+    (&enemies, &bosses, &healths).join()
+        .find(|(enemy, _, _)| enemy.home_room == Some(current))
+        .map(|(_, boss, &HealthPoints(health))| (health, boss.max_health))
+}
+
+/// Draws the health of the boss across the top of the screen
+pub fn render_boss_health_bar<T: RenderTarget>(ctx: &mut RenderContext<T>, health: usize, max_health: usize) -> Result<(), SDLError> {
+    Text::new(&ctx.font, "Boss", LABEL_HEIGHT)
+        .render(ctx.canvas, (255, 255, 255, 255), TextLayout::CenteredAtTop(TOP_MARGIN))?;
+
+    let (screen_width, _) = ctx.canvas.logical_size();
+    let background = Rect::new(
+        (screen_width as i32 - BAR_WIDTH as i32) / 2,
+        (TOP_MARGIN + LABEL_SPACING) as i32,
+        BAR_WIDTH,
+        BAR_HEIGHT,
+    );
+    ctx.canvas.set_draw_color(Color::RGB(40, 40, 40));
+    ctx.canvas.fill_rect(background).map_err(SDLError::Sdl)?;
+
+    let filled = bar_fill(health, max_health, BAR_WIDTH);
+    if filled > 0 {
+        let mut foreground = background;
+        foreground.set_width(filled);
+        ctx.canvas.set_draw_color(Color::RGB(200, 40, 40));
+        ctx.canvas.fill_rect(foreground).map_err(SDLError::Sdl)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the width (in px) of the part of a bar with the given width that is filled for the
+/// given health
+fn bar_fill(health: usize, max_health: usize, width: u32) -> u32 {
+    if max_health == 0 {
+        return 0;
+    }
+    (health.min(max_health) as u64 * width as u64 / max_health as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generator::EnemyValues;
+    use crate::components::{BoundingBox, EnemyBehaviour};
+    use crate::map::TilePos;
+    use crate::test_helpers::{TestWorld, test_animations};
+
+    #[test]
+    fn bar_shown_while_player_in_boss_room() {
+        let tile_size = 16;
+        let mut test = TestWorld::new(7, 7, tile_size);
+        let boss = test.spawn_enemy_at(TilePos {row: 3, col: 5}, EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 0.0,
+            health_points: 150,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: tile_size, height: tile_size},
+        });
+        test.world.write_storage().insert(boss, Boss {max_health: 150}).unwrap();
+
+        // The player has not been placed in any room yet
+        assert_eq!(boss_in_current_room(&test.world), None);
+
+        test.spawn_player_at(TilePos {row: 3, col: 1});
+        test.step(1);
+        assert_eq!(boss_in_current_room(&test.world), Some((150, 150)));
+
+        test.world.write_storage().insert(boss, HealthPoints(30)).unwrap();
+        assert_eq!(boss_in_current_room(&test.world), Some((30, 150)));
+        assert_eq!(bar_fill(30, 150, BAR_WIDTH), BAR_WIDTH / 5);
+        assert_eq!(bar_fill(0, 150, BAR_WIDTH), 0);
+    }
+}
@@ -0,0 +1,195 @@
+use std::cmp;
+
+use sdl2::rect::{Point, Rect};
+
+/// The part of a level that is shown on the screen. Converts between positions on the map (world
+/// coordinates) and positions on the screen (screen coordinates, in logical px).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Camera {
+    /// The position on the map that the camera is trying to center on the screen
+    focus: Point,
+    /// How far the screen is moved away from being centered on the focus (e.g. while shaking)
+    shift: Point,
+    /// The logical size of the screen
+    screen_size: (u32, u32),
+    /// The area of the map covered by the level. The screen never goes past its edges unless the
+    /// level is smaller than the screen.
+    level_boundary: Rect,
+    /// The position on the map of the top left corner of the screen
+    top_left: Point,
+}
+
+impl Camera {
+    /// Creates a camera that is as close to centered on the given focus as the edges of the level
+    /// allow
+    pub fn new(focus: Point, screen_size: (u32, u32), level_boundary: Rect) -> Self {
+        Self::with_shift(focus, Point::new(0, 0), screen_size, level_boundary)
+    }
+
+    fn with_shift(focus: Point, shift: Point, screen_size: (u32, u32), level_boundary: Rect) -> Self {
+        let (screen_width, screen_height) = screen_size;
+        let screen_center = Point::new(screen_width as i32 / 2, screen_height as i32 / 2);
+        // Clamped after shifting so that a screen that is already against an edge of the level
+        // stays there
+        let top_left = clamp_to_level(focus - screen_center + shift, level_boundary, screen_width, screen_height);
+        Self {focus, shift, screen_size, level_boundary, top_left}
+    }
+
+    /// Creates a camera that shows the entire level, with the level centered on the screen if it
+    /// is smaller than the screen
+    pub fn whole_level(level_boundary: Rect, screen_size: (u32, u32)) -> Self {
+        Self::new(level_boundary.center(), screen_size, level_boundary)
+    }
+
+    /// Returns the camera with the screen moved by the given offset (e.g. while the screen is
+    /// shaking). The screen still cannot go past the edges of the level.
+    pub fn shifted(self, offset: Point) -> Self {
+        Self::with_shift(self.focus, self.shift + offset, self.screen_size, self.level_boundary)
+    }
+
+    /// Returns the position on the map that the camera is trying to center on the screen
+    pub fn focus(&self) -> Point {
+        self.focus
+    }
+
+    /// Returns the position on the map of the top left corner of the screen
+    pub fn top_left(&self) -> Point {
+        self.top_left
+    }
+
+    /// Returns the area of the map that is shown on the screen
+    pub fn visible_world_rect(&self) -> Rect {
+        let (screen_width, screen_height) = self.screen_size;
+        Rect::new(self.top_left.x(), self.top_left.y(), screen_width, screen_height)
+    }
+
+    /// Converts a position on the map to the position where it is drawn on the screen
+    pub fn world_to_screen(&self, pos: Point) -> Point {
+        pos - self.top_left
+    }
+
+    /// Converts a position on the screen to the position on the map that is drawn there
+    pub fn screen_to_world(&self, pos: Point) -> Point {
+        pos + self.top_left
+    }
+
+    /// Converts an area of the map to the area of the screen where it is drawn
+    pub fn world_rect_to_screen(&self, rect: Rect) -> Rect {
+        let top_left = self.world_to_screen(rect.top_left());
+        Rect::new(top_left.x(), top_left.y(), rect.width(), rect.height())
+    }
+}
+
+/// Moves the top-left corner of a screen with the given size so that the entire screen stays within
+/// the given level boundary. If the screen is bigger than the level (e.g. when zoomed out), the
+/// level is centered on the screen instead.
+pub fn clamp_to_level(top_left: Point, level_boundary: Rect, screen_width: u32, screen_height: u32) -> Point {
+    Point::new(
+        clamp_to_level_axis(top_left.x(), level_boundary.x(), level_boundary.width(), screen_width),
+        clamp_to_level_axis(top_left.y(), level_boundary.y(), level_boundary.height(), screen_height),
+    )
+}
+
+/// Same as `clamp_to_level` but only along a single axis
+fn clamp_to_level_axis(start: i32, level_start: i32, level_size: u32, screen_size: u32) -> i32 {
+    // The valid range for the start of the screen
+    let (min, max) = (level_start, level_start + level_size as i32 - screen_size as i32);
+    if min > max {
+        level_start - (screen_size as i32 - level_size as i32) / 2
+    } else {
+        cmp::min(cmp::max(min, start), max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN_SIZE: (u32, u32) = (320, 240);
+
+    #[test]
+    fn clamped_at_every_edge() {
+        let level_boundary = Rect::new(0, 0, 50 * 16, 40 * 16);
+        let camera = |x, y| Camera::new(Point::new(x, y), SCREEN_SIZE, level_boundary);
+
+        // Centered when far enough from every edge
+        assert_eq!(camera(400, 300).top_left(), Point::new(240, 180));
+        // Left, right, top and bottom edges
+        assert_eq!(camera(10, 300).top_left(), Point::new(0, 180));
+        assert_eq!(camera(790, 300).top_left(), Point::new(800 - 320, 180));
+        assert_eq!(camera(400, 10).top_left(), Point::new(240, 0));
+        assert_eq!(camera(400, 630).top_left(), Point::new(240, 640 - 240));
+        // Way past a corner
+        assert_eq!(camera(-500, 5000).top_left(), Point::new(0, 640 - 240));
+
+        for &(x, y) in &[(10, 300), (790, 300), (400, 10), (400, 630), (-500, 5000)] {
+            let screen = camera(x, y).visible_world_rect();
+            assert_eq!(screen.union(level_boundary), level_boundary, "screen left the level at {:?}", (x, y));
+        }
+    }
+
+    #[test]
+    fn shifting_stays_within_level() {
+        let level_boundary = Rect::new(0, 0, 50 * 16, 40 * 16);
+        let camera = Camera::new(Point::new(400, 300), SCREEN_SIZE, level_boundary);
+        assert_eq!(camera.shifted(Point::new(3, -2)).top_left(), Point::new(243, 178));
+        // Shifting does not move the focus
+        assert_eq!(camera.shifted(Point::new(3, -2)).focus(), camera.focus());
+
+        // The screen is so far past each corner that shifting a little cannot move it at all
+        let corners = [
+            Point::new(0, 0),
+            Point::new(level_boundary.right(), 0),
+            Point::new(0, level_boundary.bottom()),
+            Point::new(level_boundary.right(), level_boundary.bottom()),
+        ];
+        for &corner in &corners {
+            let camera = Camera::new(corner, SCREEN_SIZE, level_boundary);
+            for &(x, y) in &[(6, 6), (-6, 6), (6, -6), (-6, -6)] {
+                assert_eq!(camera.shifted(Point::new(x, y)).top_left(), camera.top_left());
+            }
+        }
+    }
+
+    #[test]
+    fn small_levels_centered() {
+        // Too narrow for the screen, but tall enough to scroll vertically
+        let level_boundary = Rect::new(0, 0, 10 * 16, 20 * 16);
+        for &focus in &[Point::new(0, 0), Point::new(80, 160), Point::new(160, 320)] {
+            let top_left = Camera::new(focus, SCREEN_SIZE, level_boundary).top_left();
+            assert_eq!(top_left.x(), -80);
+            assert!(top_left.y() >= 0 && top_left.y() <= 320 - 240);
+        }
+
+        // Smaller than the screen in both directions
+        let level_boundary = Rect::new(0, 0, 100, 50);
+        let camera = Camera::new(Point::new(500, -500), SCREEN_SIZE, level_boundary);
+        assert_eq!(camera.top_left(), Point::new(-110, -95));
+        assert_eq!(camera.top_left(), Camera::whole_level(level_boundary, SCREEN_SIZE).top_left());
+    }
+
+    #[test]
+    fn world_and_screen_round_trip() {
+        let big_level = Rect::new(0, 0, 50 * 16, 40 * 16);
+        let small_level = Rect::new(0, 0, 100, 50);
+        let cameras = [
+            Camera::new(Point::new(400, 300), SCREEN_SIZE, big_level),
+            Camera::new(Point::new(790, 630), SCREEN_SIZE, big_level),
+            Camera::new(Point::new(50, 25), SCREEN_SIZE, small_level),
+        ];
+        for camera in &cameras {
+            for &pos in &[Point::new(0, 0), Point::new(17, 93), Point::new(-40, 700), camera.focus()] {
+                assert_eq!(camera.screen_to_world(camera.world_to_screen(pos)), pos);
+                assert_eq!(camera.world_to_screen(camera.screen_to_world(pos)), pos);
+            }
+            // The top left corner of the screen
+            assert_eq!(camera.world_to_screen(camera.visible_world_rect().top_left()), Point::new(0, 0));
+        }
+
+        // The small level is drawn in the middle of the screen
+        let camera = &cameras[2];
+        assert_eq!(camera.world_to_screen(Point::new(0, 0)), Point::new(110, 95));
+        assert_eq!(camera.world_rect_to_screen(small_level), Rect::new(110, 95, 100, 50));
+        assert_eq!(camera.screen_to_world(Point::new(0, 0)), Point::new(-110, -95));
+    }
+}
@@ -3,11 +3,12 @@ use std::path::Path;
 use sdl2::{image::SaveSurface, pixels::PixelFormatEnum, surface::Surface};
 use specs::World;
 
-use crate::assets::AssetManager;
+use crate::assets::{AssetManager, HeroPalette};
 use crate::map::FloorMap;
 use super::SDLError;
 
-use super::renderer::{RenderData, RenderContext, render_area};
+use super::renderer::{RenderData, RenderContext, TileVisibility, render_area};
+use super::camera::Camera;
 
 /// Render the entire state of the level (the entire map) to the given filename.
 ///
@@ -20,22 +21,22 @@ pub fn render_to_file<P: AsRef<Path>>(map: &FloorMap, world: &World, path: P) ->
 
     let level_boundary = map.level_boundary();
     let mut canvas = Surface::new(level_boundary.width(), level_boundary.height(),
-        PixelFormatEnum::RGBA8888).and_then(|c| c.into_canvas()).map_err(SDLError)?;
+        PixelFormatEnum::RGBA8888).and_then(|c| c.into_canvas()).map_err(SDLError::Sdl)?;
     let texture_creator = canvas.texture_creator();
 
-    let tile_size = 16;
     let AssetManager {
-        textures,
+        mut textures,
         map_sprites,
         sprites,
         ..
-    } = AssetManager::load(&texture_creator, 30, tile_size)?;
+    } = AssetManager::load(&texture_creator, 30, HeroPalette::Default)?;
 
-    let mut ctx = RenderContext::new(&mut canvas, &textures, &sprites, &map_sprites);
+    let mut ctx = RenderContext::new(&mut canvas, &mut textures, &sprites, &map_sprites)?;
 
     let data: RenderData = world.system_data();
-    render_area(data, map, level_boundary, &mut ctx, |_, _| true)?;
+    let camera = Camera::whole_level(level_boundary, (level_boundary.width(), level_boundary.height()));
+    render_area(data, map, &camera, &mut ctx, |_, _| TileVisibility::Visible)?;
 
-    canvas.into_surface().save(path).map_err(SDLError)?;
+    canvas.into_surface().save(path).map_err(SDLError::Sdl)?;
     Ok(())
 }
@@ -0,0 +1,290 @@
+//! Plain-text descriptions of the game state. Useful for screen readers and for debugging.
+
+use std::fmt::Write;
+
+use specs::{World, Join, ReadExpect, ReadStorage};
+
+use crate::components::{
+    Position,
+    BoundingBox,
+    CameraFocus,
+    HealthPoints,
+    Door,
+    Stairs,
+    Enemy,
+    MapFragment,
+    Chest,
+    Cage,
+};
+use crate::systems::{find_visible_tiles, visibility_start};
+use crate::map::{FloorMap, RoomType, TilePos};
+
+#[derive(SystemData)]
+struct DescribeData<'a> {
+    map: ReadExpect<'a, FloorMap>,
+    positions: ReadStorage<'a, Position>,
+    bounding_boxes: ReadStorage<'a, BoundingBox>,
+    camera_focuses: ReadStorage<'a, CameraFocus>,
+    healths: ReadStorage<'a, HealthPoints>,
+    doors: ReadStorage<'a, Door>,
+    stairs: ReadStorage<'a, Stairs>,
+    enemies: ReadStorage<'a, Enemy>,
+    map_fragments: ReadStorage<'a, MapFragment>,
+    chests: ReadStorage<'a, Chest>,
+    cages: ReadStorage<'a, Cage>,
+}
+
+/// Something notable near the player
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Landmark {
+    /// The distance in tiles (diagonal steps count as a single tile)
+    distance: usize,
+    /// The position of the landmark, used to order landmarks at the same distance
+    pos: TilePos,
+    label: &'static str,
+    direction: &'static str,
+}
+
+impl Landmark {
+    fn new(label: &'static str, from: TilePos, to: TilePos) -> Self {
+        let (drow, dcol) = to.difference(from);
+        Self {
+            distance: drow.abs().max(dcol.abs()) as usize,
+            pos: to,
+            label,
+            direction: compass_direction(drow, dcol),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.distance {
+            0 => format!("{} here", self.label),
+            1 => format!("{} to the {}", self.label, self.direction),
+            distance => format!("{} {} tiles {}", self.label, distance, self.direction),
+        }
+    }
+}
+
+/// Returns the compass direction (e.g. "north" or "southeast") that most closely matches the
+/// given difference in rows and columns. Rows increase to the south and columns increase to the
+/// east.
+fn compass_direction(drow: isize, dcol: isize) -> &'static str {
+    // A direction only counts if it makes up a large enough part of the difference
+    let north_south = if drow != 0 && 2 * drow.abs() >= dcol.abs() {
+        if drow < 0 { "north" } else { "south" }
+    } else {
+        ""
+    };
+    let east_west = if dcol != 0 && 2 * dcol.abs() >= drow.abs() {
+        if dcol < 0 { "west" } else { "east" }
+    } else {
+        ""
+    };
+
+    match (north_south, east_west) {
+        ("", "") => "here",
+        ("north", "east") => "northeast",
+        ("north", "west") => "northwest",
+        ("south", "east") => "southeast",
+        ("south", "west") => "southwest",
+        (dir, "") | ("", dir) => dir,
+        _ => unreachable!("bug: every combination of directions should be covered"),
+    }
+}
+
+/// Writes a single line listing the given landmarks from nearest to farthest
+fn write_landmarks(out: &mut String, heading: &str, mut landmarks: Vec<Landmark>) {
+    landmarks.sort();
+    let landmarks: Vec<_> = landmarks.iter().map(Landmark::describe).collect();
+    let landmarks = if landmarks.is_empty() { "none".to_string() } else { landmarks.join(", ") };
+    writeln!(out, "{}: {}", heading, landmarks).expect("bug: unable to write to string");
+}
+
+/// Returns a plain-text description of everything the player can currently see: their health,
+/// the room they are in, and the exits, enemies, and items around them. The same state always
+/// produces the same description. The description is empty if there is no camera focus to describe
+/// the surroundings of.
+pub fn describe_surroundings(world: &World) -> String {
+    let DescribeData {
+        map,
+        positions,
+        bounding_boxes,
+        camera_focuses,
+        healths,
+        doors,
+        stairs,
+        enemies,
+        map_fragments,
+        chests,
+        cages,
+    } = world.system_data();
+    let grid = map.grid();
+
+    let (player, &Position(player_pos), _) = match (&*world.entities(), &positions, &camera_focuses).join().next() {
+        Some(focus) => focus,
+        None => return String::new(),
+    };
+    let player_tile = map.world_to_tile_pos(player_pos);
+    let tile_size = map.tile_size() as i32;
+    let start = visibility_start(grid, player_pos, bounding_boxes.get(player).cloned(), tile_size, &positions, &doors);
+    let visible = find_visible_tiles(grid, start, tile_size, &positions, &doors);
+
+    let mut out = String::new();
+    if let Some(HealthPoints(health)) = healths.get(player) {
+        writeln!(out, "HP: {}", health).expect("bug: unable to write to string");
+    }
+
+    let room_id = grid.get(player_tile).floor_room_id();
+    match room_id {
+        Some(room_id) => {
+            let room_type = match map.room(room_id).room_type() {
+                RoomType::Normal => "normal",
+                RoomType::Challenge => "challenge",
+                RoomType::PlayerStart => "start",
+                RoomType::TreasureChamber => "treasure chamber",
+                RoomType::Merchant => "merchant",
+            };
+            writeln!(out, "Room: {} ({})", room_id, room_type)
+        },
+        None => writeln!(out, "Room: none"),
+    }.expect("bug: unable to write to string");
+
+    let mut exits = Vec::new();
+    let mut enemies_seen = Vec::new();
+    let mut items = Vec::new();
+    for (entity, &Position(pos)) in (&*world.entities(), &positions).join() {
+        let tile = map.world_to_tile_pos(pos);
+        if entity == player || !visible.contains(&tile) {
+            continue;
+        }
+
+        let landmark = |label| Landmark::new(label, player_tile, tile);
+        if let Some(door) = doors.get(entity) {
+            exits.push(landmark(if door.is_open() { "open door" } else { "door" }));
+        } else if let Some(staircase) = stairs.get(entity) {
+            exits.push(landmark(match staircase {
+                Stairs::ToNextLevel {..} => "stairs down",
+                Stairs::ToPrevLevel {..} => "stairs up",
+            }));
+        } else if enemies.get(entity).is_some() {
+            enemies_seen.push(landmark("enemy"));
+        } else if map_fragments.get(entity).is_some() {
+            items.push(landmark("map fragment"));
+        } else if chests.get(entity).is_some() {
+            items.push(landmark("chest"));
+        } else if cages.get(entity).is_some() {
+            items.push(landmark("caged prisoner"));
+        }
+    }
+
+    // Entrances of the current room that do not have a door are exits too
+    if let Some(room_id) = room_id {
+        let door_tiles: Vec<_> = (&positions, &doors).join()
+            .map(|(&Position(pos), _)| map.world_to_tile_pos(pos))
+            .collect();
+        exits.extend(visible.iter()
+            .filter(|&&pos| grid.get(pos).is_room_floor(room_id) && grid.is_room_entrance(pos))
+            .filter(|pos| !door_tiles.contains(pos))
+            .map(|&pos| Landmark::new("doorway", player_tile, pos)));
+    }
+
+    write_landmarks(&mut out, "Exits", exits);
+    write_landmarks(&mut out, "Enemies", enemies_seen);
+    write_landmarks(&mut out, "Items", items);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::Builder;
+
+    use crate::components::EnemyBehaviour;
+    use crate::test_helpers::rooms_side_by_side;
+
+    #[test]
+    fn compass_directions() {
+        assert_eq!(compass_direction(0, 0), "here");
+        assert_eq!(compass_direction(-1, 0), "north");
+        assert_eq!(compass_direction(0, 3), "east");
+        assert_eq!(compass_direction(4, 1), "south");
+        assert_eq!(compass_direction(-1, -3), "west");
+        assert_eq!(compass_direction(-2, -3), "northwest");
+        assert_eq!(compass_direction(5, 5), "southeast");
+        assert_eq!(compass_direction(-3, 2), "northeast");
+        assert_eq!(compass_direction(2, -4), "southwest");
+    }
+
+    /// Creates a world with two rooms side by side (not connected) and a player in the first one
+    fn two_room_world(player_tile: TilePos) -> World {
+        let tile_size = 16;
+        let (map, _) = rooms_side_by_side(7, &[8, 8], false, tile_size);
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<BoundingBox>();
+        world.register::<CameraFocus>();
+        world.register::<HealthPoints>();
+        world.register::<Door>();
+        world.register::<Stairs>();
+        world.register::<Enemy>();
+        world.register::<MapFragment>();
+        world.register::<Chest>();
+        world.register::<Cage>();
+        world.add_resource(map);
+
+        world.create_entity()
+            .with(CameraFocus)
+            .with(HealthPoints(17))
+            .with(Position(player_tile.center(tile_size as i32)))
+            .build();
+        world
+    }
+
+    fn add_at<C: specs::Component + Send + Sync>(world: &mut World, tile: TilePos, component: C) {
+        world.create_entity()
+            .with(Position(tile.center(16)))
+            .with(component)
+            .build();
+    }
+
+    #[test]
+    fn describes_visible_room() {
+        let mut world = two_room_world(TilePos {row: 3, col: 3});
+        add_at(&mut world, TilePos {row: 2, col: 3}, Stairs::ToNextLevel {id: 0});
+        add_at(&mut world, TilePos {row: 3, col: 6}, Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None});
+        add_at(&mut world, TilePos {row: 5, col: 1}, Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None});
+        add_at(&mut world, TilePos {row: 0, col: 1}, MapFragment {rooms: 2});
+        // Not visible since it is in the other room
+        add_at(&mut world, TilePos {row: 3, col: 11}, Enemy {speed: 1.0, behaviour: EnemyBehaviour::Random, home_room: None});
+
+        assert_eq!(describe_surroundings(&world), "\
+HP: 17
+Room: 0 (normal)
+Exits: stairs down to the north
+Enemies: enemy 2 tiles southwest, enemy 3 tiles east
+Items: map fragment 3 tiles northwest
+");
+    }
+
+    #[test]
+    fn describes_empty_room() {
+        let world = two_room_world(TilePos {row: 3, col: 11});
+
+        assert_eq!(describe_surroundings(&world), "\
+HP: 17
+Room: 1 (normal)
+Exits: none
+Enemies: none
+Items: none
+");
+    }
+
+    #[test]
+    fn nothing_to_describe_without_camera_focus() {
+        let mut world = two_room_world(TilePos {row: 3, col: 3});
+        world.delete_all();
+        assert_eq!(describe_surroundings(&world), "");
+    }
+}
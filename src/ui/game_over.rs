@@ -0,0 +1,355 @@
+use sdl2::render::{RenderTarget, BlendMode};
+
+use crate::resources::{Event, Key, RunStats, FloorStats, format_play_time};
+use crate::run_history::RunRecord;
+use crate::upgrades::{RunOutcome, ESCAPE_BONUS};
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// How dark the level is behind the game over screen, from 0 (not at all) to 255 (black)
+const BACKGROUND_DARKNESS: u8 = 200;
+
+/// The distance (in px) between the top of the screen and the top of the title
+const TITLE_TOP: u32 = 36;
+/// The height of the title
+const TITLE_HEIGHT: f32 = 20.0;
+/// The height of each line of statistics and each choice below the title
+const LINE_HEIGHT: f32 = 10.0;
+/// The distance (in px) between the top of one line and the top of the next line
+const LINE_SPACING: u32 = 16;
+/// The height of each line in the list of previous runs, which is smaller to fit more runs
+const HISTORY_LINE_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of one run and the top of the next run in the list
+const HISTORY_LINE_SPACING: u32 = 12;
+
+/// What the player can do once the run is over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverChoice {
+    /// Start a new run on a freshly generated map
+    NewSeed,
+    /// Start a new run on the same map that was just played
+    Retry,
+}
+
+impl GameOverChoice {
+    /// All of the choices, in the order they are shown
+    pub const ALL: [GameOverChoice; 2] = [
+        GameOverChoice::NewSeed,
+        GameOverChoice::Retry,
+    ];
+
+    /// The text shown for this choice
+    pub fn title(self) -> &'static str {
+        use self::GameOverChoice::*;
+        match self {
+            NewSeed => "New seed",
+            Retry => "Retry this map",
+        }
+    }
+}
+
+/// The statistics of a run that just ended along with the choice of what to do next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameOver {
+    outcome: RunOutcome,
+    /// True if the player won by escaping back to the surface with the treasure
+    escaped: bool,
+    /// The number of the floor that the run ended on (starting at 1)
+    floor: usize,
+    stats: RunStats,
+    /// The time at which each floor was cleared, listed below the statistics
+    splits: Vec<String>,
+    /// The number of frames in each second, used to display the length of the run
+    fps: usize,
+    /// The choice that is currently highlighted
+    selected: GameOverChoice,
+    /// The choice that the player confirmed, if any
+    chosen: Option<GameOverChoice>,
+    /// The most recent runs of the game, most recent first
+    history: Vec<RunRecord>,
+    /// True if the list of recent runs is being shown instead of the statistics and choices
+    showing_history: bool,
+}
+
+impl GameOver {
+    pub fn new(outcome: RunOutcome, floor: usize, stats: RunStats, fps: usize) -> Self {
+        Self {
+            outcome,
+            escaped: false,
+            floor,
+            stats,
+            splits: Vec::new(),
+            fps,
+            selected: GameOverChoice::NewSeed,
+            chosen: None,
+            history: Vec::new(),
+            showing_history: false,
+        }
+    }
+
+    /// Returns how the run ended
+    pub fn outcome(&self) -> RunOutcome {
+        self.outcome
+    }
+
+    /// Returns the number of the floor that the run ended on (starting at 1)
+    pub fn floor(&self) -> usize {
+        self.floor
+    }
+
+    /// Returns the title shown at the top of the screen
+    pub fn title(&self) -> &'static str {
+        match (self.outcome, self.escaped) {
+            (RunOutcome::Death, _) => "You Died",
+            (RunOutcome::Victory, false) => "Victory!",
+            (RunOutcome::Victory, true) => "You Escaped!",
+        }
+    }
+
+    /// Marks the run as won by escaping back to the surface with the treasure
+    pub fn set_escaped(&mut self) {
+        self.escaped = true;
+    }
+
+    /// Returns the statistics of the run that ended
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    /// Sets the lines listing the time at which each floor was cleared
+    pub fn set_splits(&mut self, splits: Vec<String>) {
+        self.splits = splits;
+    }
+
+    /// Sets the runs listed when the player asks to see their recent runs, most recent first
+    pub fn set_history(&mut self, history: Vec<RunRecord>) {
+        self.history = history;
+    }
+
+    /// Returns true if the list of recent runs is currently shown
+    pub fn is_showing_history(&self) -> bool {
+        self.showing_history
+    }
+
+    /// Returns the choice that is currently highlighted
+    pub fn selected(&self) -> GameOverChoice {
+        self.selected
+    }
+
+    /// Returns the choice that the player confirmed, if any
+    pub fn chosen(&self) -> Option<GameOverChoice> {
+        self.chosen
+    }
+
+    /// Moves the highlight with the up and down arrows and confirms the highlighted choice with
+    /// the A key. The Select key shows the list of recent runs, which is closed again with any of
+    /// Select, A, B, or Start. Nothing changes once a choice has been confirmed.
+    pub fn dispatch(&mut self, events: &[Event]) {
+        for event in events {
+            if self.chosen.is_some() {
+                return;
+            }
+
+            if self.showing_history {
+                match event {
+                    Event::KeyDown(Key::Select) | Event::KeyDown(Key::A) |
+                    Event::KeyDown(Key::B) | Event::KeyDown(Key::Start) => self.showing_history = false,
+                    _ => {},
+                }
+                continue;
+            }
+
+            let index = GameOverChoice::ALL.iter().position(|&choice| choice == self.selected)
+                .expect("bug: selected choice should be one of the choices");
+            let count = GameOverChoice::ALL.len();
+            match event {
+                Event::KeyDown(Key::UpArrow) => self.selected = GameOverChoice::ALL[(index + count - 1) % count],
+                Event::KeyDown(Key::DownArrow) => self.selected = GameOverChoice::ALL[(index + 1) % count],
+                Event::KeyDown(Key::A) | Event::KeyDown(Key::Start) => self.chosen = Some(self.selected),
+                Event::KeyDown(Key::Select) => self.showing_history = true,
+                _ => {},
+            }
+        }
+    }
+
+    /// Returns the lines of text shown below the title
+    pub fn lines(&self) -> Vec<String> {
+        let mut totals = self.stats.totals;
+        totals.add(self.stats.floor);
+        let FloorStats {frames, enemies_killed, damage_taken, damage_dealt, ..} = totals;
+        let mut lines = vec![
+            format!("Floor reached: {}", self.floor),
+            format!("Time: {}", format_play_time(frames, self.fps)),
+            format!("Enemies defeated: {}", enemies_killed),
+            format!("Damage dealt: {}", damage_dealt),
+            format!("Damage taken: {}", damage_taken),
+        ];
+        if self.escaped {
+            lines.push(format!("Escape bonus: x{} coins", ESCAPE_BONUS));
+        }
+        lines
+    }
+
+    /// Returns a line of text for each of the recent runs, most recent first
+    pub fn history_lines(&self) -> Vec<String> {
+        self.history.iter().map(|run| {
+            format!("{}  {}  floor {}  {}  {} defeated", run.date(), run.outcome.id(), run.floor,
+                format_play_time(run.frames, self.fps), run.enemies_killed)
+        }).collect()
+    }
+
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        ctx.canvas.set_blend_mode(BlendMode::Blend);
+        ctx.canvas.set_draw_color((0, 0, 0, BACKGROUND_DARKNESS));
+        ctx.canvas.fill_rect(None).map_err(SDLError::Sdl)?;
+
+        let white = (255, 255, 255, 255);
+        let highlight = (255, 215, 0, 255);
+        let gray = (150, 150, 150, 255);
+
+        if self.showing_history {
+            return self.render_history(ctx, white, gray);
+        }
+
+        let title_color = match self.outcome {
+            RunOutcome::Death => (200, 40, 40, 255),
+            RunOutcome::Victory => highlight,
+        };
+        Text::new(&ctx.font, self.title(), TITLE_HEIGHT)
+            .render(ctx.canvas, title_color, TextLayout::CenteredAtTop(TITLE_TOP))?;
+
+        let mut top = TITLE_TOP + TITLE_HEIGHT as u32 + LINE_SPACING;
+        for line in self.lines() {
+            Text::new(&ctx.font, line, LINE_HEIGHT)
+                .render(ctx.canvas, white, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+        for line in &self.splits {
+            Text::new(&ctx.font, line, HISTORY_LINE_HEIGHT)
+                .render(ctx.canvas, gray, TextLayout::CenteredAtTop(top))?;
+            top += HISTORY_LINE_SPACING;
+        }
+
+        // Leave a gap between the statistics and the choices
+        top += LINE_SPACING / 2;
+        for &choice in &GameOverChoice::ALL {
+            let (text, color) = if choice == self.selected {
+                (format!("> {} <", choice.title()), highlight)
+            } else {
+                (choice.title().to_string(), white)
+            };
+            Text::new(&ctx.font, text, LINE_HEIGHT)
+                .render(ctx.canvas, color, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+
+        Text::new(&ctx.font, "Select: recent runs  Y: upgrades", HISTORY_LINE_HEIGHT)
+            .render(ctx.canvas, gray, TextLayout::CenteredAtTop(top))?;
+
+        Ok(())
+    }
+
+    fn render_history<T: RenderTarget>(
+        &self,
+        ctx: &mut RenderContext<T>,
+        white: (u8, u8, u8, u8),
+        gray: (u8, u8, u8, u8),
+    ) -> Result<(), SDLError> {
+        Text::new(&ctx.font, "Recent Runs", TITLE_HEIGHT)
+            .render(ctx.canvas, white, TextLayout::CenteredAtTop(TITLE_TOP))?;
+
+        let mut top = TITLE_TOP + TITLE_HEIGHT as u32 + LINE_SPACING / 2;
+        let lines = self.history_lines();
+        if lines.is_empty() {
+            Text::new(&ctx.font, "No runs yet", LINE_HEIGHT)
+                .render(ctx.canvas, gray, TextLayout::CenteredAtTop(top))?;
+        }
+        for line in lines {
+            Text::new(&ctx.font, line, HISTORY_LINE_HEIGHT)
+                .render(ctx.canvas, white, TextLayout::CenteredAtTop(top))?;
+            top += HISTORY_LINE_SPACING;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn lines_include_current_floor() {
+        let stats = RunStats {
+            rescues: 0,
+            floor: FloorStats {frames: 30 * 20, enemies_killed: 1, damage_taken: 20, damage_dealt: 15, items_found: 0, secrets_found: 0},
+            totals: FloorStats {frames: 30 * 75, enemies_killed: 6, damage_taken: 9, damage_dealt: 90, items_found: 2, secrets_found: 0},
+        };
+        let game_over = GameOver::new(RunOutcome::Death, 3, stats, 30);
+        assert_eq!(game_over.lines(), &[
+            "Floor reached: 3",
+            "Time: 1:35",
+            "Enemies defeated: 7",
+            "Damage dealt: 105",
+            "Damage taken: 29",
+        ]);
+    }
+
+    #[test]
+    fn title_depends_on_outcome() {
+        let game_over = GameOver::new(RunOutcome::Death, 2, RunStats::default(), 30);
+        assert_eq!((game_over.outcome(), game_over.title()), (RunOutcome::Death, "You Died"));
+        let mut game_over = GameOver::new(RunOutcome::Victory, 5, RunStats::default(), 30);
+        assert_eq!((game_over.outcome(), game_over.title()), (RunOutcome::Victory, "Victory!"));
+        game_over.set_escaped();
+        assert_eq!(game_over.title(), "You Escaped!");
+        assert_eq!(game_over.lines().last().map(String::as_str), Some("Escape bonus: x1.5 coins"));
+    }
+
+    #[test]
+    fn history_lines_show_recent_runs() {
+        let mut game_over = GameOver::new(RunOutcome::Death, 2, RunStats::default(), 30);
+        assert!(game_over.history_lines().is_empty());
+
+        game_over.set_history(vec![RunRecord {
+            map_key: rand::random(),
+            outcome: RunOutcome::Death,
+            floor: 4,
+            enemies_killed: 12,
+            frames: 30 * 125,
+            duration: Duration::from_secs(130),
+            finished_at: 1_700_000_000,
+        }]);
+        assert_eq!(game_over.history_lines(), &["2023-11-14  death  floor 4  2:05  12 defeated"]);
+    }
+
+    #[test]
+    fn choose_with_arrows() {
+        let mut game_over = GameOver::new(RunOutcome::Death, 1, RunStats::default(), 30);
+        assert_eq!(game_over.selected(), GameOverChoice::NewSeed);
+
+        // The selection wraps around in both directions
+        game_over.dispatch(&[Event::KeyDown(Key::UpArrow)]);
+        assert_eq!(game_over.selected(), GameOverChoice::Retry);
+        game_over.dispatch(&[Event::KeyDown(Key::DownArrow), Event::KeyDown(Key::DownArrow)]);
+        assert_eq!(game_over.selected(), GameOverChoice::Retry);
+        // Releasing a key does not confirm anything
+        game_over.dispatch(&[Event::KeyUp(Key::A)]);
+        assert_eq!(game_over.chosen(), None);
+
+        // The list of recent runs is opened and closed without confirming anything
+        game_over.dispatch(&[Event::KeyDown(Key::Select)]);
+        assert!(game_over.is_showing_history());
+        game_over.dispatch(&[Event::KeyDown(Key::DownArrow), Event::KeyDown(Key::B)]);
+        assert!(!game_over.is_showing_history());
+        assert_eq!((game_over.selected(), game_over.chosen()), (GameOverChoice::Retry, None));
+
+        // Nothing changes after the choice is confirmed
+        game_over.dispatch(&[Event::KeyDown(Key::A), Event::KeyDown(Key::UpArrow)]);
+        assert_eq!(game_over.chosen(), Some(GameOverChoice::Retry));
+        assert_eq!(game_over.selected(), GameOverChoice::Retry);
+    }
+}
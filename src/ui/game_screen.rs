@@ -1,57 +1,98 @@
+use std::mem;
 use std::path::Path;
 
-use sdl2::render::RenderTarget;
+use sdl2::{rect::Point, render::RenderTarget};
 use component_group::ComponentGroup;
 
-use crate::generator::GenLevel;
-use crate::components::PlayerComponents;
-use crate::resources::{FramesElapsed, Event, GameState};
+use crate::achievements::{Achievements, Achievement, Profile};
+use crate::upgrades::{Upgrades, RunOutcome, escape_bonus};
+use crate::generator::{GenLevel, MapKey};
+use crate::components::{PlayerComponents, PurchaseError};
+use crate::resources::{FramesElapsed, Event, Key, GameState, GameStateMachine, LevelDirection, SoundQueue, Notification, GameEvents, GameEvent, PlayClock, Split, format_split_time};
+use crate::map::{RoomId, RoomUid, RoomType};
+use crate::run_history::RunRecord;
+use crate::interrupts::InterruptEvent;
 
 use super::text::{Text, TextLayout};
-use super::{SDLError, LevelScreen, RenderContext};
-
-/// An animation of text that tells the user which level they are on
-struct LevelTextAnimation {
-    // The zero-based index of the current level
-    level: usize,
-    // The number of frames remaining in the animation
-    timer: usize,
-}
+use super::{SDLError, LevelScreen, RenderContext, NotificationBanner, Transition, FloorSummary, GameOver, GameOverChoice, GhostRun, RecordedInput, Interruption, Shop, ShopAction, UpgradeShop, UpgradeShopAction, render_map_key_footer};
 
-impl LevelTextAnimation {
-    //TODO: Remove the dependence of frames here, the animation should be about 3 seconds
-    const LEVEL_TEXT_FADE_LENGTH: usize = 30; // frames
+/// The number of frames that the summary of a finished floor is shown for unless it is skipped
+const SUMMARY_FRAMES: usize = 120;
+/// The height of the play clock shown in the corner of the screen
+const CLOCK_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the play clock and the top and right edges of the screen
+const CLOCK_MARGIN: i32 = 4;
 
-    pub fn new(level: usize) -> Self {
-        Self {level, timer: Self::LEVEL_TEXT_FADE_LENGTH}
-    }
+/// Returns the notification that tells the user which level they are on
+fn floor_notification(level: usize) -> Notification {
+    Notification::new(format!("Floor {}", level + 1))
+}
 
-    pub fn dispatch(&mut self, frames_elapsed: FramesElapsed) {
-        if self.timer == 0 {
-            return;
-        }
-        self.timer = self.timer.saturating_sub(frames_elapsed.0);
-    }
+/// Returns the notification that tells the user that they unlocked an achievement
+fn achievement_notification(achievement: Achievement) -> Notification {
+    Notification::new(format!("Achievement: {}", achievement.title()))
+}
 
-    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
-        if self.timer == 0 {
-            return Ok(());
-        }
-        // fade out gradually (linearly) as the animation goes on
-        let alpha = (self.timer * 255) / Self::LEVEL_TEXT_FADE_LENGTH;
-        Text::new(&ctx.font, format!("Floor {}", self.level + 1), 30.0)
-            .render(ctx.canvas, (255, 255, 255, alpha as u8), TextLayout::Centered)
-    }
+/// A change of level that happens at the midpoint of a transition. The game is in the
+/// LevelTransition state until the transition is complete.
+struct LevelChange {
+    transition: Transition,
+    /// Shown during the hold of the transition
+    summary: Option<FloorSummary>,
 }
 
 pub struct GameScreen<'a, 'b> {
     levels: Vec<LevelScreen<'a, 'b>>,
     current_level: usize,
-    level_text_animation: LevelTextAnimation,
+    /// Every change between playing, pausing, shopping, changing levels and the end of the game
+    /// goes through this state
+    state: GameStateMachine,
+    notifications: NotificationBanner,
+    /// The level change currently in progress, if any
+    level_change: Option<LevelChange>,
+    /// Shown once the player has won or run out of health. The game does not continue after this.
+    game_over: Option<GameOver>,
+    /// Events that occurred during the last level change or interruption. These are delivered once
+    /// the level continues so that every key release is still paired with its key press.
+    delayed_events: Vec<Event>,
+    /// Keys that were pressed to skip the summary of a floor. Their releases are dropped so that
+    /// the level never sees a key release without its key press.
+    skip_keys: Vec<Key>,
+    achievements: Achievements,
+    /// Game events that have not been given to the achievements yet
+    game_events: Vec<GameEvent>,
+    /// Achievements unlocked since the last call to `take_unlocked`
+    unlocked: Vec<Achievement>,
+    /// The banked coins and upgrades of the profile, kept so that saving the profile does not lose
+    /// them
+    coins: u64,
+    upgrades: Upgrades,
+    /// The number of frames in each second
+    fps: usize,
+    /// A previous run on the same map played back alongside this one, if any
+    ghost: Option<GhostRun>,
+    /// Every input given to the game until the run ended, so that the run can be played back
+    inputs: Vec<RecordedInput>,
+    /// Problems that the game is paused for until the player acknowledges them
+    interruption: Interruption,
+    /// The shop of the merchant that the player is buying from, if any. The level is paused while
+    /// it is open.
+    shop: Option<Shop>,
+    /// The key of the map being played, shown while the game is paused or over
+    map_key: Option<MapKey>,
+    /// The time at which each floor was cleared, in the order they were cleared
+    splits: Vec<Split>,
+    /// The upgrades that can be bought with the banked coins, if the player opened them from the
+    /// game over screen
+    upgrade_shop: Option<UpgradeShop>,
+    /// If true, reaching the treasure starts the escape back to the surface instead of ending
+    /// the run
+    escape_ending: bool,
 }
 
 impl<'a, 'b> GameScreen<'a, 'b> {
-    pub fn new(player: PlayerComponents, mut levels: Vec<GenLevel<'a, 'b>>) -> Self {
+    /// Starts the game on the first level, continuing from the achievements in the given profile
+    pub fn new(player: PlayerComponents, mut levels: Vec<GenLevel<'a, 'b>>, profile: &Profile, fps: usize) -> Self {
         // Add player
         {
             let first_world = &mut levels.first_mut()
@@ -60,10 +101,35 @@ impl<'a, 'b> GameScreen<'a, 'b> {
             player.create(first_world);
         }
 
+        let mut levels: Vec<LevelScreen> = levels.into_iter().map(Into::into).collect();
+        levels[0].spawn_enemies();
+
+        let mut notifications = NotificationBanner::default();
+        notifications.push(floor_notification(0));
+
         Self {
-            levels: levels.into_iter().map(Into::into).collect(),
+            levels,
             current_level: 0,
-            level_text_animation: LevelTextAnimation::new(0),
+            state: GameStateMachine::default(),
+            notifications,
+            level_change: None,
+            game_over: None,
+            delayed_events: Vec::new(),
+            skip_keys: Vec::new(),
+            achievements: Achievements::new(profile),
+            game_events: vec![GameEvent::FloorEntered {floor: 1}],
+            unlocked: Vec::new(),
+            coins: profile.coins,
+            upgrades: profile.upgrades.clone(),
+            fps,
+            ghost: None,
+            inputs: Vec::new(),
+            interruption: Interruption::default(),
+            shop: None,
+            map_key: None,
+            splits: Vec::new(),
+            upgrade_shop: None,
+            escape_ending: false,
         }
     }
 
@@ -72,29 +138,482 @@ impl<'a, 'b> GameScreen<'a, 'b> {
         &self.levels[self.current_level]
     }
 
+    /// Returns the index of the current level
+    pub fn current_level_index(&self) -> usize {
+        self.current_level
+    }
+
+    /// Returns the state that the game is currently in
+    pub fn game_state(&self) -> GameState {
+        self.state.current()
+    }
+
+    /// Moves the player straight to the entrance of the level with the given index without going
+    /// through any of the levels in between. Only meant for debugging, so nothing about the floors
+    /// skipped this way counts towards the splits or achievements. Does nothing unless the game is
+    /// being played.
+    pub fn go_to_level(&mut self, level: usize) {
+        if self.state.current() != GameState::Playing || level == self.current_level {
+            return;
+        }
+        if level >= self.levels.len() {
+            self.notifications.push(Notification::new(format!("There is no floor {}", level + 1)));
+            return;
+        }
+
+        self.levels[self.current_level].stop_screen_shake();
+        self.change_level(level, |level| level.find_level_entrance());
+        self.notifications.push(floor_notification(self.current_level));
+    }
+
+    /// Returns true once the game has ended (e.g. the player has run out of health)
+    pub fn is_game_over(&self) -> bool {
+        self.run_outcome().is_some()
+    }
+
+    /// Returns how the run ended or None if the game is not over yet
+    pub fn run_outcome(&self) -> Option<RunOutcome> {
+        match self.state.current() {
+            GameState::Victory => Some(RunOutcome::Victory),
+            GameState::Defeat => Some(RunOutcome::Death),
+            _ => None,
+        }
+    }
+
+    /// Returns how long the player has spent playing during the run. Level transitions and the
+    /// game over screen do not count.
+    pub fn play_clock(&self) -> PlayClock {
+        self.current_level().play_clock()
+    }
+
+    /// Returns the time at which each floor was cleared so far, in the order they were cleared. A
+    /// floor that is cleared again after going back up to it has a split for each time.
+    pub fn splits(&self) -> &[Split] {
+        &self.splits
+    }
+
+    /// Returns a line of text for each split: the floor, the time the floor was cleared, and the
+    /// time spent since the previous split
+    pub fn split_lines(&self) -> Vec<String> {
+        let mut previous = 0;
+        self.splits.iter().map(|split| {
+            let frames = split.clock.frames();
+            let line = format!("Floor {}  {}  +{}", split.floor, split.clock.format_precise(self.fps),
+                format_split_time(frames - previous, self.fps));
+            previous = frames;
+            line
+        }).collect()
+    }
+
+    /// Returns the game over screen if the game has ended
+    pub fn game_over(&self) -> Option<&GameOver> {
+        self.game_over.as_ref()
+    }
+
+    /// Sets the runs listed on the game over screen, most recent first. Does nothing if the game
+    /// is not over yet.
+    pub fn set_run_history(&mut self, history: Vec<RunRecord>) {
+        if let Some(game_over) = &mut self.game_over {
+            game_over.set_history(history);
+        }
+    }
+
+    /// Plays back a previous run on the same map alongside this one. The ghost advances by one
+    /// frame of its track every time this game is dispatched.
+    pub fn set_ghost(&mut self, ghost: GhostRun) {
+        self.ghost = Some(ghost);
+    }
+
+    /// Returns every input given to the game until the run ended. Replaying these inputs on a
+    /// game generated from the same map key plays the run again, as long as the game was never
+    /// interrupted and the player started with the same upgrades.
+    pub fn recorded_inputs(&self) -> &[RecordedInput] {
+        &self.inputs
+    }
+
+    /// Pauses the game to show the given problem until the player acknowledges it. Problems that
+    /// occur while the game is already paused are shown one after the other.
+    pub fn interrupt(&mut self, event: InterruptEvent) {
+        self.interruption.push(event);
+        // A game that is already paused or over has nothing left to pause
+        let _ = self.state.transition(GameState::Paused);
+    }
+
+    /// Returns true if the game is paused until the player acknowledges a problem
+    pub fn is_interrupted(&self) -> bool {
+        self.interruption.is_active()
+    }
+
+    /// Shows the tutorial on the first level until the player completes or skips it
+    pub fn start_tutorial(&mut self) {
+        self.levels[0].start_tutorial();
+    }
+
+    /// Sets the key of the map being played so that it can be shown to the player
+    pub fn set_map_key(&mut self, key: MapKey) {
+        self.map_key = Some(key);
+    }
+
+    /// Sets whether the player must escape back to the surface with the treasure to win
+    pub fn set_escape_ending(&mut self, enabled: bool) {
+        self.escape_ending = enabled;
+    }
+
+    /// Returns true if the current screen shows the map key (i.e. the game is paused or over)
+    pub fn shows_map_key(&self) -> bool {
+        self.state.current() == GameState::Paused || self.is_game_over()
+    }
+
+    /// Shows the given notification after every notification already queued
+    pub fn notify(&mut self, notification: Notification) {
+        self.notifications.push(notification);
+    }
+
     /// Returns an iterator of the level screens
     pub fn levels(&self) -> impl Iterator<Item=&LevelScreen<'a, 'b>> {
         self.levels.iter()
     }
 
-    /// Dispatch the given events and update the state based on the frames that have elapsed
-    pub fn dispatch(&mut self, frames_elapsed: FramesElapsed, events: Vec<Event>) {
-        let newstate = self.levels[self.current_level].dispatch(frames_elapsed, events);
-        if let Some(newstate) = newstate {
-            use self::GameState::*;
-            match newstate {
-                GoToNextLevel {id} => self.to_next_level(id),
-                GoToPrevLevel {id} => self.to_prev_level(id),
-                Pause => unimplemented!(),
+    /// Dispatch the given events and update the state based on the frames that have elapsed.
+    /// Returns the sound effects that should be played as a result.
+    pub fn dispatch(&mut self, frames_elapsed: FramesElapsed, events: Vec<Event>) -> SoundQueue {
+        if self.run_outcome().is_none() {
+            let FramesElapsed(frames) = frames_elapsed;
+            self.inputs.push(RecordedInput {frames_elapsed: frames, events: events.clone()});
+        }
+
+        let events: Vec<_> = events.into_iter()
+            .filter(|event| !self.is_skip_key_release(event))
+            .collect();
+
+        if self.interruption.is_active() {
+            // Nothing else changes at all until every problem is acknowledged. Other keys are
+            // delivered afterwards so that every key release is still paired with its key press.
+            let events = self.interruption.dispatch(events);
+            self.delayed_events.extend(events);
+            if !self.interruption.is_active() && self.state.current() == GameState::Paused {
+                self.state.resume().expect("bug: unable to resume after an interruption");
             }
-            match newstate {
-                GoToNextLevel {..} | GoToPrevLevel {..} => {
-                    self.level_text_animation = LevelTextAnimation::new(self.current_level);
+            return SoundQueue::default();
+        }
+
+        if let GameState::Shop {..} = self.state.current() {
+            self.dispatch_shop(events);
+            self.update_achievements(None);
+            self.notifications.dispatch(frames_elapsed);
+            // The level is paused while the player is buying something
+            return SoundQueue::default();
+        }
+
+        if let Some(ghost) = &mut self.ghost {
+            ghost.step();
+        }
+
+        match self.state.current() {
+            GameState::Playing => {},
+            GameState::Victory | GameState::Defeat => {
+                self.dispatch_game_over(&events);
+                self.update_achievements(None);
+                self.notifications.dispatch(frames_elapsed);
+                // The level stays exactly as it was when the game ended
+                return SoundQueue::default();
+            },
+            GameState::LevelTransition {..} => {
+                let holding = self.level_change.as_ref()
+                    .map(|change| change.transition.is_holding())
+                    .expect("bug: no level change in progress");
+                let mut skipped = false;
+                for event in events {
+                    match event {
+                        // Any key skips the summary. The key is only used for skipping, so it is
+                        // never given to the level.
+                        Event::KeyDown(key) if holding => {
+                            self.skip_keys.push(key);
+                            skipped = true;
+                        },
+                        // Skipping with a quick tap releases the key right away
+                        ref event if self.is_skip_key_release(event) => {},
+                        event => self.delayed_events.push(event),
+                    }
+                }
+                if skipped {
+                    if let Some(change) = &mut self.level_change {
+                        change.transition.skip_hold();
+                    }
+                }
+
+                self.dispatch_level_change(frames_elapsed);
+                self.update_achievements(None);
+                self.notifications.dispatch(frames_elapsed);
+                // The level is paused during the transition
+                return SoundQueue::default();
+            },
+            state @ GameState::Paused | state @ GameState::Shop {..} => {
+                unreachable!("bug: the level should not be dispatched in the {:?} state", state);
+            },
+        }
+
+        let mut all_events = mem::take(&mut self.delayed_events);
+        all_events.extend(events);
+        self.levels[self.current_level].dispatch(frames_elapsed, all_events, &mut self.state);
+        // Need to take the sounds and notifications before the level potentially changes below
+        let sounds = self.levels[self.current_level].take_sounds();
+        self.notifications.extend(self.levels[self.current_level].take_notifications());
+        let GameEvents(game_events) = self.levels[self.current_level].take_game_events();
+        self.game_events.extend(game_events);
+        match self.state.current() {
+            GameState::LevelTransition {..} => self.start_level_change(),
+            GameState::Shop {merchant} => {
+                self.levels[self.current_level].stop_screen_shake();
+                self.shop = Some(super::Shop::new(merchant));
+            },
+            _ => {},
+        }
+        if self.escape_ending && self.reached_treasure() {
+            self.begin_escape();
+        }
+        if self.current_level().is_player_defeated() && self.state.transition(GameState::Defeat).is_ok() {
+            self.end_run(RunOutcome::Death);
+        } else if self.reached_exit() {
+            let floor = self.current_level + 1;
+            self.splits.push(Split {floor, clock: self.play_clock()});
+            self.end_run(RunOutcome::Victory);
+        }
+        self.update_achievements(Some(frames_elapsed));
+        self.notifications.dispatch(frames_elapsed);
+
+        sounds
+    }
+
+    /// Passes the given events to the game over screen or to the upgrades opened from it. The Y
+    /// key opens the upgrades unless the player is looking at their recent runs.
+    fn dispatch_game_over(&mut self, events: &[Event]) {
+        let game_over = match &mut self.game_over {
+            Some(game_over) => game_over,
+            None => return,
+        };
+
+        let shop = match &mut self.upgrade_shop {
+            Some(shop) => shop,
+            None => {
+                let mut open_upgrades = false;
+                for event in events {
+                    if let Event::KeyDown(Key::Y) = event {
+                        open_upgrades = !game_over.is_showing_history() && game_over.chosen().is_none();
+                    }
+                }
+                if open_upgrades {
+                    self.upgrade_shop = Some(UpgradeShop::default());
+                } else {
+                    game_over.dispatch(events);
+                }
+                return;
+            },
+        };
+
+        for action in shop.dispatch(events) {
+            match action {
+                UpgradeShopAction::Buy(upgrade) => {
+                    let notification = match self.upgrades.next_cost(upgrade) {
+                        None => format!("{} is fully upgraded", upgrade.title()),
+                        Some(cost) if self.upgrades.purchase(upgrade, &mut self.coins) => {
+                            format!("Bought {} for {} coins", upgrade.title(), cost)
+                        },
+                        Some(cost) => format!("Not enough coins ({} of {})", self.coins, cost),
+                    };
+                    self.notifications.push(Notification::new(notification));
+                },
+                UpgradeShopAction::Close => {
+                    self.upgrade_shop = None;
+                    break;
                 },
-                _ => {},
             }
+        }
+    }
+
+    /// Returns true if the player is in the treasure chamber at the bottom of the caves
+    fn reached_treasure(&self) -> bool {
+        self.current_room().map(|(_, room_type)| room_type) == Some(RoomType::TreasureChamber)
+    }
+
+    /// Ends the run in victory if the player has made it to the end of the run. Without the escape
+    /// ending, that is the treasure chamber. With it, the player must bring the treasure back out
+    /// through the mouth of the dungeon on the first floor. Returns true if the run was won.
+    fn reached_exit(&mut self) -> bool {
+        if self.escape_ending {
+            // Leaving through the mouth of the dungeon ends the run from within the level
+            self.state.current() == GameState::Victory
         } else {
-            self.level_text_animation.dispatch(frames_elapsed);
+            self.reached_treasure() && self.state.transition(GameState::Victory).is_ok()
+        }
+    }
+
+    /// Starts the escape back to the surface, clearing the floor with the treasure. Does nothing
+    /// if the player is already escaping.
+    fn begin_escape(&mut self) {
+        let mut phase = self.current_level().run_phase();
+        if !phase.begin_escape() {
+            return;
+        }
+        self.levels[self.current_level].update_run_phase(phase);
+        let floor = self.current_level + 1;
+        self.splits.push(Split {floor, clock: self.play_clock()});
+        self.notifications.push(Notification::new("Escape to the surface!"));
+    }
+
+    /// Banks the gold that the player is carrying and shows the game over screen for the run that
+    /// just ended. The game must already be in the Victory or Defeat state.
+    fn end_run(&mut self, outcome: RunOutcome) {
+        let escaped = outcome == RunOutcome::Victory && self.escape_ending;
+        let mut gold = self.current_level().player_gold() as u64;
+        if escaped {
+            gold = escape_bonus(gold);
+        }
+        let mut profile = self.profile();
+        profile.bank_coins(gold, outcome);
+        if profile.coins > self.coins {
+            self.notifications.push(Notification::new(format!("Banked {} coins", profile.coins - self.coins)));
+        }
+        self.coins = profile.coins;
+
+        self.levels[self.current_level].stop_screen_shake();
+        let stats = self.current_level().run_stats();
+        let mut game_over = GameOver::new(outcome, self.current_level + 1, stats, self.fps);
+        if outcome == RunOutcome::Victory {
+            self.game_events.push(GameEvent::RunWon {frames: self.play_clock().frames()});
+            game_over.set_splits(self.split_lines());
+            if escaped {
+                game_over.set_escaped();
+            }
+        }
+        self.game_over = Some(game_over);
+    }
+
+    /// Passes the given events to the open shop, buying items or closing the shop as the player
+    /// chooses. Other keys are delivered once the shop is closed.
+    fn dispatch_shop(&mut self, events: Vec<Event>) {
+        let shop = self.shop.as_mut().expect("bug: no shop open");
+        let merchant = shop.merchant();
+        let stock_len = self.levels[self.current_level].merchant_stock(merchant).len();
+        let (events, actions) = shop.dispatch(events, stock_len);
+        self.delayed_events.extend(events);
+
+        for action in actions {
+            match action {
+                ShopAction::Buy(index) => match self.levels[self.current_level].buy(merchant, index) {
+                    Ok(item) => self.notifications.push(Notification::new(format!("Bought {}", item.name()))),
+                    Err(PurchaseError::NotEnoughGold {price, gold}) => {
+                        self.notifications.push(Notification::new(format!("Not enough gold ({} of {})", gold, price)));
+                    },
+                    // Nothing left to buy
+                    Err(PurchaseError::SoldOut) => {},
+                },
+                ShopAction::Close => {
+                    self.shop = None;
+                    self.state.transition(GameState::Playing).expect("bug: unable to close the shop");
+                },
+            }
+        }
+    }
+
+    /// Gives the game events that have occurred so far to the achievements and announces any
+    /// achievements that were unlocked. Frames only count if the player was on a floor.
+    fn update_achievements(&mut self, frames_elapsed: Option<FramesElapsed>) {
+        let events = mem::take(&mut self.game_events);
+        for achievement in self.achievements.dispatch(frames_elapsed, &events) {
+            self.notifications.push(achievement_notification(achievement));
+            self.unlocked.push(achievement);
+        }
+    }
+
+    /// Takes the achievements that were unlocked since the last time this was called
+    pub fn take_unlocked(&mut self) -> Vec<Achievement> {
+        mem::take(&mut self.unlocked)
+    }
+
+    /// Returns the choice made by the player once the run ended, if any. The game is over
+    /// once this returns a choice.
+    pub fn game_over_choice(&self) -> Option<GameOverChoice> {
+        self.game_over.as_ref().and_then(|game_over| game_over.chosen())
+    }
+
+    /// Returns the profile that keeps the achievements unlocked and progress made so far
+    pub fn profile(&self) -> Profile {
+        Profile {
+            coins: self.coins,
+            upgrades: self.upgrades.clone(),
+            ..self.achievements.profile()
+        }
+    }
+
+    /// Returns true if the given event is the release of a key that was used to skip the summary
+    /// of a floor. The key is forgotten so that only its first release is treated this way.
+    fn is_skip_key_release(&mut self, event: &Event) -> bool {
+        let key = match event {
+            Event::KeyUp(key) => key,
+            _ => return false,
+        };
+        match self.skip_keys.iter().position(|skip_key| skip_key == key) {
+            Some(index) => {
+                self.skip_keys.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Starts a transition that will change the level at its midpoint. The game must already be
+    /// in the LevelTransition state.
+    fn start_level_change(&mut self) {
+        let to = match self.state.current() {
+            GameState::LevelTransition {to, ..} => to,
+            state => unreachable!("bug: cannot change the level in the {:?} state", state),
+        };
+        // The level is paused during the transition
+        self.levels[self.current_level].stop_screen_shake();
+        let stats = self.levels[self.current_level].finish_floor();
+        let secrets_total = stats.secrets_found + self.levels[self.current_level].secrets_remaining();
+        let summary = match to {
+            // Only floors that the player went down from are cleared
+            LevelDirection::Next => {
+                let floor = self.current_level + 1;
+                self.splits.push(Split {floor, clock: self.play_clock()});
+                self.game_events.push(GameEvent::FloorCleared {floor, stats});
+                Some(FloorSummary {floor, stats, secrets_total, fps: self.fps})
+            },
+            LevelDirection::Prev => None,
+        };
+        let hold_frames = if summary.is_some() { SUMMARY_FRAMES } else { 0 };
+
+        self.level_change = Some(LevelChange {
+            transition: Transition::new(hold_frames),
+            summary,
+        });
+    }
+
+    /// Advances the level change in progress, changing the level once its midpoint is reached
+    fn dispatch_level_change(&mut self, frames_elapsed: FramesElapsed) {
+        let change = self.level_change.as_mut()
+            .expect("bug: no level change in progress");
+        let reached_midpoint = change.transition.dispatch(frames_elapsed);
+
+        if reached_midpoint {
+            match self.state.current() {
+                GameState::LevelTransition {to: LevelDirection::Next, id} => self.enter_next_level(id),
+                GameState::LevelTransition {to: LevelDirection::Prev, id} => self.enter_prev_level(id),
+                state => unreachable!("bug: cannot change the level in the {:?} state", state),
+            }
+            self.notifications.push(floor_notification(self.current_level));
+            self.game_events.push(GameEvent::FloorEntered {floor: self.current_level + 1});
+        }
+
+        let complete = match &self.level_change {
+            Some(change) => change.transition.is_complete(),
+            None => false,
+        };
+        if complete {
+            self.level_change = None;
+            self.state.transition(GameState::Playing).expect("bug: unable to finish the level change");
         }
     }
 
@@ -105,41 +624,832 @@ impl<'a, 'b> GameScreen<'a, 'b> {
         self.current_level().render_to_file(path)
     }
 
+    /// Returns a plain-text description of everything the player can currently see
+    pub fn describe_surroundings(&self) -> String {
+        self.current_level().describe_surroundings()
+    }
+
+    /// Returns the room that the player is currently considered to be in, if any
+    pub fn current_room(&self) -> Option<(RoomId, RoomType)> {
+        self.current_level().current_room()
+    }
+
+    /// Returns the uid and type of the room that the player is currently in, if any
+    pub fn current_room_uid(&self) -> Option<(RoomUid, RoomType)> {
+        self.current_level().current_room_uid()
+    }
+
     /// Draw the game
     pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
-        self.current_level().render(ctx)?;
-        self.level_text_animation.render(ctx)
+        let ghost = self.ghost.as_ref().and_then(|ghost| ghost.visible_on(self.current_level));
+        self.current_level().render(ctx, ghost)?;
+        if ctx.show_play_clock {
+            self.render_play_clock(ctx)?;
+        }
+
+        if let Some(shop) = &self.shop {
+            let level = self.current_level();
+            shop.render(ctx, &level.merchant_stock(shop.merchant()), level.player_gold())?;
+        }
+
+        if let Some(LevelChange {transition, summary}) = &self.level_change {
+            transition.render(ctx)?;
+            if let (true, Some(summary)) = (transition.is_holding(), summary) {
+                summary.render(ctx)?;
+            }
+        }
+
+        if let Some(shop) = &self.upgrade_shop {
+            shop.render(ctx, &self.upgrades, self.coins)?;
+        } else if let Some(game_over) = &self.game_over {
+            game_over.render(ctx)?;
+        }
+
+        self.interruption.render(ctx)?;
+
+        if let (true, Some(key)) = (self.shows_map_key(), self.map_key) {
+            render_map_key_footer(ctx, key)?;
+        }
+        // Drawn last so that notifications (e.g. that the map key was copied) are never darkened
+        self.notifications.render(ctx)?;
+
+        Ok(())
     }
 
-    /// Advances to the next level. Panics if there is no next level
-    fn to_next_level(&mut self, gate_id: usize) {
-        // Fetch the player as-is from the current world
-        let mut player = self.current_level().player_components();
+    /// Draws the time played so far in the top right corner of the screen
+    fn render_play_clock<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        let (screen_width, _) = ctx.canvas.logical_size();
+        let text = Text::new(&ctx.font, self.play_clock().format_precise(self.fps), CLOCK_HEIGHT);
+        let left = (screen_width as i32 - text.width().ceil() as i32 - CLOCK_MARGIN).max(0);
+        text.render(ctx.canvas, (255, 255, 255, 255), TextLayout::TopLeftAt(Point::new(left, CLOCK_MARGIN)))
+    }
 
-        // Go to the next level
-        self.current_level += 1;
-        assert!(self.current_level < self.levels.len(), "bug: advanced too many levels");
+    /// Advances to the next level. Panics if there is no next level
+    fn enter_next_level(&mut self, gate_id: usize) {
+        // The tutorial only teaches the controls at the very start of the run
+        self.levels[self.current_level].end_tutorial();
 
         // When going to the next level, we need to connect back to the corresponding gate that
         // will take you back to the previous level
-        player.position.0 = self.current_level().find_to_prev_level_adjacent(gate_id);
-        // Move the player from the previous level to the next level
-        self.levels[self.current_level].update_player(player);
+        let next = self.current_level + 1;
+        assert!(next < self.levels.len(), "bug: advanced too many levels");
+        self.change_level(next, |level| level.find_to_prev_level_adjacent(gate_id));
     }
 
     /// Goes back to the previous level. Panics if there is no previous level.
-    fn to_prev_level(&mut self, gate_id: usize) {
-        // Fetch the player as-is from the current world
-        let mut player = self.current_level().player_components();
-
-        // Go the previous level
-        self.current_level = self.current_level.checked_sub(1)
-            .expect("bug: went back too many levels");
-
+    fn enter_prev_level(&mut self, gate_id: usize) {
         // When going to the previous level, we need to connect back to the corresponding gate that
         // will take you to the next level
-        player.position.0 = self.current_level().find_to_next_level_adjacent(gate_id);
-        // Move the player from the next level to the previous level
+        let prev = self.current_level.checked_sub(1)
+            .expect("bug: went back too many levels");
+        self.change_level(prev, |level| level.find_to_next_level_adjacent(gate_id));
+    }
+
+    /// Moves the player and the run statistics from the current level to the level with the given
+    /// index, placing the player at the position returned by `find_position`. Each level keeps its
+    /// own world, so the enemies left behind are exactly as they were when the player returns.
+    /// Only the enemies that have never been spawned on the new level are spawned.
+    fn change_level(&mut self, level: usize, find_position: impl FnOnce(&LevelScreen<'a, 'b>) -> Point) {
+        // Fetch the player and the run statistics as-is from the current world
+        let mut player = self.current_level().player_components();
+        let stats = self.current_level().run_stats();
+        let phase = self.current_level().run_phase();
+        let clock = self.current_level().play_clock();
+
+        self.current_level = level;
+        player.position.0 = find_position(self.current_level());
+        // Move the player from the previous level to the new level
         self.levels[self.current_level].update_player(player);
+        self.levels[self.current_level].update_run_stats(stats);
+        self.levels[self.current_level].update_run_phase(phase);
+        self.levels[self.current_level].update_play_clock(clock);
+        self.levels[self.current_level].spawn_enemies();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{SeedableRng, rngs::StdRng};
+    use specs::{Builder, Join, ReadStorage};
+
+    use crate::components::{BoundingBox, EnemySpawn, Ghost, Position, Stairs, DungeonMouth, HealthPoints, Enemy, EnemyBehaviour, AiState, Movement, MovementDirection, Knockback, Wait, Item, Chest};
+    use crate::generator::EnemyValues;
+    use crate::upgrades::Upgrade;
+    use crate::map::{FloorMap, Room, GridSize, TilePos, TileRect};
+    use crate::resources::{ExploredTiles, RunPhase, LevelUids};
+    use crate::systems::{LevelDispatcher, SequentialDispatcher, Keyboard, build_dispatcher};
+    use crate::test_helpers::{walled_room, level_world, player_components, test_animations};
+    use crate::ui;
+    use crate::ui::{GhostTrack, RecordedInput};
+    use crate::interrupts::InterruptEvent;
+    use crate::assets::TextureId;
+
+    const TILE_SIZE: u32 = 16;
+
+    fn map_size() -> GridSize {
+        GridSize {rows: 5, cols: 12}
+    }
+
+    /// A level with a single room that contains the given staircase
+    fn test_level<'a, 'b>(stairs: Stairs, stairs_pos: TilePos) -> GenLevel<'a, 'b> {
+        let GridSize {rows, cols} = map_size();
+        let mut world = level_world(walled_room(rows, cols, TILE_SIZE));
+        let mut dispatcher = LevelDispatcher::Watchdog(
+            build_dispatcher(SequentialDispatcher::default(), Keyboard::default()));
+        dispatcher.setup(&mut world.res);
+        ui::setup(&mut world.res);
+        world.register::<EnemySpawn>();
+
+        world.create_entity()
+            .with(Ghost)
+            .with(Position(stairs_pos.center(TILE_SIZE as i32)))
+            .with(BoundingBox::Full {width: TILE_SIZE / 2, height: TILE_SIZE / 2})
+            .with(stairs)
+            .build();
+
+        GenLevel {world, dispatcher, spawn_rng: StdRng::seed_from_u64(0)}
+    }
+
+    fn test_player() -> PlayerComponents {
+        player_components(TilePos {row: 2, col: 2}.center(TILE_SIZE as i32))
+    }
+
+    /// Changes the type of the only room of the given level (e.g. into the treasure chamber)
+    fn change_room(level: &mut GenLevel<'_, '_>, change: impl FnOnce(&mut Room)) {
+        let mut map = level.world.write_resource::<FloorMap>();
+        let (room_id, _) = map.rooms().next().expect("bug: test level should have a room");
+        change(map.room_mut(room_id));
+    }
+
+    /// Starts changing the level as if the player had just taken the staircase with the given id
+    fn take_stairs(screen: &mut GameScreen<'_, '_>, to: LevelDirection, id: usize) {
+        screen.state.transition(GameState::LevelTransition {to, id}).unwrap();
+        screen.start_level_change();
+    }
+
+    #[test]
+    fn explored_tiles_kept_between_levels() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        let explored = screen.levels[0].explored_tiles();
+        assert!(explored.contains(TilePos {row: 2, col: 2}));
+
+        // Nothing on the next level has been explored yet
+        screen.enter_next_level(0);
+        assert_eq!(screen.levels[1].explored_tiles(), ExploredTiles::new(map_size()));
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        let next_explored = screen.levels[1].explored_tiles();
+        assert!(next_explored.contains(TilePos {row: 1, col: 1}));
+
+        // Coming back finds both levels exactly as they were left
+        screen.enter_prev_level(0);
+        assert_eq!(screen.levels[0].explored_tiles(), explored);
+        assert_eq!(screen.levels[1].explored_tiles(), next_explored);
+    }
+
+    /// Returns the complete state of every enemy on the given level
+    fn enemy_states(level: &mut LevelScreen<'_, '_>) -> Vec<String> {
+        let (enemies, positions, healths, movements, ai_states, knockbacks, waits) = level.world_mut().system_data::<(
+            ReadStorage<'_, Enemy>,
+            ReadStorage<'_, Position>,
+            ReadStorage<'_, HealthPoints>,
+            ReadStorage<'_, Movement>,
+            ReadStorage<'_, AiState>,
+            ReadStorage<'_, Knockback>,
+            ReadStorage<'_, Wait>,
+        )>();
+        (&enemies, &positions, &healths, &movements, ai_states.maybe(), knockbacks.maybe(), waits.maybe()).join()
+            .map(|state| format!("{:?}", state))
+            .collect()
+    }
+
+    #[test]
+    fn enemy_combat_state_kept_between_levels() {
+        let mut first = test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10});
+        first.world.create_entity()
+            .with(Position(TilePos {row: 2, col: 7}.center(TILE_SIZE as i32)))
+            .with(EnemySpawn {probability: 1.0, enemy: EnemyValues {
+                behaviour: EnemyBehaviour::Random,
+                animations: test_animations(),
+                attack: 1,
+                speed: 3.0,
+                health_points: 20,
+                hit_wait: 12,
+                bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+            }})
+            .build();
+        let levels = vec![
+            first,
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+
+        // Leave the enemy in the middle of a fight: hurt, knocked back, waiting out its hit wait
+        // and chasing the player
+        {
+            let world = screen.levels[0].world_mut();
+            let entities: Vec<_> = (&world.entities(), &world.read_storage::<Enemy>()).join()
+                .map(|(entity, _)| entity)
+                .collect();
+            assert_eq!(entities.len(), 1);
+            let enemy = entities[0];
+            world.write_storage::<HealthPoints>().insert(enemy, HealthPoints(10)).unwrap();
+            world.write_storage::<Movement>().get_mut(enemy).unwrap().direction = MovementDirection::West;
+            world.write_storage::<AiState>().insert(enemy, AiState::Chasing).unwrap();
+            world.write_storage::<Knockback>().insert(enemy, Knockback::new(MovementDirection::East, 4, 6)).unwrap();
+            world.write_storage::<Wait>().insert(enemy, Wait {duration: 12, frames_elapsed: 5}).unwrap();
+        }
+        let before = enemy_states(&mut screen.levels[0]);
+
+        // Each level keeps its own world, so nothing about the enemy is lost while the player is
+        // away and it is not spawned a second time when the player returns
+        screen.enter_next_level(0);
+        screen.enter_prev_level(0);
+        assert_eq!(enemy_states(&mut screen.levels[0]), before);
+    }
+
+    #[test]
+    fn achievements_unlocked_when_floor_cleared() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut profile = Profile::default();
+        profile.unlocked.insert(Achievement::Speedrunner);
+        profile.coins = 75;
+        let mut screen = GameScreen::new(test_player(), levels, &profile, 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.take_unlocked(), &[]);
+
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(2), Vec::new());
+        }
+        // Already unlocked achievements are not unlocked again
+        assert_eq!(screen.take_unlocked(), &[Achievement::Untouchable]);
+        assert_eq!(screen.take_unlocked(), &[]);
+        assert!(screen.notifications.current().is_some());
+
+        let profile = screen.profile();
+        assert!(profile.unlocked.contains(&Achievement::Speedrunner));
+        assert!(profile.unlocked.contains(&Achievement::Untouchable));
+        // Coins are not lost when the achievements are saved
+        assert_eq!(profile.coins, 75);
+    }
+
+    #[test]
+    fn taking_stairs_changes_level() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        let mut player = screen.current_level().player_components();
+        player.position = Position(TilePos {row: 2, col: 10}.center(TILE_SIZE as i32));
+        screen.levels[0].update_player(player);
+
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.game_state(), GameState::LevelTransition {to: LevelDirection::Next, id: 0});
+
+        // A problem during the transition pauses it until the problem is acknowledged
+        screen.interrupt(InterruptEvent::SaveFailed {
+            what: "profile",
+            path: "profile.txt".to_string(),
+            error: "disk full".to_string(),
+        });
+        assert_eq!(screen.game_state(), GameState::Paused);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::A)]);
+        assert_eq!(screen.game_state(), GameState::LevelTransition {to: LevelDirection::Next, id: 0});
+
+        while screen.game_state() != GameState::Playing {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        assert_eq!(screen.current_level_index(), 1);
+        assert!(screen.level_change.is_none());
+    }
+
+    #[test]
+    fn skip_key_not_given_to_level() {
+        let mut first = test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10});
+        first.world.create_entity()
+            .with(Position(TilePos {row: 3, col: 5}.center(TILE_SIZE as i32)))
+            .with(BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE})
+            .with(Chest::Item(Item::Gold(5)))
+            .build();
+        let levels = vec![first, test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1})];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        let summary = screen.level_change.as_ref().unwrap().summary.as_ref().unwrap();
+        assert_eq!(summary.lines().last().unwrap(), "Secrets found: 0/1");
+
+        // Keys pressed before the summary is shown are still given to the level afterwards
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::RightArrow)]);
+        while !screen.level_change.as_ref().unwrap().transition.is_holding() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        assert!(!screen.level_change.as_ref().unwrap().transition.is_holding());
+        assert_eq!(screen.delayed_events, &[Event::KeyDown(Key::RightArrow)]);
+
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        // Releasing the key that skipped the summary does not interact with anything
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::A), Event::KeyUp(Key::RightArrow)]);
+        assert!(screen.skip_keys.is_empty());
+        assert!(screen.delayed_events.is_empty());
+
+        // Tapping a key during the summary skips it without ever giving the key to the level
+        take_stairs(&mut screen, LevelDirection::Prev, 0);
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        while !screen.level_change.as_ref().unwrap().transition.is_holding() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A), Event::KeyUp(Key::A)]);
+        assert!(!screen.level_change.as_ref().unwrap().transition.is_holding());
+        assert!(screen.skip_keys.is_empty());
+        assert!(screen.delayed_events.is_empty());
+    }
+
+    #[test]
+    fn current_room_identified_by_uid() {
+        let mut level = test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10});
+        let (uids, room_uid) = {
+            let map = level.world.read_resource::<FloorMap>();
+            let (_, room) = map.rooms().next().unwrap();
+            (LevelUids::new(1, &map, Vec::new()), RoomUid::new(1, room))
+        };
+        level.world.add_resource(uids);
+        let mut screen = GameScreen::new(test_player(), vec![level], &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.current_room_uid(), Some((room_uid, RoomType::Normal)));
+    }
+
+    #[test]
+    fn game_over_once_player_defeated() {
+        let levels = vec![test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10})];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert!(screen.game_over.is_none());
+        assert!(!screen.shows_map_key());
+
+        let mut player = screen.current_level().player_components();
+        player.health_points = HealthPoints(0);
+        screen.levels[0].update_player(player);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        let game_over = screen.game_over.as_ref().expect("game should be over");
+        assert_eq!(game_over.lines()[0], "Floor reached: 1");
+        assert_eq!(screen.run_outcome(), Some(RunOutcome::Death));
+        assert_eq!(screen.game_over_choice(), None);
+        assert!(screen.shows_map_key());
+
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::DownArrow)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        assert_eq!(screen.game_over_choice(), Some(GameOverChoice::Retry));
+    }
+
+    #[test]
+    fn gold_banked_and_spent_on_upgrades_after_defeat() {
+        let levels = vec![test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10})];
+        let profile = Profile {coins: 30, ..Profile::default()};
+        let mut screen = GameScreen::new(test_player(), levels, &profile, 30);
+        let mut player = screen.current_level().player_components();
+        player.health_points = HealthPoints(0);
+        player.inventory.add(Item::Gold(41));
+        screen.levels[0].update_player(player);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert!(screen.is_game_over());
+        // Only a fraction of the gold is kept after a defeat
+        assert_eq!(screen.profile().coins, 30 + 20);
+
+        // The selection on the game over screen does not move while the upgrades are open
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::Y)]);
+        assert!(screen.upgrade_shop.is_some());
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::DownArrow), Event::KeyDown(Key::A)]);
+        assert_eq!(screen.game_over().unwrap().selected(), GameOverChoice::NewSeed);
+        let profile = screen.profile();
+        assert_eq!(profile.upgrades.tier(Upgrade::StartingPotion), 1);
+        assert_eq!(profile.coins, 50 - Upgrade::StartingPotion.cost(1).unwrap());
+
+        // Nothing is spent without enough coins
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        assert_eq!(screen.profile(), profile);
+
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::B)]);
+        assert!(screen.upgrade_shop.is_none());
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        assert_eq!(screen.game_over_choice(), Some(GameOverChoice::NewSeed));
+        // Once a choice is made, the upgrades can no longer be opened
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::Y)]);
+        assert!(screen.upgrade_shop.is_none());
+    }
+
+    #[test]
+    fn victory_once_treasure_reached() {
+        let mut last = test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1});
+        change_room(&mut last, Room::become_treasure_chamber);
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            last,
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.run_outcome(), None);
+
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.game_state(), GameState::Victory);
+        assert_eq!(screen.run_outcome(), Some(RunOutcome::Victory));
+        assert!(screen.shows_map_key());
+        let game_over = screen.game_over().expect("game should be over");
+        assert_eq!((game_over.outcome(), game_over.floor()), (RunOutcome::Victory, 2));
+        let unlocked = screen.take_unlocked();
+        assert!(unlocked.contains(&Achievement::Victorious));
+        assert!(unlocked.contains(&Achievement::Lightning));
+        // Reaching the treasure clears the last floor
+        let floors: Vec<_> = screen.splits().iter().map(|split| split.floor).collect();
+        assert_eq!(floors, &[1, 2]);
+
+        // Running out of health afterwards does not change how the run ended
+        let mut player = screen.current_level().player_components();
+        player.health_points = HealthPoints(0);
+        screen.levels[1].update_player(player);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.run_outcome(), Some(RunOutcome::Victory));
+    }
+
+    #[test]
+    fn escape_ending_requires_return_to_surface() {
+        let mut first = test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10});
+        change_room(&mut first, Room::become_player_start);
+        first.world.create_entity()
+            .with(Ghost)
+            .with(Position(TilePos {row: 1, col: 9}.center(TILE_SIZE as i32)))
+            .with(BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE})
+            .with(DungeonMouth)
+            .build();
+        let mut last = test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1});
+        change_room(&mut last, Room::become_treasure_chamber);
+        let mut player = test_player();
+        player.inventory.add(Item::Gold(40));
+        let mut screen = GameScreen::new(player, vec![first, last], &Profile::default(), 30);
+        screen.set_escape_ending(true);
+        // Starting in the room where the player entered the caves does not win the game
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.game_state(), GameState::Playing);
+
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        // Reaching the treasure starts the escape instead of ending the run
+        assert_eq!(screen.game_state(), GameState::Playing);
+        assert_eq!(screen.current_level().run_phase(), RunPhase::Escaping);
+        let floors: Vec<_> = screen.splits().iter().map(|split| split.floor).collect();
+        assert_eq!(floors, &[1, 2]);
+        // Staying with the treasure does not clear the floor again
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.splits().len(), 2);
+
+        take_stairs(&mut screen, LevelDirection::Prev, 0);
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.current_level_index(), 0);
+        // Making it back to the first floor is not enough to leave the caves
+        assert_eq!(screen.game_state(), GameState::Playing);
+
+        // The mouth of the dungeon is right beside where the player arrives
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::LeftArrow)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::LeftArrow)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A), Event::KeyUp(Key::A)]);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.run_outcome(), Some(RunOutcome::Victory));
+        assert_eq!(screen.game_over().map(GameOver::title), Some("You Escaped!"));
+        // Escaping earns a bonus on the coins carried out of the caves
+        assert_eq!(screen.profile().coins, escape_bonus(40));
+        assert!(escape_bonus(40) > 40);
+    }
+
+    #[test]
+    fn play_clock_only_runs_during_play() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        for _ in 0..5 {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        assert_eq!(screen.play_clock().frames(), 5);
+
+        // Stopped for the entire transition, including the summary of the floor
+        take_stairs(&mut screen, LevelDirection::Next, 0);
+        let mut transition_frames = 0;
+        while screen.level_change.is_some() {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+            transition_frames += 1;
+        }
+        assert!(transition_frames > 0);
+        assert_eq!(screen.current_level_index(), 1);
+        assert_eq!(screen.play_clock().frames(), 5);
+
+        // Carried over to the next level
+        screen.dispatch(FramesElapsed(3), Vec::new());
+        assert_eq!(screen.play_clock().frames(), 8);
+
+        let mut player = screen.current_level().player_components();
+        player.health_points = HealthPoints(0);
+        screen.levels[1].update_player(player);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert!(screen.is_game_over());
+        let played = screen.play_clock().frames();
+        // Stopped on the game over screen, even while looking at the recent runs
+        screen.dispatch(FramesElapsed(40), vec![Event::KeyDown(Key::Select)]);
+        screen.dispatch(FramesElapsed(40), Vec::new());
+        assert_eq!(screen.play_clock().frames(), played);
+        assert_eq!(screen.play_clock().format(30), "0:00");
+    }
+
+    #[test]
+    fn splits_exclude_pauses() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        let finish_level_change = |screen: &mut GameScreen<'_, '_>, to| {
+            take_stairs(screen, to, 0);
+            while screen.level_change.is_some() {
+                screen.dispatch(FramesElapsed(1), Vec::new());
+            }
+        };
+
+        // Play, pause for a while, then play some more
+        screen.dispatch(FramesElapsed(4), Vec::new());
+        screen.interrupt(InterruptEvent::SaveFailed {
+            what: "profile",
+            path: "profile.txt".to_string(),
+            error: "disk full".to_string(),
+        });
+        for _ in 0..10 {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::A)]);
+        screen.dispatch(FramesElapsed(6), Vec::new());
+        assert_eq!(screen.play_clock().frames(), 10);
+
+        finish_level_change(&mut screen, LevelDirection::Next);
+        screen.dispatch(FramesElapsed(5), Vec::new());
+        // Going back up is not a split, but clearing the same floor again is
+        finish_level_change(&mut screen, LevelDirection::Prev);
+        screen.dispatch(FramesElapsed(30), Vec::new());
+        finish_level_change(&mut screen, LevelDirection::Next);
+
+        let splits: Vec<_> = screen.splits().iter().map(|split| (split.floor, split.clock.frames())).collect();
+        assert_eq!(splits, &[(1, 10), (1, 45)]);
+        assert_eq!(screen.split_lines(), &["Floor 1  0:00.333  +0:00.333", "Floor 1  0:01.500  +0:01.166"]);
+    }
+
+    #[test]
+    fn go_to_level_keeps_enemies_across_levels() {
+        let enemy = EnemyValues {
+            behaviour: EnemyBehaviour::Random,
+            animations: test_animations(),
+            attack: 1,
+            speed: 3.0,
+            health_points: 20,
+            hit_wait: 12,
+            bounding_box: BoundingBox::Full {width: TILE_SIZE, height: TILE_SIZE},
+        };
+        let mut levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+            test_level(Stairs::ToPrevLevel {id: 1}, TilePos {row: 2, col: 1}),
+        ];
+        for level in &mut levels {
+            level.world.create_entity()
+                .with(Position(TilePos {row: 2, col: 7}.center(TILE_SIZE as i32)))
+                .with(EnemySpawn {probability: 1.0, enemy: enemy.clone()})
+                .build();
+        }
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        let first = enemy_states(&mut screen.levels[0]);
+        assert_eq!(first.len(), 1);
+
+        // Skips straight past the second level, arriving beside the staircase back up
+        screen.go_to_level(2);
+        assert_eq!(screen.current_level_index(), 2);
+        let Position(pos) = screen.current_level().player_components().position;
+        assert_eq!(pos, TilePos {row: 1, col: 1}.center(TILE_SIZE as i32));
+        let third = enemy_states(&mut screen.levels[2]);
+        assert_eq!(third.len(), 1);
+        // Levels that were skipped are never spawned
+        assert_eq!(enemy_states(&mut screen.levels[1]), Vec::<String>::new());
+
+        // Going up and back down leaves every level's enemies exactly as they were
+        screen.go_to_level(0);
+        let Position(pos) = screen.current_level().player_components().position;
+        let GridSize {rows, cols} = map_size();
+        let room = TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows, cols});
+        assert_eq!(pos, room.center_tile().center(TILE_SIZE as i32));
+        screen.go_to_level(2);
+        assert_eq!(enemy_states(&mut screen.levels[0]), first);
+        assert_eq!(enemy_states(&mut screen.levels[2]), third);
+        // Only the skipped level still has the spawn point it was generated with
+        let spawn_points: Vec<_> = screen.levels.iter_mut()
+            .map(|level| level.world_mut().read_storage::<EnemySpawn>().join().count())
+            .collect();
+        assert_eq!(spawn_points, &[0, 1, 0]);
+    }
+
+    #[test]
+    fn go_to_missing_level_does_nothing() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        // Clear the notification of the first floor
+        screen.notifications.dispatch(FramesElapsed(Notification::DURATION));
+        let Position(before) = screen.current_level().player_components().position;
+
+        screen.go_to_level(2);
+        assert_eq!(screen.current_level_index(), 0);
+        let Position(pos) = screen.current_level().player_components().position;
+        assert_eq!(pos, before);
+        assert_eq!(screen.notifications.current().map(|notification| &*notification.text), Some("There is no floor 3"));
+    }
+
+    #[test]
+    fn player_placed_beside_matching_staircase() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 3}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 3}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.enter_next_level(3);
+        let Position(pos) = screen.current_level().player_components().position;
+        let beside_stairs = TilePos {row: 1, col: 1}.center(TILE_SIZE as i32);
+        assert_eq!(pos, beside_stairs);
+    }
+
+    #[test]
+    fn missing_staircase_falls_back_to_room_center() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            // Does not match the staircase that the player takes
+            test_level(Stairs::ToPrevLevel {id: 1}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.enter_next_level(0);
+        let Position(pos) = screen.current_level().player_components().position;
+        let GridSize {rows, cols} = map_size();
+        let room = TileRect::new(TilePos {row: 0, col: 0}, GridSize {rows, cols});
+        assert_eq!(pos, room.center_tile().center(TILE_SIZE as i32));
+    }
+
+    #[test]
+    fn escape_carried_back_up_through_levels() {
+        let levels = vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.enter_next_level(0);
+        assert_eq!(screen.current_level().run_phase(), RunPhase::Descending);
+
+        let mut phase = screen.current_level().run_phase();
+        assert!(phase.begin_escape());
+        screen.levels[1].update_run_phase(phase);
+
+        // The level above finds out that the player is escaping once they return to it
+        assert_eq!(screen.levels[0].run_phase(), RunPhase::Descending);
+        screen.enter_prev_level(0);
+        assert_eq!(screen.current_level().run_phase(), RunPhase::Escaping);
+    }
+
+    #[test]
+    fn ghost_track_follows_replayed_inputs() {
+        let new_screen = || GameScreen::new(test_player(), vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+            test_level(Stairs::ToPrevLevel {id: 0}, TilePos {row: 2, col: 1}),
+        ], &Profile::default(), 30);
+        let inputs: Vec<_> = (0..40).map(|frame| RecordedInput {
+            frames_elapsed: 1,
+            events: match frame {
+                5 => vec![Event::KeyDown(Key::RightArrow)],
+                25 => vec![Event::KeyUp(Key::RightArrow)],
+                _ => Vec::new(),
+            },
+        }).collect();
+
+        let mut reports = Vec::new();
+        let track = GhostTrack::extract(new_screen(), &inputs, |done, total| reports.push((done, total)));
+        assert_eq!(track.len(), inputs.len());
+        assert_eq!(reports.first(), Some(&(0, inputs.len())));
+        assert_eq!(reports.last(), Some(&(inputs.len(), inputs.len())));
+
+        // Playing the same inputs live keeps the player right on top of the ghost
+        let mut screen = new_screen();
+        screen.set_ghost(GhostRun::new(track, TextureId::placeholder(0)));
+        for input in &inputs {
+            screen.dispatch(FramesElapsed(input.frames_elapsed), input.events.clone());
+            let Position(pos) = screen.current_level().player_components().position;
+            let ghost = screen.ghost.as_ref().unwrap().visible_on(screen.current_level_index());
+            assert_eq!(ghost.map(|ghost| ghost.pos), Some(pos));
+        }
+        // The player actually moved
+        assert_ne!(screen.current_level().player_components().position.0, test_player().position.0);
+    }
+
+    #[test]
+    fn recorded_inputs_play_back_the_run() {
+        let new_screen = || GameScreen::new(test_player(), vec![
+            test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10}),
+        ], &Profile::default(), 30);
+
+        let mut screen = new_screen();
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::RightArrow)]);
+        screen.dispatch(FramesElapsed(2), Vec::new());
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::RightArrow), Event::KeyDown(Key::DownArrow)]);
+        for _ in 0..10 {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        let inputs = screen.recorded_inputs();
+        assert_eq!(inputs.len(), 13);
+        assert_eq!(inputs[1], RecordedInput {frames_elapsed: 2, events: Vec::new()});
+
+        let track = GhostTrack::extract(new_screen(), inputs, |_, _| {});
+        let mut ghost = GhostRun::new(track, TextureId::placeholder(0));
+        for _ in inputs {
+            ghost.step();
+        }
+        let Position(pos) = screen.current_level().player_components().position;
+        assert_eq!(ghost.current().map(|frame| frame.pos), Some(pos));
+        assert_ne!(pos, test_player().position.0);
+    }
+
+    #[test]
+    fn interruption_pauses_until_acknowledged() {
+        let levels = vec![test_level(Stairs::ToNextLevel {id: 0}, TilePos {row: 2, col: 10})];
+        let mut screen = GameScreen::new(test_player(), levels, &Profile::default(), 30);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::RightArrow)]);
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        let snapshot = |screen: &GameScreen<'_, '_>| (
+            format!("{:?}", screen.current_level().player_components()),
+            screen.current_level().run_stats(),
+            screen.play_clock(),
+            screen.current_level().explored_tiles(),
+        );
+        let before = snapshot(&screen);
+
+        screen.interrupt(InterruptEvent::SaveFailed {
+            what: "profile",
+            path: "profile.txt".to_string(),
+            error: "disk full".to_string(),
+        });
+        assert!(screen.is_interrupted());
+        assert!(screen.shows_map_key());
+        // Holding the arrow down does nothing while paused and releasing a key that was pressed
+        // before the pause does not acknowledge anything
+        for _ in 0..10 {
+            screen.dispatch(FramesElapsed(1), Vec::new());
+        }
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::A), Event::KeyDown(Key::B)]);
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyDown(Key::A)]);
+        assert!(screen.is_interrupted());
+        assert_eq!(snapshot(&screen), before);
+
+        screen.dispatch(FramesElapsed(1), vec![Event::KeyUp(Key::A), Event::KeyUp(Key::RightArrow)]);
+        assert!(!screen.is_interrupted());
+        assert!(!screen.shows_map_key());
+        // Exactly the same as before the interruption
+        assert_eq!(snapshot(&screen), before);
+
+        // The arrow released during the pause stops the player once the game continues
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        let Position(stopped) = screen.current_level().player_components().position;
+        screen.dispatch(FramesElapsed(1), Vec::new());
+        assert_eq!(screen.current_level().player_components().position.0, stopped);
+        assert_eq!(screen.play_clock().frames(), before.2.frames() + 2);
     }
 }
@@ -0,0 +1,163 @@
+//! A translucent copy of the player that retraces a previous run on the same map
+
+use sdl2::rect::Point;
+
+use crate::assets::{SpriteId, TextureId};
+use crate::components::{Position, Sprite};
+use crate::resources::{Event, FramesElapsed};
+
+use super::GameScreen;
+
+/// The most frames that are extracted from a recorded run. Keeps extraction from taking too long
+/// on very long runs. The ghost simply disappears once it reaches the end of its track.
+pub const MAX_GHOST_FRAMES: usize = 30 * 60 * 30;
+/// The number of frames between each report of the progress of an extraction
+const PROGRESS_INTERVAL: usize = 300;
+
+/// The input given to the game during a single frame of a recorded run
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedInput {
+    pub frames_elapsed: usize,
+    pub events: Vec<Event>,
+}
+
+/// Where the player was during a single frame of a recorded run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GhostFrame {
+    /// The index of the level that the player was on
+    pub level: usize,
+    pub pos: Point,
+    pub sprite: SpriteId,
+}
+
+/// The position of the player during every frame of a recorded run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GhostTrack {
+    frames: Vec<GhostFrame>,
+}
+
+impl GhostTrack {
+    /// Replays the given inputs on a game that has not been played yet and records where the
+    /// player is after each frame. The game should be generated from the same map key as the
+    /// recorded run and is discarded afterwards.
+    ///
+    /// The progress callback is given the number of frames replayed so far and the total number
+    /// of frames that will be replayed. Extraction stops early if the player is defeated.
+    pub fn extract(
+        mut screen: GameScreen<'_, '_>,
+        inputs: &[RecordedInput],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Self {
+        let total = inputs.len().min(MAX_GHOST_FRAMES);
+        let mut frames = Vec::with_capacity(total);
+        for (i, input) in inputs.iter().take(total).enumerate() {
+            if i % PROGRESS_INTERVAL == 0 {
+                progress(i, total);
+            }
+
+            // Sounds are not needed since nothing is ever heard from this game
+            screen.dispatch(FramesElapsed(input.frames_elapsed), input.events.clone());
+            if screen.is_game_over() {
+                break;
+            }
+
+            let player = screen.current_level().player_components();
+            let Position(pos) = player.position;
+            let Sprite(sprite) = player.sprite;
+            frames.push(GhostFrame {level: screen.current_level_index(), pos, sprite});
+        }
+        progress(frames.len(), total);
+
+        Self {frames}
+    }
+
+    /// Returns the number of frames in the track
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// Plays back a track alongside the live game, one frame of the track for each frame of the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GhostRun {
+    track: GhostTrack,
+    /// A copy of the texture of the player with a lowered alpha so that the ghost is translucent
+    texture: TextureId,
+    /// The number of frames of the track that have been played so far
+    played: usize,
+}
+
+impl GhostRun {
+    pub fn new(track: GhostTrack, texture: TextureId) -> Self {
+        Self {track, texture, played: 0}
+    }
+
+    /// Moves on to the next frame of the track
+    pub fn step(&mut self) {
+        self.played = (self.played + 1).min(self.track.len() + 1);
+    }
+
+    /// Returns the frame of the track that is currently shown or None if the track has ended.
+    /// The first frame is shown until the first step so that the ghost starts with the player.
+    pub fn current(&self) -> Option<&GhostFrame> {
+        self.track.frames.get(self.played.saturating_sub(1))
+    }
+
+    /// Returns the position and sprite of the ghost if it should be shown on the given level
+    pub fn visible_on(&self, level: usize) -> Option<GhostSprite> {
+        self.current()
+            .filter(|frame| frame.level == level)
+            .map(|frame| GhostSprite {pos: frame.pos, sprite: frame.sprite, texture: self.texture})
+    }
+}
+
+/// A sprite of the ghost to render over a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GhostSprite {
+    pub pos: Point,
+    pub sprite: SpriteId,
+    /// The texture to use instead of the texture that the sprite normally comes from
+    pub texture: TextureId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::Rect;
+
+    use crate::assets::{SpriteManager, SpriteImage};
+
+    fn frame(level: usize, x: i32, sprite: SpriteId) -> GhostFrame {
+        GhostFrame {level, pos: Point::new(x, 8), sprite}
+    }
+
+    #[test]
+    fn ghost_disappears_once_track_ends() {
+        let texture = TextureId::placeholder(3);
+        let sprite = SpriteManager::default().add(SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, 16, 16)));
+        let track = GhostTrack {frames: vec![frame(0, 8, sprite), frame(0, 10, sprite), frame(1, 12, sprite)]};
+        let mut ghost = GhostRun::new(track, texture);
+
+        assert_eq!(ghost.visible_on(0), Some(GhostSprite {pos: Point::new(8, 8), sprite, texture}));
+        ghost.step();
+        assert_eq!(ghost.current(), Some(&frame(0, 8, sprite)));
+        ghost.step();
+        assert_eq!(ghost.current(), Some(&frame(0, 10, sprite)));
+        // Only shown on the level that the player was on at the time
+        ghost.step();
+        assert_eq!(ghost.visible_on(0), None);
+        assert!(ghost.visible_on(1).is_some());
+
+        // Every frame after the end of the track is skipped
+        for _ in 0..5 {
+            ghost.step();
+            assert_eq!(ghost.current(), None);
+            assert_eq!(ghost.visible_on(1), None);
+        }
+    }
+}
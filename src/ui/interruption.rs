@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+
+use sdl2::render::{RenderTarget, BlendMode};
+
+use crate::interrupts::InterruptEvent;
+use crate::resources::{Event, Key};
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// How dark the game is behind the message, from 0 (not at all) to 255 (black)
+const BACKGROUND_DARKNESS: u8 = 200;
+
+/// The distance (in px) between the top of the screen and the top of the title
+const TITLE_TOP: u32 = 60;
+/// The height of the title
+const TITLE_HEIGHT: f32 = 20.0;
+/// The height of each line of the message below the title
+const LINE_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of one line and the top of the next line
+const LINE_SPACING: u32 = 14;
+
+/// The problems that the game is paused for. Each one is shown until the player acknowledges it.
+#[derive(Debug, Default)]
+pub struct Interruption {
+    /// The problem currently being shown is at the front
+    pending: VecDeque<InterruptEvent>,
+    /// True if A or Start was pressed while the current problem was shown
+    acknowledging: bool,
+}
+
+impl Interruption {
+    /// Adds a problem to be shown after every problem already shown
+    pub fn push(&mut self, event: InterruptEvent) {
+        self.pending.push_back(event);
+    }
+
+    /// Returns the problem currently being shown, if any
+    pub fn current(&self) -> Option<&InterruptEvent> {
+        self.pending.front()
+    }
+
+    /// Returns true if there is a problem that has not been acknowledged yet
+    pub fn is_active(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Acknowledges the current problem once A or Start is pressed and released. Those keys are
+    /// never passed on so that acknowledging does not also do something in the game. Clicks are
+    /// ignored. Every other event is returned so that the game still finds out about any key that
+    /// was pressed or released while it was paused.
+    pub fn dispatch(&mut self, events: Vec<Event>) -> Vec<Event> {
+        let mut passed_on = Vec::new();
+        for event in events {
+            if !self.is_active() {
+                passed_on.push(event);
+                continue;
+            }
+
+            match event {
+                Event::KeyDown(Key::A) | Event::KeyDown(Key::Start) => self.acknowledging = true,
+                // A release without a press was held down from before the problem was shown
+                Event::KeyUp(Key::A) | Event::KeyUp(Key::Start) => if self.acknowledging {
+                    self.acknowledging = false;
+                    self.pending.pop_front();
+                },
+                // Clicks have no release to pair up with, so they are simply dropped
+                Event::PointerAttack(_) | Event::PointerInteract(_) => {},
+                _ => passed_on.push(event),
+            }
+        }
+        passed_on
+    }
+
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        let event = match self.current() {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+
+        ctx.canvas.set_blend_mode(BlendMode::Blend);
+        ctx.canvas.set_draw_color((0, 0, 0, BACKGROUND_DARKNESS));
+        ctx.canvas.fill_rect(None).map_err(SDLError::Sdl)?;
+
+        let white = (255, 255, 255, 255);
+        Text::new(&ctx.font, "Paused", TITLE_HEIGHT)
+            .render(ctx.canvas, white, TextLayout::CenteredAtTop(TITLE_TOP))?;
+
+        let mut top = TITLE_TOP + TITLE_HEIGHT as u32 + LINE_SPACING;
+        for line in event.lines() {
+            Text::new(&ctx.font, line, LINE_HEIGHT)
+                .render(ctx.canvas, white, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+
+        top += LINE_SPACING;
+        Text::new(&ctx.font, "Press A to continue", LINE_HEIGHT)
+            .render(ctx.canvas, (255, 215, 0, 255), TextLayout::CenteredAtTop(top))?;
+
+        Ok(())
+    }
+}
@@ -1,29 +1,37 @@
+use std::mem;
 use std::path::Path;
 
 use sdl2::{
     rect::Point,
     render::RenderTarget,
 };
-use specs::{Dispatcher, World, Join, Entity, Entities, ReadStorage};
+use specs::{World, Join, Entity, Entities, ReadExpect, ReadStorage};
 use component_group::ComponentGroup;
 
-use crate::generator::GenLevel;
-use crate::map::FloorMap;
-use crate::components::{PlayerComponents, Player, Position, Stairs};
-use crate::resources::{FramesElapsed, Event, ChangeGameState, GameState, ActionQueue, EventQueue};
+use rand::rngs::StdRng;
+
+use crate::generator::{GenLevel, spawn_enemies};
+use crate::map::{FloorMap, RoomId, RoomUid, RoomType};
+use crate::systems::LevelDispatcher;
+use crate::components::{PlayerComponents, Player, Position, Stairs, HealthPoints, Item, Inventory, Merchant, Chest, MapFragment, Cage, PurchaseError};
+use crate::resources::{FramesElapsed, Event, GameStateMachine, ActionQueue, EventQueue, SoundQueue, NotificationQueue, GameEvents, RunStats, RunPhase, PlayClock, FloorStats, ScreenShake, RoomTracker, LevelUids, CameraOffset, TutorialState};
 
 use super::debug;
+use super::describe::describe_surroundings;
 use super::renderer::{RenderContext, render_player_visible};
-use super::SDLError;
+use super::tutorial::render_tutorial_prompt;
+use super::boss_health::{boss_in_current_room, render_boss_health_bar};
+use super::{SDLError, GhostSprite};
 
 pub struct LevelScreen<'a, 'b> {
-    dispatcher: Dispatcher<'a, 'b>,
+    dispatcher: LevelDispatcher<'a, 'b>,
     world: World,
+    spawn_rng: StdRng,
 }
 
 impl<'a, 'b> From<GenLevel<'a, 'b>> for LevelScreen<'a, 'b> {
-    fn from(GenLevel {dispatcher, world}: GenLevel<'a, 'b>) -> Self {
-        Self {dispatcher, world}
+    fn from(GenLevel {dispatcher, world, spawn_rng}: GenLevel<'a, 'b>) -> Self {
+        Self {dispatcher, world, spawn_rng}
     }
 }
 
@@ -34,36 +42,75 @@ impl<'a, 'b> LevelScreen<'a, 'b> {
             .expect("bug: expected player to be in world").1
     }
 
-    /// Finds the position next to the ToNextLevel gate with the given ID
-    pub fn find_to_next_level_adjacent(&self, gate_id: usize) -> Point {
-        let (positions, stairs) = self.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
-        let pos = (&positions, &stairs).join().find_map(|(&Position(pos), stairs)| match stairs {
-            Stairs::ToNextLevel {id} if *id == gate_id => Some(pos),
-            _ => None,
-        }).expect("bug: could not find next level gate with matching ID");
+    /// Returns true if the player on this level has run out of health
+    pub fn is_player_defeated(&self) -> bool {
+        let (players, healths) = self.world.system_data::<(ReadStorage<'_, Player>, ReadStorage<'_, HealthPoints>)>();
+        (&players, &healths).join().any(|(_, &HealthPoints(health))| health == 0)
+    }
 
-        // Find the empty position adjacent to this staircase. There should only be one.
-        let map = self.world.read_resource::<FloorMap>();
-        let tile_pos = map.world_to_tile_pos(pos);
-        let empty = map.grid().adjacent_positions(tile_pos).find(|&p| !map.grid().get(p).is_wall())
-            .expect("bug: should be one empty position adjacent to a staircase");
-        empty.center(map.tile_size() as i32)
+    /// Stops the screen from shaking. Used when the level is paused so the screen does not stay
+    /// moved to one side.
+    pub fn stop_screen_shake(&mut self) {
+        self.world.write_resource::<ScreenShake>().stop();
     }
 
-    /// Finds the position next to the ToPrevLevel gate with the given ID
+    /// Finds the position next to the ToNextLevel gate with the given ID. If there is no such
+    /// gate, falls back to the position that the player would start at on this level.
+    pub fn find_to_next_level_adjacent(&self, gate_id: usize) -> Point {
+        self.find_stairs_adjacent(|stairs| match stairs {
+            Stairs::ToNextLevel {id} => *id == gate_id,
+            Stairs::ToPrevLevel {..} => false,
+        }).unwrap_or_else(|| {
+            error!("bug: could not find next level gate with ID {}", gate_id);
+            self.fallback_player_position()
+        })
+    }
+
+    /// Finds the position next to the ToPrevLevel gate with the given ID. If there is no such
+    /// gate, falls back to the position that the player would start at on this level.
     pub fn find_to_prev_level_adjacent(&self, gate_id: usize) -> Point {
-        let (positions, stairs) = self.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
-        let pos = (&positions, &stairs).join().find_map(|(&Position(pos), stairs)| match stairs {
-            Stairs::ToPrevLevel {id} if *id == gate_id => Some(pos),
-            _ => None,
-        }).expect("bug: could not find previous level gate with matching ID");
+        self.find_stairs_adjacent(|stairs| match stairs {
+            Stairs::ToPrevLevel {id} => *id == gate_id,
+            Stairs::ToNextLevel {..} => false,
+        }).unwrap_or_else(|| {
+            error!("bug: could not find previous level gate with ID {}", gate_id);
+            self.fallback_player_position()
+        })
+    }
+
+    /// Finds the position next to the first ToPrevLevel gate, where the player arrives from the
+    /// previous level. Levels without such a gate (e.g. the first level) fall back to the position
+    /// that the player would start at on this level.
+    pub fn find_level_entrance(&self) -> Point {
+        self.find_stairs_adjacent(|stairs| match stairs {
+            Stairs::ToPrevLevel {..} => true,
+            Stairs::ToNextLevel {..} => false,
+        }).unwrap_or_else(|| self.fallback_player_position())
+    }
+
+    /// Finds the center of the open tile in front of the first staircase that matches the given
+    /// predicate. Staircases are placed in walls, so there should only be one open tile beside
+    /// each of them.
+    fn find_stairs_adjacent(&self, is_match: impl Fn(&Stairs) -> bool) -> Option<Point> {
+        let (map, positions, stairs) = self.world.system_data::<(ReadExpect<'_, FloorMap>, ReadStorage<'_, Position>, ReadStorage<'_, Stairs>)>();
+        let pos = (&positions, &stairs).join()
+            .find(|(_, stairs)| is_match(stairs))
+            .map(|(&Position(pos), _)| pos)?;
 
-        // Find the empty position adjacent to this staircase. There should only be one.
-        let map = self.world.read_resource::<FloorMap>();
         let tile_pos = map.world_to_tile_pos(pos);
-        let empty = map.grid().adjacent_positions(tile_pos).find(|&p| !map.grid().get(p).is_wall())
-            .expect("bug: should be one empty position adjacent to a staircase");
-        empty.center(map.tile_size() as i32)
+        let open = map.grid().adjacent_positions(tile_pos).find(|&p| map.grid().get(p).is_floor())?;
+        Some(open.center(map.tile_size() as i32))
+    }
+
+    /// Returns the center of the player start room if this level has one or the center of the
+    /// first room otherwise. Used when the player cannot be placed beside a staircase.
+    fn fallback_player_position(&self) -> Point {
+        let map = self.world.read_resource::<FloorMap>();
+        let room = map.rooms().find(|(_, room)| room.room_type() == RoomType::PlayerStart)
+            .or_else(|| map.rooms().next())
+            .map(|(_, room)| *room.boundary())
+            .expect("bug: every level should have at least one room");
+        room.center_tile().center(map.tile_size() as i32)
     }
 
     /// Updates the player entity on this level
@@ -75,30 +122,144 @@ impl<'a, 'b> LevelScreen<'a, 'b> {
         }
     }
 
+    /// Starts the tutorial on this level. It is shown whenever the player is in the start room
+    /// until every step has been completed or the tutorial is skipped.
+    pub fn start_tutorial(&mut self) {
+        *self.world.write_resource() = TutorialState::start();
+    }
+
+    /// Ends the tutorial on this level, if it was still going, so that it is never shown again
+    pub fn end_tutorial(&mut self) {
+        self.world.write_resource::<TutorialState>().skip();
+    }
+
+    /// Returns the statistics of the run so far
+    pub fn run_stats(&self) -> RunStats {
+        self.world.read_resource::<RunStats>().clone()
+    }
+
+    /// Ends the current visit to this floor and returns what was accomplished during it
+    pub fn finish_floor(&mut self) -> FloorStats {
+        self.world.write_resource::<RunStats>().finish_floor()
+    }
+
+    /// Returns the number of chests, map fragments and caged prisoners on this floor that the
+    /// player has not found yet
+    pub fn secrets_remaining(&self) -> usize {
+        let chests = self.world.read_storage::<Chest>();
+        let unopened = chests.join().filter(|chest| matches!(chest, Chest::Item(_))).count();
+        let fragments = self.world.read_storage::<MapFragment>().join().count();
+        let prisoners = self.world.read_storage::<Cage>().join().count();
+        unopened + fragments + prisoners
+    }
+
+    /// Replaces the statistics of the run on this level
+    pub fn update_run_stats(&mut self, stats: RunStats) {
+        *self.world.write_resource() = stats;
+    }
+
+    /// Returns which way the player is headed during the run
+    pub fn run_phase(&self) -> RunPhase {
+        *self.world.read_resource::<RunPhase>()
+    }
+
+    /// Replaces the phase of the run on this level
+    pub fn update_run_phase(&mut self, phase: RunPhase) {
+        *self.world.write_resource() = phase;
+    }
+
+    /// Returns how long the player has spent playing during the run
+    pub fn play_clock(&self) -> PlayClock {
+        *self.world.read_resource::<PlayClock>()
+    }
+
+    /// Replaces the play time of the run on this level
+    pub fn update_play_clock(&mut self, clock: PlayClock) {
+        *self.world.write_resource() = clock;
+    }
+
+    /// Returns the items that the given merchant has for sale and their prices (in gold)
+    pub fn merchant_stock(&self, merchant: Entity) -> Vec<(Item, u32)> {
+        self.world.read_storage::<Merchant>().get(merchant)
+            .map(|merchant| merchant.stock.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the amount of gold carried by the player on this level
+    pub fn player_gold(&self) -> u32 {
+        let (players, inventories) = self.world.system_data::<(ReadStorage<'_, Player>, ReadStorage<'_, Inventory>)>();
+        (&players, &inventories).join().map(|(_, inventory)| inventory.gold()).sum()
+    }
+
+    /// Has the player buy the item at the given position in the stock of the given merchant
+    pub fn buy(&mut self, merchant: Entity, index: usize) -> Result<Item, PurchaseError> {
+        let player = self.player_entity().expect("bug: expected player to be in world");
+        let mut merchants = self.world.write_storage::<Merchant>();
+        let mut inventories = self.world.write_storage::<Inventory>();
+        let merchant = match merchants.get_mut(merchant) {
+            Some(merchant) => merchant,
+            None => return Err(PurchaseError::SoldOut),
+        };
+        let inventory = inventories.entry(player)
+            .expect("bug: unable to get inventory of player")
+            .or_insert_with(Inventory::default);
+        merchant.sell(index, inventory)
+    }
+
+    /// Spawns the enemies of this level. Only spawns enemies the first time this is called, so it
+    /// is safe to call every time the player enters the level.
+    pub fn spawn_enemies(&mut self) {
+        spawn_enemies(&mut self.world, &mut self.spawn_rng);
+        self.world.maintain();
+    }
+
     /// Gets the entity of the player on this level or None if a player hasn't been created yet
     fn player_entity(&self) -> Option<Entity> {
         let (entities, players) = self.world.system_data::<(Entities<'_>, ReadStorage<'_, Player>)>();
         let mut player_iter = (&entities, &players).join();
         let player_entity = player_iter.next().map(|(entity, _)| entity);
-        player_iter.next().map(|_| unreachable!("bug: more than one player in world"));
+        if player_iter.next().is_some() {
+            unreachable!("bug: more than one player in world");
+        }
         player_entity
     }
 
-    /// Dispatch the given events and update the state based on the frames that have elapsed
-    pub fn dispatch(&mut self, frames_elapsed: FramesElapsed, events: Vec<Event>) -> Option<GameState> {
+    /// Dispatch the given events and update the state based on the frames that have elapsed. The
+    /// systems change the given game state through its transitions (e.g. when the player takes
+    /// the stairs).
+    pub fn dispatch(&mut self, frames_elapsed: FramesElapsed, events: Vec<Event>, state: &mut GameStateMachine) {
         //NOTE: All resources here must already be added when the world is created
         *self.world.write_resource() = frames_elapsed;
-        *self.world.write_resource() = ChangeGameState::default();
+        *self.world.write_resource() = *state;
         *self.world.write_resource() = ActionQueue::default();
+        *self.world.write_resource() = SoundQueue::default();
+        *self.world.write_resource() = NotificationQueue::default();
+        *self.world.write_resource() = GameEvents::default();
         *self.world.write_resource() = EventQueue(events);
+        self.world.write_resource::<RunStats>().floor.frames += frames_elapsed.0;
+        self.world.write_resource::<PlayClock>().advance(frames_elapsed.0);
 
-        self.dispatcher.dispatch(&mut self.world.res);
+        self.dispatcher.dispatch(&self.world.res);
 
         // Register any updates
         self.world.maintain();
 
-        // Return any changes of game state that have been requested
-        self.world.read_resource::<ChangeGameState>().get()
+        *state = *self.world.read_resource::<GameStateMachine>();
+    }
+
+    /// Takes the sound effects queued during the last dispatch
+    pub fn take_sounds(&mut self) -> SoundQueue {
+        mem::take(&mut *self.world.write_resource::<SoundQueue>())
+    }
+
+    /// Takes the notifications queued during the last dispatch
+    pub fn take_notifications(&mut self) -> NotificationQueue {
+        mem::take(&mut *self.world.write_resource::<NotificationQueue>())
+    }
+
+    /// Takes the game events that occurred during the last dispatch
+    pub fn take_game_events(&mut self) -> GameEvents {
+        mem::take(&mut *self.world.write_resource::<GameEvents>())
     }
 
     /// Render the entire state of the level (the entire map) to the given filename.
@@ -109,7 +270,58 @@ impl<'a, 'b> LevelScreen<'a, 'b> {
         debug::render_to_file(&map, &self.world, path)
     }
 
-    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
-        render_player_visible(self.world.system_data(), ctx)
+    /// Returns the tiles of this level that have been explored so far
+    #[cfg(test)]
+    pub fn explored_tiles(&self) -> crate::resources::ExploredTiles {
+        self.world.read_resource::<crate::resources::ExploredTiles>().clone()
+    }
+
+    /// Returns the world of this level so that tests can inspect and modify its entities
+    #[cfg(test)]
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Returns a plain-text description of everything the player can currently see
+    pub fn describe_surroundings(&self) -> String {
+        describe_surroundings(&self.world)
+    }
+
+    /// Returns the room that the player is currently considered to be in, if any
+    pub fn current_room(&self) -> Option<(RoomId, RoomType)> {
+        let map = self.world.read_resource::<FloorMap>();
+        self.world.read_resource::<RoomTracker>().current()
+            .map(|room_id| (room_id, map.room(room_id).room_type()))
+    }
+
+    /// Returns the uid and type of the room that the player is currently in, if any
+    pub fn current_room_uid(&self) -> Option<(RoomUid, RoomType)> {
+        let uids = self.world.read_resource::<LevelUids>();
+        self.current_room().map(|(room_id, room_type)| (uids.room_uid(room_id), room_type))
+    }
+
+    /// Returns the position on the map of the top left corner of the screen the last time this
+    /// level was rendered, if it has been rendered
+    pub fn camera_offset(&self) -> Option<Point> {
+        self.world.read_resource::<CameraOffset>().0
+    }
+
+    /// Renders the part of the level visible to the player along with the given ghost, if any
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>, ghost: Option<GhostSprite>) -> Result<(), SDLError> {
+        render_player_visible(self.world.system_data(), ctx, ghost)?;
+
+        let step = self.world.read_resource::<TutorialState>().visible_step();
+        if let (Some(step), Some(camera_offset)) = (step, self.camera_offset()) {
+            let (positions, players) = self.world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Player>)>();
+            if let Some((&Position(pos), _)) = (&positions, &players).join().next() {
+                render_tutorial_prompt(ctx, step, pos - camera_offset)?;
+            }
+        }
+
+        if let Some((health, max_health)) = boss_in_current_room(&self.world) {
+            render_boss_health_bar(ctx, health, max_health)?;
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,71 @@
+use rusttype::Font;
+use sdl2::render::RenderTarget;
+
+use crate::generator::MapKey;
+
+use super::text::{Text, TextLayout, wrap_text};
+use super::{SDLError, RenderContext};
+
+/// The height of each line of the map key
+const KEY_LINE_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of one line and the top of the next line
+const KEY_LINE_SPACING: u32 = 10;
+/// The distance (in px) between the sides of the screen and the text
+const SIDE_MARGIN: u32 = 4;
+/// The distance (in px) between the bottom of the last line and the bottom of the screen
+const BOTTOM_MARGIN: u32 = 4;
+
+/// Returns the lines of the footer: the map key, wrapped to fit the given screen width (in px),
+/// followed by how to copy it
+pub fn map_key_lines(font: &Font, key: MapKey, screen_width: u32) -> Vec<String> {
+    let max_width = screen_width.saturating_sub(2 * SIDE_MARGIN) as f32;
+    let mut lines = wrap_text(font, &format!("Map key: {}", key), KEY_LINE_HEIGHT, max_width);
+    lines.push("Ctrl+C: copy map key".to_string());
+    lines
+}
+
+/// Draws the map key at the bottom of the screen so that players can share or replay the map
+/// even when they never see the standard output of the game
+pub fn render_map_key_footer<T: RenderTarget>(ctx: &mut RenderContext<T>, key: MapKey) -> Result<(), SDLError> {
+    let (screen_width, screen_height) = ctx.canvas.logical_size();
+    let lines = map_key_lines(&ctx.font, key, screen_width);
+
+    let hint = lines.len() - 1;
+    let height = lines.len() as u32 * KEY_LINE_SPACING;
+    let mut top = screen_height.saturating_sub(height + BOTTOM_MARGIN);
+    for (i, line) in lines.into_iter().enumerate() {
+        let color = if i == hint { (150, 150, 150, 255) } else { (255, 255, 255, 255) };
+        Text::new(&ctx.font, line, KEY_LINE_HEIGHT)
+            .render(ctx.canvas, color, TextLayout::CenteredAtTop(top))?;
+        top += KEY_LINE_SPACING;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use crate::ui::load_font;
+
+    #[test]
+    fn map_key_wrapped_to_screen_width() {
+        let font = load_font().unwrap();
+        let key: MapKey = StdRng::seed_from_u64(7).gen();
+
+        for &screen_width in &[320, 160] {
+            let lines = map_key_lines(&font, key, screen_width);
+            let (hint, key_lines) = lines.split_last().unwrap();
+            assert_eq!(hint, "Ctrl+C: copy map key");
+            // The whole key is shown, no matter how many lines it takes
+            assert_eq!(key_lines.concat().replace(' ', ""), format!("Mapkey:{}", key));
+            for line in &lines {
+                let width = Text::new(&font, line, KEY_LINE_HEIGHT).width();
+                assert!(width <= (screen_width - 2 * SIDE_MARGIN) as f32, "{:?} is too wide", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+
+use sdl2::render::RenderTarget;
+
+use crate::resources::{FramesElapsed, Notification, NotificationQueue};
+
+use super::text::{Text, TextLayout, wrap_text};
+use super::{SDLError, RenderContext};
+
+/// The distance (in px) between the top of the screen and the top of the notification text
+const BANNER_TOP: u32 = 16;
+/// The height of the notification text
+const BANNER_TEXT_HEIGHT: f32 = 20.0;
+/// The distance (in px) between the top of one line of a long notification and the next line
+const BANNER_LINE_SPACING: u32 = 22;
+
+/// A banner near the top of the screen that shows notifications one at a time. Each notification
+/// fades out before the next one is shown.
+#[derive(Debug, Default)]
+pub struct NotificationBanner {
+    /// The notification currently being shown is at the front
+    pending: VecDeque<Notification>,
+}
+
+impl NotificationBanner {
+    /// Adds a notification to be shown after every notification already queued
+    pub fn push(&mut self, notification: Notification) {
+        self.pending.push_back(notification);
+    }
+
+    /// Adds every notification in the given queue, preserving their order
+    pub fn extend(&mut self, NotificationQueue(notifications): NotificationQueue) {
+        self.pending.extend(notifications);
+    }
+
+    /// Returns the notification currently being shown, if any
+    pub fn current(&self) -> Option<&Notification> {
+        self.pending.front()
+    }
+
+    /// Counts down the current notification and moves on to the next one once it is complete
+    pub fn dispatch(&mut self, FramesElapsed(frames_elapsed): FramesElapsed) {
+        if let Some(current) = self.pending.front_mut() {
+            current.frames_remaining = current.frames_remaining.saturating_sub(frames_elapsed);
+            if current.frames_remaining == 0 {
+                self.pending.pop_front();
+            }
+        }
+    }
+
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        let current = match self.current() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        // fade out gradually (linearly) as the notification goes on
+        let alpha = (current.frames_remaining * 255) / Notification::DURATION;
+        // Notifications that are too wide for the screen continue onto more lines
+        let (screen_width, _) = ctx.canvas.logical_size();
+        let mut top = BANNER_TOP;
+        for line in wrap_text(&ctx.font, &current.text, BANNER_TEXT_HEIGHT, screen_width as f32) {
+            Text::new(&ctx.font, line, BANNER_TEXT_HEIGHT)
+                .render(ctx.canvas, (255, 255, 255, alpha as u8), TextLayout::CenteredAtTop(top))?;
+            top += BANNER_LINE_SPACING;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_shown_sequentially() {
+        let mut banner = NotificationBanner::default();
+        assert_eq!(banner.current(), None);
+
+        banner.push(Notification::new("Floor 2"));
+        let mut queue = NotificationQueue::default();
+        queue.push("Challenge Room!");
+        queue.push("Found a key");
+        banner.extend(queue);
+
+        let mut shown = Vec::new();
+        let mut frames = 0;
+        while let Some(current) = banner.current() {
+            if shown.last() != Some(&current.text) {
+                shown.push(current.text.clone());
+            }
+            banner.dispatch(FramesElapsed(2));
+            frames += 2;
+        }
+
+        // Only one notification at a time, each for its full duration
+        assert_eq!(shown, &["Floor 2", "Challenge Room!", "Found a key"]);
+        assert_eq!(frames, 3 * Notification::DURATION);
+    }
+}
@@ -0,0 +1,107 @@
+use sdl2::rect::Point;
+
+/// Converts a position in the window (in px) to a position on the map. The game is drawn at its
+/// logical size and then scaled up to fit the window as closely as possible (keeping its aspect
+/// ratio) with bars added to either side if needed, just like SDL does when a canvas has a logical
+/// size. The logical size already accounts for the zoom of the game and the window size already
+/// accounts for DISPLAY_SCALE.
+///
+/// If `integer_scale` is true, the game is only ever scaled up by a whole number (see `Zoom`), so
+/// the bars may be bigger than they need to be.
+///
+/// `camera_offset` is the position on the map of the top left corner of the screen. Returns None
+/// if the position is on one of the bars instead of the game.
+pub fn window_to_world(
+    pos: Point,
+    (window_width, window_height): (u32, u32),
+    (logical_width, logical_height): (u32, u32),
+    integer_scale: bool,
+    camera_offset: Point,
+) -> Option<Point> {
+    if logical_width == 0 || logical_height == 0 {
+        return None;
+    }
+
+    let scale_x = window_width as f64 / logical_width as f64;
+    let scale_y = window_height as f64 / logical_height as f64;
+    let scale = scale_x.min(scale_y);
+    let scale = if integer_scale { scale.floor() } else { scale };
+    if scale <= 0.0 {
+        return None;
+    }
+
+    // The game is centered in the window
+    let bar_x = (window_width as f64 - logical_width as f64 * scale) / 2.0;
+    let bar_y = (window_height as f64 - logical_height as f64 * scale) / 2.0;
+    let x = ((pos.x() as f64 - bar_x) / scale).floor();
+    let y = ((pos.y() as f64 - bar_y) / scale).floor();
+    if x < 0.0 || y < 0.0 || x >= logical_width as f64 || y >= logical_height as f64 {
+        return None;
+    }
+
+    Some(camera_offset + Point::new(x as i32, y as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The logical size of the game in a 320x240 window with a zoom of 2
+    const LOGICAL_SIZE: (u32, u32) = (160, 120);
+
+    #[test]
+    fn scales_by_zoom_and_display_scale() {
+        let origin = Point::new(0, 0);
+        // Zoom only: every logical pixel is 2x2 window pixels
+        assert_eq!(window_to_world(Point::new(0, 0), (320, 240), LOGICAL_SIZE, false, origin), Some(Point::new(0, 0)));
+        assert_eq!(window_to_world(Point::new(1, 1), (320, 240), LOGICAL_SIZE, false, origin), Some(Point::new(0, 0)));
+        assert_eq!(window_to_world(Point::new(319, 239), (320, 240), LOGICAL_SIZE, false, origin), Some(Point::new(159, 119)));
+        assert_eq!(window_to_world(Point::new(160, 120), (320, 240), LOGICAL_SIZE, false, origin), Some(Point::new(80, 60)));
+
+        // DISPLAY_SCALE=1.5 makes the window 480x360, so every logical pixel is 3x3 window pixels
+        assert_eq!(window_to_world(Point::new(240, 180), (480, 360), LOGICAL_SIZE, false, origin), Some(Point::new(80, 60)));
+        assert_eq!(window_to_world(Point::new(5, 8), (480, 360), LOGICAL_SIZE, false, origin), Some(Point::new(1, 2)));
+        assert_eq!(window_to_world(Point::new(479, 359), (480, 360), LOGICAL_SIZE, false, origin), Some(Point::new(159, 119)));
+    }
+
+    #[test]
+    fn offset_by_camera() {
+        let camera = Point::new(200, -16);
+        assert_eq!(window_to_world(Point::new(0, 0), (320, 240), LOGICAL_SIZE, false, camera), Some(Point::new(200, -16)));
+        assert_eq!(window_to_world(Point::new(160, 120), (320, 240), LOGICAL_SIZE, false, camera), Some(Point::new(280, 44)));
+    }
+
+    #[test]
+    fn ignores_bars_around_resized_window() {
+        let origin = Point::new(0, 0);
+        // Wider than the game: 2x scale with 80px bars on the left and right
+        let wide = (480, 240);
+        assert_eq!(window_to_world(Point::new(79, 120), wide, LOGICAL_SIZE, false, origin), None);
+        assert_eq!(window_to_world(Point::new(80, 0), wide, LOGICAL_SIZE, false, origin), Some(Point::new(0, 0)));
+        assert_eq!(window_to_world(Point::new(399, 239), wide, LOGICAL_SIZE, false, origin), Some(Point::new(159, 119)));
+        assert_eq!(window_to_world(Point::new(400, 120), wide, LOGICAL_SIZE, false, origin), None);
+
+        // Taller than the game: 2x scale with 60px bars above and below
+        let tall = (320, 360);
+        assert_eq!(window_to_world(Point::new(160, 59), tall, LOGICAL_SIZE, false, origin), None);
+        assert_eq!(window_to_world(Point::new(160, 60), tall, LOGICAL_SIZE, false, origin), Some(Point::new(80, 0)));
+        assert_eq!(window_to_world(Point::new(160, 300), tall, LOGICAL_SIZE, false, origin), None);
+
+        // Minimized
+        assert_eq!(window_to_world(Point::new(0, 0), (0, 0), LOGICAL_SIZE, false, origin), None);
+    }
+
+    #[test]
+    fn integer_scale_leaves_bigger_bars() {
+        let origin = Point::new(0, 0);
+        // 2.5x would fit, but only 2x is allowed, leaving 40px bars on the left and right and
+        // 30px bars above and below
+        let window = (400, 300);
+        assert_eq!(window_to_world(Point::new(39, 150), window, LOGICAL_SIZE, true, origin), None);
+        assert_eq!(window_to_world(Point::new(40, 30), window, LOGICAL_SIZE, true, origin), Some(Point::new(0, 0)));
+        assert_eq!(window_to_world(Point::new(200, 150), window, LOGICAL_SIZE, true, origin), Some(Point::new(80, 60)));
+        assert_eq!(window_to_world(Point::new(359, 269), window, LOGICAL_SIZE, true, origin), Some(Point::new(159, 119)));
+        assert_eq!(window_to_world(Point::new(360, 150), window, LOGICAL_SIZE, true, origin), None);
+        assert_eq!(window_to_world(Point::new(200, 150), window, LOGICAL_SIZE, false, origin), Some(Point::new(80, 60)));
+    }
+}
@@ -1,52 +1,153 @@
 use std::cmp;
+use std::time::Duration;
 use std::iter::once;
 use std::collections::HashSet;
 
 use sdl2::{
     rect::{Point, Rect},
-    render::{Canvas, RenderTarget},
+    render::{Canvas, RenderTarget, BlendMode, Texture},
+    pixels::Color,
 };
 use rusttype::Font;
-use specs::{Join, ReadStorage, Resources, SystemData, Read};
+use specs::{Join, ReadStorage, Resources, SystemData, Read, Write};
 
-use crate::assets::{TextureManager, SpriteManager, SpriteImage};
-use crate::components::{Position, Sprite, CameraFocus, Door, Ghost};
-use crate::map::{FloorMap, TileGrid, Tile, TilePos};
-use crate::map_sprites::MapSprites;
-use super::{SDLError, Text, TextLayout};
+use crate::assets::{TextureManager, SpriteManager, SpriteImage, SpriteId, scale_to_tile_size};
+use crate::components::{
+    Position,
+    BoundingBox,
+    Sprite,
+    CameraFocus,
+    Door,
+    Ghost,
+    RenderLayer,
+    Discovered,
+    Invulnerable,
+    HealthPoints,
+    HealthBar,
+    AlertIndicator,
+    Player,
+    Enemy,
+    EnemySpawn,
+    FloatingText,
+    StatusEffects,
+    StatusKind,
+    Equipment,
+    WeaponKind,
+};
+use crate::resources::{ExploredTiles, ScreenShake, DecalBuffer, Decal, DecalKind, CameraOffset};
+use crate::systems::{find_visible_tiles, visibility_start};
+use crate::map::{FloorMap, GridSize, Tile, TilePos, RoomUid, RoomType};
+use crate::map_sprites::{MapSprites, WallSpriteAlternate};
+use super::{SDLError, Text, TextLayout, DigitGlyphs, GhostSprite, Camera};
+
+/// The opacity of the shadow drawn over tiles that have been explored but are not visible
+const EXPLORED_SHADOW_ALPHA: u8 = 128;
+/// The size (in px) of the health bars drawn above damaged entities
+const HEALTH_BAR_WIDTH: u32 = 12;
+const HEALTH_BAR_HEIGHT: u32 = 2;
+/// The space (in px) between a health bar and the top of the entity's bounding box
+const HEALTH_BAR_MARGIN: i32 = 2;
+/// The rectangles (x, y, width, height) that make up a splat of blood, relative to its center
+/// (in px at NATIVE_TILE_SIZE)
+const BLOOD_SPLAT: [(i32, i32, i32, i32); 3] = [(-3, -1, 6, 3), (-1, -3, 3, 2), (3, 2, 2, 2)];
+/// The rectangles (x, y, width, height) that make up the "!" shown above an enemy that just became
+/// aware of the player, relative to the bottom of the mark (in px at NATIVE_TILE_SIZE)
+const ALERT_MARK: [(i32, i32, i32, i32); 2] = [(-1, -7, 2, 4), (-1, -2, 2, 2)];
+/// The height (in px) of floating text, e.g. damage numbers
+const FLOATING_TEXT_HEIGHT: f32 = 8.0;
+/// The rectangles (x, y, width, height) that make up the icon of each status effect shown in the
+/// HUD, relative to the top left corner of the icon (in px on the screen)
+const POISON_ICON: [(i32, i32, i32, i32); 4] = [(2, 0, 2, 2), (1, 2, 4, 2), (0, 4, 6, 3), (1, 7, 4, 1)];
+const SLOW_ICON: [(i32, i32, i32, i32); 5] = [(0, 0, 6, 1), (1, 1, 4, 2), (2, 3, 2, 2), (1, 5, 4, 2), (0, 7, 6, 1)];
+/// The size (in px) of the dark square drawn behind each status effect icon
+const STATUS_ICON_SIZE: u32 = 10;
+/// The distance (in px) between the status effect icons and the top and left edges of the screen
+const STATUS_ICON_MARGIN: i32 = 4;
+/// The rectangles (x, y, width, height) that make up the icon of each weapon shown in the bottom
+/// left corner of the HUD. Drawn the same way as the status effect icons.
+const DAGGER_ICON: [(i32, i32, i32, i32); 3] = [(2, 2, 2, 3), (1, 5, 4, 1), (2, 6, 2, 2)];
+const SWORD_ICON: [(i32, i32, i32, i32); 3] = [(2, 0, 2, 5), (0, 5, 6, 1), (2, 6, 2, 2)];
+const SPEAR_ICON: [(i32, i32, i32, i32); 3] = [(2, 0, 2, 1), (1, 1, 4, 2), (2, 3, 2, 5)];
 
-pub struct RenderContext<'a, T: RenderTarget> {
+/// Everything needed to draw a frame. Textures are borrowed mutably (for `'a`) so that they can
+/// be reloaded between frames, but they live for as long as their texture creator (`'t`).
+pub struct RenderContext<'a, 't, T: RenderTarget> {
     pub font: Font<'static>,
+    /// Digits rasterized ahead of time for the floating text drawn over the level
+    pub digits: DigitGlyphs,
     pub canvas: &'a mut Canvas<T>,
-    pub textures: &'a TextureManager<'a, <T as RenderTarget>::Context>,
+    pub textures: &'a mut TextureManager<'t, <T as RenderTarget>::Context>,
     pub sprites: &'a SpriteManager,
     pub map_sprites: &'a MapSprites,
+    /// If true, the player also gets a health bar when damaged. Off by default since the HUD
+    /// already shows the player's health.
+    pub show_player_health_bar: bool,
+    /// If false, the screen never shakes
+    pub screen_shake: bool,
+    /// If true, the bounding box of every entity and the outline of every tile that cannot be
+    /// walked through are drawn over the level. Useful for debugging collisions.
+    pub show_bounding_boxes: bool,
+    /// If true, the tile grid, room boundaries, enemy spawn points, the camera focus, the tiles
+    /// visible to the player, and the bounding box of every entity are drawn over the level.
+    pub show_tile_overlay: bool,
+    /// If true, the time played so far is shown in the top right corner of the screen
+    pub show_play_clock: bool,
 }
 
-impl<'a, T: RenderTarget> RenderContext<'a, T> {
+impl<'a, 't, T: RenderTarget> RenderContext<'a, 't, T> {
     pub fn new(
         canvas: &'a mut Canvas<T>,
-        textures: &'a TextureManager<'a, <T as RenderTarget>::Context>,
+        textures: &'a mut TextureManager<'t, <T as RenderTarget>::Context>,
         sprites: &'a SpriteManager,
         map_sprites: &'a MapSprites,
-    ) -> Self {
-        Self {font: super::text::load_font(), canvas, textures, sprites, map_sprites}
+    ) -> Result<Self, SDLError> {
+        let font = super::text::load_font()?;
+        Ok(Self {
+            digits: DigitGlyphs::new(&font, FLOATING_TEXT_HEIGHT),
+            font,
+            canvas,
+            textures,
+            sprites,
+            map_sprites,
+            show_player_health_bar: false,
+            screen_shake: true,
+            show_bounding_boxes: false,
+            show_tile_overlay: false,
+            show_play_clock: false,
+        })
     }
 }
 
 #[derive(SystemData)]
 pub(in super) struct RenderData<'a> {
     map: Option<Read<'a, FloorMap>>,
+    explored: Option<Read<'a, ExploredTiles>>,
+    screen_shake: Option<Read<'a, ScreenShake>>,
+    decals: Option<Read<'a, DecalBuffer>>,
     camera_focuses: ReadStorage<'a, CameraFocus>,
     positions: ReadStorage<'a, Position>,
     doors: ReadStorage<'a, Door>,
     sprites: ReadStorage<'a, Sprite>,
     ghosts: ReadStorage<'a, Ghost>,
+    render_layers: ReadStorage<'a, RenderLayer>,
+    discovered: ReadStorage<'a, Discovered>,
+    invulnerables: ReadStorage<'a, Invulnerable>,
+    bounding_boxes: ReadStorage<'a, BoundingBox>,
+    healths: ReadStorage<'a, HealthPoints>,
+    health_bars: ReadStorage<'a, HealthBar>,
+    alert_indicators: ReadStorage<'a, AlertIndicator>,
+    players: ReadStorage<'a, Player>,
+    enemies: ReadStorage<'a, Enemy>,
+    enemy_spawns: ReadStorage<'a, EnemySpawn>,
+    floating_texts: ReadStorage<'a, FloatingText>,
+    status_effects: ReadStorage<'a, StatusEffects>,
+    equipments: ReadStorage<'a, Equipment>,
+    camera_offset: Write<'a, CameraOffset>,
 }
 
 impl<'a> AsRef<RenderData<'a>> for RenderData<'a> {
     fn as_ref(&self) -> &Self {
-        &self
+        self
     }
 }
 
@@ -54,8 +155,104 @@ pub fn setup(res: &mut Resources) {
     RenderData::setup(res);
 }
 
+/// Describes how much of a tile should be rendered. Ordered from most visible to least visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TileVisibility {
+    /// The tile is currently visible and should be rendered normally
+    Visible,
+    /// The tile has been explored but is not currently visible. It should be rendered dimmed and
+    /// only discovered entities should be rendered on top of it.
+    Explored,
+    /// The tile should not be rendered at all
+    Hidden,
+}
+
+impl TileVisibility {
+    /// Returns true if an entity may be drawn over a tile with this visibility
+    fn shows_entity(self, is_discovered: bool) -> bool {
+        match self {
+            TileVisibility::Visible => true,
+            TileVisibility::Explored => is_discovered,
+            TileVisibility::Hidden => false,
+        }
+    }
+}
+
+/// The part of a sprite that should be drawn. Sprites can be bigger than a tile (e.g. pillars,
+/// vertical doors, staircases, characters), so the tiles they extend into need to be checked too.
+/// Otherwise those sprites would give away what is going on in tiles that are not visible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SpriteClip {
+    /// None of the sprite should be drawn
+    Hidden,
+    /// The entire sprite should be drawn
+    Full,
+    /// Only the parts of the sprite within these rectangles (in world coordinates) should be drawn
+    Partial(Vec<Rect>),
+}
+
+impl SpriteClip {
+    /// Clips a sprite that will be drawn into the given rectangle (in world coordinates) to the
+    /// tiles that it may be drawn over.
+    ///
+    /// The sprite may always be drawn over `home`, the tile that it belongs to, since the decision
+    /// to draw the sprite at all has already been made based on that tile. Every other tile is
+    /// checked with `can_draw`. Nothing is drawn outside of the grid.
+    fn for_tiles(
+        dest: Rect,
+        home: TilePos,
+        tile_size: u32,
+        grid_size: GridSize,
+        can_draw: impl Fn(TilePos) -> bool,
+    ) -> Self {
+        let tile_size = tile_size as i32;
+        let tile_index = |coord: i32| coord.div_euclid(tile_size);
+        let rows = tile_index(dest.top())..=tile_index(dest.bottom() - 1);
+        let cols = tile_index(dest.left())..=tile_index(dest.right() - 1);
+
+        let is_drawable = |row: i32, col: i32| {
+            if row < 0 || col < 0 || row as usize >= grid_size.rows || col as usize >= grid_size.cols {
+                return false;
+            }
+
+            let pos = TilePos {row: row as usize, col: col as usize};
+            pos == home || can_draw(pos)
+        };
+
+        // Checked first so that the (very common) case of a sprite within a single visible tile
+        // does not need to allocate
+        let is_full = rows.clone().all(|row| cols.clone().all(|col| is_drawable(row, col)));
+        if is_full {
+            return SpriteClip::Full;
+        }
+
+        let mut visible = Vec::new();
+        for row in rows {
+            for col in cols.clone() {
+                if !is_drawable(row, col) {
+                    continue;
+                }
+
+                let tile = Rect::new(col * tile_size, row * tile_size, tile_size as u32, tile_size as u32);
+                visible.extend(tile.intersection(dest));
+            }
+        }
+
+        if visible.is_empty() {
+            SpriteClip::Hidden
+        } else {
+            SpriteClip::Partial(visible)
+        }
+    }
+}
+
 pub struct DebugInfo {
     pub fps: u32,
+    /// The time it took to generate the slowest level. Since levels are generated in parallel,
+    /// this is roughly how long generation took overall.
+    pub generation_time: Duration,
+    /// The uid of the room that the player is currently in, if any
+    pub room: Option<(RoomUid, RoomType)>,
 }
 
 /// Renders a debug view
@@ -63,212 +260,723 @@ pub fn render_debug_view<T: RenderTarget>(
     ctx: &mut RenderContext<T>,
     debug_info: DebugInfo,
 ) -> Result<(), SDLError> {
-    let text = Text::new(&ctx.font, format!("{}FPS", debug_info.fps), 10.0);
+    let DebugInfo {fps, generation_time, room} = debug_info;
+    let gray = Color::RGB(128, 128, 128);
+    let mut lines = vec![vec![(format!("{}FPS (gen: {}ms)", fps, generation_time.as_millis()), gray)]];
+    if let Some((room_uid, room_type)) = room {
+        lines.push(vec![(format!("{} ({:?})", room_uid, room_type), gray)]);
+    }
+    if ctx.show_bounding_boxes {
+        lines.push(DebugOutline::ALL.iter().map(|&outline| (outline.title().to_string(), outline.color())).collect());
+    }
     let padding = 3;
-    let (canvas_width, canvas_height) = ctx.canvas.logical_size();
+    let (canvas_width, mut box_bottom) = ctx.canvas.logical_size();
 
-    let box_width = text.width().ceil() as u32 + padding * 2;
-    let box_height = text.line_height().ceil() as u32 + padding * 2;
-    let box_x = (canvas_width - box_width) as i32;
-    let box_y = (canvas_height - box_height) as i32;
-    ctx.canvas.set_draw_color((60, 60, 60));
-    ctx.canvas.fill_rect(Rect::new(box_x, box_y, box_width, box_height)).map_err(SDLError)?;
+    // Lines are stacked up from the bottom right corner of the screen, last line first. Each line
+    // is made up of pieces of text that can each have their own color.
+    let font = &ctx.font;
+    for line in lines.into_iter().rev() {
+        let texts: Vec<_> = line.into_iter()
+            .map(|(text, color)| (Text::new(font, text, 10.0), color))
+            .collect();
+        let spacing = padding;
+        let text_width: f32 = texts.iter().map(|(text, _)| text.width().ceil() + spacing as f32).sum();
+        let line_height = texts.iter().map(|(text, _)| text.line_height().ceil() as u32).max().unwrap_or(0);
+        let box_width = text_width as u32 - spacing + padding * 2;
+        let box_height = line_height + padding * 2;
+        let box_x = (canvas_width - box_width) as i32;
+        let box_y = (box_bottom - box_height) as i32;
+        ctx.canvas.set_draw_color((60, 60, 60));
+        ctx.canvas.fill_rect(Rect::new(box_x, box_y, box_width, box_height)).map_err(SDLError::Sdl)?;
 
-    text.render(ctx.canvas, (128, 128, 128), TextLayout::TopLeftAt(Point::new(
-        box_x + padding as i32,
-        box_y + padding as i32,
-    )))?;
+        let mut x = box_x + padding as i32;
+        for (text, color) in texts {
+            let width = text.width().ceil() as i32;
+            text.render(ctx.canvas, color, TextLayout::TopLeftAt(Point::new(x, box_y + padding as i32)))?;
+            x += width + spacing as i32;
+        }
+        box_bottom -= box_height;
+    }
 
     Ok(())
 }
 
 /// Renders the area of the world that is visible to the player
 pub(in super) fn render_player_visible<T: RenderTarget>(
-    data: RenderData<'_>,
+    mut data: RenderData<'_>,
     ctx: &mut RenderContext<T>,
+    ghost: Option<GhostSprite>,
 ) -> Result<(), SDLError> {
-    let RenderData {map, positions, camera_focuses, doors, ..} = &data;
+    let RenderData {map, explored, screen_shake, positions, camera_focuses, doors, bounding_boxes, ..} = &data;
     let map = map.as_ref().expect("bug: map must be added as a resource to render area visible to player");
+    let explored = explored.as_ref().expect("bug: explored tiles must be added as a resource to render area visible to player");
     let tile_size = map.tile_size() as i32;
     let grid = map.grid();
 
-    let mut camera_focuses = (positions, camera_focuses).join();
-    let (&Position(camera_focus), _) = camera_focuses.next()
+    let mut camera_focuses = (positions, bounding_boxes.maybe(), camera_focuses).join();
+    let (&Position(camera_focus), focus_bounds, _) = camera_focuses.next()
         .expect("Renderer was not told which entity to focus on");
     assert!(camera_focuses.next().is_none(),
         "Renderer was asked to focus on more than one thing");
 
-    let (screen_width, screen_height) = ctx.canvas.logical_size();
-    let screen_center = Point::new(screen_width as i32 / 2, screen_height as i32 / 2);
-
-    // The position on the map of the screen's top left corner
-    // Adding this point to the position of the camera_focus would make it render in the center
-    // of the screen
-    let render_top_left = camera_focus - screen_center;
-
-    // Need to make sure the camera stays within the level boundary
-    let level_boundary = map.level_boundary();
-
-    // The valid ranges for the top-left corner of the screen
-    let (min_x, max_x) = (0, level_boundary.x() + level_boundary.width() as i32 - screen_width as i32);
-    let (min_y, max_y) = (0, level_boundary.y() + level_boundary.height() as i32 - screen_height as i32);
-    let clamp = |min, x, max| cmp::min(cmp::max(min, x), max);
-    let render_top_left = Point::new(
-        clamp(min_x, render_top_left.x, max_x),
-        clamp(min_y, render_top_left.y, max_y),
-    );
-
-    // Get the tiles surrounding the camera focus
-    let screen = Rect::new(
-        render_top_left.x(),
-        render_top_left.y(),
-        screen_width,
-        screen_height,
-    );
+    // The camera stays within the level boundary
+    let camera = Camera::new(camera_focus, ctx.canvas.logical_size(), map.level_boundary());
+    // Shaking moves the whole screen, but it still cannot go past the edges of the level
+    let camera = match screen_shake {
+        Some(shake) if ctx.screen_shake => camera.shifted(shake.offset()),
+        _ => camera,
+    };
 
     // Only render tiles that are visible to the camera focus.
 
     // The tile that the camera focus is currently standing on
-    let focus_pos = map.world_to_tile_pos(camera_focus);
+    let focus_pos = visibility_start(grid, camera_focus, focus_bounds.cloned(), tile_size, positions, doors);
 
     // The returned set will contain all tiles that are directly visible to the camera focus
     // without passing through entrances that have still not been opened.
     let visible_tiles = find_visible_tiles(grid, focus_pos, tile_size, positions, doors);
 
-    let should_render = |pt, tile: &Tile| {
-        visible_tiles.contains(&pt) ||
-        // Need to specially handle wall corners because they are not *directly* visible.
-        // A corner is a wall tile with at least two visible walls
-        tile.is_wall() && grid.adjacent_positions(pt)
-            .filter(|pt| visible_tiles.contains(pt)).count() >= 2
-    };
+    let visibility = |pt, _: &Tile| tile_visibility(&visible_tiles, explored, pt);
+
+    render_area(&data, map, &camera, ctx, visibility)?;
+
+    let player_effects = (&data.players, &data.status_effects).join().next()
+        .map(|(_, effects)| effects.kinds())
+        .unwrap_or_default();
+    render_status_icons(&player_effects, ctx)?;
+    if let Some((_, equipment)) = (&data.players, &data.equipments).join().next() {
+        render_weapon_icon(equipment.weapon.kind, ctx)?;
+    }
+
+    // The ghost of a previous run goes over everything else, but only where the player can see
+    if let Some(ghost) = ghost {
+        let ghost_tile = map.world_to_tile_pos(ghost.pos);
+        if visible_tiles.contains(&ghost_tile) {
+            let sprite = SpriteImage {texture_id: ghost.texture, ..ctx.sprites.get(ghost.sprite).clone()};
+            let clip = SpriteClip::for_tiles(sprite.dest_rect(ghost.pos, map.tile_size()), ghost_tile,
+                map.tile_size(), grid.dimensions(), |pos| visible_tiles.contains(&pos));
+            render_sprite(ghost.pos, map.tile_size(), &sprite, ctx, &camera, &clip, None)?;
+        }
+    }
+
+    if ctx.show_bounding_boxes {
+        render_debug_outlines(&data, map, &camera, ctx)?;
+    }
+    if ctx.show_tile_overlay {
+        render_tile_overlay(&data, map, &camera, &visible_tiles, ctx)?;
+    }
 
-    render_area(&data, &map, screen, ctx, should_render)
+    // Published so that positions on the screen can be converted back into positions on the map
+    *data.camera_offset = CameraOffset(Some(camera.top_left()));
+
+    Ok(())
 }
 
-fn find_visible_tiles(
-    grid: &TileGrid,
-    pos: TilePos,
-    tile_size: i32,
-    positions: &ReadStorage<'_, Position>,
-    doors: &ReadStorage<'_, Door>,
-) -> HashSet<TilePos> {
-    let find_door = |target: TilePos| {
-        let target_center = target.center(tile_size);
-        (positions, doors).join()
-            .find(|(&Position(pos), Door {..})| pos == target_center)
-    };
+/// The kinds of outlines drawn when bounding boxes are shown for debugging. Each kind has its own
+/// color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugOutline {
+    Player,
+    Enemy,
+    /// Any other entity that can be collided with (e.g. a closed door)
+    Solid,
+    /// An entity that nothing collides with. These only react to being overlapped (e.g. stairs).
+    Ghost,
+    /// The full area of an entity, drawn only where it differs from its bounding box
+    FullBounds,
+    /// A tile that cannot be walked through
+    Tile,
+}
 
-    // If the position center is at a door, start one tile back away from it
-    let pos = match find_door(pos) {
-        //TODO: This code is fragile. It only works because we have two bounding boxes: full and
-        // bottom half. If we were to one day add another type, it would no longer work.
-        // Reason: This code is meant to handle the special case where the top of a bottom half
-        // bounding box is toching a door north of its position. Since the center of the bounding
-        // box is at the top, we can run into a situation where the search below only results in
-        // a single tile. We need to start the search one tile below for everything to workout.
-        // Ideally, we would calculate the position one tile "away" from the door and use that as
-        // an exact point to start. This works because we only have two bounding box types.
-        Some(_) => pos.adjacent_south(grid.rows_len()).unwrap(),
-        None => pos,
+impl DebugOutline {
+    /// All of the outlines, in the order they are listed in the legend
+    pub const ALL: [DebugOutline; 6] = [
+        DebugOutline::Player,
+        DebugOutline::Enemy,
+        DebugOutline::Solid,
+        DebugOutline::Ghost,
+        DebugOutline::FullBounds,
+        DebugOutline::Tile,
+    ];
+
+    /// Returns the outline used for the bounding box of an entity with the given components. An
+    /// entity that is more than one of these (e.g. a ghost player) is classified by the first that
+    /// applies out of player, enemy and ghost.
+    pub fn classify(is_player: bool, is_enemy: bool, is_ghost: bool) -> Self {
+        if is_player {
+            DebugOutline::Player
+        } else if is_enemy {
+            DebugOutline::Enemy
+        } else if is_ghost {
+            DebugOutline::Ghost
+        } else {
+            DebugOutline::Solid
+        }
+    }
+
+    pub fn color(self) -> Color {
+        use self::DebugOutline::*;
+        match self {
+            Player => Color::RGB(60, 220, 60),
+            Enemy => Color::RGB(230, 50, 50),
+            Solid => Color::RGB(240, 200, 40),
+            Ghost => Color::RGB(70, 200, 230),
+            FullBounds => Color::RGB(200, 90, 220),
+            Tile => Color::RGB(150, 150, 150),
+        }
+    }
+
+    /// The name of this outline shown in the legend of the debug view
+    pub fn title(self) -> &'static str {
+        use self::DebugOutline::*;
+        match self {
+            Player => "player",
+            Enemy => "enemy",
+            Solid => "solid",
+            Ghost => "ghost",
+            FullBounds => "full",
+            Tile => "tile",
+        }
+    }
+}
+
+/// Returns the tiles within (or around) the given region that cannot be walked through
+fn solid_tiles_within(map: &FloorMap, region: Rect) -> impl Iterator<Item=TilePos> + '_ {
+    map.tiles_within(region)
+        .filter(|(_, _, tile)| !tile.is_floor())
+        .map(|(_, pos, _)| pos)
+}
+
+/// Outlines every tile that cannot be walked through and the bounding box of every entity within
+/// the area shown by the given camera, regardless of whether the player can see them
+fn render_debug_outlines<T: RenderTarget>(
+    data: &RenderData<'_>,
+    map: &FloorMap,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+) -> Result<(), SDLError> {
+    ctx.canvas.set_draw_color(DebugOutline::Tile.color());
+    for pos in solid_tiles_within(map, camera.visible_world_rect()) {
+        ctx.canvas.draw_rect(camera.world_rect_to_screen(pos.tile_rect(map.tile_size()))).map_err(SDLError::Sdl)?;
+    }
+
+    render_entity_outlines(data, camera, ctx)
+}
+
+/// Outlines the bounding box of every entity within the area shown by the given camera
+fn render_entity_outlines<T: RenderTarget>(
+    data: &RenderData<'_>,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+) -> Result<(), SDLError> {
+    let RenderData {positions, bounding_boxes, players, enemies, ghosts, ..} = data;
+    let region = camera.visible_world_rect();
+    let offset = |rect| camera.world_rect_to_screen(rect);
+
+    for (&Position(pos), bounds, player, enemy, ghost) in (positions, bounding_boxes, players.maybe(), enemies.maybe(), ghosts.maybe()).join() {
+        let full_rect = bounds.to_full_rect(pos);
+        if !full_rect.has_intersection(region) {
+            continue;
+        }
+
+        let rect = bounds.to_rect(pos);
+        if full_rect != rect {
+            ctx.canvas.set_draw_color(DebugOutline::FullBounds.color());
+            ctx.canvas.draw_rect(offset(full_rect)).map_err(SDLError::Sdl)?;
+        }
+        ctx.canvas.set_draw_color(DebugOutline::classify(player.is_some(), enemy.is_some(), ghost.is_some()).color());
+        ctx.canvas.draw_rect(offset(rect)).map_err(SDLError::Sdl)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the x-coordinates of the vertical lines and the y-coordinates of the horizontal lines
+/// of the tile grid that fall within the given region
+fn grid_lines(region: Rect, tile_size: i32) -> (Vec<i32>, Vec<i32>) {
+    let lines = |start: i32, end: i32| {
+        let first = start + (tile_size - start.rem_euclid(tile_size)) % tile_size;
+        (first..end).step_by(tile_size as usize).collect()
     };
+    (lines(region.left(), region.right()), lines(region.top(), region.bottom()))
+}
+
+/// Draws the tile grid, the boundary and ID of each room, the enemy spawn points, the camera focus,
+/// the tiles visible to the player, and the bounding box of every entity within the area shown by
+/// the given camera. Everything is drawn regardless of whether the player can see it.
+fn render_tile_overlay<T: RenderTarget>(
+    data: &RenderData<'_>,
+    map: &FloorMap,
+    camera: &Camera,
+    visible_tiles: &HashSet<TilePos>,
+    ctx: &mut RenderContext<T>,
+) -> Result<(), SDLError> {
+    let RenderData {positions, enemy_spawns, ..} = data;
+    let region = camera.visible_world_rect();
+    let offset = |rect| camera.world_rect_to_screen(rect);
+    let tile_size = map.tile_size();
 
-    grid.depth_first_search(pos, |node, _| {
-        // Stop searching at walls or closed entrances (but still include them in the result)
-        !grid.get(node).is_wall() && find_door(node).is_none()
-    })
+    ctx.canvas.set_blend_mode(BlendMode::Blend);
+    ctx.canvas.set_draw_color((255, 255, 100, 40));
+    for (_, pos, _) in map.tiles_within(region) {
+        if visible_tiles.contains(&pos) {
+            ctx.canvas.fill_rect(offset(pos.tile_rect(tile_size))).map_err(SDLError::Sdl)?;
+        }
+    }
+
+    let (columns, rows) = grid_lines(region, tile_size as i32);
+    ctx.canvas.set_draw_color((255, 255, 255, 50));
+    for x in columns {
+        let x = camera.world_to_screen(Point::new(x, 0)).x();
+        ctx.canvas.draw_line((x, 0), (x, region.height() as i32)).map_err(SDLError::Sdl)?;
+    }
+    for y in rows {
+        let y = camera.world_to_screen(Point::new(0, y)).y();
+        ctx.canvas.draw_line((0, y), (region.width() as i32, y)).map_err(SDLError::Sdl)?;
+    }
+
+    let room_color = Color::RGB(255, 140, 0);
+    for (room_id, room) in map.rooms() {
+        let boundary = room.boundary();
+        let top_left = boundary.top_left().top_left(tile_size as i32);
+        let GridSize {rows, cols} = boundary.dimensions();
+        let rect = Rect::new(top_left.x(), top_left.y(), cols as u32 * tile_size, rows as u32 * tile_size);
+        if !rect.has_intersection(region) {
+            continue;
+        }
+        let rect = offset(rect);
+        ctx.canvas.set_draw_color(room_color);
+        ctx.canvas.draw_rect(rect).map_err(SDLError::Sdl)?;
+        // The label is skipped once the top left corner of the room scrolls off the screen
+        let label = Point::new(rect.x() + 2, rect.y() + 2);
+        if label.x() >= 0 && label.y() >= 0 {
+            Text::new(&ctx.font, room_id.to_string(), 6.0).render(ctx.canvas, room_color, TextLayout::TopLeftAt(label))?;
+        }
+    }
+
+    ctx.canvas.set_draw_color(Color::RGB(230, 60, 230));
+    for (&Position(pos), _) in (positions, enemy_spawns).join() {
+        let pos = camera.world_to_screen(pos);
+        ctx.canvas.draw_line(pos.offset(-3, -3), pos.offset(3, 3)).map_err(SDLError::Sdl)?;
+        ctx.canvas.draw_line(pos.offset(-3, 3), pos.offset(3, -3)).map_err(SDLError::Sdl)?;
+    }
+
+    render_entity_outlines(data, camera, ctx)?;
+
+    let focus = camera.world_to_screen(camera.focus());
+    ctx.canvas.set_draw_color(Color::RGB(255, 255, 255));
+    ctx.canvas.draw_line(focus.offset(-4, 0), focus.offset(4, 0)).map_err(SDLError::Sdl)?;
+    ctx.canvas.draw_line(focus.offset(0, -4), focus.offset(0, 4)).map_err(SDLError::Sdl)?;
+
+    Ok(())
+}
+
+/// Determines how a tile should be rendered based on the tiles currently visible to the player
+/// and the tiles that have been explored
+fn tile_visibility(
+    visible_tiles: &HashSet<TilePos>,
+    explored: &ExploredTiles,
+    pos: TilePos,
+) -> TileVisibility {
+    if visible_tiles.contains(&pos) {
+        TileVisibility::Visible
+    } else if explored.contains(pos) {
+        TileVisibility::Explored
+    } else {
+        TileVisibility::Hidden
+    }
 }
 
 pub(in super) fn render_area<'a, T: RenderTarget>(
     data: impl AsRef<RenderData<'a>>,
     map: &FloorMap,
-    region: Rect,
+    camera: &Camera,
     ctx: &mut RenderContext<T>,
-    should_render: impl Fn(TilePos, &Tile) -> bool + Clone,
+    visibility: impl Fn(TilePos, &Tile) -> TileVisibility + Clone,
 ) -> Result<(), SDLError> {
-    let RenderData {positions, sprites: esprites, ghosts, ..} = data.as_ref();
-    let render_top_left = region.top_left();
+    let data = data.as_ref();
+    let RenderData {
+        positions,
+        bounding_boxes,
+        healths,
+        health_bars,
+        alert_indicators,
+        floating_texts,
+        players,
+        decals,
+        discovered,
+        ..
+    } = data;
+    let region = camera.visible_world_rect();
 
-    // Rendering strategy: For each row, first render all the backgrounds, then render all of
-    // entities that should be rendered under other entities, then render all other entities.
-    // This allows an object to overlap the background of the tile on its right.
-    render_background(&*map, region, ctx, should_render.clone())?;
+    // Rendering strategy: First render all the backgrounds, then render every entity sorted by
+    // its layer and then by where it touches the floor. This allows an object to overlap the
+    // background of the tile on its right and anything further south to be drawn in front. The
+    // parts of the map that things can stand behind (e.g. the fronts of walls) are sorted along
+    // with the entities so that they cover anyone standing behind them.
+    render_background(map, camera, ctx, visibility.clone())?;
 
     let grid = map.grid();
-    let should_render_pos = |pos| {
+    let should_render_pos = |pos, is_discovered| {
         let tile_pos = map.world_to_tile_pos(pos);
+        let mut tile_visibility = visibility(tile_pos, grid.get(tile_pos));
 
         // Do not want to render the wall decoration if we are not going to render the
         // tile south of this wall. Reason: Objects within a room should only be visible
         // when that room is visible
         if grid.get(tile_pos).is_wall() {
-            let should_render_south = tile_pos.adjacent_south(grid.rows_len())
-                .map(|south| should_render(south, grid.get(south)))
-                .unwrap_or(false);
-            if !should_render_south {
-                return false;
-            }
+            let south_visibility = tile_pos.adjacent_south(grid.rows_len())
+                .map(|south| visibility(south, grid.get(south)))
+                .unwrap_or(TileVisibility::Hidden);
+            tile_visibility = cmp::max(tile_visibility, south_visibility);
         }
 
-        should_render(tile_pos, grid.get(tile_pos))
+        tile_visibility.shows_entity(is_discovered)
     };
+    // Parts of a sprite that extend beyond its own tile are only drawn over tiles that would show
+    // the entity. Unlike above, walls do not borrow the visibility of the tile south of them since
+    // that would let a tall sprite (e.g. a vertical door) show through a wall that is not visible.
+    let sprite_clip = |pos, dest, is_discovered| {
+        if !should_render_pos(pos, is_discovered) {
+            return SpriteClip::Hidden;
+        }
 
-    render_entities((positions, esprites, ghosts).join().map(|(p, s, _)| (p, s)),
-        map.tile_size(), render_top_left, ctx, should_render_pos)?;
-    render_entities((positions, esprites, !ghosts).join().map(|(p, s, _)| (p, s)),
-        map.tile_size(), render_top_left, ctx, should_render_pos)?;
+        SpriteClip::for_tiles(dest, map.world_to_tile_pos(pos), map.tile_size(), grid.dimensions(),
+            |tile_pos| visibility(tile_pos, grid.get(tile_pos)).shows_entity(is_discovered))
+    };
+
+    // Decals are on the floor, so they go under every entity
+    if let Some(decals) = decals {
+        render_decals(decals.iter(), map.tile_size(), camera, ctx, should_render_pos)?;
+    }
+
+    let drawables = drawables(data, map, region, ctx.map_sprites, ctx.sprites, visibility.clone());
+    render_entities(drawables, map.tile_size(), camera, ctx, sprite_clip)?;
+
+    // Health bars go on top of every entity so they are never covered by a neighbour
+    let show_player = ctx.show_player_health_bar;
+    render_health_bars((positions, bounding_boxes, healths, health_bars, discovered.maybe(), players.maybe()).join()
+        .filter(|&(_, _, _, _, _, player)| show_player || player.is_none())
+        .map(|(p, b, h, bar, d, _)| (p, b, h, bar, d.is_some())),
+        camera, ctx, should_render_pos)?;
+    render_alert_indicators((positions, bounding_boxes, alert_indicators).join(),
+        map.tile_size(), camera, ctx, should_render_pos)?;
+    render_floating_texts((positions, floating_texts).join(), camera, ctx, should_render_pos)?;
+
+    Ok(())
+}
+
+/// Renders each of the given floating texts centered above its position, faded based on the time
+/// it has left
+fn render_floating_texts<'a, T: RenderTarget>(
+    components: impl Iterator<Item=(&'a Position, &'a FloatingText)>,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+    should_render: impl Fn(Point, bool) -> bool,
+) -> Result<(), SDLError> {
+    for (&Position(pos), floating_text) in components {
+        if !should_render(pos, false) {
+            continue;
+        }
+
+        let color = if floating_text.critical {
+            Color::RGBA(255, 200, 40, floating_text.alpha())
+        } else {
+            Color::RGBA(255, 255, 255, floating_text.alpha())
+        };
+        let text = &floating_text.text;
+        let bottom_center = camera.world_to_screen(pos + floating_text.offset());
+        if ctx.digits.can_render(text) {
+            let width = ctx.digits.width(text).ceil() as i32;
+            let top_left = bottom_center.offset(-width / 2, -(FLOATING_TEXT_HEIGHT as i32));
+            ctx.digits.render(ctx.canvas, color, text, top_left)?;
+        } else {
+            let text = Text::new(&ctx.font, text, FLOATING_TEXT_HEIGHT);
+            let top_left = bottom_center.offset(-(text.width().ceil() as i32) / 2, -(text.line_height().ceil() as i32));
+            // Laid out text cannot go past the edges of the screen
+            if top_left.x() >= 0 && top_left.y() >= 0 {
+                text.render(ctx.canvas, color, TextLayout::TopLeftAt(top_left))?;
+            }
+        }
+    }
 
     Ok(())
 }
 
-/// Renders the tiles of the background (map) within the given region
-fn render_entities<'a, T: RenderTarget>(
-    components: impl Iterator<Item=(&'a Position, &'a Sprite)>,
+/// Renders a "!" above the health bar position of each of the given entities
+fn render_alert_indicators<'a, T: RenderTarget>(
+    components: impl Iterator<Item=(&'a Position, &'a BoundingBox, &'a AlertIndicator)>,
     tile_size: u32,
-    render_top_left: Point,
+    camera: &Camera,
     ctx: &mut RenderContext<T>,
-    should_render: impl Fn(Point) -> bool,
+    should_render: impl Fn(Point, bool) -> bool,
 ) -> Result<(), SDLError> {
-    for (&Position(pos), &Sprite(sprite)) in components {
-        if !should_render(pos) {
+    ctx.canvas.set_draw_color(Color::RGB(250, 220, 60));
+    for (&Position(pos), bounds, _) in components {
+        if !should_render(pos, false) {
             continue;
         }
 
-        let sprite = ctx.sprites.get(sprite);
-        // Render the sprite in a (tile_size)x(tile_size) square centered around its position.
-        // TODO: If the sprite is bigger than this, it will (currently) still be rendered and not
-        // clipped.
-        render_sprite(pos, tile_size, sprite, ctx, render_top_left)?;
+        let bottom = bounds.to_rect(pos).top() - 2 * HEALTH_BAR_MARGIN - HEALTH_BAR_HEIGHT as i32;
+        let bottom = camera.world_to_screen(Point::new(pos.x(), bottom));
+        for &(x, y, width, height) in &ALERT_MARK {
+            let scale = |length| scale_to_tile_size(length, tile_size);
+            let part = Rect::new(bottom.x() + scale(x), bottom.y() + scale(y),
+                scale(width).max(1) as u32, scale(height).max(1) as u32);
+            ctx.canvas.fill_rect(part).map_err(SDLError::Sdl)?;
+        }
     }
 
     Ok(())
 }
 
-/// Renders the tiles of the background (map) within the given region
-fn render_background<T: RenderTarget>(
+/// Renders a health bar centered above the bounding box of each of the given entities. Bars that
+/// have completely faded out are skipped.
+fn render_health_bars<'a, T: RenderTarget>(
+    components: impl Iterator<Item=(&'a Position, &'a BoundingBox, &'a HealthPoints, &'a HealthBar, bool)>,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+    should_render: impl Fn(Point, bool) -> bool,
+) -> Result<(), SDLError> {
+    ctx.canvas.set_blend_mode(BlendMode::Blend);
+    for (&Position(pos), bounds, &HealthPoints(health), bar, is_discovered) in components {
+        let alpha = bar.alpha();
+        if alpha == 0 || !should_render(pos, is_discovered) {
+            continue;
+        }
+
+        let bounds = bounds.to_rect(pos);
+        let background = camera.world_rect_to_screen(Rect::new(
+            pos.x() - HEALTH_BAR_WIDTH as i32 / 2,
+            bounds.top() - HEALTH_BAR_MARGIN - HEALTH_BAR_HEIGHT as i32,
+            HEALTH_BAR_WIDTH,
+            HEALTH_BAR_HEIGHT,
+        ));
+        ctx.canvas.set_draw_color(Color::RGBA(40, 40, 40, alpha));
+        ctx.canvas.fill_rect(background).map_err(SDLError::Sdl)?;
+
+        let filled = (bar.fraction(health) * HEALTH_BAR_WIDTH as f64).round() as u32;
+        if filled > 0 {
+            let mut foreground = background;
+            foreground.set_width(filled);
+            ctx.canvas.set_draw_color(Color::RGBA(200, 40, 40, alpha));
+            ctx.canvas.fill_rect(foreground).map_err(SDLError::Sdl)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders each of the given decals on the floor, faded based on the time it has left
+fn render_decals<'a, T: RenderTarget>(
+    decals: impl Iterator<Item=&'a Decal>,
+    tile_size: u32,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+    should_render: impl Fn(Point, bool) -> bool,
+) -> Result<(), SDLError> {
+    ctx.canvas.set_blend_mode(BlendMode::Blend);
+    for decal in decals {
+        // Decals are not remembered once they are out of sight
+        if !should_render(decal.pos, false) {
+            continue;
+        }
+
+        let center = camera.world_to_screen(decal.pos);
+        let (parts, color) = match decal.kind {
+            DecalKind::Blood => (&BLOOD_SPLAT, Color::RGBA(110, 10, 10, decal.alpha())),
+        };
+        ctx.canvas.set_draw_color(color);
+        for &(x, y, width, height) in parts {
+            let scale = |length| scale_to_tile_size(length, tile_size);
+            let part = Rect::new(center.x() + scale(x), center.y() + scale(y),
+                scale(width).max(1) as u32, scale(height).max(1) as u32);
+            ctx.canvas.fill_rect(part).map_err(SDLError::Sdl)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Something drawn over the background, either an entity or a part of the map that things can
+/// stand behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Drawable {
+    pos: Point,
+    sprite: SpriteId,
+    layer: RenderLayer,
+    /// The y-coordinate (in world coordinates) of the bottom of the sprite, where it touches the
+    /// floor
+    base: i32,
+    is_discovered: bool,
+    /// True for the front of a wall. Walls are already drawn with the background, so the front is
+    /// only drawn again over anything standing behind it.
+    is_wall_front: bool,
+    /// The color that the sprite is multiplied by when it is drawn, if any
+    tint: Option<Color>,
+}
+
+/// Returns everything that should be drawn over the background within the given region in the
+/// order it should be drawn: by layer, and then from north to south (by base) within each layer
+fn drawables(
+    data: &RenderData<'_>,
     map: &FloorMap,
     region: Rect,
+    map_sprites: &MapSprites,
+    sprites: &SpriteManager,
+    visibility: impl Fn(TilePos, &Tile) -> TileVisibility,
+) -> Vec<Drawable> {
+    let RenderData {positions, sprites: sprite_components, render_layers, discovered, invulnerables, doors, enemies, status_effects, ..} = data;
+
+    // Invulnerable entities blink by skipping some frames
+    let is_blinking = |invulnerable: Option<&Invulnerable>| invulnerable.map(|i| !i.is_visible()).unwrap_or(false);
+    // Open doors are not rendered at all
+    let is_hidden = |invulnerable, door: Option<&Door>| {
+        is_blinking(invulnerable) || door.map(|door| door.is_open()).unwrap_or(false)
+    };
+
+    let tile_size = map.tile_size();
+    let base = |pos, sprite| sprites.get(sprite).dest_rect(pos, tile_size).bottom();
+    // Enemies under a status effect are tinted the color of their most important effect
+    let tint = |enemy: Option<&Enemy>, effects: Option<&StatusEffects>| {
+        enemy.and(effects)?.kinds().first().map(|&kind| status_color(kind))
+    };
+    let mut drawables: Vec<_> = (positions, sprite_components, render_layers.maybe(), discovered.maybe(), invulnerables.maybe(), doors.maybe(), enemies.maybe(), status_effects.maybe()).join()
+        .filter(|&(_, _, _, _, i, door, _, _)| !is_hidden(i, door))
+        .map(|(&Position(pos), &Sprite(sprite), layer, d, _, door, enemy, effects)| {
+            // Doors in vertical walls are as tall as the walls around them, so they are sorted
+            // with the characters just like the walls are
+            let is_tall_door = door.is_some()
+                && sprites.get(sprite).dest_rect(pos, tile_size).height() > tile_size;
+            Drawable {
+                pos,
+                sprite,
+                layer: if is_tall_door { RenderLayer::CHARACTERS } else { layer.cloned().unwrap_or_default() },
+                base: base(pos, sprite),
+                is_discovered: d.is_some(),
+                is_wall_front: false,
+                tint: tint(enemy, effects),
+            }
+        })
+        .collect();
+
+    // Pillars and the fronts of walls (walls with floor to the south) are drawn again with the
+    // characters so that anyone standing behind them is covered. Only visible walls are drawn
+    // since the rest are already dimmed in the background.
+    let grid = map.grid();
+    let (top_left, size) = map.grid_area_within(region);
+    for (row, row_tiles) in grid.rows().enumerate().skip(top_left.row).take(size.rows) {
+        for (col, tile) in row_tiles.iter().enumerate().skip(top_left.col).take(size.cols) {
+            let tile_pos = TilePos {row, col};
+            if !tile.is_wall() || visibility(tile_pos, tile) != TileVisibility::Visible {
+                continue;
+            }
+            let is_pillar = tile.wall_sprite().alt == WallSpriteAlternate::BrickPillar;
+            let has_front = tile_pos.adjacent_south(grid.rows_len())
+                .map(|south| grid.get(south).is_floor())
+                .unwrap_or(false);
+            if !is_pillar && !has_front {
+                continue;
+            }
+
+            let pos = tile_pos.center(tile_size as i32);
+            let sprite = tile.background_sprite(map_sprites);
+            drawables.push(Drawable {
+                pos,
+                sprite,
+                layer: RenderLayer::CHARACTERS,
+                base: base(pos, sprite),
+                is_discovered: true,
+                is_wall_front: !is_pillar,
+                tint: None,
+            });
+        }
+    }
+
+    // Stable so that entities at the same position are always drawn in the same order
+    drawables.sort_by_key(|drawable| (drawable.layer, drawable.base));
+    drawables
+}
+
+/// Returns the part of each of the given drawables that should be drawn, in the same order.
+///
+/// Wall fronts are only drawn over the parts of the characters (and anything else drawn with them)
+/// that are behind the wall. Anything else on the wall (e.g. a torch or the top of a staircase)
+/// was drawn over it before the characters and stays on top.
+fn clip_drawables(
+    drawables: Vec<Drawable>,
+    sprites: &SpriteManager,
+    tile_size: u32,
+    sprite_clip: impl Fn(Point, Rect, bool) -> SpriteClip,
+) -> Vec<(Drawable, SpriteClip)> {
+    // The parts of the level (in world coordinates) covered by the characters drawn so far
+    let mut covered = Vec::new();
+    drawables.into_iter().map(|drawable| {
+        // The sprite is aligned against a (tile_size)x(tile_size) square centered around its
+        // position, but may extend beyond that square into the surrounding tiles
+        let dest = sprites.get(drawable.sprite).dest_rect(drawable.pos, tile_size);
+        if drawable.is_wall_front {
+            let behind: Vec<_> = covered.iter().filter_map(|&part: &Rect| part.intersection(dest)).collect();
+            let clip = if behind.is_empty() { SpriteClip::Hidden } else { SpriteClip::Partial(behind) };
+            return (drawable, clip);
+        }
+
+        let clip = sprite_clip(drawable.pos, dest, drawable.is_discovered);
+        if drawable.layer >= RenderLayer::CHARACTERS {
+            match &clip {
+                SpriteClip::Hidden => {},
+                SpriteClip::Full => covered.push(dest),
+                SpriteClip::Partial(parts) => covered.extend(parts),
+            }
+        }
+        (drawable, clip)
+    }).collect()
+}
+
+/// Renders each of the given entities in order
+fn render_entities<T: RenderTarget>(
+    drawables: Vec<Drawable>,
+    tile_size: u32,
+    camera: &Camera,
     ctx: &mut RenderContext<T>,
-    mut should_render: impl FnMut(TilePos, &Tile) -> bool,
+    sprite_clip: impl Fn(Point, Rect, bool) -> SpriteClip,
+) -> Result<(), SDLError> {
+    let sprites = ctx.sprites;
+    for (Drawable {pos, sprite, tint, ..}, clip) in clip_drawables(drawables, sprites, tile_size, sprite_clip) {
+        render_sprite(pos, tile_size, sprites.get(sprite), ctx, camera, &clip, tint)?;
+    }
+
+    Ok(())
+}
+
+/// Renders the tiles of the background (map) within the area shown by the given camera
+fn render_background<T: RenderTarget>(
+    map: &FloorMap,
+    camera: &Camera,
+    ctx: &mut RenderContext<T>,
+    visibility: impl Fn(TilePos, &Tile) -> TileVisibility,
 ) -> Result<(), SDLError> {
-    let render_top_left = region.top_left();
     // Need to paint the default floor under every tile in case the background sprite being
     // used is actually something that doesn't take up the entire space (e.g. a column tile)
-    let default_floor = ctx.map_sprites.floor_sprite(Default::default());
+    let default_floor = ctx.map_sprites.floor_sprite(Default::default(), Default::default());
 
     let tile_size = map.tile_size() as i32;
     let grid = map.grid();
+    // Tiles that extend into their neighbours (e.g. pillars) should not be drawn over tiles that
+    // are hidden
+    let is_shown = |tile_pos| visibility(tile_pos, grid.get(tile_pos)) != TileVisibility::Hidden;
 
-    let (top_left, size) = map.grid_area_within(region);
+    let (top_left, size) = map.grid_area_within(camera.visible_world_rect());
     for (row, row_tiles) in grid.rows().enumerate().skip(top_left.row).take(size.rows) {
         for (col, tile) in row_tiles.iter().enumerate().skip(top_left.col).take(size.cols) {
             let tile_pos = TilePos {row, col};
             let pos = tile_pos.center(tile_size);
 
-            if !should_render(tile_pos, tile) {
+            let tile_visibility = visibility(tile_pos, tile);
+            if tile_visibility == TileVisibility::Hidden {
                 // Render an empty tile
                 let sprite = ctx.sprites.get(ctx.map_sprites.empty_tile_sprite());
-                render_sprite(pos, tile_size as u32, sprite, ctx, render_top_left)?;
+                render_sprite(pos, tile_size as u32, sprite, ctx, camera, &SpriteClip::Full, None)?;
                 continue;
             }
 
@@ -277,7 +985,17 @@ fn render_background<T: RenderTarget>(
 
             for sprite in tile_layers {
                 let sprite = ctx.sprites.get(sprite);
-                render_sprite(pos, tile_size as u32, sprite, ctx, render_top_left)?;
+                let clip = SpriteClip::for_tiles(sprite.dest_rect(pos, tile_size as u32), tile_pos,
+                    tile_size as u32, grid.dimensions(), is_shown);
+                render_sprite(pos, tile_size as u32, sprite, ctx, camera, &clip, None)?;
+            }
+
+            if tile_visibility == TileVisibility::Explored {
+                // Draw a shadow over the tile so it is clear that it is not currently visible
+                ctx.canvas.set_blend_mode(BlendMode::Blend);
+                ctx.canvas.set_draw_color(Color::RGBA(0, 0, 0, EXPLORED_SHADOW_ALPHA));
+                ctx.canvas.fill_rect(Rect::from_center(camera.world_to_screen(pos), tile_size as u32, tile_size as u32))
+                    .map_err(SDLError::Sdl)?;
             }
         }
     }
@@ -285,48 +1003,509 @@ fn render_background<T: RenderTarget>(
     Ok(())
 }
 
+/// Renders the given clip of a sprite, multiplied by the given tint color, if any
 fn render_sprite<T: RenderTarget>(
     center: Point,
     tile_size: u32,
     sprite: &SpriteImage,
     ctx: &mut RenderContext<T>,
-    render_top_left: Point,
+    camera: &Camera,
+    clip: &SpriteClip,
+    tint: Option<Color>,
 ) -> Result<(), SDLError> {
-    //TODO: This code needs to be way more robust. Currently, we make a bunch of assumptions and
-    // there is actually no way that this code will work for sprites larger than one tile once we
-    // make the should_render logic more complicated. In reality, what we need to do is run
-    // should_render on each tile-sized area of the sprite to be rendered and then clip the parts
-    // of the sprite that shouldn't be rendered. This is more complicated behaviour and we will
-    // eventually need to do this to continue advancing this code.
-
     let texture = ctx.textures.get(sprite.texture_id);
-    // Source rect should never be modified here because it represents the exact place
-    // on the spritesheet of this sprite. No reaosn to modify that.
-    let source_rect = sprite.region;
-
-    // The destination rectangle that this sprite should be aligned against. The sprite
-    // is not required to be confined to this rectangle. It is only used to decide how
-    // the sprite's layout should be calculated.
-    let dest = Rect::from_center(
-        // Need to subtract the position (world coordinates) of this tile from the position
-        // in world coordinates of the top-left corner of the screen so that we are left
-        // with the position of this sprite on the screen in screen coordinates
-        center - render_top_left,
-        tile_size,
-        tile_size,
-    );
-    let mut dest_rect = sprite.apply_anchor(dest);
-
-    let dest_offset = sprite.dest_offset;
-    dest_rect.offset(dest_offset.x(), dest_offset.y());
-
-    ctx.canvas.copy_ex(
-        texture,
-        source_rect,
-        dest_rect,
-        0.0,
-        None,
-        sprite.flip_horizontal,
-        sprite.flip_vertical,
-    ).map_err(SDLError)
+    if let Some(tint) = tint {
+        set_color_mod(texture, tint);
+    }
+
+    let canvas = &mut ctx.canvas;
+    draw_sprite(center, tile_size, sprite, clip, |source_rect, dest_rect| {
+        // The pieces are positioned on the map, but they are drawn on the screen
+        let dest_rect = camera.world_rect_to_screen(dest_rect);
+
+        canvas.copy_ex(
+            texture,
+            source_rect,
+            dest_rect,
+            0.0,
+            None,
+            sprite.flip_horizontal,
+            sprite.flip_vertical,
+        ).map_err(SDLError::Sdl)
+    })?;
+
+    // Every sprite on the same spritesheet shares the texture, so the tint must not stay on it
+    if tint.is_some() {
+        set_color_mod(texture, Color::RGB(255, 255, 255));
+    }
+
+    Ok(())
+}
+
+/// Calls `draw` with the (source, destination) pair of each piece that should be copied in order
+/// to draw the given clip of a sprite centered at `center`. Destinations are positioned on the map.
+fn draw_sprite<E>(
+    center: Point,
+    tile_size: u32,
+    sprite: &SpriteImage,
+    clip: &SpriteClip,
+    mut draw: impl FnMut(Rect, Rect) -> Result<(), E>,
+) -> Result<(), E> {
+    // The sprite is aligned against the (tile_size)x(tile_size) square around its center and
+    // scaled from the size of the tiles in its spritesheet
+    let dest_rect = sprite.dest_rect(center, tile_size);
+
+    for (source_rect, dest_rect) in sprite_pieces(sprite, dest_rect, clip) {
+        draw(source_rect, dest_rect)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the color that every pixel of the given texture is multiplied by when it is drawn
+fn set_color_mod(texture: &Texture<'_>, color: Color) {
+    // Safe because the texture stays valid for as long as it is borrowed here. The sdl2 crate only
+    // allows this through a mutable reference, but textures are shared by the texture manager.
+    // Only the color of the texture changes, and it is always reset right after drawing.
+    unsafe {
+        sdl2::sys::SDL_SetTextureColorMod(texture.raw(), color.r, color.g, color.b);
+    }
+}
+
+/// Returns the color used for the given status effect, both for its icon and to tint enemies
+fn status_color(kind: StatusKind) -> Color {
+    match kind {
+        StatusKind::Poison => Color::RGB(110, 220, 80),
+        StatusKind::Slow => Color::RGB(120, 160, 245),
+    }
+}
+
+/// Draws an icon for each of the given status effects in the top left corner of the screen
+fn render_status_icons<T: RenderTarget>(kinds: &[StatusKind], ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+    let mut left = STATUS_ICON_MARGIN;
+    for &kind in kinds {
+        let parts: &[_] = match kind {
+            StatusKind::Poison => &POISON_ICON,
+            StatusKind::Slow => &SLOW_ICON,
+        };
+        render_hud_icon(Point::new(left, STATUS_ICON_MARGIN), parts, status_color(kind), ctx)?;
+
+        left += STATUS_ICON_SIZE as i32 + STATUS_ICON_MARGIN / 2;
+    }
+
+    Ok(())
+}
+
+/// Draws an icon for the given weapon in the bottom left corner of the screen
+fn render_weapon_icon<T: RenderTarget>(kind: WeaponKind, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+    let parts: &[_] = match kind {
+        WeaponKind::Dagger => &DAGGER_ICON,
+        WeaponKind::Sword => &SWORD_ICON,
+        WeaponKind::Spear => &SPEAR_ICON,
+    };
+    let (_, screen_height) = ctx.canvas.logical_size();
+    let top = screen_height as i32 - STATUS_ICON_MARGIN - STATUS_ICON_SIZE as i32;
+    render_hud_icon(Point::new(STATUS_ICON_MARGIN, top), parts, Color::RGB(220, 220, 220), ctx)
+}
+
+/// Draws an icon made of the given rectangles over a dark square with the given top left corner
+fn render_hud_icon<T: RenderTarget>(
+    top_left: Point,
+    parts: &[(i32, i32, i32, i32)],
+    color: Color,
+    ctx: &mut RenderContext<T>,
+) -> Result<(), SDLError> {
+    ctx.canvas.set_blend_mode(BlendMode::Blend);
+    let background = Rect::new(top_left.x(), top_left.y(), STATUS_ICON_SIZE, STATUS_ICON_SIZE);
+    ctx.canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    ctx.canvas.fill_rect(background).map_err(SDLError::Sdl)?;
+
+    // Icons are 6x8, centered in the background
+    let top_left = background.top_left().offset(2, 1);
+    ctx.canvas.set_draw_color(color);
+    for &(x, y, width, height) in parts {
+        let part = Rect::new(top_left.x() + x, top_left.y() + y, width as u32, height as u32);
+        ctx.canvas.fill_rect(part).map_err(SDLError::Sdl)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the (source, destination) pairs that should be copied in order to draw the given clip
+/// of a sprite that would otherwise be drawn entirely into `dest`
+fn sprite_pieces(sprite: &SpriteImage, dest: Rect, clip: &SpriteClip) -> Vec<(Rect, Rect)> {
+    match clip {
+        SpriteClip::Hidden => Vec::new(),
+        // Source rect should never be modified when drawing the entire sprite because it
+        // represents the exact place on the spritesheet of this sprite
+        SpriteClip::Full => vec![(sprite.region, dest)],
+        SpriteClip::Partial(visible) => visible.iter()
+            .map(|&part| (clipped_source(sprite, dest, part), part))
+            .collect(),
+    }
+}
+
+/// Returns the region of the spritesheet that should be drawn into `part`, a part of the
+/// rectangle `dest` that the entire sprite would be drawn into
+fn clipped_source(sprite: &SpriteImage, dest: Rect, part: Rect) -> Rect {
+    let region = sprite.region;
+
+    // Maps the span [start, end) of dest (relative to its start) to the matching span of the
+    // region. Flipped sprites are drawn mirrored, so the span is mirrored before it is mapped.
+    let map_span = |start: i32, end: i32, dest_len: u32, region_len: u32, flipped: bool| {
+        let (dest_len, region_len) = (dest_len as i32, region_len as i32);
+        let (start, end) = if flipped { (dest_len - end, dest_len - start) } else { (start, end) };
+        // Rounding outwards so that no pixel of the region is skipped between adjacent parts
+        let region_start = start * region_len / dest_len;
+        let region_end = (end * region_len + dest_len - 1) / dest_len;
+        (region_start, (region_end - region_start) as u32)
+    };
+
+    let (x, width) = map_span(part.left() - dest.left(), part.right() - dest.left(),
+        dest.width(), region.width(), sprite.flip_horizontal);
+    let (y, height) = map_span(part.top() - dest.top(), part.bottom() - dest.top(),
+        dest.height(), region.height(), sprite.flip_vertical);
+    Rect::new(region.x() + x, region.y() + y, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder};
+
+    use crate::assets::TextureId;
+    use crate::map::GridSize;
+    use crate::map_sprites::WallSprite;
+    use crate::test_helpers::{walled_room, open_room, rooms_side_by_side};
+
+    #[test]
+    fn outlines_classified_by_components() {
+        assert_eq!(DebugOutline::classify(false, false, false), DebugOutline::Solid);
+        assert_eq!(DebugOutline::classify(false, false, true), DebugOutline::Ghost);
+        assert_eq!(DebugOutline::classify(false, true, false), DebugOutline::Enemy);
+        assert_eq!(DebugOutline::classify(true, false, false), DebugOutline::Player);
+        // Being a player or an enemy matters more than being a ghost
+        assert_eq!(DebugOutline::classify(true, false, true), DebugOutline::Player);
+        assert_eq!(DebugOutline::classify(false, true, true), DebugOutline::Enemy);
+
+        // Every outline can be told apart in the legend
+        let colors: HashSet<_> = DebugOutline::ALL.iter().map(|outline| {
+            let Color {r, g, b, ..} = outline.color();
+            (r, g, b)
+        }).collect();
+        assert_eq!(colors.len(), DebugOutline::ALL.len());
+    }
+
+    #[test]
+    fn solid_tiles_only_within_region() {
+        let mut map = walled_room(10, 12, 16);
+        map.grid_mut().place_tile(TilePos {row: 3, col: 4}, Tile::new_wall(WallSprite::default()));
+        map.grid_mut().place_tile(TilePos {row: 7, col: 9}, Tile::new_wall(WallSprite::default()));
+
+        // Covers part of the top-left corner of the room, including the wall at (3, 4)
+        let region = Rect::new(8, 8, 5 * 16, 3 * 16);
+        let tiles: HashSet<_> = solid_tiles_within(&map, region).collect();
+        let expected: HashSet<_> = map.grid().tile_positions()
+            .filter(|&pos| map.grid().get(pos).is_wall())
+            .filter(|&pos| pos.tile_rect(16).has_intersection(region))
+            .collect();
+        assert_eq!(tiles, expected);
+        assert!(tiles.contains(&TilePos {row: 3, col: 4}));
+        assert!(!tiles.contains(&TilePos {row: 7, col: 9}));
+        assert!(!tiles.contains(&TilePos {row: 9, col: 11}));
+    }
+
+    #[test]
+    fn grid_lines_only_on_tile_edges_within_region() {
+        let (columns, rows) = grid_lines(Rect::new(5, -3, 40, 20), 16);
+        assert_eq!(columns, &[16, 32]);
+        assert_eq!(rows, &[0, 16]);
+
+        // Lines on the top and left edges of the region are included, but not the bottom and right
+        let (columns, rows) = grid_lines(Rect::new(32, 16, 32, 16), 16);
+        assert_eq!(columns, &[32, 48]);
+        assert_eq!(rows, &[16]);
+    }
+
+    #[test]
+    fn revealed_rooms_are_dimmed() {
+        // Two rooms side by side with no entrance between them
+        let (map, rooms) = rooms_side_by_side(5, &[5, 5], false, 16);
+        let player_room = *map.room(rooms[0]).boundary();
+        let revealed_room = *map.room(rooms[1]).boundary();
+
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Door>();
+        let (positions, doors) = world.system_data::<(ReadStorage<'_, Position>, ReadStorage<'_, Door>)>();
+        let visible_tiles = find_visible_tiles(map.grid(), player_room.center_tile(), 16, &positions, &doors);
+
+        let mut explored = ExploredTiles::new(map.grid().dimensions());
+        explored.explore(visible_tiles.iter().cloned());
+        explored.reveal_rooms(&map, &[rooms[1]]);
+
+        for pos in player_room.tile_positions() {
+            assert_eq!(tile_visibility(&visible_tiles, &explored, pos), TileVisibility::Visible);
+        }
+        // Revealing a room explores it but does not make it visible
+        for pos in revealed_room.tile_positions() {
+            assert_eq!(tile_visibility(&visible_tiles, &explored, pos), TileVisibility::Explored);
+        }
+
+        // Nothing is visible in a room that hasn't been explored or revealed
+        let explored = ExploredTiles::new(map.grid().dimensions());
+        for pos in revealed_room.tile_positions() {
+            assert_eq!(tile_visibility(&HashSet::new(), &explored, pos), TileVisibility::Hidden);
+        }
+    }
+
+    #[test]
+    fn sorted_by_layer_then_y() {
+        // A pillar in the middle of a room with nothing hidden
+        let mut map = open_room(5, 5, 16);
+        let pillar = TilePos {row: 2, col: 2};
+        map.grid_mut().place_tile(pillar, Tile::new_wall(WallSprite {
+            alt: WallSpriteAlternate::BrickPillar,
+            ..WallSprite::default()
+        }));
+        let pillar_pos = pillar.center(16);
+        let region = Rect::new(0, 0, 5 * 16, 5 * 16);
+        let mut sprites = SpriteManager::default();
+        let map_sprites = MapSprites::from_dungeon_spritesheet(TextureId::placeholder(0), &mut sprites);
+
+        let mut world = World::new();
+        setup(&mut world.res);
+        let player_sprite = map_sprites.cage();
+        // Standing right behind (north of) the pillar
+        let player = world.create_entity()
+            .with(Position(pillar_pos - Point::new(0, 10)))
+            .with(Sprite(player_sprite))
+            .build();
+        // Stairs are always drawn first, even if they are further south
+        world.create_entity()
+            .with(Position(pillar_pos + Point::new(0, 16)))
+            .with(Sprite(map_sprites.staircase_down_right()))
+            .with(RenderLayer::FIXTURES)
+            .build();
+
+        let sprites_in_order = |world: &World| -> Vec<_> {
+            drawables(&world.system_data(), &map, region, &map_sprites, &sprites, |_, _| TileVisibility::Visible)
+                .into_iter().map(|drawable| drawable.sprite).collect()
+        };
+        let pillar_sprite = map.grid().get(pillar).background_sprite(&map_sprites);
+        assert_eq!(sprites_in_order(&world),
+            &[map_sprites.staircase_down_right(), player_sprite, pillar_sprite]);
+
+        // Standing in front of (south of) the pillar
+        world.write_storage::<Position>().insert(player, Position(pillar_pos + Point::new(0, 10))).unwrap();
+        assert_eq!(sprites_in_order(&world),
+            &[map_sprites.staircase_down_right(), pillar_sprite, player_sprite]);
+    }
+
+    #[test]
+    fn sprites_clipped_to_drawable_tiles() {
+        let grid_size = GridSize {rows: 4, cols: 4};
+        let home = TilePos {row: 2, col: 1};
+        let tile = home.tile_rect(16);
+        let nothing_else = |_| false;
+
+        // Sprites within their own tile are always drawn in full
+        assert_eq!(SpriteClip::for_tiles(tile, home, 16, grid_size, nothing_else), SpriteClip::Full);
+        // Sprites that extend into other tiles are only drawn where those tiles can be drawn over
+        let tall = Rect::new(tile.x(), tile.y() - 16, 16, 32);
+        assert_eq!(SpriteClip::for_tiles(tall, home, 16, grid_size, |_| true), SpriteClip::Full);
+        assert_eq!(SpriteClip::for_tiles(tall, home, 16, grid_size, nothing_else),
+            SpriteClip::Partial(vec![tile]));
+        // Characters overlap several tiles without lining up with any of them
+        let character = Rect::new(tile.x() - 4, tile.y() - 20, 24, 24);
+        let north = TilePos {row: 1, col: 1};
+        assert_eq!(SpriteClip::for_tiles(character, home, 16, grid_size, |pos| pos == north),
+            SpriteClip::Partial(vec![Rect::new(16, 16, 16, 16), Rect::new(16, 32, 16, 4)]));
+
+        // Nothing is drawn off of the grid, even for a sprite belonging to a tile on the edge
+        let corner = TilePos {row: 0, col: 0};
+        assert_eq!(SpriteClip::for_tiles(Rect::new(0, -16, 16, 32), corner, 16, grid_size, |_| true),
+            SpriteClip::Partial(vec![Rect::new(0, 0, 16, 16)]));
+        // A sprite that does not even cover its own tile (e.g. an offset character) can be hidden
+        assert_eq!(SpriteClip::for_tiles(Rect::new(16, 0, 8, 8), corner, 16, grid_size, nothing_else),
+            SpriteClip::Hidden);
+    }
+
+    #[test]
+    fn clipped_source_mirrors_flipped_sprites() {
+        let sprite = SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(160, 16, 16, 32));
+        // Drawn at twice the size of the spritesheet
+        let dest = Rect::new(0, 0, 32, 64);
+        let bottom_half = Rect::new(0, 32, 32, 32);
+        assert_eq!(clipped_source(&sprite, dest, bottom_half), Rect::new(160, 32, 16, 16));
+        // The bottom of a vertically flipped sprite is the top of the region on the spritesheet
+        let flipped = sprite.clone().flip_vertically();
+        assert_eq!(clipped_source(&flipped, dest, bottom_half), Rect::new(160, 16, 16, 16));
+        // Flipping horizontally makes no difference to a part that spans the entire width
+        let flipped = sprite.flip_horizontally();
+        assert_eq!(clipped_source(&flipped, dest, bottom_half), Rect::new(160, 32, 16, 16));
+        assert_eq!(clipped_source(&flipped, dest, Rect::new(8, 0, 24, 64)), Rect::new(160, 16, 12, 32));
+    }
+
+    #[test]
+    fn vertical_door_clipped_at_fog_boundary() {
+        // The door is in the bottom row and its top half extends into the row above it. Small
+        // tiles keep the rendered image small enough to compare against by eye.
+        let tile_size = 4;
+        let grid_size = GridSize {rows: 2, cols: 3};
+        let door = TilePos {row: 1, col: 1};
+        let mut sprites = SpriteManager::default();
+        let map_sprites = MapSprites::from_dungeon_spritesheet(TextureId::placeholder(0), &mut sprites);
+        let sprite = sprites.get(map_sprites.door_vertical());
+        let center = door.center(tile_size as i32);
+
+        // Draws the door the same way render_sprite does, labelling each pixel with the half of
+        // the sprite (upper or lower) that it was copied from
+        let render = |visible_rows: &[usize]| {
+            let clip = SpriteClip::for_tiles(sprite.dest_rect(center, tile_size), door, tile_size, grid_size,
+                |pos| visible_rows.contains(&pos.row));
+            let size = grid_size.to_rect(tile_size);
+            let mut image = vec![vec!['.'; size.width() as usize]; size.height() as usize];
+            draw_sprite(center, tile_size, sprite, &clip, |source, dest| {
+                for y in dest.top()..dest.bottom() {
+                    let source_y = source.y() + (y - dest.y()) * source.height() as i32 / dest.height() as i32;
+                    let is_upper = source_y - sprite.region.y() < sprite.region.height() as i32 / 2;
+                    for x in dest.left()..dest.right() {
+                        image[y as usize][x as usize] = if is_upper { 'U' } else { 'L' };
+                    }
+                }
+                Ok::<_, ()>(())
+            }).unwrap();
+            image.into_iter().map(|row| row.into_iter().collect::<String>()).collect::<Vec<_>>().join("\n")
+        };
+
+        let expected_visible = "\
+            ....UUUU....\n\
+            ....UUUU....\n\
+            ....UUUU....\n\
+            ....UUUU....\n\
+            ....LLLL....\n\
+            ....LLLL....\n\
+            ....LLLL....\n\
+            ....LLLL....";
+        assert_eq!(render(&[0, 1]), expected_visible);
+
+        // Only the half of the door within the visible row is drawn
+        let expected_clipped = "\
+            ............\n\
+            ............\n\
+            ............\n\
+            ............\n\
+            ....LLLL....\n\
+            ....LLLL....\n\
+            ....LLLL....\n\
+            ....LLLL....";
+        assert_eq!(render(&[1]), expected_clipped);
+    }
+
+    #[test]
+    fn wall_fronts_cover_characters_behind_them() {
+        // A wall between two floor tiles. Small tiles keep the rendered image small enough to
+        // compare against by eye.
+        let tile_size = 4;
+        let mut map = open_room(3, 1, tile_size);
+        let wall = TilePos {row: 1, col: 0};
+        map.grid_mut().place_tile(wall, Tile::new_wall(WallSprite::default()));
+        let region = map.grid().dimensions().to_rect(tile_size);
+
+        let mut sprites = SpriteManager::default();
+        let map_sprites = MapSprites::from_dungeon_spritesheet(TextureId::placeholder(0), &mut sprites);
+        let player_sprite = sprites.add(SpriteImage::new_unflipped(TextureId::placeholder(0), Rect::new(0, 0, 16, 16)));
+        let mut world = World::new();
+        setup(&mut world.res);
+        let player = world.create_entity()
+            .with(Position(Point::new(0, 0)))
+            .with(Sprite(player_sprite))
+            .build();
+
+        // Renders the level the same way render_area does, labelling each pixel with what was
+        // drawn there last: the floor (.), the wall (#), or the player (P)
+        let render = |world: &World| {
+            let mut image: Vec<Vec<_>> = map.grid().rows()
+                .flat_map(|row| vec![row.iter().map(|tile| if tile.is_wall() { '#' } else { '.' }).collect::<Vec<_>>(); tile_size as usize])
+                .map(|row| row.into_iter().flat_map(|c| vec![c; tile_size as usize]).collect())
+                .collect();
+            let drawables = drawables(&world.system_data(), &map, region, &map_sprites, &sprites, |_, _| TileVisibility::Visible);
+            let sprite_clip = |_, _, _| SpriteClip::Full;
+            for (drawable, clip) in clip_drawables(drawables, &sprites, tile_size, sprite_clip) {
+                let label = if drawable.is_wall_front { '#' } else { 'P' };
+                draw_sprite(drawable.pos, tile_size, sprites.get(drawable.sprite), &clip, |_, dest| {
+                    for y in dest.top()..dest.bottom() {
+                        for x in dest.left()..dest.right() {
+                            image[y as usize][x as usize] = label;
+                        }
+                    }
+                    Ok::<_, ()>(())
+                }).unwrap();
+            }
+            image.into_iter().map(|row| row.into_iter().collect::<String>()).collect::<Vec<_>>().join("\n")
+        };
+
+        // Standing north of the wall with the bottom row of the sprite overlapping it
+        world.write_storage::<Position>().insert(player, Position(Point::new(2, 3))).unwrap();
+        let expected_behind = "\
+            ....\n\
+            PPPP\n\
+            PPPP\n\
+            PPPP\n\
+            ####\n\
+            ####\n\
+            ####\n\
+            ####\n\
+            ....\n\
+            ....\n\
+            ....\n\
+            ....";
+        assert_eq!(render(&world), expected_behind);
+
+        // Standing south of the wall with the top row of the sprite overlapping it
+        world.write_storage::<Position>().insert(player, Position(Point::new(2, 9))).unwrap();
+        let expected_in_front = "\
+            ....\n\
+            ....\n\
+            ....\n\
+            ....\n\
+            ####\n\
+            ####\n\
+            ####\n\
+            PPPP\n\
+            PPPP\n\
+            PPPP\n\
+            PPPP\n\
+            ....";
+        assert_eq!(render(&world), expected_in_front);
+    }
+
+    #[test]
+    fn shaking_never_leaves_level() {
+        let level_boundary = Rect::new(0, 0, 50 * 16, 40 * 16);
+        let (screen_width, screen_height) = (320, 240);
+        let mut shake = ScreenShake::default();
+        shake.start(6, 30);
+        // Centered on each corner of the level
+        let corners = [
+            Point::new(0, 0),
+            Point::new(level_boundary.right(), 0),
+            Point::new(0, level_boundary.bottom()),
+            Point::new(level_boundary.right(), level_boundary.bottom()),
+        ];
+        for &corner in &corners {
+            let camera = Camera::new(corner, (screen_width, screen_height), level_boundary);
+            let mut shake = shake;
+            for _ in 0..30 {
+                let shaken = camera.shifted(shake.offset());
+                let screen = shaken.visible_world_rect();
+                assert_eq!(screen.union(level_boundary), level_boundary, "{:?} at {:?}", screen, corner);
+                // The screen is so far past each corner that shaking cannot move it at all
+                assert_eq!(shaken.top_left(), camera.top_left());
+                shake.step(1);
+            }
+        }
+    }
 }
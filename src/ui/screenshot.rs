@@ -0,0 +1,190 @@
+use std::thread;
+use std::sync::mpsc::{self, Sender, Receiver};
+
+use sdl2::{
+    image::SaveSurface,
+    pixels::PixelFormatEnum,
+    render::{Canvas, RenderTarget},
+    surface::Surface,
+};
+
+use crate::generator::MapKey;
+
+use super::SDLError;
+
+/// Every pixel is read as 4 bytes (red, green, blue, alpha)
+const PIXEL_FORMAT: PixelFormatEnum = PixelFormatEnum::ABGR8888;
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The number of seconds in a day
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Returns the name of the file that a screenshot taken at the given time (in seconds since the
+/// Unix epoch) of the map with the given key is saved to. Times are in UTC so that the name does
+/// not depend on where the game is played.
+pub fn screenshot_filename(timestamp: u64, key: MapKey) -> String {
+    let (year, month, day) = civil_from_days(timestamp / SECONDS_PER_DAY);
+    let seconds = timestamp % SECONDS_PER_DAY;
+    format!("screenshot-{:04}{:02}{:02}-{:02}{:02}{:02}-{}.png",
+        year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60, key)
+}
+
+/// Converts a number of days since the Unix epoch to a (year, month, day) date. Based on the
+/// `civil_from_days` algorithm from http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shifted so that each era (400 years) starts on March 1st, putting leap days at the very end
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Picks the window pixel at the center of every logical pixel so that the screenshot has exactly
+/// one pixel for every pixel the game drew, no matter how much the game was scaled up to fill the
+/// window. The game is assumed to be scaled and centered just like in `window_to_world`.
+///
+/// `pitch` is the length (in bytes) of each row of the window pixels, which may be longer than the
+/// width of the window. The returned rows have no padding.
+fn to_logical_pixels(
+    pixels: &[u8],
+    pitch: usize,
+    (window_width, window_height): (u32, u32),
+    (logical_width, logical_height): (u32, u32),
+    integer_scale: bool,
+) -> Vec<u8> {
+    let scale_x = window_width as f64 / logical_width as f64;
+    let scale_y = window_height as f64 / logical_height as f64;
+    let scale = scale_x.min(scale_y);
+    let scale = if integer_scale { scale.floor().max(1.0) } else { scale };
+
+    // The game is centered in the window
+    let bar_x = (window_width as f64 - logical_width as f64 * scale) / 2.0;
+    let bar_y = (window_height as f64 - logical_height as f64 * scale) / 2.0;
+    let window_pos = |bar: f64, pos: u32, size: u32| {
+        let pos = (bar + (pos as f64 + 0.5) * scale).floor().max(0.0) as usize;
+        pos.min(size as usize - 1)
+    };
+
+    let mut logical = Vec::with_capacity(logical_width as usize * logical_height as usize * BYTES_PER_PIXEL);
+    for y in 0..logical_height {
+        let row = window_pos(bar_y, y, window_height) * pitch;
+        for x in 0..logical_width {
+            let start = row + window_pos(bar_x, x, window_width) * BYTES_PER_PIXEL;
+            logical.extend_from_slice(&pixels[start..start + BYTES_PER_PIXEL]);
+        }
+    }
+    logical
+}
+
+/// Encodes the given pixels as a PNG file at the given path
+fn save_png(mut pixels: Vec<u8>, (width, height): (u32, u32), path: &str) -> Result<(), String> {
+    let pitch = width * BYTES_PER_PIXEL as u32;
+    let surface = Surface::from_data(&mut pixels, width, height, pitch, PIXEL_FORMAT)?;
+    surface.save(path)
+}
+
+/// Saves screenshots of the game in the background so that encoding them never slows down the
+/// game
+pub struct Screenshots {
+    sender: Sender<Result<String, String>>,
+    receiver: Receiver<Result<String, String>>,
+}
+
+impl Default for Screenshots {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {sender, receiver}
+    }
+}
+
+impl Screenshots {
+    /// Reads the frame currently drawn on the given canvas and starts saving it to the given
+    /// path. Must be called after the frame is drawn but before it is presented. Only reading the
+    /// frame happens right away.
+    pub fn capture<T: RenderTarget>(&self, canvas: &Canvas<T>, integer_scale: bool, path: String) -> Result<(), SDLError> {
+        let window_size = canvas.output_size().map_err(SDLError::Sdl)?;
+        // Canvases without a logical size are drawn at the size of the window
+        let logical_size = match canvas.logical_size() {
+            (0, _) | (_, 0) => window_size,
+            size => size,
+        };
+        let pixels = canvas.read_pixels(None, PIXEL_FORMAT).map_err(SDLError::Sdl)?;
+        let pitch = window_size.0 as usize * BYTES_PER_PIXEL;
+
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let pixels = to_logical_pixels(&pixels, pitch, window_size, logical_size, integer_scale);
+            let result = save_png(pixels, logical_size, &path)
+                .map(|()| path.clone())
+                .map_err(|err| format!("unable to save `{}`: {}", path, err));
+            // Nobody is left to tell if the game has already ended
+            sender.send(result).ok();
+        });
+        Ok(())
+    }
+
+    /// Returns the path of every screenshot that finished saving since the last call, or the
+    /// reason that it could not be saved
+    pub fn finished(&self) -> Vec<Result<String, String>> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn filename_has_utc_time_and_map_key() {
+        let key: MapKey = StdRng::seed_from_u64(1).gen();
+        assert_eq!(screenshot_filename(0, key), format!("screenshot-19700101-000000-{}.png", key));
+        assert_eq!(screenshot_filename(1_700_000_000, key), format!("screenshot-20231114-221320-{}.png", key));
+        // Leap day and the last second of a day
+        assert_eq!(screenshot_filename(951_868_799, key), format!("screenshot-20000229-235959-{}.png", key));
+        assert_eq!(screenshot_filename(1_798_761_599, key), format!("screenshot-20261231-235959-{}.png", key));
+    }
+
+    #[test]
+    fn one_pixel_per_logical_pixel() {
+        // Every pixel of the window is (x, y, 0, 255) and every byte of padding is 99
+        let window_pixels = |width: u32, height: u32, padding: usize| {
+            let pitch = width as usize * BYTES_PER_PIXEL + padding;
+            let mut pixels = vec![99; pitch * height as usize];
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let start = y * pitch + x * BYTES_PER_PIXEL;
+                    pixels[start..start + BYTES_PER_PIXEL].copy_from_slice(&[x as u8, y as u8, 0, 255]);
+                }
+            }
+            (pixels, pitch)
+        };
+
+        // Not scaled, with padding at the end of every row that must never be copied
+        let (pixels, pitch) = window_pixels(4, 2, 8);
+        assert_eq!(to_logical_pixels(&pixels, pitch, (4, 2), (4, 2), false), &[
+            0, 0, 0, 255, 1, 0, 0, 255, 2, 0, 0, 255, 3, 0, 0, 255,
+            0, 1, 0, 255, 1, 1, 0, 255, 2, 1, 0, 255, 3, 1, 0, 255,
+        ]);
+
+        // Scaled up by 2: every logical pixel is read from the center of a 2x2 block
+        let (pixels, pitch) = window_pixels(4, 4, 4);
+        assert_eq!(to_logical_pixels(&pixels, pitch, (4, 4), (2, 2), false), &[
+            1, 1, 0, 255, 3, 1, 0, 255,
+            1, 3, 0, 255, 3, 3, 0, 255,
+        ]);
+
+        // A window wider than the game has bars on the left and right that are left out
+        let (pixels, pitch) = window_pixels(8, 2, 0);
+        assert_eq!(to_logical_pixels(&pixels, pitch, (8, 2), (2, 1), true), &[
+            3, 1, 0, 255, 5, 1, 0, 255,
+        ]);
+    }
+}
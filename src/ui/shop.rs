@@ -0,0 +1,178 @@
+use sdl2::render::{RenderTarget, BlendMode};
+use specs::Entity;
+
+use crate::components::Item;
+use crate::resources::{Event, Key};
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// How dark the game is behind the list of items, from 0 (not at all) to 255 (black)
+const BACKGROUND_DARKNESS: u8 = 200;
+
+/// The distance (in px) between the top of the screen and the top of the title
+const TITLE_TOP: u32 = 40;
+/// The height of the title
+const TITLE_HEIGHT: f32 = 20.0;
+/// The height of each line below the title
+const LINE_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of one line and the top of the next line
+const LINE_SPACING: u32 = 14;
+
+/// Something that the player chose to do in the shop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShopAction {
+    /// Buy the item at the given position in the stock
+    Buy(usize),
+    /// Leave the shop and go back to the game
+    Close,
+}
+
+/// The list of items sold by a merchant. The game is paused while it is open.
+#[derive(Debug)]
+pub struct Shop {
+    merchant: Entity,
+    /// The position in the stock of the item that is currently selected
+    selected: usize,
+    /// True if A or Start was pressed while the shop was open
+    buying: bool,
+    /// True if B was pressed while the shop was open
+    closing: bool,
+}
+
+impl Shop {
+    /// Opens the shop of the given merchant with the first item selected
+    pub fn new(merchant: Entity) -> Self {
+        Self {merchant, selected: 0, buying: false, closing: false}
+    }
+
+    /// Returns the merchant selling the items
+    pub fn merchant(&self) -> Entity {
+        self.merchant
+    }
+
+    /// Returns the position in the stock of the item that is currently selected
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Moves the selection with the up and down keys, wrapping around at either end of a stock of
+    /// the given length. Buying and closing happen once A/Start or B is pressed and released so
+    /// that the release is never seen by the game. Clicks are ignored. Every other event is
+    /// returned so that the game still finds out about any key that was pressed or released while
+    /// the shop was open.
+    pub fn dispatch(&mut self, events: Vec<Event>, stock_len: usize) -> (Vec<Event>, Vec<ShopAction>) {
+        // Items may have been bought since the last time the shop was dispatched
+        self.selected = self.selected.min(stock_len.saturating_sub(1));
+
+        let mut passed_on = Vec::new();
+        let mut actions = Vec::new();
+        for event in events {
+            match event {
+                Event::KeyDown(Key::UpArrow) => if stock_len > 0 {
+                    self.selected = (self.selected + stock_len - 1) % stock_len;
+                },
+                Event::KeyDown(Key::DownArrow) => if stock_len > 0 {
+                    self.selected = (self.selected + 1) % stock_len;
+                },
+                Event::KeyDown(Key::A) | Event::KeyDown(Key::Start) => self.buying = true,
+                // A release without a press was held down from before the shop was opened
+                Event::KeyUp(Key::A) | Event::KeyUp(Key::Start) => if self.buying {
+                    self.buying = false;
+                    actions.push(ShopAction::Buy(self.selected));
+                },
+                Event::KeyDown(Key::B) => self.closing = true,
+                Event::KeyUp(Key::B) => if self.closing {
+                    self.closing = false;
+                    actions.push(ShopAction::Close);
+                },
+                // Nothing happens when the arrows are released
+                Event::KeyUp(Key::UpArrow) | Event::KeyUp(Key::DownArrow) => {},
+                // Clicks have no release to pair up with, so they are simply dropped
+                Event::PointerAttack(_) | Event::PointerInteract(_) => {},
+                _ => passed_on.push(event),
+            }
+        }
+        (passed_on, actions)
+    }
+
+    /// Draws the given stock over the game along with the gold that the player has to spend
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>, stock: &[(Item, u32)], gold: u32) -> Result<(), SDLError> {
+        ctx.canvas.set_blend_mode(BlendMode::Blend);
+        ctx.canvas.set_draw_color((0, 0, 0, BACKGROUND_DARKNESS));
+        ctx.canvas.fill_rect(None).map_err(SDLError::Sdl)?;
+
+        let white = (255, 255, 255, 255);
+        let gold_color = (255, 215, 0, 255);
+        Text::new(&ctx.font, "Merchant", TITLE_HEIGHT)
+            .render(ctx.canvas, white, TextLayout::CenteredAtTop(TITLE_TOP))?;
+
+        let mut top = TITLE_TOP + TITLE_HEIGHT as u32 + LINE_SPACING;
+        Text::new(&ctx.font, format!("Your gold: {}", gold), LINE_HEIGHT)
+            .render(ctx.canvas, gold_color, TextLayout::CenteredAtTop(top))?;
+        top += LINE_SPACING * 2;
+
+        if stock.is_empty() {
+            Text::new(&ctx.font, "Sold out", LINE_HEIGHT)
+                .render(ctx.canvas, white, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+        for (i, (item, price)) in stock.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            let color = if *price <= gold { white } else { (150, 150, 150, 255) };
+            Text::new(&ctx.font, format!("{} {} - {} gold", marker, item.name(), price), LINE_HEIGHT)
+                .render(ctx.canvas, color, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+
+        top += LINE_SPACING;
+        Text::new(&ctx.font, "Press A to buy, B to leave", LINE_HEIGHT)
+            .render(ctx.canvas, gold_color, TextLayout::CenteredAtTop(top))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use specs::{World, Builder};
+
+    fn press(key: Key) -> Vec<Event> {
+        vec![Event::KeyDown(key), Event::KeyUp(key)]
+    }
+
+    /// Returns the number of events passed on and the actions chosen
+    fn dispatch(shop: &mut Shop, events: Vec<Event>, stock_len: usize) -> (usize, Vec<ShopAction>) {
+        let (passed_on, actions) = shop.dispatch(events, stock_len);
+        (passed_on.len(), actions)
+    }
+
+    #[test]
+    fn selection_wraps_and_buys_on_release() {
+        let mut world = World::new();
+        let mut shop = Shop::new(world.create_entity().build());
+
+        // Wraps around at both ends
+        assert_eq!(dispatch(&mut shop, press(Key::UpArrow), 3), (0, vec![]));
+        assert_eq!(shop.selected(), 2);
+        dispatch(&mut shop, press(Key::DownArrow), 3);
+        assert_eq!(shop.selected(), 0);
+        dispatch(&mut shop, press(Key::DownArrow), 3);
+
+        // Nothing is bought until the key is released
+        assert_eq!(dispatch(&mut shop, vec![Event::KeyDown(Key::A)], 3), (0, vec![]));
+        assert_eq!(dispatch(&mut shop, vec![Event::KeyUp(Key::A)], 3), (0, vec![ShopAction::Buy(1)]));
+        // A release left over from before the shop was opened does nothing
+        assert_eq!(dispatch(&mut shop, vec![Event::KeyUp(Key::Start)], 3), (0, vec![]));
+
+        // The selection stays within the stock once items are bought
+        dispatch(&mut shop, press(Key::DownArrow), 3);
+        assert_eq!(dispatch(&mut shop, press(Key::Start), 2), (0, vec![ShopAction::Buy(1)]));
+
+        // Other keys are passed on
+        assert_eq!(dispatch(&mut shop, press(Key::RightArrow), 2), (2, vec![]));
+        assert_eq!(dispatch(&mut shop, press(Key::B), 2), (0, vec![ShopAction::Close]));
+    }
+}
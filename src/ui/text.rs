@@ -1,3 +1,5 @@
+use std::mem;
+
 use rusttype::{point, Font, FontCollection, PositionedGlyph, Scale};
 use sdl2::{
     rect::Point,
@@ -7,22 +9,56 @@ use sdl2::{
 
 use super::SDLError;
 
-pub fn load_font() -> Font<'static> {
+pub fn load_font() -> Result<Font<'static>, SDLError> {
     let font_data = include_bytes!("../../assets/fonts/Kenney Pixel Square.ttf");
-    let collection = FontCollection::from_bytes(font_data as &[u8]).unwrap_or_else(|e| {
-        panic!("bug: unable to construct a FontCollection from bytes: {}", e);
-    });
+    let collection = FontCollection::from_bytes(font_data as &[u8])
+        .map_err(|err| SDLError::FontLoad(err.to_string()))?;
     // only succeeds if collection consists of one font
-    collection.into_font().unwrap_or_else(|e| {
-        panic!("bug: unable to turn FontCollection into a Font: {}", e);
-    })
+    collection.into_font().map_err(|err| SDLError::FontLoad(err.to_string()))
+}
+
+/// Splits the given text into lines that are each at most the given width (in px) when drawn at
+/// the given height. Lines are broken between words where possible. Words that are too wide to fit
+/// on a line by themselves (e.g. a map key) are broken between characters.
+pub fn wrap_text(font: &Font, text: &str, height: f32, max_width: f32) -> Vec<String> {
+    let fits = |line: &str| Text::new(font, line, height).width() <= max_width;
+    // Most text fits on a single line, so it is only laid out once
+    if fits(text) {
+        return vec![text.to_string()];
+    }
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let joined = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        if fits(&joined) {
+            line = joined;
+            continue;
+        }
+
+        if !line.is_empty() {
+            lines.push(mem::take(&mut line));
+        }
+        for ch in word.chars() {
+            line.push(ch);
+            // A single character is always kept, even if it does not fit
+            if line.chars().count() > 1 && !fits(&line) {
+                line.pop();
+                lines.push(mem::replace(&mut line, ch.to_string()));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
 }
 
 /// The way the text layout will be calculated on the screen
 #[derive(Debug, Clone)]
 pub enum TextLayout {
-    /// Centered in the middle of the screen
-    Centered,
+    /// Centered horizontally with the top of the text rect at the given y-coordinate
+    CenteredAtTop(u32),
     /// Top-left corner of text rect will be at the given point
     TopLeftAt(Point),
 }
@@ -59,7 +95,7 @@ impl<'a> Text<'a> {
         let glyphs: Vec<PositionedGlyph<'a>> = font.layout(text, scale, offset).collect();
 
         let width = glyphs.iter()
-            .map(|g| g.position().x as f32 + g.unpositioned().h_metrics().advance_width)
+            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
             .fold(0.0, f32::max);
 
         Self {glyphs, width, line_height}
@@ -80,16 +116,12 @@ impl<'a> Text<'a> {
         layout: TextLayout,
     ) -> Result<(), SDLError> {
         let width = self.width.ceil() as u32;
-        let line_height = self.line_height().ceil() as u32;
 
         use self::TextLayout::*;
         let layout_offset = match layout {
-            Centered => {
-                let (canvas_width, canvas_height) = canvas.logical_size();
-                point(
-                    canvas_width / 2 - width / 2,
-                    canvas_height / 2 - line_height / 2,
-                )
+            CenteredAtTop(top) => {
+                let (canvas_width, _) = canvas.logical_size();
+                point(canvas_width / 2 - width / 2, top)
             },
             TopLeftAt(top_left) => {
                 assert!(top_left.x() >= 0 && top_left.y() >= 0,
@@ -117,10 +149,132 @@ impl<'a> Text<'a> {
                     canvas.set_draw_color(color);
                     result = canvas.draw_point((x, y));
                 });
-                result.map_err(SDLError)?;
+                result.map_err(SDLError::Sdl)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A glyph that has already been rasterized so that it can be drawn many times without laying it
+/// out again
+#[derive(Debug, Clone)]
+struct RasterizedGlyph {
+    /// Each pixel of the glyph relative to the top left corner of the line, along with how much
+    /// of that pixel is covered by the glyph (from 0.0 to 1.0)
+    pixels: Vec<(i32, i32, f32)>,
+    advance_width: f32,
+}
+
+/// The digits 0-9 rasterized once at a single height. Used for numbers that are drawn on almost
+/// every frame (e.g. damage numbers) since laying out text with rusttype is slow.
+#[derive(Debug, Clone)]
+pub struct DigitGlyphs {
+    /// The glyph for each digit, indexed by the value of that digit
+    digits: Vec<RasterizedGlyph>,
+}
+
+impl DigitGlyphs {
+    pub fn new(font: &Font, height: f32) -> Self {
+        let scale = Scale {x: height, y: height};
+        // Same offset as Text so that the glyphs are never clipped
+        let offset = point(0.0, font.v_metrics(scale).ascent);
+        let digits = (0..10).map(|digit| {
+            let digit = std::char::from_digit(digit, 10).expect("bug: not a digit");
+            let glyph = font.glyph(digit).scaled(scale).positioned(offset);
+            let advance_width = glyph.unpositioned().h_metrics().advance_width;
+            let mut pixels = Vec::new();
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| pixels.push((x as i32 + bb.min.x, y as i32 + bb.min.y, v)));
+            }
+
+            RasterizedGlyph {pixels, advance_width}
+        }).collect();
+
+        Self {digits}
+    }
+
+    /// Returns true if every character of the given text is a digit
+    pub fn can_render(&self, text: &str) -> bool {
+        text.chars().all(|ch| ch.is_ascii_digit())
+    }
+
+    /// Returns the width of the given text. Every character must be a digit.
+    pub fn width(&self, text: &str) -> f32 {
+        self.glyphs(text).map(|glyph| glyph.advance_width).sum()
+    }
+
+    /// Renders the given text with its top left corner at the given point. Every character must be
+    /// a digit. Unlike Text, the text may go past the edges of the screen.
+    pub fn render<T: RenderTarget, C: Into<Color>>(
+        &self,
+        canvas: &mut Canvas<T>,
+        color: C,
+        text: &str,
+        top_left: Point,
+    ) -> Result<(), SDLError> {
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        let mut color = color.into();
+        let alpha_start = color.a as f32;
+        let mut x_offset = 0.0;
+        for glyph in self.glyphs(text) {
+            for &(x, y, v) in &glyph.pixels {
+                color.a = (alpha_start * v) as u8;
+                canvas.set_draw_color(color);
+                canvas.draw_point((top_left.x() + x_offset as i32 + x, top_left.y() + y))
+                    .map_err(SDLError::Sdl)?;
             }
+            x_offset += glyph.advance_width;
         }
 
         Ok(())
     }
+
+    fn glyphs<'a>(&'a self, text: &'a str) -> impl Iterator<Item=&'a RasterizedGlyph> {
+        text.chars().map(move |ch| {
+            let digit = ch.to_digit(10).expect("bug: only digits can be rendered from the digit cache");
+            &self.digits[digit as usize]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digits_match_laid_out_text() {
+        let font = load_font().unwrap();
+        let digits = DigitGlyphs::new(&font, 8.0);
+
+        assert!(digits.can_render("1234567890"));
+        assert!(!digits.can_render("-12"));
+        for text in &["0", "7", "42", "1089"] {
+            assert_eq!(digits.width(text), Text::new(&font, text, 8.0).width());
+        }
+    }
+
+    #[test]
+    fn wrap_text_fits_width() {
+        let font = load_font().unwrap();
+        let width = |line: &str| Text::new(&font, line, 8.0).width();
+
+        assert_eq!(wrap_text(&font, "Floor 2", 8.0, 320.0), &["Floor 2"]);
+
+        // Broken between words, never in the middle of one that fits on a line
+        let text = "Unable to save the profile because the disk is full";
+        let lines = wrap_text(&font, text, 8.0, 120.0);
+        assert!(lines.len() > 1);
+        assert_eq!(lines.join(" "), text);
+        assert!(lines.iter().all(|line| width(line) <= 120.0), "{:?}", lines);
+
+        // Words wider than the line are broken between characters
+        let key = "WdNgmbyQa0sB6XtEqSUAg4lKnAIwJMoAz7SEVEJQdD0";
+        let lines = wrap_text(&font, key, 8.0, 100.0);
+        assert!(lines.len() > 1);
+        assert_eq!(lines.concat(), key);
+        assert!(lines.iter().all(|line| width(line) <= 100.0), "{:?}", lines);
+    }
 }
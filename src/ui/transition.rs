@@ -0,0 +1,231 @@
+use sdl2::render::{RenderTarget, BlendMode};
+
+use crate::resources::{FramesElapsed, FloorStats, format_play_time};
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// The number of frames it takes to fade out (or back in)
+const FADE_FRAMES: usize = 10;
+
+/// The distance (in px) between the top of the screen and the top of the summary title
+const SUMMARY_TOP: u32 = 60;
+/// The height of the summary title
+const SUMMARY_TITLE_HEIGHT: f32 = 20.0;
+/// The height of each line of the summary below the title
+const SUMMARY_LINE_HEIGHT: f32 = 10.0;
+/// The distance (in px) between the top of one line of the summary and the top of the next line
+const SUMMARY_LINE_SPACING: u32 = 16;
+
+/// The phases of a transition, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionPhase {
+    /// The screen is gradually fading to black
+    FadeOut {frames_remaining: usize},
+    /// The screen is completely black. This is the midpoint of the transition.
+    Hold {frames_remaining: usize},
+    /// The screen is gradually fading back in
+    FadeIn {frames_remaining: usize},
+    Complete,
+}
+
+/// A fade to black and back with a hold in the middle that can be skipped
+#[derive(Debug, Clone)]
+pub struct Transition {
+    phase: TransitionPhase,
+    /// The number of frames to stay in the hold phase unless it is skipped
+    hold_frames: usize,
+}
+
+impl Transition {
+    /// Creates a transition that holds at its midpoint for the given number of frames
+    pub fn new(hold_frames: usize) -> Self {
+        Self {
+            phase: TransitionPhase::FadeOut {frames_remaining: FADE_FRAMES},
+            hold_frames,
+        }
+    }
+
+    pub fn is_holding(&self) -> bool {
+        matches!(self.phase, TransitionPhase::Hold {..})
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.phase == TransitionPhase::Complete
+    }
+
+    /// Advances the transition by the given number of frames, moving through as many phases as
+    /// necessary. Returns true if the midpoint (the start of the hold) was reached during this
+    /// update.
+    pub fn dispatch(&mut self, FramesElapsed(mut frames): FramesElapsed) -> bool {
+        use self::TransitionPhase::*;
+        let mut reached_midpoint = false;
+        loop {
+            let (frames_remaining, next) = match self.phase {
+                FadeOut {frames_remaining} => (frames_remaining, Hold {frames_remaining: self.hold_frames}),
+                Hold {frames_remaining} => (frames_remaining, FadeIn {frames_remaining: FADE_FRAMES}),
+                FadeIn {frames_remaining} => (frames_remaining, Complete),
+                Complete => break,
+            };
+
+            if frames < frames_remaining {
+                let frames_remaining = frames_remaining - frames;
+                self.phase = match self.phase {
+                    FadeOut {..} => FadeOut {frames_remaining},
+                    Hold {..} => Hold {frames_remaining},
+                    FadeIn {..} => FadeIn {frames_remaining},
+                    Complete => unreachable!(),
+                };
+                break;
+            }
+
+            frames -= frames_remaining;
+            // The hold starts at the end of the fade out
+            if let FadeOut {..} = self.phase {
+                reached_midpoint = true;
+            }
+            self.phase = next;
+        }
+
+        reached_midpoint
+    }
+
+    /// Ends the hold early. Has no effect outside of the hold.
+    pub fn skip_hold(&mut self) {
+        if self.is_holding() {
+            self.phase = TransitionPhase::FadeIn {frames_remaining: FADE_FRAMES};
+        }
+    }
+
+    /// Returns how dark the screen should be from 0 (not at all) to 255 (completely black)
+    pub fn darkness(&self) -> u8 {
+        use self::TransitionPhase::*;
+        let darkness = match self.phase {
+            FadeOut {frames_remaining} => (FADE_FRAMES - frames_remaining) * 255 / FADE_FRAMES,
+            Hold {..} => 255,
+            FadeIn {frames_remaining} => frames_remaining * 255 / FADE_FRAMES,
+            Complete => 0,
+        };
+        darkness as u8
+    }
+
+    /// Darkens the entire screen based on the current phase
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        ctx.canvas.set_blend_mode(BlendMode::Blend);
+        ctx.canvas.set_draw_color((0, 0, 0, self.darkness()));
+        ctx.canvas.fill_rect(None).map_err(SDLError::Sdl)
+    }
+}
+
+/// A summary of what the player accomplished on a floor that they just finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloorSummary {
+    /// The number of the floor that was finished (starting at 1)
+    pub floor: usize,
+    pub stats: FloorStats,
+    /// The number of secrets that were on the floor when the player arrived, found or not
+    pub secrets_total: usize,
+    /// The number of frames in each second, used to display the time spent on the floor
+    pub fps: usize,
+}
+
+impl FloorSummary {
+    /// Returns the lines of text shown below the title of the summary
+    pub fn lines(&self) -> Vec<String> {
+        let FloorStats {frames, enemies_killed, damage_taken, items_found, secrets_found, ..} = self.stats;
+        vec![
+            format!("Time: {}", format_play_time(frames, self.fps)),
+            format!("Enemies defeated: {}", enemies_killed),
+            format!("Damage taken: {}", damage_taken),
+            format!("Items found: {}", items_found),
+            format!("Secrets found: {}/{}", secrets_found, self.secrets_total),
+        ]
+    }
+
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>) -> Result<(), SDLError> {
+        let white = (255, 255, 255, 255);
+
+        let title = format!("Floor {} cleared", self.floor);
+        Text::new(&ctx.font, title, SUMMARY_TITLE_HEIGHT)
+            .render(ctx.canvas, white, TextLayout::CenteredAtTop(SUMMARY_TOP))?;
+
+        let mut top = SUMMARY_TOP + SUMMARY_TITLE_HEIGHT as u32 + SUMMARY_LINE_SPACING;
+        for line in self.lines() {
+            Text::new(&ctx.font, line, SUMMARY_LINE_HEIGHT)
+                .render(ctx.canvas, white, TextLayout::CenteredAtTop(top))?;
+            top += SUMMARY_LINE_SPACING;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use self::TransitionPhase::*;
+
+    #[test]
+    fn transition_phases() {
+        let mut transition = Transition::new(30);
+        assert_eq!(transition.darkness(), 0);
+
+        // Only reports the midpoint once
+        assert!(!transition.dispatch(FramesElapsed(FADE_FRAMES - 1)));
+        assert!(transition.dispatch(FramesElapsed(2)));
+        assert_eq!(transition.phase, Hold {frames_remaining: 29});
+        assert_eq!(transition.darkness(), 255);
+        assert!(!transition.dispatch(FramesElapsed(29)));
+        assert_eq!(transition.phase, FadeIn {frames_remaining: FADE_FRAMES});
+
+        assert!(!transition.dispatch(FramesElapsed(FADE_FRAMES)));
+        assert!(transition.is_complete());
+        assert_eq!(transition.darkness(), 0);
+
+        // Without a hold, the midpoint is passed right away
+        let mut transition = Transition::new(0);
+        assert!(transition.dispatch(FramesElapsed(FADE_FRAMES + 1)));
+        assert_eq!(transition.phase, FadeIn {frames_remaining: FADE_FRAMES - 1});
+    }
+
+    #[test]
+    fn hold_is_skippable() {
+        let mut transition = Transition::new(1000);
+
+        // Skipping before the hold does nothing
+        transition.skip_hold();
+        assert_eq!(transition.phase, FadeOut {frames_remaining: FADE_FRAMES});
+
+        assert!(transition.dispatch(FramesElapsed(FADE_FRAMES)));
+        assert!(transition.is_holding());
+        transition.dispatch(FramesElapsed(10));
+        assert!(transition.is_holding());
+
+        transition.skip_hold();
+        assert_eq!(transition.phase, FadeIn {frames_remaining: FADE_FRAMES});
+        // Skipping again has no effect
+        transition.skip_hold();
+        assert_eq!(transition.phase, FadeIn {frames_remaining: FADE_FRAMES});
+
+        assert!(!transition.dispatch(FramesElapsed(FADE_FRAMES)));
+        assert!(transition.is_complete());
+    }
+
+    #[test]
+    fn summary_lines() {
+        let summary = FloorSummary {
+            floor: 2,
+            stats: FloorStats {frames: 30 * 95, enemies_killed: 4, damage_taken: 17, damage_dealt: 60, items_found: 1, secrets_found: 2},
+            secrets_total: 3,
+            fps: 30,
+        };
+        assert_eq!(summary.lines(), &[
+            "Time: 1:35",
+            "Enemies defeated: 4",
+            "Damage taken: 17",
+            "Items found: 1",
+            "Secrets found: 2/3",
+        ]);
+    }
+}
@@ -0,0 +1,73 @@
+use sdl2::{rect::Point, render::RenderTarget};
+
+use crate::resources::TutorialStep;
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// The height of the prompt text
+const PROMPT_HEIGHT: f32 = 8.0;
+/// The height of the hint for skipping the tutorial, shown below the prompt
+const HINT_HEIGHT: f32 = 6.0;
+/// The distance (in px) between the top of the prompt and the top of the hint
+const HINT_SPACING: u32 = 10;
+/// The distance (in px) between the bottom of the text and the center of the player
+const PLAYER_GAP: i32 = 14;
+/// The closest (in px) that the text can get to the edges of the screen
+const SCREEN_MARGIN: i32 = 4;
+
+/// Returns the top-left corner of text with the given size (in px) so that it is centered just
+/// above the given point on the screen without going past the edges of the screen. Text that is
+/// wider or taller than the screen sticks to the left or top edge.
+pub fn prompt_top_left(anchor: Point, (width, height): (u32, u32), (screen_width, screen_height): (u32, u32)) -> Point {
+    let x = anchor.x() - width as i32 / 2;
+    let y = anchor.y() - PLAYER_GAP - height as i32;
+
+    let max_x = screen_width as i32 - SCREEN_MARGIN - width as i32;
+    let max_y = screen_height as i32 - SCREEN_MARGIN - height as i32;
+    Point::new(x.min(max_x).max(SCREEN_MARGIN), y.min(max_y).max(SCREEN_MARGIN))
+}
+
+/// Draws the prompt of the given tutorial step just above the player, whose position on the screen
+/// is given
+pub fn render_tutorial_prompt<T: RenderTarget>(
+    ctx: &mut RenderContext<T>,
+    step: TutorialStep,
+    player: Point,
+) -> Result<(), SDLError> {
+    let prompt = Text::new(&ctx.font, step.prompt(), PROMPT_HEIGHT);
+    let hint = Text::new(&ctx.font, "Space: skip tutorial", HINT_HEIGHT);
+
+    let width = prompt.width().max(hint.width()).ceil() as u32;
+    let height = HINT_SPACING + HINT_HEIGHT.ceil() as u32;
+    let top_left = prompt_top_left(player, (width, height), ctx.canvas.logical_size());
+
+    // Each line is centered within the space taken up by the longest line
+    let line_left = |text: &Text| top_left.x() + (width as i32 - text.width() as i32) / 2;
+    let prompt_left = line_left(&prompt);
+    let hint_left = line_left(&hint);
+    prompt.render(ctx.canvas, (255, 255, 255, 255), TextLayout::TopLeftAt(Point::new(prompt_left, top_left.y())))?;
+    hint.render(ctx.canvas, (150, 150, 150, 255), TextLayout::TopLeftAt(Point::new(hint_left, top_left.y() + HINT_SPACING as i32)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_clamped_to_screen() {
+        let screen = (320, 240);
+        let size = (60, 16);
+
+        // Centered above the player when there is room
+        assert_eq!(prompt_top_left(Point::new(160, 120), size, screen), Point::new(130, 120 - PLAYER_GAP - 16));
+        // Pushed back onto the screen near each of the edges
+        assert_eq!(prompt_top_left(Point::new(10, 10), size, screen), Point::new(SCREEN_MARGIN, SCREEN_MARGIN));
+        assert_eq!(prompt_top_left(Point::new(318, 120), size, screen), Point::new(320 - SCREEN_MARGIN - 60, 120 - PLAYER_GAP - 16));
+        assert_eq!(prompt_top_left(Point::new(160, 300), size, screen), Point::new(130, 240 - SCREEN_MARGIN - 16));
+        // Too wide for the screen
+        assert_eq!(prompt_top_left(Point::new(160, 120), (400, 16), screen).x(), SCREEN_MARGIN);
+    }
+}
@@ -0,0 +1,138 @@
+use sdl2::render::{RenderTarget, BlendMode};
+
+use crate::resources::{Event, Key};
+use crate::upgrades::{Upgrade, Upgrades};
+
+use super::text::{Text, TextLayout};
+use super::{SDLError, RenderContext};
+
+/// How dark the game is behind the list of upgrades, from 0 (not at all) to 255 (black)
+const BACKGROUND_DARKNESS: u8 = 230;
+
+/// The distance (in px) between the top of the screen and the top of the title
+const TITLE_TOP: u32 = 40;
+/// The height of the title
+const TITLE_HEIGHT: f32 = 20.0;
+/// The height of each line below the title
+const LINE_HEIGHT: f32 = 8.0;
+/// The distance (in px) between the top of one line and the top of the next line
+const LINE_SPACING: u32 = 14;
+
+/// Something that the player chose to do on the upgrades screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeShopAction {
+    /// Buy the next tier of the given upgrade
+    Buy(Upgrade),
+    /// Go back to the game over screen
+    Close,
+}
+
+/// The list of permanent upgrades that can be bought with banked coins once a run is over
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UpgradeShop {
+    /// The position in `Upgrade::ALL` of the upgrade that is currently selected
+    selected: usize,
+}
+
+impl UpgradeShop {
+    /// Returns the upgrade that is currently selected
+    pub fn selected(&self) -> Upgrade {
+        Upgrade::ALL[self.selected]
+    }
+
+    /// Moves the selection with the up and down arrows, wrapping around at either end. The A key
+    /// or Start buys the selected upgrade and B or Y goes back to the game over screen.
+    pub fn dispatch(&mut self, events: &[Event]) -> Vec<UpgradeShopAction> {
+        let count = Upgrade::ALL.len();
+        let mut actions = Vec::new();
+        for event in events {
+            match event {
+                Event::KeyDown(Key::UpArrow) => self.selected = (self.selected + count - 1) % count,
+                Event::KeyDown(Key::DownArrow) => self.selected = (self.selected + 1) % count,
+                Event::KeyDown(Key::A) | Event::KeyDown(Key::Start) => actions.push(UpgradeShopAction::Buy(self.selected())),
+                Event::KeyDown(Key::B) | Event::KeyDown(Key::Y) => actions.push(UpgradeShopAction::Close),
+                _ => {},
+            }
+        }
+        actions
+    }
+
+    /// Returns a line of text for each upgrade: its name, the tier bought so far, and the cost of
+    /// the next tier
+    pub fn lines(upgrades: &Upgrades) -> Vec<String> {
+        Upgrade::ALL.iter().map(|&upgrade| {
+            let tier = format!("{}/{}", upgrades.tier(upgrade), upgrade.max_tier());
+            match upgrades.next_cost(upgrade) {
+                Some(cost) => format!("{}  {}  {} coins", upgrade.title(), tier, cost),
+                None => format!("{}  {}  max", upgrade.title(), tier),
+            }
+        }).collect()
+    }
+
+    /// Draws the upgrades that have been bought so far along with the coins that are left to spend
+    pub fn render<T: RenderTarget>(&self, ctx: &mut RenderContext<T>, upgrades: &Upgrades, coins: u64) -> Result<(), SDLError> {
+        ctx.canvas.set_blend_mode(BlendMode::Blend);
+        ctx.canvas.set_draw_color((0, 0, 0, BACKGROUND_DARKNESS));
+        ctx.canvas.fill_rect(None).map_err(SDLError::Sdl)?;
+
+        let white = (255, 255, 255, 255);
+        let gold_color = (255, 215, 0, 255);
+        let gray = (150, 150, 150, 255);
+        Text::new(&ctx.font, "Upgrades", TITLE_HEIGHT)
+            .render(ctx.canvas, white, TextLayout::CenteredAtTop(TITLE_TOP))?;
+
+        let mut top = TITLE_TOP + TITLE_HEIGHT as u32 + LINE_SPACING;
+        Text::new(&ctx.font, format!("Banked coins: {}", coins), LINE_HEIGHT)
+            .render(ctx.canvas, gold_color, TextLayout::CenteredAtTop(top))?;
+        top += LINE_SPACING * 2;
+
+        for (i, line) in Self::lines(upgrades).into_iter().enumerate() {
+            let upgrade = Upgrade::ALL[i];
+            let marker = if i == self.selected { ">" } else { " " };
+            let affordable = upgrades.next_cost(upgrade).map(|cost| cost <= coins).unwrap_or(false);
+            let color = if affordable { white } else { gray };
+            Text::new(&ctx.font, format!("{} {}", marker, line), LINE_HEIGHT)
+                .render(ctx.canvas, color, TextLayout::CenteredAtTop(top))?;
+            top += LINE_SPACING;
+        }
+
+        top += LINE_SPACING;
+        Text::new(&ctx.font, "Press A to buy, B to go back", LINE_HEIGHT)
+            .render(ctx.canvas, gold_color, TextLayout::CenteredAtTop(top))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_wraps_and_buys_selected_upgrade() {
+        let mut shop = UpgradeShop::default();
+        assert_eq!(shop.selected(), Upgrade::ALL[0]);
+
+        assert!(shop.dispatch(&[Event::KeyDown(Key::UpArrow)]).is_empty());
+        assert_eq!(shop.selected(), Upgrade::ALL[Upgrade::ALL.len() - 1]);
+        shop.dispatch(&[Event::KeyDown(Key::DownArrow), Event::KeyDown(Key::DownArrow)]);
+        assert_eq!(shop.selected(), Upgrade::ALL[1]);
+
+        // Releasing a key does nothing
+        assert!(shop.dispatch(&[Event::KeyUp(Key::A)]).is_empty());
+        assert_eq!(shop.dispatch(&[Event::KeyDown(Key::A), Event::KeyDown(Key::B)]),
+            &[UpgradeShopAction::Buy(Upgrade::ALL[1]), UpgradeShopAction::Close]);
+    }
+
+    #[test]
+    fn lines_show_tier_and_next_cost() {
+        let mut upgrades = Upgrades::default();
+        upgrades.set_tier(Upgrade::MaxHealth, Upgrade::MaxHealth.max_tier());
+        upgrades.set_tier(Upgrade::MoveSpeed, 1);
+        assert_eq!(UpgradeShop::lines(&upgrades), &[
+            "+2 Max HP  3/3  max",
+            "+1 Starting Potion  0/2  40 coins",
+            "+5% Move Speed  1/3  150 coins",
+        ]);
+    }
+}
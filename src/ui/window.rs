@@ -4,8 +4,11 @@ use sdl2::{
     self,
     Sdl,
     TimerSubsystem,
+    AudioSubsystem,
     EventPump,
+    clipboard::ClipboardUtil,
     image::{Sdl2ImageContext, InitFlag},
+    mixer::{self, Sdl2MixerContext},
     pixels::Color,
     render::{TextureCreator, Canvas},
     video::{Window as SDLWindow, WindowContext},
@@ -17,14 +20,31 @@ pub struct Window {
     sdl_context: Sdl,
     /// Required to use images, but not used for anything after it is created
     _image_context: Sdl2ImageContext,
+    /// Required to play audio, but not used for anything after it is created. None if audio could
+    /// not be initialized.
+    _audio: Option<(AudioSubsystem, Sdl2MixerContext)>,
     canvas: Canvas<SDLWindow>,
 }
 
+/// Opens the audio device so that sound effects and music can be played
+fn init_audio(sdl_context: &Sdl) -> Result<(AudioSubsystem, Sdl2MixerContext), String> {
+    let audio_subsystem = sdl_context.audio()?;
+    mixer::open_audio(mixer::DEFAULT_FREQUENCY, mixer::DEFAULT_FORMAT, mixer::DEFAULT_CHANNELS, 1024)?;
+    let mixer_context = mixer::init(mixer::InitFlag::OGG)?;
+    Ok((audio_subsystem, mixer_context))
+}
+
 impl Window {
+    /// Opens a window for a screen with the given size (in px). The game is drawn at that size
+    /// until a Zoom is applied to the canvas.
     pub fn init(width: u32, height: u32) -> Result<Self, SDLError> {
-        let sdl_context = sdl2::init().map_err(SDLError)?;
-        let video_subsystem = sdl_context.video().map_err(SDLError)?;
+        let sdl_context = sdl2::init().map_err(SDLError::Sdl)?;
+        let video_subsystem = sdl_context.video().map_err(SDLError::Sdl)?;
         let _image_context = sdl2::image::init(InitFlag::PNG).unwrap();
+        // The game can still be played without audio
+        let _audio = init_audio(&sdl_context)
+            .map_err(|err| warn!("Unable to initialize audio, continuing without it: {}", err))
+            .ok();
 
         // Scale display if a certain environment variable is set
         let display_scale = env::var("DISPLAY_SCALE")
@@ -50,15 +70,13 @@ impl Window {
         // The background color
         canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
 
-        // Scales the game *within* the window so it is easier to see things
-        let zoom = 2;
-
         //FIXME: Remove this unwrap() when we start using proper error types
-        canvas.set_logical_size(width / zoom, height / zoom).unwrap();
+        canvas.set_logical_size(width, height).unwrap();
 
         Ok(Self {
             sdl_context,
             _image_context,
+            _audio,
             canvas,
         })
     }
@@ -72,11 +90,15 @@ impl Window {
     }
 
     pub fn timer(&self) -> Result<TimerSubsystem, SDLError> {
-        self.sdl_context.timer().map_err(SDLError)
+        self.sdl_context.timer().map_err(SDLError::Sdl)
     }
 
     pub fn event_pump(&self) -> Result<EventPump, SDLError> {
-        self.sdl_context.event_pump().map_err(SDLError)
+        self.sdl_context.event_pump().map_err(SDLError::Sdl)
+    }
+
+    pub fn clipboard(&self) -> Result<ClipboardUtil, SDLError> {
+        Ok(self.sdl_context.video().map_err(SDLError::Sdl)?.clipboard())
     }
 
     pub fn canvas_mut(&mut self) -> &mut Canvas<SDLWindow> {
@@ -0,0 +1,151 @@
+use sdl2::render::{Canvas, RenderTarget};
+
+use super::SDLError;
+
+/// Every zoom level that can be chosen, from furthest out to furthest in
+const ZOOM_LEVELS: [f32; 4] = [1.0, 1.5, 2.0, 3.0];
+/// The zoom level that the game starts at
+const DEFAULT_ZOOM_LEVEL: f32 = 2.0;
+
+/// How much the game is scaled up *within* the window so it is easier to see things. The game is
+/// drawn at a logical size that is the screen size divided by the zoom level and then scaled up to
+/// fit the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zoom {
+    /// The size (in px) of the screen before it is zoomed
+    screen_size: (u32, u32),
+    level: f32,
+    /// If true, only whole number zoom levels can be chosen and the game is only ever scaled up
+    /// by a whole number to fit the window. This keeps every pixel of a sprite the same size.
+    pixel_perfect: bool,
+}
+
+impl Zoom {
+    /// Creates the zoom for a screen with the given size (in px), starting at the default level
+    pub fn new(screen_size: (u32, u32), pixel_perfect: bool) -> Self {
+        Self {screen_size, level: DEFAULT_ZOOM_LEVEL, pixel_perfect}
+    }
+
+    /// The current zoom level, e.g. 2.0 draws everything twice as big
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn pixel_perfect(&self) -> bool {
+        self.pixel_perfect
+    }
+
+    /// Returns every zoom level that can currently be chosen, from furthest out to furthest in
+    pub fn levels(&self) -> impl Iterator<Item=f32> {
+        let pixel_perfect = self.pixel_perfect;
+        ZOOM_LEVELS.iter().cloned().filter(move |level| !pixel_perfect || level.fract() == 0.0)
+    }
+
+    /// Moves to the next zoom level further in. Returns false if already zoomed in all the way.
+    pub fn zoom_in(&mut self) -> bool {
+        let level = self.level;
+        match self.levels().find(|&next| next > level) {
+            Some(next) => {
+                self.level = next;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Moves to the next zoom level further out. Returns false if already zoomed out all the way.
+    pub fn zoom_out(&mut self) -> bool {
+        let level = self.level;
+        match self.levels().filter(|&next| next < level).last() {
+            Some(next) => {
+                self.level = next;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Turns pixel perfect scaling on or off. A zoom level that can no longer be chosen is
+    /// replaced with the next level further out.
+    pub fn set_pixel_perfect(&mut self, pixel_perfect: bool) {
+        self.pixel_perfect = pixel_perfect;
+        let level = self.level;
+        if !self.levels().any(|next| next == level) {
+            self.level = self.levels().filter(|&next| next < level).last()
+                .or_else(|| self.levels().next())
+                .expect("bug: there should always be at least one zoom level");
+        }
+    }
+
+    /// Returns the size (in px) that the game should be drawn at before it is scaled up to fit
+    /// the window
+    pub fn logical_size(&self) -> (u32, u32) {
+        let (width, height) = self.screen_size;
+        ((width as f32 / self.level) as u32, (height as f32 / self.level) as u32)
+    }
+
+    /// Updates the given canvas to draw at this zoom level. Anything that depends on the logical
+    /// size of the canvas (e.g. where the camera can go) changes from the next frame rendered.
+    pub fn apply<T: RenderTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), SDLError> {
+        let (width, height) = self.logical_size();
+        canvas.set_logical_size(width, height).map_err(|err| SDLError::Sdl(err.to_string()))?;
+
+        let integer_scale = if self.pixel_perfect {
+            sdl2::sys::SDL_bool::SDL_TRUE
+        } else {
+            sdl2::sys::SDL_bool::SDL_FALSE
+        };
+        // Safe because the renderer is owned by the canvas and stays valid for as long as it is
+        // borrowed here. The sdl2 crate has no safe wrapper for this function.
+        let result = unsafe { sdl2::sys::SDL_RenderSetIntegerScale(canvas.raw(), integer_scale) };
+        if result != 0 {
+            return Err(SDLError::Sdl(sdl2::get_error()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_levels() {
+        let mut zoom = Zoom::new((320, 240), false);
+        assert_eq!(zoom.level(), 2.0);
+        assert_eq!(zoom.logical_size(), (160, 120));
+
+        assert!(zoom.zoom_in());
+        assert_eq!(zoom.level(), 3.0);
+        assert_eq!(zoom.logical_size(), (106, 80));
+        assert!(!zoom.zoom_in());
+
+        assert!(zoom.zoom_out());
+        assert!(zoom.zoom_out());
+        assert_eq!(zoom.level(), 1.5);
+        assert_eq!(zoom.logical_size(), (213, 160));
+        assert!(zoom.zoom_out());
+        assert_eq!(zoom.logical_size(), (320, 240));
+        assert!(!zoom.zoom_out());
+    }
+
+    #[test]
+    fn pixel_perfect_only_whole_levels() {
+        let mut zoom = Zoom::new((320, 240), true);
+        assert_eq!(zoom.levels().collect::<Vec<_>>(), &[1.0, 2.0, 3.0]);
+        assert!(zoom.zoom_out());
+        assert_eq!(zoom.level(), 1.0);
+        assert!(zoom.zoom_in());
+        assert_eq!(zoom.level(), 2.0);
+
+        // Turning it on while at a fractional level moves further out
+        let mut zoom = Zoom::new((320, 240), false);
+        zoom.zoom_out();
+        assert_eq!(zoom.level(), 1.5);
+        zoom.set_pixel_perfect(true);
+        assert_eq!(zoom.level(), 1.0);
+        zoom.set_pixel_perfect(false);
+        assert_eq!(zoom.level(), 1.0);
+    }
+}
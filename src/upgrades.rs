@@ -0,0 +1,315 @@
+//! Permanent upgrades bought with the coins banked across every run of the game
+
+use std::collections::BTreeMap;
+
+use crate::components::{PlayerComponents, HealthPoints, MaxHealthPoints, Item};
+
+/// The fraction of the coins collected during a run that is banked when the player dies
+const DEATH_BANK_FRACTION: f64 = 0.5;
+/// The multiplier applied to the coins collected during a run that was won by escaping back to the
+/// surface with the treasure
+pub const ESCAPE_BONUS: f64 = 1.5;
+/// The strength of each potion that the player starts a run with
+const STARTING_POTION_STRENGTH: u32 = 5;
+/// The fraction added to the speed of the player by each tier of the move speed upgrade
+const MOVE_SPEED_BONUS: f32 = 0.05;
+
+/// A permanent boost to the player that can be bought several times, up to a maximum tier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Upgrade {
+    /// Start each run with more health points
+    MaxHealth,
+    /// Start each run with a potion in the inventory
+    StartingPotion,
+    /// Walk faster
+    MoveSpeed,
+}
+
+impl Upgrade {
+    /// All of the upgrades that can be bought
+    pub const ALL: [Upgrade; 3] = [
+        Upgrade::MaxHealth,
+        Upgrade::StartingPotion,
+        Upgrade::MoveSpeed,
+    ];
+
+    /// The name of this upgrade in the profile file. Must never change once released.
+    pub fn id(self) -> &'static str {
+        use self::Upgrade::*;
+        match self {
+            MaxHealth => "max_health",
+            StartingPotion => "starting_potion",
+            MoveSpeed => "move_speed",
+        }
+    }
+
+    /// Returns the upgrade with the given ID, if any
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|upgrade| upgrade.id() == id)
+    }
+
+    /// The name of this upgrade shown to the player
+    pub fn title(self) -> &'static str {
+        use self::Upgrade::*;
+        match self {
+            MaxHealth => "+2 Max HP",
+            StartingPotion => "+1 Starting Potion",
+            MoveSpeed => "+5% Move Speed",
+        }
+    }
+
+    /// The highest tier of this upgrade that can be bought
+    pub fn max_tier(self) -> u32 {
+        use self::Upgrade::*;
+        match self {
+            MaxHealth => 3,
+            StartingPotion => 2,
+            MoveSpeed => 3,
+        }
+    }
+
+    /// The number of coins that it costs to buy the given tier (starting at 1) of this upgrade.
+    /// Each tier costs more than the last. None if the tier does not exist.
+    pub fn cost(self, tier: u32) -> Option<u64> {
+        use self::Upgrade::*;
+        let base_cost = match self {
+            MaxHealth => 50,
+            StartingPotion => 40,
+            MoveSpeed => 75,
+        };
+        if tier == 0 || tier > self.max_tier() {
+            return None;
+        }
+        Some(base_cost * tier as u64)
+    }
+}
+
+/// The tier that has been bought of each upgrade. Upgrades that have never been bought are at
+/// tier zero.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Upgrades {
+    tiers: BTreeMap<Upgrade, u32>,
+}
+
+impl Upgrades {
+    /// Returns the highest tier of the given upgrade that has been bought
+    pub fn tier(&self, upgrade: Upgrade) -> u32 {
+        self.tiers.get(&upgrade).cloned().unwrap_or(0)
+    }
+
+    /// Sets the tier of the given upgrade, up to its maximum tier
+    pub fn set_tier(&mut self, upgrade: Upgrade, tier: u32) {
+        let tier = tier.min(upgrade.max_tier());
+        if tier == 0 {
+            self.tiers.remove(&upgrade);
+        } else {
+            self.tiers.insert(upgrade, tier);
+        }
+    }
+
+    /// Returns the cost of the next tier of the given upgrade or None if it is already at its
+    /// maximum tier
+    pub fn next_cost(&self, upgrade: Upgrade) -> Option<u64> {
+        upgrade.cost(self.tier(upgrade) + 1)
+    }
+
+    /// Buys the next tier of the given upgrade with the given coins. Returns false (and spends
+    /// nothing) if the upgrade is at its maximum tier or there are not enough coins.
+    pub fn purchase(&mut self, upgrade: Upgrade, coins: &mut u64) -> bool {
+        match self.next_cost(upgrade) {
+            Some(cost) if cost <= *coins => {
+                *coins -= cost;
+                let tier = self.tier(upgrade) + 1;
+                self.set_tier(upgrade, tier);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the upgrades that have been bought along with their tier
+    pub fn iter(&self) -> impl Iterator<Item=(Upgrade, u32)> + '_ {
+        self.tiers.iter().map(|(&upgrade, &tier)| (upgrade, tier))
+    }
+
+    /// Applies every upgrade to the components of a player that is about to start a new run
+    pub fn apply(&self, player: &mut PlayerComponents) {
+        let bonus_health = 2 * self.tier(Upgrade::MaxHealth) as usize;
+        let HealthPoints(health) = &mut player.health_points;
+        *health += bonus_health;
+        let MaxHealthPoints(max_health) = &mut player.max_health_points;
+        *max_health += bonus_health;
+
+        for _ in 0..self.tier(Upgrade::StartingPotion) {
+            player.inventory.add(Item::Potion {stength: STARTING_POTION_STRENGTH});
+        }
+
+        player.keyboard_controlled.speed_multiplier += MOVE_SPEED_BONUS * self.tier(Upgrade::MoveSpeed) as f32;
+    }
+}
+
+/// How a run of the game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The player ran out of health
+    Death,
+    /// The player made it out of the caves
+    Victory,
+}
+
+impl RunOutcome {
+    /// All of the outcomes
+    pub const ALL: [RunOutcome; 2] = [
+        RunOutcome::Death,
+        RunOutcome::Victory,
+    ];
+
+    /// The name of this outcome in saved files. Must never change once released.
+    pub fn id(self) -> &'static str {
+        use self::RunOutcome::*;
+        match self {
+            Death => "death",
+            Victory => "victory",
+        }
+    }
+
+    /// Returns the outcome with the given ID, if any
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|outcome| outcome.id() == id)
+    }
+}
+
+/// Returns the number of coins that are banked from the coins collected during a run. Only a
+/// fraction of the coins are kept when the player dies.
+pub fn banked_coins(collected: u64, outcome: RunOutcome) -> u64 {
+    match outcome {
+        RunOutcome::Death => (collected as f64 * DEATH_BANK_FRACTION) as u64,
+        RunOutcome::Victory => collected,
+    }
+}
+
+/// Returns the coins collected during a run with the bonus for escaping back to the surface
+pub fn escape_bonus(collected: u64) -> u64 {
+    (collected as f64 * ESCAPE_BONUS) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::rect::Point;
+    use component_group::ComponentGroup;
+
+    use crate::components::Inventory;
+    use crate::map::TilePos;
+    use crate::resources::{Event, Key};
+    use crate::test_helpers::{TestWorld, player_components};
+
+    #[test]
+    fn tiers_cost_more_and_are_capped() {
+        let upgrade = Upgrade::MaxHealth;
+        let mut upgrades = Upgrades::default();
+        let mut coins = 1000;
+        let mut costs = Vec::new();
+        while let Some(cost) = upgrades.next_cost(upgrade) {
+            costs.push(cost);
+            assert!(upgrades.purchase(upgrade, &mut coins));
+        }
+        assert_eq!(costs, vec![50, 100, 150]);
+        assert_eq!(coins, 1000 - 300);
+        assert_eq!(upgrades.tier(upgrade), upgrade.max_tier());
+
+        // Nothing is spent once the maximum tier is reached
+        assert!(!upgrades.purchase(upgrade, &mut coins));
+        assert_eq!(coins, 700);
+        assert_eq!(upgrade.cost(0), None);
+        assert_eq!(upgrade.cost(upgrade.max_tier() + 1), None);
+    }
+
+    #[test]
+    fn purchase_requires_enough_coins() {
+        let mut upgrades = Upgrades::default();
+        let mut coins = 49;
+        assert!(!upgrades.purchase(Upgrade::MaxHealth, &mut coins));
+        assert_eq!((coins, upgrades.tier(Upgrade::MaxHealth)), (49, 0));
+
+        coins += 1;
+        assert!(upgrades.purchase(Upgrade::MaxHealth, &mut coins));
+        assert_eq!((coins, upgrades.tier(Upgrade::MaxHealth)), (0, 1));
+    }
+
+    #[test]
+    fn upgrades_apply_to_new_player() {
+        let mut player = player_components(Point::new(0, 0));
+        let HealthPoints(base_health) = player.health_points;
+        Upgrades::default().apply(&mut player);
+        assert_eq!(player.health_points.0, base_health);
+
+        let mut upgrades = Upgrades::default();
+        upgrades.set_tier(Upgrade::MaxHealth, 2);
+        let mut player = player_components(Point::new(0, 0));
+        upgrades.apply(&mut player);
+        assert_eq!(player.health_points.0, base_health + 4);
+        assert_eq!(player.max_health_points.0, base_health + 4);
+        assert!(player.inventory.items.is_empty());
+        assert_eq!(player.keyboard_controlled.speed_multiplier, 1.0);
+
+        upgrades.set_tier(Upgrade::StartingPotion, 2);
+        upgrades.set_tier(Upgrade::MoveSpeed, 3);
+        let mut player = player_components(Point::new(0, 0));
+        upgrades.apply(&mut player);
+        assert_eq!(player.inventory.items, &[Item::Potion {stength: 5}, Item::Potion {stength: 5}]);
+        assert!((player.keyboard_controlled.speed_multiplier - 1.15).abs() < 1e-6);
+    }
+
+    #[test]
+    fn starting_potions_can_be_drunk() {
+        let mut upgrades = Upgrades::default();
+        upgrades.set_tier(Upgrade::StartingPotion, 1);
+
+        let mut level = TestWorld::new(5, 5, 16);
+        let pos = level.tile_center(TilePos {row: 2, col: 2});
+        let mut player = player_components(pos);
+        upgrades.apply(&mut player);
+        let HealthPoints(max_health) = player.health_points;
+        player.health_points = HealthPoints(max_health - 10);
+        let player = player.create(&mut level.world);
+
+        level.step_with_events(vec![Event::KeyUp(Key::Y)]);
+        assert_eq!(level.world.read_storage::<HealthPoints>().get(player).unwrap().0,
+            max_health - 10 + STARTING_POTION_STRENGTH as usize);
+        assert!(level.world.read_storage::<Inventory>().get(player).unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn every_upgrade_is_capped() {
+        for &upgrade in &Upgrade::ALL {
+            let mut upgrades = Upgrades::default();
+            let mut coins = 10_000;
+            let mut bought = 0;
+            while upgrades.purchase(upgrade, &mut coins) {
+                bought += 1;
+            }
+            assert_eq!(bought, upgrade.max_tier());
+            assert_eq!(Upgrade::from_id(upgrade.id()), Some(upgrade));
+            upgrades.set_tier(upgrade, 99);
+            assert_eq!(upgrades.tier(upgrade), upgrade.max_tier());
+        }
+    }
+
+    #[test]
+    fn escape_bonus_multiplies_coins() {
+        assert_eq!(escape_bonus(0), 0);
+        assert_eq!(escape_bonus(40), 60);
+        // Partial coins are rounded down
+        assert_eq!(escape_bonus(41), 61);
+        assert_eq!(banked_coins(escape_bonus(40), RunOutcome::Victory), 60);
+    }
+
+    #[test]
+    fn coins_banked_for_each_outcome() {
+        assert_eq!(banked_coins(41, RunOutcome::Victory), 41);
+        assert_eq!(banked_coins(41, RunOutcome::Death), 20);
+        assert_eq!(banked_coins(0, RunOutcome::Death), 0);
+    }
+}